@@ -0,0 +1,77 @@
+//! The benchmarks of the attackers over synthetic Zipf histograms of growing domains. The cost of the attacks is what
+//! bounds the size of the experiments, and the Hungarian assignment dominates it.
+
+use criterion::{
+    criterion_group, criterion_main, BenchmarkId, Criterion, Throughput,
+};
+use fse::{
+    attack::{LpAttacker, MLEAttacker},
+    bench_support::{AttackFixture, ATTACK_DOMAINS},
+    util::{build_histogram, build_histogram_vec},
+};
+use pathfinding::{kuhn_munkres::kuhn_munkres_min, matrix::Matrix};
+
+criterion_group! {
+    name = attack_benches;
+    config = Criterion::default().significance_level(0.1).sample_size(10);
+    targets = histogram_bench, cost_matrix_bench, hungarian_bench, mle_bench
+}
+
+criterion_main!(attack_benches);
+
+/// Run `routine` over the fixture of each domain in the group `{name}_bench`.
+fn bench_domains<F>(c: &mut Criterion, name: &str, mut routine: F)
+where
+    F: FnMut(&mut criterion::Bencher<'_>, &AttackFixture),
+{
+    let mut group = c.benchmark_group(format!("{}_bench", name));
+    for domain in ATTACK_DOMAINS {
+        let fixture = AttackFixture::new(domain);
+        group.throughput(Throughput::Elements(domain as u64));
+        group.bench_function(BenchmarkId::from_parameter(domain), |b| {
+            routine(b, &fixture)
+        });
+    }
+    group.finish();
+}
+
+fn histogram_bench(c: &mut Criterion) {
+    bench_domains(c, "histogram", |b, fixture| {
+        b.iter(|| {
+            build_histogram_vec(&build_histogram(&fixture.raw_ciphertexts))
+        })
+    });
+}
+
+fn cost_matrix_bench(c: &mut Criterion) {
+    let attacker = LpAttacker::<String>::new(2);
+    bench_domains(c, "cost_matrix", |b, fixture| {
+        b.iter(|| {
+            attacker.build_cost_matrix(&fixture.auxiliary, &fixture.ciphertexts)
+        })
+    });
+}
+
+fn hungarian_bench(c: &mut Criterion) {
+    let attacker = LpAttacker::<String>::new(2);
+    bench_domains(c, "hungarian", |b, fixture| {
+        let cost_matrix = Matrix::from_rows(
+            attacker
+                .build_cost_matrix(&fixture.auxiliary, &fixture.ciphertexts),
+        )
+        .unwrap();
+        b.iter(|| kuhn_munkres_min(&cost_matrix))
+    });
+}
+
+fn mle_bench(c: &mut Criterion) {
+    bench_domains(c, "mle", |b, fixture| {
+        b.iter(|| {
+            MLEAttacker::new().attack(
+                &fixture.correct,
+                &fixture.local_table,
+                &fixture.raw_ciphertexts,
+            )
+        })
+    });
+}
@@ -0,0 +1,76 @@
+//! The benchmarks of counting histograms, which every attack, encoder and evaluation does first. `siphash` is the
+//! counting of `build_histogram` before it moved to `HistogramState` and reserved its capacity, so it is the baseline
+//! of the speedup.
+
+use std::collections::HashMap;
+
+use criterion::{
+    criterion_group, criterion_main, BenchmarkId, Criterion, Throughput,
+};
+use fse::util::{build_histogram, build_histogram_fast, build_histogram_par};
+use rand::prelude::Distribution;
+use rand_core::OsRng;
+use rand_distr::Zipf;
+
+/// The numbers of items of the datasets.
+const SIZES: [usize; 3] = [10_000, 1_000_000, 4_000_000];
+
+criterion_group! {
+    name = histogram_benches;
+    config = Criterion::default().significance_level(0.1).sample_size(10);
+    targets = low_cardinality_bench, high_cardinality_bench
+}
+
+criterion_main!(histogram_benches);
+
+/// `size` items drawn from a Zipf distribution over `domain` values.
+fn dataset(size: usize, domain: usize) -> Vec<String> {
+    let zipf = Zipf::new(domain as u64, 1.1).unwrap();
+    (0..size)
+        .map(|_| format!("value-{}", zipf.sample(&mut OsRng) as u64))
+        .collect()
+}
+
+fn siphash(dataset: &[String]) -> HashMap<String, usize> {
+    let mut histogram = HashMap::new();
+    for item in dataset.iter() {
+        *histogram.entry(item.clone()).or_insert(0) += 1;
+    }
+    histogram
+}
+
+fn bench_sizes<F>(c: &mut Criterion, name: &str, domain: F)
+where
+    F: Fn(usize) -> usize,
+{
+    let mut group = c.benchmark_group(format!("{}_bench", name));
+    for size in SIZES {
+        let dataset = dataset(size, domain(size));
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_function(BenchmarkId::new("siphash", size), |b| {
+            b.iter(|| siphash(&dataset))
+        });
+        group.bench_function(BenchmarkId::new("build_histogram", size), |b| {
+            b.iter(|| build_histogram(&dataset))
+        });
+        group.bench_function(
+            BenchmarkId::new("build_histogram_fast", size),
+            |b| b.iter(|| build_histogram_fast(&dataset)),
+        );
+        group.bench_function(
+            BenchmarkId::new("build_histogram_par", size),
+            |b| b.iter(|| build_histogram_par(&dataset)),
+        );
+    }
+    group.finish();
+}
+
+/// A column of a thousand values, the common case of the evaluations.
+fn low_cardinality_bench(c: &mut Criterion) {
+    bench_sizes(c, "low_cardinality", |_| 1000);
+}
+
+/// A column of about as many values as items, e.g., the ciphertexts of RND.
+fn high_cardinality_bench(c: &mut Criterion) {
+    bench_sizes(c, "high_cardinality", |size| size);
+}
@@ -1,13 +1,8 @@
-use criterion::{criterion_group, BenchmarkId, Criterion, Throughput};
+use criterion::{criterion_group, Criterion};
 use fse::{
-    fse::{exponential, BaseCrypto, PartitionFrequencySmoothing},
-    lpfse::{ContextLPFSE, EncoderBHE, EncoderIHBE},
-    native::ContextNative,
-    pfse::ContextPFSE,
-    util::read_csv_exact,
+    bench_support::{bench_scheme, BenchDb},
+    FSEType,
 };
-use rand::seq::SliceRandom;
-use rand_core::OsRng;
 
 criterion_group! {
     name = fse_benches_init_real;
@@ -16,147 +11,43 @@ criterion_group! {
 }
 
 fn dte_bench_on_real(c: &mut Criterion) {
-    let mut vec = read_csv_exact("./data/test.csv", "order_number").unwrap();
-    vec.shuffle(&mut OsRng);
-
-    let mut group = c.benchmark_group("dte_init_bench_on_real");
-    for size in [100, 1000, 10000, 100000, 1000000] {
-        let mut ctx = ContextNative::new(false);
-        let slice = &vec[..size];
-        ctx.key_generate();
-
-        group.throughput(Throughput::Elements(size as u64));
-        group.bench_with_input(
-            BenchmarkId::from_parameter(size),
-            &size,
-            |b, _| {
-                b.iter(|| {
-                    for message in slice.iter() {
-                        ctx.encrypt(message).unwrap();
-                    }
-                })
-            },
-        );
-    }
-    group.finish();
+    bench_scheme(c, FSEType::Dte, "init", BenchDb::Offline, |b, bench_ctx| {
+        b.iter(|| bench_ctx.encrypt_all())
+    });
 }
 
 fn pfse_bench_on_real(c: &mut Criterion) {
-    let mut vec = read_csv_exact("./data/test.csv", "order_number").unwrap();
-    vec.shuffle(&mut OsRng);
-
-    // Benchmark with different input sizes.
-    let mut group = c.benchmark_group("pfse_init_bench_on_real");
-    for size in [100, 1000, 10000, 100000, 1000000] {
-        for lambda in [0.25, 0.5, 0.75, 1.0] {
-            let slice = &vec[..size];
-
-            group.throughput(Throughput::Elements(size as u64));
-            group.bench_with_input(
-                BenchmarkId::from_parameter(format!("{}_{}", size, lambda)),
-                &(size, lambda),
-                |b, (_, lambda)| {
-                    b.iter(|| {
-                        let mut ctx = ContextPFSE::default();
-                        ctx.key_generate();
-                        ctx.set_params(*lambda, 1.0, 2_f64.powf(-10_f64));
-
-                        ctx.partition(slice, &exponential);
-                        ctx.transform();
-                        ctx.smooth()
-                    })
-                },
-            );
-        }
-    }
-    group.finish();
+    bench_scheme(
+        c,
+        FSEType::Pfse,
+        "init",
+        BenchDb::Offline,
+        |b, bench_ctx| b.iter(|| bench_ctx.rebuild()),
+    );
 }
 
 fn lpfse_ihbe_on_real(c: &mut Criterion) {
-    let mut vec = read_csv_exact("./data/test.csv", "order_number").unwrap();
-    vec.shuffle(&mut OsRng);
-
-    let mut group = c.benchmark_group("lpfse_ihbe_init_bench_on_real");
-    for size in [100, 1000, 10000, 100000, 1000000] {
-        let slice = &vec[..size];
-
-        group.throughput(Throughput::Elements(size as u64));
-        group.bench_with_input(
-            BenchmarkId::from_parameter(size),
-            &size,
-            |b, _| {
-                b.iter(|| {
-                    let mut ctx = ContextLPFSE::new(
-                        2f64.powf(-10_f64),
-                        Box::new(EncoderIHBE::new()),
-                    );
-                    ctx.key_generate();
-                    ctx.initialize(slice, "", "", false);
-
-                    for message in slice.iter() {
-                        ctx.encrypt(message).unwrap();
-                    }
-                })
-            },
-        );
-    }
-    group.finish();
+    bench_scheme(
+        c,
+        FSEType::LpfseIhbe,
+        "init",
+        BenchDb::Offline,
+        |b, bench_ctx| b.iter(|| bench_ctx.rebuild()),
+    );
 }
 
 fn lpfse_bhe_on_real(c: &mut Criterion) {
-    let mut vec = read_csv_exact("./data/test.csv", "order_number").unwrap();
-    vec.shuffle(&mut OsRng);
-
-    let mut group = c.benchmark_group("lpfse_bhe_init_bench_on_real");
-    for size in [100, 1000, 10000, 100000, 1000000] {
-        let slice = &vec[..size];
-
-        group.throughput(Throughput::Elements(size as u64));
-        group.bench_with_input(
-            BenchmarkId::from_parameter(size),
-            &size,
-            |b, _| {
-                let mut ctx = ContextLPFSE::new(
-                    2f64.powf(-10_f64),
-                    Box::new(EncoderBHE::new()),
-                );
-                ctx.key_generate();
-                ctx.initialize(slice, "", "", false);
-
-                b.iter(|| {
-                    for message in slice.iter() {
-                        ctx.encrypt(message).unwrap();
-                    }
-                })
-            },
-        );
-    }
-    group.finish();
+    bench_scheme(
+        c,
+        FSEType::LpfseBhe,
+        "init",
+        BenchDb::Offline,
+        |b, bench_ctx| b.iter(|| bench_ctx.encrypt_all()),
+    );
 }
 
 fn rnd_bench_on_real(c: &mut Criterion) {
-    let mut vec = read_csv_exact("./data/test.csv", "order_number").unwrap();
-    vec.shuffle(&mut OsRng);
-
-    let mut group = c.benchmark_group("rnd_init_bench_on_real");
-    for size in [100, 1000, 10000, 100000, 1000000] {
-        let mut ctx = ContextNative::new(true);
-        let slice = &vec[..size];
-        ctx.key_generate();
-
-        group.throughput(Throughput::Elements(size as u64));
-        group.bench_with_input(
-            BenchmarkId::from_parameter(size),
-            &size,
-            |b, _| {
-                b.iter(|| {
-                    for message in slice.iter() {
-                        ctx.encrypt(message).unwrap();
-                    }
-                })
-            },
-        );
-    }
-
-    group.finish();
+    bench_scheme(c, FSEType::Rnd, "init", BenchDb::Offline, |b, bench_ctx| {
+        b.iter(|| bench_ctx.encrypt_all())
+    });
 }
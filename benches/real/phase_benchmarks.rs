@@ -0,0 +1,20 @@
+use criterion::{criterion_group, Criterion};
+use fse::bench_support::{bench_pfse_phase, PfsePhase};
+
+criterion_group! {
+    name = fse_benches_phase_real;
+    config = Criterion::default().significance_level(0.1).sample_size(10);
+    targets = pfse_partition_on_real, pfse_transform_on_real, pfse_smooth_on_real
+}
+
+fn pfse_partition_on_real(c: &mut Criterion) {
+    bench_pfse_phase(c, PfsePhase::Partition);
+}
+
+fn pfse_transform_on_real(c: &mut Criterion) {
+    bench_pfse_phase(c, PfsePhase::Transform);
+}
+
+fn pfse_smooth_on_real(c: &mut Criterion) {
+    bench_pfse_phase(c, PfsePhase::Smooth);
+}
@@ -1,18 +1,26 @@
-use std::{
-    collections::HashMap,
-    fs::{File, OpenOptions},
-    hash::Hash,
-    io::{Read, Write},
-};
+use std::{collections::HashMap, fs::OpenOptions, io::Write};
 
 use chrono::Local;
 use fse::{
-    attack::{AttackType, LpAttacker, MLEAttacker},
-    fse::{exponential, BaseCrypto, PartitionFrequencySmoothing, ValueType},
+    attack::{
+        composition_leakage, decile_accuracy, precision_recall_curve,
+        rank_accuracy, AttackMeta, AttackType, CompositionLeakage, Guess,
+        LeakageCollector, LpAttacker, MLEAttacker, OrderAttacker,
+        PersistentView, PrecisionRecall, Recovery, SaltCountAttacker,
+        ServerView,
+    },
+    db::{new_run_id, CollectionId, Connector, Data},
+    fse::{BaseCrypto, PartitionFrequencySmoothing, ValueType},
     lpfse::{ContextLPFSE, EncoderBHE, EncoderIHBE, HomophoneEncoder},
     native::ContextNative,
     pfse::ContextPFSE,
-    util::read_csv_multiple,
+    plain::ContextPlain,
+    preprocess::{CapOverflow, Preprocess},
+    util::{
+        build_histogram, build_histogram_vec, checked_div, sample_or_cycle,
+        ZipfMixture,
+    },
+    wre::ContextWRE,
 };
 use itertools::Itertools;
 use log::{debug, info, warn};
@@ -21,47 +29,89 @@ use rand_core::OsRng;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    config::{AttackConfig, FSEType},
+    config::{
+        random_seed, read_columns, AttackConfig, FSEType, PersistentConfig,
+    },
+    perf::insert_load,
+    queue::SuiteQueue,
     Args, Result,
 };
 
-/// A struct that contains the metadata for the attack.
-#[derive(Debug)]
-struct AttackMeta<T>
-where
-    T: Eq + Hash,
-{
-    correct: HashMap<T, Vec<Vec<u8>>>,
-    local_table: HashMap<T, Vec<ValueType>>,
-    raw_ciphertexts: Vec<Vec<u8>>,
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct ColumnResult {
+    pub column_name: String,
+    pub accuracy: f64,
+    /// The accuracy within each frequency decile of the plaintexts, from the most frequent to the least. A decile
+    /// with no message, which happens with fewer than ten distinct messages, is `nan`.
+    pub decile_accuracy: Vec<f64>,
+    /// The accuracy on the 10% most frequent plaintexts.
+    pub head_accuracy: f64,
+    /// The accuracy on the remaining 90%.
+    pub tail_accuracy: f64,
+    /// The analytical advantage bound reported next to the empirical accuracy.
+    pub advantage_bound: Option<f64>,
+    /// The largest total-variation distance between the ciphertext histogram observed in the live collection and
+    /// the simulated one. Present only if the attack is mounted against a live collection.
+    pub live_distance: Option<f64>,
+    /// The accuracy of the ordering attack. Present only if the insertion order is attacked.
+    pub order_accuracy: Option<f64>,
+    /// The fraction of the observed ciphertexts that are dummies. Present only if the scheme adds dummies.
+    pub dummy_mass: Option<f64>,
+    /// The accuracy of the same attack against the ciphertexts a search can reach, i.e., with the dummies removed.
+    /// Present only if the scheme adds dummies.
+    pub accuracy_without_dummies: Option<f64>,
+    /// The fraction of the dummies a server filtering the stored messages by their format keeps, i.e., cannot tell
+    /// from the real ones. Present only if the scheme adds dummies.
+    pub dummy_realism: Option<f64>,
+    /// The accuracy of the same attack after the server has filtered the dummies by their format. Present only if the
+    /// scheme adds dummies.
+    pub accuracy_filtered: Option<f64>,
+    /// The precision and the recall of the guesses the attacker trusts most, from the most trusted fraction to all of
+    /// them; the thresholds are those of the last round. Present only if requested and the attack scores its guesses.
+    pub precision_recall: Option<Vec<PrecisionRecall>>,
+    /// The fraction of the column diverted by the frequency cap. The accuracy is that of the occurrences within the
+    /// cap. Present only if the column is capped.
+    pub diverted_mass: Option<f64>,
+    /// The accuracy over the whole column, counting the occurrences diverted to RND as not recovered as their
+    /// ciphertexts are unlinkable. Present only if the overflow of the cap is encrypted by RND.
+    pub column_accuracy: Option<f64>,
+    /// The accuracy of an adversary that attacks each snapshot taken after a batch of updates alone. Present only if
+    /// the persistent adversary is simulated.
+    pub snapshot_accuracy: Option<Vec<f64>>,
+    /// The accuracy of an adversary that diffs the snapshots to isolate the updates, at each snapshot. Present only
+    /// if the persistent adversary is simulated.
+    pub persistent_accuracy: Option<Vec<f64>>,
+    /// The leakage of the column encrypted into both the suite's collection and a second one whose records the
+    /// attacker links to it. Present only if the composition is analyzed.
+    pub composition: Option<CompositionLeakage>,
 }
 
+/// The joint result of all the columns of a suite.
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "snake_case")]
-struct MainResult {
-    accuracy: f64,
-    column_name: String,
+pub struct MainResult {
+    /// The mean accuracy over all columns.
+    pub mean_accuracy: f64,
+    /// The highest accuracy among all columns.
+    pub max_accuracy: f64,
+    pub columns: Vec<ColumnResult>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "snake_case")]
-struct AttackResult {
-    result: MainResult,
-    config: AttackConfig,
+pub struct AttackResult {
+    pub result: MainResult,
+    pub config: AttackConfig,
 }
 
 /// Execute the attack given the CLI arguments.
 pub fn execute_attack(args: &Args) -> Result<()> {
-    // Parse the toml.
-    let mut file = File::open(&args.config_path)?;
-    let mut content = Vec::new();
-    file.read_to_end(&mut content)?;
-
-    let mut test_suites =
-        toml::from_slice::<HashMap<String, Vec<AttackConfig>>>(&content)?
-            .remove("test_suites")
-            .unwrap();
-    test_suites.truncate(args.suite_num.unwrap_or(test_suites.len()));
+    let mut test_suites = SuiteQueue::<AttackConfig>::new(
+        &args.config_path,
+        args.watch,
+        args.suite_num,
+    )?;
 
     let mut file = match args.output_path.as_ref() {
         Some(path) => OpenOptions::new().append(true).create(true).open(path),
@@ -74,307 +124,738 @@ pub fn execute_attack(args: &Args) -> Result<()> {
         }
     }?;
 
-    for (idx, config) in test_suites.into_iter().enumerate() {
+    // Suites often attack the same columns of the same file, so the parsed datasets are kept across suites.
+    let mut datasets = HashMap::new();
+    while let Some((idx, config)) = test_suites.next_suite() {
         info!("#{:<04}: Doing attack evaluations...", idx + 1,);
         debug!("The configuration is {:#?}", config);
 
-        if config.attributes.is_none() {
-            return Err("Unsupported feature for `all`...".into());
-        }
-
-        let mut dataset = read_csv_multiple(
-            &config.data_path,
-            config.attributes.as_ref().unwrap().as_slice(),
-        )?;
-
-        if config.shuffle {
-            dataset.iter_mut().for_each(|v| v.shuffle(&mut OsRng))
-        }
+        let attributes = match config.attributes.as_ref() {
+            Some(attributes) => attributes,
+            None => return Err("Unsupported feature for `all`...".into()),
+        };
 
+        let key = (
+            config.data_path.clone(),
+            attributes.clone(),
+            config.csv.clone(),
+        );
+        let dataset = match datasets.get(&key) {
+            Some(dataset) => Vec::clone(dataset),
+            None => {
+                let dataset = read_columns(
+                    &config.data_path,
+                    attributes.as_slice(),
+                    config.csv.as_ref(),
+                )?;
+                datasets.insert(key, dataset.clone());
+                dataset
+            }
+        };
         info!("Dataset read finished.");
+        let result = attack_suite(args.round, &config, dataset)?;
+
+        // Store the joint attack result of the suite.
+        let mut toml = HashMap::new();
+        toml.insert("attack_result".to_string(), vec![result]);
+        let content = toml::Value::try_from(&toml)?.to_string();
+        file.write_all(content.as_bytes())?;
+        file.write_all(b"\n")?;
+    }
 
-        for (idx, &accuracy) in
-            do_attack(args.round, &config, &dataset)?.iter().enumerate()
-        {
-            let column_name = config
-                .attributes
-                .as_ref()
-                .unwrap()
-                .get(idx)
-                .unwrap()
-                .clone();
-            let result = AttackResult {
-                config: config.clone(),
-                result: MainResult {
-                    column_name,
-                    accuracy,
-                },
-            };
+    Ok(())
+}
 
-            // Store the attack result.
-            let mut toml = HashMap::new();
-            toml.insert("attack_result".to_string(), vec![result]);
-            let content = toml::to_vec(&toml)?;
-            file.write_all(content.as_slice())?;
-            file.write_all(b"\n")?;
-        }
+/// Attack the columns of `dataset`, which are those named by the `attributes` of `config`, for `round` rounds. The
+/// columns are preprocessed and shuffled as configured.
+pub fn attack_suite(
+    round: usize,
+    config: &AttackConfig,
+    mut dataset: Vec<Vec<String>>,
+) -> Result<AttackResult> {
+    let mut config = config.clone();
+    let seed = *config.seed.get_or_insert_with(random_seed);
+    info!("The columns are sampled with seed {}.", seed);
+    let attributes = match config.attributes.as_ref() {
+        Some(attributes) => attributes,
+        None => return Err("Unsupported feature for `all`...".into()),
+    };
+    if let Some(preprocess) = config.preprocess.as_ref() {
+        dataset = dataset.iter().map(|e| preprocess.apply_all(e)).collect();
+    }
+    if config.shuffle {
+        dataset.iter_mut().for_each(|v| v.shuffle(&mut OsRng))
     }
 
-    Ok(())
+    let columns = do_attack(round, &config, &dataset)?
+        .into_iter()
+        .zip(attributes.iter())
+        .map(|(res, column_name)| ColumnResult {
+            column_name: column_name.clone(),
+            accuracy: res.accuracy,
+            decile_accuracy: res.decile_accuracy,
+            head_accuracy: res.head_accuracy,
+            tail_accuracy: res.tail_accuracy,
+            advantage_bound: res.bound,
+            live_distance: res.live_distance,
+            order_accuracy: res.order_accuracy,
+            dummy_mass: res.dummy_mass,
+            accuracy_without_dummies: res.accuracy_without_dummies,
+            dummy_realism: res.dummy_realism,
+            accuracy_filtered: res.accuracy_filtered,
+            precision_recall: res.precision_recall,
+            diverted_mass: res.diverted_mass,
+            column_accuracy: res.column_accuracy,
+            snapshot_accuracy: res.snapshot_accuracy,
+            persistent_accuracy: res.persistent_accuracy,
+            composition: res.composition,
+        })
+        .collect::<Vec<_>>();
+    let accuracies = columns.iter().map(|e| e.accuracy).collect_vec();
+    Ok(AttackResult {
+        config,
+        result: MainResult {
+            mean_accuracy: accuracies.iter().sum::<f64>()
+                / accuracies.len().max(1) as f64,
+            max_accuracy: accuracies.into_iter().fold(0.0, f64::max),
+            columns,
+        },
+    })
+}
+
+/// The attack result of a single column averaged over all rounds.
+#[derive(Debug, Clone, Default)]
+struct ColumnMeasurement {
+    accuracy: f64,
+    decile_accuracy: Vec<f64>,
+    head_accuracy: f64,
+    tail_accuracy: f64,
+    bound: Option<f64>,
+    live_distance: Option<f64>,
+    order_accuracy: Option<f64>,
+    dummy_mass: Option<f64>,
+    accuracy_without_dummies: Option<f64>,
+    dummy_realism: Option<f64>,
+    accuracy_filtered: Option<f64>,
+    precision_recall: Option<Vec<PrecisionRecall>>,
+    diverted_mass: Option<f64>,
+    column_accuracy: Option<f64>,
+    snapshot_accuracy: Option<Vec<f64>>,
+    persistent_accuracy: Option<Vec<f64>>,
+    composition: Option<CompositionLeakage>,
 }
 
+/// Attack every column of the dataset for `round` rounds and return the mean accuracy, the advantage bound and the
+/// live distance of each column. With cross-validation, each round attacks every fold and the accuracy is averaged
+/// over the folds as well.
 fn do_attack(
     round: usize,
     config: &AttackConfig,
     dataset: &[Vec<String>],
-) -> Result<Vec<f64>> {
-    let mut res = Vec::new();
-
-    for data in dataset.iter() {
-        let mut accuracy = 0f64;
-        // Run multiple rounds.
-        for idx in 1..=round {
-            info!("Round #{:<04} started.", idx);
-            accuracy += match config.attack_type {
-                AttackType::LpOptimization => lp_optimization(config, data)?,
-                AttackType::MleAttack => mle_attack(config, data)?,
+) -> Result<Vec<ColumnMeasurement>> {
+    if matches!(config.attack_type, AttackType::LpOptimization)
+        && config.p_norm.is_none()
+    {
+        return Err("No p_norm found. Check configuration file.".into());
+    }
+
+    // The bound and the distance depend on the smoothing state of each round; report the loosest ones.
+    let max = |lhs: Option<f64>, rhs: Option<f64>| match (lhs, rhs) {
+        (Some(lhs), Some(rhs)) => Some(lhs.max(rhs)),
+        (lhs, rhs) => lhs.or(rhs),
+    };
+
+    let conn = match config.live.as_ref() {
+        Some(live) => Some(Connector::<Data>::new(
+            &live.addr,
+            &live.db_name,
+            live.drop,
+        )?),
+        None => None,
+    };
+
+    if matches!(config.folds, Some(k) if k < 2) {
+        return Err("Cross-validation needs at least two folds.".into());
+    }
+
+    // The live collection of each column; the suites of a run never share one.
+    let run = format!("attack-{}", new_run_id());
+    let id = |column: usize| {
+        let name = config
+            .attributes
+            .as_ref()
+            .and_then(|e| e.get(column))
+            .cloned()
+            .unwrap_or_else(|| column.to_string());
+        CollectionId::new(&config.fse_type, &name)
+            .params(config.fse_params.as_ref())
+            .run(&run)
+    };
+
+    // Resolved and recorded by the suite.
+    let seed = config.seed.unwrap_or_else(random_seed);
+    let mut res = vec![ColumnMeasurement::default(); dataset.len()];
+    for idx in 1..=round {
+        info!("Round #{:<04} started.", idx);
+        for (column, data) in dataset.iter().enumerate() {
+            // A column smaller than the size of the suite is sampled with replacement up to it. A large enough one
+            // is kept in its order, which the ordering attack depends on.
+            let resampled;
+            let data = match config.size {
+                Some(size) if size > data.len() => {
+                    resampled = sample_or_cycle(
+                        data,
+                        size,
+                        seed.wrapping_add(idx as u64),
+                    );
+                    &resampled
+                }
+                _ => data,
+            };
+            let splits = match config.folds {
+                Some(k) => {
+                    let size =
+                        config.size.unwrap_or(data.len()).min(data.len());
+                    k_fold(&data[..size], k)
+                        .into_iter()
+                        .map(|(target, auxiliary)| (target, Some(auxiliary)))
+                        .collect()
+                }
+                None => vec![(data.clone(), None)],
             };
-            info!("Round #{:<04} finished.", idx);
+
+            for (data, auxiliary) in splits {
+                let (mut data, diverted_mass) = match config.cap.as_ref() {
+                    Some(cap) => {
+                        let capped = cap.apply(&data);
+                        let diverted_mass = capped.diverted_mass();
+                        (capped.kept, Some(diverted_mass))
+                    }
+                    None => (data, None),
+                };
+                if matches!(config.ordering.as_ref(), Some(e) if e.sorted) {
+                    data.sort();
+                }
+                if let Some(persistent) = config.persistent.as_ref() {
+                    let (snapshot, accuracy) =
+                        attack_persistent(config, persistent, &data)?;
+                    accumulate(&mut res[column].snapshot_accuracy, snapshot);
+                    accumulate(&mut res[column].persistent_accuracy, accuracy);
+                }
+
+                let mut meta = collect_meta(
+                    config,
+                    &data,
+                    auxiliary.as_deref(),
+                    seed.wrapping_add(idx as u64),
+                )?;
+                if let Some(composition) = config.composition.as_ref() {
+                    let leakage = attack_composition(
+                        &composition.second(config),
+                        &data,
+                        &meta,
+                    )?;
+                    let sum = res[column]
+                        .composition
+                        .get_or_insert_with(CompositionLeakage::default);
+                    sum.first += leakage.first;
+                    sum.second += leakage.second;
+                    sum.joint += leakage.joint;
+                    sum.unique += leakage.unique;
+                }
+                if let Some(ordering) = config.ordering.as_ref() {
+                    let mut sequence = meta.sequence.clone();
+                    ordering.order.arrange(&mut sequence);
+                    let accuracy = OrderAttacker::new().attack(
+                        &meta.correct,
+                        &data[..sequence.len()],
+                        &sequence,
+                    );
+                    res[column].order_accuracy = Some(
+                        res[column].order_accuracy.unwrap_or_default()
+                            + accuracy,
+                    );
+                }
+
+                if let Some(conn) = conn.as_ref() {
+                    let distance = observe_live(conn, &id(column), &mut meta)?;
+                    res[column].live_distance =
+                        max(res[column].live_distance, Some(distance));
+                }
+                if !meta.dummies.is_empty() {
+                    let (accuracy, ..) = run_attack(
+                        config,
+                        &meta,
+                        &meta.reachable_ciphertexts(),
+                    );
+                    let measurement = &mut res[column];
+                    measurement.accuracy_without_dummies = Some(
+                        measurement
+                            .accuracy_without_dummies
+                            .unwrap_or_default()
+                            + accuracy,
+                    );
+                    measurement.dummy_mass = Some(
+                        measurement.dummy_mass.unwrap_or_default()
+                            + meta.dummy_mass(),
+                    );
+
+                    let (accuracy, ..) =
+                        run_attack(config, &meta, &meta.filtered_ciphertexts());
+                    let measurement = &mut res[column];
+                    measurement.accuracy_filtered = Some(
+                        measurement.accuracy_filtered.unwrap_or_default()
+                            + accuracy,
+                    );
+                    measurement.dummy_realism = Some(
+                        measurement.dummy_realism.unwrap_or_default()
+                            + meta.dummy_realism(),
+                    );
+                }
+                let (accuracy, recovery, guesses) =
+                    run_attack(config, &meta, &meta.raw_ciphertexts);
+                let measurement = &mut res[column];
+                measurement.accuracy += accuracy;
+                if let (Some(points), Some(guesses)) =
+                    (config.precision_recall_points, guesses)
+                {
+                    let curve = precision_recall_curve(&guesses, points);
+                    match measurement.precision_recall.as_mut() {
+                        Some(sum) => {
+                            sum.iter_mut().zip(curve).for_each(|(lhs, rhs)| {
+                                lhs.threshold = rhs.threshold;
+                                lhs.precision += rhs.precision;
+                                lhs.recall += rhs.recall;
+                            })
+                        }
+                        None => measurement.precision_recall = Some(curve),
+                    }
+                }
+                if let Some(diverted_mass) = diverted_mass {
+                    measurement.diverted_mass = Some(
+                        measurement.diverted_mass.unwrap_or_default()
+                            + diverted_mass,
+                    );
+                    if config.cap.map(|e| e.overflow) == Some(CapOverflow::Rnd)
+                    {
+                        measurement.column_accuracy = Some(
+                            measurement.column_accuracy.unwrap_or_default()
+                                + accuracy * (1.0 - diverted_mass),
+                        );
+                    }
+                }
+                measurement.head_accuracy += rank_accuracy(&recovery, 0.0..0.1);
+                measurement.tail_accuracy += rank_accuracy(&recovery, 0.1..1.0);
+                let deciles = decile_accuracy(&recovery);
+                match measurement.decile_accuracy.is_empty() {
+                    true => measurement.decile_accuracy = deciles,
+                    false => measurement
+                        .decile_accuracy
+                        .iter_mut()
+                        .zip(deciles)
+                        .for_each(|(lhs, rhs)| *lhs += rhs),
+                }
+                res[column].bound = max(res[column].bound, meta.bound);
+            }
         }
-        accuracy /= round as f64;
+        info!("Round #{:<04} finished.", idx);
+    }
 
+    // Each fold of each round is one measurement.
+    let measurements = (round * config.folds.unwrap_or(1)) as f64;
+    for measurement in res.iter_mut() {
+        measurement.accuracy /= measurements;
+        measurement.head_accuracy /= measurements;
+        measurement.tail_accuracy /= measurements;
+        measurement
+            .decile_accuracy
+            .iter_mut()
+            .for_each(|e| *e /= measurements);
+        measurement.order_accuracy =
+            measurement.order_accuracy.map(|e| e / measurements);
+        measurement.dummy_mass =
+            measurement.dummy_mass.map(|e| e / measurements);
+        measurement.accuracy_without_dummies = measurement
+            .accuracy_without_dummies
+            .map(|e| e / measurements);
+        measurement.dummy_realism =
+            measurement.dummy_realism.map(|e| e / measurements);
+        measurement.accuracy_filtered =
+            measurement.accuracy_filtered.map(|e| e / measurements);
+        measurement
+            .precision_recall
+            .iter_mut()
+            .flatten()
+            .for_each(|e| {
+                e.precision /= measurements;
+                e.recall /= measurements;
+            });
+        measurement.diverted_mass =
+            measurement.diverted_mass.map(|e| e / measurements);
+        measurement.column_accuracy =
+            measurement.column_accuracy.map(|e| e / measurements);
+        if let Some(composition) = measurement.composition.as_mut() {
+            composition.first /= measurements;
+            composition.second /= measurements;
+            composition.joint /= measurements;
+            composition.unique /= measurements;
+            warn!(
+                "[+] Linking a second collection raises the recovery rate from {} and {} to {}, singling out {} of the records.",
+                composition.first, composition.second, composition.joint, composition.unique
+            );
+        }
+        for series in [
+            &mut measurement.snapshot_accuracy,
+            &mut measurement.persistent_accuracy,
+        ] {
+            series.iter_mut().flatten().for_each(|e| *e /= measurements);
+        }
+        if let (Some(snapshot), Some(persistent)) = (
+            measurement.snapshot_accuracy.as_ref(),
+            measurement.persistent_accuracy.as_ref(),
+        ) {
+            warn!(
+                "[+] Over the snapshots, the accuracy of the snapshot adversary is {:?} and that of the persistent adversary is {:?}.",
+                snapshot, persistent
+            );
+        }
+        if let Some(diverted_mass) = measurement.diverted_mass {
+            warn!(
+                "[+] The cap diverted {} of the column; the accuracy over the whole column is {:?}.",
+                diverted_mass, measurement.column_accuracy
+            );
+        }
         warn!(
-            "[+] Attack {:?} finished against {:?}. The accuracy is {}.",
-            config.attack_type, &config.fse_type, accuracy
+            "[+] Attack {:?} finished against {:?}. The accuracy is {} (head {}, tail {}), the advantage bound is {:?}, the live distance is {:?}, and the ordering accuracy is {:?}. The dummies are {:?} of the ciphertexts, without which the accuracy is {:?}.",
+            config.attack_type, &config.fse_type, measurement.accuracy, measurement.head_accuracy, measurement.tail_accuracy, measurement.bound, measurement.live_distance, measurement.order_accuracy, measurement.dummy_mass, measurement.accuracy_without_dummies
         );
-
-        res.push(accuracy);
     }
 
     Ok(res)
 }
 
-fn mle_attack(config: &AttackConfig, data: &[String]) -> Result<f64> {
-    let meta = collect_meta(config, data)?;
-
-    info!("Mounting mle_attack...");
-    let mut attacker = MLEAttacker::new();
-    Ok(
-        attacker.attack(
-            &meta.correct,
-            &meta.local_table,
-            &meta.raw_ciphertexts,
-        ),
-    )
+/// Encrypt `data` again under `second`, the configuration of the second collection, with a fresh key, and compute the
+/// leakage of linking its records to those of `meta`, collected from the same `data` by [`collect_meta`].
+fn attack_composition(
+    second: &AttackConfig,
+    data: &[String],
+    meta: &AttackMeta<String>,
+) -> Result<CompositionLeakage> {
+    let size = second.size.unwrap_or(data.len()).min(data.len());
+    info!(
+        "Collecting meta of the second collection under {:?}...",
+        second.fse_type
+    );
+    let other = init_collector(second)?.collect_leakage(&data[..size])?;
+    composition_leakage(&data[..size], meta, &other)
 }
 
-fn lp_optimization(config: &AttackConfig, data: &[String]) -> Result<f64> {
-    let meta = collect_meta(config, data)?;
-
-    let p_norm = match config.p_norm {
-        Some(p) => p,
-        None => return Err("No p_norm found. Check configuration file.".into()),
-    };
+/// Deploy the simulated ciphertexts into a live collection through the same load path as the perf evaluation, then
+/// let a key-less server group them by ciphertext and use its histogram as the ciphertexts of the attack. Returns the
+/// distance between the observed and the simulated ciphertext histograms.
+fn observe_live(
+    conn: &Connector<Data>,
+    id: &CollectionId,
+    meta: &mut AttackMeta<String>,
+) -> Result<f64> {
+    let name = id.name();
+    conn.drop_collection(&name);
+    insert_load(conn, &meta.raw_ciphertexts, &name, false)?;
+
+    let view = ServerView::observe_histogram(conn, &name)?;
+    let distance = view.distance(&meta.raw_ciphertexts);
+    if distance > 0.0 {
+        warn!(
+            "[!] The live collection {} deviates from the simulation by {}.",
+            name, distance
+        );
+    }
 
-    info!("Mounting l{}_optimization attack...", p_norm);
-    let mut attacker = LpAttacker::new(p_norm as usize);
-    Ok(
-        attacker.attack(
-            &meta.correct,
-            &meta.local_table,
-            &meta.raw_ciphertexts,
-        ),
-    )
+    meta.raw_ciphertexts = view.get_ciphertexts().to_vec();
+    conn.drop_collection(&name);
+    Ok(distance)
 }
 
-fn collect_meta(
+/// Set the scheme up with the first `initial` of `data` and insert the rest in `batches` batches, taking a snapshot of
+/// the store after each. Returns the accuracy at each snapshot but the first of a snapshot adversary, which attacks
+/// the snapshot alone, and of a persistent adversary, which attacks the initial load in the first snapshot and the
+/// updates isolated by diffing the snapshots. Both are over every record stored at the snapshot.
+fn attack_persistent(
     config: &AttackConfig,
+    persistent: &PersistentConfig,
     data: &[String],
-) -> Result<AttackMeta<String>> {
+) -> Result<(Vec<f64>, Vec<f64>)> {
     let size = config.size.unwrap_or(data.len()).min(data.len());
-    let data_slice = &data[..size];
-    let meta = match config.fse_type {
-        FSEType::Dte | FSEType::Rnd => collect_meta_native(config, data_slice),
-        FSEType::Pfse => collect_meta_pfse(config, data_slice),
-        FSEType::LpfseBhe | FSEType::LpfseIhbe => {
-            collect_meta_lpfse(config, data_slice)
-        }
-        FSEType::Wre => todo!(),
-    };
-
-    info!("Meta collected.");
-    meta
-}
-
-fn collect_meta_lpfse(
-    config: &AttackConfig,
-    data: &[String],
-) -> Result<AttackMeta<String>> {
-    let params = match &config.fse_params {
-        Some(params) => params,
-        None => return Err("Parameter not found.".into()),
-    };
+    if size == 0 {
+        return Err("Cannot insert an empty column over time.".into());
+    }
+    let initial =
+        ((size as f64 * persistent.initial).round() as usize).clamp(1, size);
+    let mut ctx = init_collector(config)?;
+    let mut meta = ctx.collect_leakage(&data[..initial])?;
+
+    // The schemes set up with the dataset cannot encrypt the messages they have not seen, so such updates are left
+    // out rather than triggering a new setup.
+    let updates = data[initial..size]
+        .iter()
+        .filter(|e| meta.correct.contains_key(*e))
+        .cloned()
+        .collect_vec();
+    if updates.len() < size - initial {
+        info!(
+            "{} updates of messages unknown at setup are left out.",
+            size - initial - updates.len()
+        );
+    }
 
-    if params.len() != 1 {
-        return Err(format!(
-            "Parameter size is not correct. Expect 1, but got {}.",
-            params.len()
-        )
-        .into());
+    let mut view = PersistentView::new();
+    view.observe(&meta.raw_ciphertexts);
+    let (initial_accuracy, ..) =
+        run_attack(config, &meta, &meta.raw_ciphertexts);
+
+    let mut inserted = Vec::new();
+    let mut inserted_ciphertexts = Vec::new();
+    let mut snapshot_accuracy = Vec::new();
+    let mut persistent_accuracy = Vec::new();
+    // The batches are of equal sizes up to rounding, so the series of all rounds have the same length.
+    let bound = |i: usize| i * updates.len() / persistent.batches;
+    for i in 0..persistent.batches {
+        let batch = &updates[bound(i)..bound(i + 1)];
+        let ciphertexts = batch
+            .iter()
+            .map(|message| ctx.encrypt_update(message))
+            .collect::<Result<Vec<_>>>()?;
+        meta.insert(batch, &ciphertexts);
+        view.observe(&meta.raw_ciphertexts);
+        inserted.extend_from_slice(batch);
+        inserted_ciphertexts.extend(ciphertexts);
+
+        let (accuracy, ..) = run_attack(config, &meta, &meta.raw_ciphertexts);
+        snapshot_accuracy.push(accuracy);
+
+        // The isolated updates are scored against their own ground truth.
+        let updates_meta =
+            AttackMeta::from_records(&inserted, &inserted_ciphertexts);
+        let (accuracy, ..) =
+            run_attack(config, &updates_meta, &view.inserted());
+        let stored = (initial + inserted.len()) as f64;
+        persistent_accuracy.push(
+            (initial_accuracy * initial as f64
+                + accuracy * inserted.len() as f64)
+                / stored,
+        );
     }
 
-    info!("Collecting meta for attack against LPFSE scheme...");
+    Ok((snapshot_accuracy, persistent_accuracy))
+}
 
-    let encoder: Box<dyn HomophoneEncoder<String>> = match config.fse_type {
-        FSEType::LpfseIhbe => Box::new(EncoderIHBE::new()),
-        FSEType::LpfseBhe => Box::new(EncoderBHE::new()),
-        _ => return Err("Not an LPFSE type.".into()),
-    };
-    let mut ctx = ContextLPFSE::new(params[0], encoder);
-    ctx.key_generate();
-    ctx.initialize(data, "", "", false);
-
-    let mut ciphertext_sets = HashMap::new();
-    let mut raw_ciphertexts = Vec::new();
-    for message in data.iter() {
-        let ciphertext = ctx.encrypt(message).unwrap().remove(0);
-        let entry = ciphertext_sets
-            .entry(message.clone())
-            .or_insert_with(Vec::new);
-        entry.push(ciphertext.clone());
-        raw_ciphertexts.push(ciphertext);
+/// Add `values` elementwise to the series accumulated so far.
+fn accumulate(series: &mut Option<Vec<f64>>, values: Vec<f64>) {
+    match series {
+        Some(series) => series
+            .iter_mut()
+            .zip(values)
+            .for_each(|(lhs, rhs)| *lhs += rhs),
+        None => *series = Some(values),
     }
+}
 
-    // Construct the local table.
-    let mut correct = HashMap::new();
-    let local_table = {
-        let mut local_table = HashMap::new();
-        for (message, count) in ctx.get_encoder().local_table().iter() {
-            let ciphertexts = match ciphertext_sets.get(message) {
-                Some(v) => v.iter().unique().cloned().collect::<Vec<_>>(),
-                None => {
-                    return Err(
-                        "Message not found in the ciphertext sets map.".into()
-                    )
+/// Mount the attack specified in the configuration against the collected meta, observing `raw_ciphertexts`. Returns
+/// the accuracy, the recovery of each message and the guesses if the attack scores them.
+fn run_attack(
+    config: &AttackConfig,
+    meta: &AttackMeta<String>,
+    raw_ciphertexts: &[Vec<u8>],
+) -> (f64, Recovery<String>, Option<Vec<Guess<String>>>) {
+    match config.attack_type {
+        AttackType::MleAttack => {
+            info!("Mounting mle_attack...");
+            let mut attacker = MLEAttacker::new();
+            attacker.set_spill_threshold(config.spill_threshold);
+            let accuracy = attacker.attack(
+                &meta.correct,
+                &meta.local_table,
+                raw_ciphertexts,
+            );
+            (
+                accuracy,
+                attacker.get_recovery().cloned().unwrap_or_default(),
+                attacker.get_guesses().cloned(),
+            )
+        }
+        AttackType::LpOptimization => {
+            let p_norm = config.p_norm.unwrap_or_default();
+            info!("Mounting l{}_optimization attack...", p_norm);
+            let mut attacker = match config.regularization {
+                Some(epsilon) => {
+                    LpAttacker::with_regularization(p_norm as usize, epsilon)
                 }
+                None => LpAttacker::new(p_norm as usize),
             };
-
-            let size = ciphertexts.len();
-            correct.insert(message.clone(), ciphertexts);
-            local_table.insert(message.clone(), vec![(0, size, *count)]);
+            attacker.set_spill_threshold(config.spill_threshold);
+            let accuracy = attacker.attack(
+                &meta.correct,
+                &meta.local_table,
+                raw_ciphertexts,
+            );
+            (
+                accuracy,
+                attacker.get_recovery().cloned().unwrap_or_default(),
+                attacker.get_guesses().cloned(),
+            )
         }
-
-        local_table
-    };
-
-    Ok(AttackMeta {
-        correct,
-        local_table,
-        raw_ciphertexts,
-    })
+        AttackType::SaltCount => {
+            info!("Mounting salt_count attack...");
+            let mut attacker = SaltCountAttacker::new();
+            attacker.set_spill_threshold(config.spill_threshold);
+            let accuracy = attacker.attack(
+                &meta.correct,
+                &meta.local_table,
+                raw_ciphertexts,
+            );
+            (
+                accuracy,
+                attacker.get_recovery().cloned().unwrap_or_default(),
+                None,
+            )
+        }
+    }
 }
 
-fn collect_meta_pfse(
+/// Collect the meta of attacking `data`. If `auxiliary` is given, the attacker knows the histogram of `auxiliary`
+/// instead of that of `data`. The auxiliary of `aux_distance` is synthesized with `seed`.
+fn collect_meta(
     config: &AttackConfig,
     data: &[String],
+    auxiliary: Option<&[String]>,
+    seed: u64,
 ) -> Result<AttackMeta<String>> {
-    let params = match &config.fse_params {
-        Some(params) => params,
-        None => return Err("Parameter not found.".into()),
-    };
-
-    let mut ctx = ContextPFSE::default();
-    ctx.key_generate();
-    ctx.set_params(params);
-
-    ctx.partition(data, exponential);
-    info!("Partition finished.");
-
-    ctx.transform();
-    info!("Transform finished.");
+    let size = config.size.unwrap_or(data.len()).min(data.len());
+    let data_slice = &data[..size];
+    info!(
+        "Collecting meta for attack against {:?}...",
+        config.fse_type
+    );
+    let mut meta = init_collector(config)?.collect_leakage(data_slice)?;
+    info!("Meta collected.");
 
-    let mut ciphertext_sets = HashMap::new();
-    for message in data.iter().unique() {
-        let mut ciphertext = ctx.encrypt(message).unwrap();
-        ciphertext_sets
-            .entry(message.clone())
-            .or_insert_with(Vec::new)
-            .append(&mut ciphertext);
+    if let Some(auxiliary) = auxiliary {
+        let target_num = data_slice.len() as f64;
+        let auxiliary_num = auxiliary.len() as f64;
+        let counts = build_histogram(auxiliary)
+            .into_iter()
+            .map(|(message, count)| {
+                let count = count as f64 * target_num / auxiliary_num;
+                (message, count.round() as usize)
+            })
+            .collect();
+        rescale_local_table(&mut meta.local_table, &counts);
     }
-
-    let mut correct = HashMap::new();
-    let mut raw_ciphertexts = Vec::new();
-    for (k, v) in ciphertext_sets.iter() {
-        correct.insert(k.clone(), v.clone().into_iter().unique().collect_vec());
-        raw_ciphertexts.append(&mut v.clone());
+    if let Some(tv) = config.aux_distance {
+        perturb_local_table(&mut meta.local_table, tv, seed);
+        info!("Auxiliary perturbed with total-variation distance {}.", tv);
     }
 
-    // Append dummies into `raw_ciphertexts`.
-    for partitions in ctx.get_partitions().iter() {
-        for (message, cnt) in partitions.inner.iter() {
-            if !ctx.get_local_table().contains_key(message) {
-                raw_ciphertexts
-                    .append(&mut vec![message.clone().into_bytes(); *cnt]);
-            }
+    Ok(meta)
+}
+
+/// Replace the message counts known by the attacker with an auxiliary histogram synthesized from a Zipf-mixture fitted
+/// on the real histogram, such that the auxiliary is `tv`-far from the target in total-variation distance. Messages
+/// the auxiliary leaves out get a zero count.
+fn perturb_local_table(
+    local_table: &mut HashMap<String, Vec<ValueType>>,
+    tv: f64,
+    seed: u64,
+) {
+    let histogram = local_table
+        .iter()
+        .map(|(k, v)| (k.clone(), v.iter().map(|e| e.2).sum::<usize>()))
+        .collect::<HashMap<_, _>>();
+    let histogram_vec = build_histogram_vec(&histogram);
+    let model = ZipfMixture::fit(&histogram_vec, 2, 20);
+    debug!("Fitted Zipf-mixture: {:?}", model);
+
+    let counts = model
+        .sample_auxiliary(&histogram_vec, tv, seed)
+        .into_iter()
+        .collect();
+    rescale_local_table(local_table, &counts);
+}
+
+/// Scale the counts of each message in the local table to `counts[message]` while keeping how they are split among
+/// the entries of the message. Messages missing from `counts` are unknown to the attacker and get a zero count.
+fn rescale_local_table(
+    local_table: &mut HashMap<String, Vec<ValueType>>,
+    counts: &HashMap<String, usize>,
+) {
+    for (message, information) in local_table.iter_mut() {
+        let original = information.iter().map(|e| e.2).sum::<usize>();
+        let count = counts.get(message).copied().unwrap_or_default();
+        for value in information.iter_mut() {
+            value.2 = checked_div((value.2 * count) as f64, original as f64)
+                .unwrap_or_default()
+                .round() as usize;
         }
     }
+}
 
-    Ok(AttackMeta {
-        correct,
-        raw_ciphertexts,
-        local_table: ctx.get_local_table().clone(),
-    })
+/// Split `data` into `k` folds and pair each fold, which is the target of the attack, with the other folds, which
+/// are the auxiliary knowledge of the attacker.
+fn k_fold(data: &[String], k: usize) -> Vec<(Vec<String>, Vec<String>)> {
+    let bound = |i: usize| i * data.len() / k;
+    (0..k)
+        .map(|i| {
+            let target = data[bound(i)..bound(i + 1)].to_vec();
+            let auxiliary = data[..bound(i)]
+                .iter()
+                .chain(data[bound(i + 1)..].iter())
+                .cloned()
+                .collect();
+            (target, auxiliary)
+        })
+        .collect()
 }
 
-fn collect_meta_native(
+/// Construct the context of the scheme specified in the configuration with its key and parameters set.
+fn init_collector(
     config: &AttackConfig,
-    data: &[String],
-) -> Result<AttackMeta<String>> {
-    let rnd = config.fse_type == FSEType::Rnd;
-    let mut ctx = ContextNative::new(rnd);
-    ctx.key_generate();
-
-    let mut message_to_ciphertexts = HashMap::new();
-    let mut local_table = HashMap::new();
-
-    for message in data.iter() {
-        let ciphertext = match ctx.encrypt(message) {
-            Some(mut c) => c.remove(0),
-            None => {
-                return Err(
-                    "Error encrypting the message using native method.".into()
-                )
+) -> Result<Box<dyn LeakageCollector<String>>> {
+    let params = config.fse_params.as_ref();
+    let missing = || "Parameter not found.";
+    let ctx: Box<dyn LeakageCollector<String>> = match config.fse_type {
+        FSEType::Dte | FSEType::Rnd => {
+            let mut ctx = ContextNative::new(config.fse_type == FSEType::Rnd);
+            ctx.key_generate();
+            Box::new(ctx)
+        }
+        FSEType::Pfse => {
+            let mut ctx = ContextPFSE::default();
+            ctx.key_generate();
+            ctx.set_params(&params.ok_or_else(missing)?.pfse()?)?;
+            if let Some(dummies) = config.dummies {
+                ctx.set_dummy_generator(dummies.generator());
             }
-        };
-
-        message_to_ciphertexts
-            .entry(message.clone())
-            .or_insert_with(Vec::new)
-            .push(ciphertext);
-
-        let entry = local_table
-            .entry(message.clone())
-            .or_insert_with(|| vec![(0usize, 0usize, 0usize)]);
-        entry[0].2 += 1;
-
-        if rnd {
-            entry[0].1 += 1;
-        } else {
-            entry[0].1 = 1;
+            Box::new(ctx)
         }
-    }
-
-    // Collect meta from `ciphertext_sets`.
-    let mut correct = HashMap::new();
-    let mut raw_ciphertexts = Vec::new();
-    for (k, v) in message_to_ciphertexts.iter() {
-        correct.insert(k.clone(), v.clone().into_iter().unique().collect_vec());
-        raw_ciphertexts.append(&mut v.clone());
-    }
+        FSEType::Plain => Box::new(ContextPlain::new()),
+        FSEType::LpfseIhbe | FSEType::LpfseBhe => {
+            let encoder: Box<dyn HomophoneEncoder<String>> =
+                match config.fse_type {
+                    FSEType::LpfseIhbe => Box::new(EncoderIHBE::new()),
+                    _ => Box::new(EncoderBHE::new()),
+                };
+            let params = params.ok_or_else(missing)?.lpfse()?;
+            let mut ctx = ContextLPFSE::from_params(&params, encoder)?;
+            ctx.key_generate();
+            Box::new(ctx)
+        }
+        FSEType::Wre => {
+            let params = params.ok_or_else(missing)?.wre()?;
+            let mut ctx = ContextWRE::from_params(&params)?;
+            ctx.key_generate();
+            Box::new(ctx)
+        }
+    };
 
-    Ok(AttackMeta {
-        correct,
-        local_table,
-        raw_ciphertexts,
-    })
+    Ok(ctx)
 }
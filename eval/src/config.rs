@@ -1,18 +1,21 @@
+use std::sync::Arc;
+
 use fse::attack::AttackType;
+use fse::cipher::SecurityLevel;
+use fse::db::{CollectionId, PaddingPolicy, RetryPolicy};
+use fse::dummy::{DummyGenerator, FormatDummies, RandomDummies};
+use fse::fse::{InsertionOrder, ResultPolicy, TokenLimit};
+use fse::params::SchemeParams;
+use fse::preprocess::{FrequencyCap, Transform};
+use fse::util::{
+    infer_csv_schema_with, read_csv_headers_with, read_csv_multiple_with,
+    select_columns, CsvOptions,
+};
+pub use fse::FSEType;
+use log::warn;
+use rand_core::{OsRng, RngCore};
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
-#[serde(rename_all = "snake_case")]
-pub enum FSEType {
-    Dte,
-    Rnd,
-    LpfseIhbe,
-    /// Currently, we do not support it.
-    LpfseBhe,
-    Pfse,
-    Wre,
-}
-
 #[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum PerfType {
@@ -30,35 +33,752 @@ pub enum DatasetType {
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
-#[serde(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
 pub struct AttackConfig {
     pub fse_type: FSEType,
     pub attack_type: AttackType,
     pub data_path: String,
+    /// How the CSV file is parsed. None ==> a comma-delimited UTF-8 file with a header.
+    pub csv: Option<CsvOptions>,
     pub shuffle: bool,
     /// None ==> all attributes.
     pub attributes: Option<Vec<String>>,
-    pub fse_params: Option<Vec<f64>>,
+    pub fse_params: Option<SchemeParams>,
     pub p_norm: Option<u8>,
+    /// The strength of the entropic regularization of the l_p optimization attack.
+    /// None ==> the exact assignment.
+    pub regularization: Option<f64>,
     pub size: Option<usize>,
+    /// The seed of the sampling of a column smaller than `size` and of the auxiliary of `aux_distance`; round `i` uses
+    /// `seed + i`. None ==> a random seed, which is logged and recorded in the result.
+    pub seed: Option<u64>,
+    /// The total-variation distance between the auxiliary and the target distribution.
+    /// None ==> the attacker knows the exact distribution.
+    pub aux_distance: Option<f64>,
+    /// Mount the attack against the ciphertexts stored in a live collection instead of the in-memory simulation.
+    /// None ==> in-memory only.
+    pub live: Option<LiveConfig>,
+    /// Also attack the insertion order of the ciphertexts. None ==> the order is not attacked.
+    pub ordering: Option<OrderingConfig>,
+    /// The transformations applied to each column before it is smoothed, in order. None ==> the raw values.
+    pub preprocess: Option<Vec<Transform>>,
+    /// The cap on the occurrences of each message, applied after the preprocessing. None ==> no cap.
+    pub cap: Option<FrequencyCap>,
+    /// The number of ciphertexts above which the attacker builds their histogram on disk.
+    /// None ==> always in memory.
+    pub spill_threshold: Option<usize>,
+    /// The number of folds k of cross-validation: each fold is attacked with the other k - 1 folds as the auxiliary.
+    /// None ==> the auxiliary is the target itself.
+    pub folds: Option<usize>,
+    /// Also attack the column as it is inserted over time by a persistent adversary that observes a snapshot after
+    /// each batch. None ==> only the final snapshot is attacked.
+    pub persistent: Option<PersistentConfig>,
+    /// How the dummies of PFSE are drawn. None ==> random.
+    pub dummies: Option<DummyKind>,
+    /// The number of confidence thresholds at which the precision and the recall of the guesses are reported.
+    /// None ==> not reported.
+    pub precision_recall_points: Option<usize>,
+    /// Also encrypt each column into a second collection, whose records the attacker links to the first one, and
+    /// report their joint leakage. None ==> a single collection.
+    pub composition: Option<CompositionConfig>,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+/// The dummy generators of the string columns. See [`fse::dummy`].
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
+pub enum DummyKind {
+    Random,
+    Format,
+}
+
+impl DummyKind {
+    pub fn generator(&self) -> Arc<dyn DummyGenerator<String>> {
+        match self {
+            Self::Random => Arc::new(RandomDummies),
+            Self::Format => Arc::new(FormatDummies),
+        }
+    }
+}
+
+/// How the column is inserted over time for the persistent adversary.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct PersistentConfig {
+    /// The fraction of the column loaded when the scheme is set up; the rest is inserted as updates.
+    pub initial: f64,
+    /// The number of batches the updates are inserted in. A snapshot is taken after each.
+    pub batches: usize,
+}
+
+/// The second collection the column is encrypted into for the composition analysis, e.g., a staging copy of the
+/// production collection. It always has its own key.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct CompositionConfig {
+    /// None ==> the scheme of the suite.
+    pub fse_type: Option<FSEType>,
+    /// None ==> the parameters of the suite.
+    pub fse_params: Option<SchemeParams>,
+}
+
+impl CompositionConfig {
+    /// The configuration of the suite with the scheme and the parameters of the second collection.
+    pub fn second(&self, config: &AttackConfig) -> AttackConfig {
+        let mut second = config.clone();
+        if let Some(fse_type) = self.fse_type.as_ref() {
+            second.fse_type = fse_type.clone();
+        }
+        if self.fse_params.is_some() {
+            second.fse_params = self.fse_params;
+        }
+        second
+    }
+}
+
+/// How the encrypted column is inserted for the ordering attack.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct OrderingConfig {
+    pub order: InsertionOrder,
+    /// Whether the input is sorted before it is encrypted.
+    pub sorted: bool,
+}
+
+/// The database where the encrypted columns are deployed before the server groups them into their histogram.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct LiveConfig {
+    pub addr: String,
+    pub db_name: String,
+    /// Whether the database is dropped once the attack finishes.
+    pub drop: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
 pub struct PerfConfig {
     pub dataset_type: DatasetType,
     pub perf_type: PerfType,
     pub fse_type: FSEType,
     pub data_path: Option<String>,
+    /// How the CSV file is parsed. None ==> a comma-delimited UTF-8 file with a header.
+    pub csv: Option<CsvOptions>,
     pub shuffle: bool,
     pub attributes: Option<Vec<String>>,
-    pub fse_params: Option<Vec<f64>>,
+    pub fse_params: Option<SchemeParams>,
+    /// The transformations applied to each column before it is smoothed, in order. None ==> the raw values.
+    pub preprocess: Option<Vec<Transform>>,
     /// Used to generate synthetic datasets.
     /// Format: [<domain>, <dist_param>]
     pub data_params: Option<Vec<f64>>,
     pub size: Option<usize>,
+    /// The seed of the sampling of a column smaller than `size`; round `i` samples with `seed + i`. None ==> a random
+    /// seed, which is logged and recorded in the result.
+    pub seed: Option<u64>,
     pub query_number: Option<usize>,
+    /// How the results of each query are returned. None ==> raw.
+    pub result_policy: Option<ResultPolicy>,
+    /// The bound on the tokens of each query. None ==> unbounded.
+    pub token_limit: Option<TokenLimit>,
+    /// The cipher of the contexts. None ==> AES-256-GCM.
+    pub security_level: Option<SecurityLevel>,
+    /// The number of queries issued before the measurement starts. These queries are excluded from the steady-state
+    /// latency. None ==> no warm-up.
+    pub warmup: Option<usize>,
+    /// How to drop the caches before the suite is run. None ==> caches are kept across suites.
+    pub cache_hook: Option<CacheHook>,
+    /// How to retry transient database failures. None ==> the default policy of the connector.
+    pub retry: Option<RetryPolicy>,
+    /// The random padding stored with each inserted document. None ==> no padding.
+    pub padding: Option<PaddingPolicy>,
+    /// The timeout of each database operation including its retries. A suite whose operation times out is given up.
+    /// None ==> no timeout.
+    pub operation_timeout_ms: Option<u64>,
+    /// The maximum number of connections to the database, which are reused across rounds and suites. None ==> the
+    /// default of the driver.
+    pub pool_size: Option<u32>,
+    /// The directory of a local key provider that holds the key of each scheme wrapped, so that the same keys are
+    /// reused across runs. The key of each column is derived from the key of its scheme. None ==> a fresh key is
+    /// generated for each context.
+    pub key_dir: Option<String>,
+    /// Run insert or query benchmarks from several clients at once. None ==> a single client.
+    pub concurrency: Option<ConcurrencyConfig>,
+    /// Capture the queries into a trace or replay them from one. None ==> the queries are sampled and not recorded.
+    pub trace: Option<TraceConfig>,
+    /// The run of the collections of the suite, see [`fse::db::CollectionId`]. Giving the id of an earlier run reuses
+    /// its collections, e.g., to query without inserting again. None ==> a fresh id, so that no run sees the
+    /// collections of another.
+    pub run_id: Option<String>,
+    /// Where the query and insert benchmarks store the ciphertexts. None ==> MongoDB.
+    pub backend: Option<Backend>,
     pub addr: Option<String>,
     pub db_name: Option<String>,
     pub drop: bool,
 }
+
+impl PerfConfig {
+    /// Whether the suite runs against an embedded store instead of the database of `addr`.
+    pub fn is_embedded(&self) -> bool {
+        matches!(self.backend, Some(Backend::Sled { .. }))
+    }
+}
+
+/// The store of the ciphertexts of a perf suite, so that the same suite can be compared across stores.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "kind", deny_unknown_fields)]
+pub enum Backend {
+    /// The MongoDB server of `addr` and `db_name`.
+    Mongo,
+    /// A sled store at `path`, or a temporary one removed once the suite finishes. See [`fse::storage::SledBackend`].
+    Sled { path: Option<String> },
+}
+
+/// The clients of a concurrent benchmark. Each client runs in its own thread with its own context and collection, but
+/// all of them share the database.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct ConcurrencyConfig {
+    pub threads: usize,
+    /// The number of ciphertexts per insert of an insert benchmark. None ==> the whole load at once.
+    pub batch_size: Option<usize>,
+}
+
+/// The dataset whose columns are described by the stats evaluation.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct StatsConfig {
+    pub data_path: String,
+    /// How the CSV file is parsed. None ==> a comma-delimited UTF-8 file with a header.
+    pub csv: Option<CsvOptions>,
+    pub attributes: Vec<String>,
+    /// The transformations applied to each column before it is described, in order. None ==> the raw values.
+    pub preprocess: Option<Vec<Transform>>,
+    /// None ==> all rows.
+    pub size: Option<usize>,
+    /// The number of heavy hitters reported per column. None ==> 10.
+    pub top_k: Option<usize>,
+    /// The advantage bound the recommended parameters must meet. None ==> 0.1.
+    pub target_bound: Option<f64>,
+}
+
+/// Whether the queries of a suite are written to or read from the trace.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceMode {
+    Capture,
+    Replay,
+}
+
+/// The query trace of a query benchmark. A captured trace replaces the file at `path` once the suite finishes.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct TraceConfig {
+    pub path: String,
+    pub mode: TraceMode,
+}
+
+/// The hook that is run before a suite to drop the OS and database caches.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct CacheHook {
+    /// A shell command, e.g., `sync; echo 3 > /proc/sys/vm/drop_caches`.
+    pub command: Option<String>,
+    /// A command document run against the `admin` database of `addr`.
+    pub admin_command: Option<toml::value::Table>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct SoakConfig {
+    pub fse_type: FSEType,
+    pub data_path: String,
+    /// The column that is used for the soak test.
+    pub attribute: String,
+    pub fse_params: Option<SchemeParams>,
+    /// The transformations applied to the column before it is smoothed, in order. None ==> the raw values.
+    pub preprocess: Option<Vec<Transform>>,
+    /// The number of messages used to initialize the context.
+    pub size: Option<usize>,
+    /// The seed of the sampling of the column up to `size`. None ==> a random seed, which is logged and recorded in the
+    /// samples.
+    pub seed: Option<u64>,
+    /// How long the soak test should run (in seconds).
+    pub duration: u64,
+    /// How often the memory usage should be sampled (in seconds).
+    pub sample_interval: u64,
+    /// The number of messages inserted per cycle.
+    pub batch_size: usize,
+    /// The number of queries issued per cycle.
+    pub query_number: usize,
+    /// Monitor the skew of the inserted messages against the initial dataset. None ==> not monitored.
+    pub drift: Option<DriftConfig>,
+    /// How to retry transient database failures. None ==> the default policy of the connector.
+    pub retry: Option<RetryPolicy>,
+    pub addr: String,
+    pub db_name: String,
+    pub drop: bool,
+}
+
+/// How the drift of the inserted messages is monitored by the soak test.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct DriftConfig {
+    /// The number of heavy hitters compared.
+    pub top_k: usize,
+    /// The total-variation distance above which the drift is reported.
+    pub threshold: f64,
+}
+
+impl From<&SoakConfig> for PerfConfig {
+    fn from(config: &SoakConfig) -> Self {
+        Self {
+            dataset_type: DatasetType::Real,
+            perf_type: PerfType::Insert,
+            fse_type: config.fse_type.clone(),
+            data_path: Some(config.data_path.clone()),
+            csv: None,
+            shuffle: true,
+            attributes: Some(vec![config.attribute.clone()]),
+            fse_params: config.fse_params,
+            preprocess: config.preprocess.clone(),
+            data_params: None,
+            size: config.size,
+            seed: config.seed,
+            query_number: Some(config.query_number),
+            result_policy: None,
+            token_limit: None,
+            security_level: None,
+            warmup: None,
+            cache_hook: None,
+            retry: config.retry,
+            padding: None,
+            operation_timeout_ms: None,
+            pool_size: None,
+            key_dir: None,
+            concurrency: None,
+            trace: None,
+            run_id: None,
+            backend: None,
+            addr: Some(config.addr.clone()),
+            db_name: Some(config.db_name.clone()),
+            drop: config.drop,
+        }
+    }
+}
+
+/// A suite configuration that is checked before any suite runs, so that all its mistakes are reported at once instead
+/// of failing deep in the evaluation.
+pub trait Validate {
+    /// All the problems of the configuration. Empty if it is valid.
+    fn validate(&self) -> Vec<String>;
+
+    /// Resolve the parts of the configuration that depend on the data before it is validated, e.g., expand the
+    /// column selectors. Returns the problems found, like [`Validate::validate`].
+    fn resolve(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+impl Validate for AttackConfig {
+    fn resolve(&mut self) -> Vec<String> {
+        match self.attributes.as_mut() {
+            Some(attributes) => {
+                resolve_columns(&self.data_path, self.csv.as_ref(), attributes)
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_scheme(&self.fse_type, self.fse_params.as_ref(), &mut problems);
+        match self.attributes.as_ref() {
+            Some(attributes) => check_columns(
+                &self.data_path,
+                self.csv.as_ref(),
+                attributes,
+                &mut problems,
+            ),
+            None => problems.push("`attributes` is required".to_string()),
+        }
+
+        if let Err(e) = self.cap.as_ref().map_or(Ok(()), FrequencyCap::validate)
+        {
+            problems.push(format!("`cap`: {}", e));
+        }
+
+        match self.attack_type {
+            AttackType::LpOptimization if self.p_norm.is_none() => {
+                problems.push("`lp_optimization` requires `p_norm`".to_string())
+            }
+            AttackType::MleAttack if self.regularization.is_some() => problems
+                .push(
+                    "`regularization` only applies to `lp_optimization`"
+                        .to_string(),
+                ),
+            _ => (),
+        }
+        if matches!(self.folds, Some(k) if k < 2) {
+            problems.push("`folds` must be at least 2".to_string());
+        }
+        if self.folds.is_some() && self.aux_distance.is_some() {
+            problems.push(
+                "`folds` and `aux_distance` are mutually exclusive".to_string(),
+            );
+        }
+        if matches!(self.aux_distance, Some(tv) if !(0.0..=1.0).contains(&tv)) {
+            problems.push("`aux_distance` must be in [0, 1]".to_string());
+        }
+        if let Some(persistent) = self.persistent.as_ref() {
+            if !(persistent.initial > 0.0 && persistent.initial < 1.0) {
+                problems
+                    .push("`persistent.initial` must be in (0, 1)".to_string());
+            }
+            if persistent.batches == 0 {
+                problems
+                    .push("`persistent.batches` must be positive".to_string());
+            }
+        }
+        if self.dummies.is_some() && self.fse_type != FSEType::Pfse {
+            problems.push("`dummies` only applies to `pfse`".to_string());
+        }
+        if self.precision_recall_points.is_some() {
+            if matches!(self.attack_type, AttackType::SaltCount) {
+                problems.push(
+                    "`precision_recall_points` does not apply to `salt_count`"
+                        .to_string(),
+                );
+            }
+            if self.precision_recall_points == Some(0) {
+                problems.push(
+                    "`precision_recall_points` must be positive".to_string(),
+                );
+            }
+        }
+        if let Some(composition) = self.composition.as_ref() {
+            let second = composition.second(self);
+            let mut scheme = Vec::new();
+            check_scheme(
+                &second.fse_type,
+                second.fse_params.as_ref(),
+                &mut scheme,
+            );
+            problems.extend(
+                scheme.into_iter().map(|e| format!("`composition`: {}", e)),
+            );
+        }
+
+        problems
+    }
+}
+
+impl Validate for PerfConfig {
+    fn resolve(&mut self) -> Vec<String> {
+        match (self.dataset_type, &self.data_path, self.attributes.as_mut()) {
+            (DatasetType::Real, Some(path), Some(attributes)) => {
+                resolve_columns(path, self.csv.as_ref(), attributes)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_scheme(&self.fse_type, self.fse_params.as_ref(), &mut problems);
+        if self.fse_type == FSEType::Wre {
+            problems.push("`wre` cannot be benchmarked yet".to_string());
+        }
+
+        match (self.dataset_type, &self.data_path, &self.attributes) {
+            (DatasetType::Real, Some(path), Some(attributes)) => check_columns(
+                path,
+                self.csv.as_ref(),
+                attributes,
+                &mut problems,
+            ),
+            (DatasetType::Real, _, _) => problems.push(
+                "a `real` dataset requires `data_path` and `attributes`"
+                    .to_string(),
+            ),
+            (DatasetType::Zipf, _, _) => {
+                if self.data_params.as_ref().map(Vec::len) != Some(2) {
+                    problems.push(
+                        "a `zipf` dataset requires `data_params` = [<domain>, <exponent>]"
+                            .to_string(),
+                    );
+                }
+            }
+            (DatasetType::Normal, _, _) => {
+                if self.data_params.as_ref().map(Vec::len) != Some(3) {
+                    problems.push(
+                        "a `normal` dataset requires `data_params` = [<domain>, <mean>, <deviation>]"
+                            .to_string(),
+                    );
+                }
+            }
+        }
+
+        match (&self.addr, &self.db_name) {
+            (Some(_), Some(_)) => (),
+            (None, None)
+                if self.perf_type == PerfType::Init || self.is_embedded() => {}
+            (None, None) => problems.push(format!(
+                "the `{}` benchmark requires `addr` and `db_name`",
+                format!("{:?}", self.perf_type).to_lowercase()
+            )),
+            _ => problems.push(
+                "`addr` and `db_name` must be given together".to_string(),
+            ),
+        }
+        if matches!(&self.cache_hook, Some(hook) if hook.admin_command.is_some())
+            && self.addr.is_none()
+        {
+            problems
+                .push("`cache_hook.admin_command` requires `addr`".to_string());
+        }
+
+        if let Some(padding) = self.padding.as_ref() {
+            if self.perf_type == PerfType::Init {
+                problems.push(
+                    "`padding` only applies to `query` and `insert`"
+                        .to_string(),
+                );
+            }
+            if let Err(e) = padding.validate() {
+                problems.push(format!("`padding`: {}", e));
+            }
+        }
+        if matches!(self.token_limit, Some(limit) if limit.max_tokens_per_query == 0)
+        {
+            problems.push(
+                "`token_limit.max_tokens_per_query` must be positive"
+                    .to_string(),
+            );
+        }
+        if self.pool_size == Some(0) {
+            problems.push("`pool_size` must be positive".to_string());
+        }
+        if self.operation_timeout_ms == Some(0) {
+            problems
+                .push("`operation_timeout_ms` must be positive".to_string());
+        }
+        if matches!(&self.run_id, Some(run) if !CollectionId::is_component(run))
+        {
+            problems.push(
+                "`run_id` must be ASCII letters, digits, `_` and `-`"
+                    .to_string(),
+            );
+        }
+
+        if let Some(trace) = self.trace.as_ref() {
+            if self.perf_type != PerfType::Query || self.concurrency.is_some() {
+                problems.push(
+                    "`trace` only applies to single-client `query` benchmarks"
+                        .to_string(),
+                );
+            }
+            if trace.mode == TraceMode::Replay
+                && !std::path::Path::new(&trace.path).exists()
+            {
+                problems.push(format!(
+                    "the trace `{}` to replay does not exist",
+                    trace.path
+                ));
+            }
+        }
+        if let Some(concurrency) = self.concurrency.as_ref() {
+            if self.perf_type == PerfType::Init {
+                problems.push(
+                    "`concurrency` only applies to `query` and `insert`"
+                        .to_string(),
+                );
+            }
+            if concurrency.threads == 0 {
+                problems
+                    .push("`concurrency.threads` must be positive".to_string());
+            }
+        }
+        if self.is_embedded() {
+            if self.concurrency.is_some() || self.padding.is_some() {
+                problems.push(
+                    "`concurrency` and `padding` need the `mongo` backend"
+                        .to_string(),
+                );
+            }
+            if !matches!(self.result_policy, None | Some(ResultPolicy::Raw)) {
+                problems.push(
+                    "the `sled` backend only returns `raw` results".to_string(),
+                );
+            }
+        }
+
+        problems
+    }
+}
+
+impl Validate for SoakConfig {
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_scheme(&self.fse_type, self.fse_params.as_ref(), &mut problems);
+        if self.fse_type == FSEType::Wre {
+            problems.push("`wre` cannot be soak tested yet".to_string());
+        }
+        check_columns(
+            &self.data_path,
+            None,
+            std::slice::from_ref(&self.attribute),
+            &mut problems,
+        );
+        if self.sample_interval == 0 {
+            problems.push("`sample_interval` must be positive".to_string());
+        }
+        if self.batch_size == 0 {
+            problems.push("`batch_size` must be positive".to_string());
+        }
+        if self.size == Some(0) {
+            problems.push("`size` must be positive".to_string());
+        }
+        if let Some(drift) = self.drift.as_ref() {
+            if drift.top_k == 0 {
+                problems.push("`drift.top_k` must be positive".to_string());
+            }
+            if !(drift.threshold > 0.0 && drift.threshold <= 1.0) {
+                problems
+                    .push("`drift.threshold` must be in (0, 1]".to_string());
+            }
+        }
+
+        problems
+    }
+}
+
+impl Validate for StatsConfig {
+    fn resolve(&mut self) -> Vec<String> {
+        resolve_columns(
+            &self.data_path,
+            self.csv.as_ref(),
+            &mut self.attributes,
+        )
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_columns(
+            &self.data_path,
+            self.csv.as_ref(),
+            &self.attributes,
+            &mut problems,
+        );
+        if matches!(self.target_bound, Some(bound) if !(bound > 0.0 && bound <= 1.0))
+        {
+            problems.push("`target_bound` must be in (0, 1]".to_string());
+        }
+
+        problems
+    }
+}
+
+/// Check that the parameters match the scheme and are in range.
+fn check_scheme(
+    fse_type: &FSEType,
+    params: Option<&SchemeParams>,
+    problems: &mut Vec<String>,
+) {
+    let res = match (fse_type, params) {
+        (FSEType::Dte | FSEType::Rnd | FSEType::Plain, None) => Ok(()),
+        (FSEType::Dte | FSEType::Rnd | FSEType::Plain, Some(_)) => {
+            Err("the scheme takes no parameters".into())
+        }
+        (_, None) => Err("the scheme requires parameters".into()),
+        (FSEType::Pfse, Some(params)) => params.pfse().map(|_| ()),
+        (FSEType::LpfseIhbe | FSEType::LpfseBhe, Some(params)) => {
+            params.lpfse().map(|_| ())
+        }
+        (FSEType::Wre, Some(params)) => params.wre().map(|_| ()),
+    };
+
+    if let Err(e) = res {
+        problems.push(format!(
+            "invalid `fse_params` for `{}`: {}",
+            fse_type.name(),
+            e
+        ));
+    }
+}
+
+/// The number of records the column types are inferred from.
+const SCHEMA_SAMPLE_ROWS: usize = 10_000;
+
+/// Expand the type selectors among the columns, e.g., `*categorical`, by inferring the schema of the CSV file. The
+/// file is only read if there is a selector.
+fn resolve_columns(
+    path: &str,
+    csv: Option<&CsvOptions>,
+    columns: &mut Vec<String>,
+) -> Vec<String> {
+    if !columns.iter().any(|e| e.starts_with('*')) {
+        return Vec::new();
+    }
+
+    let options = csv.cloned().unwrap_or_default();
+    let resolved =
+        infer_csv_schema_with(path, Some(SCHEMA_SAMPLE_ROWS), &options)
+            .and_then(|schema| select_columns(&schema, columns));
+    match resolved {
+        Ok(resolved) => {
+            *columns = resolved;
+            Vec::new()
+        }
+        Err(e) => {
+            vec![format!("cannot resolve the columns of `{}`: {}", path, e)]
+        }
+    }
+}
+
+/// Check that the CSV file exists and has all the columns.
+fn check_columns(
+    path: &str,
+    csv: Option<&CsvOptions>,
+    columns: &[String],
+    problems: &mut Vec<String>,
+) {
+    let headers =
+        match read_csv_headers_with(path, &csv.cloned().unwrap_or_default()) {
+            Ok(headers) => headers,
+            Err(e) => {
+                problems.push(format!("cannot read `{}`: {}", path, e));
+                return;
+            }
+        };
+
+    for column in columns.iter().filter(|e| !headers.contains(e)) {
+        problems.push(format!(
+            "`{}` has no column `{}`; found {:?}",
+            path, column, headers
+        ));
+    }
+}
+
+/// A random sampling seed for a suite that configures none. It is at most `i64::MAX` so that it can be recorded in the
+/// TOML result.
+pub fn random_seed() -> u64 {
+    OsRng.next_u64() >> 1
+}
+
+/// Read the columns of a CSV file, warning about the malformed records that were skipped.
+pub fn read_columns(
+    path: &str,
+    columns: &[String],
+    csv: Option<&CsvOptions>,
+) -> crate::Result<Vec<Vec<String>>> {
+    let options = csv.cloned().unwrap_or_default();
+    let (dataset, stats) = read_csv_multiple_with(path, columns, &options)?;
+    if stats.malformed > 0 {
+        warn!(
+            "Skipped {} malformed records of `{}`; {} records were read.",
+            stats.malformed, path, stats.rows
+        );
+    }
+
+    Ok(dataset)
+}
@@ -0,0 +1,68 @@
+//! The evaluation of the schemes against inference attacks and of their performance. The `eval` binary runs the suites
+//! of a configuration file; [`plan::EvalPlan`] runs a single one from other code.
+#![deny(clippy::needless_borrow)]
+#![deny(clippy::unused_io_amount)]
+
+pub mod attack;
+pub mod config;
+pub mod micro;
+pub mod perf;
+pub mod plan;
+pub mod progress;
+mod queue;
+pub mod report;
+pub mod soak;
+pub mod stats;
+mod trace;
+
+use clap::{Parser, ValueEnum};
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Debug, ValueEnum, Clone)]
+pub enum EvalType {
+    Attack,
+    /// Run small, self-contained benchmarks of the client-side operations without a configuration file or a database.
+    Micro,
+    Perf,
+    /// Aggregate the results given by `--input` into the tables and plots of the standard figures.
+    Report,
+    Soak,
+    Stats,
+}
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+#[clap(propagate_version = true)]
+pub struct Args {
+    /// The path to the configuration file.
+    #[arg(short, long, default_value_t = String::from("./config.toml"))]
+    pub config_path: String,
+    /// The output path.
+    #[arg(short, long)]
+    pub output_path: Option<String>,
+    /// The test round.
+    #[arg(short, long, default_value_t = 10)]
+    pub round: usize,
+    /// How many test suites should be performed.
+    #[arg(short, long)]
+    pub suite_num: Option<usize>,
+    #[arg(short, long, value_enum, default_value_t = EvalType::Attack)]
+    /// The type of the evaluation you need to perform.
+    pub evaluation_type: EvalType,
+    /// Watch the configuration file and enqueue newly added test suites during the run.
+    #[arg(short, long, default_value_t = false)]
+    pub watch: bool,
+    /// Insert the initial load of a context even if the same load is already in the collection.
+    #[arg(short, long, default_value_t = false)]
+    pub force: bool,
+    /// Break the latency of the PFSE init benchmarks down into the partition, transform and smooth phases.
+    #[arg(short, long, default_value_t = false)]
+    pub phase: bool,
+    /// Draw the progress of partitioning, transforming and smoothing the PFSE contexts.
+    #[arg(long, default_value_t = false)]
+    pub progress: bool,
+    /// The result files of the attack and perf evaluations to report on. The output path is a directory.
+    #[arg(short, long, num_args = 1..)]
+    pub input: Vec<String>,
+}
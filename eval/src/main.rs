@@ -2,42 +2,12 @@
 #![deny(clippy::needless_borrow)]
 #![deny(clippy::unused_io_amount)]
 
-mod attack;
-mod config;
-mod perf;
-
-use clap::{Parser, ValueEnum};
+use clap::Parser;
+use eval::{
+    attack, micro, perf, progress, report, soak, stats, Args, EvalType, Result,
+};
 use log::{error, info};
 
-pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
-#[derive(Debug, ValueEnum, Clone)]
-pub enum EvalType {
-    Attack,
-    Perf,
-}
-
-#[derive(Parser)]
-#[clap(author, version, about, long_about = None)]
-#[clap(propagate_version = true)]
-pub struct Args {
-    /// The path to the configuration file.
-    #[arg(short, long, default_value_t = String::from("./config.toml"))]
-    config_path: String,
-    /// The output path.
-    #[arg(short, long)]
-    output_path: Option<String>,
-    /// The test round.
-    #[arg(short, long, default_value_t = 10)]
-    round: usize,
-    /// How many test suites should be performed.
-    #[arg(short, long)]
-    suite_num: Option<usize>,
-    #[arg(short, long, value_enum, default_value_t = EvalType::Attack)]
-    /// The type of the evaluation you need to perform.
-    evaluation_type: EvalType,
-}
-
 fn main() {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "INFO");
@@ -45,6 +15,9 @@ fn main() {
     env_logger::init();
 
     let args = Args::parse();
+    if args.progress {
+        progress::enable();
+    }
     if let Err(e) = dispatcher(&args) {
         error!("Failed to execute the performance evaluation due to {}", e);
         return;
@@ -58,6 +31,10 @@ fn dispatcher(args: &Args) -> Result<()> {
 
     match args.evaluation_type {
         EvalType::Attack => attack::execute_attack(args),
+        EvalType::Micro => micro::execute_micro(args),
         EvalType::Perf => perf::execute_perf(args),
+        EvalType::Report => report::execute_report(args),
+        EvalType::Soak => soak::execute_soak(args),
+        EvalType::Stats => stats::execute_stats(args),
     }
 }
@@ -0,0 +1,187 @@
+//! The micro benchmarks: small, self-contained performance checks of the client-side operations that are cheap enough
+//! to run on every change. Unlike the perf evaluation, they need neither a configuration file nor a database: the
+//! dataset is a synthetic Zipf column of [`MICRO_ROWS`] rows, and the server is an in-memory index of the smoothed
+//! ciphertexts.
+//!
+//! The result is a compact JSON object of the median and the minimum latency of each operation over `--round` rounds,
+//! in nanoseconds per call for the PFSE phases, per distinct value for the PFSE encryption and search, which return
+//! every ciphertext of the value, and per message for the others.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use chrono::Local;
+use fse::{
+    fse::{exponential, BaseCrypto, PartitionFrequencySmoothing},
+    lpfse::{EncoderBHE, EncoderIHBE, HomophoneEncoder},
+    native::ContextNative,
+    params::PfseParams,
+    pfse::ContextPFSE,
+    util::build_histogram,
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{Args, Result};
+
+/// The number of rows of the synthetic column.
+const MICRO_ROWS: usize = 10_000;
+/// The number of distinct values of the synthetic column.
+const MICRO_SUPPORT: usize = 100;
+/// The exponent of the Zipf distribution of the synthetic column.
+const MICRO_EXPONENT: f64 = 1.0;
+
+/// The parameters of the benchmarked PFSE and LPFSE schemes.
+const PFSE_PARAMS: (f64, f64, f64) = (0.25, 1.0, 0.1);
+const LPFSE_ADVANTAGE: f64 = 0.1;
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+struct OpLatency {
+    median_ns: u64,
+    min_ns: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+struct MicroResult {
+    rows: usize,
+    rounds: usize,
+    /// The operations in alphabetical order, so that two results diff line by line once pretty-printed.
+    latencies: BTreeMap<String, OpLatency>,
+}
+
+/// Run the micro benchmarks given the CLI arguments.
+pub fn execute_micro(args: &Args) -> Result<()> {
+    let rounds = args.round.max(1);
+    let dataset = synthetic_dataset();
+    let mut samples = BTreeMap::<&'static str, Vec<Duration>>::new();
+
+    for round in 0..rounds {
+        info!("Micro benchmark round #{:<04}...", round + 1);
+        for (op, latency) in do_round(&dataset)? {
+            samples.entry(op).or_default().push(latency);
+        }
+    }
+
+    let result = MicroResult {
+        rows: dataset.len(),
+        rounds,
+        latencies: samples
+            .into_iter()
+            .map(|(op, mut latencies)| {
+                latencies.sort();
+                let latency = OpLatency {
+                    median_ns: latencies[latencies.len() / 2].as_nanos() as u64,
+                    min_ns: latencies[0].as_nanos() as u64,
+                };
+                (op.to_string(), latency)
+            })
+            .collect(),
+    };
+
+    let path = match args.output_path.as_ref() {
+        Some(path) => path.clone(),
+        None => format!("./micro_{:?}.json", Local::now()),
+    };
+    std::fs::write(&path, serde_json::to_string(&result)?)?;
+    info!("Micro benchmark result written to {}.", path);
+
+    Ok(())
+}
+
+/// A Zipf column over `MICRO_SUPPORT` values whose counts are rounded down from their expected counts, with the rest
+/// given to the most frequent value. It is the same on every run, so that the latencies of two runs are comparable.
+fn synthetic_dataset() -> Vec<String> {
+    let weights = (1..=MICRO_SUPPORT)
+        .map(|rank| (rank as f64).powf(-MICRO_EXPONENT))
+        .collect::<Vec<_>>();
+    let total = weights.iter().sum::<f64>();
+    let mut counts = weights
+        .iter()
+        .map(|weight| (weight / total * MICRO_ROWS as f64) as usize)
+        .collect::<Vec<_>>();
+    counts[0] += MICRO_ROWS - counts.iter().sum::<usize>();
+
+    counts
+        .iter()
+        .enumerate()
+        .flat_map(|(rank, &count)| vec![format!("value-{:03}", rank); count])
+        .collect()
+}
+
+/// The latency of `f` divided by `per`.
+fn time<F, R>(per: usize, f: F) -> Result<(Duration, R)>
+where
+    F: FnOnce() -> Result<R>,
+{
+    let instant = Instant::now();
+    let output = f()?;
+    Ok((instant.elapsed() / per.max(1) as u32, output))
+}
+
+/// Measure every operation once.
+fn do_round(dataset: &[String]) -> Result<Vec<(&'static str, Duration)>> {
+    let mut latencies = Vec::new();
+    let n = dataset.len();
+
+    for (op, rnd) in [("det_encrypt", false), ("rnd_encrypt", true)] {
+        let mut ctx = ContextNative::new(rnd);
+        ctx.key_generate();
+        let (latency, _) =
+            time(n, || Ok(dataset.iter().map(|e| ctx.encrypt(e)).count()))?;
+        latencies.push((op, latency));
+    }
+
+    let (lambda, scale, advantage) = PFSE_PARAMS;
+    let mut ctx = ContextPFSE::default();
+    ctx.key_generate();
+    ctx.set_params(&PfseParams::new(lambda, scale, advantage))?;
+    let (latency, _) = time(1, || ctx.partition(dataset, exponential))?;
+    latencies.push(("pfse_partition", latency));
+    let (latency, _) = time(1, || Ok(ctx.transform()))?;
+    latencies.push(("pfse_transform", latency));
+    let (latency, ciphertexts) = time(1, || Ok(ctx.smooth()))?;
+    latencies.push(("pfse_smooth", latency));
+    let histogram = build_histogram(dataset);
+    let mut distinct = histogram.keys().collect::<Vec<_>>();
+    distinct.sort();
+    let (latency, _) = time(distinct.len(), || {
+        Ok(distinct.iter().map(|e| ctx.encrypt(e)).count())
+    })?;
+    latencies.push(("pfse_encrypt", latency));
+
+    // The in-memory server: the number of documents of each ciphertext.
+    let mut server = HashMap::<&[u8], usize>::new();
+    for ciphertext in ciphertexts.iter() {
+        *server.entry(ciphertext).or_default() += 1;
+    }
+    let (latency, _) = time(distinct.len(), || {
+        Ok(distinct
+            .iter()
+            .filter_map(|e| ctx.search_tokens(e))
+            .map(|tokens| {
+                tokens
+                    .iter()
+                    .filter_map(|token| server.get(token))
+                    .sum::<usize>()
+            })
+            .sum::<usize>())
+    })?;
+    latencies.push(("pfse_search", latency));
+
+    let encoders: [(&'static str, Box<dyn HomophoneEncoder<String>>); 2] = [
+        ("bhe_encode", Box::new(EncoderBHE::new())),
+        ("ihbe_encode", Box::new(EncoderIHBE::new())),
+    ];
+    for (op, mut encoder) in encoders {
+        encoder.initialize_histogram(&histogram, LPFSE_ADVANTAGE)?;
+        let (latency, _) =
+            time(n, || Ok(dataset.iter().map(|e| encoder.encode(e)).count()))?;
+        latencies.push((op, latency));
+    }
+
+    Ok(latencies)
+}
@@ -1,19 +1,33 @@
 use std::{
     collections::HashMap,
     fs::{File, OpenOptions},
-    io::{Read, Write},
+    io::Write,
+    panic::AssertUnwindSafe,
+    process::Command,
+    sync::Barrier,
     time::{Duration, Instant},
 };
 
 use chrono::Local;
 use fse::{
-    db::{Connector, Data},
-    fse::{exponential, BaseCrypto, PartitionFrequencySmoothing, Random},
+    db::{
+        new_run_id, ping, set_max_pool_size, Bandwidth, CollectionId,
+        Connector, Data,
+    },
+    error::FseError,
+    fse::{
+        exponential, BaseCrypto, PartitionFrequencySmoothing, Random,
+        ResultPolicy, SearchTiming, SmoothedLoad,
+    },
+    keys::{KeyProvider, LocalFileProvider},
     lpfse::{ContextLPFSE, EncoderBHE, EncoderIHBE, HomophoneEncoder},
     native::ContextNative,
     pfse::ContextPFSE,
+    plain::ContextPlain,
+    preprocess::Preprocess,
+    storage::{SledBackend, Storage, StorageSearch},
     util::{
-        generate_synthetic_normal, generate_synthetic_zipf, read_csv_multiple,
+        generate_synthetic_normal, generate_synthetic_zipf, sample_or_cycle,
     },
 };
 use log::{debug, info, warn};
@@ -22,38 +36,384 @@ use rand_core::OsRng;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    config::{DatasetType, FSEType, PerfConfig, PerfType},
+    config::{
+        random_seed, read_columns, Backend, CacheHook, ConcurrencyConfig,
+        DatasetType, FSEType, PerfConfig, PerfType, TraceConfig, TraceMode,
+    },
+    progress,
+    queue::SuiteQueue,
+    trace::QueryTrace,
     Args, Result,
 };
 
+/// How long the database of a suite is waited for before the suite is skipped.
+const PING_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The ciphertexts of the dataset together with the context that encrypted them.
+type InitializedContext = (Vec<Vec<u8>>, Box<dyn BaseCrypto<String>>);
+
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "snake_case")]
-struct MainResult {
-    latency: String,
-    client_storage: usize,
+pub struct MainResult {
+    /// The steady-state latency, i.e., the warm-up queries are excluded for query benchmarks.
+    pub latency: String,
+    /// The latency of the first query that touches the freshly loaded collection.
+    pub cold_latency: Option<String>,
+    /// The time to establish the connection to the database, which is excluded from the other latencies. Zero if a
+    /// pooled connection is reused.
+    pub connect_latency: Option<String>,
+    /// The total number of retries performed on transient database failures.
+    pub retries: usize,
+    pub client_storage: usize,
+    pub server_storage: usize,
+    pub column_name: String,
+    /// The number of messages of the column the benchmark is run on.
+    pub message_num: usize,
+    /// The storage overhead of the padding. Present only if the documents are padded.
+    pub padding: Option<PaddingResult>,
+    /// Present only if the benchmark is run by concurrent clients.
+    pub concurrency: Option<ConcurrencyResult>,
+    /// Present only if the init benchmark is broken down into phases.
+    pub phases: Option<PhaseResult>,
+    /// Present only for the insert benchmarks and the query benchmarks of a single client under the raw policy.
+    pub breakdown: Option<BreakdownResult>,
+    /// The bytes exchanged with the database. Present only if the benchmark touches the database.
+    pub bandwidth: Option<BandwidthResult>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct PaddingResult {
+    /// The number of padding bytes inserted.
+    pub bytes: usize,
+    /// The padding bytes relative to the ciphertext bytes.
+    pub overhead: f64,
+}
+
+/// The bytes exchanged with the database per operation, counted as the BSON sizes of the filters and documents. The
+/// warm-up queries are excluded.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct BandwidthResult {
+    /// The bytes of the token filters sent per query. Present only for query benchmarks.
+    pub query_sent: Option<usize>,
+    /// The bytes of the result documents received per query. Present only for query benchmarks.
+    pub query_received: Option<usize>,
+    /// The number of insert batches of all rounds, including those loading the collection of a query benchmark.
+    pub insert_batches: usize,
+    /// The bytes of the documents sent per insert batch, including their padding.
+    pub insert_batch_sent: Option<usize>,
+}
+
+/// The latency of each phase of the PFSE pipeline.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct PhaseResult {
+    pub partition: String,
+    pub transform: String,
+    pub smooth: String,
+}
+
+/// The latency split into the client, the network and the server. The server time is reported by MongoDB in whole
+/// milliseconds, so it is present only for queries, and the network time is the rest of the round trip.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct BreakdownResult {
+    /// The token generation of a query, or the encryption of the dataset of an insert.
+    pub token_generation: String,
+    /// Building the BSON documents sent to the server.
+    pub serialization: String,
+    pub round_trip: String,
+    pub server: Option<String>,
+    pub network: Option<String>,
+    pub decryption: String,
+    /// The time spent on the client, i.e., the latency minus the round trip.
+    pub client: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct ConcurrencyResult {
+    pub threads: usize,
+    /// The operations per second of all clients together.
+    pub throughput: f64,
+    pub clients: Vec<ClientResult>,
+}
+
+/// The latency distribution of the operations of a single client.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct ClientResult {
+    pub operations: usize,
+    pub p50: String,
+    pub p95: String,
+    pub p99: String,
+    pub max: String,
+}
+
+/// The measurement of a single column.
+#[derive(Clone, Debug, Default)]
+struct Measurement {
+    latency: Duration,
+    cold_latency: Option<Duration>,
+    connect_time: Option<Duration>,
     server_storage: usize,
-    column_name: String,
+    client_storage: usize,
+    /// The number of retries performed on transient database failures.
+    retries: usize,
+    /// The number of padding bytes and ciphertext bytes inserted.
+    padding_bytes: usize,
+    ciphertext_bytes: usize,
+    /// The samples of concurrent clients.
+    concurrency: Option<ConcurrentSamples>,
+    /// The latencies of the PFSE phases.
+    phases: Option<PhaseLatencies>,
+    breakdown: Option<Breakdown>,
+    traffic: Option<Traffic>,
+}
+
+/// The bytes exchanged with the database and the number of queries they are spread over.
+#[derive(Clone, Copy, Debug, Default)]
+struct Traffic {
+    queries: usize,
+    bandwidth: Bandwidth,
+}
+
+impl Traffic {
+    fn accumulate(&mut self, other: &Traffic) {
+        self.queries += other.queries;
+        self.bandwidth.accumulate(&other.bandwidth);
+    }
+
+    fn result(&self) -> BandwidthResult {
+        let per = |bytes: usize, operations: usize| {
+            (operations != 0).then(|| bytes / operations)
+        };
+        BandwidthResult {
+            query_sent: per(self.bandwidth.search_sent, self.queries),
+            query_received: per(self.bandwidth.search_received, self.queries),
+            insert_batches: self.bandwidth.insert_batches,
+            insert_batch_sent: per(
+                self.bandwidth.insert_sent,
+                self.bandwidth.insert_batches,
+            ),
+        }
+    }
+}
+
+/// The latencies of the phases of the PFSE pipeline.
+#[derive(Clone, Copy, Debug, Default)]
+struct PhaseLatencies {
+    partition: Duration,
+    transform: Duration,
+    smooth: Duration,
+}
+
+impl PhaseLatencies {
+    fn result(&self) -> PhaseResult {
+        PhaseResult {
+            partition: format!("{:?}", self.partition),
+            transform: format!("{:?}", self.transform),
+            smooth: format!("{:?}", self.smooth),
+        }
+    }
+}
+
+/// The latency of a single operation split into its steps.
+#[derive(Clone, Copy, Debug, Default)]
+struct Breakdown {
+    tokens: Duration,
+    serialization: Duration,
+    round_trip: Duration,
+    server: Option<Duration>,
+    decryption: Duration,
+}
+
+impl Breakdown {
+    fn accumulate(&mut self, other: &Breakdown) {
+        self.tokens += other.tokens;
+        self.serialization += other.serialization;
+        self.round_trip += other.round_trip;
+        self.server = match (self.server, other.server) {
+            (Some(lhs), Some(rhs)) => Some(lhs + rhs),
+            (lhs, rhs) => lhs.or(rhs),
+        };
+        self.decryption += other.decryption;
+    }
+
+    fn average(&mut self, round: u32) {
+        self.tokens /= round;
+        self.serialization /= round;
+        self.round_trip /= round;
+        self.server = self.server.map(|e| e / round);
+        self.decryption /= round;
+    }
+
+    fn result(&self) -> BreakdownResult {
+        BreakdownResult {
+            token_generation: format!("{:?}", self.tokens),
+            serialization: format!("{:?}", self.serialization),
+            round_trip: format!("{:?}", self.round_trip),
+            server: self.server.map(|e| format!("{:?}", e)),
+            network: self
+                .server
+                .map(|e| format!("{:?}", self.round_trip.saturating_sub(e))),
+            decryption: format!("{:?}", self.decryption),
+            client: format!(
+                "{:?}",
+                self.tokens + self.serialization + self.decryption
+            ),
+        }
+    }
+}
+
+impl From<SearchTiming> for Breakdown {
+    fn from(timing: SearchTiming) -> Self {
+        Self {
+            tokens: timing.tokens,
+            serialization: timing.serialization,
+            round_trip: timing.round_trip,
+            server: timing.server,
+            decryption: timing.decryption,
+        }
+    }
+}
+
+/// The latencies of the operations of each concurrent client, and the time from the first operation of any client to
+/// the last one.
+#[derive(Clone, Debug, Default)]
+struct ConcurrentSamples {
+    latencies: Vec<Vec<Duration>>,
+    elapsed: Duration,
+}
+
+impl ConcurrentSamples {
+    fn result(&self) -> ConcurrencyResult {
+        let operations = self.latencies.iter().map(Vec::len).sum::<usize>();
+        ConcurrencyResult {
+            threads: self.latencies.len(),
+            throughput: operations as f64 / self.elapsed.as_secs_f64(),
+            clients: self
+                .latencies
+                .iter()
+                .map(|latencies| {
+                    let mut latencies = latencies.clone();
+                    latencies.sort();
+                    let percentile = |p: f64| {
+                        let rank = (p * latencies.len() as f64).ceil() as usize;
+                        let latency = latencies
+                            .get(rank.max(1) - 1)
+                            .copied()
+                            .unwrap_or_default();
+                        format!("{:?}", latency)
+                    };
+                    ClientResult {
+                        operations: latencies.len(),
+                        p50: percentile(0.5),
+                        p95: percentile(0.95),
+                        p99: percentile(0.99),
+                        max: percentile(1.0),
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Measurement {
+    fn accumulate(&mut self, other: &Measurement) {
+        self.latency += other.latency;
+        self.cold_latency = match (self.cold_latency, other.cold_latency) {
+            (Some(lhs), Some(rhs)) => Some(lhs + rhs),
+            (lhs, rhs) => lhs.or(rhs),
+        };
+        self.connect_time = match (self.connect_time, other.connect_time) {
+            (Some(lhs), Some(rhs)) => Some(lhs + rhs),
+            (lhs, rhs) => lhs.or(rhs),
+        };
+        self.server_storage += other.server_storage;
+        self.client_storage += other.client_storage;
+        self.retries += other.retries;
+        self.padding_bytes += other.padding_bytes;
+        self.ciphertext_bytes += other.ciphertext_bytes;
+        if let Some(other) = other.concurrency.as_ref() {
+            // The samples of all rounds are pooled per client.
+            let samples =
+                self.concurrency.get_or_insert_with(|| ConcurrentSamples {
+                    latencies: vec![Vec::new(); other.latencies.len()],
+                    elapsed: Duration::default(),
+                });
+            samples
+                .latencies
+                .iter_mut()
+                .zip(other.latencies.iter())
+                .for_each(|(lhs, rhs)| lhs.extend_from_slice(rhs));
+            samples.elapsed += other.elapsed;
+        }
+        if let Some(other) = other.phases {
+            let phases = self.phases.get_or_insert_with(Default::default);
+            phases.partition += other.partition;
+            phases.transform += other.transform;
+            phases.smooth += other.smooth;
+        }
+        if let Some(other) = other.breakdown.as_ref() {
+            self.breakdown
+                .get_or_insert_with(Default::default)
+                .accumulate(other);
+        }
+        if let Some(other) = other.traffic.as_ref() {
+            self.traffic
+                .get_or_insert_with(Default::default)
+                .accumulate(other);
+        }
+    }
+
+    /// Average the accumulated measurement over `round` rounds. The retries are kept as the total, and the traffic is
+    /// averaged per operation by [`Traffic::result`].
+    fn average(&mut self, round: usize) {
+        self.latency /= round as u32;
+        self.cold_latency = self.cold_latency.map(|e| e / round as u32);
+        self.connect_time = self.connect_time.map(|e| e / round as u32);
+        self.server_storage /= round;
+        self.client_storage /= round;
+        self.padding_bytes /= round;
+        self.ciphertext_bytes /= round;
+        if let Some(phases) = self.phases.as_mut() {
+            phases.partition /= round as u32;
+            phases.transform /= round as u32;
+            phases.smooth /= round as u32;
+        }
+        if let Some(breakdown) = self.breakdown.as_mut() {
+            breakdown.average(round as u32);
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "snake_case")]
-struct PerfResult {
-    result: MainResult,
+pub struct PerfResult {
+    pub result: MainResult,
+    pub config: PerfConfig,
+}
+
+/// A suite that is not run, e.g., because its database is unreachable, or given up, e.g., because a database
+/// operation timed out.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+struct SkippedResult {
+    status: String,
+    reason: String,
     config: PerfConfig,
 }
 
 /// Execute the performance evaluation given the CLI arguments.
 /// Criterion has some weird issues when we want to filter benchmark groups.
 pub fn execute_perf(args: &Args) -> Result<()> {
-    let mut file = File::open(&args.config_path)?;
-    let mut content = Vec::new();
-    file.read_to_end(&mut content)?;
-
-    let mut test_suites =
-        toml::from_slice::<HashMap<String, Vec<PerfConfig>>>(&content)?
-            .remove("test_suites")
-            .unwrap();
-    test_suites.truncate(args.suite_num.unwrap_or(test_suites.len()));
+    let mut test_suites = SuiteQueue::<PerfConfig>::new(
+        &args.config_path,
+        args.watch,
+        args.suite_num,
+    )?;
 
     let mut file = match args.output_path.as_ref() {
         Some(path) => OpenOptions::new().append(true).create(true).open(path),
@@ -66,207 +426,870 @@ pub fn execute_perf(args: &Args) -> Result<()> {
         }
     }?;
 
-    for (idx, config) in test_suites.into_iter().enumerate() {
+    while let Some((idx, config)) = test_suites.next_suite() {
         info!("#{:<04}: Doing perf evaluations...", idx + 1,);
         debug!("The configuration is {:#?}", config);
 
-        let dataset = match config.dataset_type {
-            DatasetType::Real => {
-                if config.attributes.is_none() {
-                    return Err("Unsupported feature for `all`...".into());
-                }
+        if let Err(e) = check_database(&config) {
+            warn!("#{:<04}: No database; skipped. {}", idx + 1, e);
+            let skipped = SkippedResult {
+                status: "skipped: no database".to_string(),
+                reason: e.to_string(),
+                config,
+            };
+            write_skipped(&mut file, skipped)?;
+            continue;
+        }
 
-                let mut dataset = read_csv_multiple(
-                    config.data_path.as_ref().unwrap(),
-                    config.attributes.as_ref().unwrap().as_slice(),
-                )?;
+        let dataset = read_dataset(&config)?;
+        info!("Dataset read finished.");
 
-                if config.shuffle {
-                    dataset.iter_mut().for_each(|v| v.shuffle(&mut OsRng));
-                }
-                dataset
+        let results = match perf_suite(
+            args.round, &config, dataset, args.force, args.phase,
+        ) {
+            Ok(results) => results,
+            // A stalled database must not hold up the remaining suites.
+            Err(e) if is_deadline(e.as_ref()) => {
+                warn!("#{:<04}: Given up. {}", idx + 1, e);
+                let skipped = SkippedResult {
+                    status: "failed: timeout".to_string(),
+                    reason: e.to_string(),
+                    config,
+                };
+                write_skipped(&mut file, skipped)?;
+                continue;
             }
+            Err(e) => return Err(e),
+        };
+        for result in results {
+            // Store the attack result.
+            let mut toml = HashMap::new();
+            toml.insert("perf_result".to_string(), vec![result]);
+            let content = toml::Value::try_from(&toml)?.to_string();
+            file.write_all(content.as_bytes())?;
+            file.write_all(b"\n")?;
+        }
+    }
 
-            ty => {
-                let params = config.data_params.as_ref().unwrap();
-                let domain = params[0] as usize;
-                let support = (0..domain)
-                    .into_iter()
-                    .map(|_| String::random(32))
-                    .collect::<Vec<_>>();
-                let dataset = match ty == DatasetType::Normal {
-                    true => generate_synthetic_normal(
-                        &support,
-                        params[1] as usize,
-                        params[2],
-                    ),
-                    false => generate_synthetic_zipf(&support, params[1]),
-                };
+    Ok(())
+}
 
-                vec![dataset]
+/// Read the columns of a real dataset or generate the synthetic one of the configuration.
+pub fn read_dataset(config: &PerfConfig) -> Result<Vec<Vec<String>>> {
+    let dataset = match config.dataset_type {
+        DatasetType::Real => {
+            if config.attributes.is_none() {
+                return Err("Unsupported feature for `all`...".into());
             }
-        };
 
-        info!("Dataset read finished.");
+            let mut dataset = read_columns(
+                config.data_path.as_ref().unwrap(),
+                config.attributes.as_ref().unwrap().as_slice(),
+                config.csv.as_ref(),
+            )?;
 
-        for (idx, &res) in
-            do_perf(args.round, &config, &dataset)?.iter().enumerate()
-        {
-            let column_name = match config.dataset_type {
-                DatasetType::Real => config
-                    .attributes
-                    .as_ref()
-                    .unwrap()
-                    .get(idx)
-                    .unwrap()
-                    .clone(),
-                ty => {
-                    format!("{:?}", ty)
-                }
-            };
+            if config.shuffle {
+                dataset.iter_mut().for_each(|v| v.shuffle(&mut OsRng));
+            }
+            dataset
+        }
 
-            let result = PerfResult {
-                config: config.clone(),
-                result: MainResult {
-                    latency: format!("{:?}", res.0),
-                    server_storage: res.1,
-                    client_storage: res.2,
-                    column_name,
-                },
+        ty => {
+            let params = config.data_params.as_ref().unwrap();
+            let domain = params[0] as usize;
+            let support = (0..domain)
+                .into_iter()
+                .map(|_| String::random(32))
+                .collect::<Vec<_>>();
+            let dataset = match ty == DatasetType::Normal {
+                true => generate_synthetic_normal(
+                    &support,
+                    params[1] as usize,
+                    params[2],
+                ),
+                false => generate_synthetic_zipf(&support, params[1]),
             };
-            // Store the attack result.
-            let mut toml = HashMap::new();
-            toml.insert("perf_result".to_string(), vec![result]);
-            let content = toml::to_vec(&toml)?;
-            file.write_all(content.as_slice())?;
-            file.write_all(b"\n")?;
+
+            vec![dataset]
         }
+    };
+
+    Ok(dataset)
+}
+
+/// Benchmark the columns of `dataset`, e.g., those read by [`read_dataset`], for `round` rounds and return the result
+/// of each column. The database of the benchmark is expected to be up; see [`check_database`]. `force` and `phase` are
+/// those of [`crate::Args`].
+pub fn perf_suite(
+    round: usize,
+    config: &PerfConfig,
+    mut dataset: Vec<Vec<String>>,
+    force: bool,
+    phase: bool,
+) -> Result<Vec<PerfResult>> {
+    let mut config = config.clone();
+    let seed = *config.seed.get_or_insert_with(random_seed);
+    info!("The columns are sampled with seed {}.", seed);
+    let config = &config;
+    set_max_pool_size(config.pool_size);
+    if let Some(hook) = config.cache_hook.as_ref() {
+        drop_caches(config, hook)?;
+        info!("Caches dropped.");
+    }
+    if let Some(preprocess) = config.preprocess.as_ref() {
+        dataset = dataset.iter().map(|e| preprocess.apply_all(e)).collect();
+    }
+
+    let columns = match config.dataset_type {
+        DatasetType::Real => config.attributes.clone().unwrap(),
+        ty => vec![format!("{:?}", ty)],
+    };
+    let mut trace = match config.trace.as_ref() {
+        Some(trace) if trace.mode == TraceMode::Replay => {
+            QueryTrace::load(&trace.path)?
+        }
+        _ => QueryTrace::default(),
+    };
+
+    let run = config.run_id.clone().unwrap_or_else(new_run_id);
+    info!("The collections of the suite are those of run {}.", run);
+    let measurements = do_perf(
+        round,
+        config,
+        &dataset,
+        (&columns, &run),
+        &mut trace,
+        force,
+        phase,
+    )?;
+    if let Some(TraceConfig {
+        path,
+        mode: TraceMode::Capture,
+    }) = config.trace.as_ref()
+    {
+        trace.store(path)?;
+        info!("{} queries captured into {}.", trace.queries.len(), path);
+    }
+
+    let mut results = Vec::new();
+    for ((res, column_name), data) in
+        measurements.iter().zip(columns).zip(dataset.iter())
+    {
+        results.push(PerfResult {
+            config: config.clone(),
+            result: MainResult {
+                latency: format!("{:?}", res.latency),
+                cold_latency: res.cold_latency.map(|e| format!("{:?}", e)),
+                connect_latency: res.connect_time.map(|e| format!("{:?}", e)),
+                retries: res.retries,
+                server_storage: res.server_storage,
+                client_storage: res.client_storage,
+                column_name,
+                message_num: column_size(config, data),
+                padding: config.padding.map(|_| PaddingResult {
+                    bytes: res.padding_bytes,
+                    overhead: res.padding_bytes as f64
+                        / res.ciphertext_bytes.max(1) as f64,
+                }),
+                concurrency: res
+                    .concurrency
+                    .as_ref()
+                    .map(ConcurrentSamples::result),
+                phases: res.phases.as_ref().map(PhaseLatencies::result),
+                breakdown: res.breakdown.as_ref().map(Breakdown::result),
+                bandwidth: res.traffic.as_ref().map(Traffic::result),
+            },
+        });
     }
 
+    Ok(results)
+}
+
+fn write_skipped(file: &mut File, skipped: SkippedResult) -> Result<()> {
+    let mut toml = HashMap::new();
+    toml.insert("perf_skipped".to_string(), vec![skipped]);
+    let content = toml::Value::try_from(&toml)?.to_string();
+    file.write_all(content.as_bytes())?;
+    file.write_all(b"\n")?;
     Ok(())
 }
 
+/// Whether the suite failed because a database operation timed out or was cancelled.
+fn is_deadline(error: &(dyn std::error::Error + 'static)) -> bool {
+    matches!(
+        error.downcast_ref::<FseError>(),
+        Some(FseError::Timeout { .. } | FseError::Cancelled { .. })
+    )
+}
+
+/// Ping the database of a suite that needs one, i.e., the query and insert benchmarks and the admin command of the
+/// cache hook. The init benchmarks only encrypt, so they run without a database.
+pub fn check_database(config: &PerfConfig) -> Result<()> {
+    let admin_command = matches!(
+        config.cache_hook,
+        Some(CacheHook {
+            admin_command: Some(_),
+            ..
+        })
+    );
+    if (config.perf_type == PerfType::Init || config.is_embedded())
+        && !admin_command
+    {
+        return Ok(());
+    }
+
+    match config.addr.as_ref() {
+        Some(addr) => ping(addr, PING_TIMEOUT),
+        None => Err("No database address is given.".into()),
+    }
+}
+
 fn do_perf(
     round: usize,
     config: &PerfConfig,
     dataset: &[Vec<String>],
-) -> Result<Vec<(Duration, usize, usize)>> {
+    (columns, run): (&[String], &str),
+    trace: &mut QueryTrace,
+    force: bool,
+    phase: bool,
+) -> Result<Vec<Measurement>> {
+    if phase
+        && (config.perf_type != PerfType::Init
+            || config.fse_type != FSEType::Pfse)
+    {
+        return Err(
+            "Only the init benchmarks of PFSE can be broken down into phases."
+                .into(),
+        );
+    }
+
+    let storage = embedded_storage(config)?;
     let mut res = Vec::new();
+    // Resolved and recorded by the suite.
+    let seed = config.seed.unwrap_or_else(random_seed);
 
-    for data in dataset.iter() {
-        let mut duration = Duration::new(0, 0);
-        let mut server_storage = 0usize;
-        let mut client_storage = 0usize;
+    for (data, column) in dataset.iter().zip(columns) {
+        let id = CollectionId::new(&config.fse_type, column)
+            .params(config.fse_params.as_ref())
+            .run(run);
+        let mut measurement = Measurement::default();
         for idx in 1..=round {
             info!("Round #{:<04} started.", idx);
 
-            let size = config.size.unwrap_or(data.len()).min(data.len());
-            let mut data = data.clone();
-            data.shuffle(&mut OsRng);
-            let data_slice = &data[..size];
-            let result = match config.perf_type {
-                PerfType::Init => (do_init(config, data_slice), 0, 0),
-                PerfType::Query => (do_query(config, data_slice), 0, 0),
-                PerfType::Insert => {
-                    let ans =
-                        do_insert_and_get_sizes(config, data_slice).unwrap();
-                    (Ok(ans.0), ans.1, ans.2)
+            let size = column_size(config, data);
+            let data =
+                sample_or_cycle(data, size, seed.wrapping_add(idx as u64));
+            let data_slice = data.as_slice();
+            let result = match (&config.perf_type, &config.concurrency) {
+                (PerfType::Init, None) if phase => {
+                    do_init_phases(config, data_slice, &id.column)?
+                }
+                (PerfType::Init, None) => {
+                    do_init(config, data_slice, &id.column)?
                 }
+                (PerfType::Init, Some(_)) => {
+                    return Err(
+                        "Init benchmarks do not touch the database and cannot be run concurrently.".into()
+                    )
+                }
+                (PerfType::Query, None) => match storage.as_ref() {
+                    Some(storage) => do_query_embedded(
+                        config,
+                        data_slice,
+                        &id,
+                        (column, idx),
+                        trace,
+                        storage,
+                    )?,
+                    None => do_query(
+                        config,
+                        data_slice,
+                        &id,
+                        (column, idx),
+                        trace,
+                        force,
+                    )?,
+                },
+                (PerfType::Insert, None) => match storage.as_ref() {
+                    Some(storage) => {
+                        do_insert_embedded(config, data_slice, &id, storage)?
+                    }
+                    None => {
+                        do_insert_and_get_sizes(config, data_slice, &id, force)?
+                    }
+                },
+                (_, Some(concurrency)) => do_concurrent(
+                    config,
+                    concurrency,
+                    data_slice,
+                    &id,
+                    force,
+                )?,
             };
-
-            duration += result.0.unwrap();
-            server_storage += result.1;
-            client_storage += result.2;
+            measurement.accumulate(&result);
 
             info!("Round #{:<04} finished.", idx);
         }
-        duration /= round as u32;
-        server_storage /= round as usize;
-        client_storage /= round as usize;
+        measurement.average(round);
+        if let (Some(storage), true) = (storage.as_ref(), config.drop) {
+            storage.drop_collection(&id.name())?;
+        }
 
         warn!(
-            "[+] Perf {:?} finished against {:?}. Estimated latency is {:?}.",
-            config.perf_type, config.fse_type, duration
+            "[+] Perf {:?} finished against {:?}. Estimated latency is {:?} (cold-start: {:?}); {} retries performed.",
+            config.perf_type, config.fse_type, measurement.latency, measurement.cold_latency, measurement.retries
         );
 
-        res.push((duration, server_storage, client_storage));
+        res.push(measurement);
     }
 
     Ok(res)
 }
 
-fn do_init(config: &PerfConfig, dataset: &[String]) -> Result<Duration> {
+/// Open the embedded store of the suite. None ==> the suite runs against MongoDB.
+fn embedded_storage(config: &PerfConfig) -> Result<Option<SledBackend>> {
+    match config.backend.as_ref() {
+        Some(Backend::Sled { path: Some(path) }) => {
+            Ok(Some(SledBackend::open(path)?))
+        }
+        Some(Backend::Sled { path: None }) => {
+            Ok(Some(SledBackend::temporary()?))
+        }
+        Some(Backend::Mongo) | None => Ok(None),
+    }
+}
+
+/// The number of messages a benchmark on `data` is run on. A column smaller than the size of the suite is sampled with
+/// replacement up to it by [`sample_or_cycle`].
+fn column_size(config: &PerfConfig, data: &[String]) -> usize {
+    config.size.unwrap_or(data.len())
+}
+
+fn do_init(
+    config: &PerfConfig,
+    dataset: &[String],
+    column: &str,
+) -> Result<Measurement> {
     let instant = Instant::now();
-    match config.fse_type {
-        FSEType::Dte | FSEType::Rnd => init_native(config, dataset),
-        FSEType::LpfseIhbe | FSEType::LpfseBhe => init_lpfse(config, dataset),
-        FSEType::Pfse => init_pfse(config, dataset),
-        FSEType::Wre => unimplemented!(),
-    }?;
-    Ok(instant.elapsed())
+    init_context(config, dataset, column)?;
+    Ok(Measurement {
+        latency: instant.elapsed(),
+        ..Default::default()
+    })
+}
+
+/// Time each phase of the PFSE pipeline. The latency is the sum of the phases, so it excludes the key generation.
+fn do_init_phases(
+    config: &PerfConfig,
+    dataset: &[String],
+    column: &str,
+) -> Result<Measurement> {
+    let (_, phases) = init_pfse_phases(config, dataset, column)?;
+    Ok(Measurement {
+        latency: phases.partition + phases.transform + phases.smooth,
+        phases: Some(phases),
+        ..Default::default()
+    })
 }
 
 fn do_insert_and_get_sizes(
     config: &PerfConfig,
     dataset: &[String],
-) -> Result<(Duration, usize, usize)> {
+    id: &CollectionId,
+    force: bool,
+) -> Result<Measurement> {
+    let name = id.name();
     let instant = Instant::now();
-    let (data, ctx) = match config.fse_type {
-        FSEType::Dte | FSEType::Rnd => init_native(config, dataset),
-        FSEType::LpfseIhbe | FSEType::LpfseBhe => init_lpfse(config, dataset),
-        FSEType::Pfse => init_pfse(config, dataset),
-        FSEType::Wre => unimplemented!(),
-    }?;
-    insert(ctx.get_conn(), &data, &format!("{:?}", config.fse_type))?;
-    let server_storage = ctx.get_conn().size(&format!("{:?}", config.fse_type));
+    let (data, ctx) = init_context(config, dataset, &id.column)?;
+    let connect_time = ctx.get_conn().get_connect_time();
+    insert_load(ctx.get_conn(), &data, &name, force)?;
+    let latency = instant
+        .elapsed()
+        .saturating_sub(connect_time.unwrap_or_default());
+    let server_storage = ctx.get_conn().size(&name);
     let client_storage = ctx.size_allocated();
-    Ok((instant.elapsed(), server_storage, client_storage))
+    let timing = ctx.get_conn().get_insert_timing();
+    Ok(Measurement {
+        latency,
+        cold_latency: None,
+        connect_time,
+        server_storage,
+        client_storage,
+        retries: ctx.get_conn().get_retry_count(),
+        padding_bytes: ctx.get_conn().get_padding_bytes(),
+        ciphertext_bytes: data.iter().map(Vec::len).sum(),
+        concurrency: None,
+        phases: None,
+        breakdown: Some(Breakdown {
+            tokens: latency
+                .saturating_sub(timing.serialization + timing.round_trip),
+            serialization: timing.serialization,
+            round_trip: timing.round_trip,
+            ..Default::default()
+        }),
+        traffic: Some(Traffic {
+            queries: 0,
+            bandwidth: ctx.get_conn().get_bandwidth(),
+        }),
+    })
 }
 
-fn do_query(config: &PerfConfig, dataset: &[String]) -> Result<Duration> {
-    let (data, mut ctx) = match config.fse_type {
-        FSEType::Dte | FSEType::Rnd => init_native(config, dataset),
-        FSEType::LpfseIhbe | FSEType::LpfseBhe => init_lpfse(config, dataset),
-        FSEType::Pfse => init_pfse(config, dataset),
-        FSEType::Wre => unimplemented!(),
-    }?;
-    let name = format!("{:?}", config.fse_type);
-    insert(ctx.get_conn(), &data, &name)?;
+/// Issue the queries and measure the cold-start latency of the first query and the steady-state latency averaged over
+/// the queries after the warm-up phase. The queries of `(column, round)` are replayed from or captured into `trace`
+/// if the configuration asks for it.
+fn do_query(
+    config: &PerfConfig,
+    dataset: &[String],
+    id: &CollectionId,
+    (column, round): (&str, usize),
+    trace: &mut QueryTrace,
+    force: bool,
+) -> Result<Measurement> {
+    let (data, mut ctx) = init_context(config, dataset, &id.column)?;
+    let name = id.name();
+    insert_load(ctx.get_conn(), &data, &name, force)?;
 
-    let histogram = {
-        let histogram = fse::util::build_histogram(dataset);
-        fse::util::build_histogram_vec(&histogram)
-    };
-    let distribution = Uniform::new(0, histogram.len());
-    let query_number = config.query_number.unwrap_or(100);
+    let queries = sample_queries(config, dataset, (column, round), trace)?;
+    let policy = config.result_policy.unwrap_or_default();
+    let capture = matches!(
+        config.trace.as_ref(),
+        Some(trace) if trace.mode == TraceMode::Capture
+    );
 
-    let instant = Instant::now();
-    for i in 0..query_number {
-        let idx = distribution.sample(&mut OsRng);
-        query(ctx.as_mut(), &histogram[idx].0, &name)?;
+    let mut cold = None;
+    let mut steady = Duration::new(0, 0);
+    let mut breakdown = None;
+    let mut query_number = 0u32;
+    // The bytes of the load; those of the queries are added without the warm-up.
+    let mut bandwidth = ctx.get_conn().get_bandwidth();
+    for (i, (warmup, message)) in queries.iter().enumerate() {
+        let before = ctx.get_conn().get_bandwidth();
+        let (elapsed, timing) = match policy {
+            // The raw search can be split into its steps without changing what it does.
+            ResultPolicy::Raw => {
+                let (_, timing) = ctx.search_timed(message, &name, true)?;
+                (timing.total(), Some(timing))
+            }
+            _ => {
+                let instant = Instant::now();
+                query(ctx.as_mut(), message, &name, policy)?;
+                (instant.elapsed(), None)
+            }
+        };
+        cold.get_or_insert(elapsed);
+        if !warmup {
+            steady += elapsed;
+            query_number += 1;
+            bandwidth
+                .accumulate(&ctx.get_conn().get_bandwidth().since(&before));
+            if let Some(timing) = timing {
+                breakdown
+                    .get_or_insert_with(Breakdown::default)
+                    .accumulate(&timing.into());
+            }
+        }
+        if capture {
+            trace.record(column, round, *warmup, message);
+        }
         debug!(
-            "Query round {:<4?}: choosing {}; elapsed time {:?}",
-            i,
-            idx,
-            instant.elapsed()
+            "Query {:<4?} (warm-up: {}): choosing {}; elapsed time {:?}",
+            i, warmup, message, elapsed
         );
     }
-    Ok(instant.elapsed() / query_number as u32)
+
+    Ok(Measurement {
+        latency: steady / query_number,
+        cold_latency: cold,
+        connect_time: ctx.get_conn().get_connect_time(),
+        server_storage: 0,
+        client_storage: 0,
+        retries: ctx.get_conn().get_retry_count(),
+        padding_bytes: ctx.get_conn().get_padding_bytes(),
+        ciphertext_bytes: data.iter().map(Vec::len).sum(),
+        concurrency: None,
+        phases: None,
+        breakdown: breakdown.map(|mut e| {
+            e.average(query_number);
+            e
+        }),
+        traffic: Some(Traffic {
+            queries: query_number as usize,
+            bandwidth,
+        }),
+    })
+}
+
+/// [`do_query`] against the embedded store. The collection is reloaded from scratch each round, and every query is a
+/// raw search without a breakdown.
+fn do_query_embedded(
+    config: &PerfConfig,
+    dataset: &[String],
+    id: &CollectionId,
+    (column, round): (&str, usize),
+    trace: &mut QueryTrace,
+    storage: &SledBackend,
+) -> Result<Measurement> {
+    let (data, mut ctx) = init_context(config, dataset, &id.column)?;
+    let name = id.name();
+    storage.drop_collection(&name)?;
+    storage.insert(&data, &name)?;
+
+    let queries = sample_queries(config, dataset, (column, round), trace)?;
+    let capture = matches!(
+        config.trace.as_ref(),
+        Some(trace) if trace.mode == TraceMode::Capture
+    );
+    let mut cold = None;
+    let mut steady = Duration::new(0, 0);
+    let mut query_number = 0u32;
+    for (warmup, message) in queries.iter() {
+        let instant = Instant::now();
+        ctx.search_in(message, storage, &name)?;
+        let elapsed = instant.elapsed();
+        cold.get_or_insert(elapsed);
+        if !warmup {
+            steady += elapsed;
+            query_number += 1;
+        }
+        if capture {
+            trace.record(column, round, *warmup, message);
+        }
+    }
+
+    Ok(Measurement {
+        latency: steady / query_number,
+        cold_latency: cold,
+        ciphertext_bytes: data.iter().map(Vec::len).sum(),
+        ..Default::default()
+    })
+}
+
+/// [`do_insert_and_get_sizes`] against the embedded store. The server storage is the size of the keys of the
+/// collection.
+fn do_insert_embedded(
+    config: &PerfConfig,
+    dataset: &[String],
+    id: &CollectionId,
+    storage: &SledBackend,
+) -> Result<Measurement> {
+    let name = id.name();
+    storage.drop_collection(&name)?;
+    let instant = Instant::now();
+    let (data, ctx) = init_context(config, dataset, &id.column)?;
+    storage.insert(&data, &name)?;
+    let latency = instant.elapsed();
+    Ok(Measurement {
+        latency,
+        server_storage: storage.size(&name)?,
+        client_storage: ctx.size_allocated(),
+        ciphertext_bytes: data.iter().map(Vec::len).sum(),
+        ..Default::default()
+    })
+}
+
+/// The queries of `(column, round)` as (warm-up, message), replayed from `trace` if the configuration asks for it or
+/// sampled from the histogram of `dataset` otherwise.
+fn sample_queries(
+    config: &PerfConfig,
+    dataset: &[String],
+    (column, round): (&str, usize),
+    trace: &QueryTrace,
+) -> Result<Vec<(bool, String)>> {
+    let queries = match config.trace.as_ref().map(|e| e.mode) {
+        Some(TraceMode::Replay) => {
+            let queries = trace.queries_of(column, round);
+            if queries.iter().all(|e| e.0) {
+                return Err(format!(
+                    "The trace has no query of {} in round {}.",
+                    column, round
+                )
+                .into());
+            }
+            queries
+        }
+        _ => {
+            let histogram = {
+                let histogram = fse::util::build_histogram(dataset);
+                fse::util::build_histogram_vec(&histogram)
+            };
+            let distribution = Uniform::new(0, histogram.len());
+            let query_number = config.query_number.unwrap_or(100).max(1);
+            let warmup = config.warmup.unwrap_or(0);
+            (0..warmup + query_number)
+                .map(|i| {
+                    let idx = distribution.sample(&mut OsRng);
+                    (i < warmup, histogram[idx].0.clone())
+                })
+                .collect::<Vec<_>>()
+        }
+    };
+
+    Ok(queries)
+}
+
+/// Run the insert or query benchmark from `threads` clients at once. Each client builds its own context and
+/// collection, which happens before the measurement; the clients then start their operations at the same time.
+fn do_concurrent(
+    config: &PerfConfig,
+    concurrency: &ConcurrencyConfig,
+    dataset: &[String],
+    id: &CollectionId,
+    force: bool,
+) -> Result<Measurement> {
+    if concurrency.threads == 0 {
+        return Err("The concurrent mode needs at least one thread.".into());
+    }
+
+    let barrier = Barrier::new(concurrency.threads);
+    let clients = std::thread::scope(|scope| {
+        let handles = (0..concurrency.threads)
+            .map(|thread| {
+                let barrier = &barrier;
+                scope.spawn(move || {
+                    run_client(
+                        config,
+                        concurrency,
+                        dataset,
+                        id,
+                        force,
+                        thread,
+                        barrier,
+                    )
+                    .map_err(|e| e.to_string())
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| match handle.join() {
+                Ok(res) => res,
+                Err(_) => Err("The client thread panicked.".to_string()),
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()
+    })?;
+
+    let start = clients.iter().map(|e| e.start).min().unwrap();
+    let end = clients.iter().map(|e| e.end).max().unwrap();
+    let latencies = clients
+        .iter()
+        .map(|e| e.latencies.clone())
+        .collect::<Vec<_>>();
+    let operations = latencies.iter().map(Vec::len).sum::<usize>().max(1);
+
+    Ok(Measurement {
+        latency: latencies.iter().flatten().sum::<Duration>()
+            / operations as u32,
+        // The clients connect at the same time, so only the slowest one holds up the others.
+        connect_time: clients.iter().filter_map(|e| e.connect_time).max(),
+        retries: clients.iter().map(|e| e.retries).sum(),
+        padding_bytes: clients.iter().map(|e| e.padding_bytes).sum(),
+        ciphertext_bytes: clients.iter().map(|e| e.ciphertext_bytes).sum(),
+        traffic: Some(clients.iter().fold(Traffic::default(), |mut acc, e| {
+            acc.accumulate(&e.traffic);
+            acc
+        })),
+        concurrency: Some(ConcurrentSamples {
+            latencies,
+            elapsed: end - start,
+        }),
+        ..Default::default()
+    })
+}
+
+/// The operations of a single concurrent client.
+struct ClientSamples {
+    start: Instant,
+    end: Instant,
+    latencies: Vec<Duration>,
+    connect_time: Option<Duration>,
+    retries: usize,
+    padding_bytes: usize,
+    ciphertext_bytes: usize,
+    traffic: Traffic,
+}
+
+fn run_client(
+    config: &PerfConfig,
+    concurrency: &ConcurrencyConfig,
+    dataset: &[String],
+    id: &CollectionId,
+    force: bool,
+    thread: usize,
+    barrier: &Barrier,
+) -> Result<ClientSamples> {
+    let name = id
+        .clone()
+        .column(&format!("{}-client-{}", id.column, thread))
+        .name();
+    // Wait for the others even if the setup fails or panics so that no client is stuck at the barrier.
+    let setup = std::panic::catch_unwind(AssertUnwindSafe(|| -> Result<_> {
+        let (data, ctx) = init_context(config, dataset, &id.column)?;
+        if config.perf_type == PerfType::Query {
+            insert_load(ctx.get_conn(), &data, &name, force)?;
+        }
+        Ok((data, ctx))
+    }))
+    .unwrap_or_else(|_| Err("The setup of the client panicked.".into()));
+    barrier.wait();
+    let (data, ctx) = setup?;
+
+    // A panicking client must not take the whole scope down with it.
+    std::panic::catch_unwind(AssertUnwindSafe(|| {
+        measure_client(config, concurrency, dataset, &data, ctx, &name)
+    }))
+    .unwrap_or_else(|_| Err("The client panicked.".into()))
+}
+
+fn measure_client(
+    config: &PerfConfig,
+    concurrency: &ConcurrencyConfig,
+    dataset: &[String],
+    data: &[Vec<u8>],
+    mut ctx: Box<dyn BaseCrypto<String>>,
+    name: &String,
+) -> Result<ClientSamples> {
+    let mut latencies = Vec::new();
+    let start = Instant::now();
+    let queries = match config.perf_type {
+        PerfType::Insert => {
+            let batch_size =
+                concurrency.batch_size.unwrap_or(data.len()).max(1);
+            for batch in data.chunks(batch_size) {
+                let instant = Instant::now();
+                insert(ctx.get_conn(), batch, name)?;
+                latencies.push(instant.elapsed());
+            }
+            0
+        }
+        _ => {
+            let histogram = fse::util::build_histogram_vec(
+                &fse::util::build_histogram(dataset),
+            );
+            let distribution = Uniform::new(0, histogram.len());
+            let policy = config.result_policy.unwrap_or_default();
+            for _ in 0..config.query_number.unwrap_or(100).max(1) {
+                let idx = distribution.sample(&mut OsRng);
+                let instant = Instant::now();
+                query(ctx.as_mut(), &histogram[idx].0, name, policy)?;
+                latencies.push(instant.elapsed());
+            }
+            latencies.len()
+        }
+    };
+
+    Ok(ClientSamples {
+        start,
+        end: Instant::now(),
+        latencies,
+        connect_time: ctx.get_conn().get_connect_time(),
+        retries: ctx.get_conn().get_retry_count(),
+        padding_bytes: ctx.get_conn().get_padding_bytes(),
+        ciphertext_bytes: data.iter().map(Vec::len).sum(),
+        traffic: Traffic {
+            queries,
+            bandwidth: ctx.get_conn().get_bandwidth(),
+        },
+    })
+}
+
+/// Drop the OS and database caches by running the hook of the suite.
+fn drop_caches(config: &PerfConfig, hook: &CacheHook) -> Result<()> {
+    if let Some(command) = hook.command.as_ref() {
+        let status = Command::new("sh").arg("-c").arg(command).status()?;
+        if !status.success() {
+            return Err(format!(
+                "Cache hook `{}` failed with {}.",
+                command, status
+            )
+            .into());
+        }
+    }
+
+    if let Some(command) = hook.admin_command.as_ref() {
+        let (addr, name) = match (&config.addr, &config.db_name) {
+            (Some(addr), Some(name)) => (addr, name),
+            _ => return Err("No database found for the admin command.".into()),
+        };
+        let conn = Connector::<Data>::new(addr, name, false)?;
+        let res = conn.run_admin_command(command)?;
+        debug!("Admin command returned {:?}", res);
+    }
+
+    Ok(())
+}
+
+/// Construct the context of the scheme specified in the configuration, and encrypt the dataset with it.
+pub(crate) fn init_context(
+    config: &PerfConfig,
+    dataset: &[String],
+    column: &str,
+) -> Result<InitializedContext> {
+    let (ciphertexts, mut ctx) = match config.fse_type {
+        FSEType::Dte | FSEType::Rnd => init_native(config, dataset, column),
+        FSEType::LpfseIhbe | FSEType::LpfseBhe => {
+            init_lpfse(config, dataset, column)
+        }
+        FSEType::Pfse => init_pfse(config, dataset, column),
+        FSEType::Plain => init_plain(config, dataset),
+        FSEType::Wre => unimplemented!(),
+    }?;
+    ctx.set_token_limit(config.token_limit);
+
+    if let (Some(policy), Some(_), Some(_)) =
+        (config.retry, &config.addr, &config.db_name)
+    {
+        ctx.get_conn().set_retry_policy(policy);
+    }
+    if let (Some(_), Some(_)) = (&config.addr, &config.db_name) {
+        ctx.get_conn().set_padding_policy(config.padding)?;
+        ctx.get_conn().set_operation_timeout(
+            config.operation_timeout_ms.map(Duration::from_millis),
+        );
+        // The init benchmarks do not touch the database.
+        if config.perf_type != PerfType::Init {
+            ctx.get_conn().connect()?;
+        }
+    }
+
+    Ok((ciphertexts, ctx))
+}
+
+/// Install the cipher of the security level, and derive the key of `column` from the key of the scheme in the key
+/// provider of `key_dir`, so that the columns of a suite share a stored key but not their tokens, or generate a fresh
+/// key if there is no provider.
+fn load_key<C>(ctx: &mut C, config: &PerfConfig, column: &str) -> Result<()>
+where
+    C: BaseCrypto<String>,
+{
+    if let Some(level) = config.security_level {
+        ctx.set_security_level(level);
+    }
+    match &config.key_dir {
+        Some(dir) => {
+            let key = LocalFileProvider::open(dir)?.get_key_of_len(
+                config.fse_type.name(),
+                ctx.get_cipher().key_len(),
+            )?;
+            ctx.set_column_key(&key, column)
+        }
+        None => {
+            ctx.key_generate();
+            Ok(())
+        }
+    }
 }
 
 fn init_native(
     config: &PerfConfig,
     dataset: &[String],
-) -> Result<(Vec<String>, Box<dyn BaseCrypto<String>>)> {
+    column: &str,
+) -> Result<InitializedContext> {
     let rnd = config.fse_type == FSEType::Rnd;
     let mut ctx = ContextNative::new(rnd);
-    ctx.key_generate();
+    load_key(&mut ctx, config, column)?;
     let ciphertexts = dataset
         .iter()
-        .map(|message| {
-            let ciphertext = ctx.encrypt(message).unwrap().remove(0);
-            String::from_utf8(ciphertext).unwrap()
-        })
+        .map(|message| ctx.encrypt(message).unwrap().remove(0))
         .collect::<Vec<_>>();
 
     if let (Some(addr), Some(name)) = (&config.addr, &config.db_name) {
@@ -276,68 +1299,118 @@ fn init_native(
     Ok((ciphertexts, Box::new(ctx)))
 }
 
-fn init_pfse(
+/// The plaintext baseline, i.e., the cost of the database alone.
+fn init_plain(
     config: &PerfConfig,
     dataset: &[String],
-) -> Result<(Vec<String>, Box<dyn BaseCrypto<String>>)> {
-    if config.fse_params.is_none() {
-        return Err("No FSE params found.".into());
+) -> Result<InitializedContext> {
+    let mut ctx = ContextPlain::new();
+    let ciphertexts = dataset
+        .iter()
+        .map(|message| ctx.encrypt(message).unwrap().remove(0))
+        .collect::<Vec<_>>();
+
+    if let (Some(addr), Some(name)) = (&config.addr, &config.db_name) {
+        ctx.initialize_conn(addr, name, config.drop);
     }
 
+    Ok((ciphertexts, Box::new(ctx)))
+}
+
+fn init_pfse(
+    config: &PerfConfig,
+    dataset: &[String],
+    column: &str,
+) -> Result<InitializedContext> {
+    init_pfse_phases(config, dataset, column).map(|(context, _)| context)
+}
+
+/// The same as [`init_pfse`], but also returns the latency of each phase.
+fn init_pfse_phases(
+    config: &PerfConfig,
+    dataset: &[String],
+    column: &str,
+) -> Result<(InitializedContext, PhaseLatencies)> {
+    let params = match &config.fse_params {
+        Some(params) => params.pfse()?,
+        None => return Err("No FSE params found.".into()),
+    };
+
     let mut ctx = ContextPFSE::default();
-    ctx.key_generate();
-    ctx.set_params(config.fse_params.as_ref().unwrap());
-    ctx.partition(dataset, exponential);
-    ctx.transform();
+    load_key(&mut ctx, config, column)?;
+    ctx.set_params(&params)?;
+    ctx.set_progress(progress::reporter());
 
-    let ciphertexts = ctx
-        .smooth()
-        .into_iter()
-        .map(|e| String::from_utf8(e).unwrap())
-        .collect::<Vec<_>>();
+    let instant = Instant::now();
+    ctx.partition(dataset, exponential)?;
+    let partition = instant.elapsed();
+    let instant = Instant::now();
+    ctx.transform();
+    let transform = instant.elapsed();
+    let instant = Instant::now();
+    let ciphertexts = ctx.smooth();
+    let smooth = instant.elapsed();
 
     if let (Some(addr), Some(name)) = (&config.addr, &config.db_name) {
         ctx.initialize_conn(addr, name, config.drop);
     }
 
-    Ok((ciphertexts, Box::new(ctx)))
+    Ok((
+        (ciphertexts, Box::new(ctx)),
+        PhaseLatencies {
+            partition,
+            transform,
+            smooth,
+        },
+    ))
 }
 
 fn init_lpfse(
     config: &PerfConfig,
     dataset: &[String],
-) -> Result<(Vec<String>, Box<dyn BaseCrypto<String>>)> {
-    let params = config.fse_params.as_ref().unwrap();
+    column: &str,
+) -> Result<InitializedContext> {
+    let params = match &config.fse_params {
+        Some(params) => params.lpfse()?,
+        None => return Err("No FSE params found.".into()),
+    };
     let encoder: Box<dyn HomophoneEncoder<String>> =
         match config.fse_type == FSEType::LpfseBhe {
             true => Box::new(EncoderBHE::new()),
             false => Box::new(EncoderIHBE::new()),
         };
-    let mut ctx = ContextLPFSE::new(params[0], encoder);
-    ctx.key_generate();
+    let mut ctx = ContextLPFSE::from_params(&params, encoder)?;
+    load_key(&mut ctx, config, column)?;
     if let (Some(addr), Some(name)) = (&config.addr, &config.db_name) {
-        ctx.initialize(dataset, addr, name, config.drop);
+        ctx.initialize(dataset, addr, name, config.drop)?;
     } else {
-        ctx.initialize(dataset, "", "", false);
+        ctx.initialize(dataset, "", "", false)?;
     }
 
     let ciphertexts = dataset
         .iter()
-        .map(|e| String::from_utf8(ctx.encrypt(e).unwrap().remove(0)).unwrap())
+        .map(|e| ctx.encrypt(e).unwrap().remove(0))
         .collect::<Vec<_>>();
 
     Ok((ciphertexts, Box::new(ctx)))
 }
 
-fn insert(
+/// Insert the initial load of a context. The same ciphertexts are refused to be loaded twice unless `force` is set.
+pub(crate) fn insert_load(
     conn: &Connector<Data>,
-    dataset: &[String],
+    dataset: &[Vec<u8>],
     collection_name: &str,
+    force: bool,
 ) -> Result<()> {
-    let docs = dataset
-        .iter()
-        .map(|data| Data { data: data.clone() })
-        .collect::<Vec<_>>();
+    SmoothedLoad::new(dataset.to_vec()).insert(conn, collection_name, force)
+}
+
+pub(crate) fn insert(
+    conn: &Connector<Data>,
+    dataset: &[Vec<u8>],
+    collection_name: &str,
+) -> Result<()> {
+    let docs = dataset.iter().cloned().map(Data::new).collect::<Vec<_>>();
     conn.insert(docs, collection_name)?;
 
     Ok(())
@@ -347,8 +1420,9 @@ fn query(
     ctx: &mut dyn BaseCrypto<String>,
     message: &String,
     name: &String,
+    policy: ResultPolicy,
 ) -> Result<()> {
-    ctx.search(message, name);
+    ctx.search_with_policy(message, name, policy);
 
     Ok(())
 }
@@ -0,0 +1,284 @@
+//! A builder of a single evaluation suite, so that other research code can run the attack and perf evaluations
+//! without writing a configuration file or shelling out to the CLI, e.g.,
+//!
+//! ```no_run
+//! use eval::plan::EvalPlan;
+//! use fse::{params::PfseParams, FSEType};
+//!
+//! let result = EvalPlan::new()
+//!     .scheme(FSEType::Pfse)
+//!     .params(PfseParams::new(0.25, 1.0, 0.1))
+//!     .dataset("../data/test.csv", &["order_number"])
+//!     .rounds(10)
+//!     .run()
+//!     .unwrap();
+//! ```
+//!
+//! The plan is turned into the same [`AttackConfig`] or [`PerfConfig`] as a suite of a configuration file and checked
+//! by the same [`Validate`] rules, so the results are those the CLI would give.
+
+use fse::{
+    attack::AttackType, params::SchemeParams, util::CsvOptions, FSEType,
+};
+
+use crate::{
+    attack::{attack_suite, AttackResult},
+    config::{
+        read_columns, AttackConfig, DatasetType, PerfConfig, PerfType, Validate,
+    },
+    perf::{check_database, perf_suite, read_dataset, PerfResult},
+    Result,
+};
+
+/// The evaluation a plan runs.
+#[derive(Clone, Debug)]
+pub enum Evaluation {
+    Attack(AttackType),
+    Perf(PerfType),
+}
+
+/// The result of [`EvalPlan::run`].
+#[derive(Debug)]
+pub enum EvalResult {
+    Attack(Box<AttackResult>),
+    /// The result of each column.
+    Perf(Vec<PerfResult>),
+}
+
+#[derive(Clone, Debug)]
+pub struct EvalPlan {
+    fse_type: FSEType,
+    fse_params: Option<SchemeParams>,
+    evaluation: Evaluation,
+    data_path: Option<String>,
+    attributes: Vec<String>,
+    csv: Option<CsvOptions>,
+    rounds: usize,
+    size: Option<usize>,
+    shuffle: bool,
+    p_norm: Option<u8>,
+    /// The address and the name of the database of the perf evaluation.
+    database: Option<(String, String)>,
+    query_number: Option<usize>,
+}
+
+impl Default for EvalPlan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EvalPlan {
+    /// The default rounds, the same as those of the CLI.
+    pub const ROUNDS: usize = 10;
+
+    /// A plan of the MLE attack against DTE over shuffled columns.
+    pub fn new() -> Self {
+        Self {
+            fse_type: FSEType::Dte,
+            fse_params: None,
+            evaluation: Evaluation::Attack(AttackType::MleAttack),
+            data_path: None,
+            attributes: Vec::new(),
+            csv: None,
+            rounds: Self::ROUNDS,
+            size: None,
+            shuffle: true,
+            p_norm: None,
+            database: None,
+            query_number: None,
+        }
+    }
+
+    pub fn scheme(mut self, fse_type: FSEType) -> Self {
+        self.fse_type = fse_type;
+        self
+    }
+
+    pub fn params(mut self, params: impl Into<SchemeParams>) -> Self {
+        self.fse_params = Some(params.into());
+        self
+    }
+
+    /// Evaluate the `attributes` columns of the CSV file at `path`.
+    pub fn dataset(mut self, path: &str, attributes: &[&str]) -> Self {
+        self.data_path = Some(path.to_string());
+        self.attributes = attributes.iter().map(|e| e.to_string()).collect();
+        self
+    }
+
+    /// Parse the CSV file with `csv` instead of the default options.
+    pub fn csv(mut self, csv: CsvOptions) -> Self {
+        self.csv = Some(csv);
+        self
+    }
+
+    pub fn rounds(mut self, rounds: usize) -> Self {
+        self.rounds = rounds;
+        self
+    }
+
+    /// Evaluate the first `size` messages of each column only.
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn shuffle(mut self, shuffle: bool) -> Self {
+        self.shuffle = shuffle;
+        self
+    }
+
+    /// Mount `attack_type` against the scheme.
+    pub fn attack(mut self, attack_type: AttackType) -> Self {
+        self.evaluation = Evaluation::Attack(attack_type);
+        self
+    }
+
+    /// The p of the l_p optimization attack.
+    pub fn p_norm(mut self, p_norm: u8) -> Self {
+        self.p_norm = Some(p_norm);
+        self
+    }
+
+    /// Run the `perf_type` benchmark of the scheme instead of an attack.
+    pub fn perf(mut self, perf_type: PerfType) -> Self {
+        self.evaluation = Evaluation::Perf(perf_type);
+        self
+    }
+
+    /// The database of the query and insert benchmarks. The database is dropped once the benchmark finishes.
+    pub fn database(mut self, addr: &str, db_name: &str) -> Self {
+        self.database = Some((addr.to_string(), db_name.to_string()));
+        self
+    }
+
+    /// The number of queries of the query benchmark.
+    pub fn query_number(mut self, query_number: usize) -> Self {
+        self.query_number = Some(query_number);
+        self
+    }
+
+    /// The configuration of the attack the plan runs. Fails with all the problems of the plan if it is invalid.
+    pub fn attack_config(&self) -> Result<AttackConfig> {
+        let attack_type = match &self.evaluation {
+            Evaluation::Attack(attack_type) => attack_type.clone(),
+            Evaluation::Perf(_) => {
+                return Err("The plan runs a perf evaluation.".into())
+            }
+        };
+        let mut config = AttackConfig {
+            fse_type: self.fse_type.clone(),
+            attack_type,
+            data_path: self.data_path.clone().unwrap_or_default(),
+            csv: self.csv.clone(),
+            shuffle: self.shuffle,
+            attributes: Some(self.attributes.clone()),
+            fse_params: self.fse_params,
+            p_norm: self.p_norm,
+            regularization: None,
+            size: self.size,
+            seed: None,
+            aux_distance: None,
+            live: None,
+            ordering: None,
+            preprocess: None,
+            cap: None,
+            spill_threshold: None,
+            folds: None,
+            persistent: None,
+            dummies: None,
+            precision_recall_points: None,
+            composition: None,
+        };
+        checked(&mut config, self.data_path.is_some())?;
+        Ok(config)
+    }
+
+    /// The configuration of the benchmark the plan runs. Fails with all the problems of the plan if it is invalid.
+    pub fn perf_config(&self) -> Result<PerfConfig> {
+        let perf_type = match &self.evaluation {
+            Evaluation::Perf(perf_type) => perf_type.clone(),
+            Evaluation::Attack(_) => {
+                return Err("The plan runs an attack.".into())
+            }
+        };
+        let mut config = PerfConfig {
+            dataset_type: DatasetType::Real,
+            perf_type,
+            fse_type: self.fse_type.clone(),
+            data_path: self.data_path.clone(),
+            csv: self.csv.clone(),
+            shuffle: self.shuffle,
+            attributes: Some(self.attributes.clone()),
+            fse_params: self.fse_params,
+            preprocess: None,
+            data_params: None,
+            size: self.size,
+            seed: None,
+            query_number: self.query_number,
+            result_policy: None,
+            token_limit: None,
+            security_level: None,
+            warmup: None,
+            cache_hook: None,
+            retry: None,
+            padding: None,
+            operation_timeout_ms: None,
+            pool_size: None,
+            key_dir: None,
+            concurrency: None,
+            trace: None,
+            run_id: None,
+            backend: None,
+            addr: self.database.as_ref().map(|e| e.0.clone()),
+            db_name: self.database.as_ref().map(|e| e.1.clone()),
+            drop: true,
+        };
+        checked(&mut config, self.data_path.is_some())?;
+        Ok(config)
+    }
+
+    /// Read the dataset and run the evaluation.
+    pub fn run(&self) -> Result<EvalResult> {
+        match &self.evaluation {
+            Evaluation::Attack(_) => {
+                let config = self.attack_config()?;
+                let dataset = read_columns(
+                    &config.data_path,
+                    &self.attributes,
+                    config.csv.as_ref(),
+                )?;
+                let result = attack_suite(self.rounds, &config, dataset)?;
+                Ok(EvalResult::Attack(Box::new(result)))
+            }
+            Evaluation::Perf(_) => {
+                let config = self.perf_config()?;
+                check_database(&config)?;
+                let dataset = read_dataset(&config)?;
+                Ok(EvalResult::Perf(perf_suite(
+                    self.rounds,
+                    &config,
+                    dataset,
+                    false,
+                    false,
+                )?))
+            }
+        }
+    }
+}
+
+/// Resolve and validate `config` like a suite of a configuration file.
+fn checked<C: Validate>(config: &mut C, has_dataset: bool) -> Result<()> {
+    let mut problems = match has_dataset {
+        true => config.resolve(),
+        false => vec!["no dataset is given".to_string()],
+    };
+    if problems.is_empty() {
+        problems = config.validate();
+    }
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(format!("Invalid plan: {}.", problems.join("; ")).into()),
+    }
+}
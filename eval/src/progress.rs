@@ -0,0 +1,70 @@
+//! Draws the progress reports of the library as `indicatif` bars, one per phase. Enabled by `--progress`; otherwise
+//! the contexts get a reporter that does nothing.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use fse::progress::{Phase, Progress, ProgressEvent, ProgressReporter};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+const BAR_TEMPLATE: &str =
+    "{prefix:>9} [{elapsed_precise}] {wide_bar} {pos}/{len} (ETA {eta})";
+const SPINNER_TEMPLATE: &str =
+    "{prefix:>9} [{elapsed_precise}] {spinner} {pos} ({per_sec})";
+
+/// Draw the progress of the contexts constructed from now on.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// The reporter handed to the contexts of the evaluation.
+pub(crate) fn reporter() -> Progress {
+    match ENABLED.load(Ordering::Relaxed) {
+        true => Progress::new(IndicatifReporter::default()),
+        false => Progress::none(),
+    }
+}
+
+#[derive(Default)]
+struct IndicatifReporter {
+    multi: MultiProgress,
+    /// The bars of the running phases.
+    bars: Mutex<HashMap<Phase, ProgressBar>>,
+}
+
+impl IndicatifReporter {
+    fn new_bar(&self, event: &ProgressEvent) -> ProgressBar {
+        let bar = match event.total {
+            Some(total) => ProgressBar::new(total).with_style(
+                ProgressStyle::with_template(BAR_TEMPLATE).unwrap(),
+            ),
+            None => ProgressBar::new_spinner().with_style(
+                ProgressStyle::with_template(SPINNER_TEMPLATE).unwrap(),
+            ),
+        };
+        self.multi.add(bar.with_prefix(event.phase.name()))
+    }
+}
+
+impl ProgressReporter for IndicatifReporter {
+    fn report(&self, event: &ProgressEvent) {
+        let mut bars = self.bars.lock().unwrap();
+        let bar = bars
+            .entry(event.phase)
+            .or_insert_with(|| self.new_bar(event));
+        bar.set_position(event.done);
+
+        if event.finished {
+            if let Some(bar) = bars.remove(&event.phase) {
+                bar.finish();
+            }
+        }
+    }
+}
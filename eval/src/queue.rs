@@ -0,0 +1,144 @@
+//! A work queue of test suites fed by the configuration file. When watching is enabled, the configuration file is
+//! re-read whenever it is modified, and newly added test suites are appended to the queue without restarting the run.
+//! Suites that were already enqueued (and thus completed or in flight) are skipped.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::Read,
+    time::SystemTime,
+};
+
+use log::{info, warn};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{config::Validate, Result};
+
+pub struct SuiteQueue<C>
+where
+    C: DeserializeOwned + Serialize + Validate,
+{
+    /// The path to the configuration file.
+    path: String,
+    /// Should we watch the configuration file for new suites.
+    watch: bool,
+    /// The last modification time of the configuration file.
+    modified: Option<SystemTime>,
+    /// Suites that are waiting to be executed.
+    pending: VecDeque<C>,
+    /// How many times each (serialized) suite has been enqueued.
+    seen: HashMap<String, usize>,
+    /// The maximum number of suites to execute.
+    limit: Option<usize>,
+    /// The number of suites dequeued so far.
+    count: usize,
+}
+
+impl<C> SuiteQueue<C>
+where
+    C: DeserializeOwned + Serialize + Validate,
+{
+    pub fn new(path: &str, watch: bool, limit: Option<usize>) -> Result<Self> {
+        let mut queue = Self {
+            path: path.to_string(),
+            watch,
+            modified: None,
+            pending: VecDeque::new(),
+            seen: HashMap::new(),
+            limit,
+            count: 0,
+        };
+        queue.reload()?;
+
+        Ok(queue)
+    }
+
+    /// Get the next suite with its index. Returns `None` if there is no more suite to execute.
+    pub fn next_suite(&mut self) -> Option<(usize, C)> {
+        if matches!(self.limit, Some(limit) if self.count >= limit) {
+            return None;
+        }
+
+        if self.watch && self.is_modified() {
+            if let Err(e) = self.reload() {
+                warn!("Failed to reload the configuration file due to {}.", e);
+            }
+        }
+
+        let suite = self.pending.pop_front()?;
+        self.count += 1;
+        Some((self.count - 1, suite))
+    }
+
+    fn is_modified(&self) -> bool {
+        match std::fs::metadata(&self.path).and_then(|meta| meta.modified()) {
+            Ok(modified) => Some(modified) != self.modified,
+            Err(_) => false,
+        }
+    }
+
+    /// Parse and validate the configuration file, and enqueue all suites that have not been enqueued before. Nothing
+    /// is enqueued if any suite is invalid; the error lists the problems of all suites.
+    fn reload(&mut self) -> Result<()> {
+        self.modified = std::fs::metadata(&self.path)?.modified().ok();
+
+        let mut file = File::open(&self.path)?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+
+        let values =
+            toml::from_slice::<HashMap<String, Vec<toml::Value>>>(&content)?
+                .remove("test_suites")
+                .ok_or("No `test_suites` found in the configuration file.")?;
+
+        // Each suite is parsed on its own so that a typo in one does not hide the problems of the others.
+        let mut test_suites = Vec::new();
+        let mut problems = Vec::new();
+        for (idx, value) in values.into_iter().enumerate() {
+            match value.try_into::<C>() {
+                Ok(mut suite) => {
+                    let mut suite_problems = suite.resolve();
+                    if suite_problems.is_empty() {
+                        suite_problems = suite.validate();
+                    }
+                    problems.extend(
+                        suite_problems
+                            .into_iter()
+                            .map(|e| format!("suite #{}: {}", idx + 1, e)),
+                    );
+                    test_suites.push(suite);
+                }
+                Err(e) => problems.push(format!("suite #{}: {}", idx + 1, e)),
+            }
+        }
+        if !problems.is_empty() {
+            return Err(format!(
+                "Invalid configuration file {}:\n  {}",
+                self.path,
+                problems.join("\n  ")
+            )
+            .into());
+        }
+
+        let mut occurrences = HashMap::new();
+        let mut added = 0usize;
+        for suite in test_suites.into_iter() {
+            let key = toml::Value::try_from(&suite)?.to_string();
+            let occurrence = occurrences.entry(key.clone()).or_insert(0usize);
+            *occurrence += 1;
+
+            let seen = self.seen.entry(key).or_insert(0usize);
+            if *occurrence > *seen {
+                *seen += 1;
+                self.pending.push_back(suite);
+                added += 1;
+            }
+        }
+
+        if self.count != 0 {
+            info!("Configuration reloaded: {} new suite(s) enqueued.", added);
+        }
+
+        Ok(())
+    }
+}
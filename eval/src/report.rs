@@ -0,0 +1,452 @@
+//! Turns the accumulated results of the attack and perf evaluations into the tables and plot specifications of the
+//! standard figures: the accuracy of the attack against the advantage, the latency against the dataset size, and the
+//! server storage against lambda. Each figure gets a CSV table aggregated by scheme and parameter, a gnuplot script
+//! and a vega-lite specification that both read the table.
+//!
+//! If the results include the `plain` baseline, the accuracy and the latency of each scheme are also compared against
+//! it in `comparison.csv`, i.e., how much of the attack the scheme prevents and how much it costs.
+
+use std::{collections::BTreeSet, fs, path::Path};
+
+use fse::params::SchemeParams;
+use itertools::Itertools;
+use log::{debug, info, warn};
+use serde::Deserialize;
+
+use crate::{
+    config::{FSEType, PerfType},
+    Args, Result,
+};
+
+/// The output directory if none is given.
+const DEFAULT_OUTPUT: &str = "./figures";
+
+/// The results of a file written by the evaluations. Files of other evaluations have none of them.
+#[derive(Deserialize, Debug, Default)]
+struct ResultFile {
+    #[serde(default)]
+    attack_result: Vec<AttackEntry>,
+    #[serde(default)]
+    perf_result: Vec<PerfEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AttackEntry {
+    result: AttackSummary,
+    config: SuiteSummary,
+}
+
+#[derive(Deserialize, Debug)]
+struct AttackSummary {
+    mean_accuracy: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct PerfEntry {
+    result: PerfSummary,
+    config: SuiteSummary,
+}
+
+#[derive(Deserialize, Debug)]
+struct PerfSummary {
+    latency: String,
+    /// Absent from the results written before it was recorded.
+    #[serde(default)]
+    message_num: Option<usize>,
+    client_storage: usize,
+    server_storage: usize,
+}
+
+/// The part of the configuration of a suite the figures depend on.
+#[derive(Deserialize, Debug)]
+struct SuiteSummary {
+    fse_type: FSEType,
+    perf_type: Option<PerfType>,
+    size: Option<usize>,
+    fse_params: Option<SchemeParams>,
+}
+
+impl SuiteSummary {
+    fn advantage(&self) -> Option<f64> {
+        match self.fse_params? {
+            SchemeParams::Pfse(params) => Some(params.advantage),
+            SchemeParams::Lpfse(params) => Some(params.advantage),
+            _ => None,
+        }
+    }
+
+    fn lambda(&self) -> Option<f64> {
+        match self.fse_params? {
+            SchemeParams::Pfse(params) => Some(params.lambda),
+            SchemeParams::Wre(params) => Some(params.lambda as f64),
+            _ => None,
+        }
+    }
+}
+
+/// A standard figure. The first value column is plotted against `x`; the other ones are only tabulated.
+struct Figure {
+    name: &'static str,
+    x: &'static str,
+    values: &'static [&'static str],
+    log_x: bool,
+}
+
+const ACCURACY_VS_ADVANTAGE: Figure = Figure {
+    name: "accuracy_vs_advantage",
+    x: "advantage",
+    values: &["accuracy"],
+    log_x: true,
+};
+
+const LATENCY_VS_SIZE: Figure = Figure {
+    name: "latency_vs_size",
+    x: "size",
+    values: &["latency_ms"],
+    log_x: true,
+};
+
+const STORAGE_VS_LAMBDA: Figure = Figure {
+    name: "storage_vs_lambda",
+    x: "lambda",
+    values: &["server_storage", "client_storage"],
+    log_x: false,
+};
+
+/// The scheme every other scheme is compared against.
+const BASELINE: FSEType = FSEType::Plain;
+
+/// A measurement of a scheme next to the same measurement of [`BASELINE`].
+#[derive(Debug, Clone, PartialEq)]
+struct Comparison {
+    metric: &'static str,
+    series: String,
+    x: f64,
+    value: f64,
+    baseline: f64,
+}
+
+/// A single measurement of a figure.
+#[derive(Debug, Clone, PartialEq)]
+struct Sample {
+    series: String,
+    x: f64,
+    values: Vec<f64>,
+}
+
+/// The mean of the samples sharing a series and an x value.
+#[derive(Debug, Clone, PartialEq)]
+struct Row {
+    series: String,
+    x: f64,
+    values: Vec<f64>,
+    samples: usize,
+}
+
+/// Generate the figures from the result files given by `--input` into the directory given by `--output-path`.
+pub fn execute_report(args: &Args) -> Result<()> {
+    if args.input.is_empty() {
+        return Err("No result file is given by `--input`.".into());
+    }
+    let output = args.output_path.as_deref().unwrap_or(DEFAULT_OUTPUT);
+    fs::create_dir_all(output)?;
+
+    let mut results = ResultFile::default();
+    for path in args.input.iter() {
+        let file = toml::from_str::<ResultFile>(&fs::read_to_string(path)?)
+            .map_err(|e| format!("Cannot parse {}: {}", path, e))?;
+        debug!(
+            "{}: {} attack and {} perf results.",
+            path,
+            file.attack_result.len(),
+            file.perf_result.len()
+        );
+        results.attack_result.extend(file.attack_result);
+        results.perf_result.extend(file.perf_result);
+    }
+
+    let comparisons = compare(&results);
+    let (accuracy, latency, storage) = collect_samples(&results);
+    for (figure, samples) in [
+        (ACCURACY_VS_ADVANTAGE, accuracy),
+        (LATENCY_VS_SIZE, latency),
+        (STORAGE_VS_LAMBDA, storage),
+    ] {
+        if samples.is_empty() {
+            warn!("No result for {}; skipped.", figure.name);
+            continue;
+        }
+
+        let rows = aggregate(samples);
+        let dir = Path::new(output);
+        fs::write(
+            dir.join(format!("{}.csv", figure.name)),
+            to_csv(&figure, &rows),
+        )?;
+        fs::write(
+            dir.join(format!("{}.gp", figure.name)),
+            to_gnuplot(&figure, &rows),
+        )?;
+        fs::write(
+            dir.join(format!("{}.vl.json", figure.name)),
+            to_vega_lite(&figure),
+        )?;
+        info!("{} written with {} rows.", figure.name, rows.len());
+    }
+
+    match comparisons.is_empty() {
+        true => debug!("No {} result to compare against.", BASELINE.name()),
+        false => {
+            fs::write(
+                Path::new(output).join("comparison.csv"),
+                comparison_to_csv(&comparisons),
+            )?;
+            info!("comparison written with {} rows.", comparisons.len());
+        }
+    }
+
+    Ok(())
+}
+
+/// Compare the accuracy and the latency of each scheme against [`BASELINE`]. The baseline has no parameters, so the
+/// accuracy of each advantage is compared against its mean accuracy, and a latency is compared against the latency of
+/// the baseline of the same benchmark and size.
+fn compare(results: &ResultFile) -> Vec<Comparison> {
+    let mut comparisons = Vec::new();
+    let (accuracy, latency, _) = collect_samples(results);
+
+    let baseline_accuracy = results
+        .attack_result
+        .iter()
+        .filter(|e| e.config.fse_type == BASELINE)
+        .map(|e| e.result.mean_accuracy)
+        .collect::<Vec<_>>();
+    if !baseline_accuracy.is_empty() {
+        let baseline = baseline_accuracy.iter().sum::<f64>()
+            / baseline_accuracy.len() as f64;
+        comparisons.extend(aggregate(accuracy).into_iter().map(|row| {
+            Comparison {
+                metric: "accuracy",
+                series: row.series,
+                x: row.x,
+                value: row.values[0],
+                baseline,
+            }
+        }));
+    }
+
+    // The latency series are named `<scheme> (<benchmark>)`.
+    let rows = aggregate(latency);
+    for row in rows.iter() {
+        let (scheme, benchmark) = match row.series.split_once(' ') {
+            Some(split) => split,
+            None => continue,
+        };
+        if scheme == BASELINE.name() {
+            continue;
+        }
+
+        let series = format!("{} {}", BASELINE.name(), benchmark);
+        if let Some(baseline) =
+            rows.iter().find(|e| e.series == series && e.x == row.x)
+        {
+            comparisons.push(Comparison {
+                metric: "latency_ms",
+                series: row.series.clone(),
+                x: row.x,
+                value: row.values[0],
+                baseline: baseline.values[0],
+            });
+        }
+    }
+
+    comparisons
+}
+
+/// Split the results into the samples of each figure. Results without the parameter of a figure are left out of it.
+fn collect_samples(
+    results: &ResultFile,
+) -> (Vec<Sample>, Vec<Sample>, Vec<Sample>) {
+    let accuracy = results
+        .attack_result
+        .iter()
+        .filter_map(|entry| {
+            Some(Sample {
+                series: entry.config.fse_type.name().to_string(),
+                x: entry.config.advantage()?,
+                values: vec![entry.result.mean_accuracy],
+            })
+        })
+        .collect();
+
+    let mut latency = Vec::new();
+    let mut storage = Vec::new();
+    for entry in results.perf_result.iter() {
+        let series = entry.config.fse_type.name().to_string();
+        let perf_type = match entry.config.perf_type.as_ref() {
+            Some(perf_type) => perf_type,
+            None => continue,
+        };
+
+        let size = entry.result.message_num.or(entry.config.size);
+        match (size, parse_latency_ms(&entry.result.latency)) {
+            (Some(size), Some(latency_ms)) => latency.push(Sample {
+                series: format!("{} ({:?})", series, perf_type).to_lowercase(),
+                x: size as f64,
+                values: vec![latency_ms],
+            }),
+            (_, None) => {
+                warn!("Unrecognized latency {}.", entry.result.latency)
+            }
+            (None, _) => debug!("No dataset size for a {} result.", series),
+        }
+
+        // Only the init benchmarks report the storage of the whole dataset.
+        if let (Some(lambda), PerfType::Init) =
+            (entry.config.lambda(), perf_type)
+        {
+            storage.push(Sample {
+                series,
+                x: lambda,
+                values: vec![
+                    entry.result.server_storage as f64,
+                    entry.result.client_storage as f64,
+                ],
+            });
+        }
+    }
+
+    (accuracy, latency, storage)
+}
+
+/// Average the samples sharing a series and an x value, sorted by series and then x.
+fn aggregate(mut samples: Vec<Sample>) -> Vec<Row> {
+    samples.sort_by(|lhs, rhs| {
+        lhs.series.cmp(&rhs.series).then(lhs.x.total_cmp(&rhs.x))
+    });
+
+    samples
+        .into_iter()
+        .group_by(|e| (e.series.clone(), e.x))
+        .into_iter()
+        .map(|((series, x), group)| {
+            let group = group.collect::<Vec<_>>();
+            let mut values = vec![0.0; group[0].values.len()];
+            for sample in group.iter() {
+                values
+                    .iter_mut()
+                    .zip(sample.values.iter())
+                    .for_each(|(sum, value)| *sum += value);
+            }
+            values.iter_mut().for_each(|e| *e /= group.len() as f64);
+
+            Row {
+                series,
+                x,
+                values,
+                samples: group.len(),
+            }
+        })
+        .collect()
+}
+
+/// Parse a latency printed by the `Debug` implementation of `Duration`, e.g., `1.5ms`, into milliseconds.
+fn parse_latency_ms(latency: &str) -> Option<f64> {
+    let units = [("ns", 1e-6), ("µs", 1e-3), ("ms", 1.0), ("s", 1e3)];
+    units.iter().find_map(|(unit, scale)| {
+        let value = latency.strip_suffix(unit)?.parse::<f64>().ok()?;
+        Some(value * scale)
+    })
+}
+
+/// The ratio is the value relative to the baseline, e.g., the slowdown of a latency.
+fn comparison_to_csv(comparisons: &[Comparison]) -> String {
+    let mut csv = "metric,series,x,value,baseline,ratio\n".to_string();
+    for e in comparisons.iter() {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            e.metric,
+            e.series,
+            e.x,
+            e.value,
+            e.baseline,
+            e.value / e.baseline
+        ));
+    }
+    csv
+}
+
+fn to_csv(figure: &Figure, rows: &[Row]) -> String {
+    let mut csv =
+        format!("series,{},{},samples\n", figure.x, figure.values.join(","));
+    for row in rows.iter() {
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            row.series,
+            row.x,
+            row.values.iter().join(","),
+            row.samples
+        ));
+    }
+    csv
+}
+
+/// A gnuplot script that draws one line per series into `<name>.png`.
+fn to_gnuplot(figure: &Figure, rows: &[Row]) -> String {
+    let series = rows
+        .iter()
+        .map(|e| e.series.as_str())
+        .collect::<BTreeSet<_>>();
+    let plots = series
+        .iter()
+        .map(|series| {
+            format!(
+                "'{name}.csv' using 2:(strcol(1) eq '{series}' ? $3 : NaN) \
+                 with linespoints title '{series}'",
+                name = figure.name,
+            )
+        })
+        .join(", \\\n     ");
+
+    format!(
+        "set datafile separator ','\n\
+         set terminal pngcairo size 800,600\n\
+         set output '{name}.png'\n\
+         set xlabel '{x}'\n\
+         set ylabel '{y}'\n\
+         {log}\
+         set key outside\n\
+         plot {plots}\n",
+        name = figure.name,
+        x = figure.x,
+        y = figure.values[0],
+        log = match figure.log_x {
+            true => "set logscale x\n",
+            false => "",
+        },
+    )
+}
+
+/// A vega-lite specification that draws one line per series.
+fn to_vega_lite(figure: &Figure) -> String {
+    format!(
+        r#"{{
+  "$schema": "https://vega.github.io/schema/vega-lite/v5.json",
+  "data": {{ "url": "{name}.csv" }},
+  "mark": {{ "type": "line", "point": true }},
+  "encoding": {{
+    "x": {{ "field": "{x}", "type": "quantitative", "scale": {{ "type": "{scale}" }} }},
+    "y": {{ "field": "{y}", "type": "quantitative" }},
+    "color": {{ "field": "series", "type": "nominal" }}
+  }}
+}}
+"#,
+        name = figure.name,
+        x = figure.x,
+        y = figure.values[0],
+        scale = match figure.log_x {
+            true => "log",
+            false => "linear",
+        },
+    )
+}
@@ -0,0 +1,237 @@
+//! The soak test runs continuous insert + query cycles against a single context for a long time while sampling the
+//! memory usage of the process, the size of the client state and the size of the collection. The samples are written
+//! to a time-series CSV file so that leaks (e.g., the nonce table of RND or the homophone sets of BHE) can be analyzed.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use chrono::Local;
+use fse::{
+    db::{new_run_id, CollectionId},
+    drift::DriftMonitor,
+    preprocess::Preprocess,
+    util::{build_histogram, read_csv_exact, sample_or_cycle},
+};
+use log::{debug, info, warn};
+use rand::{seq::SliceRandom, Rng};
+use rand_core::OsRng;
+
+use crate::{
+    config::{random_seed, PerfConfig, SoakConfig},
+    perf::{init_context, insert, insert_load},
+    queue::SuiteQueue,
+    Args, Result,
+};
+
+/// A single sample of the soak test.
+#[derive(Debug, Clone)]
+struct SoakSample {
+    elapsed: Duration,
+    cycle: usize,
+    rss: usize,
+    client_storage: usize,
+    server_storage: usize,
+    /// The number of retries performed on transient database failures so far.
+    retries: usize,
+    /// The drift of the inserted messages from the initial dataset, if monitored.
+    drift: Option<f64>,
+    /// The seed the column was sampled with.
+    seed: u64,
+}
+
+impl SoakSample {
+    const HEADER: &'static str =
+        "suite,elapsed_secs,cycle,rss_kb,client_storage,server_storage,retries,drift,seed";
+
+    fn to_csv_line(&self, suite: usize) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{}",
+            suite,
+            self.elapsed.as_secs(),
+            self.cycle,
+            self.rss,
+            self.client_storage,
+            self.server_storage,
+            self.retries,
+            self.drift.map(|e| e.to_string()).unwrap_or_default(),
+            self.seed
+        )
+    }
+}
+
+/// Execute the soak test given the CLI arguments.
+pub fn execute_soak(args: &Args) -> Result<()> {
+    let mut test_suites = SuiteQueue::<SoakConfig>::new(
+        &args.config_path,
+        args.watch,
+        args.suite_num,
+    )?;
+
+    let mut file = match args.output_path.as_ref() {
+        Some(path) => OpenOptions::new().append(true).create(true).open(path),
+        None => {
+            let date = Local::now();
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(format!("./soak_{:?}.csv", date))
+        }
+    }?;
+    writeln!(file, "{}", SoakSample::HEADER)?;
+
+    while let Some((idx, config)) = test_suites.next_suite() {
+        info!("#{:<04}: Doing soak test...", idx + 1);
+        debug!("The configuration is {:#?}", config);
+
+        let samples = do_soak(&config, &mut file, idx + 1, args.force)?;
+        check_growth(&config, &samples);
+    }
+
+    Ok(())
+}
+
+fn do_soak(
+    config: &SoakConfig,
+    file: &mut File,
+    suite: usize,
+    force: bool,
+) -> Result<Vec<SoakSample>> {
+    let mut dataset = read_csv_exact(&config.data_path, &config.attribute)?;
+    if let Some(preprocess) = config.preprocess.as_ref() {
+        dataset = preprocess.apply_all(&dataset);
+    }
+    let size = config.size.unwrap_or(dataset.len());
+    // The batches and the queries draw their messages from the dataset, which must not be empty.
+    if size == 0 || dataset.is_empty() {
+        return Err(format!(
+            "Cannot soak `{}`: the column is empty or `size` is 0.",
+            config.attribute
+        )
+        .into());
+    }
+    let seed = config.seed.unwrap_or_else(random_seed);
+    info!("The column is sampled with seed {}.", seed);
+    let dataset = sample_or_cycle(&dataset, size, seed);
+
+    let perf_config = PerfConfig::from(config);
+    let id = CollectionId::new(&config.fse_type, &config.attribute)
+        .params(config.fse_params.as_ref())
+        .run(&format!("soak-{}", new_run_id()));
+    let (data, mut ctx) =
+        init_context(&perf_config, &dataset[..size], &id.column)?;
+    let name = id.name();
+    insert_load(ctx.get_conn(), &data, &name, force)?;
+    info!("Context initialized with {} messages.", size);
+    let mut monitor = match config.drift.as_ref() {
+        Some(drift) => Some(DriftMonitor::new(
+            ctx.get_key(),
+            &build_histogram(&dataset[..size]),
+            drift.top_k,
+            drift.threshold,
+        )?),
+        None => None,
+    };
+
+    let duration = Duration::from_secs(config.duration);
+    let interval = Duration::from_secs(config.sample_interval);
+    let mut samples = Vec::new();
+    let mut cycle = 0usize;
+    let instant = Instant::now();
+    let mut last_sample = instant;
+
+    while instant.elapsed() < duration {
+        cycle += 1;
+
+        // Insert a batch of messages drawn from the support of the dataset.
+        let mut batch = Vec::with_capacity(config.batch_size);
+        for _ in 0..config.batch_size {
+            let message = &dataset[OsRng.gen_range(0..size)];
+            if let Some(monitor) = monitor.as_mut() {
+                monitor.observe(message);
+            }
+            if let Some(ciphertexts) = ctx.encrypt(message) {
+                if let Some(ciphertext) = ciphertexts.choose(&mut OsRng) {
+                    batch.push(ciphertext.clone());
+                }
+            }
+        }
+        insert(ctx.get_conn(), &batch, &name)?;
+
+        // Then issue some queries.
+        for _ in 0..config.query_number {
+            let message = &dataset[OsRng.gen_range(0..size)];
+            ctx.search(message, &name);
+        }
+
+        if last_sample.elapsed() >= interval {
+            last_sample = Instant::now();
+            let report = monitor.as_ref().map(DriftMonitor::report);
+            if let Some(report) = report.as_ref().filter(|e| e.alert) {
+                warn!(
+                    "The inserted messages drifted from the initial dataset by {:.4} (top-k mass {:.4} vs. {:.4}).",
+                    report.distance, report.observed_skew, report.baseline_skew
+                );
+            }
+            let sample = SoakSample {
+                elapsed: instant.elapsed(),
+                cycle,
+                rss: resident_set_size(),
+                client_storage: ctx.size_allocated(),
+                server_storage: ctx.get_conn().size(&name),
+                retries: ctx.get_conn().get_retry_count(),
+                drift: report.map(|e| e.distance),
+                seed,
+            };
+            debug!("Sampled {:?}", sample);
+            writeln!(file, "{}", sample.to_csv_line(suite))?;
+            samples.push(sample);
+        }
+    }
+
+    ctx.get_conn().drop_collection(&name);
+    Ok(samples)
+}
+
+/// Flag the client state as leaking if it never shrinks and keeps growing over the whole run.
+fn check_growth(config: &SoakConfig, samples: &[SoakSample]) {
+    let monotonic = |values: Vec<usize>| {
+        values.len() > 1
+            && values.windows(2).all(|w| w[0] <= w[1])
+            && values.first() < values.last()
+    };
+
+    if monotonic(samples.iter().map(|e| e.client_storage).collect()) {
+        warn!(
+            "[!] The client state of {:?} grows monotonically: {} -> {} bytes.",
+            config.fse_type,
+            samples.first().unwrap().client_storage,
+            samples.last().unwrap().client_storage
+        );
+    }
+
+    if monotonic(samples.iter().map(|e| e.rss).collect()) {
+        warn!(
+            "[!] The RSS of the process grows monotonically against {:?}: {} -> {} kB.",
+            config.fse_type,
+            samples.first().unwrap().rss,
+            samples.last().unwrap().rss
+        );
+    }
+}
+
+/// Read the resident set size (in kB) of the current process. Returns 0 if it is not available on this platform.
+fn resident_set_size() -> usize {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find(|line| line.starts_with("VmRSS:"))
+                .and_then(|line| line.split_whitespace().nth(1))
+                .and_then(|rss| rss.parse().ok())
+        })
+        .unwrap_or(0)
+}
@@ -0,0 +1,249 @@
+//! The statistics of a dataset: the shape of the distribution of each column and the parameters that smooth it to a
+//! target advantage bound. They help to choose the scheme and its parameters before running the attacks.
+
+use std::{collections::HashMap, fs::OpenOptions, io::Write};
+
+use chrono::Local;
+use fse::{
+    fse::{exponential, BaseCrypto, PartitionFrequencySmoothing},
+    lpfse::{EncoderBHE, EncoderIHBE, HomophoneEncoder},
+    params::{LpfseParams, PfseParams, SchemeParams},
+    pfse::ContextPFSE,
+    preprocess::Preprocess,
+    security::advantage_bound,
+    util::{build_histogram, build_histogram_vec, ZipfMixture},
+};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{read_columns, FSEType, StatsConfig},
+    queue::SuiteQueue,
+    Args, Result,
+};
+
+/// The advantages tried by the calibration, from the cheapest to the most expensive.
+const ADVANTAGE_GRID: [f64; 11] = [
+    0.5, 0.2, 0.1, 0.05, 0.02, 0.01, 5e-3, 2e-3, 1e-3, 1e-4, 1e-5,
+];
+
+/// The partition parameter and the scaling factor of the calibrated PFSE.
+const PFSE_LAMBDA: f64 = 0.25;
+const PFSE_SCALE: f64 = 1.0;
+
+/// The number of EM iterations of the Zipf fit.
+const ZIPF_ITERATIONS: usize = 20;
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+struct HeavyHitter {
+    value: String,
+    count: usize,
+    frequency: f64,
+}
+
+/// The cheapest parameters of a scheme whose analytical advantage bound meets the target.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+struct Recommendation {
+    fse_type: FSEType,
+    fse_params: SchemeParams,
+    advantage_bound: f64,
+    /// The number of stored ciphertexts per message, dummies and copies included. Present only for PFSE.
+    storage_overhead: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+struct ColumnStats {
+    column_name: String,
+    rows: usize,
+    distinct: usize,
+    /// The Shannon entropy in bits.
+    entropy: f64,
+    /// The entropy of the uniform distribution over the distinct values, i.e., the most a scheme can hide.
+    max_entropy: f64,
+    /// The exponent of the Zipf distribution fitted to the ranked histogram.
+    zipf_exponent: f64,
+    heavy_hitters: Vec<HeavyHitter>,
+    /// The schemes that cannot meet the target bound on the grid are left out.
+    recommendations: Vec<Recommendation>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+struct StatsResult {
+    columns: Vec<ColumnStats>,
+    config: StatsConfig,
+}
+
+/// Report the statistics of the datasets given the CLI arguments.
+pub fn execute_stats(args: &Args) -> Result<()> {
+    let mut test_suites = SuiteQueue::<StatsConfig>::new(
+        &args.config_path,
+        args.watch,
+        args.suite_num,
+    )?;
+
+    let mut file = match args.output_path.as_ref() {
+        Some(path) => OpenOptions::new().append(true).create(true).open(path),
+        None => {
+            let date = Local::now();
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(format!("./stats_{:?}.toml", date))
+        }
+    }?;
+
+    while let Some((idx, config)) = test_suites.next_suite() {
+        info!("#{:<04}: Computing dataset statistics...", idx + 1);
+        debug!("The configuration is {:#?}", config);
+
+        let mut dataset = read_columns(
+            &config.data_path,
+            &config.attributes,
+            config.csv.as_ref(),
+        )?;
+        if let Some(preprocess) = config.preprocess.as_ref() {
+            dataset = dataset.iter().map(|e| preprocess.apply_all(e)).collect();
+        }
+        if let Some(size) = config.size {
+            dataset.iter_mut().for_each(|e| e.truncate(size));
+        }
+
+        let columns = dataset
+            .iter()
+            .zip(config.attributes.iter())
+            .map(|(data, column_name)| column_stats(&config, data, column_name))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut toml = HashMap::new();
+        toml.insert(
+            "stats_result".to_string(),
+            vec![StatsResult {
+                columns,
+                config: config.clone(),
+            }],
+        );
+        let content = toml::Value::try_from(&toml)?.to_string();
+        file.write_all(content.as_bytes())?;
+        file.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+fn column_stats(
+    config: &StatsConfig,
+    data: &[String],
+    column_name: &str,
+) -> Result<ColumnStats> {
+    let histogram = build_histogram(data);
+    let ranked = build_histogram_vec(&histogram);
+    let rows = data.len();
+
+    let entropy = ranked
+        .iter()
+        .map(|e| e.1 as f64 / rows as f64)
+        .map(|p| -p * p.log2())
+        .sum::<f64>();
+    let zipf_exponent = ZipfMixture::fit(&ranked, 1, ZIPF_ITERATIONS)
+        .components
+        .first()
+        .map_or(0.0, |e| e.1);
+    let heavy_hitters = ranked
+        .iter()
+        .take(config.top_k.unwrap_or(10))
+        .map(|(value, count)| HeavyHitter {
+            value: value.clone(),
+            count: *count,
+            frequency: *count as f64 / rows as f64,
+        })
+        .collect();
+
+    let target = config.target_bound.unwrap_or(0.1);
+    let mut recommendations = Vec::new();
+    for fse_type in [FSEType::Pfse, FSEType::LpfseIhbe, FSEType::LpfseBhe] {
+        if let Some(recommendation) = calibrate(fse_type, &histogram, target)? {
+            recommendations.push(recommendation);
+        }
+    }
+
+    Ok(ColumnStats {
+        column_name: column_name.to_string(),
+        rows,
+        distinct: ranked.len(),
+        entropy,
+        max_entropy: (ranked.len().max(1) as f64).log2(),
+        zipf_exponent,
+        heavy_hitters,
+        recommendations,
+    })
+}
+
+/// Find the largest advantage on the grid, i.e., the fewest dummies or homophones, whose bound meets `target`.
+fn calibrate(
+    fse_type: FSEType,
+    histogram: &HashMap<String, usize>,
+    target: f64,
+) -> Result<Option<Recommendation>> {
+    for advantage in ADVANTAGE_GRID {
+        let (fse_params, state, storage_overhead) = match fse_type {
+            FSEType::Pfse => {
+                let params =
+                    PfseParams::new(PFSE_LAMBDA, PFSE_SCALE, advantage);
+                let mut ctx = ContextPFSE::default();
+                ctx.key_generate();
+                ctx.set_params(&params)?;
+                ctx.partition_histogram(histogram, exponential)?;
+                let stats = ctx.transform();
+                let stored = ctx
+                    .get_local_table()
+                    .values()
+                    .flatten()
+                    .map(|&(_, size, cnt)| size * cnt)
+                    .sum::<usize>()
+                    + stats.dummy_ciphertext_num();
+                let messages = histogram.values().sum::<usize>().max(1);
+                let overhead = stored as f64 / messages as f64;
+                (
+                    SchemeParams::Pfse(params),
+                    ctx.scheme_state(),
+                    Some(overhead),
+                )
+            }
+            _ => {
+                let mut encoder: Box<dyn HomophoneEncoder<String>> =
+                    match fse_type {
+                        FSEType::LpfseIhbe => Box::new(EncoderIHBE::new()),
+                        _ => Box::new(EncoderBHE::new()),
+                    };
+                // A grid point the encoder cannot be built for is not a candidate.
+                if let Err(e) =
+                    encoder.initialize_histogram(histogram, advantage)
+                {
+                    debug!("Skipping advantage {}: {}", advantage, e);
+                    continue;
+                }
+                (
+                    SchemeParams::Lpfse(LpfseParams::new(advantage)),
+                    encoder.scheme_state(),
+                    None,
+                )
+            }
+        };
+
+        let bound = state.as_ref().map_or(1.0, advantage_bound);
+        if bound <= target {
+            return Ok(Some(Recommendation {
+                fse_type,
+                fse_params,
+                advantage_bound: bound,
+                storage_overhead,
+            }));
+        }
+    }
+
+    Ok(None)
+}
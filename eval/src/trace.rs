@@ -0,0 +1,72 @@
+//! A query trace records the messages queried by a perf run so that another run can issue exactly the same queries,
+//! e.g., against another scheme or another parameter set. Comparing the latencies (or the leakage) of two schemes
+//! under the same trace removes the noise of sampling the queries independently.
+//!
+//! The replaying run should read the same dataset with the same `size` and without shuffling, as a traced message
+//! that is not in its dataset simply matches nothing.
+
+use std::{fs::File, io::Read};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// A single query of a trace.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct TracedQuery {
+    pub column: String,
+    /// The round of the run, counted from 1.
+    pub round: usize,
+    /// Whether the query was issued during the warm-up.
+    pub warmup: bool,
+    pub message: String,
+}
+
+/// The queries of a run in the order they were issued.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct QueryTrace {
+    pub queries: Vec<TracedQuery>,
+}
+
+impl QueryTrace {
+    pub fn load(path: &str) -> Result<Self> {
+        let mut content = Vec::new();
+        File::open(path)?.read_to_end(&mut content)?;
+        Ok(toml::from_slice(&content)?)
+    }
+
+    pub fn store(&self, path: &str) -> Result<()> {
+        std::fs::write(path, toml::Value::try_from(self)?.to_string())?;
+        Ok(())
+    }
+
+    pub fn record(
+        &mut self,
+        column: &str,
+        round: usize,
+        warmup: bool,
+        message: &str,
+    ) {
+        self.queries.push(TracedQuery {
+            column: column.to_string(),
+            round,
+            warmup,
+            message: message.to_string(),
+        });
+    }
+
+    /// The `(warmup, message)` queries of `column` in `round`.
+    pub fn queries_of(
+        &self,
+        column: &str,
+        round: usize,
+    ) -> Vec<(bool, String)> {
+        self.queries
+            .iter()
+            .filter(|e| e.column == column && e.round == round)
+            .map(|e| (e.warmup, e.message.clone()))
+            .collect()
+    }
+}
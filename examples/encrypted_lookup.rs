@@ -0,0 +1,108 @@
+//! A minimal encrypted lookup service. The client smooths the `department` column of a small CSV with PFSE and
+//! uploads the ciphertexts to a server that only stores and matches opaque bytes; a lookup sends the search tokens of a
+//! department and decrypts whatever the server returns.
+//!
+//! The server is kept in memory so that the example runs without MongoDB; [`BaseCrypto::search`] does the same
+//! against a collection. Run it by
+//!
+//! ```sh
+//! cargo run --example encrypted_lookup -- sales
+//! ```
+
+use fse::{
+    fse::{exponential, BaseCrypto, FromBytes, PartitionFrequencySmoothing},
+    params::PfseParams,
+    pfse::ContextPFSE,
+    token::TokenSet,
+    util::read_csv_exact,
+    Result,
+};
+
+const DATA_PATH: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/examples/data/employees.csv");
+const COLUMN: &str = "department";
+
+/// The server only sees the smoothed ciphertexts.
+struct LookupServer {
+    documents: Vec<Vec<u8>>,
+}
+
+impl LookupServer {
+    fn new(documents: Vec<Vec<u8>>) -> Self {
+        Self { documents }
+    }
+
+    /// Return every document that equals one of the tokens.
+    fn lookup(&self, tokens: &TokenSet) -> Vec<Vec<u8>> {
+        self.documents
+            .iter()
+            .filter(|e| tokens.contains(e))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Upload the column and look up `query`. Returns the decrypted matches and the number of stored documents.
+fn run(query: &str) -> Result<(Vec<String>, usize)> {
+    let data = read_csv_exact(DATA_PATH, COLUMN)?;
+
+    let mut ctx = ContextPFSE::default();
+    ctx.key_generate();
+    ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1))?;
+    ctx.partition(&data, exponential)?;
+    let stats = ctx.transform();
+    println!(
+        "[+] Smoothed {} records of `{}` into {} partitions with {} dummies.",
+        data.len(),
+        COLUMN,
+        stats.partitions.len(),
+        stats.dummy_num()
+    );
+
+    let server = LookupServer::new(ctx.smooth());
+
+    let tokens = ctx
+        .search_tokens(&query.to_string())
+        .ok_or("The department is not in the dataset.")?;
+    let matches = server
+        .lookup(&tokens)
+        .iter()
+        .map(|e| Ok(String::from_bytes(&ctx.decrypt_checked(e)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((matches, server.documents.len()))
+}
+
+fn main() -> Result<()> {
+    let query = std::env::args().nth(1).unwrap_or_else(|| "sales".into());
+    let (matches, stored) = run(&query)?;
+
+    println!(
+        "[+] The server stores {} documents; {} match `{}`.",
+        stored,
+        matches.len(),
+        query
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use fse::util::build_histogram;
+
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let data = read_csv_exact(DATA_PATH, COLUMN).unwrap();
+        let histogram = build_histogram(&data);
+
+        for (department, count) in histogram.iter() {
+            let (matches, _) = run(department).unwrap();
+            // The copies are repeated evenly, so rounding may return a few more documents than the records.
+            assert!(matches.len() >= *count);
+            assert!(matches.iter().all(|e| e == department));
+        }
+        assert!(run("unknown").is_err());
+    }
+}
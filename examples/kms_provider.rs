@@ -0,0 +1,139 @@
+//! Keep the key of a context in a mock of an AWS-KMS-like service instead of generating and storing it locally.
+//!
+//! The service owns a key-encryption key per key id that never leaves it. The client only ever stores data keys
+//! wrapped under it, as returned by `GenerateDataKey`, and asks the service to unwrap them when a context is built. A
+//! second context built from the same key id can thus decrypt what the first one encrypted. Run it by
+//!
+//! ```sh
+//! cargo run --example kms_provider -- alice@example.com
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use fse::{
+    error::FseError,
+    fse::{BaseCrypto, FromBytes},
+    keys::{generate_key, unwrap_key, wrap_key, KeyProvider},
+    native::ContextNative,
+    Result,
+};
+
+const KEY_ID: &str = "arn:aws:kms:us-east-1:000000000000:key/fse-demo";
+
+/// An in-memory stand-in for a KMS. Every call is counted as a request to the service.
+#[derive(Debug)]
+struct MockKms {
+    key_id: String,
+    /// The key-encryption key of `key_id`, which a real service never reveals.
+    kek: Vec<u8>,
+    /// The wrapped data keys, which a real client would keep next to its data.
+    wrapped: Mutex<HashMap<String, Vec<u8>>>,
+    requests: AtomicUsize,
+}
+
+impl MockKms {
+    fn new(key_id: &str) -> Self {
+        Self {
+            key_id: key_id.to_string(),
+            kek: generate_key(),
+            wrapped: Mutex::new(HashMap::new()),
+            requests: AtomicUsize::new(0),
+        }
+    }
+
+    /// `GenerateDataKey`: a fresh data key in the clear and wrapped.
+    fn generate_data_key(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        let key = generate_key();
+        let wrapped = wrap_key(&self.kek, &key)?;
+        Ok((key, wrapped))
+    }
+
+    fn requests(&self) -> usize {
+        self.requests.load(Ordering::Relaxed)
+    }
+}
+
+impl KeyProvider for MockKms {
+    fn get_key(&self, name: &str) -> Result<Vec<u8>> {
+        let wrapped = self.wrapped.lock().unwrap().get(name).cloned();
+        if let Some(wrapped) = wrapped {
+            return self.unwrap(&wrapped);
+        }
+
+        let (key, wrapped) = self.generate_data_key()?;
+        self.wrapped
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), wrapped);
+        Ok(key)
+    }
+
+    /// `Encrypt`.
+    fn wrap(&self, key: &[u8]) -> Result<Vec<u8>> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        wrap_key(&self.kek, key)
+    }
+
+    /// `Decrypt`.
+    fn unwrap(&self, wrapped: &[u8]) -> Result<Vec<u8>> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        unwrap_key(&self.kek, wrapped).map_err(|_| {
+            FseError::InvalidParams(format!(
+                "The key was not wrapped under {}",
+                self.key_id
+            ))
+            .into()
+        })
+    }
+}
+
+/// Encrypt `message` by one context and decrypt it by another one built from the same key.
+fn run(kms: &MockKms, message: &str) -> Result<String> {
+    let mut writer = ContextNative::new(false);
+    writer.key_from_provider(kms, "email")?;
+    let ciphertext = writer.encrypt(&message.to_string()).unwrap().remove(0);
+
+    let mut reader = ContextNative::<String>::new(false);
+    reader.key_from_provider(kms, "email")?;
+    Ok(String::from_bytes(&reader.decrypt_checked(&ciphertext)?))
+}
+
+fn main() -> Result<()> {
+    let message = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "alice@example.com".into());
+    let kms = MockKms::new(KEY_ID);
+    let decrypted = run(&kms, &message)?;
+
+    println!(
+        "[+] Decrypted `{}` with a data key of {} after {} requests to the KMS.",
+        decrypted,
+        kms.key_id,
+        kms.requests()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_kms() {
+        let kms = MockKms::new(KEY_ID);
+        assert_eq!(run(&kms, "bob@example.com").unwrap(), "bob@example.com");
+        // One `GenerateDataKey` and one `Decrypt`.
+        assert_eq!(kms.requests(), 2);
+
+        // Another key id cannot unwrap the data key.
+        let wrapped = kms.wrapped.lock().unwrap()["email"].clone();
+        assert!(MockKms::new("other").unwrap(&wrapped).is_err());
+    }
+}
@@ -1,18 +1,47 @@
 //! This module mainly implements the inference-attack family. This contains the frequency analysis, l_p optimization as well as
 //! the (scaled) MLE attack. This module should be enabled by the `attack` (optional) feature.
 
-use std::{collections::HashMap, fmt::Debug, hash::Hash, marker::PhantomData};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    hash::Hash,
+    marker::PhantomData,
+    ops::Range,
+};
 
-use log::error;
+use itertools::Itertools;
+use log::{error, info};
+use mongodb::bson::doc;
 use pathfinding::{
     kuhn_munkres::kuhn_munkres_min,
     prelude::{Matrix, Weights},
 };
+use rand::seq::SliceRandom;
+use rand_core::OsRng;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    fse::{HistType, Random, ValueType},
-    util::{self, build_histogram, build_histogram_vec, pad_auxiliary},
+    bucketed::ContextBucketed,
+    db::{Connector, Data},
+    dummy::DomainFilter,
+    error::FseError,
+    fse::{
+        exponential, AsBytes, BaseCrypto, FromBytes, HistType,
+        PartitionFrequencySmoothing, Random, ValueType,
+    },
+    lpfse::{ContextLPFSE, HomophoneEncoder},
+    native::ContextNative,
+    pfse::ContextPFSE,
+    plain::ContextPlain,
+    security::advantage_bound,
+    streaming::ContextStreaming,
+    util::{
+        self, build_histogram, build_histogram_fast, build_histogram_vec,
+        build_histogram_vec_spilled, checked_div, pad_auxiliary,
+        total_variation, SizeAllocated,
+    },
+    wre::ContextWRE,
+    Result,
 };
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -20,6 +49,141 @@ use crate::{
 pub enum AttackType {
     LpOptimization,
     MleAttack,
+    /// See [`SaltCountAttacker`]; meant for WRE.
+    SaltCount,
+}
+
+/// The outcome of an attack per plaintext: `message -> (occurrences, recovered occurrences)`. The recovered
+/// occurrences are in expectation for soft assignments.
+pub type Recovery<T> = HashMap<T, (usize, f64)>;
+
+/// The accuracy of the attack on the messages whose frequency rank falls within `ranks`, given as fractions of the
+/// number of distinct messages: `0.0..0.1` is the 10% most frequent messages. Like the overall accuracy, it is
+/// weighted by occurrences. Returns `NaN` if no message falls within `ranks`.
+pub fn rank_accuracy<T>(recovery: &Recovery<T>, ranks: Range<f64>) -> f64
+where
+    T: Eq + Hash,
+{
+    let mut entries = recovery.values().collect::<Vec<_>>();
+    entries.sort_by_key(|e| std::cmp::Reverse(e.0));
+
+    let n = entries.len() as f64;
+    let (total, recovered) = entries
+        .into_iter()
+        .enumerate()
+        .filter(|(rank, _)| ranks.contains(&(*rank as f64 / n)))
+        .fold((0usize, 0f64), |(total, recovered), (_, &(cnt, rec))| {
+            (total + cnt, recovered + rec)
+        });
+
+    match total {
+        0 => f64::NAN,
+        total => recovered / total as f64,
+    }
+}
+
+/// Break the accuracy down by the frequency deciles of the plaintexts, from the most frequent to the least. Smoothing
+/// schemes usually protect the tail much better than the head, which a single accuracy hides.
+pub fn decile_accuracy<T>(recovery: &Recovery<T>) -> Vec<f64>
+where
+    T: Eq + Hash,
+{
+    (0..10)
+        .map(|i| {
+            rank_accuracy(recovery, i as f64 / 10.0..(i + 1) as f64 / 10.0)
+        })
+        .collect()
+}
+
+/// A guess of the attacker about the occurrences of a message in one auxiliary entry, together with how much the
+/// attacker trusts it. The confidence is only comparable between the guesses of the same attack.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Guess<T> {
+    pub message: T,
+    /// The occurrences of the message the guess is about.
+    pub count: usize,
+    /// Higher ==> more trusted.
+    pub confidence: f64,
+    /// The fraction of the occurrences actually recovered, which the attacker does not know.
+    pub recovered: f64,
+}
+
+/// The precision and the recall of the guesses an attacker trusts, i.e., those with a confidence of at least
+/// `threshold`. Both are weighted by occurrences: the precision is the accuracy on the trusted guesses and the recall
+/// is the fraction of all occurrences recovered by them.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct PrecisionRecall {
+    pub threshold: f64,
+    pub precision: f64,
+    pub recall: f64,
+}
+
+/// The precision and the recall of the guesses with a confidence of at least `threshold`. The precision is `NaN` if
+/// no guess is trusted.
+pub fn precision_recall<T>(
+    guesses: &[Guess<T>],
+    threshold: f64,
+) -> PrecisionRecall {
+    let total = guesses.iter().map(|e| e.count).sum::<usize>();
+    let (trusted, recovered) = guesses
+        .iter()
+        .filter(|e| e.confidence >= threshold)
+        .fold((0usize, 0f64), |(trusted, recovered), e| {
+            (trusted + e.count, recovered + e.recovered * e.count as f64)
+        });
+
+    PrecisionRecall {
+        threshold,
+        precision: checked_div(recovered, trusted as f64).unwrap_or(f64::NAN),
+        recall: checked_div(recovered, total as f64).unwrap_or_default(),
+    }
+}
+
+/// The precision and the recall at `points` thresholds: the i-th point trusts the i / `points` most confident of the
+/// guesses, so the last point trusts all of them and its recall is the accuracy. The points of different attacks can
+/// thus be averaged although their confidences are on different scales.
+pub fn precision_recall_curve<T>(
+    guesses: &[Guess<T>],
+    points: usize,
+) -> Vec<PrecisionRecall> {
+    let mut confidences = guesses.iter().map(|e| e.confidence).collect_vec();
+    confidences.sort_by(|lhs, rhs| rhs.total_cmp(lhs));
+    if confidences.is_empty() {
+        return Vec::new();
+    }
+
+    (1..=points)
+        .map(|i| {
+            let trusted = (i * confidences.len() / points).max(1);
+            precision_recall(guesses, confidences[trusted - 1])
+        })
+        .collect()
+}
+
+/// Accumulate the recovery and the guess of one auxiliary entry. Messages the auxiliary data was padded with are not
+/// plaintexts and are left out.
+fn record_recovery<T>(
+    recovery: &mut Recovery<T>,
+    guesses: &mut Vec<Guess<T>>,
+    correct: &HashMap<T, Vec<Vec<u8>>>,
+    message: &T,
+    count: usize,
+    rate: f64,
+    confidence: f64,
+) where
+    T: Eq + Hash + Clone,
+{
+    if correct.contains_key(message) {
+        let entry = recovery.entry(message.clone()).or_default();
+        entry.0 += count;
+        entry.1 += rate * count as f64;
+        guesses.push(Guess {
+            message: message.clone(),
+            count,
+            confidence,
+            recovered: rate,
+        });
+    }
 }
 
 /// An attacker that uses the $\ell_{p}$-norm to optimize the attack. The basic idea is find an as-signment from ciphertexts to
@@ -31,8 +195,20 @@ where
 {
     /// The `p` norm.
     p: usize,
+    /// The strength of the entropic regularization. If set, the attacker solves the regularized transport problem
+    /// instead of the exact assignment and outputs a soft assignment.
+    regularization: Option<f64>,
     /// The assignment.
     assignment: Option<Vec<usize>>,
+    /// The soft assignment: `soft_assignment[i][j]` is the probability that the i-th message is mapped to the j-th
+    /// ciphertext.
+    soft_assignment: Option<Vec<Vec<f64>>>,
+    /// The recovery of each message under the last assignment.
+    recovery: Option<Recovery<T>>,
+    /// The guesses of the last assignment. See [`LpAttacker::get_guesses`].
+    guesses: Option<Vec<Guess<T>>>,
+    /// The number of ciphertexts above which their histogram is built on disk. See [`ciphertext_histogram`].
+    spill_threshold: Option<usize>,
     /// A marker.
     _marker: PhantomData<T>,
 }
@@ -44,13 +220,53 @@ where
     pub fn new(p: usize) -> Self {
         Self {
             p,
+            regularization: None,
             assignment: None,
+            soft_assignment: None,
+            recovery: None,
+            guesses: None,
+            spill_threshold: None,
             _marker: PhantomData,
         }
     }
 
+    /// Construct an attacker that outputs a soft assignment by entropic regularization with strength `epsilon`. The
+    /// costs are normalized into `[0, 1]`, so `epsilon` does not depend on the size of the dataset; smaller values
+    /// approach the exact assignment.
+    pub fn with_regularization(p: usize, epsilon: f64) -> Self {
+        Self {
+            regularization: Some(epsilon),
+            ..Self::new(p)
+        }
+    }
+
+    /// Build the histogram of the ciphertexts on disk once there are more than `threshold` of them.
+    pub fn set_spill_threshold(&mut self, threshold: Option<usize>) {
+        self.spill_threshold = threshold;
+    }
+
+    pub fn get_assignment(&self) -> Option<&Vec<usize>> {
+        self.assignment.as_ref()
+    }
+
+    pub fn get_soft_assignment(&self) -> Option<&Vec<Vec<f64>>> {
+        self.soft_assignment.as_ref()
+    }
+
+    /// Get the recovery of each message under the last assignment. See [`decile_accuracy`].
+    pub fn get_recovery(&self) -> Option<&Recovery<T>> {
+        self.recovery.as_ref()
+    }
+
+    /// Get the guesses of the last assignment. The confidence of an exact assignment is the cost margin of each
+    /// message, i.e., how much more its cheapest other ciphertext would cost, in units of the largest cost; that of a
+    /// soft assignment is the probability of its most likely ciphertext. See [`precision_recall`].
+    pub fn get_guesses(&self) -> Option<&Vec<Guess<T>>> {
+        self.guesses.as_ref()
+    }
+
     /// Perform the lp optimization attack and store the assignment within itself.
-    /// Finally it outputs the recovery rate.
+    /// Finally it outputs the recovery rate, which is the expected recovery rate for the soft assignment.
     pub fn attack(
         &mut self,
         correct: &HashMap<T, Vec<Vec<u8>>>,
@@ -68,62 +284,178 @@ where
         }
         auxiliary.sort_by(|lhs, rhs| rhs.1.partial_cmp(&lhs.1).unwrap());
 
-        let ciphertexts = {
-            let histogram = build_histogram(raw_ciphertexts);
-            build_histogram_vec(&histogram)
-        };
+        let ciphertexts =
+            ciphertext_histogram(raw_ciphertexts, self.spill_threshold);
+
+        // The soft assignment does not need |C| = |M|, so the histograms are transported as they are.
+        if let Some(epsilon) = self.regularization {
+            let rows = auxiliary.iter().map(|e| e.2).collect::<Vec<_>>();
+            let columns = ciphertexts.iter().map(|e| e.1).collect::<Vec<_>>();
+            self.soft_assignment = Some(sinkhorn(
+                &self.build_soft_cost_matrix(&auxiliary, &ciphertexts),
+                &rows,
+                &columns,
+                epsilon,
+            ));
+            return self.get_expected_recovery_rate(
+                correct,
+                &auxiliary,
+                &ciphertexts,
+            );
+        }
 
         // If the sizes of these two datasets does not match, we do some random padding so that |C| = |M|.
         pad_auxiliary(&mut auxiliary, &ciphertexts);
 
         // Second, build the cost matrix.
         let n = auxiliary.len();
-        let cost_matrix =
-            Matrix::from_rows(self.build_cost_matrix(&auxiliary, &ciphertexts))
-                .unwrap();
+        let rows = self.build_cost_matrix(&auxiliary, &ciphertexts);
+        let scale = rows.iter().flatten().cloned().max().unwrap_or(0).max(1);
+        let cost_matrix = Matrix::from_rows(rows).unwrap();
 
         // Invoke the Kuhn-Munkres algorithm to find the minimum matching.
         self.assignment = Some(kuhn_munkres_min(&cost_matrix).1);
-        self.get_recovery_rate(correct, &auxiliary, &ciphertexts)
+        self.get_recovery_rate(
+            correct,
+            &auxiliary,
+            &ciphertexts,
+            &cost_matrix,
+            scale,
+        )
     }
 
     /// Given a correct mapping from plaintext to the ciphertext, calculate the accuracy of the attack.
     fn get_recovery_rate(
-        &self,
+        &mut self,
         correct: &HashMap<T, Vec<Vec<u8>>>,
         auxiliary: &[(T, f64, usize)],
         ciphertexts: &[HistType<Vec<u8>>],
+        cost_matrix: &Matrix<i64>,
+        scale: i64,
     ) -> f64 {
         let mut sum = 0f64;
         let message_num = auxiliary.iter().map(|e| e.2).sum::<usize>();
+        let mut recovery = Recovery::new();
+        let mut guesses = Vec::new();
 
         for (i, j) in self.assignment.as_ref().unwrap().iter().enumerate() {
             // assignment[i] = j ==> The i-th message is assigned to j-th ciphertext.
             let (message, _, count) = &auxiliary.get(i).unwrap();
             let message_weight = *count as f64 / message_num as f64;
-            let (ciphertext, count) = &ciphertexts.get(*j).unwrap();
-
-            if let Some(value) = correct.get(message) {
-                let correct_num =
-                    (value.iter().filter(|&e| e == ciphertext).count() as f64);
-                sum += (correct_num / value.len() as f64) * message_weight;
-            }
+            let (ciphertext, _) = &ciphertexts.get(*j).unwrap();
+            let rate = recovered(correct, message, ciphertext);
+            // A message with a single candidate cannot be moved, so its margin is the largest cost.
+            let margin = (0..cost_matrix.columns())
+                .filter(|column| column != j)
+                .map(|column| cost_matrix.at(i, column))
+                .min()
+                .map_or(scale, |second| second - cost_matrix.at(i, *j));
+            record_recovery(
+                &mut recovery,
+                &mut guesses,
+                correct,
+                message,
+                *count,
+                rate,
+                margin as f64 / scale as f64,
+            );
+            sum += rate * message_weight;
         }
 
+        self.recovery = Some(recovery);
+        self.guesses = Some(guesses);
         // Weighted rate.
         sum
     }
 
+    /// Given a correct mapping from plaintext to the ciphertext, calculate the expected accuracy of the soft
+    /// assignment, i.e., each message recovers the ciphertexts with the probabilities of its row.
+    fn get_expected_recovery_rate(
+        &mut self,
+        correct: &HashMap<T, Vec<Vec<u8>>>,
+        auxiliary: &[(T, f64, usize)],
+        ciphertexts: &[HistType<Vec<u8>>],
+    ) -> f64 {
+        let message_num = auxiliary.iter().map(|e| e.2).sum::<usize>();
+        let soft_assignment = self.soft_assignment.as_ref().unwrap();
+        let mut recovery = Recovery::new();
+        let mut guesses = Vec::new();
+
+        let mut sum = 0f64;
+        for ((message, _, count), row) in auxiliary.iter().zip(soft_assignment)
+        {
+            let message_weight = *count as f64 / message_num as f64;
+            let expected = row
+                .iter()
+                .zip(ciphertexts)
+                .map(|(probability, (ciphertext, _))| {
+                    probability * recovered(correct, message, ciphertext)
+                })
+                .sum::<f64>();
+            let confidence = row.iter().cloned().fold(0.0, f64::max);
+            record_recovery(
+                &mut recovery,
+                &mut guesses,
+                correct,
+                message,
+                *count,
+                expected,
+                confidence,
+            );
+            sum += expected * message_weight;
+        }
+
+        self.recovery = Some(recovery);
+        self.guesses = Some(guesses);
+        sum
+    }
+
+    /// The `p`-th power of the distance between two counts. `p = 0` counts the mismatches.
+    fn distance(&self, lhs: usize, rhs: usize) -> f64 {
+        match self.p {
+            0 => (lhs != rhs) as usize as f64,
+            p => (lhs as f64 - rhs as f64).abs().powi(p as i32),
+        }
+    }
+
+    /// Construct the cost matrix of the regularized problem, normalized into `[0, 1]`. It need not be square.
+    fn build_soft_cost_matrix(
+        &self,
+        auxiliary: &[(T, f64, usize)],
+        ciphertexts: &[HistType<Vec<u8>>],
+    ) -> Vec<Vec<f64>> {
+        let cost_matrix = auxiliary
+            .iter()
+            .map(|(_, _, lhs)| {
+                ciphertexts
+                    .iter()
+                    .map(|(_, rhs)| self.distance(*lhs, *rhs))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let max = cost_matrix.iter().flatten().cloned().fold(0.0, f64::max);
+        match max > 0.0 {
+            true => cost_matrix
+                .into_iter()
+                .map(|row| row.into_iter().map(|e| e / max).collect())
+                .collect(),
+            false => cost_matrix,
+        }
+    }
+
     /// Construct the cost matrix for the histograms of the auxiliary dataset as well as the ciphertexts.
     ///
     /// As long as p < 1, this optimization problem can be for-mulated as a LSAP with cost matrix such that
     /// ```tex
     /// C_{ij} = || v_i - w_j ||_{p}.
     /// ```
-    fn build_cost_matrix(
+    ///
+    /// The histograms must be of the same length; see [`pad_auxiliary`]. This is exposed for the benchmarks.
+    pub fn build_cost_matrix(
         &self,
-        auxiliary: &Vec<(T, f64, usize)>,
-        ciphertexts: &Vec<HistType<Vec<u8>>>,
+        auxiliary: &[(T, f64, usize)],
+        ciphertexts: &[HistType<Vec<u8>>],
     ) -> Vec<Vec<i64>> {
         let mut cost_matrix = Vec::new();
 
@@ -140,7 +472,10 @@ where
                 let lhs = auxiliary.get(i).unwrap().2 as i64;
                 let rhs = ciphertexts.get(j).unwrap().1 as i64;
 
-                cur.push((lhs - rhs).pow(self.p as u32));
+                cur.push(match self.p {
+                    0 => (lhs != rhs) as i64,
+                    p => (lhs - rhs).abs().saturating_pow(p as u32),
+                });
             }
 
             cost_matrix.push(cur);
@@ -150,6 +485,106 @@ where
     }
 }
 
+/// The fraction of the correct ciphertexts of `message` that equal `ciphertext`.
+fn recovered<T>(
+    correct: &HashMap<T, Vec<Vec<u8>>>,
+    message: &T,
+    ciphertext: &Vec<u8>,
+) -> f64
+where
+    T: Eq + Hash,
+{
+    match correct.get(message) {
+        Some(value) => {
+            let correct_num = value.iter().filter(|&e| e == ciphertext).count();
+            correct_num as f64 / value.len() as f64
+        }
+        None => 0.0,
+    }
+}
+
+/// The maximum number of Sinkhorn iterations.
+const SINKHORN_ITERATIONS: usize = 1000;
+
+/// The tolerance on the column marginals at which the Sinkhorn iteration stops.
+const SINKHORN_TOLERANCE: f64 = 1e-9;
+
+/// Solve the entropy-regularized transport problem between the histograms `rows` and `columns` with the log-domain
+/// Sinkhorn iteration, and return the transport plan with each row normalized into the conditional distribution of
+/// that row.
+fn sinkhorn(
+    cost_matrix: &[Vec<f64>],
+    rows: &[usize],
+    columns: &[usize],
+    epsilon: f64,
+) -> Vec<Vec<f64>> {
+    let n = cost_matrix.len();
+    let m = cost_matrix.first().map(|row| row.len()).unwrap_or(0);
+    if n == 0 || m == 0 {
+        return vec![Vec::new(); n];
+    }
+
+    let log_sum_exp = |values: &mut dyn Iterator<Item = f64>| {
+        let values = values.collect::<Vec<_>>();
+        let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        max + values.iter().map(|e| (e - max).exp()).sum::<f64>().ln()
+    };
+    let marginal = |histogram: &[usize]| {
+        let total = histogram.iter().sum::<usize>().max(1) as f64;
+        histogram
+            .iter()
+            .map(|&e| (e.max(1) as f64 / total).ln())
+            .collect::<Vec<_>>()
+    };
+    let (log_a, log_b) = (marginal(rows), marginal(columns));
+    let mut f = vec![0f64; n];
+    let mut g = vec![0f64; m];
+
+    for _ in 0..SINKHORN_ITERATIONS {
+        for i in 0..n {
+            f[i] = epsilon
+                * (log_a[i]
+                    - log_sum_exp(
+                        &mut (0..m)
+                            .map(|j| (g[j] - cost_matrix[i][j]) / epsilon),
+                    ));
+        }
+        for j in 0..m {
+            g[j] = epsilon
+                * (log_b[j]
+                    - log_sum_exp(
+                        &mut (0..n)
+                            .map(|i| (f[i] - cost_matrix[i][j]) / epsilon),
+                    ));
+        }
+
+        // The columns are exact after updating `g`, so only the rows need to be checked.
+        let error = (0..n)
+            .map(|i| {
+                let row = (0..m)
+                    .map(|j| {
+                        ((f[i] + g[j] - cost_matrix[i][j]) / epsilon).exp()
+                    })
+                    .sum::<f64>();
+                (row - log_a[i].exp()).abs()
+            })
+            .sum::<f64>();
+        if error < SINKHORN_TOLERANCE {
+            break;
+        }
+    }
+
+    (0..n)
+        .map(|i| {
+            let row = (0..m)
+                .map(|j| ((f[i] + g[j] - cost_matrix[i][j]) / epsilon).exp())
+                .collect::<Vec<_>>();
+            let sum = row.iter().sum::<f64>();
+            row.into_iter().map(|e| e / sum).collect()
+        })
+        .collect()
+}
+
 /// This struct mainly implements the MLE-based attacker that aims to recover the one-to-many mapping
 /// from the message to a set of ciphertexts obtained by the frequency smoothing scheme.
 ///
@@ -183,6 +618,12 @@ where
 {
     /// The assignment of the attacker.
     assignment: Option<Vec<(usize, Vec<Vec<u8>>)>>,
+    /// The recovery of each message under the last assignment.
+    recovery: Option<Recovery<T>>,
+    /// The guesses of the last assignment. See [`MLEAttacker::get_guesses`].
+    guesses: Option<Vec<Guess<T>>>,
+    /// The number of ciphertexts above which their histogram is built on disk. See [`ciphertext_histogram`].
+    spill_threshold: Option<usize>,
     /// A marker.
     _marker: PhantomData<T>,
 }
@@ -194,10 +635,30 @@ where
     pub fn new() -> Self {
         Self {
             assignment: None,
+            recovery: None,
+            guesses: None,
+            spill_threshold: None,
             _marker: PhantomData,
         }
     }
 
+    /// Build the histogram of the ciphertexts on disk once there are more than `threshold` of them.
+    pub fn set_spill_threshold(&mut self, threshold: Option<usize>) {
+        self.spill_threshold = threshold;
+    }
+
+    /// Get the recovery of each message under the last assignment. See [`decile_accuracy`].
+    pub fn get_recovery(&self) -> Option<&Recovery<T>> {
+        self.recovery.as_ref()
+    }
+
+    /// Get the guesses of the last assignment. The confidence of a message is the log-likelihood ratio, per
+    /// ciphertext, of its assigned ciphertext counts under its own scaled frequency against that of the closest of
+    /// its neighbours in the scaled order, modelling each count as Poisson. See [`precision_recall`].
+    pub fn get_guesses(&self) -> Option<&Vec<Guess<T>>> {
+        self.guesses.as_ref()
+    }
+
     /// Perform the MLE attack. The attack proceeds as follows.
     /// 1. Sort the ciphertexts and auxiliary datasets so that each element is in descending order per frequency.
     ///    This step is automatically done by [`util::build_histogram_vec`].
@@ -229,13 +690,12 @@ where
             r.partial_cmp(&l).unwrap()
         });
 
-        let ciphertexts = {
-            let histogram = build_histogram(raw_ciphertexts);
-            build_histogram_vec(&histogram)
-        };
+        let ciphertexts =
+            ciphertext_histogram(raw_ciphertexts, self.spill_threshold);
 
         // Do the assignment.
         let mut assignment = Vec::new();
+        let mut confidences = Vec::new();
         // The index for the message: which one are we accessing.
         let mut cur = 0usize;
         // The left boundary iterator for ciphertext set.
@@ -247,6 +707,11 @@ where
                 .cloned()
                 .map(|e| e.0)
                 .collect::<Vec<_>>();
+            let counts = ciphertexts[i..i + current_size]
+                .iter()
+                .map(|e| e.1)
+                .collect::<Vec<_>>();
+            confidences.push(likelihood_ratio(&auxiliary, cur, &counts));
 
             assignment.push((cur, ciphertext_set));
             cur += 1;
@@ -254,23 +719,34 @@ where
         }
 
         self.assignment = Some(assignment);
-        self.get_recovery_rate(message_num, correct, &auxiliary, &ciphertexts)
+        self.get_recovery_rate(
+            message_num,
+            correct,
+            &auxiliary,
+            &ciphertexts,
+            &confidences,
+        )
     }
 
     fn get_recovery_rate(
-        &self,
+        &mut self,
         message_num: usize,
         correct: &HashMap<T, Vec<Vec<u8>>>,
         auxiliary: &[(T, usize, usize)],
         ciphertexts: &[HistType<Vec<u8>>],
+        confidences: &[f64],
     ) -> f64 {
         let mut sum = 0f64;
+        let mut recovery = Recovery::new();
+        let mut guesses = Vec::new();
 
         log::debug!(
             "There are {} assignments.",
             self.assignment.as_ref().unwrap().len()
         );
-        for (index, assignment) in self.assignment.as_ref().unwrap().iter() {
+        for ((index, assignment), confidence) in
+            self.assignment.as_ref().unwrap().iter().zip(confidences)
+        {
             let (current_message, _, count) = &auxiliary.get(*index).unwrap();
             let correct_ciphertexts = correct.get(current_message).unwrap();
 
@@ -292,13 +768,53 @@ where
             // Find the weight of the ciphertexts.
             let ciphertext_weight =
                 common.len() as f64 / correct_ciphertexts.len() as f64;
+            record_recovery(
+                &mut recovery,
+                &mut guesses,
+                correct,
+                current_message,
+                *count,
+                ciphertext_weight,
+                *confidence,
+            );
             sum += message_weight * ciphertext_weight;
         }
 
+        self.recovery = Some(recovery);
+        self.guesses = Some(guesses);
         sum
     }
 }
 
+/// The log-likelihood ratio, per ciphertext, of the ciphertext `counts` assigned to the `index`-th auxiliary entry
+/// under its scaled frequency against that of the closest of its neighbours in `auxiliary`, which is sorted by the
+/// scaled frequency. Each count is Poisson, so the ratio of a count `c` is `c ln(a / b) - (a - b)`. An entry without
+/// neighbours cannot be confused and is infinitely confident.
+fn likelihood_ratio<T>(
+    auxiliary: &[(T, usize, usize)],
+    index: usize,
+    counts: &[usize],
+) -> f64 {
+    let scaled = |(_, size, count): &(T, usize, usize)| {
+        *count as f64 / (*size).max(1) as f64
+    };
+    let own = scaled(&auxiliary[index]);
+    index
+        .checked_sub(1)
+        .into_iter()
+        .chain(Some(index + 1))
+        .filter_map(|neighbour| auxiliary.get(neighbour))
+        .map(|neighbour| {
+            let other = scaled(neighbour);
+            counts
+                .iter()
+                .map(|&c| c as f64 * (own / other).ln() - (own - other))
+                .sum::<f64>()
+                / counts.len().max(1) as f64
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
 impl<T> Default for MLEAttacker<T>
 where
     T: Eq + Clone + Hash + Debug,
@@ -307,3 +823,870 @@ where
         Self::new()
     }
 }
+
+/// Build the histogram of the observed ciphertexts. If there are more than `spill_threshold` of them, the histogram
+/// is built by an external sort on disk (see [`build_histogram_vec_spilled`]) so that the memory of the attack is
+/// bounded by the number of distinct ciphertexts; should that fail, it falls back to counting in memory.
+fn ciphertext_histogram(
+    raw_ciphertexts: &[Vec<u8>],
+    spill_threshold: Option<usize>,
+) -> Vec<HistType<Vec<u8>>> {
+    if let Some(threshold) = spill_threshold {
+        if raw_ciphertexts.len() > threshold {
+            match build_histogram_vec_spilled(raw_ciphertexts, threshold) {
+                Ok(histogram_vec) => return histogram_vec,
+                Err(e) => error!(
+                    "Cannot spill the histogram to disk due to {:?}; counting in memory.",
+                    e
+                ),
+            }
+        }
+    }
+
+    build_histogram_vec(&build_histogram_fast(raw_ciphertexts))
+}
+
+/// The view of an honest-but-curious server. It holds no key and only observes the ciphertexts stored in a collection,
+/// which is what the attackers are given when they are mounted against a live deployment.
+#[derive(Debug, Clone)]
+pub struct ServerView {
+    ciphertexts: Vec<Vec<u8>>,
+    /// The histogram of the ciphertexts sorted by descending count.
+    histogram: Vec<HistType<Vec<u8>>>,
+}
+
+impl ServerView {
+    /// Scan every document of the collection.
+    pub fn observe(
+        conn: &Connector<Data>,
+        collection_name: &str,
+    ) -> Result<Self> {
+        let mut ciphertexts = Vec::new();
+        for document in conn.search(doc! {}, collection_name)? {
+            ciphertexts.push(document?.data);
+        }
+        let histogram =
+            build_histogram_vec(&build_histogram_fast(&ciphertexts));
+
+        Ok(Self {
+            ciphertexts,
+            histogram,
+        })
+    }
+
+    /// Pull the histogram of the collection from the server by [`Connector::frequency_histogram`] instead of
+    /// scanning every document. The ciphertexts are those of the histogram grouped by ciphertext, so the order they
+    /// were stored in is lost, which the frequency attacks do not need.
+    pub fn observe_histogram(
+        conn: &Connector<Data>,
+        collection_name: &str,
+    ) -> Result<Self> {
+        let histogram = conn.frequency_histogram(collection_name)?;
+        let ciphertexts = histogram
+            .iter()
+            .flat_map(|(ciphertext, count)| vec![ciphertext.clone(); *count])
+            .collect();
+
+        Ok(Self {
+            ciphertexts,
+            histogram,
+        })
+    }
+
+    pub fn get_ciphertexts(&self) -> &[Vec<u8>] {
+        &self.ciphertexts
+    }
+
+    pub fn get_histogram(&self) -> &[HistType<Vec<u8>>] {
+        &self.histogram
+    }
+
+    /// The total-variation distance between the observed ciphertext histogram and the one of `expected`, e.g., the
+    /// ciphertexts of the in-memory simulation. A deployment that leaks exactly what the simulation assumes gives 0.
+    pub fn distance(&self, expected: &[Vec<u8>]) -> f64 {
+        let expected = build_histogram_vec(&build_histogram_fast(expected));
+        total_variation(&self.histogram, &expected)
+    }
+}
+
+/// An attacker that exploits the insertion order. It knows (or guesses) the sequence of the inserted plaintexts, e.g.,
+/// that the input was sorted, and maps the i-th observed ciphertext to the plaintext of the same relative rank.
+#[derive(Debug)]
+pub struct OrderAttacker<T>
+where
+    T: Eq + Hash,
+{
+    /// A marker.
+    _marker: PhantomData<T>,
+}
+
+impl<T> OrderAttacker<T>
+where
+    T: Eq + Hash,
+{
+    pub fn new() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+
+    /// Perform the attack and output the fraction of the observed ciphertexts that are mapped to their plaintexts.
+    pub fn attack(
+        &mut self,
+        correct: &HashMap<T, Vec<Vec<u8>>>,
+        auxiliary: &[T],
+        observed: &[Vec<u8>],
+    ) -> f64 {
+        if auxiliary.is_empty() || observed.is_empty() {
+            return 0.0;
+        }
+
+        let hit = observed
+            .iter()
+            .enumerate()
+            .filter(|(i, ciphertext)| {
+                let rank = i * auxiliary.len() / observed.len();
+                correct
+                    .get(&auxiliary[rank])
+                    .map(|e| e.contains(ciphertext))
+                    .unwrap_or_default()
+            })
+            .count();
+
+        hit as f64 / observed.len() as f64
+    }
+}
+
+impl<T> Default for OrderAttacker<T>
+where
+    T: Eq + Hash,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The counting attack of Lacharité and Paterson against WRE. The tags of a message under the fixed Poisson salt
+/// allocation sum up to its count, so the attacker, who knows the count of each message, looks for a set of tags whose
+/// counts sum up to it. The messages are processed from the most frequent to the least, each taking the largest
+/// unassigned tags that still fit into its count, until the count is met within the tolerance.
+///
+/// The bucketized allocation cuts the messages by buckets that are shared across them instead of per message, so the
+/// tag counts no longer follow the count of each message as closely; the attack measures how much this mitigates it.
+#[derive(Debug)]
+pub struct SaltCountAttacker<T>
+where
+    T: Eq + Clone + Hash + Debug,
+{
+    /// The fraction of its count by which the tags of a message may miss it.
+    tolerance: f64,
+    /// The tags assigned to each message by the last attack.
+    assignment: Option<HashMap<T, Vec<Vec<u8>>>>,
+    /// The recovery of each message under the last assignment.
+    recovery: Option<Recovery<T>>,
+    /// The number of ciphertexts above which their histogram is built on disk. See [`ciphertext_histogram`].
+    spill_threshold: Option<usize>,
+}
+
+impl<T> SaltCountAttacker<T>
+where
+    T: Eq + Clone + Hash + Debug,
+{
+    /// The default tolerance.
+    pub const TOLERANCE: f64 = 0.05;
+
+    pub fn new() -> Self {
+        Self::with_tolerance(Self::TOLERANCE)
+    }
+
+    pub fn with_tolerance(tolerance: f64) -> Self {
+        Self {
+            tolerance,
+            assignment: None,
+            recovery: None,
+            spill_threshold: None,
+        }
+    }
+
+    /// Build the histogram of the ciphertexts on disk once there are more than `threshold` of them.
+    pub fn set_spill_threshold(&mut self, threshold: Option<usize>) {
+        self.spill_threshold = threshold;
+    }
+
+    /// Get the tags assigned to each message by the last attack.
+    pub fn get_assignment(&self) -> Option<&HashMap<T, Vec<Vec<u8>>>> {
+        self.assignment.as_ref()
+    }
+
+    /// Get the recovery of each message under the last assignment. See [`decile_accuracy`].
+    pub fn get_recovery(&self) -> Option<&Recovery<T>> {
+        self.recovery.as_ref()
+    }
+
+    /// Perform the attack and output the fraction of the observed ciphertexts that are assigned to their plaintexts.
+    /// The count of each message is taken from `local_table` and scaled to the number of observed ciphertexts.
+    pub fn attack(
+        &mut self,
+        correct: &HashMap<T, Vec<Vec<u8>>>,
+        local_table: &HashMap<T, Vec<ValueType>>,
+        raw_ciphertexts: &[Vec<u8>],
+    ) -> f64 {
+        let total = local_table
+            .values()
+            .flatten()
+            .map(|&(_, _, count)| count)
+            .sum::<usize>();
+        let mut counts = local_table
+            .iter()
+            .map(|(message, information)| {
+                let count = information.iter().map(|e| e.2).sum::<usize>();
+                let expected = checked_div(
+                    (count * raw_ciphertexts.len()) as f64,
+                    total as f64,
+                )
+                .unwrap_or_default();
+                (message, expected)
+            })
+            .collect::<Vec<_>>();
+        counts.sort_by(|lhs, rhs| rhs.1.total_cmp(&lhs.1));
+
+        // The histogram is sorted by descending count.
+        let tags = ciphertext_histogram(raw_ciphertexts, self.spill_threshold);
+        let mut assigned = vec![false; tags.len()];
+        let mut assignment = HashMap::new();
+        for (message, expected) in counts {
+            let slack = (expected * self.tolerance).max(0.5);
+            let mut remaining = expected;
+            let mut set = Vec::new();
+            for (index, (tag, count)) in tags.iter().enumerate() {
+                if remaining <= slack {
+                    break;
+                }
+                if !assigned[index] && *count as f64 <= remaining + slack {
+                    assigned[index] = true;
+                    remaining -= *count as f64;
+                    set.push(tag.clone());
+                }
+            }
+            assignment.insert(message.clone(), set);
+        }
+
+        let tag_counts = tags.into_iter().collect::<HashMap<_, _>>();
+        let mut recovery = Recovery::new();
+        let mut hit = 0usize;
+        for (message, ciphertexts) in correct.iter() {
+            let occurrences = ciphertexts
+                .iter()
+                .filter_map(|e| tag_counts.get(e))
+                .sum::<usize>();
+            let recovered = assignment
+                .get(message)
+                .map(|set: &Vec<Vec<u8>>| {
+                    set.iter()
+                        .filter(|e| ciphertexts.contains(e))
+                        .filter_map(|e| tag_counts.get(e))
+                        .sum::<usize>()
+                })
+                .unwrap_or_default();
+            hit += recovered;
+            recovery.insert(message.clone(), (occurrences, recovered as f64));
+        }
+
+        self.assignment = Some(assignment);
+        self.recovery = Some(recovery);
+        checked_div(hit as f64, raw_ciphertexts.len() as f64)
+            .unwrap_or_default()
+    }
+}
+
+impl<T> Default for SaltCountAttacker<T>
+where
+    T: Eq + Clone + Hash + Debug,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The leakage of encrypting a dataset together with the ground truth, i.e., everything an attack is mounted with.
+#[derive(Debug, Clone)]
+pub struct AttackMeta<T>
+where
+    T: Eq + Hash,
+{
+    /// The distinct ciphertexts of each message.
+    pub correct: HashMap<T, Vec<Vec<u8>>>,
+    /// The knowledge of the attacker: the count of each message and the number of its ciphertexts.
+    pub local_table: HashMap<T, Vec<ValueType>>,
+    /// The ciphertexts observed by the server, dummies included.
+    pub raw_ciphertexts: Vec<Vec<u8>>,
+    /// One ciphertext per message of the input in the order of the input.
+    pub sequence: Vec<Vec<u8>>,
+    /// The analytical advantage bound of the smoothing state, if any.
+    pub bound: Option<f64>,
+    /// The dummy ciphertexts among `raw_ciphertexts`. No search ever returns them.
+    pub dummies: HashSet<Vec<u8>>,
+    /// The dummies among `dummies` whose stored messages a server can tell from the real ones by their format. See
+    /// [`DomainFilter`].
+    pub implausible_dummies: HashSet<Vec<u8>>,
+}
+
+impl<T> AttackMeta<T>
+where
+    T: Eq + Hash,
+{
+    /// The fraction of the observed ciphertexts that are dummies.
+    pub fn dummy_mass(&self) -> f64 {
+        let dummies = self
+            .raw_ciphertexts
+            .iter()
+            .filter(|e| self.dummies.contains(*e))
+            .count();
+        checked_div(dummies as f64, self.raw_ciphertexts.len() as f64)
+            .unwrap_or_default()
+    }
+
+    /// The fraction of the observed dummy ciphertexts that pass the [`DomainFilter`] of the server. One if there are
+    /// no dummies.
+    pub fn dummy_realism(&self) -> f64 {
+        let (dummies, implausible) = self
+            .raw_ciphertexts
+            .iter()
+            .filter(|e| self.dummies.contains(*e))
+            .fold((0usize, 0usize), |(dummies, implausible), e| {
+                let filtered = self.implausible_dummies.contains(e) as usize;
+                (dummies + 1, implausible + filtered)
+            });
+        checked_div((dummies - implausible) as f64, dummies as f64)
+            .unwrap_or(1.0)
+    }
+
+    /// The observed ciphertexts without the implausible dummies, i.e., the view of an attacker that filters the
+    /// stored messages by their format.
+    pub fn filtered_ciphertexts(&self) -> Vec<Vec<u8>> {
+        self.raw_ciphertexts
+            .iter()
+            .filter(|e| !self.implausible_dummies.contains(*e))
+            .cloned()
+            .collect()
+    }
+
+    /// The observed ciphertexts without the dummies, i.e., the view of an attacker that can tell the dummies apart.
+    pub fn reachable_ciphertexts(&self) -> Vec<Vec<u8>> {
+        self.raw_ciphertexts
+            .iter()
+            .filter(|e| !self.dummies.contains(*e))
+            .cloned()
+            .collect()
+    }
+
+    /// The leakage of records stored one ciphertext each, e.g., the updates isolated by a [`PersistentView`].
+    pub fn from_records(messages: &[T], ciphertexts: &[Vec<u8>]) -> Self
+    where
+        T: Clone,
+    {
+        let mut meta = Self {
+            correct: HashMap::new(),
+            local_table: HashMap::new(),
+            raw_ciphertexts: Vec::new(),
+            sequence: Vec::new(),
+            bound: None,
+            dummies: HashSet::new(),
+            implausible_dummies: HashSet::new(),
+        };
+        meta.insert(messages, ciphertexts);
+        meta
+    }
+
+    /// Record the ciphertexts of `messages` inserted after the leakage was collected, in the same order. The attacker
+    /// learns the new histogram: the counts of each message in the local table are scaled up to include the inserted
+    /// records, and the new distinct ciphertexts of a message are added to the set of its last entry.
+    pub fn insert(&mut self, messages: &[T], ciphertexts: &[Vec<u8>])
+    where
+        T: Clone,
+    {
+        let mut inserted = HashMap::<_, Vec<_>>::new();
+        for (message, ciphertext) in messages.iter().zip(ciphertexts.iter()) {
+            inserted.entry(message).or_default().push(ciphertext);
+        }
+
+        for (message, batch) in inserted.into_iter() {
+            let set = self.correct.entry(message.clone()).or_default();
+            let known = set.iter().cloned().collect::<HashSet<_>>();
+            let fresh = batch
+                .iter()
+                .filter(|e| !known.contains(**e))
+                .unique()
+                .map(|e| e.to_vec())
+                .collect_vec();
+
+            let information = self
+                .local_table
+                .entry(message.clone())
+                .or_insert_with(|| vec![(0, 0, 0)]);
+            let original = information.iter().map(|e| e.2).sum::<usize>();
+            let total = original + batch.len();
+            match original {
+                0 => information.last_mut().unwrap().2 = total,
+                _ => {
+                    for value in information.iter_mut() {
+                        value.2 = ((value.2 * total) as f64 / original as f64)
+                            .round() as usize;
+                    }
+                }
+            }
+            information.last_mut().unwrap().1 += fresh.len();
+            set.extend(fresh);
+        }
+
+        self.raw_ciphertexts.extend(ciphertexts.iter().cloned());
+        self.sequence.extend(ciphertexts.iter().cloned());
+    }
+}
+
+/// The leakage of the same column encrypted twice under independent contexts, e.g., into a staging and a production
+/// collection, by an attacker that links the two copies of each record, e.g., by their primary keys. The attacker sees
+/// how often the ciphertext of a record occurs among the records of each collection and intersects the plaintexts
+/// consistent with each frequency; smoothing one collection does not hide the frequency class of the other.
+///
+/// Each rate is the fraction of the records whose plaintext the Bayes-optimal attacker recovers, i.e., the one that
+/// guesses the most frequent plaintext among the records of the same observation.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct CompositionLeakage {
+    /// The recovery rate from the first collection alone.
+    pub first: f64,
+    /// The recovery rate from the second collection alone.
+    pub second: f64,
+    /// The recovery rate from the pair of frequencies of each record.
+    pub joint: f64,
+    /// The fraction of the records whose plaintext is the only one consistent with the pair, i.e., that the
+    /// intersection singles out.
+    pub unique: f64,
+}
+
+impl CompositionLeakage {
+    /// How much more the pair recovers than the leakier of the two collections alone.
+    pub fn gain(&self) -> f64 {
+        self.joint - self.first.max(self.second)
+    }
+}
+
+/// Compute the [`CompositionLeakage`] of `data` encrypted into `first` and into `second`, both collected from `data`
+/// so that the i-th ciphertext of each sequence is that of the i-th record.
+///
+/// The sequences are aligned by position, which stands for the linkage of the records across the two collections: the
+/// caller must collect both in the order of `data`, or reorder the ciphertexts of a live collection by the keys that
+/// link its records to those of the other. Only a difference in length is detected; misaligned sequences of the same
+/// length give meaningless rates.
+pub fn composition_leakage<T>(
+    data: &[T],
+    first: &AttackMeta<T>,
+    second: &AttackMeta<T>,
+) -> Result<CompositionLeakage>
+where
+    T: Eq + Hash,
+{
+    if first.sequence.len() != data.len() || second.sequence.len() != data.len()
+    {
+        return Err(format!(
+            "The contexts encrypted {} and {} records instead of the {} of the column.",
+            first.sequence.len(),
+            second.sequence.len(),
+            data.len()
+        )
+        .into());
+    }
+
+    // The frequency of the ciphertext of each record among the records of the column. The dummies are not linked to
+    // any record and are left out.
+    let frequencies = |meta: &AttackMeta<T>| {
+        let histogram = build_histogram(&meta.sequence);
+        meta.sequence
+            .iter()
+            .map(|e| histogram.get(e).copied().unwrap_or_default())
+            .collect_vec()
+    };
+    let first_frequencies = frequencies(first);
+    let second_frequencies = frequencies(second);
+
+    let (first, _) = bayes_recovery(data, &first_frequencies);
+    let (second, _) = bayes_recovery(data, &second_frequencies);
+    let pairs = first_frequencies
+        .into_iter()
+        .zip(second_frequencies)
+        .collect_vec();
+    let (joint, unique) = bayes_recovery(data, &pairs);
+
+    Ok(CompositionLeakage {
+        first,
+        second,
+        joint,
+        unique,
+    })
+}
+
+/// The fraction of the records recovered by guessing the most frequent plaintext of each observation, and the fraction
+/// whose observation is consistent with a single plaintext.
+fn bayes_recovery<T, O>(data: &[T], observations: &[O]) -> (f64, f64)
+where
+    T: Eq + Hash,
+    O: Eq + Hash,
+{
+    let mut classes = HashMap::<&O, HashMap<&T, usize>>::new();
+    for (message, observation) in data.iter().zip(observations.iter()) {
+        *classes
+            .entry(observation)
+            .or_default()
+            .entry(message)
+            .or_default() += 1;
+    }
+
+    let (recovered, unique) =
+        classes.values().fold((0, 0), |(recovered, unique), class| {
+            let max = class.values().max().copied().unwrap_or_default();
+            let single = if class.len() == 1 { max } else { 0 };
+            (recovered + max, unique + single)
+        });
+    let total = data.len() as f64;
+    (
+        checked_div(recovered as f64, total).unwrap_or_default(),
+        checked_div(unique as f64, total).unwrap_or_default(),
+    )
+}
+
+/// The view of a persistent adversary, e.g., a compromised server or a backup operator, that observes the store
+/// repeatedly rather than once. Diffing each snapshot against the previous one isolates the ciphertexts inserted in
+/// between, whose frequencies are those of the updates alone: the dummies and the smoothing of the initial load do not
+/// cover them.
+#[derive(Debug, Clone, Default)]
+pub struct PersistentView {
+    /// The histogram of the last snapshot.
+    last: HashMap<Vec<u8>, usize>,
+    /// The number of snapshots observed.
+    snapshots: usize,
+    /// The ciphertexts new to each snapshot but the first.
+    batches: Vec<Vec<Vec<u8>>>,
+}
+
+impl PersistentView {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Observe a snapshot of the store. The first snapshot is the baseline, and each later one is diffed against its
+    /// predecessor as a multiset; ciphertexts that disappear in between are ignored.
+    pub fn observe(&mut self, snapshot: &[Vec<u8>]) {
+        let histogram = build_histogram(snapshot);
+        if self.snapshots > 0 {
+            let mut batch = Vec::new();
+            for (ciphertext, count) in histogram.iter() {
+                let previous =
+                    self.last.get(ciphertext).copied().unwrap_or_default();
+                for _ in previous..*count {
+                    batch.push(ciphertext.clone());
+                }
+            }
+            self.batches.push(batch);
+        }
+
+        self.last = histogram;
+        self.snapshots += 1;
+    }
+
+    pub fn get_snapshot_num(&self) -> usize {
+        self.snapshots
+    }
+
+    /// The ciphertexts new to each snapshot but the first.
+    pub fn get_batches(&self) -> &[Vec<Vec<u8>>] {
+        &self.batches
+    }
+
+    /// Every ciphertext inserted after the first snapshot, i.e., the diffs aggregated across all snapshots.
+    pub fn inserted(&self) -> Vec<Vec<u8>> {
+        self.batches.iter().flatten().cloned().collect()
+    }
+}
+
+/// A context whose leakage can be collected, so that any scheme can be attacked without knowing its internals.
+pub trait LeakageCollector<T>
+where
+    T: Eq + Hash,
+{
+    /// Initialize the context with `data` as its dataset, encrypt it and collect its leakage. The context must have
+    /// its key and parameters set.
+    fn collect_leakage(&mut self, data: &[T]) -> Result<AttackMeta<T>>;
+
+    /// Encrypt a record of `message` inserted after the leakage was collected into the ciphertext stored for it. The
+    /// context is not set up again, so the update is encrypted under the state of the collected dataset.
+    fn encrypt_update(&mut self, message: &T) -> Result<Vec<u8>>;
+}
+
+/// Encrypt `message` into the ciphertext stored for its record by a context that encrypts each record on its own.
+fn encrypt_record<T, C>(ctx: &mut C, message: &T) -> Result<Vec<u8>>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+    C: BaseCrypto<T> + ?Sized,
+{
+    ctx.encrypt(message)
+        .and_then(|mut c| (!c.is_empty()).then(|| c.remove(0)))
+        .ok_or_else(|| "Cannot encrypt the message.".into())
+}
+
+/// Collect the leakage of a context that encrypts each record on its own, i.e., a call of `encrypt` yields the
+/// ciphertext that is stored for the record.
+pub fn collect_per_record<T, C>(
+    ctx: &mut C,
+    data: &[T],
+) -> Result<AttackMeta<T>>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+    C: BaseCrypto<T> + ?Sized,
+{
+    let mut message_to_ciphertexts = HashMap::new();
+    let mut sequence = Vec::with_capacity(data.len());
+    for message in data.iter() {
+        let ciphertext = encrypt_record(ctx, message)?;
+        sequence.push(ciphertext.clone());
+        message_to_ciphertexts
+            .entry(message.clone())
+            .or_insert_with(Vec::new)
+            .push(ciphertext);
+    }
+
+    let mut correct = HashMap::new();
+    let mut local_table = HashMap::new();
+    let mut raw_ciphertexts = Vec::with_capacity(data.len());
+    for (message, ciphertexts) in message_to_ciphertexts.into_iter() {
+        let unique = ciphertexts.iter().unique().cloned().collect_vec();
+        local_table.insert(
+            message.clone(),
+            vec![(0, unique.len(), ciphertexts.len())],
+        );
+        correct.insert(message, unique);
+        raw_ciphertexts.extend(ciphertexts);
+    }
+
+    Ok(AttackMeta {
+        correct,
+        local_table,
+        raw_ciphertexts,
+        sequence,
+        bound: None,
+        dummies: HashSet::new(),
+        implausible_dummies: HashSet::new(),
+    })
+}
+
+/// Collect the leakage of a transformed PFSE context. Each distinct ciphertext of a message is observed once, and each
+/// dummy, encrypted by [`ContextPFSE::encrypt_dummy`], as many times as it is stored. The server filters the dummies
+/// by the formats of the messages of `data`.
+fn collect_transformed<T>(
+    ctx: &mut ContextPFSE<T>,
+    data: &[T],
+) -> Result<AttackMeta<T>>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + Random + SizeAllocated,
+{
+    let mut correct = HashMap::new();
+    let mut raw_ciphertexts = Vec::new();
+    for message in data.iter().unique() {
+        let ciphertexts =
+            ctx.encrypt(message).ok_or("Cannot encrypt the message.")?;
+        raw_ciphertexts.extend(ciphertexts.iter().cloned());
+        correct.insert(
+            message.clone(),
+            ciphertexts.into_iter().unique().collect_vec(),
+        );
+    }
+
+    let filter = DomainFilter::new(data.iter().unique());
+    let mut dummies = HashSet::new();
+    let mut implausible_dummies = HashSet::new();
+    for (index, partition) in ctx.get_partitions().iter().enumerate() {
+        for (message, cnt) in partition.inner.iter() {
+            if !ctx.get_local_table().contains_key(message) {
+                let dummy = ctx
+                    .encrypt_dummy(message, index)
+                    .ok_or("Cannot encrypt the dummy.")?;
+                raw_ciphertexts.extend(vec![dummy.clone(); *cnt]);
+                if !filter.is_plausible(message) {
+                    implausible_dummies.insert(dummy.clone());
+                }
+                dummies.insert(dummy);
+            }
+        }
+    }
+
+    // Each inserted record is encrypted under one of the ciphertexts of its message.
+    let sequence = data
+        .iter()
+        .map(|message| correct[message].choose(&mut OsRng).unwrap().clone())
+        .collect();
+
+    Ok(AttackMeta {
+        correct,
+        local_table: ctx.get_local_table().clone(),
+        raw_ciphertexts,
+        sequence,
+        bound: ctx.scheme_state().as_ref().map(advantage_bound),
+        dummies,
+        implausible_dummies,
+    })
+}
+
+impl<T> LeakageCollector<T> for ContextNative<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+{
+    fn collect_leakage(&mut self, data: &[T]) -> Result<AttackMeta<T>> {
+        collect_per_record(self, data)
+    }
+
+    fn encrypt_update(&mut self, message: &T) -> Result<Vec<u8>> {
+        encrypt_record(self, message)
+    }
+}
+
+/// Every ciphertext is its message, so the attacks recover everything but ties of equal frequencies.
+impl<T> LeakageCollector<T> for ContextPlain<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+{
+    fn collect_leakage(&mut self, data: &[T]) -> Result<AttackMeta<T>> {
+        collect_per_record(self, data)
+    }
+
+    fn encrypt_update(&mut self, message: &T) -> Result<Vec<u8>> {
+        encrypt_record(self, message)
+    }
+}
+
+impl<T> LeakageCollector<T> for ContextPFSE<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + Random + SizeAllocated,
+{
+    fn collect_leakage(&mut self, data: &[T]) -> Result<AttackMeta<T>> {
+        self.partition(data, exponential)?;
+        info!("Partition finished.");
+        let stats = self.transform();
+        info!("Transform finished with {} dummies.", stats.dummy_num());
+
+        collect_transformed(self, data)
+    }
+
+    /// An update is stored under one of the ciphertexts of its message drawn at random, and no dummy is added for it
+    /// as the partitions are only smoothed at setup.
+    fn encrypt_update(&mut self, message: &T) -> Result<Vec<u8>> {
+        self.encrypt(message)
+            .and_then(|ciphertexts| ciphertexts.choose(&mut OsRng).cloned())
+            .ok_or_else(|| "Cannot encrypt the message.".into())
+    }
+}
+
+impl<T, E> LeakageCollector<T> for ContextLPFSE<T, E>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+    E: HomophoneEncoder<T>,
+{
+    fn collect_leakage(&mut self, data: &[T]) -> Result<AttackMeta<T>> {
+        self.initialize_histogram(&build_histogram(data))?;
+
+        let mut meta = collect_per_record(self, data)?;
+        // The attacker knows the histogram the encoder is initialized with rather than the sizes of the observed
+        // ciphertext sets.
+        meta.local_table = self
+            .get_encoder()
+            .local_table()
+            .into_iter()
+            .map(|(message, count)| {
+                let size = meta
+                    .correct
+                    .get(&message)
+                    .ok_or("Message not found in the ciphertext sets map.")?
+                    .len();
+                Ok((message, vec![(0, size, count)]))
+            })
+            .collect::<Result<_>>()?;
+        meta.bound = self.scheme_state().as_ref().map(advantage_bound);
+        Ok(meta)
+    }
+
+    fn encrypt_update(&mut self, message: &T) -> Result<Vec<u8>> {
+        encrypt_record(self, message)
+    }
+}
+
+impl<T> LeakageCollector<T> for ContextWRE<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+{
+    fn collect_leakage(&mut self, data: &[T]) -> Result<AttackMeta<T>> {
+        self.initialize_histogram(&build_histogram(data))?;
+        collect_per_record(self, data)
+    }
+
+    fn encrypt_update(&mut self, message: &T) -> Result<Vec<u8>> {
+        encrypt_record(self, message)
+    }
+}
+
+/// The stream is encrypted in the order of `data`, so the salts of later epochs follow the frequencies observed in
+/// the earlier ones.
+impl<T> LeakageCollector<T> for ContextStreaming<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+{
+    fn collect_leakage(&mut self, data: &[T]) -> Result<AttackMeta<T>> {
+        collect_per_record(self, data)
+    }
+
+    fn encrypt_update(&mut self, message: &T) -> Result<Vec<u8>> {
+        encrypt_record(self, message)
+    }
+}
+
+/// The ciphertexts of a message are those of its bucket, so a message is only recovered up to its bucket.
+impl<T> LeakageCollector<T> for ContextBucketed<T>
+where
+    T: Hash + AsBytes + Eq + Clone,
+{
+    fn collect_leakage(&mut self, data: &[T]) -> Result<AttackMeta<T>> {
+        self.partition(data, exponential)?;
+        self.transform();
+
+        let buckets = data
+            .iter()
+            .map(|message| self.bucket(message).ok_or(FseError::NotInitialized))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let inner = collect_transformed(self.get_inner_mut(), &buckets)?;
+
+        let mut correct = HashMap::new();
+        let mut local_table = HashMap::new();
+        for (message, bucket) in data.iter().zip(buckets.iter()) {
+            let ciphertexts = &inner.correct[bucket];
+            correct
+                .entry(message.clone())
+                .or_insert_with(|| ciphertexts.clone());
+            local_table
+                .entry(message.clone())
+                .or_insert_with(|| vec![(0, ciphertexts.len(), 0)])[0]
+                .2 += 1;
+        }
+
+        Ok(AttackMeta {
+            correct,
+            local_table,
+            raw_ciphertexts: inner.raw_ciphertexts,
+            sequence: inner.sequence,
+            bound: inner.bound,
+            dummies: inner.dummies,
+            implausible_dummies: inner.implausible_dummies,
+        })
+    }
+
+    fn encrypt_update(&mut self, message: &T) -> Result<Vec<u8>> {
+        let bucket = self.bucket(message).ok_or(FseError::NotInitialized)?;
+        self.get_inner_mut().encrypt_update(&bucket)
+    }
+}
@@ -0,0 +1,204 @@
+//! This module implements an optional audit log of search queries. The log is an append-only JSONL file where each
+//! line records a single search: the timestamp, the collection (column), the number of search tokens, the number of
+//! results, and a keyed hash (HMAC-SHA256) of the queried message. The plaintext itself is never written, and without
+//! the key the hash cannot be inverted by enumerating the values of a low-entropy column.
+//!
+//! The entries are chained by HMAC under the same key, i.e., the MAC of each entry covers the MAC of the previous one,
+//! so that whoever can edit the file but does not hold the key cannot remove, modify or insert an entry without
+//! [`AuditLog::verify`] noticing. Cutting entries off the end leaves a valid chain; it is detected by anchoring the
+//! [`AuditHead`] elsewhere, e.g., in a separate system, and checking the log against it by
+//! [`AuditLog::verify_anchored`].
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{util::to_hex, Result};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The hash of the (non-existing) entry before the first one.
+const GENESIS_HASH: &str =
+    "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A single line of the audit log.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct AuditEntry {
+    /// Milliseconds since the UNIX epoch.
+    pub timestamp: u128,
+    pub column: String,
+    pub token_num: usize,
+    pub result_num: usize,
+    /// The keyed hash of the queried message.
+    pub message_hash: String,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self, key: &[u8]) -> String {
+        let mut mac = keyed(key, b"entry");
+        mac.update(self.prev_hash.as_bytes());
+        mac.update(&self.timestamp.to_le_bytes());
+        // The column is the only field of variable length.
+        mac.update(&(self.column.len() as u64).to_le_bytes());
+        mac.update(self.column.as_bytes());
+        mac.update(&(self.token_num as u64).to_le_bytes());
+        mac.update(&(self.result_num as u64).to_le_bytes());
+        mac.update(self.message_hash.as_bytes());
+        to_hex(&mac.finalize().into_bytes())
+    }
+}
+
+/// The HMAC under `key`, separated by `label` between the entries and the messages.
+fn keyed(key: &[u8], label: &[u8]) -> HmacSha256 {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key)
+        .expect("HMAC takes keys of any length");
+    mac.update(label);
+    mac
+}
+
+/// The number of entries of a log and the hash of the last one. Stored outside the log, it lets
+/// [`AuditLog::verify_anchored`] detect the entries cut off the end of the log since.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditHead {
+    pub count: usize,
+    pub hash: String,
+}
+
+/// An append-only, HMAC-chained audit log.
+#[derive(Debug)]
+pub struct AuditLog {
+    /// The log file opened in append mode.
+    file: File,
+    /// The secret key of the message hashes and of the chain.
+    key: Vec<u8>,
+    /// The number of entries and the hash of the last one.
+    head: AuditHead,
+}
+
+impl AuditLog {
+    /// Open an audit log at `path` under the secret `key`. If the log already exists, it is verified and new entries
+    /// are chained to its last entry.
+    pub fn open(path: &str, key: &[u8]) -> Result<Self> {
+        if key.is_empty() {
+            return Err("The audit log needs a non-empty key.".into());
+        }
+        let head = match File::open(path) {
+            Ok(_) => Self::verify(path, key)?,
+            Err(_) => AuditHead {
+                count: 0,
+                hash: GENESIS_HASH.to_string(),
+            },
+        };
+
+        Ok(Self {
+            file: OpenOptions::new().append(true).create(true).open(path)?,
+            key: key.to_vec(),
+            head,
+        })
+    }
+
+    /// The head of the log, to be anchored outside of it.
+    pub fn head(&self) -> &AuditHead {
+        &self.head
+    }
+
+    /// Append a search query to the log.
+    pub fn record(
+        &mut self,
+        column: &str,
+        message: &[u8],
+        token_num: usize,
+        result_num: usize,
+    ) -> Result<()> {
+        let mut mac = keyed(&self.key, b"message");
+        mac.update(message);
+
+        let mut entry = AuditEntry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)?
+                .as_millis(),
+            column: column.to_string(),
+            token_num,
+            result_num,
+            message_hash: to_hex(&mac.finalize().into_bytes()),
+            prev_hash: self.head.hash.clone(),
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash(&self.key);
+
+        writeln!(self.file, "{}", serde_json::to_string(&entry)?)?;
+        self.head.hash = entry.hash;
+        self.head.count += 1;
+        Ok(())
+    }
+
+    /// Verify the chain of the log at `path` under `key` and return its head.
+    pub fn verify(path: &str, key: &[u8]) -> Result<AuditHead> {
+        let mut prev_hash = GENESIS_HASH.to_string();
+        let mut count = 0usize;
+
+        for (idx, line) in BufReader::new(File::open(path)?).lines().enumerate()
+        {
+            let entry = serde_json::from_str::<AuditEntry>(&line?)?;
+            if entry.prev_hash != prev_hash
+                || entry.hash != entry.compute_hash(key)
+            {
+                return Err(format!(
+                    "The audit log is broken at line {}.",
+                    idx + 1
+                )
+                .into());
+            }
+
+            prev_hash = entry.hash;
+            count += 1;
+        }
+
+        Ok(AuditHead {
+            count,
+            hash: prev_hash,
+        })
+    }
+
+    /// Verify the log at `path` like [`AuditLog::verify`] and check that it still holds the entries up to `anchor`, a
+    /// head taken earlier. The log may have grown since.
+    pub fn verify_anchored(
+        path: &str,
+        key: &[u8],
+        anchor: &AuditHead,
+    ) -> Result<AuditHead> {
+        let head = Self::verify(path, key)?;
+        if anchor.count == 0 {
+            return Ok(head);
+        }
+        if head.count < anchor.count {
+            return Err(format!(
+                "The audit log has {} entries but {} were anchored.",
+                head.count, anchor.count
+            )
+            .into());
+        }
+
+        let line = BufReader::new(File::open(path)?)
+            .lines()
+            .nth(anchor.count - 1)
+            .ok_or("The audit log is shorter than its anchor.")??;
+        match serde_json::from_str::<AuditEntry>(&line)?.hash == anchor.hash {
+            true => Ok(head),
+            false => Err(format!(
+                "The audit log does not match its anchor at line {}.",
+                anchor.count
+            )
+            .into()),
+        }
+    }
+}
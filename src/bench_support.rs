@@ -0,0 +1,434 @@
+//! This module provides the fixtures shared by the criterion benchmarks so that each benchmark target only needs to
+//! contain the measured closure. It encapsulates dataset loading, context construction and the optional database setup
+//! for every scheme in [`FSEType`], so that new schemes get benchmarks for free.
+//!
+//! This module should be enabled by the `bench` (optional) feature.
+
+use criterion::{
+    measurement::WallTime, BatchSize, Bencher, BenchmarkId, Criterion,
+    Throughput,
+};
+use rand::seq::SliceRandom;
+use rand_core::OsRng;
+use rand_distr::{Distribution, Uniform};
+
+use std::collections::HashMap;
+
+use crate::{
+    db::{CollectionId, Data},
+    fse::{
+        exponential, BaseCrypto, HistType, PartitionFrequencySmoothing,
+        ValueType,
+    },
+    lpfse::{ContextLPFSE, EncoderBHE, EncoderIHBE, HomophoneEncoder},
+    native::ContextNative,
+    params::{LpfseParams, PfseParams, SchemeParams},
+    pfse::ContextPFSE,
+    plain::ContextPlain,
+    util::{
+        build_histogram, build_histogram_fast, build_histogram_vec,
+        generate_synthetic_zipf, read_csv_exact, sample_or_cycle,
+    },
+    FSEType, Result,
+};
+
+pub const BENCH_DATA_PATH: &str = "./data/test.csv";
+pub const BENCH_COLUMN: &str = "order_number";
+pub const BENCH_ADDRESS: &str = "mongodb://127.0.0.1:27017";
+pub const BENCH_DB_NAME: &str = "bench";
+pub const BENCH_SIZES: [usize; 5] = [100, 1000, 10000, 100000, 1000000];
+
+/// The seed of the records drawn by [`load_dataset`], so that the runs of a benchmark measure the same records.
+pub const BENCH_SEED: u64 = 0x5eed;
+
+/// The numbers of distinct messages of the synthetic attack inputs.
+pub const ATTACK_DOMAINS: [usize; 4] = [10, 100, 500, 1000];
+
+/// The Zipf exponent of the synthetic attack inputs.
+pub const ATTACK_ZIPF_EXPONENT: f64 = 1.1;
+
+/// The default advantage used by the benchmarks.
+pub const BENCH_ADVANTAGE: f64 = 0.0009765625;
+
+/// The ciphertexts of a dataset and the context that encrypted them. See [`build_context`].
+pub type EncryptedDataset = (Vec<Vec<u8>>, Box<dyn BaseCrypto<String>>);
+
+/// The parameter sets that are benchmarked for `fse_type`.
+pub fn bench_params(fse_type: &FSEType) -> Vec<Option<SchemeParams>> {
+    match fse_type {
+        FSEType::Pfse => [0.25, 0.5, 0.75, 1.0]
+            .into_iter()
+            .map(|lambda| {
+                Some(PfseParams::new(lambda, 1.0, BENCH_ADVANTAGE).into())
+            })
+            .collect(),
+        FSEType::LpfseIhbe | FSEType::LpfseBhe => {
+            vec![Some(LpfseParams::new(BENCH_ADVANTAGE).into())]
+        }
+        _ => vec![None],
+    }
+}
+
+/// The phases of the PFSE pipeline in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PfsePhase {
+    Partition,
+    Transform,
+    Smooth,
+}
+
+impl PfsePhase {
+    pub const ALL: [Self; 3] = [Self::Partition, Self::Transform, Self::Smooth];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Partition => "partition",
+            Self::Transform => "transform",
+            Self::Smooth => "smooth",
+        }
+    }
+
+    /// Run this phase on `ctx`, which must have run the phases before it.
+    pub fn run(&self, ctx: &mut ContextPFSE<String>, dataset: &[String]) {
+        match self {
+            Self::Partition => ctx.partition(dataset, exponential).unwrap(),
+            Self::Transform => {
+                ctx.transform();
+            }
+            Self::Smooth => {
+                ctx.smooth();
+            }
+        }
+    }
+}
+
+/// Run the benchmark group `pfse_{phase}_bench_on_real` over all [`BENCH_SIZES`] and [`bench_params`]. Each
+/// iteration measures `phase` alone on a context that has already run the phases before it.
+pub fn bench_pfse_phase(c: &mut Criterion, phase: PfsePhase) {
+    let mut group =
+        c.benchmark_group(format!("pfse_{}_bench_on_real", phase.name()));
+
+    for size in BENCH_SIZES {
+        let dataset = load_dataset(size);
+        for param in bench_params(&FSEType::Pfse) {
+            let params = param.unwrap().pfse().unwrap();
+            let mut ctx = ContextPFSE::default();
+            ctx.key_generate();
+            ctx.set_params(&params).unwrap();
+            PfsePhase::ALL
+                .iter()
+                .take_while(|&&e| e != phase)
+                .for_each(|e| e.run(&mut ctx, &dataset));
+
+            group.throughput(Throughput::Elements(size as u64));
+            group.bench_function(
+                BenchmarkId::from_parameter(format!(
+                    "{}_{}",
+                    size, params.lambda
+                )),
+                |b| {
+                    b.iter_batched(
+                        || ctx.clone(),
+                        |mut ctx| phase.run(&mut ctx, &dataset),
+                        BatchSize::LargeInput,
+                    )
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+/// How the context of a benchmark interacts with the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchDb {
+    /// The context works offline.
+    Offline,
+    /// The context is connected to the benchmark database.
+    Connect,
+    /// The context is connected and its ciphertexts are inserted before the measurement.
+    Populate,
+}
+
+/// Run the benchmark group `{scheme}_{stage}_bench_on_real` for `fse_type` over all [`BENCH_SIZES`] and
+/// [`bench_params`]. A [`BenchContext`] is constructed for each input and `routine` only needs to do the measurement.
+pub fn bench_scheme<F>(
+    c: &mut Criterion,
+    fse_type: FSEType,
+    stage: &str,
+    db: BenchDb,
+    mut routine: F,
+) where
+    F: FnMut(&mut Bencher<'_, WallTime>, &mut BenchContext),
+{
+    let params = bench_params(&fse_type);
+    let mut group = c.benchmark_group(format!(
+        "{}_{}_bench_on_real",
+        fse_type.name(),
+        stage
+    ));
+
+    for size in BENCH_SIZES {
+        for param in params.iter() {
+            let mut bench_ctx = match db {
+                BenchDb::Offline => {
+                    BenchContext::new(fse_type.clone(), size, *param)
+                }
+                _ => BenchContext::new_with_db(fse_type.clone(), size, *param),
+            };
+            if db == BenchDb::Populate {
+                bench_ctx.insert().unwrap();
+            }
+            let id = match param {
+                Some(SchemeParams::Pfse(params)) => {
+                    format!("{}_{}", size, params.lambda)
+                }
+                _ => size.to_string(),
+            };
+
+            group.throughput(Throughput::Elements(size as u64));
+            group.bench_function(BenchmarkId::from_parameter(id), |b| {
+                routine(b, &mut bench_ctx)
+            });
+
+            if db != BenchDb::Offline {
+                bench_ctx.drop_collection();
+            }
+        }
+    }
+    group.finish();
+}
+
+/// Load `size` records of the benchmark dataset by [`sample_or_cycle`], so that every size is benchmarked whatever the
+/// size of the file.
+pub fn load_dataset(size: usize) -> Vec<String> {
+    let dataset = read_csv_exact(BENCH_DATA_PATH, BENCH_COLUMN).unwrap();
+    sample_or_cycle(&dataset, size, BENCH_SEED)
+}
+
+/// Construct the context of `fse_type` over `dataset` and encrypt the dataset with it. If `db` = `(address, db_name)`
+/// is given, the context is also connected to the database.
+pub fn build_context(
+    fse_type: &FSEType,
+    dataset: &[String],
+    params: Option<&SchemeParams>,
+    db: Option<(&str, &str)>,
+) -> Result<EncryptedDataset> {
+    match fse_type {
+        FSEType::Dte | FSEType::Rnd => {
+            let mut ctx = ContextNative::new(fse_type == &FSEType::Rnd);
+            ctx.key_generate();
+            if let Some((address, db_name)) = db {
+                ctx.initialize_conn(address, db_name, true);
+            }
+
+            let ciphertexts = dataset
+                .iter()
+                .map(|e| ctx.encrypt(e).unwrap().remove(0))
+                .collect();
+            Ok((ciphertexts, Box::new(ctx)))
+        }
+        FSEType::Pfse => {
+            let mut ctx = ContextPFSE::default();
+            ctx.key_generate();
+            ctx.set_params(&params.ok_or("No FSE params found.")?.pfse()?)?;
+            if let Some((address, db_name)) = db {
+                ctx.initialize_conn(address, db_name, true);
+            }
+            ctx.partition(dataset, exponential)?;
+            ctx.transform();
+
+            let ciphertexts = ctx.smooth();
+            Ok((ciphertexts, Box::new(ctx)))
+        }
+        FSEType::LpfseIhbe | FSEType::LpfseBhe => {
+            let encoder: Box<dyn HomophoneEncoder<String>> =
+                match fse_type == &FSEType::LpfseBhe {
+                    true => Box::new(EncoderBHE::new()),
+                    false => Box::new(EncoderIHBE::new()),
+                };
+            let params = params.ok_or("No FSE params found.")?.lpfse()?;
+            let mut ctx = ContextLPFSE::from_params(&params, encoder)?;
+            ctx.key_generate();
+            match db {
+                Some((address, db_name)) => {
+                    ctx.initialize(dataset, address, db_name, true)?
+                }
+                None => ctx.initialize(dataset, "", "", false)?,
+            }
+
+            let ciphertexts = dataset
+                .iter()
+                .map(|e| ctx.encrypt(e).unwrap().remove(0))
+                .collect();
+            Ok((ciphertexts, Box::new(ctx)))
+        }
+        FSEType::Plain => {
+            let mut ctx = ContextPlain::new();
+            if let Some((address, db_name)) = db {
+                ctx.initialize_conn(address, db_name, true);
+            }
+
+            let ciphertexts = dataset
+                .iter()
+                .map(|e| ctx.encrypt(e).unwrap().remove(0))
+                .collect();
+            Ok((ciphertexts, Box::new(ctx)))
+        }
+        FSEType::Wre => Err("WRE is not supported yet.".into()),
+    }
+}
+
+/// A fully constructed context together with its dataset and ciphertexts.
+#[derive(Debug)]
+pub struct BenchContext {
+    pub fse_type: FSEType,
+    pub params: Option<SchemeParams>,
+    pub dataset: Vec<String>,
+    pub ciphertexts: Vec<Vec<u8>>,
+    pub ctx: Box<dyn BaseCrypto<String>>,
+}
+
+impl BenchContext {
+    /// Construct a context of `fse_type` over `size` messages of the benchmark dataset without any database.
+    pub fn new(
+        fse_type: FSEType,
+        size: usize,
+        params: Option<SchemeParams>,
+    ) -> Self {
+        Self::build(fse_type, size, params, None)
+    }
+
+    /// The same as [`BenchContext::new`], but also connects to the benchmark database.
+    pub fn new_with_db(
+        fse_type: FSEType,
+        size: usize,
+        params: Option<SchemeParams>,
+    ) -> Self {
+        Self::build(
+            fse_type,
+            size,
+            params,
+            Some((BENCH_ADDRESS, BENCH_DB_NAME)),
+        )
+    }
+
+    fn build(
+        fse_type: FSEType,
+        size: usize,
+        params: Option<SchemeParams>,
+        db: Option<(&str, &str)>,
+    ) -> Self {
+        let dataset = load_dataset(size);
+        let (ciphertexts, ctx) =
+            build_context(&fse_type, &dataset, params.as_ref(), db).unwrap();
+
+        Self {
+            fse_type,
+            params,
+            dataset,
+            ciphertexts,
+            ctx,
+        }
+    }
+
+    /// The name of the collection that stores the ciphertexts of this context. Benchmarks of the same scheme under
+    /// other parameters get other collections, but repeated benchmarks reuse theirs.
+    pub fn collection(&self) -> String {
+        CollectionId::new(&self.fse_type, BENCH_COLUMN)
+            .params(self.params.as_ref())
+            .run(BENCH_DB_NAME)
+            .name()
+    }
+
+    /// The ciphertexts as documents.
+    pub fn documents(&self) -> Vec<Data> {
+        self.ciphertexts.iter().cloned().map(Data::new).collect()
+    }
+
+    /// Insert the ciphertexts into [`BenchContext::collection`].
+    pub fn insert(&self) -> Result<()> {
+        self.ctx
+            .get_conn()
+            .insert(self.documents(), &self.collection())
+    }
+
+    /// Drop [`BenchContext::collection`].
+    pub fn drop_collection(&self) {
+        self.ctx.get_conn().drop_collection(&self.collection());
+    }
+
+    /// Encrypt every message of the dataset with the constructed context.
+    pub fn encrypt_all(&mut self) {
+        for message in self.dataset.iter() {
+            self.ctx.encrypt(message).unwrap();
+        }
+    }
+
+    /// Construct a fresh offline context over the same dataset and parameters, which includes the setup and the
+    /// encryption of the dataset.
+    pub fn rebuild(&self) -> Vec<Vec<u8>> {
+        build_context(&self.fse_type, &self.dataset, self.params.as_ref(), None)
+            .unwrap()
+            .0
+    }
+
+    /// Uniformly sample a message from the dataset.
+    pub fn random_message(&self) -> String {
+        let idx = Uniform::new(0, self.dataset.len()).sample(&mut OsRng);
+        self.dataset[idx].clone()
+    }
+}
+
+/// The input of an attacker over a synthetic Zipf histogram of `domain` messages. The "encryption" is deterministic
+/// and each message is its own ciphertext, so the attackers run on the histograms alone without any context.
+#[derive(Debug, Clone)]
+pub struct AttackFixture {
+    pub dataset: Vec<String>,
+    pub correct: HashMap<String, Vec<Vec<u8>>>,
+    pub local_table: HashMap<String, Vec<ValueType>>,
+    pub raw_ciphertexts: Vec<Vec<u8>>,
+    /// The auxiliary histogram `(message, weight, count)` ordered by frequency, as built by the attackers.
+    pub auxiliary: Vec<(String, f64, usize)>,
+    /// The ciphertext histogram ordered by frequency.
+    pub ciphertexts: Vec<HistType<Vec<u8>>>,
+}
+
+impl AttackFixture {
+    pub fn new(domain: usize) -> Self {
+        let support =
+            (0..domain).map(|i| format!("m{}", i)).collect::<Vec<_>>();
+        let mut dataset =
+            generate_synthetic_zipf(&support, ATTACK_ZIPF_EXPONENT);
+        dataset.shuffle(&mut OsRng);
+
+        let histogram = build_histogram(&dataset);
+        let correct = histogram
+            .keys()
+            .map(|message| (message.clone(), vec![message.as_bytes().to_vec()]))
+            .collect();
+        let local_table = histogram
+            .iter()
+            .map(|(message, &count)| (message.clone(), vec![(0, 1, count)]))
+            .collect();
+        let auxiliary = build_histogram_vec(&histogram)
+            .into_iter()
+            .map(|(message, count)| (message, count as f64, count))
+            .collect();
+        let raw_ciphertexts = dataset
+            .iter()
+            .map(|message| message.as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let ciphertexts =
+            build_histogram_vec(&build_histogram_fast(&raw_ciphertexts));
+
+        Self {
+            dataset,
+            correct,
+            local_table,
+            raw_ciphertexts,
+            auxiliary,
+            ciphertexts,
+        }
+    }
+}
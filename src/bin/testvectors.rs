@@ -0,0 +1,15 @@
+//! Dump the test vectors of the encodings. See [`fse::testvectors`].
+//!
+//! Usage: `cargo run --bin testvectors -- [output path]`. The vectors are printed if no path is given.
+
+use fse::testvectors::TestVectors;
+
+fn main() -> fse::Result<()> {
+    let vectors = serde_json::to_string_pretty(&TestVectors::generate()?)?;
+    match std::env::args().nth(1) {
+        Some(path) => std::fs::write(path, vectors + "\n")?,
+        None => println!("{}", vectors),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,470 @@
+//! This module abstracts the symmetric cipher that the schemes use to turn (salted) messages into ciphertexts, so that
+//! the smoothing logic does not depend on AES directly. The default is AES-256-GCM wrapped in a [`CommittingCipher`],
+//! and AES-128-GCM can be chosen by a [`SecurityLevel`]; the `debug-crypto` feature adds an [`IdentityCipher`] that
+//! leaves the plaintext readable.
+
+use std::fmt::Debug;
+
+use aes_gcm::{
+    aead::{consts::U12, Aead, AeadCore, Payload},
+    Aes128Gcm, Aes256Gcm, KeyInit, Nonce,
+};
+use dyn_clone::{clone_trait_object, DynClone};
+use hmac::{Hmac, Mac};
+use log::error;
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{error::FseError, Result};
+
+/// The length of the nonce in bytes.
+pub const NONCE_LEN: usize = 12;
+
+/// The all-zero nonce used by the deterministic encryptions of the schemes.
+pub const ZERO_NONCE: [u8; NONCE_LEN] = [0u8; NONCE_LEN];
+
+/// The length of the key commitment prepended by [`CommittingCipher`] in bytes.
+pub const COMMITMENT_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A symmetric cipher that takes the key and the nonce on each call. Implementations log the reason of a failure and
+/// return `None`. They are shared by the threads of [`crate::native::ContextNative::encrypt_batch`].
+pub trait Cipher: Debug + DynClone + Send + Sync {
+    /// Generate a fresh key.
+    fn key_generate(&self) -> Vec<u8>;
+
+    /// The length of the keys of the cipher in bytes.
+    fn key_len(&self) -> usize {
+        SecurityLevel::Aes256.key_len()
+    }
+
+    fn encrypt(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        plaintext: &[u8],
+    ) -> Option<Vec<u8>>;
+
+    fn decrypt(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        ciphertext: &[u8],
+    ) -> Option<Vec<u8>>;
+
+    /// Encrypt `plaintext` and authenticate `aad` along with it, so that the ciphertext only decrypts with the same
+    /// associated data. Ciphers that cannot authenticate associated data fail unless it is empty.
+    fn encrypt_with_aad(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Option<Vec<u8>> {
+        if !aad.is_empty() {
+            error!("[-] The cipher cannot authenticate associated data.");
+            return None;
+        }
+        self.encrypt(key, nonce, plaintext)
+    }
+
+    /// Decrypt a ciphertext of [`Cipher::encrypt_with_aad`], which fails unless `aad` is the associated data it was
+    /// produced with.
+    fn decrypt_with_aad(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Option<Vec<u8>> {
+        if !aad.is_empty() {
+            error!("[-] The cipher cannot authenticate associated data.");
+            return None;
+        }
+        self.decrypt(key, nonce, ciphertext)
+    }
+
+    /// Check that `ciphertext` was produced under `key`. Ciphers that do not commit to their key cannot tell and
+    /// accept every ciphertext.
+    fn verify_key(&self, key: &[u8], ciphertext: &[u8]) -> Result<()> {
+        let _ = (key, ciphertext);
+        Ok(())
+    }
+}
+
+clone_trait_object!(Cipher);
+
+/// The cipher used by all contexts unless another one is set.
+pub fn default_cipher() -> Box<dyn Cipher> {
+    SecurityLevel::default().cipher()
+}
+
+/// The strength of the cipher of a context, which trades the key length for the cost of each encryption.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityLevel {
+    /// AES-128-GCM.
+    Aes128,
+    /// AES-256-GCM.
+    #[default]
+    Aes256,
+}
+
+impl SecurityLevel {
+    /// The length of the keys in bytes.
+    pub fn key_len(&self) -> usize {
+        match self {
+            Self::Aes128 => 16,
+            Self::Aes256 => 32,
+        }
+    }
+
+    /// The key-committing cipher of this level.
+    pub fn cipher(&self) -> Box<dyn Cipher> {
+        let inner: Box<dyn Cipher> = match self {
+            Self::Aes128 => Box::new(Aes128GcmCipher),
+            Self::Aes256 => Box::new(AesGcmCipher),
+        };
+        Box::new(CommittingCipher::new(inner))
+    }
+}
+
+/// A wrapper that makes a cipher key-committing, i.e., a ciphertext can only be decrypted under the key it was
+/// produced with. AES-GCM alone does not guarantee this, so a ciphertext of one tenant or of a rotated key could
+/// otherwise be decrypted into garbage instead of being rejected.
+///
+/// The key is split by HMAC-SHA256 into an encryption key for the inner cipher and a commitment that is prepended to
+/// every ciphertext. The commitment only depends on the key, so deterministic encryptions stay deterministic.
+#[derive(Debug, Clone)]
+pub struct CommittingCipher {
+    inner: Box<dyn Cipher>,
+}
+
+impl CommittingCipher {
+    pub fn new(inner: Box<dyn Cipher>) -> Self {
+        Self { inner }
+    }
+
+    fn derive(key: &[u8], label: &[u8]) -> HmacSha256 {
+        // HMAC accepts keys of any length, so this never fails.
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(key).unwrap();
+        mac.update(label);
+        mac
+    }
+
+    /// The key of the inner cipher, cut to its key length.
+    fn encryption_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut encryption_key = Self::derive(key, b"fse-encryption-key")
+            .finalize()
+            .into_bytes()
+            .to_vec();
+        encryption_key.truncate(self.inner.key_len());
+        encryption_key
+    }
+
+    /// The commitment to `key`.
+    pub fn commitment(key: &[u8]) -> Vec<u8> {
+        Self::derive(key, b"fse-key-commitment")
+            .finalize()
+            .into_bytes()
+            .to_vec()
+    }
+}
+
+impl Cipher for CommittingCipher {
+    fn key_generate(&self) -> Vec<u8> {
+        self.inner.key_generate()
+    }
+
+    fn key_len(&self) -> usize {
+        self.inner.key_len()
+    }
+
+    fn encrypt(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        plaintext: &[u8],
+    ) -> Option<Vec<u8>> {
+        self.encrypt_with_aad(key, nonce, plaintext, &[])
+    }
+
+    fn decrypt(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        ciphertext: &[u8],
+    ) -> Option<Vec<u8>> {
+        self.decrypt_with_aad(key, nonce, ciphertext, &[])
+    }
+
+    fn encrypt_with_aad(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Option<Vec<u8>> {
+        let ciphertext = self.inner.encrypt_with_aad(
+            &self.encryption_key(key),
+            nonce,
+            plaintext,
+            aad,
+        )?;
+        let mut committed = Self::commitment(key);
+        committed.extend_from_slice(&ciphertext);
+        Some(committed)
+    }
+
+    fn decrypt_with_aad(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Option<Vec<u8>> {
+        if let Err(e) = self.verify_key(key, ciphertext) {
+            error!("[-] {}", e);
+            return None;
+        }
+
+        self.inner.decrypt_with_aad(
+            &self.encryption_key(key),
+            nonce,
+            &ciphertext[COMMITMENT_LEN..],
+            aad,
+        )
+    }
+
+    fn verify_key(&self, key: &[u8], ciphertext: &[u8]) -> Result<()> {
+        if ciphertext.len() < COMMITMENT_LEN {
+            return Err(Box::new(FseError::KeyMismatch));
+        }
+
+        // Compare in constant time.
+        Self::derive(key, b"fse-key-commitment")
+            .verify_slice(&ciphertext[..COMMITMENT_LEN])
+            .map_err(|_| FseError::KeyMismatch.into())
+    }
+}
+
+/// AES-256-GCM.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AesGcmCipher;
+
+/// AES-128-GCM.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Aes128GcmCipher;
+
+fn aes_context<A: KeyInit>(key: &[u8], nonce: &[u8]) -> Option<A> {
+    if nonce.len() != NONCE_LEN {
+        error!("[-] The nonce must be {} bytes.", NONCE_LEN);
+        return None;
+    }
+
+    match A::new_from_slice(key) {
+        Ok(aes) => Some(aes),
+        Err(e) => {
+            error!(
+                "[-] Error constructing the AES context due to {:?}.",
+                e.to_string()
+            );
+            None
+        }
+    }
+}
+
+fn aes_encrypt<A>(
+    key: &[u8],
+    nonce: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Option<Vec<u8>>
+where
+    A: KeyInit + Aead + AeadCore<NonceSize = U12>,
+{
+    let aes = aes_context::<A>(key, nonce)?;
+    let payload = Payload {
+        msg: plaintext,
+        aad,
+    };
+    match aes.encrypt(Nonce::from_slice(nonce), payload) {
+        Ok(ciphertext) => Some(ciphertext),
+        Err(e) => {
+            error!("[-] Error when encrypting the message due to {:?}", e);
+            None
+        }
+    }
+}
+
+fn aes_decrypt<A>(
+    key: &[u8],
+    nonce: &[u8],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Option<Vec<u8>>
+where
+    A: KeyInit + Aead + AeadCore<NonceSize = U12>,
+{
+    let aes = aes_context::<A>(key, nonce)?;
+    let payload = Payload {
+        msg: ciphertext,
+        aad,
+    };
+    aes.decrypt(Nonce::from_slice(nonce), payload).ok()
+}
+
+impl Cipher for AesGcmCipher {
+    fn key_generate(&self) -> Vec<u8> {
+        Aes256Gcm::generate_key(&mut OsRng).to_vec()
+    }
+
+    fn encrypt(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        plaintext: &[u8],
+    ) -> Option<Vec<u8>> {
+        aes_encrypt::<Aes256Gcm>(key, nonce, plaintext, &[])
+    }
+
+    fn decrypt(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        ciphertext: &[u8],
+    ) -> Option<Vec<u8>> {
+        aes_decrypt::<Aes256Gcm>(key, nonce, ciphertext, &[])
+    }
+
+    fn encrypt_with_aad(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Option<Vec<u8>> {
+        aes_encrypt::<Aes256Gcm>(key, nonce, plaintext, aad)
+    }
+
+    fn decrypt_with_aad(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Option<Vec<u8>> {
+        aes_decrypt::<Aes256Gcm>(key, nonce, ciphertext, aad)
+    }
+}
+
+impl Cipher for Aes128GcmCipher {
+    fn key_generate(&self) -> Vec<u8> {
+        Aes128Gcm::generate_key(&mut OsRng).to_vec()
+    }
+
+    fn key_len(&self) -> usize {
+        SecurityLevel::Aes128.key_len()
+    }
+
+    fn encrypt(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        plaintext: &[u8],
+    ) -> Option<Vec<u8>> {
+        aes_encrypt::<Aes128Gcm>(key, nonce, plaintext, &[])
+    }
+
+    fn decrypt(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        ciphertext: &[u8],
+    ) -> Option<Vec<u8>> {
+        aes_decrypt::<Aes128Gcm>(key, nonce, ciphertext, &[])
+    }
+
+    fn encrypt_with_aad(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Option<Vec<u8>> {
+        aes_encrypt::<Aes128Gcm>(key, nonce, plaintext, aad)
+    }
+
+    fn decrypt_with_aad(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Option<Vec<u8>> {
+        aes_decrypt::<Aes128Gcm>(key, nonce, ciphertext, aad)
+    }
+}
+
+/// A cipher that does NOT encrypt: the "ciphertext" is the plaintext followed by the nonce, so that the stored
+/// documents can be read to check the partitions and homophones of a scheme.
+///
+/// # Warning
+///
+/// This cipher provides no confidentiality at all. It is only meant for debugging and must never be used in production.
+#[cfg(feature = "debug-crypto")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IdentityCipher;
+
+#[cfg(feature = "debug-crypto")]
+impl Cipher for IdentityCipher {
+    fn key_generate(&self) -> Vec<u8> {
+        log::warn!("[!] IdentityCipher is in use; nothing is encrypted.");
+        vec![0u8; 32]
+    }
+
+    fn encrypt(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        plaintext: &[u8],
+    ) -> Option<Vec<u8>> {
+        Some([plaintext, nonce].concat())
+    }
+
+    fn decrypt(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        ciphertext: &[u8],
+    ) -> Option<Vec<u8>> {
+        let plaintext = ciphertext.strip_suffix(nonce)?;
+        Some(plaintext.to_vec())
+    }
+
+    /// The associated data goes between the plaintext and the nonce.
+    fn encrypt_with_aad(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Option<Vec<u8>> {
+        Some([plaintext, aad, nonce].concat())
+    }
+
+    fn decrypt_with_aad(
+        &self,
+        key: &[u8],
+        nonce: &[u8],
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Option<Vec<u8>> {
+        let plaintext = ciphertext.strip_suffix(nonce)?.strip_suffix(aad)?;
+        Some(plaintext.to_vec())
+    }
+}
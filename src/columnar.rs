@@ -0,0 +1,121 @@
+//! This module feeds Arrow columns (the storage of a polars `Series` or an Arrow `RecordBatch`) to the message
+//! pipeline without materializing them as `Vec<String>` first, and turns decrypted search results back into columns.
+//!
+//! Both [`crate::fse::PartitionFrequencySmoothing::partition`] and [`crate::lpfse::ContextLPFSE::initialize`] only
+//! need the histogram of the dataset, so a column is counted in place: only its distinct values are copied out of
+//! the Arrow buffers. Null slots are skipped.
+
+use std::{collections::HashMap, fmt::Debug, hash::Hash, sync::Arc};
+
+use arrow_array::{Array, ArrayRef, Int64Array, StringArray};
+
+use crate::{
+    fse::{AsBytes, BaseCrypto, FromBytes, PartitionFrequencySmoothing},
+    util::SizeAllocated,
+    Result,
+};
+
+#[cfg(feature = "lpfse")]
+use crate::lpfse::ContextLPFSE;
+
+/// An Arrow array whose values can be used as messages.
+pub trait MessageColumn: Array + Sized {
+    type Message: Hash
+        + Eq
+        + Clone
+        + Debug
+        + AsBytes
+        + FromBytes
+        + SizeAllocated;
+
+    /// Count the occurrences of each non-null value in the column.
+    fn histogram(&self) -> HashMap<Self::Message, usize>;
+
+    /// Build a column holding `messages` in order.
+    fn from_messages(messages: Vec<Self::Message>) -> Self;
+
+    /// The same as [`MessageColumn::from_messages`], but returns a type-erased [`ArrayRef`] that can be put into a
+    /// `RecordBatch` or converted into a polars `Series`.
+    fn array_ref(messages: Vec<Self::Message>) -> ArrayRef
+    where
+        Self: 'static,
+    {
+        Arc::new(Self::from_messages(messages))
+    }
+}
+
+impl MessageColumn for StringArray {
+    type Message = String;
+
+    fn histogram(&self) -> HashMap<String, usize> {
+        // Count borrowed slices first so that each distinct value is copied only once.
+        let mut histogram = HashMap::<&str, usize>::new();
+        for value in self.iter().flatten() {
+            *histogram.entry(value).or_default() += 1;
+        }
+
+        histogram
+            .into_iter()
+            .map(|(value, cnt)| (value.to_string(), cnt))
+            .collect()
+    }
+
+    fn from_messages(messages: Vec<String>) -> Self {
+        Self::from(messages)
+    }
+}
+
+impl MessageColumn for Int64Array {
+    type Message = i64;
+
+    fn histogram(&self) -> HashMap<i64, usize> {
+        let mut histogram = HashMap::new();
+        for value in self.iter().flatten() {
+            *histogram.entry(value).or_default() += 1;
+        }
+        histogram
+    }
+
+    fn from_messages(messages: Vec<i64>) -> Self {
+        Self::from(messages)
+    }
+}
+
+/// Partition the values of `column`. See [`PartitionFrequencySmoothing::partition`].
+pub fn partition_column<C, A>(
+    ctx: &mut C,
+    column: &A,
+    partition_func: fn(f64, usize) -> f64,
+) -> Result<()>
+where
+    A: MessageColumn,
+    C: PartitionFrequencySmoothing<A::Message>,
+{
+    ctx.partition_histogram(&column.histogram(), partition_func)
+}
+
+/// Initialize the encoder of an LPFSE context with the values of `column`. See [`ContextLPFSE::initialize`].
+#[cfg(feature = "lpfse")]
+pub fn initialize_column<A>(
+    ctx: &mut ContextLPFSE<A::Message>,
+    column: &A,
+) -> Result<()>
+where
+    A: MessageColumn,
+{
+    ctx.initialize_histogram(&column.histogram())
+}
+
+/// Search `message` in the collection `name` and collect the decrypted results into a column.
+#[cfg(feature = "db-mongo")]
+pub fn search_column<C, A>(
+    ctx: &mut C,
+    message: &A::Message,
+    name: &str,
+) -> Option<A>
+where
+    A: MessageColumn,
+    C: BaseCrypto<A::Message>,
+{
+    ctx.search(message, name).map(A::from_messages)
+}
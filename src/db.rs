@@ -1,21 +1,145 @@
 //! This module mainly implements a context that contains a database instance.
 //! We use MongoDB as our backend database.
 
-use std::marker::PhantomData;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
+use base64::{engine::general_purpose, Engine};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use mongodb::{
-    bson::{doc, Document},
+    bson::{
+        doc, from_document, oid::ObjectId, spec::BinarySubtype, to_document,
+        Binary, Bson, Document,
+    },
+    error::{
+        BulkWriteFailure, Error as MongoError, ErrorKind, WriteFailure,
+        RETRYABLE_WRITE_ERROR, TRANSIENT_TRANSACTION_ERROR,
+    },
+    options::{
+        AggregateOptions, ClientOptions, CreateIndexOptions, FindOptions,
+        IndexOptions, InsertManyOptions, ReplaceOptions, SessionOptions,
+        WriteConcern,
+    },
     sync::{Client, Cursor, Database},
     IndexModel,
 };
+use rand::distributions::{Distribution, Uniform};
+use rand_core::{OsRng, RngCore};
+use rand_distr::Exp;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::{util::SizeAllocated, Result};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::FseError,
+    fse::HistType,
+    params::SchemeParams,
+    token::TokenSet,
+    util::{to_hex, SizeAllocated},
+    FSEType, Result,
+};
+
+/// The metadata collection that tracks which loads have been inserted into which collection.
+pub const LOADS_COLLECTION: &str = "loads";
+
+/// The metadata collection that holds the dataset fingerprint of each collection.
+pub const FINGERPRINTS_COLLECTION: &str = "fingerprints";
+
+/// The field the two halves of a document split across two servers are joined by. See [`crate::split`].
+pub const JOIN_FIELD: &str = "id";
+
+/// The maximum number of ids of each query of [`Connector::find_by_ids`].
+pub const JOIN_CHUNK_SIZE: usize = 10_000;
 
-/// A sample data store.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// The number of documents [`Connector::import`] inserts per batch.
+pub const IMPORT_BATCH_SIZE: usize = 10_000;
+
+/// The server error codes that indicate a transient failure, i.e., the union of the retryable read and write codes.
+const TRANSIENT_CODES: [i32; 13] = [
+    6, 7, 89, 91, 134, 189, 262, 9001, 10107, 11600, 11602, 13435, 13436,
+];
+
+/// The server error code of a duplicate key.
+const DUPLICATE_KEY_CODE: i32 = 11000;
+
+/// The server error code of an operation that exceeded its `maxTimeMS`.
+const MAX_TIME_EXPIRED_CODE: i32 = 50;
+
+/// The server error code of a write concern that was not satisfied within its `wtimeout`.
+const WRITE_CONCERN_TIMEOUT_CODE: i32 = 64;
+
+/// How often a cancelled token is checked while waiting.
+const CANCELLATION_POLL: Duration = Duration::from_millis(10);
+
+/// A sample data store. The ciphertext is stored as a BSON binary.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Data {
-    pub data: String,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+    /// The insertion sequence number, if the load was inserted under an [`crate::fse::InsertionOrder`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+/// A ciphertext together with the number of documents that store it. See [`Connector::count_matches`] and
+/// [`Connector::frequency_histogram`].
+#[derive(Debug, Deserialize)]
+struct CountedData {
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+    count: i64,
+}
+
+/// The output of a `$count` stage.
+#[derive(Debug, Deserialize)]
+struct MatchTotal {
+    n: i64,
+}
+
+impl Data {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { data, seq: None }
+    }
+
+    pub fn with_seq(data: Vec<u8>, seq: u64) -> Self {
+        Self {
+            data,
+            seq: Some(seq),
+        }
+    }
+
+    /// Encode the ciphertext into base64 for the portable import/export format.
+    pub fn to_base64(&self) -> String {
+        general_purpose::STANDARD_NO_PAD.encode(&self.data)
+    }
+
+    /// Decode a ciphertext in the portable import/export format.
+    pub fn from_base64(encoded: &str) -> Result<Self> {
+        Ok(Self::new(general_purpose::STANDARD_NO_PAD.decode(encoded)?))
+    }
+}
+
+impl AsRef<[u8]> for Data {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl From<Vec<u8>> for Data {
+    fn from(data: Vec<u8>) -> Self {
+        Self::new(data)
+    }
 }
 
 impl SizeAllocated for Data {
@@ -24,51 +148,989 @@ impl SizeAllocated for Data {
     }
 }
 
+/// Convert a ciphertext or a search token into the BSON binary it is stored as, so that it can be compared against
+/// the `data` field in a filter.
+pub fn to_binary<B: AsRef<[u8]>>(bytes: B) -> Bson {
+    Bson::Binary(Binary {
+        subtype: BinarySubtype::Generic,
+        bytes: bytes.as_ref().to_vec(),
+    })
+}
+
+/// The retry policy of the [`Connector`] for transient failures such as network errors or primary step-downs.
+/// The `n`-th retry waits `initial_backoff_ms * 2^(n - 1)` milliseconds, capped at `max_backoff_ms`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, rename_all = "snake_case")]
+pub struct RetryPolicy {
+    /// The maximum number of attempts including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 10_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// The time to wait before the `attempt`-th retry (starting from 1).
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let shift = attempt.saturating_sub(1).min(63);
+        let backoff = self.initial_backoff_ms.saturating_mul(1 << shift);
+        Duration::from_millis(backoff.min(self.max_backoff_ms))
+    }
+}
+
+/// Whether the error is transient so that the operation is worth retrying.
+pub fn is_transient(error: &MongoError) -> bool {
+    match error.kind.as_ref() {
+        ErrorKind::Io(_)
+        | ErrorKind::ConnectionPoolCleared { .. }
+        | ErrorKind::ServerSelection { .. } => true,
+        ErrorKind::Command(e) => TRANSIENT_CODES.contains(&e.code),
+        ErrorKind::Write(WriteFailure::WriteConcernError(e))
+        | ErrorKind::BulkWrite(BulkWriteFailure {
+            write_concern_error: Some(e),
+            ..
+        }) => TRANSIENT_CODES.contains(&e.code),
+        _ => {
+            error.contains_label(RETRYABLE_WRITE_ERROR)
+                || error.contains_label(TRANSIENT_TRANSACTION_ERROR)
+        }
+    }
+}
+
+/// Whether the error is a server-side timeout, i.e., `maxTimeMS` or `wtimeout` expired.
+fn is_timeout(error: &MongoError) -> bool {
+    match error.kind.as_ref() {
+        ErrorKind::Command(e) => e.code == MAX_TIME_EXPIRED_CODE,
+        ErrorKind::Write(WriteFailure::WriteConcernError(e))
+        | ErrorKind::BulkWrite(BulkWriteFailure {
+            write_concern_error: Some(e),
+            ..
+        }) => e.code == WRITE_CONCERN_TIMEOUT_CODE,
+        _ => false,
+    }
+}
+
+/// Whether the error only consists of duplicate keys, i.e., the documents have been inserted by a previous attempt.
+fn is_duplicate_only(error: &MongoError) -> bool {
+    match error.kind.as_ref() {
+        ErrorKind::BulkWrite(BulkWriteFailure {
+            write_errors: Some(errors),
+            write_concern_error: None,
+            ..
+        }) => errors.iter().all(|e| e.code == DUPLICATE_KEY_CODE),
+        _ => false,
+    }
+}
+
+/// Whether a single insert failed because its `_id` or a unique index key exists.
+fn is_duplicate_key(error: &MongoError) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == DUPLICATE_KEY_CODE
+    )
+}
+
+/// The field of a document that holds its padding. See [`PaddingPolicy`].
+pub const PADDING_FIELD: &str = "pad";
+
+/// The distribution of the sizes the stored documents are padded up to, so that the document sizes do not cluster by
+/// the plaintext lengths. Each document is padded up to a target size drawn for it; a document that is already larger
+/// is not padded. The padding is stored in the [`PADDING_FIELD`] of the document rather than appended to the
+/// ciphertext: the `data` field is still matched by the search tokens as it is, and the padding is dropped when the
+/// document is read back as [`Data`].
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "distribution", rename_all = "snake_case")]
+pub enum PaddingPolicy {
+    /// A uniform target size in `[0, max]` bytes.
+    Uniform { max: usize },
+    /// An exponentially distributed target size with the given mean in bytes, capped at `max`.
+    Exponential { mean: f64, max: usize },
+}
+
+impl PaddingPolicy {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Self::Exponential { mean, .. }
+                if !(mean.is_finite() && *mean > 0.0) =>
+            {
+                Err(FseError::InvalidParams(format!(
+                    "the mean padding must be positive, got {}",
+                    mean
+                ))
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sample the target size of a document in bytes.
+    pub fn sample(&self) -> Result<usize> {
+        match *self {
+            Self::Uniform { max } => {
+                Ok(Uniform::new_inclusive(0, max).sample(&mut OsRng))
+            }
+            Self::Exponential { mean, max } => {
+                let len = Exp::new(1.0 / mean)?.sample(&mut OsRng);
+                Ok((len.round() as usize).min(max))
+            }
+        }
+    }
+
+    /// Random bytes that pad a document of `len` bytes up to a sampled target size, or none if the document is
+    /// already larger. They are incompressible, so the storage engine cannot shrink them.
+    pub fn sample_padding(&self, len: usize) -> Result<Vec<u8>> {
+        let mut padding = vec![0u8; self.sample()?.saturating_sub(len)];
+        OsRng.fill_bytes(&mut padding);
+        Ok(padding)
+    }
+}
+
+/// The padding policy and the padding metrics shared by all clones of a [`Connector`].
+#[derive(Debug, Default)]
+struct PaddingState {
+    policy: Mutex<Option<PaddingPolicy>>,
+    bytes: AtomicUsize,
+}
+
+/// A token that cancels the operations of the [`Connector`]s it is given to, e.g., from a watchdog thread. Clones
+/// share the same state, and a cancelled token stays cancelled.
+///
+/// The cancellation is cooperative: an operation checks the token before each attempt and while it waits to retry,
+/// but an attempt that has reached the server runs until it finishes or its timeout expires.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Sleep for `duration` or until the token is cancelled, whichever comes first.
+    pub fn wait(&self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        while !self.is_cancelled() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            thread::sleep(CANCELLATION_POLL.min(deadline - now));
+        }
+    }
+}
+
+/// The operation timeout and the cancellation token shared by all clones of a [`Connector`].
+#[derive(Debug, Default)]
+struct DeadlineState {
+    timeout: Mutex<Option<Duration>>,
+    cancel: Mutex<CancellationToken>,
+}
+
+/// The retry policy and the retry metrics shared by all clones of a [`Connector`].
+#[derive(Debug, Default)]
+struct RetryState {
+    policy: Mutex<RetryPolicy>,
+    retries: AtomicUsize,
+}
+
+/// The ownership of a database that is dropped with its connector. It is shared by the clones of the connector, so
+/// the database is dropped once the last of them is dropped, not by whichever clone goes first.
+#[derive(Debug)]
+struct DropGuard {
+    database: Database,
+    drop: bool,
+}
+
+impl Drop for DropGuard {
+    /// Automatically delete the database.
+    fn drop(&mut self) {
+        if self.drop {
+            log::debug!("database dropped.");
+            self.database.drop(None).unwrap_or_default();
+        }
+    }
+}
+
+/// The name of `index`, or the one the server gives it by default, i.e., its keys and directions joined by `_`.
+fn index_name(index: &IndexModel) -> String {
+    match index.options.as_ref().and_then(|e| e.name.as_ref()) {
+        Some(name) => name.clone(),
+        None => index
+            .keys
+            .iter()
+            .map(|(field, direction)| format!("{}_{}", field, direction))
+            .collect::<Vec<_>>()
+            .join("_"),
+    }
+}
+
+/// The time the inserts of a [`Connector`] and its clones spent on each side of the client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InsertTiming {
+    /// Converting the documents into BSON, including their padding.
+    pub serialization: Duration,
+    /// Sending the documents until the server acknowledges them, including the retries.
+    pub round_trip: Duration,
+}
+
+/// The bytes a [`Connector`] and its clones exchanged with the server, counted as the BSON sizes of the filters,
+/// pipelines and documents, i.e., without the framing of the wire protocol. Each operation is counted once however
+/// many times it is retried.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Bandwidth {
+    /// The number of round trips of the searches, i.e., of their filters and pipelines.
+    pub searches: usize,
+    /// The filters and pipelines sent by the searches, e.g., the tokens of the queries.
+    pub search_sent: usize,
+    /// The result documents received by the searches.
+    pub search_received: usize,
+    /// The number of calls of [`Connector::insert`].
+    pub insert_batches: usize,
+    /// The documents sent by the inserts, including their padding.
+    pub insert_sent: usize,
+}
+
+impl Bandwidth {
+    /// The bytes exchanged since `earlier`, a snapshot of the same connector.
+    pub fn since(&self, earlier: &Bandwidth) -> Bandwidth {
+        Bandwidth {
+            searches: self.searches.saturating_sub(earlier.searches),
+            search_sent: self.search_sent.saturating_sub(earlier.search_sent),
+            search_received: self
+                .search_received
+                .saturating_sub(earlier.search_received),
+            insert_batches: self
+                .insert_batches
+                .saturating_sub(earlier.insert_batches),
+            insert_sent: self.insert_sent.saturating_sub(earlier.insert_sent),
+        }
+    }
+
+    pub fn accumulate(&mut self, other: &Bandwidth) {
+        self.searches += other.searches;
+        self.search_sent += other.search_sent;
+        self.search_received += other.search_received;
+        self.insert_batches += other.insert_batches;
+        self.insert_sent += other.insert_sent;
+    }
+
+    fn add_search(&mut self, sent: usize) {
+        self.searches += 1;
+        self.search_sent += sent;
+    }
+}
+
+/// The size of `document` in BSON.
+fn bson_len(document: &Document) -> usize {
+    mongodb::bson::to_vec(document)
+        .map(|e| e.len())
+        .unwrap_or_default()
+}
+
+/// The cursor of [`Connector::search`], which counts the bytes of the documents it yields into the [`Bandwidth`] of
+/// the connector.
+#[derive(Debug)]
+pub struct SearchCursor<T> {
+    cursor: Cursor<T>,
+    bandwidth: Arc<Mutex<Bandwidth>>,
+}
+
+impl<T> Iterator for SearchCursor<T>
+where
+    T: DeserializeOwned,
+{
+    type Item = mongodb::error::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.cursor.advance() {
+            Ok(true) => {
+                let len = self.cursor.current().as_bytes().len();
+                self.bandwidth.lock().unwrap().search_received += len;
+                Some(self.cursor.deserialize_current())
+            }
+            Ok(false) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// The manifest written next to an archive of [`Connector::export`], from which [`Connector::import`] checks that the
+/// archive is complete.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ExportManifest {
+    pub collection: String,
+    /// The number of exported documents.
+    pub count: u64,
+    /// The hex-encoded SHA-256 digest of the uncompressed NDJSON lines.
+    pub sha256: String,
+    /// The UNIX timestamp of the export.
+    pub timestamp: u64,
+    /// The records of the loads of the collection, which the import records for the collection it imports into.
+    #[serde(default)]
+    pub loads: Vec<LoadRecord>,
+}
+
+impl ExportManifest {
+    /// The path of the manifest of the archive at `path`.
+    pub fn path_of(path: &str) -> String {
+        format!("{}.manifest.json", path)
+    }
+}
+
+/// A line of an archive of [`Connector::export`].
+#[derive(Serialize, Deserialize)]
+struct ExportRecord {
+    /// The `_id` of the document, e.g., the one [`Connector::insert_smoothed`] derives from the load.
+    #[serde(rename = "_id")]
+    id: Bson,
+    data: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    /// The base64-encoded [`PADDING_FIELD`] of the document, if it is padded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pad: Option<String>,
+}
+
+impl ExportRecord {
+    fn from_document(document: Document) -> Result<Self> {
+        let pad = match document.get(PADDING_FIELD) {
+            Some(Bson::Binary(pad)) => {
+                Some(general_purpose::STANDARD_NO_PAD.encode(&pad.bytes))
+            }
+            _ => None,
+        };
+        let id = document.get("_id").cloned().ok_or("missing `_id`")?;
+        let data = from_document::<Data>(document)?;
+        Ok(Self {
+            id,
+            data: data.to_base64(),
+            seq: data.seq,
+            pad,
+        })
+    }
+
+    /// The document the record is imported as, with its `_id` and its padding as they were exported.
+    fn into_document(self) -> Result<Document> {
+        let mut data = Data::from_base64(&self.data)?;
+        data.seq = self.seq;
+        let mut document = to_document(&data)?;
+        document.insert("_id", self.id);
+        if let Some(pad) = self.pad {
+            let pad = general_purpose::STANDARD_NO_PAD.decode(pad)?;
+            document.insert(PADDING_FIELD, to_binary(pad));
+        }
+        Ok(document)
+    }
+}
+
+/// Call `f` on each line of the archive at `path`, including its newline.
+fn for_each_archive_line<F>(path: &str, mut f: F) -> Result<()>
+where
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    let mut reader = BufReader::new(GzDecoder::new(File::open(path)?));
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line)? != 0 {
+        f(&line)?;
+        line.clear();
+    }
+    Ok(())
+}
+
+/// A record of the `loads` metadata collection.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LoadRecord {
+    pub load_id: String,
+    pub collection: String,
+    /// The number of inserted documents.
+    pub count: u64,
+    /// The UNIX timestamp of the insertion.
+    pub timestamp: u64,
+    /// Whether the documents of the load are still being inserted, i.e., the insertion was interrupted if no loader
+    /// is running. See [`Connector::insert_smoothed`].
+    #[serde(default)]
+    pub pending: bool,
+}
+
+/// The queries expected against a collection, from which [`Connector::advise_indexes`] derives its indexes.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WorkloadHints {
+    /// Whether the collection is searched by exact matches on `data`, i.e., by the searches of the schemes.
+    pub exact_data: bool,
+    /// The column fields that are matched together with `data` by conjunctive queries.
+    pub conjunctive: Vec<String>,
+}
+
+/// The outcome of [`Connector::advise_indexes`].
+#[derive(Clone, Debug, Default)]
+pub struct IndexAdvice {
+    /// The names of the created indexes.
+    pub created: Vec<String>,
+    /// The names of the dropped indexes.
+    pub dropped: Vec<String>,
+    /// The conjunctive fields that the sampled document does not have, so that no index is built on them.
+    pub missing: Vec<String>,
+    /// The winning plan of a query on the sampled document before the indexes changed. None ==> the collection is
+    /// empty.
+    pub plan_before: Option<Document>,
+    /// The winning plan of the same query after the indexes changed.
+    pub plan_after: Option<Document>,
+}
+
+/// The typed name of a collection of ciphertexts, so that the collections of different schemes, columns, parameters
+/// and runs never collide in the same database. Its canonical name is `fse.<scheme>.<column>.<params>.<run>`, see
+/// [`CollectionId::name`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CollectionId {
+    pub scheme: FSEType,
+    pub column: String,
+    /// A short hash of the scheme parameters, or `none` if the scheme has none. See [`CollectionId::params_hash`].
+    pub params: String,
+    /// The run that created the collection, e.g., [`new_run_id`].
+    pub run: String,
+}
+
+impl CollectionId {
+    /// The prefix of every canonical name.
+    pub const PREFIX: &'static str = "fse";
+
+    /// The parameters component of a collection without scheme parameters.
+    pub const NO_PARAMS: &'static str = "none";
+
+    /// The run component of a collection that is not bound to any run.
+    pub const DEFAULT_RUN: &'static str = "default";
+
+    /// The id of the collection of `column` under `fse_type` without parameters and in the default run.
+    pub fn new(fse_type: &FSEType, column: &str) -> Self {
+        Self {
+            scheme: fse_type.clone(),
+            column: sanitize(column),
+            params: Self::NO_PARAMS.to_string(),
+            run: Self::DEFAULT_RUN.to_string(),
+        }
+    }
+
+    pub fn params(mut self, params: Option<&SchemeParams>) -> Self {
+        self.params = Self::params_hash(params);
+        self
+    }
+
+    pub fn run(mut self, run: &str) -> Self {
+        self.run = sanitize(run);
+        self
+    }
+
+    /// The same collection in another column, e.g., `<column>-client-1` for the client threads of a run.
+    pub fn column(mut self, column: &str) -> Self {
+        self.column = sanitize(column);
+        self
+    }
+
+    /// The first 16 hex characters of the SHA-256 digest of the JSON encoding of `params`, or [`Self::NO_PARAMS`].
+    pub fn params_hash(params: Option<&SchemeParams>) -> String {
+        match params {
+            Some(params) => {
+                // Serializing plain numbers into JSON never fails.
+                let encoded = serde_json::to_vec(params).unwrap();
+                to_hex(&Sha256::digest(encoded)[..8])
+            }
+            None => Self::NO_PARAMS.to_string(),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        format!(
+            "{}.{}.{}.{}.{}",
+            Self::PREFIX,
+            self.scheme.name(),
+            self.column,
+            self.params,
+            self.run
+        )
+    }
+
+    /// Parse a canonical name. Returns `None` if `name` is not one, e.g., a metadata collection.
+    pub fn parse(name: &str) -> Option<Self> {
+        let parts = name.split('.').collect::<Vec<_>>();
+        match parts[..] {
+            [prefix, scheme, column, params, run] if prefix == Self::PREFIX => {
+                Some(Self {
+                    scheme: FSEType::from_name(scheme)?,
+                    column: column.to_string(),
+                    params: params.to_string(),
+                    run: run.to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `component` is kept as it is in a canonical name, i.e., it is non-empty and has ASCII letters, digits,
+    /// `_` and `-` only.
+    pub fn is_component(component: &str) -> bool {
+        !component.is_empty() && component.chars().all(is_component_char)
+    }
+
+    /// The prefix of the names of all the collections, for [`Connector::collections`] and [`Connector::drop_prefix`].
+    pub fn all_prefix() -> String {
+        format!("{}.", Self::PREFIX)
+    }
+
+    /// The prefix of the names of all the collections of `fse_type`.
+    pub fn scheme_prefix(fse_type: &FSEType) -> String {
+        format!("{}.{}.", Self::PREFIX, fse_type.name())
+    }
+
+    /// The prefix of the names of all the collections of `column` under this scheme and these parameters, whatever
+    /// their run.
+    pub fn run_prefix(&self) -> String {
+        format!(
+            "{}.{}.{}.{}.",
+            Self::PREFIX,
+            self.scheme.name(),
+            self.column,
+            self.params
+        )
+    }
+}
+
+impl Display for CollectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name())
+    }
+}
+
+/// Replace everything but ASCII letters, digits, `_` and `-` by `-`, so that a component never contains the `.`
+/// separator or a character MongoDB forbids in collection names.
+fn sanitize(component: &str) -> String {
+    let sanitized = component
+        .chars()
+        .map(|c| match is_component_char(c) {
+            true => c,
+            false => '-',
+        })
+        .collect::<String>();
+    match sanitized.is_empty() {
+        true => "-".to_string(),
+        false => sanitized,
+    }
+}
+
+fn is_component_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// A fresh run id of 8 random hex characters.
+pub fn new_run_id() -> String {
+    let mut bytes = [0u8; 4];
+    OsRng.fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+/// The clients shared by all the connectors of the process. A client holds a pool of connections to its server, so
+/// connectors to the same address reuse the established connections instead of opening new ones, which would otherwise
+/// be part of the latency of their first operations.
+static CLIENTS: Mutex<Vec<PooledClient>> = Mutex::new(Vec::new());
+
+/// The maximum number of connections of the clients created from now on. None ==> the default of the driver.
+static MAX_POOL_SIZE: Mutex<Option<u32>> = Mutex::new(None);
+
+#[derive(Debug, Clone)]
+struct PooledClient {
+    address: String,
+    max_pool_size: Option<u32>,
+    client: Client,
+    /// Whether a connection has been established by [`Connector::connect`].
+    established: Arc<Mutex<bool>>,
+}
+
+/// Set the maximum number of connections of the clients created from now on. Connectors to an address that is
+/// already pooled with another size get a new client.
+pub fn set_max_pool_size(size: Option<u32>) {
+    *MAX_POOL_SIZE.lock().unwrap() = size;
+}
+
+pub fn get_max_pool_size() -> Option<u32> {
+    *MAX_POOL_SIZE.lock().unwrap()
+}
+
+/// The number of clients in the pool.
+pub fn pooled_clients() -> usize {
+    CLIENTS.lock().unwrap().len()
+}
+
+/// Remove all the clients from the pool, e.g., to measure the cost of fresh connections. The connectors that exist
+/// keep their clients.
+pub fn clear_pool() {
+    CLIENTS.lock().unwrap().clear();
+}
+
+/// Get the pooled client of `address` with the current maximum pool size, or create one.
+fn pooled_client(address: &str) -> Result<PooledClient> {
+    let max_pool_size = get_max_pool_size();
+    let mut clients = CLIENTS.lock().unwrap();
+    if let Some(pooled) = clients
+        .iter()
+        .find(|e| e.address == address && e.max_pool_size == max_pool_size)
+    {
+        return Ok(pooled.clone());
+    }
+
+    let mut options = ClientOptions::parse(address)?;
+    if max_pool_size.is_some() {
+        options.max_pool_size = max_pool_size;
+    }
+    let pooled = PooledClient {
+        address: address.to_string(),
+        max_pool_size,
+        client: Client::with_options(options)?,
+        established: Arc::default(),
+    };
+    clients.push(pooled.clone());
+    Ok(pooled)
+}
+
+/// Check that the server at `address` answers a `ping` within `timeout`. [`Connector::new`] connects lazily, so an
+/// unreachable server only shows up at the first operation.
+pub fn ping(address: &str, timeout: Duration) -> Result<()> {
+    let mut options = ClientOptions::parse(address)?;
+    options.connect_timeout = Some(timeout);
+    options.server_selection_timeout = Some(timeout);
+    let client = Client::with_options(options)?;
+    client
+        .database("admin")
+        .run_command(doc! { "ping": 1 }, None)?;
+    Ok(())
+}
+
 /// A context that can be used to perform database-related operations such as insert, search.
 ///
 /// Note that `T` must derive `Serialize` and `Deserialize` so that it can be stored in MongoDB.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Connector<T>
 where
     T: Serialize + DeserializeOwned,
 {
+    /// The client that owns the database instance.
+    client: Client,
     /// The database instance.
     database: Database,
+    /// The retry policy for transient failures.
+    retry: Arc<RetryState>,
+    /// The padding of the inserted documents.
+    padding: Arc<PaddingState>,
+    /// The timeout and the cancellation of the operations.
+    deadline: Arc<DeadlineState>,
+    /// Whether the pooled client has established a connection.
+    established: Arc<Mutex<bool>>,
+    /// The time [`Connector::connect`] took.
+    connect_time: Arc<Mutex<Option<Duration>>>,
+    /// The time spent by the inserts so far.
+    insert_timing: Arc<Mutex<InsertTiming>>,
+    /// The bytes exchanged with the server so far.
+    bandwidth: Arc<Mutex<Bandwidth>>,
     /// A marker.
     _marker: PhantomData<T>,
-    /// Should we drop the database on `drop`.
-    drop: bool,
+    /// The ownership of the database shared with the clones. None ==> detached by [`Connector::detach`].
+    owner: Mutex<Option<Arc<DropGuard>>>,
+}
+
+impl<T> Clone for Connector<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// The clone shares the ownership of the database, so it is dropped only when both are.
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            database: self.database.clone(),
+            retry: self.retry.clone(),
+            padding: self.padding.clone(),
+            deadline: self.deadline.clone(),
+            established: self.established.clone(),
+            connect_time: self.connect_time.clone(),
+            insert_timing: self.insert_timing.clone(),
+            bandwidth: self.bandwidth.clone(),
+            _marker: PhantomData,
+            owner: Mutex::new(self.owner.lock().unwrap().clone()),
+        }
+    }
 }
 
 impl<T> Connector<T>
 where
     T: Serialize + DeserializeOwned,
 {
+    /// Connect to the database `db_name` at `address`. The client of the address is taken from the pool of the
+    /// process if there is one, so no connection is opened here. If `drop` is set, the database is dropped once this
+    /// connector and all its clones that are not detached are dropped.
     pub fn new(address: &str, db_name: &str, drop: bool) -> Result<Self> {
-        let client = Client::with_uri_str(address)?;
+        let PooledClient {
+            client,
+            established,
+            ..
+        } = pooled_client(address)?;
 
+        let database = client.database(db_name);
         Ok(Self {
-            database: client.database(db_name),
+            owner: Mutex::new(Some(Arc::new(DropGuard {
+                database: database.clone(),
+                drop,
+            }))),
+            database,
+            client,
+            retry: Arc::default(),
+            padding: Arc::default(),
+            deadline: Arc::default(),
+            established,
+            connect_time: Arc::default(),
+            insert_timing: Arc::default(),
+            bandwidth: Arc::default(),
             _marker: PhantomData,
-            drop,
         })
     }
 
+    /// Give up the ownership of the database, so that dropping this connector never drops it. The other clones keep
+    /// theirs, but if this connector was the last owner, the database is kept for good. Clones made after detaching
+    /// are detached as well.
+    pub fn detach(&self) {
+        if let Some(guard) = self.owner.lock().unwrap().take() {
+            if let Ok(mut guard) = Arc::try_unwrap(guard) {
+                guard.drop = false;
+            }
+        }
+    }
+
+    /// Whether the database is dropped once this connector and the clones sharing its ownership are dropped.
+    pub fn owns_database(&self) -> bool {
+        matches!(self.owner.lock().unwrap().as_ref(), Some(guard) if guard.drop)
+    }
+
+    /// The number of connectors sharing the ownership of the database with this one, itself included, or 0 if it is
+    /// detached.
+    pub fn owner_count(&self) -> usize {
+        self.owner
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, Arc::strong_count)
+    }
+
     /// Get the name of the current database.
     pub fn name(&self) -> &str {
         self.database.name()
     }
 
+    /// Establish a connection to the server unless the pooled client has one already, and return the time it took,
+    /// i.e., zero if a connection is reused. Calling it before the measured operations keeps the cost of the
+    /// connection out of their latencies.
+    pub fn connect(&self) -> Result<Duration> {
+        let mut connect_time = self.connect_time.lock().unwrap();
+        if let Some(connect_time) = *connect_time {
+            return Ok(connect_time);
+        }
+
+        let mut established = self.established.lock().unwrap();
+        let elapsed = match *established {
+            true => Duration::ZERO,
+            false => {
+                let instant = Instant::now();
+                self.client
+                    .database("admin")
+                    .run_command(doc! { "ping": 1 }, None)?;
+                instant.elapsed()
+            }
+        };
+        *established = true;
+        *connect_time = Some(elapsed);
+        Ok(elapsed)
+    }
+
+    /// The time [`Connector::connect`] took, if it has been called.
+    pub fn get_connect_time(&self) -> Option<Duration> {
+        *self.connect_time.lock().unwrap()
+    }
+
+    /// Set the retry policy for transient failures of insert, search and size.
+    pub fn set_retry_policy(&self, policy: RetryPolicy) {
+        *self.retry.policy.lock().unwrap() = policy;
+    }
+
+    pub fn get_retry_policy(&self) -> RetryPolicy {
+        *self.retry.policy.lock().unwrap()
+    }
+
+    /// Get the number of retries performed so far.
+    pub fn get_retry_count(&self) -> usize {
+        self.retry.retries.load(Ordering::Relaxed)
+    }
+
+    /// Pad the documents inserted from now on. None ==> no padding. Fails if the policy is invalid.
+    pub fn set_padding_policy(
+        &self,
+        policy: Option<PaddingPolicy>,
+    ) -> Result<()> {
+        if let Some(policy) = policy.as_ref() {
+            policy.validate()?;
+        }
+        *self.padding.policy.lock().unwrap() = policy;
+        Ok(())
+    }
+
+    pub fn get_padding_policy(&self) -> Option<PaddingPolicy> {
+        *self.padding.policy.lock().unwrap()
+    }
+
+    /// Get the time spent by the inserts so far.
+    pub fn get_insert_timing(&self) -> InsertTiming {
+        *self.insert_timing.lock().unwrap()
+    }
+
+    /// Get the bytes exchanged with the server so far.
+    pub fn get_bandwidth(&self) -> Bandwidth {
+        *self.bandwidth.lock().unwrap()
+    }
+
+    /// Get the number of padding bytes inserted so far.
+    pub fn get_padding_bytes(&self) -> usize {
+        self.padding.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Bound each operation by `timeout`, including its retries. Queries and commands pass it to the server as
+    /// `maxTimeMS` and writes as `wtimeout`; an operation that exceeds it fails with [`FseError::Timeout`].
+    /// None ==> no timeout.
+    pub fn set_operation_timeout(&self, timeout: Option<Duration>) {
+        *self.deadline.timeout.lock().unwrap() = timeout;
+    }
+
+    pub fn get_operation_timeout(&self) -> Option<Duration> {
+        *self.deadline.timeout.lock().unwrap()
+    }
+
+    /// Cancel the operations of this connector and its clones when `token` is cancelled. The operations fail with
+    /// [`FseError::Cancelled`].
+    pub fn set_cancellation_token(&self, token: CancellationToken) {
+        *self.deadline.cancel.lock().unwrap() = token;
+    }
+
+    pub fn get_cancellation_token(&self) -> CancellationToken {
+        self.deadline.cancel.lock().unwrap().clone()
+    }
+
+    /// Run `f` until it succeeds, fails permanently or the attempts are used up. `f` receives the current attempt.
+    /// The operation is given up early if it is cancelled or the next retry would exceed the operation timeout.
+    fn with_retry<R>(
+        &self,
+        operation: &str,
+        mut f: impl FnMut(u32) -> mongodb::error::Result<R>,
+    ) -> Result<R> {
+        let policy = self.get_retry_policy();
+        let timeout = self.get_operation_timeout();
+        let cancel = self.get_cancellation_token();
+        let timed_out = |timeout| FseError::Timeout {
+            operation: operation.to_string(),
+            timeout,
+        };
+        let start = Instant::now();
+        let mut attempt = 1;
+        loop {
+            if cancel.is_cancelled() {
+                return Err(FseError::Cancelled {
+                    operation: operation.to_string(),
+                }
+                .into());
+            }
+
+            match f(attempt) {
+                Ok(res) => return Ok(res),
+                Err(e) if timeout.is_some() && is_timeout(&e) => {
+                    return Err(timed_out(timeout.unwrap()).into())
+                }
+                Err(e) if !is_transient(&e) => return Err(e.into()),
+                Err(e) if attempt >= policy.max_attempts => {
+                    return Err(FseError::RetriesExhausted {
+                        operation: operation.to_string(),
+                        attempts: attempt,
+                        reason: e.to_string(),
+                    }
+                    .into())
+                }
+                Err(e) => {
+                    let backoff = policy.backoff(attempt);
+                    match timeout {
+                        Some(timeout)
+                            if start.elapsed() + backoff >= timeout =>
+                        {
+                            return Err(timed_out(timeout).into())
+                        }
+                        _ => (),
+                    }
+                    log::warn!(
+                        "[!] Transient failure of {} (attempt {}): {}. Retrying in {:?}.",
+                        operation,
+                        attempt,
+                        e,
+                        backoff
+                    );
+                    self.retry.retries.fetch_add(1, Ordering::Relaxed);
+                    cancel.wait(backoff);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn aggregate_options(&self) -> AggregateOptions {
+        AggregateOptions::builder()
+            .max_time(self.get_operation_timeout())
+            .build()
+    }
+
+    /// Run a command against the `admin` database of the server, e.g., to flush or clear caches between benchmarks.
+    pub fn run_admin_command<C: Serialize>(
+        &self,
+        command: &C,
+    ) -> Result<Document> {
+        let command = mongodb::bson::to_document(command)?;
+        Ok(self.client.database("admin").run_command(command, None)?)
+    }
+
     /// Get the size of the collection.
     pub fn size(&self, collection_name: &str) -> usize {
+        let mut command = doc! {
+          "collStats": collection_name,
+        };
+        if let Some(timeout) = self.get_operation_timeout() {
+            command.insert("maxTimeMS", timeout.as_millis() as i64);
+        }
         let res = self
-            .database
-            .run_command(
-                doc! {
-                  "collStats": collection_name,
-                },
-                None,
-            )
+            .with_retry("size", |_| {
+                self.database.run_command(command.clone(), None)
+            })
             .unwrap();
 
         res.get_i32("totalSize").unwrap() as usize
@@ -79,40 +1141,777 @@ where
         &self,
         document: Document,
         collection_name: &str,
-    ) -> Result<Cursor<T>> {
+    ) -> Result<SearchCursor<T>> {
         let collection = self.database.collection(collection_name);
-        Ok(collection.find(document, None)?)
+        let options = FindOptions::builder()
+            .max_time(self.get_operation_timeout())
+            .build();
+        let cursor = self.with_retry("search", |_| {
+            collection.find(document.clone(), options.clone())
+        })?;
+        self.bandwidth
+            .lock()
+            .unwrap()
+            .add_search(bson_len(&document));
+
+        Ok(SearchCursor {
+            cursor,
+            bandwidth: self.bandwidth.clone(),
+        })
+    }
+
+    /// Count the filters or pipelines sent and the documents received by a search.
+    fn record_search(&self, sent: &[Document], received: &[Document]) {
+        let mut bandwidth = self.bandwidth.lock().unwrap();
+        bandwidth.add_search(sent.iter().map(bson_len).sum());
+        bandwidth.search_received +=
+            received.iter().map(bson_len).sum::<usize>();
+    }
+
+    /// Run an aggregation pipeline on the collection and deserialize the resulting documents.
+    pub fn aggregate(
+        &self,
+        pipeline: Vec<Document>,
+        collection_name: &str,
+    ) -> Result<Vec<T>> {
+        let collection = self.database.collection::<T>(collection_name);
+        let cursor = self.with_retry("aggregate", |_| {
+            collection.aggregate(pipeline.clone(), self.aggregate_options())
+        })?;
+        let documents = cursor.collect::<mongodb::error::Result<Vec<_>>>()?;
+        self.record_search(&pipeline, &documents);
+        let mut res = Vec::new();
+        for document in documents {
+            res.push(from_document(document)?);
+        }
+
+        Ok(res)
+    }
+
+    /// Draw a uniform random sample of at most `k` documents among those matching `filter`, together with the number
+    /// of matching documents. The count and the sample are two aggregations (`$match`, then `$count` or `$sample`),
+    /// and the sample is streamed through a cursor, so it is not bound by the size limit of a single document. Under
+    /// concurrent writes, the count and the sample may therefore see different documents.
+    pub fn sample(
+        &self,
+        filter: Document,
+        k: usize,
+        collection_name: &str,
+    ) -> Result<(Vec<T>, usize)> {
+        if k == 0 {
+            return Err("Cannot sample zero documents.".into());
+        }
+        let collection = self.database.collection::<Document>(collection_name);
+
+        let pipeline =
+            vec![doc! { "$match": filter.clone() }, doc! { "$count": "n" }];
+        let cursor = self.with_retry("sample", |_| {
+            collection.aggregate(pipeline.clone(), self.aggregate_options())
+        })?;
+        let documents = cursor.collect::<mongodb::error::Result<Vec<_>>>()?;
+        self.record_search(&pipeline, &documents);
+        let total = match documents.into_iter().next() {
+            Some(document) => from_document::<MatchTotal>(document)?.n as usize,
+            None => return Ok((Vec::new(), 0)),
+        };
+
+        let pipeline = vec![
+            doc! { "$match": filter },
+            doc! { "$sample": { "size": k as i64 } },
+        ];
+        let cursor = self.with_retry("sample", |_| {
+            collection.aggregate(pipeline.clone(), self.aggregate_options())
+        })?;
+        self.record_search(&pipeline, &[]);
+        let mut sample = Vec::with_capacity(k.min(total));
+        for document in cursor {
+            let document = document?;
+            self.bandwidth.lock().unwrap().search_received +=
+                bson_len(&document);
+            sample.push(from_document(document)?);
+        }
+
+        Ok((sample, total))
+    }
+
+    /// Count the documents of the collection that store each of the `tokens`. The documents are grouped by the
+    /// server, so only one document per distinct matching ciphertext is transferred. Tokens without any match are
+    /// left out.
+    pub fn count_matches(
+        &self,
+        tokens: &TokenSet,
+        collection_name: &str,
+    ) -> Result<Vec<(Vec<u8>, usize)>> {
+        let tokens = tokens.iter().map(to_binary).collect::<Vec<_>>();
+        let pipeline = vec![
+            doc! { "$match": { "data": { "$in": tokens } } },
+            doc! { "$group": { "_id": "$data", "count": { "$sum": 1 } } },
+            doc! { "$project": { "_id": 0, "data": "$_id", "count": 1 } },
+        ];
+
+        self.aggregate_counts(
+            "count_matches",
+            pipeline,
+            self.aggregate_options(),
+            collection_name,
+        )
+    }
+
+    /// The histogram of the ciphertexts stored in the collection, i.e., what an honest-but-curious server observes,
+    /// sorted by descending count. The documents are grouped by the server, so only one document per distinct
+    /// ciphertext is transferred and nothing is decrypted. The grouping may spill to disk on a large collection.
+    pub fn frequency_histogram(
+        &self,
+        collection_name: &str,
+    ) -> Result<Vec<HistType<Vec<u8>>>> {
+        let pipeline = vec![
+            doc! { "$group": { "_id": "$data", "count": { "$sum": 1 } } },
+            doc! { "$sort": { "count": -1, "_id": 1 } },
+            doc! { "$project": { "_id": 0, "data": "$_id", "count": 1 } },
+        ];
+        let mut options = self.aggregate_options();
+        options.allow_disk_use = Some(true);
+
+        self.aggregate_counts(
+            "frequency_histogram",
+            pipeline,
+            options,
+            collection_name,
+        )
+    }
+
+    /// Run an aggregation `pipeline` that ends with [`CountedData`] documents.
+    fn aggregate_counts(
+        &self,
+        operation: &str,
+        pipeline: Vec<Document>,
+        options: AggregateOptions,
+        collection_name: &str,
+    ) -> Result<Vec<(Vec<u8>, usize)>> {
+        let collection = self.database.collection::<Document>(collection_name);
+        let cursor = self.with_retry(operation, |_| {
+            collection.aggregate(pipeline.clone(), options.clone())
+        })?;
+        let documents = cursor.collect::<mongodb::error::Result<Vec<_>>>()?;
+        self.record_search(&pipeline, &documents);
+        let mut res = Vec::new();
+        for document in documents {
+            let counted = from_document::<CountedData>(document)?;
+            res.push((counted.data, counted.count as usize));
+        }
+
+        Ok(res)
     }
 
     /// Insert documents into the collection.
+    ///
+    /// The `_id`s are assigned before the first attempt, so a retry after a partially applied insert only reports
+    /// duplicate keys for the documents that are already there, which are skipped.
     pub fn insert(
         &self,
         document: Vec<T>,
         collection_name: &str,
     ) -> Result<()> {
-        let collection = self.database.collection(collection_name);
-        let index = IndexModel::builder().keys(doc! {"data":1}).build();
-        collection.create_index(index, None)?;
-        collection.insert_many(document, None)?;
+        self.insert_impl(document, collection_name, None)
+    }
+
+    /// Insert documents into the collection. The `_id` of each document of a load is derived from `load_id`, its
+    /// ciphertext and how many copies of the ciphertext precede it, so inserting the load again, even arranged in
+    /// another order, only stores the documents that are missing.
+    fn insert_impl(
+        &self,
+        document: Vec<T>,
+        collection_name: &str,
+        load_id: Option<&str>,
+    ) -> Result<()> {
+        let instant = Instant::now();
+        let padding = self.get_padding_policy();
+        let mut padding_bytes = 0;
+        let mut documents = Vec::with_capacity(document.len());
+        let mut copies = HashMap::<Vec<u8>, usize>::new();
+        for e in document.iter() {
+            let mut document = to_document(e)?;
+            match load_id {
+                Some(load_id) => {
+                    let digest = match document.get("data") {
+                        Some(Bson::Binary(data)) => Sha256::digest(&data.bytes),
+                        _ => Sha256::digest(mongodb::bson::to_vec(&document)?),
+                    };
+                    let copy = copies.entry(digest.to_vec()).or_default();
+                    document.insert(
+                        "_id",
+                        format!("{}:{}:{}", load_id, to_hex(&digest), copy),
+                    );
+                    *copy += 1;
+                }
+                None if !document.contains_key("_id") => {
+                    document.insert("_id", ObjectId::new());
+                }
+                None => (),
+            }
+            // Every document gets the field, so that only the target size decides the size of a padded document.
+            if let Some(padding) = padding.as_ref() {
+                let len = mongodb::bson::to_vec(&document)?.len();
+                let padding = padding.sample_padding(len)?;
+                padding_bytes += padding.len();
+                document.insert(PADDING_FIELD, to_binary(padding));
+            }
+            documents.push(document);
+        }
+        self.insert_timing.lock().unwrap().serialization += instant.elapsed();
+
+        self.insert_documents(&documents, collection_name, load_id.is_some())?;
+        self.padding
+            .bytes
+            .fetch_add(padding_bytes, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Insert serialized documents into the collection as a single batch. The duplicate keys of a retry are always
+    /// skipped, and those of the first attempt only if `skip_duplicates`.
+    fn insert_documents(
+        &self,
+        documents: &[Document],
+        collection_name: &str,
+        skip_duplicates: bool,
+    ) -> Result<()> {
+        let collection = self.database.collection::<Document>(collection_name);
+        let timeout = self.get_operation_timeout();
+        self.create_index(collection_name, doc! { "data": 1 })?;
+        let sent = documents.iter().map(bson_len).sum::<usize>();
+
+        let instant = Instant::now();
+        let options = InsertManyOptions::builder()
+            .ordered(false)
+            .write_concern(
+                timeout.map(|e| WriteConcern::builder().w_timeout(e).build()),
+            )
+            .build();
+        self.with_retry("insert", |attempt| {
+            match collection.insert_many(documents.iter(), options.clone()) {
+                Err(e)
+                    if (attempt > 1 || skip_duplicates)
+                        && is_duplicate_only(&e) =>
+                {
+                    Ok(())
+                }
+                res => res.map(|_| ()),
+            }
+        })?;
+        self.insert_timing.lock().unwrap().round_trip += instant.elapsed();
+
+        let mut bandwidth = self.bandwidth.lock().unwrap();
+        bandwidth.insert_batches += 1;
+        bandwidth.insert_sent += sent;
+        Ok(())
+    }
+
+    /// Create the index of `keys` on the collection unless it exists.
+    pub fn create_index(
+        &self,
+        collection_name: &str,
+        keys: Document,
+    ) -> Result<()> {
+        self.create_index_model(
+            collection_name,
+            IndexModel::builder().keys(keys).build(),
+        )
+    }
+
+    fn create_index_model(
+        &self,
+        collection_name: &str,
+        index: IndexModel,
+    ) -> Result<()> {
+        let collection = self.database.collection::<Document>(collection_name);
+        let options = CreateIndexOptions::builder()
+            .max_time(self.get_operation_timeout())
+            .build();
+        self.with_retry("create_index", |_| {
+            collection.create_index(index.clone(), options.clone())
+        })?;
+
+        Ok(())
+    }
+
+    /// Find the documents whose [`JOIN_FIELD`] is one of `ids`, e.g., the payloads of the tags matched on another
+    /// server. The ids are sent in chunks of [`JOIN_CHUNK_SIZE`], and the documents come in no particular order.
+    pub fn find_by_ids(
+        &self,
+        ids: &[Vec<u8>],
+        collection_name: &str,
+    ) -> Result<Vec<T>> {
+        let collection = self.database.collection::<Document>(collection_name);
+        let options = FindOptions::builder()
+            .max_time(self.get_operation_timeout())
+            .build();
+        let mut res = Vec::with_capacity(ids.len());
+        for chunk in ids.chunks(JOIN_CHUNK_SIZE) {
+            let ids = chunk.iter().map(to_binary).collect::<Vec<_>>();
+            let filter = doc! { JOIN_FIELD: { "$in": ids } };
+            let cursor = self.with_retry("find_by_ids", |_| {
+                collection.find(filter.clone(), options.clone())
+            })?;
+            let documents =
+                cursor.collect::<mongodb::error::Result<Vec<_>>>()?;
+            self.record_search(&[filter], &documents);
+            for document in documents {
+                res.push(from_document(document)?);
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Insert the documents of a smoothed load into the collection and record the load in [`LOADS_COLLECTION`].
+    /// Inserting the same load twice would double the collection and destroy the smoothed distribution, so it is
+    /// refused with [`FseError::DuplicateLoad`] unless `force` is set.
+    ///
+    /// The record is inserted first, as pending, under a unique index on the load and the collection, so that of two
+    /// concurrent loaders only one records the load. The documents get `_id`s derived from the load, so a loader that
+    /// finds the record still pending, e.g., after a crash between the two writes, inserts the load again without
+    /// doubling the documents already stored. With `force`, the documents are inserted once more as new ones.
+    pub fn insert_smoothed(
+        &self,
+        load_id: &str,
+        document: Vec<T>,
+        collection_name: &str,
+        force: bool,
+    ) -> Result<()> {
+        let loads = self.database.collection::<LoadRecord>(LOADS_COLLECTION);
+        self.create_loads_index()?;
+        let filter = doc! { "load_id": load_id, "collection": collection_name };
+        let mut record = LoadRecord {
+            load_id: load_id.to_string(),
+            collection: collection_name.to_string(),
+            count: document.len() as u64,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            pending: true,
+        };
+
+        if force {
+            self.insert(document, collection_name)?;
+            record.pending = false;
+            let options = ReplaceOptions::builder().upsert(true).build();
+            loads.replace_one(filter, record, options)?;
+            return Ok(());
+        }
+
+        match loads.insert_one(&record, None) {
+            Ok(_) => (),
+            Err(e) if is_duplicate_key(&e) => {
+                match loads.find_one(filter.clone(), None)? {
+                    Some(existing) if existing.pending => {
+                        log::warn!(
+                            "Load {} of {} is pending; inserting its missing documents.",
+                            load_id, collection_name
+                        );
+                    }
+                    _ => {
+                        return Err(FseError::DuplicateLoad {
+                            collection: collection_name.to_string(),
+                            load_id: load_id.to_string(),
+                        }
+                        .into())
+                    }
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        self.insert_impl(document, collection_name, Some(load_id))?;
+        loads.update_one(
+            filter,
+            doc! { "$set": { "pending": false } },
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    /// Create the unique index on the load and the collection of [`LOADS_COLLECTION`] unless it exists.
+    fn create_loads_index(&self) -> Result<()> {
+        self.create_index_model(
+            LOADS_COLLECTION,
+            IndexModel::builder()
+                .keys(doc! { "load_id": 1, "collection": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+    }
+
+    /// Create the indexes on `data` that `hints` ask for and drop the other indexes on `data`, e.g., those advised for
+    /// earlier hints. The shape of the documents is taken from a sampled one, and the winning plans of a query on it
+    /// are reported from `explain` before and after the change. The other indexes are left alone.
+    ///
+    /// Note that [`Connector::insert`] creates the index on `data` alone again.
+    pub fn advise_indexes(
+        &self,
+        collection_name: &str,
+        hints: &WorkloadHints,
+    ) -> Result<IndexAdvice> {
+        let collection = self.database.collection::<Document>(collection_name);
+        let sample = self.with_retry("advise_indexes", |_| {
+            collection.find_one(None, None)
+        })?;
+
+        let mut advice = IndexAdvice::default();
+        let mut advised = Vec::new();
+        if hints.exact_data {
+            advised.push(doc! { "data": 1 });
+        }
+        let mut keys = doc! { "data": 1 };
+        for field in hints.conjunctive.iter() {
+            match sample.as_ref() {
+                Some(sample) if sample.contains_key(field) => {
+                    keys.insert(field, 1);
+                }
+                _ => advice.missing.push(field.clone()),
+            }
+        }
+        if keys.len() > 1 {
+            advised.push(keys);
+        }
+
+        let query = sample.as_ref().map(|sample| {
+            let mut filter = doc! {};
+            for (field, _) in advised.iter().flatten() {
+                if let Some(value) = sample.get(field) {
+                    filter.insert(field, value.clone());
+                }
+            }
+            filter
+        });
+        advice.plan_before = self.explain(collection_name, query.as_ref())?;
+
+        let existing = self.with_retry("list_indexes", |_| {
+            collection
+                .list_indexes(None)?
+                .collect::<mongodb::error::Result<Vec<_>>>()
+        })?;
+        for index in existing.iter() {
+            let name = index_name(index);
+            if index.keys.keys().next().map(|e| e.as_str()) == Some("data")
+                && !advised.contains(&index.keys)
+            {
+                self.with_retry("drop_index", |_| {
+                    collection.drop_index(&name, None)
+                })?;
+                advice.dropped.push(name);
+            }
+        }
+
+        let options = CreateIndexOptions::builder()
+            .max_time(self.get_operation_timeout())
+            .build();
+        for keys in advised {
+            if existing.iter().any(|e| e.keys == keys) {
+                continue;
+            }
+            let index = IndexModel::builder().keys(keys).build();
+            self.with_retry("create_index", |_| {
+                collection.create_index(index.clone(), options.clone())
+            })?;
+            advice.created.push(index_name(&index));
+        }
+        advice.plan_after = self.explain(collection_name, query.as_ref())?;
+
+        Ok(advice)
+    }
+
+    /// The time the server took to find `filter` in the collection as reported by `explain`, in whole milliseconds. The
+    /// query is run once more by the server to measure it.
+    pub fn execution_time(
+        &self,
+        filter: Document,
+        collection_name: &str,
+    ) -> Result<Duration> {
+        let command = doc! {
+            "explain": { "find": collection_name, "filter": filter },
+            "verbosity": "executionStats",
+        };
+        let res = self.with_retry("explain", |_| {
+            self.database.run_command(command.clone(), None)
+        })?;
+        let millis = match res
+            .get_document("executionStats")?
+            .get("executionTimeMillis")
+        {
+            Some(Bson::Int32(millis)) => *millis as u64,
+            Some(Bson::Int64(millis)) => *millis as u64,
+            _ => return Err("The explain reports no execution time.".into()),
+        };
+        Ok(Duration::from_millis(millis))
+    }
+
+    /// The winning plan of finding `filter` in the collection. None ==> no filter.
+    fn explain(
+        &self,
+        collection_name: &str,
+        filter: Option<&Document>,
+    ) -> Result<Option<Document>> {
+        let filter = match filter {
+            Some(filter) => filter,
+            None => return Ok(None),
+        };
+        let command = doc! {
+            "explain": { "find": collection_name, "filter": filter },
+            "verbosity": "queryPlanner",
+        };
+        let res = self.with_retry("explain", |_| {
+            self.database.run_command(command.clone(), None)
+        })?;
+        Ok(Some(
+            res.get_document("queryPlanner")?
+                .get_document("winningPlan")?
+                .clone(),
+        ))
+    }
+
+    /// Get all loads that have been inserted into the collection.
+    pub fn loads(&self, collection_name: &str) -> Result<Vec<LoadRecord>> {
+        let loads = self.database.collection::<LoadRecord>(LOADS_COLLECTION);
+        let mut res = Vec::new();
+        for record in
+            loads.find(doc! { "collection": collection_name }, None)?
+        {
+            res.push(record?);
+        }
+
+        Ok(res)
+    }
+
+    /// Delete all documents matching `document` from the collection.
+    pub fn delete(
+        &self,
+        document: Document,
+        collection_name: &str,
+    ) -> Result<()> {
+        let collection = self.database.collection::<T>(collection_name);
+        collection.delete_many(document, None)?;
+
+        Ok(())
+    }
+
+    /// Store the dataset fingerprint of the collection in [`FINGERPRINTS_COLLECTION`], replacing any previous one.
+    pub fn set_fingerprint(
+        &self,
+        collection_name: &str,
+        fingerprint: &str,
+    ) -> Result<()> {
+        let fingerprints = self
+            .database
+            .collection::<Document>(FINGERPRINTS_COLLECTION);
+        let filter = doc! { "collection": collection_name };
+        fingerprints.delete_many(filter, None)?;
+        fingerprints.insert_one(
+            doc! { "collection": collection_name, "fingerprint": fingerprint },
+            None,
+        )?;
 
         Ok(())
     }
 
+    /// Get the dataset fingerprint of the collection, if any.
+    pub fn get_fingerprint(
+        &self,
+        collection_name: &str,
+    ) -> Result<Option<String>> {
+        let fingerprints = self
+            .database
+            .collection::<Document>(FINGERPRINTS_COLLECTION);
+        let filter = doc! { "collection": collection_name };
+        Ok(match fingerprints.find_one(filter, None)? {
+            Some(document) => {
+                Some(document.get_str("fingerprint")?.to_string())
+            }
+            None => None,
+        })
+    }
+
     /// Drop a given collection.
     pub fn drop_collection(&self, collection_name: &str) {
         self.database.collection::<T>(collection_name).drop(None);
+        for metadata in [LOADS_COLLECTION, FINGERPRINTS_COLLECTION] {
+            self.database
+                .collection::<Document>(metadata)
+                .delete_many(doc! { "collection": collection_name }, None)
+                .ok();
+        }
+    }
+
+    /// Rename the collection `from` to `to`, replacing `to` if it exists. The server swaps the two atomically, so a
+    /// reader of `to` sees either the old or the new collection, never a partial one.
+    pub fn rename_collection(&self, from: &str, to: &str) -> Result<()> {
+        let database = self.database.name();
+        let command = doc! {
+            "renameCollection": format!("{}.{}", database, from),
+            "to": format!("{}.{}", database, to),
+            "dropTarget": true,
+        };
+        self.with_retry("rename", |_| {
+            self.client
+                .database("admin")
+                .run_command(command.clone(), None)
+        })?;
+        Ok(())
+    }
+
+    /// The sorted names of the collections whose names start with `prefix`, e.g., [`CollectionId::scheme_prefix`].
+    pub fn collections(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut names = self
+            .database
+            .list_collection_names(None)?
+            .into_iter()
+            .filter(|name| name.starts_with(prefix))
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+
+        Ok(names)
+    }
+
+    /// Drop the collections whose names start with `prefix` together with their metadata, e.g., all the collections
+    /// of a run left behind by a crashed evaluation. Returns the names of the dropped collections.
+    pub fn drop_prefix(&self, prefix: &str) -> Result<Vec<String>> {
+        let names = self.collections(prefix)?;
+        for name in names.iter() {
+            self.drop_collection(name);
+        }
+
+        Ok(names)
     }
 }
 
-impl<T> Drop for Connector<T>
-where
-    T: Serialize + DeserializeOwned,
-{
-    /// Automatically delete the current database.
-    fn drop(&mut self) {
-        if self.drop {
-            log::debug!("database dropped.");
-            self.database.drop(None).unwrap_or_default();
+impl Connector<Data> {
+    /// Export the collection into the gzip-compressed NDJSON archive at `path`, one `{"_id", "data", "seq", "pad"}`
+    /// line per document in `_id` order, and write its [`ExportManifest`] to [`ExportManifest::path_of`].
+    ///
+    /// The documents and the records of their loads are read in a snapshot session, so the archive is the collection
+    /// as it was when the export started even if it is written to meanwhile. Snapshot reads need a replica set or a
+    /// sharded cluster.
+    pub fn export(
+        &self,
+        collection_name: &str,
+        path: &str,
+    ) -> Result<ExportManifest> {
+        let mut session = self.client.start_session(Some(
+            SessionOptions::builder().snapshot(true).build(),
+        ))?;
+        let loads = self.database.collection::<LoadRecord>(LOADS_COLLECTION);
+        let filter = doc! { "collection": collection_name };
+        let mut cursor = self.with_retry("export", |_| {
+            loads.find_with_session(filter.clone(), None, &mut session)
+        })?;
+        let mut records = Vec::new();
+        while let Some(record) = cursor.next(&mut session) {
+            records.push(record?);
+        }
+
+        let collection = self.database.collection::<Document>(collection_name);
+        let options = FindOptions::builder()
+            .sort(doc! { "_id": 1 })
+            .max_time(self.get_operation_timeout())
+            .build();
+        let mut cursor = self.with_retry("export", |_| {
+            collection.find_with_session(None, options.clone(), &mut session)
+        })?;
+
+        let mut writer = GzEncoder::new(
+            BufWriter::new(File::create(path)?),
+            Compression::default(),
+        );
+        let mut hasher = Sha256::new();
+        let mut count = 0u64;
+        while let Some(document) = cursor.next(&mut session) {
+            let document = document?;
+            self.bandwidth.lock().unwrap().search_received +=
+                bson_len(&document);
+            let mut line =
+                serde_json::to_vec(&ExportRecord::from_document(document)?)?;
+            line.push(b'\n');
+            hasher.update(&line);
+            writer.write_all(&line)?;
+            count += 1;
+        }
+        writer.finish()?.flush()?;
+
+        let manifest = ExportManifest {
+            collection: collection_name.to_string(),
+            count,
+            sha256: to_hex(&hasher.finalize()),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            loads: records,
+        };
+        serde_json::to_writer_pretty(
+            File::create(ExportManifest::path_of(path))?,
+            &manifest,
+        )?;
+
+        Ok(manifest)
+    }
+
+    /// Import the archive written by [`Connector::export`] into the collection. The archive is read twice: first to
+    /// check it against the count and the digest of its manifest, so that nothing is inserted from a corrupt one, and
+    /// then to insert the documents in batches of [`IMPORT_BATCH_SIZE`]. The documents keep their `_id`s and those
+    /// already in the collection are skipped, so an import that failed midway, or is run twice, can be run again
+    /// without doubling the collection. The loads of the manifest are then recorded for the collection. Returns the
+    /// number of documents in the archive.
+    pub fn import(&self, path: &str, collection_name: &str) -> Result<usize> {
+        let manifest: ExportManifest = serde_json::from_reader(
+            BufReader::new(File::open(ExportManifest::path_of(path))?),
+        )?;
+
+        let mut hasher = Sha256::new();
+        let mut count = 0u64;
+        for_each_archive_line(path, |line| {
+            hasher.update(line);
+            serde_json::from_slice::<ExportRecord>(line)?.into_document()?;
+            count += 1;
+            Ok(())
+        })?;
+        if count != manifest.count {
+            return Err(format!(
+                "the archive has {} documents but its manifest records {}",
+                count, manifest.count
+            )
+            .into());
+        }
+        if to_hex(&hasher.finalize()) != manifest.sha256 {
+            return Err(
+                "the archive does not match the digest of its manifest".into(),
+            );
+        }
+
+        let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+        for_each_archive_line(path, |line| {
+            batch.push(
+                serde_json::from_slice::<ExportRecord>(line)?
+                    .into_document()?,
+            );
+            if batch.len() == IMPORT_BATCH_SIZE {
+                self.insert_documents(&batch, collection_name, true)?;
+                batch.clear();
+            }
+            Ok(())
+        })?;
+        if !batch.is_empty() {
+            self.insert_documents(&batch, collection_name, true)?;
+        }
+
+        if !manifest.loads.is_empty() {
+            let loads =
+                self.database.collection::<LoadRecord>(LOADS_COLLECTION);
+            self.create_loads_index()?;
+            let options = ReplaceOptions::builder().upsert(true).build();
+            for mut record in manifest.loads {
+                record.collection = collection_name.to_string();
+                let filter = doc! {
+                    "load_id": &record.load_id,
+                    "collection": collection_name,
+                };
+                loads.replace_one(filter, record, options.clone())?;
+            }
         }
+        Ok(count as usize)
     }
 }
@@ -0,0 +1,219 @@
+//! This module detects when the skew of the inserted messages drifts away from the skew the context was built from.
+//! The smoothing parameters are computed once from the initial histogram, so a message that becomes more frequent
+//! afterwards is no longer smoothed enough, and the guarantee degrades silently.
+//!
+//! A [`DriftMonitor`] tracks the heavy hitters of the inserted messages by the space-saving algorithm over their keyed
+//! hashes, so that its state reveals no plaintext, and compares them with the heavy hitters at initialization.
+
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+use crate::{
+    error::FseError,
+    fse::AsBytes,
+    util::{build_histogram_vec, hmac, SizeAllocated},
+    Result,
+};
+
+/// The space-saving summary of the most frequent items of a stream with a fixed number of counters. The count of a
+/// tracked item is overestimated by at most its error, and any item more frequent than `total / capacity` is tracked.
+#[derive(Debug, Clone)]
+pub struct SpaceSaving {
+    capacity: usize,
+    /// The item -> (count, error) table.
+    counters: HashMap<u64, (u64, u64)>,
+    total: u64,
+}
+
+impl SpaceSaving {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            counters: HashMap::new(),
+            total: 0,
+        }
+    }
+
+    pub fn insert(&mut self, item: u64) {
+        self.total += 1;
+        if let Some(counter) = self.counters.get_mut(&item) {
+            counter.0 += 1;
+            return;
+        }
+        if self.counters.len() < self.capacity {
+            self.counters.insert(item, (1, 0));
+            return;
+        }
+
+        // Replace the least frequent item, which the new one inherits the count of as its error.
+        let (&victim, &(count, _)) =
+            self.counters.iter().min_by_key(|(_, e)| e.0).unwrap();
+        self.counters.remove(&victim);
+        self.counters.insert(item, (count + 1, count));
+    }
+
+    /// The estimated count of `item`, or 0 if it is not tracked.
+    pub fn estimate(&self, item: u64) -> u64 {
+        self.counters.get(&item).map_or(0, |e| e.0)
+    }
+
+    /// The `k` most frequent items with their estimated counts in descending order.
+    pub fn top(&self, k: usize) -> Vec<(u64, u64)> {
+        let mut top = self
+            .counters
+            .iter()
+            .map(|(&item, &(count, _))| (item, count))
+            .collect::<Vec<_>>();
+        top.sort_by(|lhs, rhs| rhs.1.cmp(&lhs.1).then(lhs.0.cmp(&rhs.0)));
+        top.truncate(k);
+        top
+    }
+
+    /// The number of items inserted.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+impl SizeAllocated for SpaceSaving {
+    fn size_allocated(&self) -> usize {
+        self.counters.capacity() * std::mem::size_of::<(u64, (u64, u64))>()
+    }
+}
+
+/// The drift of the inserted messages measured by [`DriftMonitor::report`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DriftReport {
+    /// The number of messages observed.
+    pub inserted: u64,
+    /// The mass of the `k` most frequent messages at initialization.
+    pub baseline_skew: f64,
+    /// The mass of the `k` most frequent messages among the inserted ones.
+    pub observed_skew: f64,
+    /// The total-variation distance between both distributions restricted to their `k` most frequent messages.
+    pub distance: f64,
+    /// Whether `distance` exceeds the threshold of the monitor.
+    pub alert: bool,
+}
+
+/// Compares the heavy hitters of the inserted messages with those of the initial histogram. See the module
+/// documentation.
+#[derive(Debug, Clone)]
+pub struct DriftMonitor {
+    key: Vec<u8>,
+    k: usize,
+    threshold: f64,
+    /// The frequencies at initialization of the messages tracked by the sketch at most, keyed by their hashes.
+    baseline: HashMap<u64, f64>,
+    sketch: SpaceSaving,
+}
+
+impl DriftMonitor {
+    /// The number of counters of the sketch per heavy hitter, which keeps the estimates of the top `k` accurate.
+    const COUNTERS_PER_ITEM: usize = 4;
+
+    /// Build a monitor of the `k` most frequent messages of `histogram`, which alerts once the distance exceeds
+    /// `threshold`. The messages are hashed under `key`, e.g., the key of the context.
+    pub fn new<T>(
+        key: &[u8],
+        histogram: &HashMap<T, usize>,
+        k: usize,
+        threshold: f64,
+    ) -> Result<Self>
+    where
+        T: AsBytes + Hash + Eq + Clone,
+    {
+        if k == 0 || !(threshold > 0.0 && threshold <= 1.0) {
+            return Err(FseError::InvalidParams(format!(
+                "The drift monitor needs k > 0 and a threshold in (0, 1], got {} and {}",
+                k, threshold
+            ))
+            .into());
+        }
+
+        let capacity = k * Self::COUNTERS_PER_ITEM;
+        let n = histogram.values().sum::<usize>().max(1) as f64;
+        let baseline = build_histogram_vec(histogram)
+            .into_iter()
+            .take(capacity)
+            .map(|(message, cnt)| (hash(key, &message), cnt as f64 / n))
+            .collect();
+
+        Ok(Self {
+            key: key.to_vec(),
+            k,
+            threshold,
+            baseline,
+            sketch: SpaceSaving::new(capacity),
+        })
+    }
+
+    /// Observe an inserted message.
+    pub fn observe<T: AsBytes>(&mut self, message: &T) {
+        self.sketch.insert(hash(&self.key, message));
+    }
+
+    pub fn report(&self) -> DriftReport {
+        let total = self.sketch.total();
+        let observed = self
+            .sketch
+            .top(self.k)
+            .into_iter()
+            .map(|(item, count)| (item, count as f64 / total.max(1) as f64))
+            .collect::<HashMap<_, _>>();
+        let mut baseline = self.baseline.iter().collect::<Vec<_>>();
+        baseline.sort_by(|lhs, rhs| rhs.1.total_cmp(lhs.1));
+        let baseline = baseline
+            .into_iter()
+            .take(self.k)
+            .map(|(&item, &freq)| (item, freq))
+            .collect::<HashMap<_, _>>();
+
+        // Nothing is inserted yet, so nothing has drifted.
+        let distance = match total {
+            0 => 0.0,
+            _ => {
+                let items = observed
+                    .keys()
+                    .chain(baseline.keys())
+                    .collect::<HashSet<_>>();
+                items
+                    .into_iter()
+                    .map(|item| {
+                        let expected =
+                            self.baseline.get(item).copied().unwrap_or(0.0);
+                        let found =
+                            self.sketch.estimate(*item) as f64 / total as f64;
+                        (expected - found).abs()
+                    })
+                    .sum::<f64>()
+                    / 2.0
+            }
+        };
+
+        DriftReport {
+            inserted: total,
+            baseline_skew: baseline.values().sum(),
+            observed_skew: observed.values().sum(),
+            distance,
+            alert: distance > self.threshold,
+        }
+    }
+}
+
+impl SizeAllocated for DriftMonitor {
+    fn size_allocated(&self) -> usize {
+        self.sketch.size_allocated()
+            + self.baseline.capacity() * std::mem::size_of::<(u64, f64)>()
+    }
+}
+
+/// The keyed hash of a message.
+fn hash<T: AsBytes>(key: &[u8], message: &T) -> u64 {
+    let digest = hmac(key, &message.to_bytes());
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    u64::from_le_bytes(prefix)
+}
@@ -0,0 +1,176 @@
+//! This module implements the generators of the dummy messages PFSE pads its partitions with. The dummies are stored
+//! as they are, so a server that knows the domain of the column can filter out those that do not look like its
+//! values, e.g., the random strings of [`RandomDummies`] in a column of zip codes, and attack the rest as if no dummy
+//! had been added. A domain-aware generator draws plausible dummies instead:
+//!
+//! - [`FormatDummies`] rewrites a real message character by character, keeping the format of the column;
+//! - [`RangeDummies`] draws numbers between the smallest and the largest real message, e.g., dates as days.
+//!
+//! The dummies are always disjoint from the real messages, since a dummy equal to a message would be looked up as
+//! that message. [`DomainFilter`] is the filter of the server, which measures how realistic the dummies are.
+
+use std::{collections::HashSet, fmt::Debug, hash::Hash, sync::Arc};
+
+use rand::{
+    distributions::{uniform::SampleUniform, Distribution, Uniform},
+    seq::SliceRandom,
+};
+use rand_core::OsRng;
+
+use crate::fse::{AsBytes, FromBytes, Random, DEFAULT_RANDOM_LEN};
+
+/// The number of draws per requested dummy before a generator gives up on a domain that has run out of free values.
+const ATTEMPTS_PER_DUMMY: usize = 64;
+
+/// A generator of the dummies of PFSE. See [`crate::pfse::ContextPFSE::set_dummy_generator`].
+pub trait DummyGenerator<T>: Debug + Send + Sync {
+    /// Draw `num` distinct dummies, none of which is in `support`, i.e., the real messages. Returns fewer if the
+    /// domain runs out of free values; the rest are then drawn by [`RandomDummies`].
+    fn generate(&self, support: &HashSet<&T>, num: usize) -> Vec<T>;
+}
+
+/// Draw distinct values by `draw` that are not in `support` until there are `num` of them or the attempts run out.
+fn draw_distinct<T, F>(support: &HashSet<&T>, num: usize, mut draw: F) -> Vec<T>
+where
+    T: Hash + Eq + Clone,
+    F: FnMut() -> Option<T>,
+{
+    let mut drawn = HashSet::new();
+    let mut res = Vec::with_capacity(num);
+    for _ in 0..num.saturating_mul(ATTEMPTS_PER_DUMMY) {
+        if res.len() == num {
+            break;
+        }
+        let dummy = match draw() {
+            Some(dummy) => dummy,
+            None => break,
+        };
+        if !support.contains(&dummy) && drawn.insert(dummy.clone()) {
+            res.push(dummy);
+        }
+    }
+    res
+}
+
+/// The default generator, which draws [`Random`] messages of [`DEFAULT_RANDOM_LEN`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomDummies;
+
+impl<T> DummyGenerator<T> for RandomDummies
+where
+    T: Random + Hash + Eq + Clone,
+{
+    fn generate(&self, support: &HashSet<&T>, num: usize) -> Vec<T> {
+        draw_distinct(support, num, || Some(T::random(DEFAULT_RANDOM_LEN)))
+    }
+}
+
+/// A generator that picks a real message and replaces each ASCII digit and letter of its encoding by a random one of
+/// the same class, so that the dummy has the length and the format of a real message, e.g., `94107` becomes `38215`.
+/// The other bytes are kept, so a UTF-8 string stays valid.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FormatDummies;
+
+impl FormatDummies {
+    fn rewrite(bytes: &mut [u8]) {
+        let digits = Uniform::new_inclusive(b'0', b'9');
+        let letters = Uniform::new(0, 26u8);
+        for byte in bytes.iter_mut() {
+            *byte = match *byte {
+                b'0'..=b'9' => digits.sample(&mut OsRng),
+                b'a'..=b'z' => b'a' + letters.sample(&mut OsRng),
+                b'A'..=b'Z' => b'A' + letters.sample(&mut OsRng),
+                other => other,
+            };
+        }
+    }
+}
+
+impl<T> DummyGenerator<T> for FormatDummies
+where
+    T: AsBytes + FromBytes + Hash + Eq + Clone,
+{
+    fn generate(&self, support: &HashSet<&T>, num: usize) -> Vec<T> {
+        let messages = support.iter().collect::<Vec<_>>();
+        draw_distinct(support, num, || {
+            let mut bytes = messages.choose(&mut OsRng)?.to_bytes();
+            Self::rewrite(&mut bytes);
+            Some(T::from_bytes(&bytes))
+        })
+    }
+}
+
+/// A generator that draws numbers uniformly between the smallest and the largest real message, e.g., integers or
+/// dates encoded as days.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RangeDummies;
+
+impl<T> DummyGenerator<T> for RangeDummies
+where
+    T: SampleUniform + PartialOrd + Hash + Eq + Clone,
+{
+    fn generate(&self, support: &HashSet<&T>, num: usize) -> Vec<T> {
+        let mut messages = support.iter();
+        let first = match messages.next() {
+            Some(first) => *first,
+            None => return Vec::new(),
+        };
+        let (low, high) =
+            messages.fold((first, first), |(low, high), e| {
+                match (*e < low, *e > high) {
+                    (true, _) => (*e, high),
+                    (_, true) => (low, *e),
+                    _ => (low, high),
+                }
+            });
+        let distribution = Uniform::new_inclusive(low.clone(), high.clone());
+        draw_distinct(support, num, || Some(distribution.sample(&mut OsRng)))
+    }
+}
+
+/// The default generator of a context.
+pub fn default_generator<T>() -> Arc<dyn DummyGenerator<T>>
+where
+    T: Random + Hash + Eq + Clone,
+{
+    Arc::new(RandomDummies)
+}
+
+/// The format of an encoding: each ASCII digit, lowercase and uppercase letter is replaced by its class.
+pub fn shape(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .iter()
+        .map(|byte| match byte {
+            b'0'..=b'9' => b'9',
+            b'a'..=b'z' => b'a',
+            b'A'..=b'Z' => b'A',
+            other => *other,
+        })
+        .collect()
+}
+
+/// The filter of a server that knows the formats of the real messages, e.g., from a public sample of the column. A
+/// message is plausible if its [`shape`] is that of a real message.
+#[derive(Debug, Clone, Default)]
+pub struct DomainFilter {
+    shapes: HashSet<Vec<u8>>,
+}
+
+impl DomainFilter {
+    pub fn new<'a, T, I>(messages: I) -> Self
+    where
+        T: AsBytes + 'a,
+        I: IntoIterator<Item = &'a T>,
+    {
+        Self {
+            shapes: messages
+                .into_iter()
+                .map(|e| shape(&e.to_bytes()))
+                .collect(),
+        }
+    }
+
+    pub fn is_plausible<T: AsBytes>(&self, message: &T) -> bool {
+        self.shapes.contains(&shape(&message.to_bytes()))
+    }
+}
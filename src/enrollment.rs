@@ -0,0 +1,169 @@
+//! This module implements a two-phase workflow that separates the setup of a scheme from its data encryption key.
+//!
+//! In the first phase, an [`Enrollment`] is computed from a *public* histogram of the message dataset. No key is
+//! involved, so the partitions (PFSE) or homophone encodings (LPFSE) can be computed by an untrusted party and shared
+//! with the data owner. In the second phase, the data owner combines the enrollment with its secret key and obtains a
+//! [`SealedContext`] that is ready for encryption and search. The enrollment is shipped to the data owner by
+//! [`Enrollment::export`] and [`Enrollment::import`].
+//!
+//! # Example
+//! ```rust
+//! use fse::{
+//!     cipher::default_cipher,
+//!     enrollment::Enrollment,
+//!     fse::{exponential, PartitionFrequencySmoothing},
+//!     params::PfseParams,
+//!     pfse::ContextPFSE,
+//!     util::build_histogram,
+//! };
+//!
+//! # fn main() -> fse::Result<()> {
+//! let messages = vec!["a".to_string(), "a".to_string(), "b".to_string()];
+//! let histogram = build_histogram(&messages);
+//! // This can be done by anyone who knows the histogram.
+//! let params = PfseParams::new(0.25, 1.0, 0.05);
+//! let enrollment = Enrollment::pfse(&histogram, &params, exponential)?;
+//! let state = enrollment.export();
+//! // This is done by the data owner.
+//! let key = default_cipher().key_generate();
+//! let ctx = ContextPFSE::<String>::default();
+//! let enrollment = Enrollment::import(ctx, &state)?;
+//! let mut ctx = enrollment.seal(&key);
+//! let ciphertexts = ctx.smooth();
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    hash::Hash,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{
+    fse::{
+        AsBytes, BaseCrypto, FromBytes, LocalState,
+        PartitionFrequencySmoothing, Random,
+    },
+    params::{LpfseParams, PfseParams},
+    util::SizeAllocated,
+    Result,
+};
+
+#[cfg(feature = "lpfse")]
+use crate::lpfse::{ContextLPFSE, HomophoneEncoder};
+#[cfg(feature = "pfse")]
+use crate::pfse::ContextPFSE;
+
+/// The keyless result of the setup phase of a scheme. It wraps a context whose parameters have been computed but
+/// which does not hold any key yet.
+#[derive(Debug, Clone)]
+pub struct Enrollment<C> {
+    ctx: C,
+}
+
+/// A context created by combining an [`Enrollment`] with a secret key. It dereferences to the underlying context.
+#[derive(Debug, Clone)]
+pub struct SealedContext<C> {
+    ctx: C,
+}
+
+#[cfg(feature = "pfse")]
+impl<T> Enrollment<ContextPFSE<T>>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + Random + SizeAllocated,
+{
+    /// Compute the partitions and the local table of PFSE from a histogram `T -> count`.
+    pub fn pfse(
+        histogram: &HashMap<T, usize>,
+        params: &PfseParams,
+        partition_func: fn(f64, usize) -> f64,
+    ) -> Result<Self> {
+        let mut ctx = ContextPFSE::default();
+        ctx.set_params(params)?;
+        ctx.partition_histogram(histogram, partition_func)?;
+        ctx.transform();
+
+        Ok(Self { ctx })
+    }
+}
+
+#[cfg(feature = "lpfse")]
+impl<T> Enrollment<ContextLPFSE<T>>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+{
+    /// Compute the homophone encodings of LPFSE from a histogram `T -> count`.
+    pub fn lpfse(
+        histogram: &HashMap<T, usize>,
+        params: &LpfseParams,
+        encoder: Box<dyn HomophoneEncoder<T>>,
+    ) -> Result<Self> {
+        let mut ctx = ContextLPFSE::from_params(params, encoder)?;
+        ctx.initialize_histogram(histogram)?;
+
+        Ok(Self { ctx })
+    }
+}
+
+impl<C> Enrollment<C> {
+    /// Get the underlying keyless context so that its parameters can be inspected.
+    pub fn inner(&self) -> &C {
+        &self.ctx
+    }
+
+    /// Combine the enrollment with a secret key.
+    pub fn seal<T>(self, key: &[u8]) -> SealedContext<C>
+    where
+        C: BaseCrypto<T>,
+        T: AsBytes + FromBytes + Debug,
+    {
+        let mut ctx = self.ctx;
+        ctx.set_key(key);
+
+        SealedContext { ctx }
+    }
+
+    /// Export the enrollment to be shipped to the data owner. See [`LocalState::export_state`].
+    pub fn export<T>(&self) -> Vec<u8>
+    where
+        C: LocalState<T>,
+        T: AsBytes + FromBytes + Debug,
+    {
+        self.ctx.export_state()
+    }
+
+    /// Import an enrollment produced by [`Enrollment::export`] into `ctx`, a fresh context of the same scheme, e.g.,
+    /// with the same homophone encoder for LPFSE. The key is set afterwards by [`Enrollment::seal`].
+    pub fn import<T>(mut ctx: C, state: &[u8]) -> Result<Self>
+    where
+        C: LocalState<T>,
+        T: AsBytes + FromBytes + Debug,
+    {
+        ctx.import_state(state)?;
+
+        Ok(Self { ctx })
+    }
+}
+
+impl<C> SealedContext<C> {
+    /// Consume the sealed context and return the underlying context.
+    pub fn into_inner(self) -> C {
+        self.ctx
+    }
+}
+
+impl<C> Deref for SealedContext<C> {
+    type Target = C;
+
+    fn deref(&self) -> &Self::Target {
+        &self.ctx
+    }
+}
+
+impl<C> DerefMut for SealedContext<C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.ctx
+    }
+}
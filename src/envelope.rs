@@ -0,0 +1,223 @@
+//! This module implements the envelope, a single portable file that carries the key and the local state of a context
+//! so that an experiment or a deployment can be moved to another machine.
+//!
+//! The layout of an envelope is
+//!
+//! ```text
+//! magic | version | scheme | params | salt | iterations | key nonce | key blob | state nonce | state blob | mac
+//! ```
+//!
+//! where every field after the magic is length-prefixed as in [`crate::util::StateWriter`]. The scheme and its
+//! parameters are in the clear so that [`read_header`] can tell what an envelope holds without the passphrase. The
+//! key and the state are encrypted by AES-256-GCM under a key derived from the passphrase by PBKDF2-HMAC-SHA256, and
+//! the whole file is authenticated by HMAC-SHA256 under a second derived key, so a wrong passphrase and a tampered
+//! file are both rejected before anything is imported.
+
+use std::fmt::Debug;
+
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+
+use crate::{
+    cipher::{AesGcmCipher, Cipher, NONCE_LEN},
+    error::FseError,
+    fse::{AsBytes, FromBytes, LocalState},
+    params::SchemeParams,
+    util::{StateReader, StateWriter},
+    FSEType, Result,
+};
+
+/// The first bytes of every envelope.
+pub const MAGIC: &[u8; 6] = b"FSEENV";
+
+/// The version of the layout written by this module.
+pub const VERSION: u64 = 1;
+
+/// The number of PBKDF2 iterations of newly written envelopes. The count is stored in the envelope, so it can be
+/// raised without breaking old files.
+pub const KDF_ITERATIONS: u32 = 100_000;
+
+/// The largest number of PBKDF2 iterations an envelope may ask for. The count is read before the envelope is
+/// authenticated, so it is bounded on both sides: a crafted file can neither stall the import nor weaken the KDF
+/// below [`KDF_ITERATIONS`].
+pub const MAX_KDF_ITERATIONS: u32 = 100 * KDF_ITERATIONS;
+
+const SALT_LEN: usize = 16;
+const MAC_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The part of an envelope that is readable without the passphrase.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvelopeHeader {
+    pub version: u64,
+    /// The name of the scheme as in [`Portable::scheme_name`].
+    pub scheme: String,
+    pub params: Option<SchemeParams>,
+}
+
+/// A context whose key and local state can be moved between machines in an envelope.
+pub trait Portable<T>: LocalState<T>
+where
+    T: AsBytes + FromBytes + Debug,
+{
+    /// The name of the scheme of the context, e.g., [`FSEType::name`]. An envelope can only be imported into a context
+    /// of the same scheme.
+    fn scheme_name(&self) -> &'static str;
+
+    /// The parameters of the context, if the scheme has any.
+    fn scheme_params(&self) -> Option<SchemeParams>;
+
+    /// Seal the key and the local state of the context into an envelope protected by `passphrase`.
+    fn export_envelope(&self, passphrase: &str) -> Result<Vec<u8>> {
+        let mut salt = vec![0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let (enc_key, mac_key) = derive_keys(passphrase, &salt, KDF_ITERATIONS);
+        let params = match self.scheme_params() {
+            Some(params) => serde_json::to_vec(&params)?,
+            None => Vec::new(),
+        };
+
+        let mut writer = StateWriter::new();
+        writer.put_u64(VERSION);
+        writer.put_bytes(self.scheme_name().as_bytes());
+        writer.put_bytes(&params);
+        writer.put_bytes(&salt);
+        writer.put_u64(KDF_ITERATIONS as u64);
+        for blob in [self.get_key().to_vec(), self.export_state()] {
+            let mut nonce = vec![0u8; NONCE_LEN];
+            OsRng.fill_bytes(&mut nonce);
+            let ciphertext = AesGcmCipher
+                .encrypt(&enc_key, &nonce, &blob)
+                .ok_or("Cannot encrypt the envelope.")?;
+            writer.put_bytes(&nonce);
+            writer.put_bytes(&ciphertext);
+        }
+
+        let mut envelope = MAGIC.to_vec();
+        envelope.extend_from_slice(&writer.finish());
+        let mut mac = HmacSha256::new_from_slice(&mac_key)?;
+        mac.update(&envelope);
+        envelope.extend_from_slice(&mac.finalize().into_bytes());
+        Ok(envelope)
+    }
+
+    /// Replace the key and the local state of the context by those sealed in `envelope`.
+    fn import_envelope(
+        &mut self,
+        envelope: &[u8],
+        passphrase: &str,
+    ) -> Result<()> {
+        let header = read_header(envelope)?;
+        if header.scheme != self.scheme_name() {
+            return Err(invalid(&format!(
+                "the envelope holds a {} context, not {}",
+                header.scheme,
+                self.scheme_name()
+            )));
+        }
+
+        let (body, tag) = envelope.split_at(envelope.len() - MAC_LEN);
+        let mut reader = StateReader::new(&body[MAGIC.len()..]);
+        let malformed = || invalid("malformed envelope");
+        // The version, the scheme and the parameters have been checked by `read_header`.
+        reader.get_u64().ok_or_else(malformed)?;
+        reader.get_bytes().ok_or_else(malformed)?;
+        reader.get_bytes().ok_or_else(malformed)?;
+        let salt = reader.get_bytes().ok_or_else(malformed)?;
+        let iterations = u32::try_from(reader.get_u64().ok_or_else(malformed)?)
+            .map_err(|_| malformed())?;
+        if !(KDF_ITERATIONS..=MAX_KDF_ITERATIONS).contains(&iterations) {
+            return Err(invalid(&format!(
+                "the envelope asks for {} KDF iterations, outside [{}, {}]",
+                iterations, KDF_ITERATIONS, MAX_KDF_ITERATIONS
+            )));
+        }
+
+        let (enc_key, mac_key) = derive_keys(passphrase, salt, iterations);
+        let mut mac = HmacSha256::new_from_slice(&mac_key)?;
+        mac.update(body);
+        mac.verify_slice(tag)
+            .map_err(|_| invalid("wrong passphrase or corrupted envelope"))?;
+
+        let mut blobs = Vec::new();
+        for _ in 0..2 {
+            let nonce = reader.get_bytes().ok_or_else(malformed)?;
+            let ciphertext = reader.get_bytes().ok_or_else(malformed)?;
+            blobs.push(
+                AesGcmCipher
+                    .decrypt(&enc_key, nonce, ciphertext)
+                    .ok_or_else(malformed)?,
+            );
+        }
+        if !reader.is_empty() {
+            return Err(malformed());
+        }
+
+        // Import the state first so that a malformed state leaves the key untouched.
+        self.import_state(&blobs[1])?;
+        self.set_key(&blobs[0]);
+        Ok(())
+    }
+
+    /// Write the envelope of the context to `path`. See [`Portable::export_envelope`].
+    fn export(&self, path: &str, passphrase: &str) -> Result<()> {
+        std::fs::write(path, self.export_envelope(passphrase)?)?;
+        Ok(())
+    }
+
+    /// Read the envelope at `path` into the context. See [`Portable::import_envelope`].
+    fn import(&mut self, path: &str, passphrase: &str) -> Result<()> {
+        self.import_envelope(&std::fs::read(path)?, passphrase)
+    }
+}
+
+/// Read the unencrypted header of an envelope. The integrity of the header is only checked on import.
+pub fn read_header(envelope: &[u8]) -> Result<EnvelopeHeader> {
+    if envelope.len() < MAGIC.len() + MAC_LEN
+        || &envelope[..MAGIC.len()] != MAGIC
+    {
+        return Err(invalid("not an envelope"));
+    }
+
+    let mut reader =
+        StateReader::new(&envelope[MAGIC.len()..envelope.len() - MAC_LEN]);
+    let malformed = || invalid("malformed envelope");
+    let version = reader.get_u64().ok_or_else(malformed)?;
+    if version != VERSION {
+        return Err(invalid(&format!("unsupported version {}", version)));
+    }
+    let scheme =
+        String::from_utf8(reader.get_bytes().ok_or_else(malformed)?.to_vec())?;
+    let params = match reader.get_bytes().ok_or_else(malformed)? {
+        [] => None,
+        params => Some(serde_json::from_slice(params)?),
+    };
+
+    Ok(EnvelopeHeader {
+        version,
+        scheme,
+        params,
+    })
+}
+
+/// Derive the encryption key and the MAC key from the passphrase.
+fn derive_keys(
+    passphrase: &str,
+    salt: &[u8],
+    iterations: u32,
+) -> (Vec<u8>, Vec<u8>) {
+    let mut okm = [0u8; 64];
+    pbkdf2::pbkdf2_hmac::<Sha256>(
+        passphrase.as_bytes(),
+        salt,
+        iterations,
+        &mut okm,
+    );
+    (okm[..32].to_vec(), okm[32..].to_vec())
+}
+
+fn invalid(reason: &str) -> Box<dyn std::error::Error> {
+    Box::new(FseError::InvalidEnvelope(reason.to_string()))
+}
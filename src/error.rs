@@ -0,0 +1,141 @@
+//! This module defines the typed errors of the library. They are boxed into [`crate::Result`] like any other error,
+//! so callers that care about the reason can recover it via `downcast_ref::<FseError>()`.
+
+use std::{error::Error, fmt::Display, time::Duration};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FseError {
+    /// The collection was populated from a different dataset than the one the context was built from.
+    StaleContext {
+        collection: String,
+        expected: String,
+        found: String,
+    },
+    /// The collection does not carry a dataset fingerprint.
+    MissingFingerprint(String),
+    /// The context has not been built from a dataset yet.
+    NotInitialized,
+    /// The parameters of a scheme are out of range.
+    InvalidParams(String),
+    /// The load has already been inserted into the collection.
+    DuplicateLoad { collection: String, load_id: String },
+    /// A database operation kept failing with transient errors until the retry policy gave up.
+    RetriesExhausted {
+        operation: String,
+        attempts: u32,
+        reason: String,
+    },
+    /// A journal entry was skipped, so the replayed state would be inconsistent.
+    JournalGap { expected: u64, found: u64 },
+    /// The operation is not allowed in the current phase of an encoder.
+    InvalidPhase { operation: String, phase: String },
+    /// The envelope cannot be imported.
+    InvalidEnvelope(String),
+    /// The ciphertext was not produced under the key of the context.
+    KeyMismatch,
+    /// The ciphertext cannot be decrypted, e.g., because it was tampered with.
+    DecryptionFailed,
+    /// The context cannot be built from an empty dataset.
+    EmptyDataset,
+    /// A database operation did not finish within the timeout of the connector.
+    Timeout {
+        operation: String,
+        timeout: Duration,
+    },
+    /// A database operation was cancelled by the cancellation token of the connector.
+    Cancelled { operation: String },
+    /// A record of a CSV file cannot be parsed or decoded.
+    MalformedRecord { line: u64, reason: String },
+    /// A search needs more tokens than the limit of the context.
+    TooManyTokens { tokens: usize, limit: usize },
+    /// The scheme is not compiled in, i.e., its cargo feature is disabled.
+    SchemeDisabled { scheme: String, feature: String },
+}
+
+impl Display for FseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::StaleContext {
+                collection,
+                expected,
+                found,
+            } => write!(
+                f,
+                "The context is stale for {}: expected fingerprint {}, found {}.",
+                collection, expected, found
+            ),
+            Self::MissingFingerprint(collection) => {
+                write!(f, "No dataset fingerprint found in {}.", collection)
+            }
+            Self::NotInitialized => {
+                write!(f, "The context is not initialized from a dataset.")
+            }
+            Self::InvalidParams(reason) => {
+                write!(f, "Invalid parameters: {}.", reason)
+            }
+            Self::DuplicateLoad {
+                collection,
+                load_id,
+            } => write!(
+                f,
+                "The load {} has already been inserted into {}.",
+                load_id, collection
+            ),
+            Self::RetriesExhausted {
+                operation,
+                attempts,
+                reason,
+            } => write!(
+                f,
+                "The {} operation failed after {} attempts: {}.",
+                operation, attempts, reason
+            ),
+            Self::JournalGap { expected, found } => write!(
+                f,
+                "Gap in the journal: expected entry {}, found {}.",
+                expected, found
+            ),
+            Self::InvalidPhase { operation, phase } => write!(
+                f,
+                "Cannot {} while the encoder is in the {} phase.",
+                operation, phase
+            ),
+            Self::InvalidEnvelope(reason) => {
+                write!(f, "Invalid envelope: {}.", reason)
+            }
+            Self::KeyMismatch => write!(
+                f,
+                "The ciphertext was encrypted under a different key."
+            ),
+            Self::DecryptionFailed => {
+                write!(f, "The ciphertext cannot be decrypted.")
+            }
+            Self::EmptyDataset => {
+                write!(f, "The context cannot be built from an empty dataset.")
+            }
+            Self::Timeout { operation, timeout } => write!(
+                f,
+                "The {} operation did not finish within {:?}.",
+                operation, timeout
+            ),
+            Self::Cancelled { operation } => {
+                write!(f, "The {} operation was cancelled.", operation)
+            }
+            Self::MalformedRecord { line, reason } => {
+                write!(f, "Malformed record at line {}: {}.", line, reason)
+            }
+            Self::TooManyTokens { tokens, limit } => write!(
+                f,
+                "The search needs {} tokens, more than the limit of {}.",
+                tokens, limit
+            ),
+            Self::SchemeDisabled { scheme, feature } => write!(
+                f,
+                "The {} scheme is disabled; enable the `{}` feature.",
+                scheme, feature
+            ),
+        }
+    }
+}
+
+impl Error for FseError {}
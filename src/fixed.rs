@@ -0,0 +1,153 @@
+//! This module implements a fixed-point decimal plaintext. A floating-point number cannot be a message as it is
+//! neither `Eq` nor `Hash`, and equal values may have distinct bit patterns (e.g., `0.0` and `-0.0`). A fixed-point
+//! number with `SCALE` fractional digits is stored as an integer count of `10^-SCALE`, so every value has a single
+//! encoding.
+
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::FseError,
+    fse::{AsBytes, FromBytes, Random},
+    util::SizeAllocated,
+};
+
+/// A decimal number with `SCALE` fractional digits, e.g., `FixedPoint<2>` holds prices in cents. `SCALE` is at most
+/// 18 so that `10^SCALE` fits in an `i64`.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Default,
+    Serialize,
+    Deserialize,
+)]
+#[serde(transparent)]
+pub struct FixedPoint<const SCALE: u32>(i64);
+
+impl<const SCALE: u32> FixedPoint<SCALE> {
+    /// The number of units in one, i.e., `10^SCALE`.
+    pub const FACTOR: i64 = 10i64.pow(SCALE);
+
+    /// Construct the number `raw * 10^-SCALE`.
+    pub fn from_raw(raw: i64) -> Self {
+        Self(raw)
+    }
+
+    /// The number of `10^-SCALE` units.
+    pub fn raw(&self) -> i64 {
+        self.0
+    }
+
+    /// Round `value` to the nearest number with `SCALE` fractional digits. Returns `None` if it is not finite or out
+    /// of range.
+    pub fn from_f64(value: f64) -> Option<Self> {
+        let raw = (value * Self::FACTOR as f64).round();
+        match raw.is_finite() && raw.abs() < i64::MAX as f64 {
+            true => Some(Self(raw as i64)),
+            false => None,
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0 as f64 / Self::FACTOR as f64
+    }
+}
+
+impl<const SCALE: u32> Display for FixedPoint<SCALE> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let integer = self.0.unsigned_abs() / Self::FACTOR as u64;
+        let fraction = self.0.unsigned_abs() % Self::FACTOR as u64;
+        match SCALE {
+            0 => write!(f, "{}{}", sign, integer),
+            _ => write!(
+                f,
+                "{}{}.{:0width$}",
+                sign,
+                integer,
+                fraction,
+                width = SCALE as usize
+            ),
+        }
+    }
+}
+
+/// Parses a decimal such as `-12.5`. More than `SCALE` fractional digits are rejected rather than rounded, as two
+/// distinct inputs would otherwise become the same message.
+impl<const SCALE: u32> FromStr for FixedPoint<SCALE> {
+    type Err = FseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            FseError::InvalidParams(format!(
+                "{} is not a decimal with at most {} fractional digits",
+                s, SCALE
+            ))
+        };
+
+        let (negative, digits) = match s.trim().strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.trim()),
+        };
+        let (integer, fraction) =
+            digits.split_once('.').unwrap_or((digits, ""));
+        let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if integer.is_empty()
+            || !is_digits(integer)
+            || !is_digits(fraction)
+            || fraction.len() > SCALE as usize
+        {
+            return Err(invalid());
+        }
+
+        let padding = 10i64.pow(SCALE - fraction.len() as u32);
+        let fraction = match fraction.is_empty() {
+            true => 0,
+            false => fraction.parse::<i64>().map_err(|_| invalid())?,
+        };
+        let raw = integer
+            .parse::<i64>()
+            .ok()
+            .and_then(|integer| integer.checked_mul(Self::FACTOR))
+            .and_then(|integer| integer.checked_add(fraction * padding))
+            .ok_or_else(invalid)?;
+
+        Ok(Self(if negative { -raw } else { raw }))
+    }
+}
+
+/// Encoded as the little-endian bytes of the raw value.
+impl<const SCALE: u32> AsBytes for FixedPoint<SCALE> {
+    fn byte_len(&self) -> usize {
+        self.0.byte_len()
+    }
+
+    fn write_bytes(&self, buf: &mut [u8]) {
+        self.0.write_bytes(buf)
+    }
+}
+
+impl<const SCALE: u32> FromBytes for FixedPoint<SCALE> {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self(i64::from_bytes(bytes))
+    }
+}
+
+impl<const SCALE: u32> Random for FixedPoint<SCALE> {
+    fn random(len: usize) -> Self {
+        Self(i64::random(len))
+    }
+}
+
+impl<const SCALE: u32> SizeAllocated for FixedPoint<SCALE> {
+    fn size_allocated(&self) -> usize {
+        std::mem::size_of::<Self>()
+    }
+}
@@ -1,16 +1,50 @@
 //! This module mainly defines a trait called `FrequencySmoothing` that should be implemented for any struct that tries to act like `FSE`.
 
-use std::{f64::consts::E, fmt::Debug, fs::File, io::Write};
+use std::{
+    collections::HashMap,
+    f64::consts::E,
+    fmt::Debug,
+    fs::{File, OpenOptions},
+    io::Write,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, SyncSender},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
 use itertools::Itertools;
 use log::{debug, error};
-use mongodb::bson::Document;
+#[cfg(feature = "db-mongo")]
+use mongodb::bson::{doc, Document};
+use rand::{seq::SliceRandom, Rng};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use crate::{
-    db::{Connector, Data},
-    util::SizeAllocated,
+    audit::AuditLog,
+    cipher::{Cipher, SecurityLevel, NONCE_LEN},
+    error::FseError,
+    journal::{
+        decode_records, encode_record, Checkpoint, Journal, JournalEntry,
+        JournalOp,
+    },
+    keys::{derive_column_key, KeyProvider},
+    progress::{Phase, Progress},
+    token::TokenSet,
+    util::{
+        keyed_fingerprint, to_hex, SizeAllocated, StateReader, StateWriter,
+    },
+    Result,
 };
 
+#[cfg(feature = "db-mongo")]
+use crate::db::{to_binary, Connector, Data, SearchCursor};
+
 pub type HistType<T> = (T, usize);
 pub type FreqType<T> = (T, f64);
 pub type ValueType = (usize, usize, usize);
@@ -29,9 +63,21 @@ pub trait Random {
     fn random(len: usize) -> Self;
 }
 
-/// A trait that defines `as_bytes` method.
+/// A trait that encodes a message into bytes. The ciphertexts, tokens and digests are computed over the encoding, so
+/// it must be injective, i.e., two messages share an encoding only if they are equal.
 pub trait AsBytes {
-    fn as_bytes(&self) -> &[u8];
+    /// The length of the encoding.
+    fn byte_len(&self) -> usize;
+
+    /// Write the encoding into the first [`AsBytes::byte_len`] bytes of `buf`. Panics if `buf` is shorter.
+    fn write_bytes(&self, buf: &mut [u8]);
+
+    /// The encoding as an owned buffer.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; self.byte_len()];
+        self.write_bytes(&mut buf);
+        buf
+    }
 }
 
 /// A trait that defines `from_bytes` method.
@@ -41,6 +87,7 @@ pub trait FromBytes {
 
 /// A trait that defines conector method.
 pub trait Conn {
+    #[cfg(feature = "db-mongo")]
     fn get_conn(&self) -> &Connector<Data>;
 }
 
@@ -53,12 +100,71 @@ where
     /// Given a security parameter, generate a secret key.
     fn key_generate(&mut self);
 
+    /// Install an existing secret key, e.g., when sealing an [`crate::enrollment::Enrollment`].
+    fn set_key(&mut self, key: &[u8]);
+
+    /// Get the secret key of the context.
+    fn get_key(&self) -> &[u8];
+
+    /// Install the key `name` of `provider` instead of generating one, so that the key is only persisted wrapped by
+    /// the provider, e.g., a KMS.
+    fn key_from_provider(
+        &mut self,
+        provider: &dyn KeyProvider,
+        name: &str,
+    ) -> Result<()> {
+        self.set_key(
+            &provider.get_key_of_len(name, self.get_cipher().key_len())?,
+        );
+        Ok(())
+    }
+
+    /// Install the key of `column` derived from `key`, the key shared by the columns of a table, instead of `key`
+    /// itself. See [`crate::keys::derive_column_key`]. Every key the context derives, e.g., for its local table, is
+    /// derived from the key of the column in turn.
+    fn set_column_key(&mut self, key: &[u8], column: &str) -> Result<()> {
+        let key = derive_column_key(key, column, self.get_cipher().key_len())?;
+        self.set_key(&key);
+        Ok(())
+    }
+
+    /// Replace the cipher of the context. The key must be (re)generated afterwards.
+    fn set_cipher(&mut self, cipher: Box<dyn Cipher>);
+
+    /// Replace the cipher of the context by the one of `level`. The key must be (re)generated afterwards.
+    fn set_security_level(&mut self, level: SecurityLevel) {
+        self.set_cipher(level.cipher());
+    }
+
+    /// Get the cipher of the context.
+    fn get_cipher(&self) -> &dyn Cipher;
+
+    /// Bound the number of tokens each search sends to the server. `None` lifts the bound.
+    fn set_token_limit(&mut self, limit: Option<TokenLimit>);
+
+    /// Get the bound on the number of tokens of each search, if any.
+    fn get_token_limit(&self) -> Option<&TokenLimit>;
+
+    /// The number of tokens [`BaseCrypto::search_tokens`] would generate for `message`, if the context can tell it
+    /// from its local table without generating them. `None` if it cannot.
+    fn token_count(&self, message: &T) -> Option<usize> {
+        None
+    }
+
     /// Encrypt the message and return the ciphertext vector. Return `None` if error occurrs.
     fn encrypt(&mut self, message: &T) -> Option<Vec<Vec<u8>>>;
 
     /// Decrypt the ciphertext and return the plaintext. Return `None` if error occurrs.
     fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>>;
 
+    /// Decrypt the ciphertext like [`BaseCrypto::decrypt`], but tell why it fails: [`FseError::KeyMismatch`] if the
+    /// ciphertext was produced under another key, and [`FseError::DecryptionFailed`] otherwise.
+    fn decrypt_checked(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        self.get_cipher().verify_key(self.get_key(), ciphertext)?;
+        self.decrypt(ciphertext)
+            .ok_or_else(|| FseError::DecryptionFailed.into())
+    }
+
     /// Store the summary of the current context into a given file.
     fn store(&self, path: &str) -> std::io::Result<()> {
         let mut file = File::create(path)?;
@@ -69,64 +175,751 @@ where
         )
     }
 
-    fn search_impl(
+    /// Search the given ciphertexts in the collection `name` and collect all the decrypted results.
+    #[cfg(feature = "db-mongo")]
+    fn search_impl(&self, ciphertexts: TokenSet, name: &str) -> Option<Vec<T>> {
+        let res = match self
+            .search_iter(ciphertexts, name)
+            .collect::<Result<Vec<_>>>()
+        {
+            Ok(res) => res,
+            Err(e) => {
+                error!("Error: {:?}", e);
+                return None;
+            }
+        };
+        debug!("Matched document: {}.", res.len());
+
+        Some(res)
+    }
+
+    /// Search the given ciphertexts in the collection `name` and return a lazy iterator over the decrypted results.
+    /// The tokens are sent in chunks, and the next chunk is only queried when the cursor of the previous one is
+    /// drained, so the caller can stop early without fetching every match.
+    #[cfg(feature = "db-mongo")]
+    fn search_iter(
         &self,
-        ciphertexts: Vec<Vec<u8>>,
+        ciphertexts: TokenSet,
         name: &str,
-    ) -> Option<Vec<T>> {
+    ) -> SearchResults<'_, T> {
         debug!("Generated {} tokens.", ciphertexts.len());
 
-        let query_result = ciphertexts
-            .into_iter()
-            .map(|e| {
-                let mut document = Document::new();
-                document
-                    .insert("data".to_string(), String::from_utf8(e).unwrap());
-                document
-            })
-            .collect::<Vec<_>>();
+        let filters = token_filters(&ciphertexts);
+        SearchResults {
+            conn: self.get_conn(),
+            decrypt: Box::new(move |ciphertext| {
+                self.decrypt_checked(ciphertext)
+            }),
+            name: name.to_string(),
+            filters: filters.into_iter(),
+            cursor: None,
+            _marker: PhantomData,
+        }
+    }
 
-        let mut res = Vec::new();
-        for encrypted_message in query_result.chunks(4096) {
-            let mut filter = Document::new();
-            filter.insert("$or", encrypted_message);
+    /// Generate the search tokens, i.e., all the ciphertexts that may encrypt the given message.
+    fn search_tokens(&mut self, message: &T) -> Option<TokenSet> {
+        self.encrypt(message).map(TokenSet::from)
+    }
 
-            let data = match self.get_conn().search(filter, name) {
-                Ok(cursor) => cursor,
-                Err(e) => {
-                    error!("Error: {:?}", e);
-                    return None;
+    /// Generate the search tokens of `message` under the token limit of the context. A message with more tokens
+    /// than the limit is rejected with [`FseError::TooManyTokens`], before its tokens are generated if
+    /// [`BaseCrypto::token_count`] knows their number, or gets a uniform sample of them under
+    /// [`TokenOverflow::Sample`].
+    fn query_tokens(&mut self, message: &T) -> Result<QueryTokens> {
+        let limit = self.get_token_limit().copied();
+        if let (Some(limit), Some(count)) = (limit, self.token_count(message)) {
+            if limit.overflow == TokenOverflow::Reject
+                && count > limit.max_tokens_per_query
+            {
+                return Err(FseError::TooManyTokens {
+                    tokens: count,
+                    limit: limit.max_tokens_per_query,
                 }
+                .into());
             }
-            .into_iter()
+        }
+
+        let tokens = self
+            .search_tokens(message)
+            .ok_or("The message has no search tokens.")?;
+        let total = tokens.len();
+        match limit {
+            Some(limit) if total > limit.max_tokens_per_query => {
+                match limit.overflow {
+                    TokenOverflow::Reject => Err(FseError::TooManyTokens {
+                        tokens: total,
+                        limit: limit.max_tokens_per_query,
+                    }
+                    .into()),
+                    TokenOverflow::Sample => {
+                        let tokens = tokens
+                            .iter()
+                            .collect::<Vec<_>>()
+                            .choose_multiple(
+                                &mut OsRng,
+                                limit.max_tokens_per_query,
+                            )
+                            .map(|token| token.to_vec())
+                            .collect();
+                        Ok(QueryTokens { tokens, total })
+                    }
+                }
+            }
+            _ => Ok(QueryTokens { tokens, total }),
+        }
+    }
+
+    /// Search a given message `T` from the remote server under the token limit of the context, telling why it
+    /// fails. The results of a sampled query come with their expected recall.
+    #[cfg(feature = "db-mongo")]
+    fn search_checked(
+        &mut self,
+        message: &T,
+        name: &str,
+    ) -> Result<SearchOutcome<T>> {
+        let query = self.query_tokens(message)?;
+        debug!(
+            "Searching {:?}: Ciphertext size = {} of {}",
+            message,
+            query.tokens.len(),
+            query.total
+        );
+        let recall = query.recall();
+        let results = self
+            .search_iter(query.tokens, name)
+            .collect::<Result<Vec<_>>>()?;
+        debug!("Matched document: {}.", results.len());
+
+        Ok(SearchOutcome { results, recall })
+    }
+
+    /// Search a given message `T` from the remote server like [`BaseCrypto::search_checked`] and time each of its
+    /// steps. If `explain` is set, the queries are explained afterwards to tell the server time from the network; the
+    /// explain is not part of any step.
+    #[cfg(feature = "db-mongo")]
+    fn search_timed(
+        &mut self,
+        message: &T,
+        name: &str,
+        explain: bool,
+    ) -> Result<(Vec<T>, SearchTiming)> {
+        let mut timing = SearchTiming::default();
+        let instant = Instant::now();
+        let tokens = self.query_tokens(message)?.tokens;
+        timing.tokens = instant.elapsed();
+
+        let instant = Instant::now();
+        let filters = token_filters(&tokens);
+        timing.serialization = instant.elapsed();
+
+        let conn = self.get_conn();
+        let queries = filters.clone();
+        let mut documents = Vec::new();
+        let instant = Instant::now();
+        for filter in queries {
+            for data in conn.search(filter, name)? {
+                documents.push(data?);
+            }
+        }
+        timing.round_trip = instant.elapsed();
+
+        let instant = Instant::now();
+        let results = documents
+            .iter()
             .map(|data| {
-                let message_bytes = self
-                    .decrypt(data.unwrap().data.as_bytes())
-                    .unwrap_or_default();
-                T::from_bytes(&message_bytes)
+                self.decrypt_checked(data.as_ref())
+                    .map(|message_bytes| T::from_bytes(&message_bytes))
             })
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>>>()?;
+        timing.decryption = instant.elapsed();
 
-            res.push(data);
+        if explain {
+            let mut server = Duration::ZERO;
+            for filter in filters {
+                server += conn.execution_time(filter, name)?;
+            }
+            timing.server = Some(server);
         }
-        let res = res.into_iter().flatten().collect::<Vec<_>>();
-        debug!("Matched document: {}.", res.len());
-
-        Some(res)
+        Ok((results, timing))
     }
 
     /// Search a given message `T` from the remote server.
+    #[cfg(feature = "db-mongo")]
     fn search(&mut self, message: &T, name: &str) -> Option<Vec<T>> {
-        let ciphertexts = match self.encrypt(message) {
-            Some(v) => v,
-            None => return None,
+        let query = match self.query_tokens(message) {
+            Ok(query) => query,
+            Err(e) => {
+                error!("Error: {}", e);
+                return None;
+            }
         };
         debug!(
             "Searching {:?}: Ciphertext size = {}",
             message,
+            query.tokens.len()
+        );
+        self.search_impl(query.tokens, name)
+    }
+
+    /// Search a given message `T` from the remote server and stream the results lazily. See [`BaseCrypto::search_iter`].
+    #[cfg(feature = "db-mongo")]
+    fn search_stream(
+        &mut self,
+        message: &T,
+        name: &str,
+    ) -> Option<SearchResults<'_, T>> {
+        let query = self.query_tokens(message).ok()?;
+        Some(self.search_iter(query.tokens, name))
+    }
+
+    /// Search a given message `T` but stop once `k` results are decrypted, e.g., for a query with a `LIMIT`. The
+    /// chunks of tokens are queried concurrently by as many workers as there are chunks, up to the available
+    /// parallelism, and the outstanding queries are cancelled once the limit is reached. Which `k` of the matches are
+    /// returned depends on the order in which the queries answer.
+    #[cfg(feature = "db-mongo")]
+    fn search_limit(
+        &mut self,
+        message: &T,
+        name: &str,
+        k: usize,
+    ) -> Result<LimitedSearch<T>> {
+        if k == 0 {
+            return Err("The limit must be positive.".into());
+        }
+        let tokens = self.query_tokens(message)?.tokens;
+        debug!(
+            "Searching {:?} with limit {}: Ciphertext size = {}",
+            message,
+            k,
+            tokens.len()
+        );
+
+        let filters = token_filters(&tokens);
+        let workers = thread::available_parallelism()
+            .map_or(1, usize::from)
+            .min(filters.len());
+        let filters = Mutex::new(filters.into_iter());
+        let stop = AtomicBool::new(false);
+        let this = &*self;
+        let conn = this.get_conn();
+
+        thread::scope(|s| {
+            let (sender, receiver) = mpsc::sync_channel(k);
+            let handles = (0..workers)
+                .map(|_| {
+                    let sender = sender.clone();
+                    let (filters, stop) = (&filters, &stop);
+                    s.spawn(move || {
+                        drain_chunks(conn, name, filters, stop, sender)
+                    })
+                })
+                .collect::<Vec<_>>();
+            drop(sender);
+
+            let mut results = Vec::with_capacity(k);
+            for ciphertext in receiver.iter() {
+                let message_bytes = match ciphertext
+                    .map_err(|e| e.into())
+                    .and_then(|ciphertext| this.decrypt_checked(&ciphertext))
+                {
+                    Ok(message_bytes) => message_bytes,
+                    Err(e) => {
+                        stop.store(true, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                };
+                results.push(T::from_bytes(&message_bytes));
+                if results.len() == k {
+                    stop.store(true, Ordering::Relaxed);
+                    break;
+                }
+            }
+
+            // A worker blocked on the channel is released once the receiver is dropped. The workers left unjoined are
+            // joined by the scope.
+            let pending = receiver.try_recv().is_ok();
+            drop(receiver);
+            let drained =
+                handles.into_iter().all(|handle| handle.join().unwrap());
+            debug!("Matched document: {}.", results.len());
+
+            Ok(LimitedSearch {
+                results,
+                truncated: pending || !drained,
+            })
+        })
+    }
+
+    /// Search a given message `T` but only return a uniform random sample of at most `k` matching documents. The
+    /// sampling is done by the server (see [`Connector::sample`]), so the client does not fetch every match of a
+    /// frequent message. The tokens are queried in chunks like [`BaseCrypto::search`]: each chunk is sampled on its
+    /// own and the samples are merged by drawing the `k` documents among the chunks in proportion to their matches,
+    /// which keeps the merged sample uniform. Returns None if `k` is 0.
+    #[cfg(feature = "db-mongo")]
+    fn search_sample(
+        &mut self,
+        message: &T,
+        name: &str,
+        k: usize,
+    ) -> Option<Vec<T>> {
+        if k == 0 {
+            error!("Cannot sample zero documents.");
+            return None;
+        }
+        let ciphertexts = self.query_tokens(message).ok()?.tokens;
+        debug!(
+            "Sampling {} of {:?}: Ciphertext size = {}",
+            k,
+            message,
             ciphertexts.len()
         );
-        self.search_impl(ciphertexts, name)
+
+        let mut samples = Vec::new();
+        for filter in token_filters(&ciphertexts) {
+            match self.get_conn().sample(filter, k, name) {
+                Ok(sample) => samples.push(sample),
+                Err(e) => {
+                    error!("Error: {:?}", e);
+                    return None;
+                }
+            }
+        }
+
+        // Draw the documents one by one without replacement: each falls into a chunk with probability proportional
+        // to the matches of the chunk not drawn yet. The sample of a chunk is uniform and holds at least as many
+        // documents as are drawn from it.
+        let mut remaining = samples.iter().map(|e| e.1).collect::<Vec<_>>();
+        let mut total = remaining.iter().sum::<usize>();
+        let mut drawn = vec![0usize; samples.len()];
+        for _ in 0..k.min(total) {
+            let mut pick = OsRng.gen_range(0..total);
+            let chunk = remaining
+                .iter()
+                .position(|&n| match pick < n {
+                    true => true,
+                    false => {
+                        pick -= n;
+                        false
+                    }
+                })
+                .unwrap();
+            remaining[chunk] -= 1;
+            drawn[chunk] += 1;
+            total -= 1;
+        }
+        let mut data = samples
+            .into_iter()
+            .zip(drawn)
+            .flat_map(|((sample, _), drawn)| sample.into_iter().take(drawn))
+            .collect::<Vec<_>>();
+        data.shuffle(&mut OsRng);
+
+        let mut res = Vec::with_capacity(data.len());
+        for data in data {
+            match self.decrypt_checked(data.as_ref()) {
+                Ok(message_bytes) => res.push(T::from_bytes(&message_bytes)),
+                Err(e) => {
+                    error!("Error: {}", e);
+                    return None;
+                }
+            }
+        }
+
+        Some(res)
+    }
+
+    /// Search a given message `T` and return the results under `policy` as `(plaintext, count)` pairs. Under
+    /// [`ResultPolicy::Raw`] every matching document is a pair with count 1. Otherwise the documents are grouped by
+    /// the server per distinct ciphertext, and the client merges the ciphertexts that decrypt to the same plaintext;
+    /// the pairs are ordered by count.
+    #[cfg(feature = "db-mongo")]
+    fn search_with_policy(
+        &mut self,
+        message: &T,
+        name: &str,
+        policy: ResultPolicy,
+    ) -> Option<Vec<HistType<T>>> {
+        if policy == ResultPolicy::Raw {
+            let res = self.search(message, name)?;
+            return Some(res.into_iter().map(|e| (e, 1)).collect());
+        }
+
+        let ciphertexts = self.query_tokens(message).ok()?.tokens;
+        let matches = match self.get_conn().count_matches(&ciphertexts, name) {
+            Ok(matches) => matches,
+            Err(e) => {
+                error!("Error: {:?}", e);
+                return None;
+            }
+        };
+        debug!("Matched distinct ciphertexts: {}.", matches.len());
+
+        let mut counts = HashMap::<Vec<u8>, usize>::new();
+        for (ciphertext, count) in matches {
+            let message_bytes = match self.decrypt_checked(&ciphertext) {
+                Ok(message_bytes) => message_bytes,
+                Err(e) => {
+                    error!("Error: {}", e);
+                    return None;
+                }
+            };
+            *counts.entry(message_bytes).or_default() += count;
+        }
+        let mut res = counts
+            .into_iter()
+            .map(|(message_bytes, count)| match policy {
+                ResultPolicy::DedupWithCounts => (message_bytes, count),
+                _ => (message_bytes, 1),
+            })
+            .collect::<Vec<_>>();
+        res.sort_by(|lhs, rhs| {
+            rhs.1.cmp(&lhs.1).then_with(|| lhs.0.cmp(&rhs.0))
+        });
+
+        Some(
+            res.into_iter()
+                .map(|(message_bytes, count)| {
+                    (T::from_bytes(&message_bytes), count)
+                })
+                .collect(),
+        )
+    }
+
+    /// Search a given message `T` from the remote server and record the query into the audit log.
+    #[cfg(feature = "db-mongo")]
+    fn search_audited(
+        &mut self,
+        message: &T,
+        name: &str,
+        audit: &mut AuditLog,
+    ) -> Option<Vec<T>> {
+        let ciphertexts = self.query_tokens(message).ok()?.tokens;
+        let token_num = ciphertexts.len();
+        let res = self.search_impl(ciphertexts, name)?;
+
+        if let Err(e) =
+            audit.record(name, &message.to_bytes(), token_num, res.len())
+        {
+            error!("Failed to write the audit log due to {}.", e);
+        }
+
+        Some(res)
+    }
+}
+
+/// How the matches of a search are returned. See [`BaseCrypto::search_with_policy`].
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultPolicy {
+    /// One result per matching document, including the copies introduced by smoothing.
+    #[default]
+    Raw,
+    /// One result per distinct plaintext.
+    Dedup,
+    /// One result per distinct plaintext, with the number of matching documents.
+    DedupWithCounts,
+}
+
+/// What a search does when the tokens of a message exceed [`TokenLimit::max_tokens_per_query`].
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenOverflow {
+    /// Fail with [`FseError::TooManyTokens`].
+    #[default]
+    Reject,
+    /// Send a uniform sample of the tokens, which finds only part of the matches.
+    Sample,
+}
+
+/// The bound on the tokens of a single search, so that the query of a frequent message cannot tie up the server.
+/// See [`BaseCrypto::query_tokens`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenLimit {
+    pub max_tokens_per_query: usize,
+    #[serde(default)]
+    pub overflow: TokenOverflow,
+}
+
+impl TokenLimit {
+    pub fn new(max_tokens_per_query: usize, overflow: TokenOverflow) -> Self {
+        Self {
+            max_tokens_per_query,
+            overflow,
+        }
+    }
+}
+
+/// The tokens of a search under a [`TokenLimit`].
+#[derive(Debug, Clone)]
+pub struct QueryTokens {
+    pub tokens: TokenSet,
+    /// The number of tokens of the message before sampling.
+    pub total: usize,
+}
+
+impl QueryTokens {
+    /// The expected fraction of the matches the tokens find. Smoothing spreads the copies of a message evenly over
+    /// its ciphertexts, so each token is expected to match as many documents as any other.
+    pub fn recall(&self) -> f64 {
+        match self.total {
+            0 => 1.0,
+            total => self.tokens.len() as f64 / total as f64,
+        }
+    }
+}
+
+/// The results of [`BaseCrypto::search_checked`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchOutcome<T> {
+    pub results: Vec<T>,
+    /// The expected fraction of the matches found, which is 1 unless the tokens were sampled.
+    pub recall: f64,
+}
+
+/// The results of [`BaseCrypto::search_limit`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitedSearch<T> {
+    /// At most `k` results.
+    pub results: Vec<T>,
+    /// Whether the search was stopped before every chunk was drained, i.e., there may be more matches.
+    pub truncated: bool,
+}
+
+/// The time a search spent in each of its steps. See [`BaseCrypto::search_timed`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchTiming {
+    /// Generating the tokens under the token limit.
+    pub tokens: Duration,
+    /// Turning the tokens into the BSON filters of the queries.
+    pub serialization: Duration,
+    /// Issuing the queries and draining their cursors, i.e., the network and the server together.
+    pub round_trip: Duration,
+    /// Decrypting the results.
+    pub decryption: Duration,
+    /// The execution time of the queries reported by the server, in whole milliseconds. None ==> not explained.
+    pub server: Option<Duration>,
+}
+
+impl SearchTiming {
+    /// The latency of the whole search.
+    pub fn total(&self) -> Duration {
+        self.tokens + self.serialization + self.round_trip + self.decryption
+    }
+
+    /// The part of the round trip spent outside the server. None ==> not explained.
+    pub fn network(&self) -> Option<Duration> {
+        self.server.map(|e| self.round_trip.saturating_sub(e))
+    }
+}
+
+/// The maximum number of tokens sent in a single query.
+#[cfg(feature = "db-mongo")]
+const SEARCH_CHUNK_SIZE: usize = 4096;
+
+/// The filters of the queries of a search, one per chunk of tokens.
+#[cfg(feature = "db-mongo")]
+pub(crate) fn token_filters(tokens: &TokenSet) -> Vec<Document> {
+    tokens
+        .chunks(SEARCH_CHUNK_SIZE)
+        .map(|chunk| {
+            let documents = chunk
+                .into_iter()
+                .map(|e| doc! { "data": to_binary(e) })
+                .collect::<Vec<_>>();
+            let mut filter = Document::new();
+            filter.insert("$or", documents);
+            filter
+        })
+        .collect()
+}
+
+/// Query the chunks of a limited search one after another and send the matching ciphertexts until `stop` is set or
+/// the receiver is dropped. The errors are sent as strings since [`crate::Result`] cannot cross threads. Returns
+/// whether every chunk the worker took was drained.
+#[cfg(feature = "db-mongo")]
+fn drain_chunks(
+    conn: &Connector<Data>,
+    name: &str,
+    filters: &Mutex<std::vec::IntoIter<Document>>,
+    stop: &AtomicBool,
+    sender: SyncSender<std::result::Result<Vec<u8>, String>>,
+) -> bool {
+    loop {
+        let filter = match filters.lock().unwrap().next() {
+            Some(filter) => filter,
+            None => return true,
+        };
+        if stop.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        let cursor = match conn.search(filter, name) {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                sender.send(Err(e.to_string())).ok();
+                return false;
+            }
+        };
+        for data in cursor {
+            if stop.load(Ordering::Relaxed) {
+                return false;
+            }
+            let ciphertext =
+                data.map(|data| data.data).map_err(|e| e.to_string());
+            if sender.send(ciphertext).is_err() {
+                return false;
+            }
+        }
+    }
+}
+
+/// Decrypts a single ciphertext of the collection.
+#[cfg(feature = "db-mongo")]
+type DecryptFn<'a> = Box<dyn Fn(&[u8]) -> Result<Vec<u8>> + 'a>;
+
+/// A lazy iterator over the decrypted results of a search. It pulls documents from the server cursor on demand and
+/// only issues the query for the next chunk of tokens once the current cursor is exhausted.
+#[cfg(feature = "db-mongo")]
+pub struct SearchResults<'a, T> {
+    conn: &'a Connector<Data>,
+    decrypt: DecryptFn<'a>,
+    name: String,
+    filters: std::vec::IntoIter<Document>,
+    cursor: Option<SearchCursor<Data>>,
+    _marker: PhantomData<T>,
+}
+
+#[cfg(feature = "db-mongo")]
+impl<'a, T> Iterator for SearchResults<'a, T>
+where
+    T: FromBytes,
+{
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(cursor) = self.cursor.as_mut() {
+                match cursor.next() {
+                    Some(Ok(data)) => {
+                        return Some((self.decrypt)(data.as_ref()).map(
+                            |message_bytes| T::from_bytes(&message_bytes),
+                        ));
+                    }
+                    Some(Err(e)) => return Some(Err(e.into())),
+                    None => self.cursor = None,
+                }
+            }
+
+            let filter = self.filters.next()?;
+            match self.conn.search(filter, &self.name) {
+                Ok(cursor) => self.cursor = Some(cursor),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// A smoothed ciphertext set identified by a load id. The id is derived from the multiset of the ciphertexts, not from
+/// their order, so smoothing the same context twice yields the same id even if the output is shuffled.
+#[derive(Debug, Clone)]
+pub struct SmoothedLoad {
+    pub load_id: String,
+    pub ciphertexts: Vec<Vec<u8>>,
+}
+
+impl SmoothedLoad {
+    pub fn new(ciphertexts: Vec<Vec<u8>>) -> Self {
+        let mut sorted = ciphertexts.iter().collect::<Vec<_>>();
+        sorted.sort_unstable();
+        let mut hasher = Sha256::new();
+        for ciphertext in sorted {
+            hasher.update((ciphertext.len() as u64).to_le_bytes());
+            hasher.update(ciphertext);
+        }
+
+        Self {
+            load_id: to_hex(&hasher.finalize()),
+            ciphertexts,
+        }
+    }
+
+    /// Convert the ciphertexts into documents.
+    #[cfg(feature = "db-mongo")]
+    pub fn documents(&self) -> Vec<Data> {
+        self.ciphertexts.iter().cloned().map(Data::new).collect()
+    }
+
+    /// Convert the ciphertexts into documents arranged by `order` and numbered by their insertion sequence.
+    #[cfg(feature = "db-mongo")]
+    pub fn ordered_documents(&self, order: InsertionOrder) -> Vec<Data> {
+        let mut ciphertexts = self.ciphertexts.clone();
+        order.arrange(&mut ciphertexts);
+        ciphertexts
+            .into_iter()
+            .enumerate()
+            .map(|(seq, ciphertext)| Data::with_seq(ciphertext, seq as u64))
+            .collect()
+    }
+
+    /// Insert the load into the collection `name`. See [`Connector::insert_smoothed`].
+    #[cfg(feature = "db-mongo")]
+    pub fn insert(
+        &self,
+        conn: &Connector<Data>,
+        name: &str,
+        force: bool,
+    ) -> Result<()> {
+        conn.insert_smoothed(&self.load_id, self.documents(), name, force)
+    }
+
+    /// Insert the load like [`SmoothedLoad::insert`], but arranged by `order` and with sequence numbers. The load id
+    /// does not depend on the order, so the same load is still refused twice.
+    #[cfg(feature = "db-mongo")]
+    pub fn insert_ordered(
+        &self,
+        conn: &Connector<Data>,
+        name: &str,
+        force: bool,
+        order: InsertionOrder,
+    ) -> Result<()> {
+        let documents = self.ordered_documents(order);
+        conn.insert_smoothed(&self.load_id, documents, name, force)
+    }
+}
+
+/// How the documents of a load are ordered when they are inserted. The server observes the insertion order (by the
+/// sequence numbers, or by the `_id`s otherwise), so inserting a sorted dataset as it is leaks the order of the
+/// plaintexts.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case", tag = "policy")]
+pub enum InsertionOrder {
+    #[default]
+    AsIs,
+    Shuffle,
+    /// Shuffle within consecutive batches of `size`, e.g., when the records are buffered before a flush.
+    BatchedShuffle {
+        size: usize,
+    },
+}
+
+impl InsertionOrder {
+    /// Arrange the items in place.
+    pub fn arrange<E>(&self, items: &mut [E]) {
+        match *self {
+            Self::AsIs => (),
+            Self::Shuffle => items.shuffle(&mut OsRng),
+            Self::BatchedShuffle { size } => items
+                .chunks_mut(size.max(1))
+                .for_each(|batch| batch.shuffle(&mut OsRng)),
+        }
     }
 }
 
@@ -135,18 +928,599 @@ pub trait PartitionFrequencySmoothing<T>: BaseCrypto<T>
 where
     T: AsBytes + FromBytes + Debug,
 {
-    /// Initialize all the parameters.
-    fn set_params(&mut self, params: &[f64]);
+    /// The typed parameters of the scheme.
+    type Params;
+
+    /// Validate and initialize all the parameters.
+    fn set_params(&mut self, params: &Self::Params) -> Result<()>;
 
     /// Given a vector of `T` and a function closure as the partitioning function, this function constructs the partitioned vectors
-    /// containing tuples `(T, usize)` (T and its count).
-    fn partition(&mut self, input: &[T], partition_func: fn(f64, usize) -> f64);
+    /// containing tuples `(T, usize)` (T and its count). Fails with [`FseError::EmptyDataset`] if `input` is empty.
+    fn partition(
+        &mut self,
+        input: &[T],
+        partition_func: fn(f64, usize) -> f64,
+    ) -> Result<()>;
+
+    /// The same as [`PartitionFrequencySmoothing::partition`], but takes a histogram `T -> count` directly so that
+    /// the caller does not need to hold the raw dataset.
+    fn partition_histogram(
+        &mut self,
+        histogram: &HashMap<T, usize>,
+        partition_func: fn(f64, usize) -> f64,
+    ) -> Result<()>;
+
+    /// Transform each partition by duplicating and smoothing each message, and pad it with dummies up to the number
+    /// of ciphertexts required by the advantage. Returns how the dummies were allocated.
+    fn transform(&mut self) -> TransformStats;
 
-    /// Transform each partition by duplicating and smoothing each message.
-    fn transform(&mut self);
+    /// Set the order in which [`PartitionFrequencySmoothing::smooth`] and
+    /// [`PartitionFrequencySmoothing::smooth_insert`] output the ciphertexts.
+    fn set_smooth_order(&mut self, order: InsertionOrder);
+
+    /// Get the order of the smoothed output. See [`PartitionFrequencySmoothing::set_smooth_order`].
+    fn get_smooth_order(&self) -> InsertionOrder;
+
+    /// Report the progress of partitioning, transforming, smoothing and inserting to `progress`.
+    fn set_progress(&mut self, progress: Progress);
+
+    /// Get the progress reporter. See [`PartitionFrequencySmoothing::set_progress`].
+    fn get_progress(&self) -> &Progress;
+
+    /// Smoothes the partitions and outputs the ciphertext set arranged by the order of the context.
+    fn smooth(&mut self) -> Vec<Vec<u8>> {
+        let mut ciphertexts = Vec::new();
+        let order = self.get_smooth_order();
+        if let Err(e) = self.smooth_ordered_into(order, |ciphertext| {
+            ciphertexts.push(ciphertext);
+            Ok(())
+        }) {
+            error!("Error smoothing the partitions due to {:?}.", e);
+        }
+        ciphertexts
+    }
+
+    /// The same as [`PartitionFrequencySmoothing::smooth`], but streams the ciphertexts to `sink` partition by
+    /// partition instead of collecting them, so that only the ciphertexts of one message are held at a time. Stops at
+    /// the first error of `sink`.
+    ///
+    /// The stream is grouped by partition and message and ignores the order of the context, so inserting it as it is
+    /// reveals the partitions to the server. Use [`PartitionFrequencySmoothing::smooth_ordered_into`] to shuffle it.
+    fn smooth_into<F>(&mut self, sink: F) -> Result<()>
+    where
+        F: FnMut(Vec<u8>) -> Result<()>;
+
+    /// Stream the ciphertexts like [`PartitionFrequencySmoothing::smooth_into`], arranged by `order`. A full shuffle
+    /// holds the whole output before the first ciphertext reaches `sink`; a batched shuffle only holds one batch, but
+    /// as the stream is grouped, a batch must span several partitions to hide their boundaries.
+    fn smooth_ordered_into<F>(
+        &mut self,
+        order: InsertionOrder,
+        mut sink: F,
+    ) -> Result<()>
+    where
+        F: FnMut(Vec<u8>) -> Result<()>,
+    {
+        let capacity = match order {
+            InsertionOrder::AsIs => return self.smooth_into(sink),
+            InsertionOrder::Shuffle => usize::MAX,
+            InsertionOrder::BatchedShuffle { size } => size.max(1),
+        };
+
+        let mut buffer = Vec::new();
+        self.smooth_into(|ciphertext| {
+            buffer.push(ciphertext);
+            if buffer.len() < capacity {
+                return Ok(());
+            }
+            order.arrange(&mut buffer);
+            buffer.drain(..).try_for_each(&mut sink)
+        })?;
+
+        order.arrange(&mut buffer);
+        buffer.into_iter().try_for_each(sink)
+    }
+
+    /// Smooth the partitions directly into the collection `name` in batches of at most `batch_size` ciphertexts,
+    /// which bounds the peak memory of the insertion by the batch size unless the order of the context is a full
+    /// shuffle.
+    #[cfg(feature = "db-mongo")]
+    fn smooth_insert(&mut self, name: &str, batch_size: usize) -> Result<()> {
+        let batch_size = batch_size.max(1);
+        let conn = self.get_conn().clone();
+        let mut batch = Vec::with_capacity(batch_size);
+        let order = self.get_smooth_order();
+        let mut tracker = self.get_progress().start(Phase::Insert, None);
+        self.smooth_ordered_into(order, |ciphertext| {
+            batch.push(Data::new(ciphertext));
+            if batch.len() < batch_size {
+                return Ok(());
+            }
+            conn.insert(std::mem::take(&mut batch), name)?;
+            tracker.advance(batch_size as u64);
+            Ok(())
+        })?;
+
+        if !batch.is_empty() {
+            let len = batch.len() as u64;
+            conn.insert(batch, name)?;
+            tracker.advance(len);
+        }
+        tracker.finish();
+        Ok(())
+    }
+
+    /// The same as [`PartitionFrequencySmoothing::smooth`], but also identifies the output by a load id so that it
+    /// can be inserted at most once via [`Connector::insert_smoothed`].
+    fn smooth_load(&mut self) -> SmoothedLoad {
+        SmoothedLoad::new(self.smooth())
+    }
+}
+
+/// The dummy allocation of a single partition computed by [`PartitionFrequencySmoothing::transform`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PartitionStats {
+    pub index: usize,
+    /// The number of distinct ciphertexts the partition should contain, i.e., `n_i`.
+    pub target: usize,
+    /// The number of distinct ciphertexts of the real messages.
+    pub real: usize,
+    /// The number of dummies inserted, i.e., `target - real`.
+    pub dummies: usize,
+    /// The number of ciphertexts by which `real` exceeds `target`, if it does.
+    pub overflow: usize,
+    /// How many times each ciphertext of the partition occurs, i.e., `1 / k'`.
+    pub ciphertext_cnt: usize,
+    /// The partition was left untransformed because its parameters are invalid.
+    pub skipped: bool,
+}
+
+/// The dummy allocation of all partitions. See [`PartitionStats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransformStats {
+    pub partitions: Vec<PartitionStats>,
+}
+
+impl TransformStats {
+    /// The number of distinct dummy ciphertexts.
+    pub fn dummy_num(&self) -> usize {
+        self.partitions.iter().map(|e| e.dummies).sum()
+    }
+
+    /// The number of dummy ciphertexts in the smoothed output, repetitions included.
+    pub fn dummy_ciphertext_num(&self) -> usize {
+        self.partitions
+            .iter()
+            .map(|e| e.dummies * e.ciphertext_cnt)
+            .sum()
+    }
+}
+
+/// This trait defines how the client-side state (i.e., the local table) of a context is exported and imported, so that
+/// it can be backed up server-side and recovered after the client loses it.
+pub trait LocalState<T>: BaseCrypto<T>
+where
+    T: AsBytes + FromBytes + Debug,
+{
+    /// Export the local state into a compact binary encoding. The key is NOT included.
+    fn export_state(&self) -> Vec<u8>;
+
+    /// Import the local state produced by [`LocalState::export_state`].
+    fn import_state(&mut self, state: &[u8]) -> Result<()>;
+
+    /// Encrypt the local state under the key of the context and store it in a dedicated collection on the server.
+    /// Any previous backup for the collection `name` is replaced, but only once the new one is fully stored: a failed
+    /// backup leaves the previous one intact. The name and the format version are authenticated along with the state,
+    /// so the backup of one collection cannot be recovered as that of another.
+    #[cfg(feature = "db-mongo")]
+    fn backup_local_state(&self, name: &str) -> Result<()> {
+        let mut nonce = vec![0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let mut ciphertext = self
+            .get_cipher()
+            .encrypt_with_aad(
+                self.get_key(),
+                &nonce,
+                self.export_state().as_slice(),
+                &backup_aad(BACKUP_VERSION, name),
+            )
+            .ok_or("Cannot encrypt the local state.")?;
+        let mut blob = BACKUP_VERSION.to_le_bytes().to_vec();
+        blob.append(&mut nonce);
+        blob.append(&mut ciphertext);
+
+        // Each document holds one chunk of the blob prefixed by its index as MongoDB limits the document size.
+        let documents = blob
+            .chunks(BACKUP_CHUNK_SIZE)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut data = (index as u64).to_le_bytes().to_vec();
+                data.extend_from_slice(chunk);
+                Data::new(data)
+            })
+            .collect::<Vec<_>>();
+
+        // The chunks are staged in a collection of their own and swapped in once all of them are stored.
+        let backup_name = backup_collection(name);
+        let staging = format!("{}_staging", backup_name);
+        let conn = self.get_conn();
+        conn.drop_collection(&staging);
+        if let Err(e) = conn.insert(documents, &staging) {
+            conn.drop_collection(&staging);
+            return Err(e);
+        }
+        conn.rename_collection(&staging, &backup_name)
+    }
+
+    /// Rebuild the local state of the context from the server-side backup of the collection `name`.
+    /// The context must hold the same key that was used to create the backup.
+    #[cfg(feature = "db-mongo")]
+    fn recover_local_state(
+        &mut self,
+        conn: &Connector<Data>,
+        name: &str,
+    ) -> Result<()> {
+        let mut chunks = Vec::new();
+        for document in
+            conn.search(Document::new(), &backup_collection(name))?
+        {
+            let mut data = document?.data;
+            if data.len() < 8 {
+                return Err("Malformed backup document.".into());
+            }
+            let chunk = data.split_off(8);
+            chunks.push((u64::from_le_bytes(data.try_into().unwrap()), chunk));
+        }
+        if chunks.is_empty() {
+            return Err(format!("No backup found for {}.", name).into());
+        }
+        chunks.sort_by_key(|e| e.0);
+
+        let blob = chunks.into_iter().flat_map(|e| e.1).collect::<Vec<_>>();
+        if blob.len() < 8 + NONCE_LEN {
+            return Err("Malformed backup.".into());
+        }
+        let (version, blob) = blob.split_at(8);
+        let version = u64::from_le_bytes(version.try_into().unwrap());
+        if version != BACKUP_VERSION {
+            return Err(format!(
+                "Unsupported backup version {}; expected {}.",
+                version, BACKUP_VERSION
+            )
+            .into());
+        }
+        let (nonce, ciphertext) = blob.split_at(NONCE_LEN);
+        let cipher = self.get_cipher();
+        cipher.verify_key(self.get_key(), ciphertext)?;
+        let state = cipher
+            .decrypt_with_aad(
+                self.get_key(),
+                nonce,
+                ciphertext,
+                &backup_aad(version, name),
+            )
+            .ok_or(
+                "Cannot decrypt the backup. Was it made for another collection?",
+            )?;
+
+        self.import_state(&state)
+    }
+}
+
+/// This trait replicates the local state of a context to a warm standby. The primary records its mutations in a
+/// [`Journal`] once recording is enabled; the standby replays them with [`Replicated::apply_journal`] and can then
+/// serve searches. Recording should be enabled before the context is built, or the journal compacted right after,
+/// so that it starts with a snapshot.
+pub trait Replicated<T>: LocalState<T>
+where
+    T: AsBytes + FromBytes + Debug,
+{
+    fn get_journal(&self) -> &Journal;
+
+    fn get_journal_mut(&mut self) -> &mut Journal;
+
+    /// Apply a [`JournalOp::Update`]. Contexts that only record snapshots reject it.
+    fn apply_update(&mut self, message: &[u8], value: &[u8]) -> Result<()> {
+        Err("The context does not record incremental updates.".into())
+    }
+
+    fn enable_journal(&mut self) {
+        self.get_journal_mut().set_enabled(true);
+    }
+
+    /// Record a snapshot of the whole state if recording is enabled.
+    fn record_snapshot(&mut self) {
+        if self.get_journal().is_enabled() {
+            let state = self.export_state();
+            self.get_journal_mut().record(JournalOp::Snapshot { state });
+        }
+    }
+
+    /// Replay the entries shipped from the primary in order. Entries that were already applied are skipped, and a
+    /// gap in the sequence numbers is an error unless a snapshot closes it. The applied entries are kept in the
+    /// journal of this context so that it can in turn serve as a primary after a takeover.
+    fn apply_journal(&mut self, entries: &[JournalEntry]) -> Result<()> {
+        let mut next = self.get_journal().last_seq().map_or(0, |seq| seq + 1);
+        let enabled = self.get_journal().is_enabled();
+        // Replaying must not record the mutations a second time.
+        self.get_journal_mut().set_enabled(false);
+
+        let mut applied = Vec::new();
+        let mut result = Ok(());
+        for entry in entries.iter() {
+            if entry.seq < next {
+                continue;
+            }
+
+            let outcome = match &entry.op {
+                JournalOp::Snapshot { state } => self.import_state(state),
+                JournalOp::Update { .. } if entry.seq > next => {
+                    Err(FseError::JournalGap {
+                        expected: next,
+                        found: entry.seq,
+                    }
+                    .into())
+                }
+                JournalOp::Update { message, value } => {
+                    self.apply_update(message, value)
+                }
+            };
+            if let Err(e) = outcome {
+                result = Err(e);
+                break;
+            }
+
+            next = entry.seq + 1;
+            applied.push(entry.clone());
+        }
+
+        let journal = self.get_journal_mut();
+        journal.set_enabled(enabled);
+        journal.extend_applied(&applied);
+        result
+    }
+
+    /// Replace the journal by a single snapshot of the current state.
+    fn compact_journal(&mut self) {
+        let state = self.export_state();
+        self.get_journal_mut().compact(state);
+    }
+
+    /// Checkpoint the state to the log-structured file at `path` and return the number of bytes written. Only the
+    /// entries recorded since the previous save to the same file are appended; the file is rewritten as a single
+    /// snapshot on the first save, after a bulk mutation, or once the appended entries outgrow the snapshot. Enables
+    /// the journal, as the entries are what tracks the changed state.
+    fn save_incremental(&mut self, path: &str) -> Result<usize> {
+        self.enable_journal();
+        let checkpoint = self
+            .get_journal()
+            .get_checkpoint()
+            .filter(|checkpoint| checkpoint.path == path)
+            .cloned();
+
+        let pending = match checkpoint.as_ref() {
+            Some(checkpoint) => self
+                .get_journal()
+                .entries_since(checkpoint.next_seq)
+                .iter()
+                .map(|entry| (entry, encode_record(entry)))
+                .collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+        let appended = pending.iter().map(|(_, record)| record.len()).sum();
+        let rewrite = match checkpoint.as_ref() {
+            Some(checkpoint) => {
+                pending.iter().any(|(entry, _)| {
+                    matches!(entry.op, JournalOp::Snapshot { .. })
+                }) || checkpoint.appended_len + appended
+                    > checkpoint.snapshot_len
+            }
+            None => true,
+        };
+
+        if !rewrite {
+            let mut file = OpenOptions::new().append(true).open(path)?;
+            for (_, record) in pending.iter() {
+                file.write_all(record)?;
+            }
+            file.sync_data()?;
+
+            let next_seq = self.get_journal().last_seq().map_or(0, |e| e + 1);
+            self.get_journal_mut().set_checkpoint(checkpoint.map(|e| {
+                Checkpoint {
+                    next_seq,
+                    appended_len: e.appended_len + appended,
+                    ..e
+                }
+            }));
+            return Ok(appended);
+        }
+
+        // Compaction: the journal is folded into a snapshot that replaces the file atomically.
+        if self.get_journal().last_seq().is_none() {
+            self.record_snapshot();
+        }
+        self.compact_journal();
+        let journal = self.get_journal();
+        let snapshot = encode_record(&journal.get_entries()[0]);
+        let next_seq = journal.last_seq().map_or(0, |e| e + 1);
+
+        let temp = format!("{}.tmp", path);
+        let mut file = File::create(&temp)?;
+        file.write_all(&snapshot)?;
+        file.sync_all()?;
+        std::fs::rename(&temp, path)?;
+
+        self.get_journal_mut().set_checkpoint(Some(Checkpoint {
+            path: path.to_string(),
+            next_seq,
+            snapshot_len: snapshot.len(),
+            appended_len: 0,
+        }));
+        Ok(snapshot.len())
+    }
+
+    /// Restore the state saved by [`Replicated::save_incremental`] to `path`, so that later saves keep appending to
+    /// the same file. An entry cut short by a crash during a save is ignored.
+    fn load_incremental(&mut self, path: &str) -> Result<()> {
+        let buf = std::fs::read(path)?;
+        let entries = decode_records(&buf)
+            .ok_or_else(|| format!("{} is not a state file.", path))?;
+        let snapshot_len = match entries.first() {
+            Some(
+                entry @ JournalEntry {
+                    op: JournalOp::Snapshot { .. },
+                    ..
+                },
+            ) => encode_record(entry).len(),
+            _ => {
+                return Err(
+                    format!("{} does not start with a snapshot.", path).into()
+                )
+            }
+        };
+        let appended_len =
+            entries[1..].iter().map(|e| encode_record(e).len()).sum();
+
+        self.enable_journal();
+        self.apply_journal(&entries)?;
+        let next_seq = self.get_journal().last_seq().map_or(0, |e| e + 1);
+        self.get_journal_mut().set_checkpoint(Some(Checkpoint {
+            path: path.to_string(),
+            next_seq,
+            snapshot_len,
+            appended_len,
+        }));
+        Ok(())
+    }
+}
+
+/// This trait ties a context to the dataset it was built from. A keyed fingerprint of the histogram is stored in
+/// [`crate::db::FINGERPRINTS_COLLECTION`] when the collection is populated, and is checked before the context is used
+/// against it, so that a stale context is rejected instead of silently returning wrong results.
+pub trait DatasetFingerprint<T>: BaseCrypto<T>
+where
+    T: AsBytes + FromBytes + Debug,
+{
+    /// Get the unkeyed digest of the histogram the context was built from, or `None` if it is not built yet.
+    fn get_histogram_digest(&self) -> Option<&[u8]>;
+
+    /// Compute the keyed fingerprint of the dataset.
+    fn fingerprint(&self) -> Result<String> {
+        let digest = self
+            .get_histogram_digest()
+            .ok_or(FseError::NotInitialized)?;
+        Ok(keyed_fingerprint(self.get_key(), digest))
+    }
+
+    /// Store the fingerprint of the collection `name`, replacing any previous one.
+    #[cfg(feature = "db-mongo")]
+    fn publish_fingerprint(&self, name: &str) -> Result<()> {
+        self.get_conn().set_fingerprint(name, &self.fingerprint()?)
+    }
+
+    /// Verify that the collection `name` was populated from the same dataset as the context.
+    #[cfg(feature = "db-mongo")]
+    fn attach(&self, name: &str) -> Result<()> {
+        let expected = self.fingerprint()?;
+        match self.get_conn().get_fingerprint(name)? {
+            Some(found) if found == expected => Ok(()),
+            Some(found) => Err(FseError::StaleContext {
+                collection: name.to_string(),
+                expected,
+                found,
+            }
+            .into()),
+            None => Err(FseError::MissingFingerprint(name.to_string()).into()),
+        }
+    }
+
+    /// Search a given message `T` after checking that the context is not stale for the collection `name`.
+    #[cfg(feature = "db-mongo")]
+    fn search_attached(&mut self, message: &T, name: &str) -> Result<Vec<T>> {
+        self.attach(name)?;
+        self.search(message, name)
+            .ok_or_else(|| "The search failed.".into())
+    }
+}
+
+/// This trait exposes the searchable plaintext domain of a context, i.e., the messages of its local table, so that a
+/// client (e.g., a search box with autocompletion) can validate a query before generating its tokens without keeping
+/// the raw data.
+pub trait Domain<T>: BaseCrypto<T>
+where
+    T: AsBytes + FromBytes + Debug,
+{
+    /// The messages that can be searched. Dummies are not included and the order is unspecified.
+    fn export_domain(&self) -> Vec<T>;
+
+    /// Whether `message` can be searched.
+    fn contains(&self, message: &T) -> bool;
+
+    /// Encrypt the domain under the key of the context as `nonce || ciphertext`. Read it by [`import_domain`].
+    fn export_domain_encrypted(&self) -> Result<Vec<u8>> {
+        let domain = self.export_domain();
+        let mut writer = StateWriter::new();
+        writer.put_u64(domain.len() as u64);
+        for message in domain.iter() {
+            writer.put_bytes(&message.to_bytes());
+        }
+
+        let mut nonce = vec![0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let mut blob = self
+            .get_cipher()
+            .encrypt(self.get_key(), &nonce, writer.finish().as_slice())
+            .ok_or("Cannot encrypt the domain.")?;
+        nonce.append(&mut blob);
+        Ok(nonce)
+    }
+}
+
+/// Decrypt a domain produced by [`Domain::export_domain_encrypted`] with the key of the context that exported it.
+pub fn import_domain<T: FromBytes>(
+    cipher: &dyn Cipher,
+    key: &[u8],
+    blob: &[u8],
+) -> Result<Vec<T>> {
+    if blob.len() < NONCE_LEN {
+        return Err("Malformed domain.".into());
+    }
+    cipher.verify_key(key, &blob[NONCE_LEN..])?;
+    let plaintext = cipher
+        .decrypt(key, &blob[..NONCE_LEN], &blob[NONCE_LEN..])
+        .ok_or("Cannot decrypt the domain. Is the key correct?")?;
+
+    let mut reader = StateReader::new(&plaintext);
+    let malformed = || "Malformed domain.";
+    let len = reader.get_usize().ok_or_else(malformed)?;
+    let mut domain = Vec::new();
+    for _ in 0..len {
+        domain.push(T::from_bytes(reader.get_bytes().ok_or_else(malformed)?));
+    }
+    if !reader.is_empty() {
+        return Err(malformed().into());
+    }
+    Ok(domain)
+}
+
+/// The size of each chunk of the local state backup.
+const BACKUP_CHUNK_SIZE: usize = 1 << 22;
+
+/// The version of the format of the local state backup, which prefixes the backup.
+const BACKUP_VERSION: u64 = 1;
+
+/// The associated data of the local state backup of the collection `name`.
+fn backup_aad(version: u64, name: &str) -> Vec<u8> {
+    let mut aad = b"fse-local-state".to_vec();
+    aad.extend_from_slice(&version.to_le_bytes());
+    aad.extend_from_slice(name.as_bytes());
+    aad
+}
 
-    /// Smoothes the partitions and outputs the ciphertext set.
-    fn smooth(&mut self) -> Vec<Vec<u8>>;
+/// The name of the collection that stores the backup of the local state for collection `name`.
+pub fn backup_collection(name: &str) -> String {
+    format!("{}_local_state", name)
 }
 
 /// A function used in the partition phase. It takes the form `f(x) = \lambda e^{-\lambda x}`.
@@ -0,0 +1,180 @@
+//! This module implements the state-change journal used to replicate the client-side state of a context to a warm
+//! standby. The primary records every mutation of its local table as a [`JournalEntry`]; the entries are shipped to
+//! the standby (they are serializable) and replayed with [`crate::fse::Replicated::apply_journal`], so that the
+//! standby can take over searches without re-initializing from the dataset.
+//!
+//! Bulk mutations such as partitioning or initializing an encoder are recorded as a snapshot of the whole state,
+//! while per-message mutations (e.g., the nonces drawn by RND) are recorded as updates. Compaction replaces the
+//! journal with a single snapshot.
+//!
+//! The same entries checkpoint a context to a log-structured state file by
+//! [`crate::fse::Replicated::save_incremental`]: the file starts with a snapshot, and each save only appends the
+//! entries recorded since the previous one, so its cost follows the write rate instead of the size of the domain. The
+//! file is rewritten as a single snapshot once the appended entries outgrow it.
+
+use serde::{Deserialize, Serialize};
+
+use crate::util::{StateReader, StateWriter};
+
+/// A mutation of the local state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "op")]
+pub enum JournalOp {
+    /// The whole state as produced by [`crate::fse::LocalState::export_state`]; it replaces everything before it.
+    Snapshot { state: Vec<u8> },
+    /// An incremental change to the local-table entry of `message`. Its meaning is defined by the context.
+    Update { message: Vec<u8>, value: Vec<u8> },
+}
+
+/// A mutation tagged with its sequence number. Sequence numbers are consecutive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub op: JournalOp,
+}
+
+/// An append-only log of the mutations of a context. Recording is disabled by default.
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    enabled: bool,
+    /// The sequence number of the next entry.
+    next_seq: u64,
+    entries: Vec<JournalEntry>,
+    /// The state file the entries are saved to, if any.
+    checkpoint: Option<Checkpoint>,
+}
+
+/// What has been saved to a state file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Checkpoint {
+    pub(crate) path: String,
+    /// The sequence number of the first entry not yet saved.
+    pub(crate) next_seq: u64,
+    /// The length of the snapshot the file starts with.
+    pub(crate) snapshot_len: usize,
+    /// The length of the entries appended after the snapshot.
+    pub(crate) appended_len: usize,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The sequence number of the last entry recorded or applied, or `None` if there is none.
+    pub fn last_seq(&self) -> Option<u64> {
+        self.next_seq.checked_sub(1)
+    }
+
+    pub fn get_entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// Get the entries whose sequence number is at least `seq`, i.e., those a standby that has applied everything
+    /// up to `seq - 1` still needs.
+    pub fn entries_since(&self, seq: u64) -> &[JournalEntry] {
+        let start = self.entries.partition_point(|e| e.seq < seq);
+        &self.entries[start..]
+    }
+
+    /// Record `op` if recording is enabled.
+    pub fn record(&mut self, op: JournalOp) {
+        if !self.enabled {
+            return;
+        }
+
+        self.entries.push(JournalEntry {
+            seq: self.next_seq,
+            op,
+        });
+        self.next_seq += 1;
+    }
+
+    /// Keep the entries replayed from another journal so that a promoted standby continues its numbering.
+    pub(crate) fn extend_applied(&mut self, entries: &[JournalEntry]) {
+        if let Some(last) = entries.last() {
+            if self.enabled {
+                self.entries.extend_from_slice(entries);
+            }
+            self.next_seq = last.seq + 1;
+        }
+    }
+
+    /// Replace all entries by a single snapshot of the current state that carries the last sequence number, so
+    /// that standbys which are up to date receive nothing new.
+    pub fn compact(&mut self, state: Vec<u8>) {
+        if let Some(seq) = self.last_seq() {
+            self.entries = vec![JournalEntry {
+                seq,
+                op: JournalOp::Snapshot { state },
+            }];
+        }
+    }
+
+    pub(crate) fn get_checkpoint(&self) -> Option<&Checkpoint> {
+        self.checkpoint.as_ref()
+    }
+
+    pub(crate) fn set_checkpoint(&mut self, checkpoint: Option<Checkpoint>) {
+        self.checkpoint = checkpoint;
+    }
+}
+
+const RECORD_SNAPSHOT: u64 = 0;
+const RECORD_UPDATE: u64 = 1;
+
+/// Frame `entry` as a record of a state file: its length followed by the sequence number, the kind and the payload.
+pub(crate) fn encode_record(entry: &JournalEntry) -> Vec<u8> {
+    let mut writer = StateWriter::new();
+    writer.put_u64(entry.seq);
+    match &entry.op {
+        JournalOp::Snapshot { state } => {
+            writer.put_u64(RECORD_SNAPSHOT);
+            writer.put_bytes(state);
+        }
+        JournalOp::Update { message, value } => {
+            writer.put_u64(RECORD_UPDATE);
+            writer.put_bytes(message);
+            writer.put_bytes(value);
+        }
+    }
+
+    let mut record = StateWriter::new();
+    record.put_bytes(&writer.finish());
+    record.finish()
+}
+
+/// Parse the records of a state file. A record cut short by a crash during an append ends the file, so the entries
+/// before it are returned, while a complete but malformed record is an error.
+pub(crate) fn decode_records(buf: &[u8]) -> Option<Vec<JournalEntry>> {
+    let mut reader = StateReader::new(buf);
+    let mut entries = Vec::new();
+    while let Some(record) = reader.get_bytes() {
+        let mut record = StateReader::new(record);
+        let seq = record.get_u64()?;
+        let op = match record.get_u64()? {
+            RECORD_SNAPSHOT => JournalOp::Snapshot {
+                state: record.get_bytes()?.to_vec(),
+            },
+            RECORD_UPDATE => JournalOp::Update {
+                message: record.get_bytes()?.to_vec(),
+                value: record.get_bytes()?.to_vec(),
+            },
+            _ => return None,
+        };
+        if !record.is_empty() {
+            return None;
+        }
+        entries.push(JournalEntry { seq, op });
+    }
+    Some(entries)
+}
@@ -0,0 +1,187 @@
+//! This module abstracts where the keys of the contexts come from, so that they can be kept in a KMS or an HSM
+//! instead of being generated and stored by the client.
+//!
+//! A [`KeyProvider`] owns a key-encryption key (KEK) that never leaves it. The data encryption keys (DEKs) of the
+//! contexts are only persisted wrapped under the KEK, and are unwrapped by the provider when a context is built by
+//! [`crate::fse::BaseCrypto::key_from_provider`].
+
+use std::{
+    fmt::Debug,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use rand_core::{OsRng, RngCore};
+
+use crate::{
+    cipher::{AesGcmCipher, Cipher, NONCE_LEN},
+    error::FseError,
+    util::hkdf_sha256,
+    Result,
+};
+
+/// The length of the keys generated by the providers in bytes.
+pub const KEY_LEN: usize = 32;
+
+/// The salt of the HKDF that derives the keys of the columns.
+const COLUMN_KEY_SALT: &[u8] = b"fse-column-key";
+
+/// A source of data encryption keys backed by a key-encryption key, e.g., a KMS.
+pub trait KeyProvider: Debug {
+    /// Get the data encryption key `name`, generating a fresh one if there is none yet.
+    fn get_key(&self, name: &str) -> Result<Vec<u8>>;
+
+    /// Get the data encryption key `name` cut to `len` bytes, e.g., the key length of a
+    /// [`crate::cipher::SecurityLevel`]. A prefix of a random key is a random key, so the same stored key serves every
+    /// level; fails if it is shorter than `len`.
+    fn get_key_of_len(&self, name: &str, len: usize) -> Result<Vec<u8>> {
+        let mut key = self.get_key(name)?;
+        if key.len() < len {
+            return Err(FseError::InvalidParams(format!(
+                "The key {} has {} bytes, but {} are needed",
+                name,
+                key.len(),
+                len
+            ))
+            .into());
+        }
+        key.truncate(len);
+        Ok(key)
+    }
+
+    /// Wrap a data encryption key under the key-encryption key.
+    fn wrap(&self, key: &[u8]) -> Result<Vec<u8>>;
+
+    /// Unwrap a key wrapped by [`KeyProvider::wrap`]. Fails with [`FseError::DecryptionFailed`] if it was wrapped
+    /// under another key-encryption key or tampered with.
+    fn unwrap(&self, wrapped: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Derive the key of `column` from `key`, the key shared by the columns of a table, by HKDF-SHA256 with the column
+/// name as the info. Equal values of two columns then have unrelated tokens, so the server cannot link the columns by
+/// their tokens, while the client still keeps a single key. The key of a column is the same on every derivation.
+pub fn derive_column_key(
+    key: &[u8],
+    column: &str,
+    len: usize,
+) -> Result<Vec<u8>> {
+    hkdf_sha256(COLUMN_KEY_SALT, key, column.as_bytes(), len).ok_or_else(|| {
+        FseError::InvalidParams(format!(
+            "A column key cannot have {} bytes",
+            len
+        ))
+        .into()
+    })
+}
+
+/// Generate a fresh key of [`KEY_LEN`] bytes.
+pub fn generate_key() -> Vec<u8> {
+    let mut key = vec![0u8; KEY_LEN];
+    OsRng.fill_bytes(&mut key);
+    key
+}
+
+/// Wrap `key` by AES-256-GCM under `kek` with a random nonce, which is prepended to the wrapped key.
+pub fn wrap_key(kek: &[u8], key: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = vec![0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let wrapped = AesGcmCipher.encrypt(kek, &nonce, key).ok_or_else(|| {
+        FseError::InvalidParams("The key cannot be wrapped".into())
+    })?;
+    Ok([nonce, wrapped].concat())
+}
+
+/// Unwrap a key wrapped by [`wrap_key`].
+pub fn unwrap_key(kek: &[u8], wrapped: &[u8]) -> Result<Vec<u8>> {
+    if wrapped.len() < NONCE_LEN {
+        return Err(FseError::DecryptionFailed.into());
+    }
+
+    let (nonce, wrapped) = wrapped.split_at(NONCE_LEN);
+    AesGcmCipher
+        .decrypt(kek, nonce, wrapped)
+        .ok_or_else(|| FseError::DecryptionFailed.into())
+}
+
+/// A provider that keeps the key-encryption key in a file of a local directory, next to the wrapped keys. Anyone who
+/// can read the directory can unwrap the keys, so it is meant for development and tests.
+#[derive(Clone)]
+pub struct LocalFileProvider {
+    dir: PathBuf,
+    kek: Vec<u8>,
+}
+
+impl Debug for LocalFileProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LocalFileProvider")
+            .field("dir", &self.dir)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LocalFileProvider {
+    /// The file of the key-encryption key.
+    const KEK_FILE: &'static str = "kek";
+
+    /// Open the key directory `dir`, creating it and its key-encryption key if they do not exist.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let path = dir.join(Self::KEK_FILE);
+        let kek = match path.exists() {
+            true => fs::read(&path)?,
+            false => {
+                let kek = generate_key();
+                fs::write(&path, &kek)?;
+                kek
+            }
+        };
+        if kek.len() != KEY_LEN {
+            return Err(FseError::InvalidParams(format!(
+                "The key-encryption key {} is not {} bytes",
+                path.display(),
+                KEY_LEN
+            ))
+            .into());
+        }
+
+        Ok(Self { dir, kek })
+    }
+
+    fn key_path(&self, name: &str) -> Result<PathBuf> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        match valid {
+            true => Ok(self.dir.join(format!("{}.key", name))),
+            false => Err(FseError::InvalidParams(format!(
+                "Invalid key name {:?}",
+                name
+            ))
+            .into()),
+        }
+    }
+}
+
+impl KeyProvider for LocalFileProvider {
+    fn get_key(&self, name: &str) -> Result<Vec<u8>> {
+        let path = self.key_path(name)?;
+        if path.exists() {
+            return self.unwrap(&fs::read(&path)?);
+        }
+
+        let key = generate_key();
+        fs::write(&path, self.wrap(&key)?)?;
+        Ok(key)
+    }
+
+    fn wrap(&self, key: &[u8]) -> Result<Vec<u8>> {
+        wrap_key(&self.kek, key)
+    }
+
+    fn unwrap(&self, wrapped: &[u8]) -> Result<Vec<u8>> {
+        unwrap_key(&self.kek, wrapped)
+    }
+}
@@ -8,9 +8,35 @@
 
 #[cfg(feature = "attack")]
 pub mod attack;
+pub mod audit;
+#[cfg(feature = "bench")]
+pub mod bench_support;
+pub mod cipher;
+#[cfg(feature = "arrow")]
+pub mod columnar;
+#[cfg(feature = "db-mongo")]
 pub mod db;
+#[cfg(feature = "pfse")]
+pub mod drift;
+pub mod dummy;
+pub mod enrollment;
+pub mod envelope;
+pub mod error;
+pub mod fixed;
 pub mod fse;
+pub mod journal;
+pub mod keys;
+#[cfg(feature = "pfse")]
+pub mod migration;
+pub mod preprocess;
+pub mod progress;
 pub mod scheme;
+pub mod security;
+pub mod split;
+pub mod storage;
+#[cfg(all(feature = "pfse", feature = "lpfse"))]
+pub mod testvectors;
+pub mod token;
 pub mod util;
 
 // Re-export
@@ -0,0 +1,230 @@
+//! This module plans the migration of a PFSE collection from one generation of a context to another, e.g., after
+//! `lambda` is changed, before anything is re-encrypted. A document stays in place if its ciphertext is the same in
+//! both generations, i.e., the same copy of the same message within the same partition under the same key; every
+//! other document of the old generation is deleted and the documents missing from the new one are encrypted.
+//!
+//! The messages are migrated in batches, so both generations coexist in the collection until the last batch is
+//! applied. The advantage bound of each interim collection is computed like [`crate::security::advantage_bound`] on
+//! the partitions of both generations, each holding only the messages currently stored under it.
+//!
+//! The plan is computed from the local tables alone, so it also applies to contexts restored by
+//! [`crate::fse::LocalState::import_state`]. Dummies are not in the local tables: they are left out of the counts,
+//! and the bounds ignore them, which can only make the bounds larger.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    hash::Hash,
+};
+
+use crate::{
+    error::FseError,
+    fse::{AsBytes, BaseCrypto, FromBytes, Random, ValueType},
+    pfse::ContextPFSE,
+    security::{advantage_bound, PartitionState, SchemeState},
+    util::SizeAllocated,
+    Result,
+};
+
+/// A batch of messages migrated together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationBatch<T> {
+    pub messages: Vec<T>,
+    /// The documents of the old generation deleted by this batch.
+    pub deleted: usize,
+    /// The documents of the new generation encrypted and inserted by this batch.
+    pub reencrypted: usize,
+    /// The advantage bound of the collection once this batch is applied.
+    pub advantage: f64,
+}
+
+/// The cost and the interim leakage of a migration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationPlan<T> {
+    /// The documents whose ciphertext is the same in both generations.
+    pub kept: usize,
+    /// The documents of the old generation that have to be deleted.
+    pub deleted: usize,
+    /// The documents of the new generation that have to be encrypted.
+    pub reencrypted: usize,
+    /// The advantage bound before the migration.
+    pub old_advantage: f64,
+    /// The advantage bound after the migration.
+    pub new_advantage: f64,
+    /// The largest advantage bound from the start to the end of the migration, while both generations coexist.
+    pub interim_advantage: f64,
+    /// The batches in the order they are applied.
+    pub batches: Vec<MigrationBatch<T>>,
+}
+
+/// The documents of a single message in both generations.
+#[derive(Debug, Clone, Copy, Default)]
+struct MessageCost {
+    old: usize,
+    new: usize,
+    kept: usize,
+}
+
+/// The number of documents of an entry of the local table.
+fn documents(values: &[ValueType]) -> usize {
+    values.iter().map(|&(_, size, cnt)| size * cnt).sum()
+}
+
+/// The documents of a message whose ciphertexts are in both generations. The `j`-th copy within a partition has the
+/// same ciphertext in both, so the first `min(size)` copies of each common partition are shared.
+fn shared(old: &[ValueType], new: &[ValueType]) -> usize {
+    old.iter()
+        .filter_map(|&(index, size, cnt)| {
+            new.iter()
+                .find(|e| e.0 == index)
+                .map(|&(_, new_size, new_cnt)| {
+                    size.min(new_size) * cnt.min(new_cnt)
+                })
+        })
+        .sum()
+}
+
+/// Accumulate the partitions of the messages of `table` that `select` accepts.
+fn add_partitions<'a, T, F>(
+    table: &'a HashMap<T, Vec<ValueType>>,
+    select: F,
+    partitions: &mut HashMap<usize, PartitionState>,
+) where
+    F: Fn(&'a T) -> bool,
+{
+    for (message, values) in table.iter().filter(|(message, _)| select(message))
+    {
+        for &(index, size, cnt) in values.iter() {
+            let partition =
+                partitions.entry(index).or_insert_with(|| PartitionState {
+                    counts: Vec::new(),
+                    ciphertext_num: 0,
+                });
+            partition.counts.push(size * cnt);
+            partition.ciphertext_num += size;
+        }
+    }
+}
+
+/// The advantage bound of a collection where the messages `migrated` accepts are stored under the new generation and
+/// the others under the old one.
+fn interim_advantage<T, F>(
+    old: &HashMap<T, Vec<ValueType>>,
+    new: &HashMap<T, Vec<ValueType>>,
+    message_num: usize,
+    migrated: F,
+) -> f64
+where
+    T: Hash + Eq,
+    F: Fn(&T) -> bool,
+{
+    let mut old_partitions = HashMap::new();
+    add_partitions(old, |message| !migrated(message), &mut old_partitions);
+    let mut new_partitions = HashMap::new();
+    add_partitions(new, &migrated, &mut new_partitions);
+
+    advantage_bound(&SchemeState::Pfse {
+        message_num,
+        partitions: old_partitions
+            .into_values()
+            .chain(new_partitions.into_values())
+            .collect(),
+    })
+}
+
+/// Plan the migration from the context `old` to the context `new` in batches of at most `batch_size` messages. The
+/// messages with the most documents in the old generation are migrated first, as they are the first an attacker
+/// recovers; the messages whose documents are all kept need no batch.
+pub fn plan_migration<T>(
+    old: &ContextPFSE<T>,
+    new: &ContextPFSE<T>,
+    batch_size: usize,
+) -> Result<MigrationPlan<T>>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + Random + SizeAllocated,
+{
+    if batch_size == 0 {
+        return Err(FseError::InvalidParams(
+            "The batch size must be positive".into(),
+        )
+        .into());
+    }
+    let (old_table, new_table) = (old.get_local_table(), new.get_local_table());
+    if old_table.is_empty() || new_table.is_empty() {
+        return Err(FseError::NotInitialized.into());
+    }
+
+    // Under another key no ciphertext is shared.
+    let same_key = old.get_key() == new.get_key();
+    let mut costs = HashMap::<&T, MessageCost>::new();
+    for (message, values) in old_table.iter() {
+        costs.entry(message).or_default().old = documents(values);
+    }
+    for (message, values) in new_table.iter() {
+        let cost = costs.entry(message).or_default();
+        cost.new = documents(values);
+        if let (true, Some(old_values)) = (same_key, old_table.get(message)) {
+            cost.kept = shared(old_values, values);
+        }
+    }
+
+    let mut order = costs
+        .iter()
+        .filter(|(_, cost)| cost.kept != cost.old || cost.kept != cost.new)
+        .map(|(message, cost)| (*message, *cost))
+        .collect::<Vec<_>>();
+    order.sort_by(|lhs, rhs| {
+        rhs.1
+            .old
+            .cmp(&lhs.1.old)
+            .then_with(|| lhs.0.to_bytes().cmp(&rhs.0.to_bytes()))
+    });
+
+    let message_num = new.get_message_num().max(old.get_message_num());
+    let unchanged = |message: &T| {
+        let cost = costs[message];
+        cost.kept == cost.old && cost.kept == cost.new
+    };
+    let old_advantage =
+        interim_advantage(old_table, new_table, message_num, |_| false);
+    let new_advantage =
+        interim_advantage(old_table, new_table, message_num, |_| true);
+
+    let mut migrated = HashSet::new();
+    let mut batches = Vec::new();
+    for chunk in order.chunks(batch_size) {
+        for (message, _) in chunk.iter() {
+            migrated.insert(*message);
+        }
+        let advantage =
+            interim_advantage(old_table, new_table, message_num, |message| {
+                unchanged(message) || migrated.contains(message)
+            });
+        batches.push(MigrationBatch {
+            messages: chunk
+                .iter()
+                .map(|(message, _)| (*message).clone())
+                .collect(),
+            deleted: chunk.iter().map(|(_, cost)| cost.old - cost.kept).sum(),
+            reencrypted: chunk
+                .iter()
+                .map(|(_, cost)| cost.new - cost.kept)
+                .sum(),
+            advantage,
+        });
+    }
+
+    let interim_advantage = batches
+        .iter()
+        .map(|batch| batch.advantage)
+        .fold(old_advantage.max(new_advantage), f64::max);
+    Ok(MigrationPlan {
+        kept: costs.values().map(|cost| cost.kept).sum(),
+        deleted: batches.iter().map(|batch| batch.deleted).sum(),
+        reencrypted: batches.iter().map(|batch| batch.reencrypted).sum(),
+        old_advantage,
+        new_advantage,
+        interim_advantage,
+        batches,
+    })
+}
@@ -0,0 +1,164 @@
+//! This module implements the preprocessing applied to a column before it is smoothed. Real deployments often
+//! encrypt a derived attribute instead of the raw value, e.g., the month of a date or the domain of an email, which
+//! changes the distribution the scheme has to hide. A [`FrequencyCap`] instead bounds how often a message occurs by
+//! diverting its excess occurrences.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::FseError,
+    util::{is_iso_date, to_hex},
+    Result,
+};
+
+/// A transformation of the raw values of a column.
+pub trait Preprocess {
+    fn apply(&self, value: &str) -> String;
+
+    fn apply_all(&self, values: &[String]) -> Vec<String> {
+        values.iter().map(|value| self.apply(value)).collect()
+    }
+}
+
+/// The granularity a date is bucketed to.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DateUnit {
+    Year,
+    Month,
+    Day,
+}
+
+/// The built-in transformations. Values a transformation does not apply to, e.g., a string that is not a date for
+/// [`Transform::BucketDate`], are left untouched.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "op")]
+pub enum Transform {
+    /// Keep the first `len` characters.
+    Prefix { len: usize },
+    /// Zero the last `digits` digits of an integer, e.g., 12345 becomes 12300 for two digits.
+    TruncateDigits { digits: u32 },
+    /// Bucket an ISO 8601 date (`YYYY-MM-DD`, optionally followed by a time) to its year, month or day.
+    BucketDate { unit: DateUnit },
+    /// Keep the domain of an email address.
+    EmailDomain,
+    /// Replace the value by the first `len` hex digits of its SHA-256 digest, i.e., one of `16^len` buckets.
+    Hash { len: usize },
+}
+
+impl Preprocess for Transform {
+    fn apply(&self, value: &str) -> String {
+        match self {
+            Self::Prefix { len } => value.chars().take(*len).collect(),
+            Self::TruncateDigits { digits } => {
+                let scale = 10i64.checked_pow(*digits);
+                match (value.trim().parse::<i64>(), scale) {
+                    (Ok(number), Some(scale)) => {
+                        (number / scale * scale).to_string()
+                    }
+                    (Ok(_), None) => "0".to_string(),
+                    (Err(_), _) => value.to_string(),
+                }
+            }
+            Self::BucketDate { unit } => match (is_iso_date(value), unit) {
+                (false, _) => value.to_string(),
+                (true, DateUnit::Year) => value[..4].to_string(),
+                (true, DateUnit::Month) => value[..7].to_string(),
+                (true, DateUnit::Day) => value[..10].to_string(),
+            },
+            Self::EmailDomain => match value.rsplit_once('@') {
+                Some((_, domain)) => domain.to_lowercase(),
+                None => value.to_string(),
+            },
+            Self::Hash { len } => {
+                let mut digest = to_hex(&Sha256::digest(value.as_bytes()));
+                digest.truncate(*len);
+                digest
+            }
+        }
+    }
+}
+
+/// A pipeline applies the transformations in order.
+impl<P> Preprocess for [P]
+where
+    P: Preprocess,
+{
+    fn apply(&self, value: &str) -> String {
+        self.iter().fold(value.to_string(), |value, transform| {
+            transform.apply(&value)
+        })
+    }
+}
+
+/// What becomes of the occurrences of a message beyond the cap of a [`FrequencyCap`].
+#[derive(
+    Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum CapOverflow {
+    /// Encrypt them by RND, so they are stored but their ciphertexts are unlinkable.
+    #[default]
+    Rnd,
+    /// Leave them out, e.g., to analyze the capped distribution alone.
+    Drop,
+}
+
+/// Caps the number of occurrences of each message at `max_count` before the column is smoothed, which bounds the
+/// worst-case frequency the scheme has to hide. Unlike a [`Transform`], the cap depends on the whole column.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct FrequencyCap {
+    pub max_count: usize,
+    #[serde(default)]
+    pub overflow: CapOverflow,
+}
+
+/// A column split by a [`FrequencyCap`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CappedColumn {
+    /// The occurrences within the cap in their original order.
+    pub kept: Vec<String>,
+    /// The occurrences beyond the cap in their original order.
+    pub diverted: Vec<String>,
+}
+
+impl CappedColumn {
+    /// The fraction of the column that is diverted.
+    pub fn diverted_mass(&self) -> f64 {
+        match self.kept.len() + self.diverted.len() {
+            0 => 0.0,
+            total => self.diverted.len() as f64 / total as f64,
+        }
+    }
+}
+
+impl FrequencyCap {
+    pub fn validate(&self) -> Result<()> {
+        match self.max_count {
+            0 => Err(FseError::InvalidParams(
+                "The cap must allow at least one occurrence".into(),
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Keep the first `max_count` occurrences of each message and divert the others.
+    pub fn apply(&self, values: &[String]) -> CappedColumn {
+        let mut counts = HashMap::new();
+        let mut column = CappedColumn::default();
+        for value in values.iter() {
+            let count = counts.entry(value.as_str()).or_insert(0usize);
+            *count += 1;
+            match *count <= self.max_count {
+                true => column.kept.push(value.clone()),
+                false => column.diverted.push(value.clone()),
+            }
+        }
+        column
+    }
+}
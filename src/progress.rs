@@ -0,0 +1,189 @@
+//! This module implements an optional progress report for the long-running phases of a context, i.e., partitioning,
+//! transforming, smoothing and inserting. The library does not draw anything: it calls a [`ProgressReporter`] with the
+//! current phase, the number of items processed so far and the total if known, and leaves the rendering to the caller
+//! (e.g., the `indicatif` adapter of the eval binary).
+//!
+//! A context without a reporter does not pay for the report beyond a branch per item.
+
+use std::{
+    fmt::{Debug, Display},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// A long-running phase of a context.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    Partition,
+    Transform,
+    Smooth,
+    Insert,
+}
+
+impl Phase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Partition => "partition",
+            Self::Transform => "transform",
+            Self::Smooth => "smooth",
+            Self::Insert => "insert",
+        }
+    }
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A snapshot of the progress of a phase.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProgressEvent {
+    pub phase: Phase,
+    /// The number of items processed so far. The items are messages when partitioning, partitions when transforming
+    /// and ciphertexts when smoothing or inserting.
+    pub done: u64,
+    /// The number of items of the phase, if known in advance.
+    pub total: Option<u64>,
+    /// The time since the phase started.
+    pub elapsed: Duration,
+    /// Is the phase finished? The last event of a phase is always sent with `finished` set.
+    pub finished: bool,
+}
+
+impl ProgressEvent {
+    /// Estimate the remaining time of the phase by the average rate so far. Returns `None` if the total is unknown or
+    /// nothing has been processed yet.
+    pub fn eta(&self) -> Option<Duration> {
+        let total = self.total?;
+        if self.done == 0 {
+            return None;
+        }
+        let remaining = total.saturating_sub(self.done) as f64;
+        Some(self.elapsed.mul_f64(remaining / self.done as f64))
+    }
+}
+
+/// Receives the progress of a context. It is implemented for every `Fn(&ProgressEvent)`, so a closure is enough.
+pub trait ProgressReporter: Send + Sync {
+    fn report(&self, event: &ProgressEvent);
+}
+
+impl<F> ProgressReporter for F
+where
+    F: Fn(&ProgressEvent) + Send + Sync,
+{
+    fn report(&self, event: &ProgressEvent) {
+        self(event)
+    }
+}
+
+/// The reporter held by a context. Clones of a context report to the same reporter.
+#[derive(Clone, Default)]
+pub struct Progress(Option<Arc<dyn ProgressReporter>>);
+
+impl Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("Progress(Some(..))"),
+            None => f.write_str("Progress(None)"),
+        }
+    }
+}
+
+impl Progress {
+    pub fn new<R: ProgressReporter + 'static>(reporter: R) -> Self {
+        Self(Some(Arc::new(reporter)))
+    }
+
+    /// A progress that reports nothing.
+    pub fn none() -> Self {
+        Self(None)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Start tracking `phase`. The tracker reports the start at once, then about every percent of `total` (or every
+    /// [`UNKNOWN_TOTAL_STEP`] items if the total is unknown), and the end when it is finished or dropped.
+    pub fn start(&self, phase: Phase, total: Option<u64>) -> PhaseTracker {
+        let step = match total {
+            Some(total) => (total / 100).max(1),
+            None => UNKNOWN_TOTAL_STEP,
+        };
+        let tracker = PhaseTracker {
+            reporter: self.0.clone(),
+            phase,
+            total,
+            done: 0,
+            next: step,
+            step,
+            start: Instant::now(),
+            finished: false,
+        };
+        tracker.report(false);
+        tracker
+    }
+}
+
+/// How many items are processed between two reports of a phase with an unknown total.
+pub const UNKNOWN_TOTAL_STEP: u64 = 10_000;
+
+/// Tracks a single phase. See [`Progress::start`].
+pub struct PhaseTracker {
+    reporter: Option<Arc<dyn ProgressReporter>>,
+    phase: Phase,
+    total: Option<u64>,
+    done: u64,
+    /// The count at which the next event is sent.
+    next: u64,
+    step: u64,
+    start: Instant,
+    finished: bool,
+}
+
+impl PhaseTracker {
+    /// Mark `n` more items as processed.
+    pub fn advance(&mut self, n: u64) {
+        if self.reporter.is_none() {
+            return;
+        }
+        self.done += n;
+        if self.done >= self.next {
+            self.next = self.done + self.step;
+            self.report(false);
+        }
+    }
+
+    /// Report the end of the phase.
+    pub fn finish(mut self) {
+        self.finish_impl();
+    }
+
+    fn finish_impl(&mut self) {
+        if !self.finished {
+            self.finished = true;
+            self.report(true);
+        }
+    }
+
+    fn report(&self, finished: bool) {
+        if let Some(reporter) = self.reporter.as_ref() {
+            reporter.report(&ProgressEvent {
+                phase: self.phase,
+                done: self.done,
+                total: self.total,
+                elapsed: self.start.elapsed(),
+                finished,
+            });
+        }
+    }
+}
+
+impl Drop for PhaseTracker {
+    fn drop(&mut self) {
+        self.finish_impl();
+    }
+}
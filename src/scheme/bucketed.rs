@@ -0,0 +1,296 @@
+//! This module implements hashed-then-smoothed encryption for high-cardinality identifiers, e.g., emails or SSNs.
+//! Such columns are almost unique, so their distribution is already flat and smoothing them only adds dummies.
+//!
+//! Each message is first mapped to one of `buckets` buckets by a keyed hash (HMAC-SHA256 under a key derived from the
+//! context key), which reduces the cardinality to at most `buckets`. The bucket ids are then smoothed by PFSE. Unlike
+//! [`crate::preprocess::Transform::Hash`], the hash is keyed, so the server cannot map a value to its bucket.
+//!
+//! A search for a message returns every record of its bucket, so some results are false positives that the client
+//! has to filter, e.g., by an additional randomized encryption of the value. The expected rate is reported by
+//! [`BucketStats`] once the dataset is partitioned, and can be estimated beforehand by
+//! [`estimate_false_positive_rate`] to choose the number of buckets.
+
+use std::{collections::HashMap, fmt::Debug, hash::Hash, marker::PhantomData};
+
+use crate::{
+    cipher::SecurityLevel,
+    envelope::Portable,
+    error::FseError,
+    fse::{
+        AsBytes, BaseCrypto, FromBytes, PartitionFrequencySmoothing,
+        TransformStats,
+    },
+    keys::KeyProvider,
+    params::PfseParams,
+    pfse::ContextPFSE,
+    token::TokenSet,
+    util::{build_histogram, hmac, SizeAllocated},
+    Result,
+};
+
+/// A keyed hash of messages into `[0, buckets)`.
+#[derive(Clone)]
+pub struct KeyedBuckets {
+    key: Vec<u8>,
+    buckets: u64,
+}
+
+impl Debug for KeyedBuckets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyedBuckets")
+            .field("buckets", &self.buckets)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyedBuckets {
+    pub fn new(key: &[u8], buckets: u64) -> Result<Self> {
+        if buckets == 0 {
+            return Err(FseError::InvalidParams(
+                "The number of buckets must be positive.".into(),
+            )
+            .into());
+        }
+
+        Ok(Self {
+            key: key.to_vec(),
+            buckets,
+        })
+    }
+
+    /// Derive the hash key from the key of a context, so that only the context key has to be kept.
+    pub fn derive(context_key: &[u8], buckets: u64) -> Result<Self> {
+        Self::new(&hmac(context_key, b"fse-bucket-key"), buckets)
+    }
+
+    pub fn buckets(&self) -> u64 {
+        self.buckets
+    }
+
+    /// The bucket of `message`. The modulo bias is negligible as long as `buckets` is far below `2^64`.
+    pub fn bucket<T: AsBytes>(&self, message: &T) -> u64 {
+        let digest = hmac(&self.key, &message.to_bytes());
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&digest[..8]);
+        u64::from_le_bytes(prefix) % self.buckets
+    }
+}
+
+/// The effect of bucketing on a dataset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BucketStats {
+    /// The number of buckets of the hash.
+    pub buckets: u64,
+    /// The number of buckets holding at least one message.
+    pub occupied: usize,
+    /// The number of distinct messages.
+    pub distinct: usize,
+    /// The number of messages.
+    pub messages: usize,
+    /// The expected fraction of the results of a search that do not match the searched message, for a search of a
+    /// message drawn from the dataset, i.e., `sum_m (c_m / n) * (1 - c_m / c_b(m))` where `c_b(m)` is the count of the
+    /// bucket of `m`.
+    pub false_positive_rate: f64,
+}
+
+impl BucketStats {
+    pub fn new<T>(buckets: &KeyedBuckets, histogram: &HashMap<T, usize>) -> Self
+    where
+        T: AsBytes,
+    {
+        let ids = histogram
+            .iter()
+            .map(|(message, count)| (buckets.bucket(message), *count))
+            .collect::<Vec<_>>();
+        let mut bucket_counts = HashMap::new();
+        for (bucket, count) in ids.iter() {
+            *bucket_counts.entry(*bucket).or_insert(0usize) += count;
+        }
+
+        let messages = histogram.values().sum::<usize>();
+        let false_positive_rate = match messages {
+            0 => 0.0,
+            _ => ids
+                .iter()
+                .map(|(bucket, count)| {
+                    let bucket_count = bucket_counts[bucket] as f64;
+                    let count = *count as f64;
+                    count / messages as f64 * (1.0 - count / bucket_count)
+                })
+                .sum(),
+        };
+
+        Self {
+            buckets: buckets.buckets(),
+            occupied: bucket_counts.len(),
+            distinct: histogram.len(),
+            messages,
+            false_positive_rate,
+        }
+    }
+}
+
+/// Estimate the false-positive rate of [`BucketStats`] before the dataset is known, assuming `distinct` messages of
+/// equal frequency and a uniform hash: each of the other `distinct - 1` messages falls into the bucket of the searched
+/// one with probability `1 / buckets`.
+pub fn estimate_false_positive_rate(distinct: usize, buckets: u64) -> f64 {
+    if distinct == 0 || buckets == 0 {
+        return 0.0;
+    }
+    let collisions = (distinct - 1) as f64 / buckets as f64;
+    collisions / (1.0 + collisions)
+}
+
+/// A context that smooths the keyed buckets of the messages by PFSE. The PFSE context of the bucket ids is exposed by
+/// [`ContextBucketed::get_inner`] for everything not specific to bucketing, e.g., the connection or the local state.
+#[derive(Debug, Clone)]
+pub struct ContextBucketed<T> {
+    inner: ContextPFSE<u64>,
+    buckets: u64,
+    hash: Option<KeyedBuckets>,
+    stats: Option<BucketStats>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> ContextBucketed<T>
+where
+    T: Hash + AsBytes + Eq + Clone,
+{
+    pub fn new(buckets: u64, params: &PfseParams) -> Result<Self> {
+        // Checks the number of buckets early.
+        KeyedBuckets::new(&[], buckets)?;
+
+        let mut inner = ContextPFSE::default();
+        inner.set_params(params)?;
+        Ok(Self {
+            inner,
+            buckets,
+            hash: None,
+            stats: None,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn key_generate(&mut self) {
+        self.inner.key_generate();
+        self.hash =
+            KeyedBuckets::derive(self.inner.get_key(), self.buckets).ok();
+    }
+
+    pub fn set_key(&mut self, key: &[u8]) {
+        self.inner.set_key(key);
+        self.hash = KeyedBuckets::derive(key, self.buckets).ok();
+    }
+
+    /// See [`BaseCrypto::key_from_provider`].
+    pub fn key_from_provider(
+        &mut self,
+        provider: &dyn KeyProvider,
+        name: &str,
+    ) -> Result<()> {
+        self.set_key(
+            &provider
+                .get_key_of_len(name, self.inner.get_cipher().key_len())?,
+        );
+        Ok(())
+    }
+
+    /// See [`BaseCrypto::set_security_level`].
+    pub fn set_security_level(&mut self, level: SecurityLevel) {
+        self.inner.set_security_level(level);
+    }
+
+    pub fn get_inner(&self) -> &ContextPFSE<u64> {
+        &self.inner
+    }
+
+    pub fn get_inner_mut(&mut self) -> &mut ContextPFSE<u64> {
+        &mut self.inner
+    }
+
+    /// The statistics of the partitioned dataset.
+    pub fn get_stats(&self) -> Option<&BucketStats> {
+        self.stats.as_ref()
+    }
+
+    /// The bucket of `message`, or `None` if no key is set.
+    pub fn bucket(&self, message: &T) -> Option<u64> {
+        self.hash.as_ref().map(|hash| hash.bucket(message))
+    }
+
+    /// Bucket the dataset and partition the bucket histogram. Returns the induced false-positive rate among others.
+    pub fn partition(
+        &mut self,
+        input: &[T],
+        partition_func: fn(f64, usize) -> f64,
+    ) -> Result<BucketStats> {
+        let hash = self.hash.as_ref().ok_or(FseError::NotInitialized)?;
+        let histogram = build_histogram(input);
+        let stats = BucketStats::new(hash, &histogram);
+
+        let mut bucket_histogram = HashMap::new();
+        for (message, count) in histogram.iter() {
+            *bucket_histogram.entry(hash.bucket(message)).or_insert(0) += count;
+        }
+        self.inner
+            .partition_histogram(&bucket_histogram, partition_func)?;
+
+        self.stats = Some(stats.clone());
+        Ok(stats)
+    }
+
+    pub fn transform(&mut self) -> TransformStats {
+        self.inner.transform()
+    }
+
+    /// Smooth the bucket ids. The ciphertexts encrypt buckets, not messages.
+    pub fn smooth(&mut self) -> Vec<Vec<u8>> {
+        self.inner.smooth()
+    }
+
+    pub fn encrypt(&mut self, message: &T) -> Option<Vec<Vec<u8>>> {
+        let bucket = self.bucket(message)?;
+        self.inner.encrypt(&bucket)
+    }
+
+    /// The tokens of the bucket of `message`. They match every message of the bucket.
+    pub fn search_tokens(&mut self, message: &T) -> Option<TokenSet> {
+        let bucket = self.bucket(message)?;
+        self.inner.search_tokens(&bucket)
+    }
+
+    /// Decrypt a ciphertext into its bucket.
+    pub fn decrypt_bucket(&self, ciphertext: &[u8]) -> Option<u64> {
+        self.inner
+            .decrypt(ciphertext)
+            .map(|plaintext| u64::from_bytes(&plaintext))
+    }
+
+    /// Seal the key and the local state of the context into an envelope protected by `passphrase`. The bucket hash is
+    /// derived from the key, so the envelope is that of the PFSE context of the bucket ids. See
+    /// [`Portable::export_envelope`].
+    pub fn export_envelope(&self, passphrase: &str) -> Result<Vec<u8>> {
+        self.inner.export_envelope(passphrase)
+    }
+
+    /// Replace the key and the local state of the context by those sealed in `envelope` and derive the bucket hash
+    /// from the key. The statistics of the dataset are not in the envelope and are cleared. See
+    /// [`Portable::import_envelope`].
+    pub fn import_envelope(
+        &mut self,
+        envelope: &[u8],
+        passphrase: &str,
+    ) -> Result<()> {
+        self.inner.import_envelope(envelope, passphrase)?;
+        self.hash =
+            Some(KeyedBuckets::derive(self.inner.get_key(), self.buckets)?);
+        self.stats = None;
+        Ok(())
+    }
+}
+
+impl<T> SizeAllocated for ContextBucketed<T> {
+    fn size_allocated(&self) -> usize {
+        self.inner.size_allocated()
+    }
+}
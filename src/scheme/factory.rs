@@ -0,0 +1,158 @@
+//! This module constructs the context of a scheme chosen at runtime, e.g., by a configuration file, behind a
+//! `Box<dyn BaseCrypto<String>>`. Each scheme but `plain` is compiled in by its cargo feature (`pfse`, `lpfse`, `wre`
+//! and `native` for DTE and RND), so a caller can build the client with the schemes it needs only; asking for a scheme
+//! that is not compiled in fails with [`FseError::SchemeDisabled`] instead of a missing symbol.
+
+use crate::{
+    error::FseError,
+    fse::{exponential, BaseCrypto, PartitionFrequencySmoothing},
+    params::SchemeParams,
+    plain::ContextPlain,
+    util::build_histogram,
+    FSEType, Result,
+};
+
+#[cfg(feature = "lpfse")]
+use crate::lpfse::{ContextLPFSE, EncoderBHE, EncoderIHBE, HomophoneEncoder};
+#[cfg(feature = "native")]
+use crate::native::ContextNative;
+#[cfg(feature = "pfse")]
+use crate::pfse::ContextPFSE;
+#[cfg(feature = "wre")]
+use crate::wre::ContextWRE;
+
+/// The context returned by [`build_context`].
+pub type BoxedContext = Box<dyn BaseCrypto<String>>;
+
+/// The cargo feature that compiles `fse_type` in, or `None` if it is always compiled in.
+pub fn feature(fse_type: &FSEType) -> Option<&'static str> {
+    match fse_type {
+        FSEType::Dte | FSEType::Rnd => Some("native"),
+        FSEType::LpfseIhbe | FSEType::LpfseBhe => Some("lpfse"),
+        FSEType::Pfse => Some("pfse"),
+        FSEType::Wre => Some("wre"),
+        FSEType::Plain => None,
+    }
+}
+
+/// Whether `fse_type` is compiled in.
+pub fn is_enabled(fse_type: &FSEType) -> bool {
+    match fse_type {
+        FSEType::Dte | FSEType::Rnd => cfg!(feature = "native"),
+        FSEType::LpfseIhbe | FSEType::LpfseBhe => cfg!(feature = "lpfse"),
+        FSEType::Pfse => cfg!(feature = "pfse"),
+        FSEType::Wre => cfg!(feature = "wre"),
+        FSEType::Plain => true,
+    }
+}
+
+/// Construct the context of `fse_type` under a fresh key and build it from `dataset`, i.e., partition and transform
+/// it for PFSE or initialize the encoder (LPFSE) or the local table (WRE). `params` are required by the schemes that
+/// have parameters. The context is not connected to any database.
+pub fn build_context(
+    fse_type: &FSEType,
+    dataset: &[String],
+    params: Option<&SchemeParams>,
+) -> Result<BoxedContext> {
+    match fse_type {
+        FSEType::Dte | FSEType::Rnd => build_native(fse_type),
+        FSEType::LpfseIhbe | FSEType::LpfseBhe => {
+            build_lpfse(fse_type, dataset, params)
+        }
+        FSEType::Pfse => build_pfse(dataset, params),
+        FSEType::Wre => build_wre(dataset, params),
+        FSEType::Plain => Ok(Box::new(ContextPlain::new())),
+    }
+}
+
+fn disabled(fse_type: &FSEType) -> FseError {
+    FseError::SchemeDisabled {
+        scheme: fse_type.name().to_string(),
+        feature: feature(fse_type).unwrap_or_default().to_string(),
+    }
+}
+
+fn require(params: Option<&SchemeParams>) -> Result<&SchemeParams> {
+    params.ok_or_else(|| {
+        FseError::InvalidParams("No FSE params found".into()).into()
+    })
+}
+
+#[cfg(feature = "native")]
+fn build_native(fse_type: &FSEType) -> Result<BoxedContext> {
+    let mut ctx = ContextNative::new(fse_type == &FSEType::Rnd);
+    ctx.key_generate();
+    Ok(Box::new(ctx))
+}
+
+#[cfg(not(feature = "native"))]
+fn build_native(fse_type: &FSEType) -> Result<BoxedContext> {
+    Err(disabled(fse_type).into())
+}
+
+#[cfg(feature = "lpfse")]
+fn build_lpfse(
+    fse_type: &FSEType,
+    dataset: &[String],
+    params: Option<&SchemeParams>,
+) -> Result<BoxedContext> {
+    let encoder: Box<dyn HomophoneEncoder<String>> =
+        match fse_type == &FSEType::LpfseBhe {
+            true => Box::new(EncoderBHE::new()),
+            false => Box::new(EncoderIHBE::new()),
+        };
+    let mut ctx =
+        ContextLPFSE::from_params(&require(params)?.lpfse()?, encoder)?;
+    ctx.key_generate();
+    ctx.initialize_histogram(&build_histogram(dataset))?;
+    Ok(Box::new(ctx))
+}
+
+#[cfg(not(feature = "lpfse"))]
+fn build_lpfse(
+    fse_type: &FSEType,
+    _dataset: &[String],
+    _params: Option<&SchemeParams>,
+) -> Result<BoxedContext> {
+    Err(disabled(fse_type).into())
+}
+
+#[cfg(feature = "pfse")]
+fn build_pfse(
+    dataset: &[String],
+    params: Option<&SchemeParams>,
+) -> Result<BoxedContext> {
+    let mut ctx = ContextPFSE::default();
+    ctx.key_generate();
+    ctx.set_params(&require(params)?.pfse()?)?;
+    ctx.partition(dataset, exponential)?;
+    ctx.transform();
+    Ok(Box::new(ctx))
+}
+
+#[cfg(not(feature = "pfse"))]
+fn build_pfse(
+    _dataset: &[String],
+    _params: Option<&SchemeParams>,
+) -> Result<BoxedContext> {
+    Err(disabled(&FSEType::Pfse).into())
+}
+
+#[cfg(feature = "wre")]
+fn build_wre(
+    dataset: &[String],
+    params: Option<&SchemeParams>,
+) -> Result<BoxedContext> {
+    let mut ctx = ContextWRE::from_params(&require(params)?.wre()?)?;
+    ctx.key_generate();
+    ctx.initialize_histogram(&build_histogram(dataset))?;
+    Ok(Box::new(ctx))
+}
+
+#[cfg(not(feature = "wre"))]
+fn build_wre(
+    _dataset: &[String],
+    _params: Option<&SchemeParams>,
+) -> Result<BoxedContext> {
+    Err(disabled(&FSEType::Wre).into())
+}
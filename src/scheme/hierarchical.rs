@@ -0,0 +1,352 @@
+//! This module implements multi-level smoothing for values with a natural hierarchy, e.g., zip codes (`94107` under
+//! `941`) or IP addresses (`10.1.2.3` under `10.1`). Each level of the [`Hierarchy`] generalizes the values to their
+//! prefix of that level and is smoothed independently by its own PFSE context under its own key, so that a query can
+//! ask for all the records of any prefix of the hierarchy, e.g., `941*`, instead of enumerating the values under it.
+//!
+//! The smoothed ciphertexts of each level are stored in a collection of their own (see [`collection_name`]), so the
+//! server learns nothing linking the levels beyond what the queries reveal. The leakage of the levels adds up, which
+//! [`HierarchyLeakage`] reports by the union bound over the per-level advantages.
+
+use rand_core::{OsRng, RngCore};
+
+use crate::{
+    cipher::SecurityLevel,
+    envelope::Portable,
+    error::FseError,
+    fse::{
+        BaseCrypto, FromBytes, PartitionFrequencySmoothing, SearchOutcome,
+        TransformStats,
+    },
+    params::PfseParams,
+    pfse::ContextPFSE,
+    security::advantage_bound,
+    token::TokenSet,
+    util::{build_histogram, hmac, SizeAllocated, StateReader, StateWriter},
+    Result,
+};
+
+/// How the values are generalized to each level. The levels are ordered from the coarsest to the finest one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Hierarchy {
+    /// Each level keeps the first `depth` characters of a value, e.g., `[3, 5]` for zip codes.
+    Prefix(Vec<usize>),
+    /// Each level keeps the first `depth` segments of a value split by `separator`, e.g., `.` and `[1, 2, 3]` for the
+    /// `/8`, `/16` and `/24` prefixes of IPv4 addresses.
+    Segments { separator: char, depths: Vec<usize> },
+}
+
+impl Hierarchy {
+    pub fn depths(&self) -> &[usize] {
+        match self {
+            Self::Prefix(depths) => depths,
+            Self::Segments { depths, .. } => depths,
+        }
+    }
+
+    pub fn level_num(&self) -> usize {
+        self.depths().len()
+    }
+
+    /// Generalize `value` to the given level. A value shorter than the level is kept as it is.
+    pub fn generalize(&self, value: &str, level: usize) -> String {
+        let depth = self.depths()[level];
+        match self {
+            Self::Prefix(_) => value.chars().take(depth).collect(),
+            Self::Segments { separator, .. } => value
+                .split(*separator)
+                .take(depth)
+                .collect::<Vec<_>>()
+                .join(&separator.to_string()),
+        }
+    }
+
+    /// The level a prefix query asks for together with the prefix itself, e.g., level 0 and `941` for `941*` under
+    /// `Prefix([3, 5])`. A trailing wildcard (and separator) is optional. Returns `None` if no level has the depth of
+    /// the query.
+    pub fn level_of(&self, query: &str) -> Option<(usize, String)> {
+        let (prefix, depth) = match self {
+            Self::Prefix(_) => {
+                let prefix = query.strip_suffix('*').unwrap_or(query);
+                (prefix.to_string(), prefix.chars().count())
+            }
+            Self::Segments { separator, .. } => {
+                let prefix = query.strip_suffix('*').unwrap_or(query);
+                let prefix = prefix.strip_suffix(*separator).unwrap_or(prefix);
+                (prefix.to_string(), prefix.split(*separator).count())
+            }
+        };
+        let level = self.depths().iter().position(|&e| e == depth)?;
+        Some((level, prefix))
+    }
+
+    fn check(&self) -> Result<()> {
+        let depths = self.depths();
+        if depths.is_empty() || depths[0] == 0 {
+            return Err(FseError::InvalidParams(
+                "The hierarchy must have a level of a positive depth.".into(),
+            )
+            .into());
+        }
+        if depths.windows(2).any(|e| e[0] >= e[1]) {
+            return Err(FseError::InvalidParams(
+                "The depths of the hierarchy must be strictly increasing."
+                    .into(),
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// The leakage of a single level.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelLeakage {
+    pub depth: usize,
+    /// The number of distinct prefixes of this level.
+    pub distinct: usize,
+    /// The advantage bound of [`crate::security::advantage_bound`] of this level.
+    pub advantage: f64,
+}
+
+/// The leakage of all levels together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HierarchyLeakage {
+    pub levels: Vec<LevelLeakage>,
+    /// The advantage of an attacker that sees every level, bounded by the sum of the per-level advantages and clamped
+    /// into `[0, 1]`.
+    pub combined: f64,
+}
+
+/// The collection the smoothed ciphertexts of `level` are stored in.
+pub fn collection_name(name: &str, level: usize) -> String {
+    format!("{}_level{}", name, level)
+}
+
+/// A context of one PFSE context per level of a [`Hierarchy`]. The key of each level is derived from the key of this
+/// context.
+#[derive(Debug, Clone)]
+pub struct ContextHierarchical {
+    hierarchy: Hierarchy,
+    levels: Vec<ContextPFSE<String>>,
+}
+
+impl ContextHierarchical {
+    pub fn new(hierarchy: Hierarchy, params: &PfseParams) -> Result<Self> {
+        hierarchy.check()?;
+
+        let levels = (0..hierarchy.level_num())
+            .map(|_| {
+                let mut ctx = ContextPFSE::default();
+                ctx.set_params(params)?;
+                Ok(ctx)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { hierarchy, levels })
+    }
+
+    pub fn get_hierarchy(&self) -> &Hierarchy {
+        &self.hierarchy
+    }
+
+    /// The PFSE context of `level`, for everything not specific to the hierarchy, e.g., the local state.
+    pub fn get_level(&self, level: usize) -> Option<&ContextPFSE<String>> {
+        self.levels.get(level)
+    }
+
+    pub fn get_level_mut(
+        &mut self,
+        level: usize,
+    ) -> Option<&mut ContextPFSE<String>> {
+        self.levels.get_mut(level)
+    }
+
+    pub fn key_generate(&mut self) {
+        let mut key = vec![0u8; SecurityLevel::Aes256.key_len()];
+        OsRng.fill_bytes(&mut key);
+        self.set_key(&key);
+    }
+
+    /// Set the key of each level to a key derived from `key`.
+    pub fn set_key(&mut self, key: &[u8]) {
+        for (level, ctx) in self.levels.iter_mut().enumerate() {
+            let label = format!("fse-hierarchy-level-{}", level);
+            let mut level_key = hmac(key, label.as_bytes());
+            level_key.truncate(ctx.get_cipher().key_len());
+            ctx.set_key(&level_key);
+        }
+    }
+
+    /// See [`BaseCrypto::set_security_level`]. The keys must be set afterwards.
+    pub fn set_security_level(&mut self, level: SecurityLevel) {
+        self.levels
+            .iter_mut()
+            .for_each(|ctx| ctx.set_security_level(level));
+    }
+
+    /// Generalize the dataset to each level and partition the histogram of the level.
+    pub fn partition(
+        &mut self,
+        input: &[String],
+        partition_func: fn(f64, usize) -> f64,
+    ) -> Result<()> {
+        for (level, ctx) in self.levels.iter_mut().enumerate() {
+            let generalized = input
+                .iter()
+                .map(|e| self.hierarchy.generalize(e, level))
+                .collect::<Vec<_>>();
+            ctx.partition_histogram(
+                &build_histogram(&generalized),
+                partition_func,
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn transform(&mut self) -> Vec<TransformStats> {
+        self.levels.iter_mut().map(|ctx| ctx.transform()).collect()
+    }
+
+    /// Smooth each level. The ciphertexts of `level` are at index `level`.
+    pub fn smooth(&mut self) -> Vec<Vec<Vec<u8>>> {
+        self.levels.iter_mut().map(|ctx| ctx.smooth()).collect()
+    }
+
+    /// Encrypt `message` at each level. The ciphertexts of `level` are at index `level`.
+    pub fn encrypt(&mut self, message: &str) -> Option<Vec<Vec<Vec<u8>>>> {
+        self.levels
+            .iter_mut()
+            .enumerate()
+            .map(|(level, ctx)| {
+                ctx.encrypt(&self.hierarchy.generalize(message, level))
+            })
+            .collect()
+    }
+
+    /// The level of a prefix query and the tokens matching every record under the prefix at that level. See
+    /// [`Hierarchy::level_of`].
+    pub fn search_tokens(&mut self, query: &str) -> Option<(usize, TokenSet)> {
+        let (level, prefix) = self.hierarchy.level_of(query)?;
+        let tokens = self.levels[level].search_tokens(&prefix)?;
+        Some((level, tokens))
+    }
+
+    /// Decrypt a ciphertext of `level` into the prefix it encrypts.
+    pub fn decrypt(&self, level: usize, ciphertext: &[u8]) -> Option<String> {
+        self.levels
+            .get(level)?
+            .decrypt(ciphertext)
+            .map(|plaintext| String::from_bytes(&plaintext))
+    }
+
+    /// The leakage of each level and of all levels together. Returns `None` if the context has not been partitioned
+    /// and transformed yet.
+    pub fn leakage(&self) -> Option<HierarchyLeakage> {
+        let levels = self
+            .levels
+            .iter()
+            .zip(self.hierarchy.depths())
+            .map(|(ctx, &depth)| {
+                Some(LevelLeakage {
+                    depth,
+                    distinct: ctx.get_local_table().len(),
+                    advantage: advantage_bound(&ctx.scheme_state()?),
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let combined = levels.iter().map(|e| e.advantage).sum::<f64>().min(1.0);
+        Some(HierarchyLeakage { levels, combined })
+    }
+
+    /// Seal the keys and the local states of the levels into an envelope protected by `passphrase`, i.e., the
+    /// envelope of each level in order. See [`Portable::export_envelope`].
+    pub fn export_envelope(&self, passphrase: &str) -> Result<Vec<u8>> {
+        let mut writer = StateWriter::new();
+        writer.put_u64(self.levels.len() as u64);
+        for ctx in self.levels.iter() {
+            writer.put_bytes(&ctx.export_envelope(passphrase)?);
+        }
+        Ok(writer.finish())
+    }
+
+    /// Replace the keys and the local states of the levels by those sealed in `envelope`. Nothing is replaced unless
+    /// every level is imported. See [`Portable::import_envelope`].
+    pub fn import_envelope(
+        &mut self,
+        envelope: &[u8],
+        passphrase: &str,
+    ) -> Result<()> {
+        let mut reader = StateReader::new(envelope);
+        let malformed =
+            || FseError::InvalidEnvelope("malformed hierarchy envelope".into());
+        let len = reader.get_usize().ok_or_else(malformed)?;
+        if len != self.levels.len() {
+            return Err(FseError::InvalidEnvelope(format!(
+                "the envelope holds {} levels, not {}",
+                len,
+                self.levels.len()
+            ))
+            .into());
+        }
+
+        let mut levels = self.levels.clone();
+        for ctx in levels.iter_mut() {
+            ctx.import_envelope(
+                reader.get_bytes().ok_or_else(malformed)?,
+                passphrase,
+            )?;
+        }
+        if !reader.is_empty() {
+            return Err(malformed().into());
+        }
+        self.levels = levels;
+        Ok(())
+    }
+
+    /// Connect the context of each level to the database.
+    #[cfg(feature = "db-mongo")]
+    pub fn initialize_conn(
+        &mut self,
+        address: &str,
+        db_name: &str,
+        drop: bool,
+    ) {
+        self.levels
+            .iter_mut()
+            .for_each(|ctx| ctx.initialize_conn(address, db_name, drop));
+    }
+
+    /// Smooth each level into its collection of `name`. See [`collection_name`].
+    #[cfg(feature = "db-mongo")]
+    pub fn smooth_insert(
+        &mut self,
+        name: &str,
+        batch_size: usize,
+    ) -> Result<()> {
+        for (level, ctx) in self.levels.iter_mut().enumerate() {
+            ctx.smooth_insert(&collection_name(name, level), batch_size)?;
+        }
+        Ok(())
+    }
+
+    /// Search the records under the prefix of `query`, e.g., `941*`. The results are the prefixes of the records at
+    /// the level of the query, one per record. See [`BaseCrypto::search_checked`].
+    #[cfg(feature = "db-mongo")]
+    pub fn search(
+        &mut self,
+        query: &str,
+        name: &str,
+    ) -> Result<SearchOutcome<String>> {
+        let (level, prefix) =
+            self.hierarchy.level_of(query).ok_or_else(|| {
+                format!("No level of the hierarchy matches {}.", query)
+            })?;
+        self.levels[level]
+            .search_checked(&prefix, &collection_name(name, level))
+    }
+}
+
+impl SizeAllocated for ContextHierarchical {
+    fn size_allocated(&self) -> usize {
+        self.levels.iter().map(|e| e.size_allocated()).sum()
+    }
+}
@@ -8,20 +8,35 @@ use std::{
     marker::PhantomData, ops::Range,
 };
 
-use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
-use base64::{engine::general_purpose, Engine};
-use dyn_clone::{clone_box, clone_trait_object, DynClone};
+use dyn_clone::{clone_trait_object, DynClone};
 use itertools::Itertools;
 use log::{debug, error, warn};
-use rand::{distributions::Uniform, prelude::Distribution};
+use rand::prelude::Distribution;
 use rand_core::OsRng;
 
 use crate::{
-    db::{Connector, Data},
-    fse::{AsBytes, BaseCrypto, Conn, FromBytes, HistType, ValueType},
-    util::{build_histogram, build_histogram_vec, compute_cdf, SizeAllocated},
+    cipher::{default_cipher, Cipher, ZERO_NONCE},
+    envelope::Portable,
+    error::FseError,
+    fse::{
+        AsBytes, BaseCrypto, Conn, DatasetFingerprint, Domain, FromBytes,
+        HistType, LocalState, Replicated, TokenLimit, ValueType,
+    },
+    journal::Journal,
+    params::{LpfseParams, SchemeParams},
+    security::{SchemeState, StateLeakage},
+    token::{TokenArena, TokenSet},
+    util::{
+        build_histogram, build_histogram_vec, ceil_eps, checked_div,
+        checked_uniform, compute_cdf, histogram_digest, SizeAllocated,
+        StateReader, StateWriter, EPSILON,
+    },
+    FSEType, Result,
 };
 
+#[cfg(feature = "db-mongo")]
+use crate::db::{Connector, Data};
+
 type IbheKeyType = (usize, Range<u64>);
 
 /// A context that represents the frequency-smoothing encryption scheme proposed by Lachrite and Paterson.
@@ -29,33 +44,38 @@ type IbheKeyType = (usize, Range<u64>);
 /// Note that in order to use FSE for plaintext in any type `T`, you must ensure that `T` has the `Hash` and `AsBytes` trait bounds.
 /// They are required because `Hash` is needed in the local table, and `AsBytes` is used when performing the cryptographic
 /// operations like encryption and pseudorandom string generation.
-#[derive(Debug)]
-pub struct ContextLPFSE<T>
+///
+/// The encoder is a type parameter so that a context built for a known scheme dispatches [`HomophoneEncoder::encode`]
+/// statically on every encryption. It defaults to [`BoxedEncoder`] for contexts whose scheme is only known at runtime.
+#[derive(Debug, Clone)]
+pub struct ContextLPFSE<T, E = BoxedEncoder<T>>
 where
     T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+    E: HomophoneEncoder<T>,
 {
     /// The advantage of an optimal distinguisher that utilizes the K-S test.
     advantage: f64,
     /// A random key.
     key: Vec<u8>,
+    /// The cipher for symmetric encryption.
+    cipher: Box<dyn Cipher>,
+    /// The bound on the tokens of each search.
+    token_limit: Option<TokenLimit>,
     /// The encoder for homophones.
-    encoder: Box<dyn HomophoneEncoder<T>>,
+    encoder: E,
     /// The connector to the database.
+    #[cfg(feature = "db-mongo")]
     conn: Option<Connector<Data>>,
-}
-
-impl<T> Clone for ContextLPFSE<T>
-where
-    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
-{
-    fn clone(&self) -> Self {
-        Self {
-            advantage: self.advantage,
-            key: self.key.clone(),
-            encoder: clone_box(&*self.encoder),
-            conn: self.conn.clone(),
-        }
-    }
+    /// The digest of the histogram this context was built from.
+    digest: Option<Vec<u8>>,
+    /// The journal of the mutations of the encoder.
+    journal: Journal,
+    /// The cap on the bit-length of the homophones, if any.
+    max_bits: Option<u32>,
+    /// The scratch buffer the homophones of a search are built into, kept across searches.
+    arena: TokenArena,
+    /// A dummy data that consumes `T`.
+    _marker: PhantomData<T>,
 }
 
 /// A trait that defines a generic bahavior of encoders.
@@ -64,13 +84,36 @@ where
     T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
 {
     /// Initialize the encoder.
-    fn initialize(&mut self, _messages: &[T], _advantage: f64);
+    fn initialize(&mut self, messages: &[T], advantage: f64) -> Result<()> {
+        self.initialize_histogram(&build_histogram(messages), advantage)
+    }
+
+    /// Initialize the encoder from a histogram `T -> count` of the message dataset. Fails with
+    /// [`FseError::EmptyDataset`] if the histogram is empty. A histogram of a single message gets a single homophone,
+    /// as there is no frequency to smooth.
+    fn initialize_histogram(
+        &mut self,
+        histogram: &HashMap<T, usize>,
+        advantage: f64,
+    ) -> Result<()>;
 
     /// Encode the message and returns one of the homophones from its homophone set.
     fn encode(&mut self, message: &T) -> Option<Vec<u8>>;
 
     /// Encode messages into all possible tokens for search.
-    fn encode_all(&self, message: &T) -> Option<Vec<Vec<u8>>>;
+    fn encode_all(&self, message: &T) -> Option<TokenSet>;
+
+    /// The same as [`HomophoneEncoder::encode_all`], but writes the tokens into `arena` instead of allocating each of
+    /// them. Returns the number of tokens written.
+    fn encode_all_into(
+        &self,
+        message: &T,
+        arena: &mut TokenArena,
+    ) -> Option<usize> {
+        let tokens = self.encode_all(message)?;
+        tokens.iter().for_each(|token| arena.push(token));
+        Some(tokens.len())
+    }
 
     /// Decode the message. Note we do not return `T` directly.
     fn decode(&self, message: &[u8]) -> Option<Vec<u8>>;
@@ -78,10 +121,139 @@ where
     /// Collect the local table for attack.
     /// This is mainly the message -> freq table :)
     fn local_table(&self) -> HashMap<T, usize>;
+
+    /// Whether `message` has homophones.
+    fn contains(&self, message: &T) -> bool {
+        self.local_table().contains_key(message)
+    }
+
+    /// Export the state needed for encoding and search into a compact binary encoding.
+    fn export_state(&self) -> Vec<u8>;
+
+    /// Import the state produced by [`HomophoneEncoder::export_state`]. Returns `None` if the state is malformed.
+    fn import_state(&mut self, state: &[u8]) -> Option<()>;
+
+    /// Get the smoothing state for [`crate::security::advantage_bound`]. Returns `None` if the encoder is not
+    /// initialized.
+    fn scheme_state(&self) -> Option<SchemeState>;
+
+    /// The scheme this encoder implements.
+    fn scheme_type(&self) -> FSEType;
+
+    /// Cap the bit-length of the homophones of the next initialization. Only IHBE supports a cap.
+    fn set_max_bits(&mut self, max_bits: Option<u32>) -> Result<()> {
+        match max_bits {
+            Some(_) => Err(FseError::InvalidParams(format!(
+                "{} does not support max_bits",
+                self.scheme_type().name()
+            ))
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// The advantage actually achieved when the cap of [`HomophoneEncoder::set_max_bits`] was hit on the last
+    /// initialization, or `None` if the target advantage was met.
+    fn residual_advantage(&self) -> Option<f64> {
+        None
+    }
+
+    /// Drop the scratch state that is only needed while the encoder may still change, e.g., to save client memory
+    /// once the dataset has been inserted. Encoders without scratch state do nothing.
+    fn seal(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether the encoder has been sealed and can no longer be initialized.
+    fn is_sealed(&self) -> bool {
+        false
+    }
 }
 
 clone_trait_object!(<T> HomophoneEncoder<T> where T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated);
 
+/// An encoder whose scheme is chosen at runtime.
+pub type BoxedEncoder<T> = Box<dyn HomophoneEncoder<T>>;
+
+impl<T, E> HomophoneEncoder<T> for Box<E>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+    E: HomophoneEncoder<T> + ?Sized,
+    Box<E>: Clone,
+{
+    fn initialize(&mut self, messages: &[T], advantage: f64) -> Result<()> {
+        self.as_mut().initialize(messages, advantage)
+    }
+
+    fn initialize_histogram(
+        &mut self,
+        histogram: &HashMap<T, usize>,
+        advantage: f64,
+    ) -> Result<()> {
+        self.as_mut().initialize_histogram(histogram, advantage)
+    }
+
+    fn encode(&mut self, message: &T) -> Option<Vec<u8>> {
+        self.as_mut().encode(message)
+    }
+
+    fn encode_all(&self, message: &T) -> Option<TokenSet> {
+        self.as_ref().encode_all(message)
+    }
+
+    fn encode_all_into(
+        &self,
+        message: &T,
+        arena: &mut TokenArena,
+    ) -> Option<usize> {
+        self.as_ref().encode_all_into(message, arena)
+    }
+
+    fn decode(&self, message: &[u8]) -> Option<Vec<u8>> {
+        self.as_ref().decode(message)
+    }
+
+    fn local_table(&self) -> HashMap<T, usize> {
+        self.as_ref().local_table()
+    }
+
+    fn contains(&self, message: &T) -> bool {
+        self.as_ref().contains(message)
+    }
+
+    fn export_state(&self) -> Vec<u8> {
+        self.as_ref().export_state()
+    }
+
+    fn import_state(&mut self, state: &[u8]) -> Option<()> {
+        self.as_mut().import_state(state)
+    }
+
+    fn scheme_state(&self) -> Option<SchemeState> {
+        self.as_ref().scheme_state()
+    }
+
+    fn scheme_type(&self) -> FSEType {
+        self.as_ref().scheme_type()
+    }
+
+    fn set_max_bits(&mut self, max_bits: Option<u32>) -> Result<()> {
+        self.as_mut().set_max_bits(max_bits)
+    }
+
+    fn residual_advantage(&self) -> Option<f64> {
+        self.as_ref().residual_advantage()
+    }
+
+    fn seal(&mut self) -> Result<()> {
+        self.as_mut().seal()
+    }
+
+    fn is_sealed(&self) -> bool {
+        self.as_ref().is_sealed()
+    }
+}
+
 /// The encoder for IHBE.
 #[derive(Debug, Clone)]
 pub struct EncoderIHBE<T>
@@ -90,6 +262,10 @@ where
 {
     /// Message -> <cnt, range>
     local_table: HashMap<T, IbheKeyType>,
+    /// The cap on the bit-length `r` of the homophones.
+    max_bits: Option<u32>,
+    /// The advantage achieved under the cap if it was hit.
+    residual_advantage: Option<f64>,
 }
 
 /// The encoder for BHE.
@@ -107,10 +283,37 @@ where
     local_table: HashMap<T, (usize, Vec<u64>)>,
     /// The message number.
     message_num: usize,
+    /// The counts accumulated by [`EncoderBHE::count`] in the counting phase.
+    counts: HashMap<T, usize>,
+    /// The current phase.
+    phase: BhePhase,
     /// A dummy data that consumes `T`.
     _marker: PhantomData<T>,
 }
 
+/// The phases of [`EncoderBHE`]. The bands depend on the whole dataset, so the encoder needs two passes: the
+/// messages are counted first, and only then can they be encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BhePhase {
+    /// The encoder is collecting the histogram and cannot encode yet.
+    Counting,
+    /// The bands are fixed; encoding records the homophones drawn for each message.
+    Ready,
+    /// The same as [`BhePhase::Ready`], but the homophones drawn are no longer recorded and the encoder cannot be
+    /// initialized again.
+    Sealed,
+}
+
+impl std::fmt::Display for BhePhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Counting => write!(f, "counting"),
+            Self::Ready => write!(f, "ready"),
+            Self::Sealed => write!(f, "sealed"),
+        }
+    }
+}
+
 impl<T> EncoderIHBE<T>
 where
     T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
@@ -118,9 +321,16 @@ where
     pub fn new() -> Self {
         Self {
             local_table: HashMap::new(),
+            max_bits: None,
+            residual_advantage: None,
         }
     }
 
+    /// Get the homophones `[start, end)` of `message`.
+    pub fn get_interval(&self, message: &T) -> Option<&Range<u64>> {
+        self.local_table.get(message).map(|(_, range)| range)
+    }
+
     /// This function applies Variant 2 on IHBE strategy which modifies how intervals (homophone sets) are allocated
     /// in such a way thatsmaller encoding bitlengths are possible. This is because some distributions can yield
     /// prohibitively large values of r_{min-1} if f_{D}(m_{1})is relatively tiny.
@@ -146,7 +356,9 @@ where
                     histogram[i].1 =
                         (pow2_rplus1 * message_num as f64).ceil() as usize;
 
-                    scale_factor = (1.0 - cur_frequency) / (1.0 - pow2_rplus1);
+                    scale_factor =
+                        checked_div(1.0 - cur_frequency, 1.0 - pow2_rplus1)
+                            .unwrap_or(scale_factor);
                 }
             } else if is_big_enough {
                 histogram[i].1 =
@@ -161,7 +373,10 @@ where
                     ((histogram[i].1 as f64) * pow2_r).ceil() as usize;
                 let cdf_cur = compute_cdf(i, histogram, message_num);
 
-                scale_factor = (1.0 - cdf_prev) / (1.0 - cdf_cur);
+                // The remaining mass may vanish on the last message.
+                if 1.0 - cdf_cur > EPSILON {
+                    scale_factor = (1.0 - cdf_prev) / (1.0 - cdf_cur);
+                }
             }
         }
     }
@@ -177,9 +392,64 @@ where
             width: 0f64,
             local_table: HashMap::new(),
             message_num: 0usize,
+            counts: HashMap::new(),
+            phase: BhePhase::Counting,
             _marker: PhantomData,
         }
     }
+
+    pub fn phase(&self) -> BhePhase {
+        self.phase
+    }
+
+    pub fn get_length(&self) -> usize {
+        self.length
+    }
+
+    pub fn get_width(&self) -> f64 {
+        self.width
+    }
+
+    /// Get the number of homophones of `message`, i.e., its homophones are `0..band`.
+    pub fn get_band(&self, message: &T) -> Option<u64> {
+        let (frequency, _) = self.local_table.get(message)?;
+        frequency_band(*frequency, self.width, self.message_num)
+    }
+
+    fn phase_error(&self, operation: &str) -> Box<dyn std::error::Error> {
+        Box::new(FseError::InvalidPhase {
+            operation: operation.to_string(),
+            phase: self.phase.to_string(),
+        })
+    }
+
+    /// Count `messages` towards the histogram the bands are computed from. This is the first pass; it can be called
+    /// repeatedly, e.g., once per batch, before [`EncoderBHE::finish_counting`].
+    pub fn count(&mut self, messages: &[T]) -> Result<()> {
+        if self.phase != BhePhase::Counting {
+            return Err(self.phase_error("count messages"));
+        }
+
+        for message in messages.iter() {
+            *self.counts.entry(message.clone()).or_default() += 1;
+        }
+        Ok(())
+    }
+
+    /// Compute the bands from the messages counted so far and move to the ready phase.
+    pub fn finish_counting(&mut self, advantage: f64) -> Result<()> {
+        if self.phase != BhePhase::Counting {
+            return Err(self.phase_error("finish counting"));
+        }
+
+        let counts = std::mem::take(&mut self.counts);
+        let res = self.initialize_histogram(&counts, advantage);
+        if res.is_err() {
+            // Keep the counts so that the caller may count more messages and retry.
+            self.counts = counts;
+        }
+        res
+    }
 }
 
 impl<T> Default for EncoderIHBE<T>
@@ -209,6 +479,7 @@ where
             .iter()
             .map(|(k, v)| k.size_allocated() + (*v).size_allocated())
             .sum::<usize>()
+            + self.counts.size_allocated()
     }
 }
 
@@ -226,28 +497,59 @@ impl<T> HomophoneEncoder<T> for EncoderIHBE<T>
 where
     T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
 {
-    fn initialize(&mut self, messages: &[T], advantage: f64) {
-        if messages.is_empty() {
-            return;
+    fn initialize_histogram(
+        &mut self,
+        histogram: &HashMap<T, usize>,
+        advantage: f64,
+    ) -> Result<()> {
+        if histogram.values().all(|&cnt| cnt == 0) {
+            return Err(FseError::EmptyDataset.into());
+        }
+        if !advantage.is_finite() || advantage <= 0.0 {
+            return Err(invalid_advantage(advantage));
         }
 
         self.local_table.clear();
-        // Construct a histogram from messages.
-        let histogram = build_histogram(messages);
-        let mut histogram_vec = build_histogram_vec(&histogram);
+        self.residual_advantage = None;
+        let mut histogram_vec = build_histogram_vec(histogram);
+        if let [(message, cnt)] = histogram_vec.as_slice() {
+            self.local_table.insert(message.clone(), (*cnt, 0..1));
+            return Ok(());
+        }
         // Also, compute the cumulative frequency for each message.
         let mut sum = 0f64;
-        let n = messages.len();
+        let n = histogram.values().sum::<usize>();
 
         // f_{D}(m_1).
         let least_frequent = histogram_vec.last().unwrap().1 as f64 / n as f64;
         let log_inner = f64::sqrt(n as f64)
             / (2.0 * f64::sqrt(2.0 * PI) * advantage * least_frequent);
-        let r = log_inner.log2().ceil();
+        // Homophones are computed in f64, so more bits than the mantissa are meaningless.
+        let mut r = log_inner
+            .log2()
+            .ceil()
+            .clamp(0.0, f64::MANTISSA_DIGITS as f64);
+        if let Some(max_bits) = self.max_bits {
+            if r > max_bits as f64 {
+                r = max_bits as f64;
+                // Solve the equation of `log_inner` for the advantage under the capped `r`.
+                let residual = (f64::sqrt(n as f64)
+                    / (2.0
+                        * f64::sqrt(2.0 * PI)
+                        * 2f64.powf(r)
+                        * least_frequent))
+                    .min(1.0);
+                warn!(
+                    "The homophones are capped at {} bits; the advantage is {} instead of {}.",
+                    max_bits, residual, advantage
+                );
+                self.residual_advantage = Some(residual);
+            }
+        }
         let pow2_r = 2f64.powf(r);
 
         // Re-adjust the distribution.
-        self.adjust_distribution(&mut histogram_vec, messages.len(), r);
+        self.adjust_distribution(&mut histogram_vec, n, r);
 
         let mut cumulative_frequency = vec![0f64];
         for item in histogram_vec.iter() {
@@ -261,38 +563,35 @@ where
                 .round() as u64;
             let rhs = (pow2_r * cumulative_frequency.get(item.0 + 1).unwrap())
                 .round() as u64;
-            let range = lhs..rhs;
+            // Rounding may collapse the interval of a rare message; every message needs at least one homophone.
+            let range = lhs..rhs.max(lhs + 1);
             let entry = histogram_vec.get(item.0).unwrap();
             self.local_table.insert(entry.0.clone(), (entry.1, range));
         }
+
+        Ok(())
     }
 
     fn encode(&mut self, message: &T) -> Option<Vec<u8>> {
         match self.local_table.get(message) {
             Some((_, interval)) => {
-                let homophone = Uniform::new(interval.start, interval.end)
+                let homophone = checked_uniform(interval.start, interval.end)?
                     .sample(&mut OsRng);
 
                 // Variant 1: Append the homophone to the message.
-                let mut encoded_message = message.as_bytes().to_vec();
-                encoded_message.extend_from_slice(b"|");
-                encoded_message.extend_from_slice(&homophone.to_le_bytes());
-                Some(encoded_message)
+                Some(encode_homophone(message, homophone))
             }
             None => None,
         }
     }
 
-    fn encode_all(&self, message: &T) -> Option<Vec<Vec<u8>>> {
+    fn encode_all(&self, message: &T) -> Option<TokenSet> {
         match self.local_table.get(message) {
             Some((_, interval)) => {
-                let mut ans = Vec::new();
+                let mut ans = TokenSet::new();
                 debug!("interval = {:?}", interval);
                 for i in interval.clone() {
-                    let mut encoded_message = message.as_bytes().to_vec();
-                    encoded_message.extend_from_slice(b"|");
-                    encoded_message.extend_from_slice(&i.to_le_bytes());
-                    ans.push(encoded_message);
+                    ans.insert(encode_homophone(message, i));
                 }
                 Some(ans)
             }
@@ -300,6 +599,19 @@ where
         }
     }
 
+    fn encode_all_into(
+        &self,
+        message: &T,
+        arena: &mut TokenArena,
+    ) -> Option<usize> {
+        let (_, interval) = self.local_table.get(message)?;
+        let prefix = arena.intern(&homophone_prefix(message));
+        for i in interval.clone() {
+            arena.push_prefixed(prefix, &i.to_le_bytes());
+        }
+        Some((interval.end - interval.start) as usize)
+    }
+
     fn decode(&self, message: &[u8]) -> Option<Vec<u8>> {
         // Simply strip the homophone from message.
         Some(
@@ -314,81 +626,168 @@ where
             .map(|(k, v)| (k.clone(), v.0))
             .collect()
     }
+
+    fn contains(&self, message: &T) -> bool {
+        self.local_table.contains_key(message)
+    }
+
+    fn export_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.put_u64(self.local_table.len() as u64);
+        for (message, (cnt, range)) in self.local_table.iter() {
+            writer.put_bytes(&message.to_bytes());
+            writer.put_u64(*cnt as u64);
+            writer.put_u64(range.start);
+            writer.put_u64(range.end);
+        }
+        writer.finish()
+    }
+
+    fn import_state(&mut self, state: &[u8]) -> Option<()> {
+        let mut reader = StateReader::new(state);
+        let mut local_table = HashMap::new();
+        for _ in 0..reader.get_usize()? {
+            let message = T::from_bytes(reader.get_bytes()?);
+            let cnt = reader.get_usize()?;
+            let range = reader.get_u64()?..reader.get_u64()?;
+            local_table.insert(message, (cnt, range));
+        }
+
+        if !reader.is_empty() {
+            return None;
+        }
+        self.local_table = local_table;
+        Some(())
+    }
+
+    fn scheme_state(&self) -> Option<SchemeState> {
+        if self.local_table.is_empty() {
+            return None;
+        }
+        if self.local_table.len() == 1 {
+            return Some(SchemeState::Degenerate {
+                message_num: self.local_table.values().map(|e| e.0).sum(),
+            });
+        }
+
+        Some(SchemeState::Ihbe {
+            message_num: self.local_table.values().map(|e| e.0).sum(),
+            intervals: self
+                .local_table
+                .values()
+                .map(|(_, range)| range.end - range.start)
+                .collect(),
+        })
+    }
+
+    fn scheme_type(&self) -> FSEType {
+        FSEType::LpfseIhbe
+    }
+
+    fn set_max_bits(&mut self, max_bits: Option<u32>) -> Result<()> {
+        self.max_bits = max_bits;
+        Ok(())
+    }
+
+    fn residual_advantage(&self) -> Option<f64> {
+        self.residual_advantage
+    }
 }
 
 impl<T> HomophoneEncoder<T> for EncoderBHE<T>
 where
     T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
 {
-    fn initialize(&mut self, messages: &[T], advantage: f64) {
-        if messages.is_empty() {
-            return;
+    /// Computes the bands and moves to the ready phase. Re-initializing a ready encoder replaces its state as a
+    /// whole; a sealed encoder cannot be initialized again. On invalid input the state is left untouched.
+    fn initialize_histogram(
+        &mut self,
+        histogram: &HashMap<T, usize>,
+        advantage: f64,
+    ) -> Result<()> {
+        if self.phase == BhePhase::Sealed {
+            return Err(self.phase_error("initialize"));
+        }
+        if histogram.values().all(|&cnt| cnt == 0) {
+            return Err(FseError::EmptyDataset.into());
+        }
+        if !advantage.is_finite() || advantage <= 0.0 {
+            return Err(invalid_advantage(advantage));
         }
 
-        // Get the histogram of the messages.
-        let histogram = build_histogram(messages);
         let most_frequent = histogram
             .iter()
             .max_by(|lhs, rhs| lhs.1.cmp(rhs.1))
             .map(|(_, v)| *v)
             .unwrap();
 
-        self.message_num = messages.len();
-        let log2 = f64::log2(
-            self.message_num as f64 / ((2.0 * advantage).powf(2.0) * PI),
-        )
-        .ceil() as usize;
-        self.length = match log2.checked_sub(1) {
-            Some(v) => v,
-            None => {
-                error!("Invalid length: {}", log2);
-                return;
+        let message_num = histogram.values().sum::<usize>();
+        // A single message fills the only band of width 1 with one homophone.
+        let length = match histogram.len() {
+            1 => 0,
+            _ => {
+                let log2 = f64::log2(
+                    message_num as f64 / ((2.0 * advantage).powf(2.0) * PI),
+                )
+                .ceil() as usize;
+                log2.checked_sub(1).ok_or_else(|| {
+                    FseError::InvalidParams(format!(
+                        "advantage {} is too large for {} messages",
+                        advantage, message_num
+                    ))
+                })?
             }
         };
+
+        self.message_num = message_num;
+        self.length = length;
         self.width = most_frequent as f64
             / (self.message_num as f64 * 2f64.powf(self.length as f64));
-
         self.local_table = histogram
-            .into_iter()
-            .map(|(k, v)| (k, (v, vec![])))
+            .iter()
+            .map(|(k, v)| (k.clone(), (*v, vec![])))
             .collect();
+        self.counts.clear();
+        self.phase = BhePhase::Ready;
+        Ok(())
     }
 
     fn encode(&mut self, message: &T) -> Option<Vec<u8>> {
+        if self.phase == BhePhase::Counting {
+            error!("{}", self.phase_error("encode"));
+            return None;
+        }
+
+        let record = self.phase == BhePhase::Ready;
         match self.local_table.get_mut(message) {
             Some((frequency, set)) => {
-                // Compute message m’s frequency band.
-                let band = (*frequency as f64
-                    / (self.width * self.message_num as f64))
-                    .ceil() as u64;
-                let homophone = Uniform::new(0, band).sample(&mut OsRng);
-                set.push(homophone);
+                let band =
+                    frequency_band(*frequency, self.width, self.message_num)?;
+                let homophone = checked_uniform(0, band)?.sample(&mut OsRng);
+                if record {
+                    set.push(homophone);
+                }
 
                 // Construct m as m || t.
-                let mut encoded_message = Vec::new();
-                encoded_message.extend_from_slice(message.as_bytes());
-                encoded_message.extend_from_slice(b"|");
-                encoded_message.extend_from_slice(&homophone.to_le_bytes());
-                Some(encoded_message)
+                Some(encode_homophone(message, homophone))
             }
             None => None,
         }
     }
 
-    fn encode_all(&self, message: &T) -> Option<Vec<Vec<u8>>> {
+    fn encode_all(&self, message: &T) -> Option<TokenSet> {
+        if self.phase == BhePhase::Counting {
+            error!("{}", self.phase_error("encode"));
+            return None;
+        }
+
         match self.local_table.get(message) {
-            Some((frequency, set)) => {
-                // Compute message m’s frequency band.
-                let band = (*frequency as f64
-                    / (self.width * self.message_num as f64))
-                    .ceil() as u64;
-                let mut ans = Vec::new();
+            Some((frequency, _)) => {
+                let band =
+                    frequency_band(*frequency, self.width, self.message_num)?;
+                let mut ans = TokenSet::new();
                 for homophone in 0..band {
-                    let mut encoded_message = Vec::new();
-                    encoded_message.extend_from_slice(message.as_bytes());
-                    encoded_message.extend_from_slice(b"|");
-                    encoded_message.extend_from_slice(&homophone.to_le_bytes());
-                    ans.push(encoded_message);
+                    ans.insert(encode_homophone(message, homophone));
                 }
                 Some(ans)
             }
@@ -396,6 +795,25 @@ where
         }
     }
 
+    fn encode_all_into(
+        &self,
+        message: &T,
+        arena: &mut TokenArena,
+    ) -> Option<usize> {
+        if self.phase == BhePhase::Counting {
+            error!("{}", self.phase_error("encode"));
+            return None;
+        }
+
+        let (frequency, _) = self.local_table.get(message)?;
+        let band = frequency_band(*frequency, self.width, self.message_num)?;
+        let prefix = arena.intern(&homophone_prefix(message));
+        for homophone in 0..band {
+            arena.push_prefixed(prefix, &homophone.to_le_bytes());
+        }
+        Some(band as usize)
+    }
+
     fn decode(&self, message: &[u8]) -> Option<Vec<u8>> {
         // Simply truncate the last l-bits.
         Some(message[..message.len() - std::mem::size_of::<u64>() - 1].to_vec())
@@ -407,81 +825,250 @@ where
             .map(|(k, v)| (k.clone(), v.0))
             .collect()
     }
+
+    fn contains(&self, message: &T) -> bool {
+        self.local_table.contains_key(message)
+    }
+
+    /// The homophones drawn so far are scratch state and are not exported.
+    fn export_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.put_u64(self.length as u64);
+        writer.put_f64(self.width);
+        writer.put_u64(self.message_num as u64);
+        writer.put_u64(self.local_table.len() as u64);
+        for (message, (cnt, _)) in self.local_table.iter() {
+            writer.put_bytes(&message.to_bytes());
+            writer.put_u64(*cnt as u64);
+        }
+        writer.finish()
+    }
+
+    fn import_state(&mut self, state: &[u8]) -> Option<()> {
+        let mut reader = StateReader::new(state);
+        let length = reader.get_usize()?;
+        let width = reader.get_f64()?;
+        let message_num = reader.get_usize()?;
+        let mut local_table = HashMap::new();
+        for _ in 0..reader.get_usize()? {
+            let message = T::from_bytes(reader.get_bytes()?);
+            local_table.insert(message, (reader.get_usize()?, vec![]));
+        }
+
+        if !reader.is_empty() {
+            return None;
+        }
+        self.length = length;
+        self.width = width;
+        self.message_num = message_num;
+        self.local_table = local_table;
+        self.counts.clear();
+        // An imported state has no scratch state, so a sealed encoder stays sealed.
+        if self.phase == BhePhase::Counting {
+            self.phase = BhePhase::Ready;
+        }
+        Some(())
+    }
+
+    fn scheme_state(&self) -> Option<SchemeState> {
+        match self.message_num {
+            0 => None,
+            message_num if self.local_table.len() == 1 => {
+                Some(SchemeState::Degenerate { message_num })
+            }
+            message_num => Some(SchemeState::Bhe {
+                message_num,
+                length: self.length,
+            }),
+        }
+    }
+
+    /// Drops the homophones drawn so far. Fails in the counting phase.
+    fn seal(&mut self) -> Result<()> {
+        if self.phase == BhePhase::Counting {
+            return Err(self.phase_error("seal"));
+        }
+
+        for (_, set) in self.local_table.values_mut() {
+            *set = Vec::new();
+        }
+        self.counts = HashMap::new();
+        self.phase = BhePhase::Sealed;
+        Ok(())
+    }
+
+    fn is_sealed(&self) -> bool {
+        self.phase == BhePhase::Sealed
+    }
+
+    fn scheme_type(&self) -> FSEType {
+        FSEType::LpfseBhe
+    }
 }
 
-impl<T> ContextLPFSE<T>
+impl<T, E> ContextLPFSE<T, E>
 where
     T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+    E: HomophoneEncoder<T>,
 {
-    pub fn new(advantage: f64, encoder: Box<dyn HomophoneEncoder<T>>) -> Self {
+    pub fn new(advantage: f64, encoder: E) -> Self {
         Self {
             advantage,
             key: Vec::new(),
+            cipher: default_cipher(),
+            token_limit: None,
             encoder,
+            #[cfg(feature = "db-mongo")]
             conn: None,
+            digest: None,
+            journal: Journal::new(),
+            max_bits: None,
+            arena: TokenArena::new(),
+            _marker: PhantomData,
         }
     }
 
-    pub fn get_encoder(&self) -> &dyn HomophoneEncoder<T> {
-        self.encoder.as_ref()
+    /// Construct the context from validated parameters.
+    pub fn from_params(params: &LpfseParams, mut encoder: E) -> Result<Self> {
+        params.validate()?;
+        encoder.set_max_bits(params.max_bits)?;
+        let mut ctx = Self::new(params.advantage, encoder);
+        ctx.max_bits = params.max_bits;
+        Ok(ctx)
+    }
+
+    pub fn get_encoder(&self) -> &E {
+        &self.encoder
+    }
+
+    /// Get the smoothing state of the encoder for [`crate::security::advantage_bound`].
+    pub fn scheme_state(&self) -> Option<SchemeState> {
+        self.encoder.scheme_state()
+    }
+
+    /// What the histogram of the encoder reveals if the local state leaks.
+    pub fn state_leakage(&self) -> StateLeakage {
+        let table = self
+            .encoder
+            .local_table()
+            .iter()
+            .map(|(message, &cnt)| (message.to_bytes(), cnt as f64))
+            .collect();
+        StateLeakage::new(table, true)
+    }
+
+    /// Seal the encoder once no more initialization is expected. See [`HomophoneEncoder::seal`].
+    pub fn seal(&mut self) -> Result<()> {
+        self.encoder.seal()
     }
 
     /// Initialize the struct and its connector.
+    #[cfg(feature = "db-mongo")]
     pub fn initialize(
         &mut self,
         messages: &[T],
         address: &str,
         db_name: &str,
         drop: bool,
-    ) {
+    ) -> Result<()> {
         // Initialize the encoder.
-        self.encoder.initialize(messages, self.advantage);
+        self.initialize_histogram(&build_histogram(messages))?;
         // Initialize the connector.
+        self.initialize_conn(address, db_name, drop);
+        Ok(())
+    }
+
+    /// Initialize the encoder only from a histogram of the message dataset. See
+    /// [`HomophoneEncoder::initialize_histogram`].
+    pub fn initialize_histogram(
+        &mut self,
+        histogram: &HashMap<T, usize>,
+    ) -> Result<()> {
+        if self.encoder.is_sealed() {
+            return Err(FseError::InvalidPhase {
+                operation: "initialize".to_string(),
+                phase: "sealed".to_string(),
+            }
+            .into());
+        }
+
+        self.encoder
+            .initialize_histogram(histogram, self.advantage)?;
+        self.digest = Some(histogram_digest(histogram));
+        self.record_snapshot();
+        Ok(())
+    }
+
+    /// Initialize the database.
+    #[cfg(feature = "db-mongo")]
+    pub fn initialize_conn(
+        &mut self,
+        address: &str,
+        db_name: &str,
+        drop: bool,
+    ) {
         if let Ok(conn) = Connector::new(address, db_name, drop) {
             self.conn = Some(conn);
         }
     }
 }
 
-impl<T> Conn for ContextLPFSE<T>
+impl<T, E> Conn for ContextLPFSE<T, E>
 where
     T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+    E: HomophoneEncoder<T>,
 {
+    #[cfg(feature = "db-mongo")]
     fn get_conn(&self) -> &Connector<Data> {
         self.conn.as_ref().unwrap()
     }
 }
 
-impl<T> SizeAllocated for ContextLPFSE<T>
+impl<T, E> SizeAllocated for ContextLPFSE<T, E>
 where
     T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+    E: HomophoneEncoder<T>,
 {
     fn size_allocated(&self) -> usize {
         self.encoder.size_allocated()
     }
 }
 
-impl<T> BaseCrypto<T> for ContextLPFSE<T>
+impl<T, E> BaseCrypto<T> for ContextLPFSE<T, E>
 where
     T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+    E: HomophoneEncoder<T>,
 {
     fn key_generate(&mut self) {
-        self.key = Aes256Gcm::generate_key(&mut OsRng).to_vec();
+        self.key = self.cipher.key_generate();
+    }
+
+    fn set_key(&mut self, key: &[u8]) {
+        self.key = key.to_vec();
+    }
+
+    fn get_key(&self) -> &[u8] {
+        &self.key
+    }
+
+    fn set_cipher(&mut self, cipher: Box<dyn Cipher>) {
+        self.cipher = cipher;
+    }
+
+    fn get_cipher(&self) -> &dyn Cipher {
+        self.cipher.as_ref()
+    }
+
+    fn set_token_limit(&mut self, limit: Option<TokenLimit>) {
+        self.token_limit = limit;
+    }
+
+    fn get_token_limit(&self) -> Option<&TokenLimit> {
+        self.token_limit.as_ref()
     }
 
     fn encrypt(&mut self, message: &T) -> Option<Vec<Vec<u8>>> {
         let mut ciphertexts = Vec::new();
-        let aes = match Aes256Gcm::new_from_slice(&self.key) {
-            Ok(aes) => aes,
-            Err(e) => {
-                error!(
-                    "Error constructing the AES context due to {:?}.",
-                    e.to_string()
-                );
-                return None;
-            }
-        };
-
         let homophone = match self.encoder.encode(message) {
             Some(h) => h,
             None => {
@@ -489,99 +1076,172 @@ where
                 return None;
             }
         };
-        let nonce = Nonce::from_slice(&[0u8; 12]);
-        let ciphertext = match aes.encrypt(nonce, homophone.as_slice()) {
-            Ok(ciphertext) => ciphertext,
-            Err(e) => {
-                error!(
-                    "Error encrypting the message due to {:?}.",
-                    e.to_string()
-                );
-                return None;
-            }
-        };
-        ciphertexts.push(
-            general_purpose::STANDARD_NO_PAD
-                .encode(ciphertext)
-                .into_bytes(),
-        );
+        let ciphertext = self.cipher.encrypt(
+            &self.key,
+            &ZERO_NONCE,
+            homophone.as_slice(),
+        )?;
+        ciphertexts.push(ciphertext);
 
         Some(ciphertexts)
     }
 
     fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
-        let aes = match Aes256Gcm::new_from_slice(&self.key) {
-            Ok(aes) => aes,
-            Err(e) => {
-                panic!(
-                    "[-] Error constructing the AES context due to {:?}.",
-                    e.to_string()
-                );
-            }
-        };
-
-        let nonce = Nonce::from_slice(&[0u8; 12]);
-        let decoded_plaintext =
-            match general_purpose::STANDARD_NO_PAD.decode(ciphertext) {
-                Ok(v) => v,
-                Err(e) => {
-                    error!(
-                        "Error decoding the base64 string due to {:?}.",
-                        e.to_string()
-                    );
+        let plaintext =
+            match self.cipher.decrypt(&self.key, &ZERO_NONCE, ciphertext) {
+                Some(plaintext) => plaintext,
+                None => {
+                    error!("Error decrypting the message.");
                     return None;
                 }
             };
-        let plaintext = match aes.decrypt(nonce, decoded_plaintext.as_slice()) {
-            Ok(plaintext) => plaintext,
-            Err(e) => {
-                error!(
-                    "Error decrypting the message due to {:?}.",
-                    e.to_string()
-                );
-                return None;
-            }
-        };
 
         self.encoder.decode(&plaintext)
     }
 
-    fn search(&mut self, message: &T, name: &str) -> Option<Vec<T>> {
-        match self.encoder.encode_all(message) {
-            Some(homophones) => {
-                let mut ciphertexts = Vec::new();
-                let aes = match Aes256Gcm::new_from_slice(&self.key) {
-                    Ok(aes) => aes,
-                    Err(e) => {
-                        panic!(
-                          "[-] Error constructing the AES context due to {:?}.",
-                          e.to_string()
-                      );
-                    }
-                };
-                let nonce = Nonce::from_slice(&[0u8; 12]);
-
-                for homophone in &homophones {
-                    let ciphertext =
-                        match aes.encrypt(nonce, homophone.as_slice()) {
-                            Ok(ciphertext) => ciphertext,
-                            Err(e) => {
-                                error!(
-                                    "Error encrypting the message due to {:?}.",
-                                    e.to_string()
-                                );
-                                return None;
-                            }
-                        };
-                    ciphertexts.push(
-                        general_purpose::STANDARD_NO_PAD
-                            .encode(ciphertext)
-                            .into_bytes(),
-                    );
-                }
-                self.search_impl(ciphertexts, name)
+    /// The homophones are built into the scratch arena of the context, so only the ciphertexts are allocated.
+    fn search_tokens(&mut self, message: &T) -> Option<TokenSet> {
+        self.arena.clear();
+        self.encoder.encode_all_into(message, &mut self.arena)?;
+        let mut ciphertexts = TokenSet::new();
+        for homophone in self.arena.iter() {
+            let ciphertext =
+                self.cipher.encrypt(&self.key, &ZERO_NONCE, homophone)?;
+            ciphertexts.insert(ciphertext);
+        }
+
+        Some(ciphertexts)
+    }
+}
+
+impl<T, E> LocalState<T> for ContextLPFSE<T, E>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+    E: HomophoneEncoder<T>,
+{
+    fn export_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.put_f64(self.advantage);
+        writer.put_bytes(&self.encoder.export_state());
+        writer.put_bytes(self.digest.as_deref().unwrap_or_default());
+        writer.finish()
+    }
+
+    fn import_state(&mut self, state: &[u8]) -> Result<()> {
+        let mut reader = StateReader::new(state);
+        let malformed = || "Malformed LPFSE state.";
+
+        let advantage = reader.get_f64().ok_or_else(malformed)?;
+        let encoder_state = reader.get_bytes().ok_or_else(malformed)?;
+        let digest = reader.get_bytes().ok_or_else(malformed)?;
+        if !reader.is_empty() {
+            return Err(malformed().into());
+        }
+        self.encoder
+            .import_state(encoder_state)
+            .ok_or_else(malformed)?;
+        self.advantage = advantage;
+        self.digest = match digest.is_empty() {
+            true => None,
+            false => Some(digest.to_vec()),
+        };
+        self.record_snapshot();
+        Ok(())
+    }
+}
+
+impl<T, E> Portable<T> for ContextLPFSE<T, E>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+    E: HomophoneEncoder<T>,
+{
+    fn scheme_name(&self) -> &'static str {
+        self.encoder.scheme_type().name()
+    }
+
+    fn scheme_params(&self) -> Option<SchemeParams> {
+        Some(
+            LpfseParams {
+                advantage: self.advantage,
+                max_bits: self.max_bits,
             }
-            None => None,
+            .into(),
+        )
+    }
+}
+
+impl<T, E> Replicated<T> for ContextLPFSE<T, E>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+    E: HomophoneEncoder<T>,
+{
+    fn get_journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    fn get_journal_mut(&mut self) -> &mut Journal {
+        &mut self.journal
+    }
+}
+
+impl<T, E> Domain<T> for ContextLPFSE<T, E>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+    E: HomophoneEncoder<T>,
+{
+    fn export_domain(&self) -> Vec<T> {
+        self.encoder.local_table().into_keys().collect()
+    }
+
+    fn contains(&self, message: &T) -> bool {
+        self.encoder.contains(message)
+    }
+}
+
+impl<T, E> DatasetFingerprint<T> for ContextLPFSE<T, E>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+    E: HomophoneEncoder<T>,
+{
+    fn get_histogram_digest(&self) -> Option<&[u8]> {
+        self.digest.as_deref()
+    }
+}
+
+/// Compute the frequency band of a message with count `frequency` for BHE, i.e., the number of its homophones. Every
+/// message occupies at least one band. Returns `None` if the encoder is not initialized.
+/// Encode `message` with `homophone` as `message || "|" || homophone`, the homophone being 8 little-endian bytes.
+/// Both IHBE and BHE use this encoding.
+pub(crate) fn encode_homophone<T: AsBytes>(
+    message: &T,
+    homophone: u64,
+) -> Vec<u8> {
+    let mut encoded_message = homophone_prefix(message);
+    encoded_message.extend_from_slice(&homophone.to_le_bytes());
+    encoded_message
+}
+
+/// The bytes every homophone of `message` starts with, i.e., `message || "|"`.
+pub(crate) fn homophone_prefix<T: AsBytes>(message: &T) -> Vec<u8> {
+    let mut prefix = message.to_bytes();
+    prefix.extend_from_slice(b"|");
+    prefix
+}
+
+fn invalid_advantage(advantage: f64) -> Box<dyn std::error::Error> {
+    FseError::InvalidParams(format!("invalid advantage {}", advantage)).into()
+}
+
+fn frequency_band(
+    frequency: usize,
+    width: f64,
+    message_num: usize,
+) -> Option<u64> {
+    match checked_div(frequency as f64, width * message_num as f64) {
+        Some(band) => Some(ceil_eps(band).max(1) as u64),
+        None => {
+            error!("The encoder is not initialized.");
+            None
         }
     }
 }
@@ -4,21 +4,72 @@ use base64::{engine::general_purpose, Engine};
 use num_traits::Num;
 use rand::{distributions::Uniform, prelude::Distribution};
 use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     fse::{AsBytes, FromBytes, Random},
     util::SizeAllocated,
 };
 
+#[cfg(feature = "pfse")]
+pub mod bucketed;
+pub mod factory;
+#[cfg(feature = "pfse")]
+pub mod hierarchical;
+#[cfg(feature = "lpfse")]
 pub mod lpfse;
+#[cfg(feature = "native")]
 pub mod native;
+pub mod params;
+#[cfg(feature = "pfse")]
 pub mod pfse;
+pub mod plain;
+pub mod streaming;
+#[cfg(feature = "wre")]
 pub mod wre;
 
-impl Random for i32 {
-    #[inline(always)]
-    fn random(_len: usize) -> Self {
-        Uniform::new_inclusive(0, Self::MAX).sample(&mut OsRng)
+/// The type of the (frequency-smoothing) encryption scheme.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum FSEType {
+    Dte,
+    Rnd,
+    LpfseIhbe,
+    /// Currently, we do not support it.
+    LpfseBhe,
+    Pfse,
+    Wre,
+    /// No encryption at all; the baseline of the evaluations.
+    Plain,
+}
+
+impl FSEType {
+    /// The snake-case name of the scheme, which is identical to its name in the configuration files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Dte => "dte",
+            Self::Rnd => "rnd",
+            Self::LpfseIhbe => "lpfse_ihbe",
+            Self::LpfseBhe => "lpfse_bhe",
+            Self::Pfse => "pfse",
+            Self::Wre => "wre",
+            Self::Plain => "plain",
+        }
+    }
+
+    /// The scheme of the given [`FSEType::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Self::Dte,
+            Self::Rnd,
+            Self::LpfseIhbe,
+            Self::LpfseBhe,
+            Self::Pfse,
+            Self::Wre,
+            Self::Plain,
+        ]
+        .into_iter()
+        .find(|e| e.name() == name)
     }
 }
 
@@ -33,60 +84,86 @@ impl Random for String {
 
 impl AsBytes for String {
     #[inline(always)]
-    fn as_bytes(&self) -> &[u8] {
-        self.as_bytes()
+    fn byte_len(&self) -> usize {
+        self.len()
     }
-}
 
-impl FromBytes for String {
     #[inline(always)]
-    fn from_bytes(bytes: &[u8]) -> Self {
-        String::from_utf8(bytes.to_vec()).unwrap()
+    fn write_bytes(&self, buf: &mut [u8]) {
+        buf[..self.len()].copy_from_slice(self.as_bytes());
     }
-}
 
-impl FromBytes for i32 {
     #[inline(always)]
-    fn from_bytes(bytes: &[u8]) -> Self {
-        Self::from_ne_bytes(bytes.try_into().unwrap())
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
     }
 }
 
-impl AsBytes for i32 {
-    /// Return the memory representation of this number as a byte array in
-    /// native byte order.
+impl FromBytes for String {
     #[inline(always)]
-    fn as_bytes(&self) -> &[u8] {
-        unsafe {
-            std::slice::from_raw_parts(
-                self.to_ne_bytes().as_ptr(),
-                std::mem::size_of::<Self>(),
-            )
-        }
+    fn from_bytes(bytes: &[u8]) -> Self {
+        String::from_utf8(bytes.to_vec()).unwrap()
     }
 }
 
-impl SizeAllocated for String {
-    fn size_allocated(&self) -> usize {
-        self.len()
-    }
-}
+/// Integers are encoded as their little-endian bytes, so the encoding does not depend on the platform. `usize` is
+/// always encoded in 8 bytes.
+macro_rules! impl_integer {
+    ($($ty:ty => $repr:ty),* $(,)?) => {$(
+        impl AsBytes for $ty {
+            #[inline(always)]
+            fn byte_len(&self) -> usize {
+                std::mem::size_of::<$repr>()
+            }
+
+            #[inline(always)]
+            fn write_bytes(&self, buf: &mut [u8]) {
+                let bytes = (*self as $repr).to_le_bytes();
+                buf[..bytes.len()].copy_from_slice(&bytes);
+            }
+        }
 
-impl SizeAllocated for usize {
-    fn size_allocated(&self) -> usize {
-        std::mem::size_of::<Self>()
-    }
-}
+        impl FromBytes for $ty {
+            /// Panics if `bytes` is not exactly as long as the encoding.
+            #[inline(always)]
+            fn from_bytes(bytes: &[u8]) -> Self {
+                <$repr>::from_le_bytes(bytes.try_into().unwrap()) as $ty
+            }
+        }
 
-impl SizeAllocated for u8 {
-    fn size_allocated(&self) -> usize {
-        std::mem::size_of::<Self>()
-    }
+        impl Random for $ty {
+            /// A non-negative number drawn uniformly.
+            #[inline(always)]
+            fn random(_len: usize) -> Self {
+                Uniform::new_inclusive(0, <$ty>::MAX).sample(&mut OsRng)
+            }
+        }
+
+        impl SizeAllocated for $ty {
+            fn size_allocated(&self) -> usize {
+                std::mem::size_of::<Self>()
+            }
+        }
+    )*};
 }
 
-impl SizeAllocated for u64 {
+impl_integer!(
+    i8 => i8,
+    i16 => i16,
+    i32 => i32,
+    i64 => i64,
+    i128 => i128,
+    u8 => u8,
+    u16 => u16,
+    u32 => u32,
+    u64 => u64,
+    u128 => u128,
+    usize => u64,
+);
+
+impl SizeAllocated for String {
     fn size_allocated(&self) -> usize {
-        std::mem::size_of::<Self>()
+        self.len()
     }
 }
 
@@ -120,6 +197,15 @@ where
     }
 }
 
+impl<T> SizeAllocated for Box<T>
+where
+    T: SizeAllocated + ?Sized,
+{
+    fn size_allocated(&self) -> usize {
+        self.as_ref().size_allocated()
+    }
+}
+
 impl<T, U> SizeAllocated for (T, U)
 where
     T: SizeAllocated,
@@ -1,19 +1,34 @@
 //! This module mainly implements a baseline deterministic encryption algorithm that does NOT hide the frequency
 //! of the message dataset it receives.
+//!
+//! RND encrypts the `i`-th occurrence of a message under the nonce `PRF(key, message || i)`, so the context only keeps
+//! the number of occurrences of each message and regenerates the nonces of a search from it. A hardened context keys
+//! these counters by keyed hashes of the messages, so a leaked state does not name the messages it counts.
 
-use std::{collections::HashMap, fmt::Debug, hash::Hash, marker::PhantomData};
+use std::{
+    collections::HashMap, fmt::Debug, hash::Hash, marker::PhantomData, thread,
+};
 
-use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
-use base64::{engine::general_purpose, Engine};
-use log::{debug, error};
-use rand_core::{OsRng, RngCore};
+use log::debug;
 
 use crate::{
-    db::{Connector, Data},
-    fse::{AsBytes, BaseCrypto, Conn, FromBytes},
-    util::SizeAllocated,
+    cipher::{default_cipher, Cipher, NONCE_LEN, ZERO_NONCE},
+    envelope::Portable,
+    fse::{
+        AsBytes, BaseCrypto, Conn, FromBytes, LocalState, Replicated,
+        TokenLimit,
+    },
+    journal::{Journal, JournalOp},
+    params::SchemeParams,
+    security::StateLeakage,
+    token::TokenSet,
+    util::{hmac, keyed_tag, SizeAllocated, StateReader, StateWriter},
+    FSEType, Result,
 };
 
+#[cfg(feature = "db-mongo")]
+use crate::db::{Connector, Data};
+
 #[derive(Debug, Clone)]
 pub struct ContextNative<T>
 where
@@ -21,12 +36,22 @@ where
 {
     /// The secret key for symmetric encryption.
     key: Vec<u8>,
+    /// The cipher for symmetric encryption.
+    cipher: Box<dyn Cipher>,
+    /// The bound on the tokens of each search.
+    token_limit: Option<TokenLimit>,
     /// Connector to the database.
+    #[cfg(feature = "db-mongo")]
     conn: Option<Connector<Data>>,
     /// Whether we use RND.
     rnd: bool,
-    /// A local table for nonce lookup.
-    local_table: HashMap<T, Vec<Vec<u8>>>,
+    /// The number of RND encryptions of each message, i.e., the counter of its next nonce, keyed by the bytes of the
+    /// message or by its keyed hash if hardened.
+    local_table: HashMap<Vec<u8>, u64>,
+    hardened: bool,
+    /// The journal of the counters.
+    journal: Journal,
+    _marker: PhantomData<T>,
 }
 
 impl<T> ContextNative<T>
@@ -36,12 +61,110 @@ where
     pub fn new(rnd: bool) -> Self {
         Self {
             key: Vec::new(),
+            cipher: default_cipher(),
+            token_limit: None,
+            #[cfg(feature = "db-mongo")]
             conn: None,
             rnd,
             local_table: HashMap::new(),
+            hardened: false,
+            journal: Journal::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Key the counters by keyed hashes of the messages instead of the messages themselves. The key must not change
+    /// afterwards. Fails if some message has been encrypted already.
+    pub fn set_hardened(&mut self, hardened: bool) -> Result<()> {
+        if !self.local_table.is_empty() {
+            return Err("The local table is already built.".into());
+        }
+
+        self.hardened = hardened;
+        Ok(())
+    }
+
+    pub fn is_hardened(&self) -> bool {
+        self.hardened
+    }
+
+    /// What the counters reveal if the local state leaks.
+    pub fn state_leakage(&self) -> StateLeakage {
+        let table = self
+            .local_table
+            .iter()
+            .map(|(message, &counter)| (message.clone(), counter as f64))
+            .collect();
+        StateLeakage::new(table, !self.hardened)
+    }
+
+    /// The key of `message` in the local table.
+    fn table_key(&self, message: &T) -> Vec<u8> {
+        match self.hardened {
+            true => keyed_tag(&self.key, &message.to_bytes()),
+            false => message.to_bytes(),
         }
     }
 
+    /// Encrypt `messages` in order on up to `threads` threads. The nonces are reserved from the counters before any
+    /// thread starts, so the ciphertexts are those of encrypting the messages one by one.
+    pub fn encrypt_batch(
+        &mut self,
+        messages: &[T],
+        threads: usize,
+    ) -> Option<Vec<Vec<u8>>>
+    where
+        T: Sync,
+    {
+        let nonces = messages
+            .iter()
+            .map(|message| self.next_nonce(message))
+            .collect::<Vec<_>>();
+        let chunk = messages.len() / threads.max(1) + 1;
+        let (cipher, key) = (self.cipher.as_ref(), self.key.as_slice());
+        thread::scope(|s| {
+            let handles = messages
+                .chunks(chunk)
+                .zip(nonces.chunks(chunk))
+                .map(|(messages, nonces)| {
+                    s.spawn(move || {
+                        messages
+                            .iter()
+                            .zip(nonces)
+                            .map(|(message, nonce)| {
+                                cipher.encrypt(key, nonce, &message.to_bytes())
+                            })
+                            .collect::<Option<Vec<_>>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut ciphertexts = Vec::with_capacity(messages.len());
+            for handle in handles {
+                ciphertexts.extend(handle.join().unwrap()?);
+            }
+            Some(ciphertexts)
+        })
+    }
+
+    /// Reserve the nonce of the next encryption of `message`.
+    fn next_nonce(&mut self, message: &T) -> Vec<u8> {
+        if !self.rnd {
+            return ZERO_NONCE.to_vec();
+        }
+
+        let table_key = self.table_key(message);
+        let counter = self.local_table.entry(table_key.clone()).or_default();
+        let nonce = derive_nonce(&self.key, &message.to_bytes(), *counter);
+        *counter += 1;
+        // The journal names the entry the same way as the table, so a hardened journal does not leak either.
+        self.journal.record(JournalOp::Update {
+            message: table_key,
+            value: counter.to_le_bytes().to_vec(),
+        });
+        nonce
+    }
+
+    #[cfg(feature = "db-mongo")]
     pub fn initialize_conn(
         &mut self,
         address: &str,
@@ -54,6 +177,15 @@ where
     }
 }
 
+/// The nonce of the `counter`-th RND encryption of `message`. The PRF key is derived from the context key.
+fn derive_nonce(key: &[u8], message: &[u8], counter: u64) -> Vec<u8> {
+    let mut input = message.to_vec();
+    input.extend_from_slice(&counter.to_le_bytes());
+    let mut nonce = hmac(&hmac(key, b"fse-rnd-nonce-key"), &input);
+    nonce.truncate(NONCE_LEN);
+    nonce
+}
+
 impl<T> Default for ContextNative<T>
 where
     T: AsBytes + FromBytes + Debug + Eq + Hash + Clone + SizeAllocated,
@@ -67,6 +199,7 @@ impl<T> Conn for ContextNative<T>
 where
     T: AsBytes + FromBytes + Debug + Eq + Hash + Clone + SizeAllocated,
 {
+    #[cfg(feature = "db-mongo")]
     fn get_conn(&self) -> &Connector<Data> {
         self.conn.as_ref().unwrap()
     }
@@ -79,7 +212,7 @@ where
     fn size_allocated(&self) -> usize {
         self.local_table
             .iter()
-            .map(|(k, v)| k.size_allocated() + v.size_allocated())
+            .map(|(k, v)| k.len() + std::mem::size_of_val(v))
             .sum()
     }
 }
@@ -89,113 +222,151 @@ where
     T: AsBytes + FromBytes + Debug + Eq + Hash + Clone + SizeAllocated,
 {
     fn key_generate(&mut self) {
-        self.key.clear();
-        self.key = Aes256Gcm::generate_key(OsRng).to_vec();
+        self.key = self.cipher.key_generate();
+    }
+
+    fn set_key(&mut self, key: &[u8]) {
+        self.key = key.to_vec();
+    }
+
+    fn get_key(&self) -> &[u8] {
+        &self.key
+    }
+
+    fn set_cipher(&mut self, cipher: Box<dyn Cipher>) {
+        self.cipher = cipher;
+    }
+
+    fn get_cipher(&self) -> &dyn Cipher {
+        self.cipher.as_ref()
+    }
+
+    fn set_token_limit(&mut self, limit: Option<TokenLimit>) {
+        self.token_limit = limit;
+    }
+
+    fn get_token_limit(&self) -> Option<&TokenLimit> {
+        self.token_limit.as_ref()
+    }
+
+    fn token_count(&self, message: &T) -> Option<usize> {
+        match self.rnd {
+            true => self
+                .local_table
+                .get(&self.table_key(message))
+                .map(|&e| e as usize),
+            false => Some(1),
+        }
     }
 
     fn encrypt(&mut self, message: &T) -> Option<Vec<Vec<u8>>> {
-        let aes = match Aes256Gcm::new_from_slice(&self.key) {
-            Ok(aes) => aes,
-            Err(e) => {
-                error!(
-                    "[-] Error constructing the AES context due to {:?}.",
-                    e.to_string()
-                );
-                return None;
-            }
-        };
-        let nonce = match self.rnd {
-            true => {
-                let mut buf = vec![0u8; 12];
-                OsRng.fill_bytes(&mut buf);
-                let nonce = Nonce::clone_from_slice(buf.as_slice());
-                self.local_table
-                    .entry(message.clone())
-                    .or_default()
-                    .push(buf);
-
-                nonce
-            }
-            false => Nonce::clone_from_slice(&[0u8; 12]),
-        };
-        let ciphertext = match aes.encrypt(&nonce, message.as_bytes()) {
-            Ok(v) => v,
-            Err(e) => {
-                error!("[-] Error when encrypting the message due to {:?}", e);
-                return None;
-            }
-        };
+        let nonce = self.next_nonce(message);
+        let ciphertext =
+            self.cipher
+                .encrypt(&self.key, &nonce, &message.to_bytes())?;
 
-        Some(vec![general_purpose::STANDARD_NO_PAD
-            .encode(ciphertext)
-            .into_bytes()])
+        Some(vec![ciphertext])
     }
 
     fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
-        let aes = match Aes256Gcm::new_from_slice(&self.key) {
-            Ok(aes) => aes,
-            Err(e) => {
-                error!(
-                    "[-] Error constructing the AES context due to {:?}.",
-                    e.to_string()
-                );
-                return None;
-            }
-        };
-
         // HACK: We do not 'literally' decrypt the message as the management of nonces is complex.
-        let nonce = Nonce::from_slice(&[0u8; 12]);
-        let decoded_ciphertext =
-            match general_purpose::STANDARD_NO_PAD.decode(ciphertext) {
-                Ok(v) => v,
-                Err(e) => {
-                    error!(
-                        "[-] Error decoding the base64 string due to {:?}.",
-                        e.to_string()
-                    );
-                    return None;
-                }
-            };
-        let plaintext = match aes.decrypt(nonce, decoded_ciphertext.as_slice())
-        {
-            Ok(v) => v,
-            Err(e) => return None,
-        };
-
-        Some(plaintext)
-    }
-
-    fn search(&mut self, message: &T, name: &str) -> Option<Vec<T>> {
-        let aes = match Aes256Gcm::new_from_slice(&self.key) {
-            Ok(aes) => aes,
-            Err(e) => {
-                println!(
-                    "[-] Error constructing the AES context due to {:?}.",
-                    e.to_string()
-                );
-                return None;
-            }
-        };
-
-        if self.rnd {
-            let nonces = self.local_table.get(message).unwrap();
-            let ciphertexts = nonces
-                .iter()
-                .map(|e| {
-                    let nonce = Nonce::from_slice(e);
-                    let ciphertext =
-                        aes.encrypt(nonce, message.as_bytes()).unwrap();
-                    general_purpose::STANDARD_NO_PAD
-                        .encode(ciphertext)
-                        .into_bytes()
-                })
-                .collect::<Vec<_>>();
-            debug!("Ciphertext size = {}", ciphertexts.len());
-            self.search_impl(ciphertexts, name)
-        } else {
-            let ciphertext = self.encrypt(message).unwrap();
-            debug!("Ciphertext size = {}", ciphertext.len());
-            self.search_impl(ciphertext, name)
+        self.cipher.decrypt(&self.key, &ZERO_NONCE, ciphertext)
+    }
+
+    fn search_tokens(&mut self, message: &T) -> Option<TokenSet> {
+        if !self.rnd {
+            return self.encrypt(message).map(TokenSet::from);
+        }
+
+        let counter = *self.local_table.get(&self.table_key(message))?;
+        let bytes = message.to_bytes();
+        let ciphertexts = (0..counter)
+            .map(|counter| {
+                let nonce = derive_nonce(&self.key, &bytes, counter);
+                self.cipher.encrypt(&self.key, &nonce, &bytes)
+            })
+            .collect::<Option<TokenSet>>()?;
+        debug!("Ciphertext size = {}", ciphertexts.len());
+        Some(ciphertexts)
+    }
+}
+
+impl<T> LocalState<T> for ContextNative<T>
+where
+    T: AsBytes + FromBytes + Debug + Eq + Hash + Clone + SizeAllocated,
+{
+    fn export_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        // The low bit tells RND from DTE and the next one a hardened table.
+        writer.put_u64(self.rnd as u64 | (self.hardened as u64) << 1);
+        writer.put_u64(self.local_table.len() as u64);
+        for (message, counter) in self.local_table.iter() {
+            writer.put_bytes(message);
+            writer.put_u64(*counter);
+        }
+        writer.finish()
+    }
+
+    fn import_state(&mut self, state: &[u8]) -> Result<()> {
+        let mut reader = StateReader::new(state);
+        let malformed = || "Malformed native state.";
+
+        let flags = reader.get_u64().ok_or_else(malformed)?;
+        let len = reader.get_usize().ok_or_else(malformed)?;
+        let mut local_table = HashMap::new();
+        for _ in 0..len {
+            let message = reader.get_bytes().ok_or_else(malformed)?.to_vec();
+            let counter = reader.get_u64().ok_or_else(malformed)?;
+            local_table.insert(message, counter);
         }
+        if !reader.is_empty() {
+            return Err(malformed().into());
+        }
+
+        self.rnd = flags & 1 != 0;
+        self.hardened = flags & 2 != 0;
+        self.local_table = local_table;
+        self.record_snapshot();
+        Ok(())
+    }
+}
+
+impl<T> Portable<T> for ContextNative<T>
+where
+    T: AsBytes + FromBytes + Debug + Eq + Hash + Clone + SizeAllocated,
+{
+    fn scheme_name(&self) -> &'static str {
+        match self.rnd {
+            true => FSEType::Rnd.name(),
+            false => FSEType::Dte.name(),
+        }
+    }
+
+    /// Neither DTE nor RND has parameters.
+    fn scheme_params(&self) -> Option<SchemeParams> {
+        None
+    }
+}
+
+impl<T> Replicated<T> for ContextNative<T>
+where
+    T: AsBytes + FromBytes + Debug + Eq + Hash + Clone + SizeAllocated,
+{
+    fn get_journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    fn get_journal_mut(&mut self) -> &mut Journal {
+        &mut self.journal
+    }
+
+    /// An update advances the counter of `message` (its keyed hash if hardened) to `value`; an older counter is
+    /// ignored.
+    fn apply_update(&mut self, message: &[u8], value: &[u8]) -> Result<()> {
+        let value = <[u8; 8]>::try_from(value)
+            .map_err(|_| "Malformed native update.")?;
+        let counter = self.local_table.entry(message.to_vec()).or_default();
+        *counter = (*counter).max(u64::from_le_bytes(value));
+        Ok(())
     }
 }
@@ -0,0 +1,335 @@
+//! This module defines the typed parameters of each scheme. They can be deserialized directly from the configuration
+//! files and are validated before a context accepts them.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::FseError, Result};
+
+/// The parameters of PFSE.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PfseParams {
+    /// The parameter of the partition function.
+    pub lambda: f64,
+    /// The scaling factor k_0.
+    pub scale: f64,
+    /// The upper-bound of the advantage relative to the baseline, e.g., 0.1 means the advantage should be no larger
+    /// than 0.1 * baseline.
+    pub advantage: f64,
+    /// How the histogram is cut into partitions.
+    #[serde(default, skip_serializing_if = "PartitionShape::is_exponential")]
+    pub shape: PartitionShape,
+}
+
+/// How PFSE cuts the histogram, sorted by descending frequency, into partitions. The exponential rule yields
+/// pathological partition counts on some distributions, e.g., a single partition for a flat histogram, so the other
+/// shapes fix the mass or the number of messages of each partition instead. In the configuration files it is written
+/// as `shape = "exponential"`, `shape = { equal_mass = 8 }` or `shape = { equal_width = 16 }`.
+#[derive(
+    Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionShape {
+    /// The i-th partition holds `scale * f(lambda, i)` of the mass, where `f` is the partition function passed to
+    /// the context, e.g., [`crate::fse::exponential`].
+    #[default]
+    Exponential,
+    /// The given number of partitions each hold the same mass. A message on the boundary of two partitions is split.
+    EqualMass(usize),
+    /// Each partition holds the given number of distinct messages, and the last one holds the rest.
+    EqualWidth(usize),
+}
+
+/// The parameters of LPFSE.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LpfseParams {
+    /// The advantage of an optimal distinguisher that utilizes the K-S test.
+    pub advantage: f64,
+    /// The cap on the bit-length of the IHBE homophones. Skewed data may need so many homophones that searching
+    /// them all is infeasible; a capped encoder settles for a larger advantage instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bits: Option<u32>,
+}
+
+/// The parameters of WRE.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct WreParams {
+    /// The parameter for the Poisson salt allocation.
+    pub lambda: usize,
+    /// How the salts are allocated.
+    #[serde(default, skip_serializing_if = "SaltAllocation::is_bucketized")]
+    pub allocation: SaltAllocation,
+}
+
+/// How WRE splits the frequency of each message among the search tags of its salts. The tag frequencies are drawn
+/// from `Exp(lambda)`, so that about `lambda` tags cover the whole dataset. In the configuration files it is written as
+/// `allocation = "fixed"` or `allocation = "bucketized"`.
+#[derive(
+    Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SaltAllocation {
+    /// Fixed Poisson: each message draws its own tag frequencies until its frequency is used up. The tags of a
+    /// message sum up to its count, which the counting attack of Lacharité and Paterson exploits; see
+    /// [`crate::attack::SaltCountAttacker`].
+    Fixed,
+    /// Bucketized Poisson: the messages are laid out on `[0, 1)` in random order and cut by a single sequence of
+    /// buckets, and the tags of a message are its overlaps with the buckets.
+    #[default]
+    Bucketized,
+}
+
+/// The parameters of the streaming scheme.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct StreamingParams {
+    /// The number of messages encrypted in each epoch before the smoothing parameters are recomputed.
+    pub epoch_size: usize,
+    /// The number of epochs covered by the sliding window.
+    pub window: usize,
+    /// Messages whose frequency within the window exceeds `1 / heavy_hitters` are smoothed.
+    pub heavy_hitters: usize,
+    /// The width of the count-min sketch.
+    pub width: usize,
+    /// The depth of the count-min sketch.
+    pub depth: usize,
+}
+
+/// The parameters of any scheme as they appear in the configuration files. The variant is inferred from the fields.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum SchemeParams {
+    Pfse(PfseParams),
+    Lpfse(LpfseParams),
+    Wre(WreParams),
+    Streaming(StreamingParams),
+}
+
+impl PfseParams {
+    pub fn new(lambda: f64, scale: f64, advantage: f64) -> Self {
+        Self {
+            lambda,
+            scale,
+            advantage,
+            shape: PartitionShape::Exponential,
+        }
+    }
+
+    /// Cut the histogram into partitions of the given shape. See [`PartitionShape`].
+    pub fn with_shape(mut self, shape: PartitionShape) -> Self {
+        self.shape = shape;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        check_positive("lambda", self.lambda)?;
+        check_positive("scale", self.scale)?;
+        check_advantage(self.advantage)?;
+        match self.shape {
+            PartitionShape::Exponential => Ok(()),
+            PartitionShape::EqualMass(partitions) => {
+                check_nonzero("equal_mass", partitions)
+            }
+            PartitionShape::EqualWidth(messages) => {
+                check_nonzero("equal_width", messages)
+            }
+        }
+    }
+}
+
+impl SaltAllocation {
+    pub fn is_bucketized(&self) -> bool {
+        matches!(self, Self::Bucketized)
+    }
+}
+
+impl PartitionShape {
+    pub fn is_exponential(&self) -> bool {
+        matches!(self, Self::Exponential)
+    }
+}
+
+impl LpfseParams {
+    pub fn new(advantage: f64) -> Self {
+        Self {
+            advantage,
+            max_bits: None,
+        }
+    }
+
+    /// Cap the bit-length of the IHBE homophones. See [`LpfseParams::max_bits`].
+    pub fn with_max_bits(mut self, max_bits: u32) -> Self {
+        self.max_bits = Some(max_bits);
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        check_advantage(self.advantage)?;
+        match self.max_bits {
+            Some(bits) if bits == 0 || bits > f64::MANTISSA_DIGITS => {
+                Err(invalid(&format!(
+                    "max_bits must be in [1, {}], got {}",
+                    f64::MANTISSA_DIGITS,
+                    bits
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl WreParams {
+    pub fn new(lambda: usize) -> Self {
+        Self {
+            lambda,
+            allocation: SaltAllocation::Bucketized,
+        }
+    }
+
+    pub fn with_allocation(mut self, allocation: SaltAllocation) -> Self {
+        self.allocation = allocation;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        check_nonzero("lambda", self.lambda)
+    }
+}
+
+impl StreamingParams {
+    pub fn new(
+        epoch_size: usize,
+        window: usize,
+        heavy_hitters: usize,
+        width: usize,
+        depth: usize,
+    ) -> Self {
+        Self {
+            epoch_size,
+            window,
+            heavy_hitters,
+            width,
+            depth,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        check_nonzero("epoch_size", self.epoch_size)?;
+        check_nonzero("window", self.window)?;
+        check_nonzero("heavy_hitters", self.heavy_hitters)?;
+        check_nonzero("width", self.width)?;
+        check_nonzero("depth", self.depth)
+    }
+}
+
+impl SchemeParams {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Self::Pfse(params) => params.validate(),
+            Self::Lpfse(params) => params.validate(),
+            Self::Wre(params) => params.validate(),
+            Self::Streaming(params) => params.validate(),
+        }
+    }
+
+    /// Get the validated parameters of PFSE.
+    pub fn pfse(&self) -> Result<PfseParams> {
+        match self {
+            Self::Pfse(params) => params.validate().map(|_| *params),
+            _ => Err(invalid(&format!(
+                "expected PFSE parameters, got {:?}",
+                self
+            ))),
+        }
+    }
+
+    /// Get the validated parameters of LPFSE.
+    pub fn lpfse(&self) -> Result<LpfseParams> {
+        match self {
+            Self::Lpfse(params) => params.validate().map(|_| *params),
+            _ => Err(invalid(&format!(
+                "expected LPFSE parameters, got {:?}",
+                self
+            ))),
+        }
+    }
+
+    /// Get the validated parameters of WRE.
+    pub fn wre(&self) -> Result<WreParams> {
+        match self {
+            Self::Wre(params) => params.validate().map(|_| *params),
+            _ => Err(invalid(&format!(
+                "expected WRE parameters, got {:?}",
+                self
+            ))),
+        }
+    }
+
+    /// Get the validated parameters of the streaming scheme.
+    pub fn streaming(&self) -> Result<StreamingParams> {
+        match self {
+            Self::Streaming(params) => params.validate().map(|_| *params),
+            _ => Err(invalid(&format!(
+                "expected streaming parameters, got {:?}",
+                self
+            ))),
+        }
+    }
+}
+
+impl From<PfseParams> for SchemeParams {
+    fn from(params: PfseParams) -> Self {
+        Self::Pfse(params)
+    }
+}
+
+impl From<LpfseParams> for SchemeParams {
+    fn from(params: LpfseParams) -> Self {
+        Self::Lpfse(params)
+    }
+}
+
+impl From<WreParams> for SchemeParams {
+    fn from(params: WreParams) -> Self {
+        Self::Wre(params)
+    }
+}
+
+impl From<StreamingParams> for SchemeParams {
+    fn from(params: StreamingParams) -> Self {
+        Self::Streaming(params)
+    }
+}
+
+fn invalid(reason: &str) -> Box<dyn std::error::Error> {
+    FseError::InvalidParams(reason.to_string()).into()
+}
+
+fn check_positive(name: &str, value: f64) -> Result<()> {
+    match value.is_finite() && value > 0.0 {
+        true => Ok(()),
+        false => Err(invalid(&format!(
+            "{} must be a positive number, got {}",
+            name, value
+        ))),
+    }
+}
+
+pub(crate) fn check_advantage(value: f64) -> Result<()> {
+    match value.is_finite() && value > 0.0 && value <= 1.0 {
+        true => Ok(()),
+        false => Err(invalid(&format!(
+            "advantage must be in (0, 1], got {}",
+            value
+        ))),
+    }
+}
+
+fn check_nonzero(name: &str, value: usize) -> Result<()> {
+    match value {
+        0 => Err(invalid(&format!("{} must be positive, got 0", name))),
+        _ => Ok(()),
+    }
+}
@@ -1,21 +1,44 @@
 //! This module implements the partition-based frequency smoothing encryption scheme.
 
-use std::{collections::HashMap, f64::consts::E, fmt::Debug, hash::Hash};
+use std::{
+    collections::{HashMap, HashSet},
+    f64::consts::E,
+    fmt::Debug,
+    hash::Hash,
+    sync::Arc,
+};
 
-use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
-use base64::{engine::general_purpose, Engine};
-use log::{debug, warn};
+use log::{debug, error, warn};
+use rand::seq::SliceRandom;
 use rand_core::OsRng;
 
 use crate::{
-    db::{Connector, Data},
+    cipher::{default_cipher, Cipher, ZERO_NONCE},
+    drift::DriftMonitor,
+    dummy::{default_generator, DummyGenerator, RandomDummies},
+    envelope::Portable,
+    error::FseError,
     fse::{
-        AsBytes, BaseCrypto, Conn, FreqType, FromBytes, HistType,
-        PartitionFrequencySmoothing, Random, ValueType, DEFAULT_RANDOM_LEN,
+        AsBytes, BaseCrypto, Conn, DatasetFingerprint, Domain, FreqType,
+        FromBytes, HistType, InsertionOrder, LocalState,
+        PartitionFrequencySmoothing, PartitionStats, Random, Replicated,
+        TokenLimit, TransformStats, ValueType,
+    },
+    journal::Journal,
+    params::{check_advantage, PartitionShape, PfseParams, SchemeParams},
+    progress::{Phase, Progress},
+    security::{PartitionState, SchemeState, StateLeakage},
+    token::TokenSet,
+    util::{
+        build_histogram, build_histogram_vec, ceil_eps, checked_div,
+        histogram_digest, SizeAllocated, StateReader, StateWriter, EPSILON,
     },
-    util::{build_histogram, build_histogram_vec, SizeAllocated},
+    FSEType, Result,
 };
 
+#[cfg(feature = "db-mongo")]
+use crate::db::{Connector, Data};
+
 #[derive(Debug, Clone)]
 pub struct PartitionMeta {
     index: usize,
@@ -53,7 +76,10 @@ where
 
     /// Find the maximum frequency within the partition.
     pub fn max_freq(&self) -> f64 {
-        self.inner.first().unwrap().1 as f64 / self.meta.message_num as f64
+        self.inner.first().map_or(0.0, |e| {
+            checked_div(e.1 as f64, self.meta.message_num as f64)
+                .unwrap_or_default()
+        })
     }
 
     /// Convert a histogram `Vec<HistType<T>>` into a frequency table `Vec<FreqType<T>>`. See [`FreqType`] and [`HistType`].
@@ -80,6 +106,34 @@ where
     }
 }
 
+/// How the advantage bound is spent over the partitions. The advantages are relative to the baseline like
+/// [`PfseParams::advantage`], and a smaller advantage buys a partition more dummies. Since the head partitions hold
+/// the frequent messages an attacker recovers first, a tighter advantage there and a looser one in the tail can
+/// protect as well as a uniform one with less storage.
+#[derive(Debug, Clone, Default)]
+pub enum AdvantageSchedule {
+    /// Every partition uses [`PfseParams::advantage`].
+    #[default]
+    Uniform,
+    /// The i-th partition uses the i-th advantage; partitions beyond the vector use [`PfseParams::advantage`].
+    PerPartition(Vec<f64>),
+    /// The advantage of the i-th partition (counted from 0) given [`PfseParams::advantage`] and i.
+    Function(fn(f64, usize) -> f64),
+}
+
+impl AdvantageSchedule {
+    /// The relative advantage of the partition `index`.
+    pub fn advantage(&self, advantage: f64, index: usize) -> f64 {
+        match self {
+            Self::Uniform => advantage,
+            Self::PerPartition(advantages) => {
+                advantages.get(index).copied().unwrap_or(advantage)
+            }
+            Self::Function(func) => func(advantage, index),
+        }
+    }
+}
+
 /// A context that represents an partition-based FSE scheme instance. This struct mainly implements the [`PartitionFrequencySmoothing`] trait.
 ///
 /// Note that in order to use FSE for plaintext in any type `T`, you must ensure that `T` has the `Hash` and `AsBytes` trait bounds.
@@ -105,6 +159,10 @@ where
     is_ready: bool,
     /// A random key used in pseudorandom function.
     key: Vec<u8>,
+    /// The cipher for symmetric encryption.
+    cipher: Box<dyn Cipher>,
+    /// The bound on the tokens of each search.
+    token_limit: Option<TokenLimit>,
     /// A table that stores the size of the ciphertext set for different partitions,
     /// given a plaintext message `T`.
     local_table: HashMap<T, Vec<ValueType>>,
@@ -118,12 +176,27 @@ where
     p_advantage: f64,
     /// The partition function pointer.
     partition_func: Option<fn(f64, usize) -> f64>,
+    /// How the histogram is cut into partitions.
+    shape: PartitionShape,
     /// The number of messages.
     message_num: usize,
     /// Partitions.
     partitions: Vec<Partition<T>>,
     /// Connector to the database.
+    #[cfg(feature = "db-mongo")]
     conn: Option<Connector<Data>>,
+    /// The digest of the histogram this context was built from.
+    digest: Option<Vec<u8>>,
+    /// The journal of the mutations of the local table.
+    journal: Journal,
+    /// The order of the smoothed output.
+    smooth_order: InsertionOrder,
+    /// How the advantage is spent over the partitions.
+    schedule: AdvantageSchedule,
+    /// Where the progress of the long-running phases is reported.
+    progress: Progress,
+    /// How the dummies are drawn.
+    dummy_generator: Arc<dyn DummyGenerator<T>>,
 }
 
 impl<T> ContextPFSE<T>
@@ -154,11 +227,196 @@ where
         self.message_num
     }
 
+    /// Set how the advantage is spent over the partitions by the next [`PartitionFrequencySmoothing::transform`].
+    pub fn set_advantage_schedule(
+        &mut self,
+        schedule: AdvantageSchedule,
+    ) -> Result<()> {
+        if let AdvantageSchedule::PerPartition(advantages) = &schedule {
+            advantages.iter().try_for_each(|e| check_advantage(*e))?;
+        }
+
+        self.schedule = schedule;
+        Ok(())
+    }
+
+    pub fn get_advantage_schedule(&self) -> &AdvantageSchedule {
+        &self.schedule
+    }
+
+    /// Draw the dummies of the next [`PartitionFrequencySmoothing::transform`] by `generator`, e.g., a
+    /// [`crate::dummy::FormatDummies`] for a column whose values a server could otherwise tell from random dummies.
+    pub fn set_dummy_generator(
+        &mut self,
+        generator: Arc<dyn DummyGenerator<T>>,
+    ) {
+        self.dummy_generator = generator;
+    }
+
+    pub fn get_dummy_generator(&self) -> &Arc<dyn DummyGenerator<T>> {
+        &self.dummy_generator
+    }
+
+    /// The dummies of the partitions, i.e., the messages of the partitions that are not in the local table.
+    pub fn dummies(&self) -> impl Iterator<Item = &T> {
+        self.partitions
+            .iter()
+            .flat_map(|e| e.inner.iter())
+            .map(|e| &e.0)
+            .filter(|e| !self.local_table.contains_key(*e))
+    }
+
     pub fn get_partitions(&self) -> &Vec<Partition<T>> {
         &self.partitions
     }
 
+    /// Build a [`DriftMonitor`] of the `k` most frequent messages the local table was built for, keyed by the
+    /// context key. Fails with [`FseError::NotInitialized`] if the context has not been transformed yet.
+    pub fn drift_monitor(
+        &self,
+        k: usize,
+        threshold: f64,
+    ) -> Result<DriftMonitor> {
+        if self.local_table.is_empty() {
+            return Err(FseError::NotInitialized.into());
+        }
+
+        let histogram = self
+            .local_table
+            .iter()
+            .map(|(message, value)| {
+                let cnt = value.iter().map(|&(_, size, cnt)| size * cnt).sum();
+                (message.clone(), cnt)
+            })
+            .collect::<HashMap<_, _>>();
+        DriftMonitor::new(&self.key, &histogram, k, threshold)
+    }
+
+    /// Get the smoothing state of the partitions for [`crate::security::advantage_bound`]. Returns `None` if the
+    /// context has not been partitioned and transformed yet.
+    pub fn scheme_state(&self) -> Option<SchemeState> {
+        if self.partitions.is_empty() || self.local_table.is_empty() {
+            return None;
+        }
+        if self.local_table.len() == 1 {
+            return Some(SchemeState::Degenerate {
+                message_num: self.message_num,
+            });
+        }
+
+        let mut sizes = HashMap::new();
+        for &(index, size, _) in self.local_table.values().flatten() {
+            *sizes.entry(index).or_insert(0usize) += size;
+        }
+
+        let partitions = self
+            .partitions
+            .iter()
+            .enumerate()
+            .map(|(index, partition)| {
+                let (messages, dummies): (Vec<_>, Vec<_>) =
+                    partition.inner.iter().partition(|(message, _)| {
+                        self.local_table.contains_key(message)
+                    });
+                PartitionState {
+                    counts: messages.iter().map(|e| e.1).collect(),
+                    ciphertext_num: sizes.get(&index).copied().unwrap_or(0)
+                        + dummies.len(),
+                }
+            })
+            .collect();
+
+        Some(SchemeState::Pfse {
+            message_num: self.message_num,
+            partitions,
+        })
+    }
+
+    /// Check that the partitions hold every occurrence of `histogram`, the histogram the context was partitioned from,
+    /// exactly once: the parts of a message split over several partitions add up to its count, the partitions add up
+    /// to the number of messages, and once transformed, the local table has one entry per part in the partition of
+    /// the part. Checked by [`PartitionFrequencySmoothing::partition`] in debug builds.
+    pub fn check_conservation(
+        &self,
+        histogram: &HashMap<T, usize>,
+    ) -> Result<()> {
+        let mut parts = HashMap::<&T, Vec<(usize, usize)>>::new();
+        for (index, partition) in self.partitions.iter().enumerate() {
+            // The dummies of a transformed partition are not in the histogram.
+            for (message, cnt) in partition.inner.iter() {
+                if histogram.contains_key(message) {
+                    parts.entry(message).or_default().push((index, *cnt));
+                }
+            }
+        }
+
+        let total = self
+            .partitions
+            .iter()
+            .map(|e| e.meta.message_num)
+            .sum::<usize>();
+        if total != self.message_num {
+            return Err(format!(
+                "The partitions hold {} messages instead of {}.",
+                total, self.message_num
+            )
+            .into());
+        }
+        for (message, &cnt) in histogram.iter() {
+            let found = parts
+                .get(message)
+                .map_or(0, |e| e.iter().map(|e| e.1).sum::<usize>());
+            if found != cnt {
+                return Err(format!(
+                    "{:?} occurs {} times, but its parts add up to {}.",
+                    message, cnt, found
+                )
+                .into());
+            }
+        }
+        if let Some((message, _)) =
+            parts.iter().find(|(_, e)| e.iter().any(|e| e.1 == 0))
+        {
+            return Err(format!("{:?} has an empty part.", message).into());
+        }
+
+        if !self.local_table.is_empty() {
+            for (message, parts) in parts.iter() {
+                let expected = parts.iter().map(|e| e.0).collect::<Vec<_>>();
+                let found = self
+                    .local_table
+                    .get(*message)
+                    .map(|e| e.iter().map(|e| e.0).collect::<Vec<_>>());
+                if found.as_ref() != Some(&expected) {
+                    return Err(format!(
+                        "The local table of {:?} has the partitions {:?} instead of {:?}.",
+                        message, found, expected
+                    )
+                    .into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// What the local state reveals if it leaks. The partitions keep the exact count of each message next to the
+    /// local table, so the leak is the histogram of the dataset, not the smoothed one.
+    pub fn state_leakage(&self) -> StateLeakage {
+        let mut histogram = HashMap::new();
+        for (message, cnt) in
+            self.partitions.iter().flat_map(|e| e.inner.iter())
+        {
+            if self.local_table.contains_key(message) {
+                *histogram.entry(message.to_bytes()).or_insert(0.0) +=
+                    *cnt as f64;
+            }
+        }
+        StateLeakage::new(histogram.into_iter().collect(), true)
+    }
+
     /// Initialize the database.
+    #[cfg(feature = "db-mongo")]
     pub fn initialize_conn(
         &mut self,
         address: &str,
@@ -170,67 +428,144 @@ where
         }
     }
 
-    /// Returns all unique ciphertexts.
-    /// Note this interface with `repeat = false` should only be invoked by `search => encrypt`.
-    fn encrypt_impl(&self, message: &T, repeat: bool) -> Option<Vec<Vec<u8>>> {
-        let value = match self.local_table.get(message) {
-            Some(v) => v,
-            None => return None,
-        };
+    /// Encrypt the `j`-th copy of `message` within the partition `index`.
+    fn encrypt_copy(
+        &self,
+        message: &T,
+        index: usize,
+        j: usize,
+    ) -> Option<Vec<u8>> {
+        let message_vec = encode_copy(message, index, j);
+        self.cipher
+            .encrypt(&self.key, &ZERO_NONCE, message_vec.as_slice())
+    }
 
-        let mut ciphertexts = Vec::new();
-        let aes = match Aes256Gcm::new_from_slice(&self.key) {
-            Ok(aes) => aes,
-            Err(e) => {
-                println!(
-                    "[-] Error constructing the AES context due to {:?}.",
-                    e.to_string()
-                );
-                return None;
+    /// Encrypt a dummy of the partition `index` the same way as the first copy of a real message, so that it cannot be
+    /// told apart by its format. Smoothing stores the dummies as they are; the attacks use this instead.
+    pub fn encrypt_dummy(&self, dummy: &T, index: usize) -> Option<Vec<u8>> {
+        self.encrypt_copy(dummy, index, 0)
+    }
+
+    /// The number of records of `message` that smoothing stores, i.e., each of its ciphertexts repeated as many times
+    /// as the ciphertexts of its partition. Returns None if the message is not in the local table.
+    pub fn smoothed_count(&self, message: &T) -> Option<usize> {
+        let value = self.local_table.get(message)?;
+        Some(value.iter().map(|&(_, size, cnt)| size * cnt).sum())
+    }
+
+    /// Returns the records smoothing stores for `message`, shuffled, for an ingestion pipeline that stores the records
+    /// itself instead of [`PartitionFrequencySmoothing::smooth`]. There are [`ContextPFSE::smoothed_count`] of them no
+    /// matter how many times the message occurs, so that the count of each ciphertext is that of the output of
+    /// smoothing; splitting only the real records would let it track the frequency of the message again.
+    ///
+    /// The pipeline attaches the real records of the message to any of them and must store the rest like the real
+    /// ones. The dummies are not included; only smoothing stores them. Fails if the message is not in the local table.
+    pub fn smoothed_records(&self, message: &T) -> Result<Vec<Vec<u8>>> {
+        let value = self.local_table.get(message).ok_or_else(|| {
+            format!("{:?} is not in the local table.", message)
+        })?;
+        let count = value.iter().map(|&(_, size, cnt)| size * cnt).sum();
+
+        let mut ciphertexts = Vec::with_capacity(count);
+        for &(index, size, cnt) in value.iter() {
+            for j in 0..size {
+                let ciphertext = self
+                    .encrypt_copy(message, index, j)
+                    .ok_or("Failed to encrypt the record.")?;
+                ciphertexts.extend(vec![ciphertext; cnt]);
             }
-        };
+        }
+        ciphertexts.shuffle(&mut OsRng);
+        Ok(ciphertexts)
+    }
 
+    /// Returns all unique ciphertexts of `message`.
+    fn encrypt_impl(&self, message: &T) -> Option<TokenSet> {
+        let value = self.local_table.get(message)?;
+
+        let mut ciphertexts = TokenSet::new();
         for &(index, size, cnt) in value.iter() {
             debug!("{index}, {size}, {cnt}");
             for j in 0..size {
-                let nonce = Nonce::from_slice(&[0u8; 12usize]);
-                let mut message_vec = message.as_bytes().to_vec();
-                message_vec.extend_from_slice(b"|");
-                message_vec.extend_from_slice(&index.to_le_bytes());
-                message_vec.extend_from_slice(b"|");
-                message_vec.extend_from_slice(&j.to_le_bytes());
-                let ciphertext =
-                    match aes.encrypt(nonce, message_vec.as_slice()) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            println!(
-                        "[-] Error when encrypting the message due to {:?}",
-                        e
-                    );
-                            return None;
-                        }
-                    };
-                let encoded_ciphertext = general_purpose::STANDARD_NO_PAD
-                    .encode(ciphertext)
-                    .into_bytes();
-
-                if repeat {
-                    let mut ciphertext_vec = vec![encoded_ciphertext; cnt];
-                    ciphertexts.append(&mut ciphertext_vec);
-                } else {
-                    ciphertexts.push(encoded_ciphertext);
-                }
+                ciphertexts.insert(self.encrypt_copy(message, index, j)?);
             }
         }
 
         Some(ciphertexts)
     }
+
+    /// Pad each partition of `pending`, given as (partition index, number of dummies, ciphertext count), with distinct
+    /// dummies of the generator. A generator that runs short is topped up by [`RandomDummies`].
+    fn insert_dummies(&mut self, pending: &[(usize, usize, usize)]) {
+        let num = pending.iter().map(|e| e.1).sum::<usize>();
+        if num == 0 {
+            return;
+        }
+
+        let mut support = self.local_table.keys().collect::<HashSet<_>>();
+        let mut dummies = self.dummy_generator.generate(&support, num);
+        if dummies.len() < num {
+            warn!(
+                "The dummy generator {:?} drew {} of {} dummies; the rest are random.",
+                self.dummy_generator,
+                dummies.len(),
+                num
+            );
+            support.extend(dummies.iter());
+            let random = RandomDummies.generate(&support, num - dummies.len());
+            dummies.extend(random);
+        }
+
+        let mut dummies = dummies.into_iter();
+        for &(index, num, ciphertext_cnt) in pending.iter() {
+            let partition = &mut self.partitions[index];
+            partition.inner.extend(
+                dummies.by_ref().take(num).map(|e| (e, ciphertext_cnt)),
+            );
+        }
+    }
+
+    /// The number of ciphertexts [`PartitionFrequencySmoothing::smooth_into`] outputs, dummies included.
+    fn smooth_num(&self) -> u64 {
+        let mut visited = HashSet::new();
+        let mut num = 0usize;
+        for (index, partition) in self.partitions.iter().enumerate() {
+            for (message, cnt) in partition.inner.iter() {
+                num += match self.local_table.get(message) {
+                    Some(value) => value
+                        .iter()
+                        .filter(|e| e.0 == index)
+                        .map(|&(_, size, cnt)| size * cnt)
+                        .sum(),
+                    None if visited.insert(message) => *cnt,
+                    None => 0,
+                };
+            }
+        }
+        num as u64
+    }
+}
+
+/// Encode the `j`-th copy of `message` within the partition `index` as `message || "|" || index || "|" || j`, the
+/// numbers being 8 little-endian bytes each.
+pub(crate) fn encode_copy<T: AsBytes>(
+    message: &T,
+    index: usize,
+    j: usize,
+) -> Vec<u8> {
+    let mut message_vec = message.to_bytes();
+    message_vec.extend_from_slice(b"|");
+    message_vec.extend_from_slice(&(index as u64).to_le_bytes());
+    message_vec.extend_from_slice(b"|");
+    message_vec.extend_from_slice(&(j as u64).to_le_bytes());
+    message_vec
 }
 
 impl<T> Conn for ContextPFSE<T>
 where
     T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + Random + SizeAllocated,
 {
+    #[cfg(feature = "db-mongo")]
     fn get_conn(&self) -> &Connector<Data> {
         self.conn.as_ref().unwrap()
     }
@@ -244,15 +579,25 @@ where
         Self {
             is_ready: false,
             key: Vec::new(),
+            cipher: default_cipher(),
+            token_limit: None,
             local_table: HashMap::new(),
             p_partition: 0f64,
             p_transform: (0f64, 0f64),
             p_advantage: 0f64,
             p_scale: 0f64,
             partition_func: None,
+            shape: PartitionShape::default(),
             message_num: 0usize,
             partitions: Vec::new(),
+            #[cfg(feature = "db-mongo")]
             conn: None,
+            digest: None,
+            journal: Journal::new(),
+            smooth_order: InsertionOrder::default(),
+            schedule: AdvantageSchedule::default(),
+            progress: Progress::none(),
+            dummy_generator: default_generator(),
         }
     }
 }
@@ -271,44 +616,49 @@ where
     T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + Random + SizeAllocated,
 {
     fn key_generate(&mut self) {
-        self.key = Aes256Gcm::generate_key(&mut OsRng).to_vec();
+        self.key = self.cipher.key_generate();
+    }
+
+    fn set_key(&mut self, key: &[u8]) {
+        self.key = key.to_vec();
+    }
+
+    fn get_key(&self) -> &[u8] {
+        &self.key
+    }
+
+    fn set_cipher(&mut self, cipher: Box<dyn Cipher>) {
+        self.cipher = cipher;
+    }
+
+    fn get_cipher(&self) -> &dyn Cipher {
+        self.cipher.as_ref()
+    }
+
+    fn set_token_limit(&mut self, limit: Option<TokenLimit>) {
+        self.token_limit = limit;
+    }
+
+    fn get_token_limit(&self) -> Option<&TokenLimit> {
+        self.token_limit.as_ref()
+    }
+
+    fn token_count(&self, message: &T) -> Option<usize> {
+        self.local_table
+            .get(message)
+            .map(|value| value.iter().map(|&(_, size, _)| size).sum())
     }
 
     fn encrypt(&mut self, message: &T) -> Option<Vec<Vec<u8>>> {
-        self.encrypt_impl(message, false)
+        self.encrypt_impl(message).map(|e| e.into_iter().collect())
     }
 
     fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
-        let aes = match Aes256Gcm::new_from_slice(&self.key) {
-            Ok(aes) => aes,
-            Err(e) => {
-                println!(
-                    "[-] Error constructing the AES context due to {:?}.",
-                    e.to_string()
-                );
-                return None;
-            }
-        };
-        let nonce = Nonce::from_slice(&[0u8; 12]);
-        let decoded_ciphertext =
-            match general_purpose::STANDARD_NO_PAD.decode(ciphertext) {
-                Ok(v) => v,
-                Err(e) => {
-                    println!(
-                        "[-] Error decoding the base64 string due to {:?}.",
-                        e.to_string()
-                    );
-                    return None;
-                }
-            };
         let mut plaintext =
-            match aes.decrypt(nonce, decoded_ciphertext.as_slice()) {
-                Ok(plaintext) => plaintext,
-                Err(e) => {
-                    println!(
-                        "[-] Error decrypting the message due to {:?}.",
-                        e.to_string()
-                    );
+            match self.cipher.decrypt(&self.key, &ZERO_NONCE, ciphertext) {
+                Some(plaintext) => plaintext,
+                None => {
+                    error!("Error decrypting the message.");
                     return None;
                 }
             };
@@ -317,49 +667,103 @@ where
 
         Some(plaintext)
     }
+
+    fn search_tokens(&mut self, message: &T) -> Option<TokenSet> {
+        self.encrypt_impl(message)
+    }
 }
 
 impl<T> PartitionFrequencySmoothing<T> for ContextPFSE<T>
 where
     T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + Random + SizeAllocated,
 {
-    fn set_params(&mut self, params: &[f64]) {
-        if params.len() != 3 {
-            log::error!("The number of the parameter is incorrect.");
-            return;
-        }
+    type Params = PfseParams;
+
+    fn set_params(&mut self, params: &PfseParams) -> Result<()> {
+        params.validate()?;
 
-        self.p_partition = params[0];
-        self.p_scale = params[1];
-        self.p_advantage = params[2];
+        self.p_partition = params.lambda;
+        self.p_scale = params.scale;
+        self.p_advantage = params.advantage;
+        self.shape = params.shape;
         self.is_ready = true;
+        Ok(())
     }
 
     fn partition(
         &mut self,
         input: &[T],
         partition_func: fn(f64, usize) -> f64,
-    ) {
+    ) -> Result<()> {
+        let histogram = build_histogram(input);
+        self.partition_histogram(&histogram, partition_func)
+    }
+
+    /// A histogram of a single message is kept as one partition, which [`PartitionFrequencySmoothing::transform`]
+    /// encrypts as a single ciphertext without dummies.
+    fn partition_histogram(
+        &mut self,
+        histogram: &HashMap<T, usize>,
+        partition_func: fn(f64, usize) -> f64,
+    ) -> Result<()> {
+        if histogram.values().all(|&cnt| cnt == 0) {
+            return Err(FseError::EmptyDataset.into());
+        }
         // Set the partition function.
         self.partition_func = Some(partition_func);
         if !self.ready() {
             panic!("[-] Context not ready.");
         }
 
-        self.message_num = input.len();
-        let mut histogram_vec = {
-            let histogram = build_histogram(input);
-            build_histogram_vec(&histogram)
-        };
+        self.message_num = histogram.values().sum();
+        self.digest = Some(histogram_digest(histogram));
+        let mut histogram_vec = build_histogram_vec(histogram);
         debug!("Histogram: {:?}", histogram_vec);
-        // Partition this according to the function f(x).
+        // A message split over two partitions is counted once its second part is partitioned.
+        let mut tracker = self
+            .progress
+            .start(Phase::Partition, Some(histogram_vec.len() as u64));
+        if histogram_vec.len() == 1 {
+            self.partitions.push(Partition::new(histogram_vec, 1, 1.0));
+            tracker.advance(1);
+            return Ok(());
+        }
+        if let PartitionShape::EqualWidth(width) = self.shape {
+            for (index, chunk) in histogram_vec.chunks(width).enumerate() {
+                let mass = chunk
+                    .iter()
+                    .map(|e| e.1 as f64 / self.message_num as f64)
+                    .sum();
+                self.partitions.push(Partition::new(
+                    chunk.to_vec(),
+                    index + 1,
+                    mass,
+                ));
+                tracker.advance(chunk.len() as u64);
+            }
+            debug!("Partition finished. Partitions: {:?}", self.partitions);
+            return Ok(());
+        }
+        // Partition this according to the function f(x), or into equal masses.
         let mut i = 0usize;
         // The group number.
         let mut group = 1usize;
         while i < histogram_vec.len() {
             // Calculate \lambda * e^{-\lambda group} * k_{0}.
-            let value = partition_func(self.p_partition, group) * self.p_scale;
-            if value * self.message_num as f64 <= 1.0 {
+            let value = match self.shape {
+                PartitionShape::EqualMass(partitions) => {
+                    1.0 / partitions as f64
+                }
+                _ => partition_func(self.p_partition, group) * self.p_scale,
+            };
+            // A NaN value also terminates the partitioning, and so does the last of the equal masses, which takes
+            // whatever the rounding of the split messages left.
+            let last = matches!(
+                self.shape,
+                PartitionShape::EqualMass(partitions) if group >= partitions
+            );
+            if value.is_nan() || value * self.message_num as f64 <= 1.0 || last
+            {
                 self.partitions.push(Partition::new(
                     histogram_vec[i..].to_vec(),
                     group,
@@ -368,6 +772,7 @@ where
                         .map(|e| e.1 as f64 / self.message_num as f64)
                         .sum(),
                 ));
+                tracker.advance((histogram_vec.len() - i) as u64);
                 break;
             }
 
@@ -383,18 +788,18 @@ where
             }
 
             // Deal with a special case: \sum_{k \in [i, j]} \in (f(group), f(group + 1));
-            if sum > value {
+            if sum - value > EPSILON {
                 let diff = sum - value;
-                // Split j-th message.
-                let message_first_part = (
-                    histogram_vec[j - 1].0.clone(),
-                    (histogram_vec[j - 1].1 as f64 * (1f64 - diff)).ceil()
-                        as usize,
-                );
-                let message_second_part = (
-                    histogram_vec[j - 1].0.clone(),
-                    (histogram_vec[j - 1].1 as f64 * diff).floor() as usize,
-                );
+                // Split j-th message. The excess `diff` is a frequency, so the second part takes that share of all
+                // messages, rounded down, and the first part the rest of the count of the message, keeping at
+                // least one occurrence.
+                let (message, cnt) = histogram_vec[j - 1].clone();
+                let second =
+                    ((diff * self.message_num as f64 + EPSILON).floor()
+                        as usize)
+                        .min(cnt - 1);
+                let message_first_part = (message.clone(), cnt - second);
+                let message_second_part = (message, second);
 
                 histogram_vec[j - 1] = message_first_part;
                 self.partitions.push(Partition::new(
@@ -411,6 +816,9 @@ where
                         })
                         .unwrap_or_else(|e| e);
                     histogram_vec.insert(pos + j, message_second_part);
+                    tracker.advance((j - i - 1) as u64);
+                } else {
+                    tracker.advance((j - i) as u64);
                 }
             } else {
                 self.partitions.push(Partition::new(
@@ -418,6 +826,7 @@ where
                     group,
                     value,
                 ));
+                tracker.advance((j - i) as u64);
             }
 
             group += 1;
@@ -425,104 +834,325 @@ where
         }
 
         debug!("Partition finished. Partitions: {:?}", self.partitions);
+        if cfg!(debug_assertions) {
+            if let Err(e) = self.check_conservation(histogram) {
+                panic!("[-] {}", e);
+            }
+        }
+        Ok(())
     }
 
-    fn transform(&mut self) {
+    fn transform(&mut self) -> TransformStats {
         // k_i &= \frac{e^{\lambda i}}{\sqrt{nk}} \\
         // n_i &= \frac{\sqrt{nk}|G_i|}{(\Delta + c) \cdot e^{\lambda i} }
+        let mut stats = TransformStats::default();
         let k = self.partitions.len() as f64;
         let n = self.message_num as f64;
 
-        // Compute `p_advantage`.
+        // A single message leaks nothing to smooth away, so it needs neither copies nor dummies.
+        if let [partition] = self.partitions.as_slice() {
+            if let [(message, cnt)] = partition.inner.as_slice() {
+                self.local_table.insert(message.clone(), vec![(0, 1, *cnt)]);
+                stats.partitions.push(PartitionStats {
+                    target: 1,
+                    real: 1,
+                    ciphertext_cnt: *cnt,
+                    ..Default::default()
+                });
+                self.record_snapshot();
+                return stats;
+            }
+        }
+
+        // Compute `p_advantage` and the advantage of each partition under the schedule.
         let baseline =
             self.partitions.iter().map(|e| e.max_freq()).sum::<f64>();
+        let advantages = (0..self.partitions.len())
+            .map(|index| {
+                self.schedule.advantage(self.p_advantage, index) * baseline
+            })
+            .collect::<Vec<_>>();
         self.p_advantage *= baseline;
+        if self.p_advantage.is_nan() || self.p_advantage <= EPSILON {
+            error!("Invalid advantage: {}", self.p_advantage);
+            return stats;
+        }
         log::info!(
             "The baseline is {}, and the advantage is {}.",
             baseline,
             self.p_advantage
         );
 
+        let mut tracker = self
+            .progress
+            .start(Phase::Transform, Some(self.partitions.len() as u64));
+        // The dummies are drawn once the local table holds all real messages, so that they avoid every one of them.
+        let mut pending = Vec::new();
         for (index, partition) in self.partitions.iter_mut().enumerate() {
-            let f_i = partition
-                .inner
-                .iter()
-                .map(|e| (e.1 as f64 / n).powf(2.0))
-                .sum::<f64>();
-            let cur_func =
-                (self.partition_func.unwrap())(self.p_partition, index + 1);
+            tracker.advance(1);
+            let mut partition_stats = PartitionStats {
+                index,
+                ..Default::default()
+            };
+            // The other shapes do not follow the partition function, so their target mass is the recorded one.
+            let cur_func = match self.shape {
+                PartitionShape::Exponential => {
+                    (self.partition_func.unwrap())(self.p_partition, index + 1)
+                }
+                _ => partition.meta.cumulative_frequency,
+            };
             let k_prime_one = cur_func / k;
-            let k_prime_one_reciprocal = 1.0 / (k_prime_one);
-            let n_i = ((n * f_i) / self.p_advantage).ceil() as usize;
+            let k_prime_one_reciprocal = match checked_div(1.0, k_prime_one) {
+                Some(v) if k_prime_one > 0.0 => v,
+                _ => {
+                    warn!(
+                        "Partition #{:<4}: invalid k' = {}.",
+                        index, k_prime_one
+                    );
+                    partition_stats.skipped = true;
+                    stats.partitions.push(partition_stats);
+                    continue;
+                }
+            };
+            let advantage = advantages[index];
+            if !advantage.is_finite() || advantage <= EPSILON {
+                warn!(
+                    "Partition #{:<4}: invalid advantage {}.",
+                    index, advantage
+                );
+                partition_stats.skipped = true;
+                stats.partitions.push(partition_stats);
+                continue;
+            }
+            // Every ciphertext of this partition, dummies included, is repeated the same number of times.
+            let ciphertext_cnt =
+                (k_prime_one_reciprocal.round() as usize).max(1);
 
-            let mut sum = 0;
+            // n_i = n * f_i / advantage where n * f_i = \sum_{m \in G_i} cnt_m^2 / n is kept in integers.
+            let square_sum = partition
+                .inner
+                .iter()
+                .map(|e| (e.1 as u128).pow(2))
+                .sum::<u128>();
+            let n_i = ceil_eps(square_sum as f64 / (n * advantage));
 
+            let mut sum = 0usize;
             for (message, cnt) in partition.inner.iter() {
-                let size = (k_prime_one * *cnt as f64).ceil() as usize;
+                let size = ceil_eps(k_prime_one * *cnt as f64).max(1);
                 let cur = self.local_table.entry(message.clone()).or_default();
-                cur.push((
-                    index,
-                    size,
-                    k_prime_one_reciprocal.round() as usize,
-                ));
+                cur.push((index, size, ciphertext_cnt));
                 sum += size;
             }
 
-            let delta = match n_i.checked_sub(sum) {
-                Some(d) => d,
+            partition_stats.target = n_i;
+            partition_stats.real = sum;
+            partition_stats.ciphertext_cnt = ciphertext_cnt;
+            match n_i.checked_sub(sum) {
+                Some(delta) => partition_stats.dummies = delta,
                 None => {
                     warn!(
-                        "Partition #{:<4}: attemping to subtract {} by {}.",
-                        index, n_i, sum
+                        "Partition #{:<4}: {} real ciphertexts exceed the target {}; no dummies are needed.",
+                        index, sum, n_i
                     );
-                    0
+                    partition_stats.overflow = sum - n_i;
                 }
-            };
+            }
 
             log::debug!(
-                "# {}... |G_i| = {}, sum = {}, ni = {}, k_one = {}, f_i = {}.",
+                "# {}... |G_i| = {}, sum = {}, ni = {}, k_one = {}, dummies = {}.",
                 index,
                 partition.inner.len(),
                 sum,
                 n_i,
                 k_prime_one,
-                f_i,
+                partition_stats.dummies,
             );
 
-            for _ in sum..delta {
-                // Insert dummy values.
-                let dummy = T::random(DEFAULT_RANDOM_LEN);
-
-                partition
-                    .inner
-                    .push((dummy, (1.0 / k_prime_one).ceil() as usize));
-            }
+            pending.push((index, partition_stats.dummies, ciphertext_cnt));
+            stats.partitions.push(partition_stats);
         }
+        self.insert_dummies(&pending);
 
         debug!("Transform finished. Local table is {:?}", self.local_table);
+        self.record_snapshot();
+        stats
     }
 
-    fn smooth(&mut self) -> Vec<Vec<u8>> {
-        let mut ciphertexts = Vec::new();
+    fn set_smooth_order(&mut self, order: InsertionOrder) {
+        self.smooth_order = order;
+    }
 
-        let mut visited = HashMap::new();
-        // Temporarily clone this thing to prevent multiple borrows to `self`.
-        for partition in self.partitions.clone().into_iter() {
+    fn get_smooth_order(&self) -> InsertionOrder {
+        self.smooth_order
+    }
+
+    fn set_progress(&mut self, progress: Progress) {
+        self.progress = progress;
+    }
+
+    fn get_progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    fn smooth_into<F>(&mut self, mut sink: F) -> Result<()>
+    where
+        F: FnMut(Vec<u8>) -> Result<()>,
+    {
+        // Counting the output takes a pass over the partitions, so it is skipped without a reporter.
+        let total = self.progress.is_enabled().then(|| self.smooth_num());
+        let mut tracker = self.progress.start(Phase::Smooth, total);
+        let mut visited = HashSet::new();
+        for (index, partition) in self.partitions.iter().enumerate() {
             for (message, cnt) in partition.inner.iter() {
-                if visited.get(message).is_none() {
-                    if let Some(mut c) = self.encrypt_impl(message, true) {
-                        ciphertexts.append(&mut c);
-                    } else {
-                        let mut dummies =
-                            vec![message.clone().as_bytes().to_vec(); *cnt];
-                        ciphertexts.append(&mut dummies);
+                let value = match self.local_table.get(message) {
+                    Some(value) => value,
+                    None => {
+                        // Dummies are not in the local table and are stored as they are.
+                        if visited.insert(message.clone()) {
+                            for _ in 0..*cnt {
+                                sink(message.to_bytes())?;
+                            }
+                            tracker.advance(*cnt as u64);
+                        }
+                        continue;
                     }
+                };
 
-                    visited.insert(message.clone(), true);
+                // Only the copies that belong to this partition.
+                for &(_, size, cnt) in value.iter().filter(|e| e.0 == index) {
+                    for j in 0..size {
+                        let ciphertext =
+                            self.encrypt_copy(message, index, j)
+                                .ok_or("Cannot encrypt the message.")?;
+                        for _ in 0..cnt {
+                            sink(ciphertext.clone())?;
+                        }
+                        tracker.advance(cnt as u64);
+                    }
                 }
             }
         }
 
-        ciphertexts
+        Ok(())
+    }
+}
+
+impl<T> LocalState<T> for ContextPFSE<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + Random + SizeAllocated,
+{
+    fn export_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.put_f64(self.p_partition);
+        writer.put_f64(self.p_scale);
+        writer.put_f64(self.p_advantage);
+        writer.put_u64(self.message_num as u64);
+        writer.put_u64(self.local_table.len() as u64);
+        for (message, values) in self.local_table.iter() {
+            writer.put_bytes(&message.to_bytes());
+            writer.put_u64(values.len() as u64);
+            for &(index, size, cnt) in values.iter() {
+                writer.put_u64(index as u64);
+                writer.put_u64(size as u64);
+                writer.put_u64(cnt as u64);
+            }
+        }
+        writer.put_bytes(self.digest.as_deref().unwrap_or_default());
+        writer.finish()
+    }
+
+    fn import_state(&mut self, state: &[u8]) -> Result<()> {
+        let mut reader = StateReader::new(state);
+        let malformed = || "Malformed PFSE state.";
+
+        let p_partition = reader.get_f64().ok_or_else(malformed)?;
+        let p_scale = reader.get_f64().ok_or_else(malformed)?;
+        let p_advantage = reader.get_f64().ok_or_else(malformed)?;
+        let message_num = reader.get_usize().ok_or_else(malformed)?;
+        let len = reader.get_usize().ok_or_else(malformed)?;
+        let mut local_table = HashMap::new();
+        for _ in 0..len {
+            let message =
+                T::from_bytes(reader.get_bytes().ok_or_else(malformed)?);
+            let value_num = reader.get_usize().ok_or_else(malformed)?;
+            let mut values = Vec::new();
+            for _ in 0..value_num {
+                values.push((
+                    reader.get_usize().ok_or_else(malformed)?,
+                    reader.get_usize().ok_or_else(malformed)?,
+                    reader.get_usize().ok_or_else(malformed)?,
+                ));
+            }
+            local_table.insert(message, values);
+        }
+        let digest = reader.get_bytes().ok_or_else(malformed)?;
+        if !reader.is_empty() {
+            return Err(malformed().into());
+        }
+
+        self.p_partition = p_partition;
+        self.p_scale = p_scale;
+        self.p_advantage = p_advantage;
+        self.message_num = message_num;
+        self.local_table = local_table;
+        self.digest = match digest.is_empty() {
+            true => None,
+            false => Some(digest.to_vec()),
+        };
+        self.is_ready = true;
+        self.record_snapshot();
+        Ok(())
+    }
+}
+
+impl<T> Portable<T> for ContextPFSE<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + Random + SizeAllocated,
+{
+    fn scheme_name(&self) -> &'static str {
+        FSEType::Pfse.name()
+    }
+
+    fn scheme_params(&self) -> Option<SchemeParams> {
+        Some(
+            PfseParams::new(self.p_partition, self.p_scale, self.p_advantage)
+                .with_shape(self.shape)
+                .into(),
+        )
+    }
+}
+
+impl<T> Replicated<T> for ContextPFSE<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + Random + SizeAllocated,
+{
+    fn get_journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    fn get_journal_mut(&mut self) -> &mut Journal {
+        &mut self.journal
+    }
+}
+
+impl<T> Domain<T> for ContextPFSE<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + Random + SizeAllocated,
+{
+    fn export_domain(&self) -> Vec<T> {
+        self.local_table.keys().cloned().collect()
+    }
+
+    fn contains(&self, message: &T) -> bool {
+        self.local_table.contains_key(message)
+    }
+}
+
+impl<T> DatasetFingerprint<T> for ContextPFSE<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + Random + SizeAllocated,
+{
+    fn get_histogram_digest(&self) -> Option<&[u8]> {
+        self.digest.as_deref()
     }
 }
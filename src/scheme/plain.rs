@@ -0,0 +1,163 @@
+//! This module implements a baseline that stores the plaintext as it is. It is NOT an encryption scheme: it only
+//! serves as the reference point of the evaluations, i.e., the pure cost of the database for the benchmarks and the
+//! upper bound of the accuracy for the attacks.
+
+use std::{fmt::Debug, marker::PhantomData};
+
+use crate::{
+    cipher::{default_cipher, Cipher},
+    envelope::Portable,
+    fse::{AsBytes, BaseCrypto, Conn, FromBytes, LocalState, TokenLimit},
+    params::SchemeParams,
+    security::StateLeakage,
+    util::SizeAllocated,
+    FSEType, Result,
+};
+
+#[cfg(feature = "db-mongo")]
+use crate::db::{Connector, Data};
+
+#[derive(Debug, Clone)]
+pub struct ContextPlain<T> {
+    /// The key is never used, but is kept so that the context can be handled like any other one.
+    key: Vec<u8>,
+    /// The cipher is never used either.
+    cipher: Box<dyn Cipher>,
+    /// The bound on the tokens of each search.
+    token_limit: Option<TokenLimit>,
+    /// Connector to the database.
+    #[cfg(feature = "db-mongo")]
+    conn: Option<Connector<Data>>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> ContextPlain<T> {
+    pub fn new() -> Self {
+        Self {
+            key: Vec::new(),
+            cipher: default_cipher(),
+            token_limit: None,
+            #[cfg(feature = "db-mongo")]
+            conn: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Nothing, as the context keeps no local state; the server holds the plaintexts anyway.
+    pub fn state_leakage(&self) -> StateLeakage {
+        StateLeakage::none()
+    }
+
+    #[cfg(feature = "db-mongo")]
+    pub fn initialize_conn(
+        &mut self,
+        address: &str,
+        db_name: &str,
+        drop: bool,
+    ) {
+        if let Ok(conn) = Connector::new(address, db_name, drop) {
+            self.conn = Some(conn);
+        }
+    }
+}
+
+impl<T> Default for ContextPlain<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Conn for ContextPlain<T> {
+    #[cfg(feature = "db-mongo")]
+    fn get_conn(&self) -> &Connector<Data> {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+/// There is no local state.
+impl<T> SizeAllocated for ContextPlain<T> {
+    fn size_allocated(&self) -> usize {
+        0
+    }
+}
+
+impl<T> BaseCrypto<T> for ContextPlain<T>
+where
+    T: AsBytes + FromBytes + Debug,
+{
+    fn key_generate(&mut self) {
+        self.key = self.cipher.key_generate();
+    }
+
+    fn set_key(&mut self, key: &[u8]) {
+        self.key = key.to_vec();
+    }
+
+    fn get_key(&self) -> &[u8] {
+        &self.key
+    }
+
+    fn set_cipher(&mut self, cipher: Box<dyn Cipher>) {
+        self.cipher = cipher;
+    }
+
+    fn get_cipher(&self) -> &dyn Cipher {
+        self.cipher.as_ref()
+    }
+
+    fn set_token_limit(&mut self, limit: Option<TokenLimit>) {
+        self.token_limit = limit;
+    }
+
+    fn get_token_limit(&self) -> Option<&TokenLimit> {
+        self.token_limit.as_ref()
+    }
+
+    fn token_count(&self, message: &T) -> Option<usize> {
+        Some(1)
+    }
+
+    /// The "ciphertext" is the encoding of the message.
+    fn encrypt(&mut self, message: &T) -> Option<Vec<Vec<u8>>> {
+        Some(vec![message.to_bytes()])
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        Some(ciphertext.to_vec())
+    }
+
+    /// Any bytes are a valid plaintext, so this never fails.
+    fn decrypt_checked(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        Ok(ciphertext.to_vec())
+    }
+}
+
+/// There is no local state, so the state is empty and only the (unused) key is carried by an envelope.
+impl<T> LocalState<T> for ContextPlain<T>
+where
+    T: AsBytes + FromBytes + Debug,
+{
+    fn export_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn import_state(&mut self, state: &[u8]) -> Result<()> {
+        match state.is_empty() {
+            true => Ok(()),
+            false => Err("Malformed plain state.".into()),
+        }
+    }
+}
+
+impl<T> Portable<T> for ContextPlain<T>
+where
+    T: AsBytes + FromBytes + Debug,
+{
+    fn scheme_name(&self) -> &'static str {
+        FSEType::Plain.name()
+    }
+
+    fn scheme_params(&self) -> Option<SchemeParams> {
+        None
+    }
+}
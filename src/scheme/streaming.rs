@@ -0,0 +1,514 @@
+//! This module implements frequency smoothing for streaming data, e.g., log ingestion, where the dataset is not known
+//! in advance and its distribution drifts over time.
+//!
+//! The stream is cut into epochs of `epoch_size` messages. The context keeps a count-min sketch for each of the last
+//! `window` epochs and tracks the heavy hitters of the sliding window. Whenever an epoch is closed, the smoothing
+//! parameters for the next epoch are recomputed: a message whose estimated frequency `f` within the window exceeds
+//! `1 / heavy_hitters` is split into `ceil(f * heavy_hitters)` salts, so that no ciphertext occurs more often than
+//! about `n / heavy_hitters` times in a window of `n` messages. The first epoch is encrypted with a single salt per
+//! message since nothing has been observed yet.
+//!
+//! Each ciphertext is tagged with the epoch it was produced in, so searches can target the recent windows only.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    ops::Range,
+};
+
+use log::{debug, error};
+use rand_core::OsRng;
+use rand_distr::{Distribution, Uniform};
+
+use crate::{
+    cipher::{default_cipher, Cipher, ZERO_NONCE},
+    envelope::Portable,
+    fse::{AsBytes, BaseCrypto, Conn, FromBytes, LocalState, TokenLimit},
+    params::{SchemeParams, StreamingParams},
+    token::TokenSet,
+    util::{ceil_eps, SizeAllocated, StateReader, StateWriter},
+    Result,
+};
+
+#[cfg(feature = "db-mongo")]
+use crate::db::{Connector, Data};
+
+/// The length of the epoch and salt suffix appended to each message before encryption.
+const SUFFIX_LEN: usize = std::mem::size_of::<u64>() * 2 + 2;
+
+/// A count-min sketch that never underestimates the count of an item.
+#[derive(Debug, Clone)]
+pub struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    table: Vec<u64>,
+}
+
+impl CountMinSketch {
+    pub fn new(width: usize, depth: usize) -> Self {
+        Self {
+            width,
+            depth,
+            table: vec![0; width * depth],
+        }
+    }
+
+    fn index(&self, row: usize, item: &[u8]) -> usize {
+        let mut hasher = DefaultHasher::new();
+        hasher.write_usize(row);
+        hasher.write(item);
+        row * self.width + hasher.finish() as usize % self.width
+    }
+
+    /// Count one occurrence of `item`.
+    pub fn insert(&mut self, item: &[u8]) {
+        for row in 0..self.depth {
+            let idx = self.index(row, item);
+            self.table[idx] += 1;
+        }
+    }
+
+    /// Estimate the number of occurrences of `item`.
+    pub fn estimate(&self, item: &[u8]) -> u64 {
+        (0..self.depth)
+            .map(|row| self.table[self.index(row, item)])
+            .min()
+            .unwrap_or_default()
+    }
+}
+
+impl SizeAllocated for CountMinSketch {
+    fn size_allocated(&self) -> usize {
+        self.table.size_allocated()
+    }
+}
+
+/// The sketch of a single epoch within the sliding window.
+#[derive(Debug, Clone)]
+struct EpochSketch {
+    sketch: CountMinSketch,
+    /// The number of messages encrypted in this epoch.
+    count: u64,
+}
+
+#[derive(Debug)]
+pub struct ContextStreaming<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+{
+    /// The secret key for symmetric encryption.
+    key: Vec<u8>,
+    /// The cipher for symmetric encryption.
+    cipher: Box<dyn Cipher>,
+    /// The bound on the tokens of each search.
+    token_limit: Option<TokenLimit>,
+    /// The connector.
+    #[cfg(feature = "db-mongo")]
+    conn: Option<Connector<Data>>,
+    /// The parameters of the scheme.
+    params: StreamingParams,
+    /// The sketches of the epochs within the sliding window, the last one being the current epoch.
+    window: VecDeque<EpochSketch>,
+    /// The candidate heavy hitters of the sliding window and their estimated counts.
+    heavy_hitters: HashMap<T, u64>,
+    /// The number of salts of each smoothed message, indexed by epoch. Messages that are absent have a single salt.
+    salts: Vec<HashMap<T, u64>>,
+}
+
+impl<T> ContextStreaming<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+{
+    /// Construct the context from validated parameters.
+    pub fn from_params(params: &StreamingParams) -> Result<Self> {
+        params.validate()?;
+
+        let mut ctx = Self {
+            key: Vec::new(),
+            cipher: default_cipher(),
+            token_limit: None,
+            #[cfg(feature = "db-mongo")]
+            conn: None,
+            params: *params,
+            window: VecDeque::new(),
+            heavy_hitters: HashMap::new(),
+            salts: Vec::new(),
+        };
+        ctx.open_epoch(HashMap::new());
+        Ok(ctx)
+    }
+
+    #[cfg(feature = "db-mongo")]
+    pub fn initialize_conn(
+        &mut self,
+        address: &str,
+        db_name: &str,
+        drop: bool,
+    ) {
+        if let Ok(conn) = Connector::new(address, db_name, drop) {
+            self.conn = Some(conn);
+        }
+    }
+
+    pub fn get_params(&self) -> &StreamingParams {
+        &self.params
+    }
+
+    /// Get the current epoch.
+    pub fn get_epoch(&self) -> u64 {
+        self.salts.len() as u64 - 1
+    }
+
+    /// Get the number of salts of `message` in `epoch`.
+    pub fn get_salts(&self, message: &T, epoch: u64) -> Option<u64> {
+        self.salts
+            .get(epoch as usize)
+            .map(|salts| salts.get(message).copied().unwrap_or(1))
+    }
+
+    /// Get the estimated number of occurrences of `message` within the sliding window.
+    pub fn estimate(&self, message: &T) -> u64 {
+        self.window
+            .iter()
+            .map(|epoch| epoch.sketch.estimate(&message.to_bytes()))
+            .sum()
+    }
+
+    /// Get the number of messages within the sliding window.
+    pub fn get_window_count(&self) -> u64 {
+        self.window.iter().map(|epoch| epoch.count).sum()
+    }
+
+    /// Close the current epoch, recompute the smoothing parameters from the sliding window and open a new epoch.
+    /// This is called automatically every `epoch_size` messages, but can also be driven by a timer.
+    pub fn advance_epoch(&mut self) {
+        let total = self.get_window_count();
+        let k = self.params.heavy_hitters as u64;
+
+        let estimates = self
+            .heavy_hitters
+            .keys()
+            .map(|message| (message.clone(), self.estimate(message)))
+            .collect::<Vec<_>>();
+        self.heavy_hitters = estimates
+            .into_iter()
+            .filter(|&(_, cnt)| cnt != 0 && cnt * k > total)
+            .collect();
+
+        let salts = self
+            .heavy_hitters
+            .iter()
+            .map(|(message, &cnt)| {
+                let salts = ceil_eps(cnt as f64 * k as f64 / total as f64);
+                (message.clone(), salts as u64)
+            })
+            .collect::<HashMap<_, _>>();
+        debug!(
+            "Epoch {} closed with {} messages in window and {} heavy hitters.",
+            self.get_epoch(),
+            total,
+            salts.len()
+        );
+
+        self.open_epoch(salts);
+        while self.window.len() > self.params.window {
+            self.window.pop_front();
+        }
+    }
+
+    fn open_epoch(&mut self, salts: HashMap<T, u64>) {
+        self.window.push_back(EpochSketch {
+            sketch: CountMinSketch::new(self.params.width, self.params.depth),
+            count: 0,
+        });
+        self.salts.push(salts);
+    }
+
+    /// Count `message` in the current epoch and track it if it becomes a heavy hitter.
+    fn observe(&mut self, message: &T) {
+        let epoch_size = self.params.epoch_size as u64;
+        if matches!(self.window.back(), Some(epoch) if epoch.count >= epoch_size)
+        {
+            self.advance_epoch();
+        }
+
+        let epoch = self.window.back_mut().unwrap();
+        epoch.sketch.insert(&message.to_bytes());
+        epoch.count += 1;
+
+        let cnt = self.estimate(message);
+        if cnt * self.params.heavy_hitters as u64 > self.get_window_count() {
+            self.heavy_hitters.insert(message.clone(), cnt);
+        }
+    }
+
+    fn encrypt_with_salt(
+        &self,
+        message: &T,
+        epoch: u64,
+        salt: u64,
+    ) -> Option<Vec<u8>> {
+        let mut message_vec = message.to_bytes();
+        message_vec.extend_from_slice(b"|");
+        message_vec.extend_from_slice(&epoch.to_le_bytes());
+        message_vec.extend_from_slice(b"|");
+        message_vec.extend_from_slice(&salt.to_le_bytes());
+        self.cipher
+            .encrypt(&self.key, &ZERO_NONCE, message_vec.as_slice())
+    }
+
+    /// Decrypt the ciphertext and return the plaintext together with the epoch it was encrypted in.
+    pub fn decrypt_with_epoch(
+        &self,
+        ciphertext: &[u8],
+    ) -> Option<(Vec<u8>, u64)> {
+        let mut plaintext =
+            match self.cipher.decrypt(&self.key, &ZERO_NONCE, ciphertext) {
+                Some(plaintext) => plaintext,
+                None => {
+                    error!("Error decrypting the message.");
+                    return None;
+                }
+            };
+
+        let len = plaintext.len().checked_sub(SUFFIX_LEN)?;
+        let epoch = plaintext[len + 1..len + 1 + std::mem::size_of::<u64>()]
+            .try_into()
+            .map(u64::from_le_bytes)
+            .ok()?;
+        plaintext.truncate(len);
+        Some((plaintext, epoch))
+    }
+
+    /// Generate the search tokens of `message` for the epochs in `epochs`. Epochs that do not exist yet are skipped.
+    pub fn search_tokens_in(
+        &self,
+        message: &T,
+        epochs: Range<u64>,
+    ) -> Option<TokenSet> {
+        let mut tokens = TokenSet::new();
+        for epoch in epochs.start..epochs.end.min(self.get_epoch() + 1) {
+            let salts = self.get_salts(message, epoch)?;
+            for salt in 0..salts {
+                tokens.insert(self.encrypt_with_salt(message, epoch, salt)?);
+            }
+        }
+        Some(tokens)
+    }
+
+    /// Search `message` in the collection `name` within the last `epochs` epochs, including the current one.
+    #[cfg(feature = "db-mongo")]
+    pub fn search_recent(
+        &self,
+        message: &T,
+        name: &str,
+        epochs: u64,
+    ) -> Option<Vec<T>> {
+        let end = self.get_epoch() + 1;
+        let tokens =
+            self.search_tokens_in(message, end.saturating_sub(epochs)..end)?;
+        self.search_impl(tokens, name)
+    }
+}
+
+impl<T> Conn for ContextStreaming<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+{
+    #[cfg(feature = "db-mongo")]
+    fn get_conn(&self) -> &Connector<Data> {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl<T> SizeAllocated for ContextStreaming<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+{
+    fn size_allocated(&self) -> usize {
+        self.window
+            .iter()
+            .map(|epoch| epoch.sketch.size_allocated())
+            .sum::<usize>()
+            + self
+                .heavy_hitters
+                .iter()
+                .map(|(k, v)| k.size_allocated() + v.size_allocated())
+                .sum::<usize>()
+            + self.salts.size_allocated()
+    }
+}
+
+impl<T> BaseCrypto<T> for ContextStreaming<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+{
+    fn key_generate(&mut self) {
+        self.key = self.cipher.key_generate();
+    }
+
+    fn set_key(&mut self, key: &[u8]) {
+        self.key = key.to_vec();
+    }
+
+    fn get_key(&self) -> &[u8] {
+        &self.key
+    }
+
+    fn set_cipher(&mut self, cipher: Box<dyn Cipher>) {
+        self.cipher = cipher;
+    }
+
+    fn get_cipher(&self) -> &dyn Cipher {
+        self.cipher.as_ref()
+    }
+
+    fn set_token_limit(&mut self, limit: Option<TokenLimit>) {
+        self.token_limit = limit;
+    }
+
+    fn get_token_limit(&self) -> Option<&TokenLimit> {
+        self.token_limit.as_ref()
+    }
+
+    fn token_count(&self, message: &T) -> Option<usize> {
+        (0..self.get_epoch() + 1)
+            .map(|epoch| self.get_salts(message, epoch).map(|e| e as usize))
+            .sum()
+    }
+
+    /// Encrypt the next message of the stream under a salt sampled uniformly for the current epoch.
+    fn encrypt(&mut self, message: &T) -> Option<Vec<Vec<u8>>> {
+        self.observe(message);
+
+        let epoch = self.get_epoch();
+        let salts = self.get_salts(message, epoch)?;
+        let salt = Uniform::new(0, salts).sample(&mut OsRng);
+        Some(vec![self.encrypt_with_salt(message, epoch, salt)?])
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        self.decrypt_with_epoch(ciphertext)
+            .map(|(plaintext, _)| plaintext)
+    }
+
+    /// Generate the search tokens of `message` for every epoch observed so far.
+    fn search_tokens(&mut self, message: &T) -> Option<TokenSet> {
+        self.search_tokens_in(message, 0..self.get_epoch() + 1)
+    }
+}
+
+impl<T> LocalState<T> for ContextStreaming<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+{
+    /// The state holds the parameters, the sketches of the sliding window, the heavy hitters and the salts of every
+    /// epoch, so that the stream can be resumed and searched across all its epochs.
+    fn export_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        let params = &self.params;
+        for value in [
+            params.epoch_size,
+            params.window,
+            params.heavy_hitters,
+            params.width,
+            params.depth,
+        ] {
+            writer.put_u64(value as u64);
+        }
+        writer.put_u64(self.window.len() as u64);
+        for epoch in self.window.iter() {
+            writer.put_u64(epoch.count);
+            for &value in epoch.sketch.table.iter() {
+                writer.put_u64(value);
+            }
+        }
+        writer.put_u64(self.heavy_hitters.len() as u64);
+        for (message, cnt) in self.heavy_hitters.iter() {
+            writer.put_bytes(&message.to_bytes());
+            writer.put_u64(*cnt);
+        }
+        writer.put_u64(self.salts.len() as u64);
+        for salts in self.salts.iter() {
+            writer.put_u64(salts.len() as u64);
+            for (message, salt) in salts.iter() {
+                writer.put_bytes(&message.to_bytes());
+                writer.put_u64(*salt);
+            }
+        }
+        writer.finish()
+    }
+
+    fn import_state(&mut self, state: &[u8]) -> Result<()> {
+        let mut reader = StateReader::new(state);
+        let malformed = || "Malformed streaming state.";
+
+        let mut values = [0usize; 5];
+        for value in values.iter_mut() {
+            *value = reader.get_usize().ok_or_else(malformed)?;
+        }
+        let [epoch_size, window_len, heavy_hitters, width, depth] = values;
+        let params = StreamingParams {
+            epoch_size,
+            window: window_len,
+            heavy_hitters,
+            width,
+            depth,
+        };
+        params.validate()?;
+
+        let len = reader.get_usize().ok_or_else(malformed)?;
+        let mut window = VecDeque::new();
+        for _ in 0..len {
+            let count = reader.get_u64().ok_or_else(malformed)?;
+            let mut sketch = CountMinSketch::new(width, depth);
+            for value in sketch.table.iter_mut() {
+                *value = reader.get_u64().ok_or_else(malformed)?;
+            }
+            window.push_back(EpochSketch { sketch, count });
+        }
+        let len = reader.get_usize().ok_or_else(malformed)?;
+        let mut hitters = HashMap::new();
+        for _ in 0..len {
+            let message =
+                T::from_bytes(reader.get_bytes().ok_or_else(malformed)?);
+            hitters.insert(message, reader.get_u64().ok_or_else(malformed)?);
+        }
+        let len = reader.get_usize().ok_or_else(malformed)?;
+        let mut salts = Vec::new();
+        for _ in 0..len {
+            let num = reader.get_usize().ok_or_else(malformed)?;
+            let mut epoch = HashMap::new();
+            for _ in 0..num {
+                let message =
+                    T::from_bytes(reader.get_bytes().ok_or_else(malformed)?);
+                epoch.insert(message, reader.get_u64().ok_or_else(malformed)?);
+            }
+            salts.push(epoch);
+        }
+        // The current epoch is the last one of both the window and the salts.
+        if !reader.is_empty() || window.is_empty() || salts.is_empty() {
+            return Err(malformed().into());
+        }
+
+        self.params = params;
+        self.window = window;
+        self.heavy_hitters = hitters;
+        self.salts = salts;
+        Ok(())
+    }
+}
+
+impl<T> Portable<T> for ContextStreaming<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+{
+    fn scheme_name(&self) -> &'static str {
+        "streaming"
+    }
+
+    fn scheme_params(&self) -> Option<SchemeParams> {
+        Some(self.params.into())
+    }
+}
@@ -2,21 +2,42 @@
 //!
 //! They present a new efficiently searchable, easily deployable database encryption scheme that is provably
 //! secure against inference attacks even when used with real, low-entropy data.
+//!
+//! Each message is encrypted deterministically together with a salt drawn from its own salts, so that its frequency is
+//! split among the search tags of the salts. A search sends the tags of every salt. The tag frequencies follow the
+//! [`SaltAllocation`]: under the fixed Poisson allocation, the tags of each message sum up to its count, which is what
+//! the counting attack of Lacharité and Paterson looks for; the bucketized allocation is their mitigation.
+//!
+//! A hardened context keys its frequency table and its salts by keyed hashes of the messages, so a leaked state does
+//! not name the messages. It cannot export its domain.
 
-use std::{collections::HashMap, fmt::Debug, hash::Hash};
+use std::{collections::HashMap, fmt::Debug, hash::Hash, marker::PhantomData};
 
-use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
 use log::error;
 use rand::seq::SliceRandom;
 use rand_core::OsRng;
-use rand_distr::{Distribution, Exp, Uniform, WeightedAliasIndex};
+use rand_distr::{Distribution, Exp, WeightedAliasIndex};
 
 use crate::{
-    db::{Connector, Data},
-    fse::{AsBytes, BaseCrypto, Conn, FromBytes},
-    util::{build_histogram, build_histogram_vec, SizeAllocated},
+    cipher::{default_cipher, Cipher, ZERO_NONCE},
+    envelope::Portable,
+    error::FseError,
+    fse::{
+        AsBytes, BaseCrypto, Conn, Domain, FromBytes, LocalState, TokenLimit,
+    },
+    params::{SaltAllocation, SchemeParams, WreParams},
+    security::StateLeakage,
+    token::TokenSet,
+    util::{
+        build_histogram, build_histogram_vec, keyed_tag, SizeAllocated,
+        StateReader, StateWriter, EPSILON,
+    },
+    FSEType, Result,
 };
 
+#[cfg(feature = "db-mongo")]
+use crate::db::{Connector, Data};
+
 #[derive(Debug)]
 pub struct ContextWRE<T>
 where
@@ -24,12 +45,22 @@ where
 {
     /// The parameter for the Poisson salt allocation.
     lambda: usize,
+    allocation: SaltAllocation,
     /// A random key.
     key: Vec<u8>,
+    /// The cipher for symmetric encryption.
+    cipher: Box<dyn Cipher>,
+    /// The bound on the tokens of each search.
+    token_limit: Option<TokenLimit>,
     /// The connector.
+    #[cfg(feature = "db-mongo")]
     conn: Option<Connector<Data>>,
-    /// The frequency table.
-    local_table: HashMap<T, f64>,
+    /// The frequency table, keyed by the bytes of each message or by its keyed hash if hardened.
+    local_table: HashMap<Vec<u8>, f64>,
+    /// The salts of each message with the frequency of the tag of each, keyed like the frequency table.
+    salts: HashMap<Vec<u8>, Vec<(usize, f64)>>,
+    hardened: bool,
+    _marker: PhantomData<T>,
 }
 
 impl<T> ContextWRE<T>
@@ -39,138 +70,192 @@ where
     pub fn new(lambda: usize) -> Self {
         Self {
             lambda,
+            allocation: SaltAllocation::default(),
             key: Vec::new(),
+            cipher: default_cipher(),
+            token_limit: None,
+            #[cfg(feature = "db-mongo")]
             conn: None,
             local_table: HashMap::new(),
+            salts: HashMap::new(),
+            hardened: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Key the local table by keyed hashes of the messages instead of the messages themselves. The key must be set
+    /// before the local table is built and must not change afterwards. Fails if the local table is built already.
+    pub fn set_hardened(&mut self, hardened: bool) -> Result<()> {
+        if !self.local_table.is_empty() {
+            return Err("The local table is already built.".into());
+        }
+
+        self.hardened = hardened;
+        Ok(())
+    }
+
+    pub fn is_hardened(&self) -> bool {
+        self.hardened
+    }
+
+    /// What the frequency table reveals if the local state leaks.
+    pub fn state_leakage(&self) -> StateLeakage {
+        let table = self
+            .local_table
+            .iter()
+            .map(|(message, &frequency)| (message.clone(), frequency))
+            .collect();
+        StateLeakage::new(table, !self.hardened)
+    }
+
+    /// The key of `message` in the local table.
+    fn table_key(&self, message: &T) -> Vec<u8> {
+        match self.hardened {
+            true => keyed_tag(&self.key, &message.to_bytes()),
+            false => message.to_bytes(),
         }
     }
 
-    /// Initializes the struct.
+    /// Construct the context from validated parameters.
+    pub fn from_params(params: &WreParams) -> Result<Self> {
+        params.validate()?;
+        let mut ctx = Self::new(params.lambda);
+        ctx.allocation = params.allocation;
+        Ok(ctx)
+    }
+
+    /// Initializes the struct. Fails with [`FseError::EmptyDataset`] if `messages` is empty.
+    #[cfg(feature = "db-mongo")]
     pub fn initialize(
         &mut self,
         messages: &[T],
         address: &str,
         db_name: &str,
         drop: bool,
-    ) {
+    ) -> Result<()> {
         // Initialize the local table.
-        let histogram = build_histogram(messages);
-        let sum = histogram.iter().map(|(k, v)| v).sum::<usize>();
-        self.local_table = histogram
-            .into_iter()
-            .map(|(k, v)| {
-                let frequency = v as f64 / sum as f64;
-                (k, frequency)
-            })
-            .collect();
+        self.initialize_histogram(&build_histogram(messages))?;
 
         // Initialize the connector.
         if let Ok(conn) = Connector::new(address, db_name, drop) {
             self.conn = Some(conn);
         }
+        Ok(())
     }
 
-    /// Get the Poisson salt. The fixed Poisson WRE approach above generated randomized search tags
-    /// for each plaintext. However, the scheme has security flaw: When the adversary has the frequencies
-    /// of all search tags and knows PM, Lacharite and Paterson pointed out another possible attack,
-    /// wherein the adversary finds a set of search tags whose counts sum up to the expected count for
-    /// a (set of) target plaintext(s). The adversary might then reasonably conclude that those search
-    /// tags all represent encryptions of the given plaintext(s).
-    ///
-    /// Thus, they use the bucketized Poisson salt allocation scheme to prevent such an attack.
-    ///
-    /// This function returns the salt hashmap where the key is the salt and the value is the weight of
-    /// this salt. The algorithm them samples a salt according to the frequency of the hashmap.
-    #[deprecated]
-    fn get_salt_set(&self, message: &T) -> (Vec<usize>, Vec<f64>) {
-        let mut s = 0usize;
-        let mut word_frequency = Vec::new();
-        let mut salts = Vec::new();
-        let mut total = 0f64;
-        let mut weights = HashMap::new();
-
-        // The exponential distribution Exp(lambda).
-        let exp_distribution = Exp::new(self.lambda as f64).unwrap();
-
-        while total < 1.0 {
-            s += 1;
-            let weight = exp_distribution.sample(&mut OsRng);
-            weights.insert(s, weight);
-            total += weight;
+    /// Initialize the local table only from a histogram of the message dataset, e.g., one read by
+    /// [`crate::util::read_csv_weighted`]. Fails with [`FseError::EmptyDataset`] if it counts no message.
+    pub fn initialize_histogram(
+        &mut self,
+        histogram: &HashMap<T, usize>,
+    ) -> Result<()> {
+        let sum = histogram.values().sum::<usize>();
+        if sum == 0 {
+            return Err(FseError::EmptyDataset.into());
         }
 
-        weights.entry(s).and_modify(|frequency| {
-            *frequency -= total - 1.0;
-        });
-
-        // Get a psedorandom permutation from message (histogram)
-        let mut m_prime = self
-            .local_table
+        self.local_table = histogram
             .iter()
-            .map(|(k, v)| (k, *v))
-            .collect::<Vec<_>>();
-        m_prime.shuffle(&mut OsRng);
-        let idx = match m_prime.iter().position(|&(k, v)| k == message) {
-            Some(idx) => idx,
-            // Does not exists, this should be an error.
-            None => return (vec![], vec![]),
-        };
-        // fr = P_M(m_1) + ... + PM(m_{x − 1}) where m = mx (<- the current message.)
-        let fr = match idx {
-            0 => m_prime.first().unwrap().1,
-            idx => m_prime[..idx].iter().map(|&(k, v)| v).sum::<f64>().min(1.0),
-        };
+            .filter(|(_, &v)| v != 0)
+            .map(|(k, &v)| (self.table_key(k), v as f64 / sum as f64))
+            .collect();
+        self.allocate_salts();
+        Ok(())
+    }
 
-        let mut i = 0usize;
-        let mut cdf = 0f64;
-        while cdf < fr {
-            cdf += *weights.get(&i).unwrap_or(&0.0);
-            i += 1;
-        }
+    /// Allocate the salts of every message of the local table. A single message has nothing to hide its frequency
+    /// from, so it takes a single salt.
+    fn allocate_salts(&mut self) {
+        let rate = self.lambda as f64;
+        let mut messages = self.local_table.iter().collect::<Vec<_>>();
+        // A histogram is a map, so the order of the messages is random anyway; shuffling makes it explicit.
+        messages.shuffle(&mut OsRng);
 
-        weights
-            .entry(i)
-            .and_modify(|frequency| *frequency = cdf - fr);
-        cdf = fr;
-        i -= 1;
-        let message_frequency = match self.local_table.get(message) {
-            Some(&v) => v,
-            None => return (vec![], vec![]),
+        self.salts = match (messages.len(), self.allocation) {
+            (1, _) => messages
+                .into_iter()
+                .map(|(message, _)| (message.clone(), vec![(0, 1.0)]))
+                .collect(),
+            (_, SaltAllocation::Fixed) => messages
+                .into_iter()
+                .map(|(message, &frequency)| {
+                    let weights = poisson_cuts(rate, frequency)
+                        .into_iter()
+                        .enumerate()
+                        .collect();
+                    (message.clone(), weights)
+                })
+                .collect(),
+            (_, SaltAllocation::Bucketized) => {
+                let mut buckets = poisson_cuts(rate, 1.0).into_iter();
+                let mut bucket = (0, buckets.next().unwrap_or(1.0));
+                let mut salts = HashMap::new();
+                for (message, &frequency) in messages {
+                    let mut weights = Vec::new();
+                    let mut remaining = frequency;
+                    while remaining > EPSILON {
+                        let overlap = remaining.min(bucket.1);
+                        weights.push((bucket.0, overlap));
+                        remaining -= overlap;
+                        bucket.1 -= overlap;
+                        if bucket.1 <= EPSILON {
+                            // Rounding may use up the buckets before the last message; it keeps the last bucket.
+                            bucket = match buckets.next() {
+                                Some(length) => (bucket.0 + 1, length),
+                                None => (bucket.0, f64::INFINITY),
+                            };
+                        }
+                    }
+                    salts.insert(message.clone(), weights);
+                }
+                salts
+            }
         };
+    }
 
-        while cdf < (fr + message_frequency).min(1.0) {
-            let weight = *weights.get(&i).unwrap();
-            word_frequency.push(weight / fr);
-            salts.push(i);
-            println!("cdf = {cdf}, fr = {fr}, message_frequency = {message_frequency}, weight = {weight}, i = {i}");
-            i += 1;
-            cdf += *weights.get(&i).unwrap_or(&0.0);
-        }
+    /// Sample a salt of `message` according to the weights of its salts.
+    fn get_salt(&self, message: &T) -> Option<usize> {
+        let weights = self.salts.get(&self.table_key(message))?;
+        let distribution =
+            WeightedAliasIndex::new(weights.iter().map(|e| e.1).collect())
+                .ok()?;
+        Some(weights[distribution.sample(&mut OsRng)].0)
+    }
 
-        if cdf > fr + message_frequency {
-            let diff = fr + message_frequency - cdf;
-            let weight = *weights.get(&i).unwrap_or(&0.0);
-            word_frequency.push((weight - diff) / fr);
-            salts.push(i);
-        }
+    /// Get the salts of `message` with the frequency of the tag of each.
+    pub fn get_salts(&self, message: &T) -> Option<&[(usize, f64)]> {
+        self.salts
+            .get(&self.table_key(message))
+            .map(|e| e.as_slice())
+    }
 
-        // After the information is collected, one can use rand_distr::WeightedAliasIndex to sample a salt
-        // from the multinomial distribution.
-        (salts, word_frequency)
+    fn encrypt_with_salt(&self, message: &T, salt: usize) -> Option<Vec<u8>> {
+        let mut plaintext = message.to_bytes();
+        plaintext.extend_from_slice(&(salt as u64).to_le_bytes());
+        self.cipher.encrypt(&self.key, &ZERO_NONCE, &plaintext)
     }
+}
 
-    /// Sample a salt according to the multinomial distribution.
-    fn get_salt(&self, weights: &(Vec<usize>, Vec<f64>)) -> usize {
-        let distribution = WeightedAliasIndex::new(weights.1.clone()).unwrap();
-        let index = distribution.sample(&mut OsRng);
-        *weights.0.get(index).unwrap()
+/// Cut `[0, total)` into consecutive pieces of `Exp(rate)` lengths, the last one truncated at `total`.
+fn poisson_cuts(rate: f64, total: f64) -> Vec<f64> {
+    let distribution = Exp::new(rate).unwrap();
+    let mut cuts = Vec::new();
+    let mut covered = 0.0;
+    while covered < total {
+        let length = distribution.sample(&mut OsRng).min(total - covered);
+        if length > 0.0 {
+            cuts.push(length);
+        }
+        covered += length;
     }
+    cuts
 }
 
 impl<T> Conn for ContextWRE<T>
 where
     T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
 {
+    #[cfg(feature = "db-mongo")]
     fn get_conn(&self) -> &Connector<Data> {
         self.conn.as_ref().unwrap()
     }
@@ -190,37 +275,154 @@ where
     T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
 {
     fn key_generate(&mut self) {
-        self.key = Aes256Gcm::generate_key(&mut OsRng).to_vec();
+        self.key = self.cipher.key_generate();
+    }
+
+    fn set_key(&mut self, key: &[u8]) {
+        self.key = key.to_vec();
+    }
+
+    fn get_key(&self) -> &[u8] {
+        &self.key
+    }
+
+    fn set_cipher(&mut self, cipher: Box<dyn Cipher>) {
+        self.cipher = cipher;
+    }
+
+    fn get_cipher(&self) -> &dyn Cipher {
+        self.cipher.as_ref()
+    }
+
+    fn set_token_limit(&mut self, limit: Option<TokenLimit>) {
+        self.token_limit = limit;
+    }
+
+    fn get_token_limit(&self) -> Option<&TokenLimit> {
+        self.token_limit.as_ref()
     }
 
     fn encrypt(&mut self, message: &T) -> Option<Vec<Vec<u8>>> {
-        let salts = self.get_salt_set(message);
-        let salt = self.get_salt(&salts);
-        let aes = match Aes256Gcm::new_from_slice(&self.key) {
-            Ok(aes) => aes,
-            Err(e) => {
-                error!(
-                    "Error constructing the AES context due to {:?}.",
-                    e.to_string()
-                );
-                return None;
+        let salt = self.get_salt(message)?;
+        Some(vec![self.encrypt_with_salt(message, salt)?])
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        let mut plaintext =
+            self.cipher.decrypt(&self.key, &ZERO_NONCE, ciphertext)?;
+        let len = plaintext.len().checked_sub(std::mem::size_of::<u64>())?;
+        plaintext.truncate(len);
+        Some(plaintext)
+    }
+
+    /// The tags of every salt of `message`.
+    fn search_tokens(&mut self, message: &T) -> Option<TokenSet> {
+        self.salts
+            .get(&self.table_key(message))?
+            .iter()
+            .map(|&(salt, _)| self.encrypt_with_salt(message, salt))
+            .collect()
+    }
+}
+
+impl<T> Domain<T> for ContextWRE<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+{
+    /// Empty if hardened, as the keyed hashes cannot be inverted.
+    fn export_domain(&self) -> Vec<T> {
+        match self.hardened {
+            true => Vec::new(),
+            false => {
+                self.local_table.keys().map(|e| T::from_bytes(e)).collect()
             }
-        };
+        }
+    }
 
-        let nonce = Nonce::from_slice(&[0u8; 12]);
-        match aes.encrypt(nonce, salt.to_le_bytes().as_slice()) {
-            Ok(ciphertext) => Some(vec![ciphertext]),
-            Err(e) => {
-                error!(
-                    "Error encrypting the message due to {:?}.",
-                    e.to_string()
-                );
-                None
+    fn contains(&self, message: &T) -> bool {
+        self.local_table.contains_key(&self.table_key(message))
+    }
+}
+
+impl<T> LocalState<T> for ContextWRE<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+{
+    /// The salts are part of the state: drawing them again would change the tags of the stored ciphertexts.
+    fn export_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.put_u64(self.hardened as u64);
+        writer.put_u64(self.lambda as u64);
+        writer.put_u64(self.allocation as u64);
+        writer.put_u64(self.local_table.len() as u64);
+        for (message, frequency) in self.local_table.iter() {
+            let salts = self.salts.get(message).map_or(&[][..], |e| e);
+            writer.put_bytes(message);
+            writer.put_f64(*frequency);
+            writer.put_u64(salts.len() as u64);
+            for &(salt, weight) in salts.iter() {
+                writer.put_u64(salt as u64);
+                writer.put_f64(weight);
             }
         }
+        writer.finish()
     }
 
-    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
-        todo!()
+    fn import_state(&mut self, state: &[u8]) -> Result<()> {
+        let mut reader = StateReader::new(state);
+        let malformed = || "Malformed WRE state.";
+
+        let hardened = reader.get_u64().ok_or_else(malformed)? != 0;
+        let lambda = reader.get_usize().ok_or_else(malformed)?;
+        let allocation = match reader.get_u64().ok_or_else(malformed)? {
+            0 => SaltAllocation::Fixed,
+            1 => SaltAllocation::Bucketized,
+            _ => return Err(malformed().into()),
+        };
+        let len = reader.get_usize().ok_or_else(malformed)?;
+        let mut local_table = HashMap::new();
+        let mut salts = HashMap::new();
+        for _ in 0..len {
+            let message = reader.get_bytes().ok_or_else(malformed)?.to_vec();
+            let frequency = reader.get_f64().ok_or_else(malformed)?;
+            let num = reader.get_usize().ok_or_else(malformed)?;
+            let mut weights = Vec::new();
+            for _ in 0..num {
+                let salt = reader.get_usize().ok_or_else(malformed)?;
+                weights.push((salt, reader.get_f64().ok_or_else(malformed)?));
+            }
+            local_table.insert(message.clone(), frequency);
+            salts.insert(message, weights);
+        }
+        if !reader.is_empty() {
+            return Err(malformed().into());
+        }
+        WreParams { lambda, allocation }.validate()?;
+
+        self.hardened = hardened;
+        self.lambda = lambda;
+        self.allocation = allocation;
+        self.local_table = local_table;
+        self.salts = salts;
+        Ok(())
+    }
+}
+
+impl<T> Portable<T> for ContextWRE<T>
+where
+    T: Hash + AsBytes + FromBytes + Eq + Debug + Clone + SizeAllocated,
+{
+    fn scheme_name(&self) -> &'static str {
+        FSEType::Wre.name()
+    }
+
+    fn scheme_params(&self) -> Option<SchemeParams> {
+        Some(
+            WreParams {
+                lambda: self.lambda,
+                allocation: self.allocation,
+            }
+            .into(),
+        )
     }
 }
@@ -0,0 +1,150 @@
+//! This module computes the analytical bounds on the advantage of inference attackers from the final smoothing state of
+//! a scheme, so that the theoretical guarantee can be reported next to the empirical attack accuracies.
+//!
+//! * PFSE: the advantage of the MLE attacker over the baseline within partition `G_i` is `n * f_i / N_i`, where
+//!   `f_i = sum_{m in G_i} f_D(m)^2` and `N_i` is the number of distinct ciphertexts (dummies included) of `G_i`.
+//!   The bound is the maximum over all partitions.
+//! * IHBE: the advantage of the K-S distinguisher is `sqrt(n) / (2 * sqrt(2 * pi) * |I_min|)`, where `|I_min|` is the
+//!   size of the smallest homophone interval, i.e., `2^r * f_D(m_1)`.
+//! * BHE: the advantage of the K-S distinguisher is `sqrt(n / (pi * 2^(l + 1))) / 2`, where `l` is the band length.
+//! * A dataset of a single distinct message: every record encrypts the same message, so the ciphertexts reveal nothing
+//!   beyond the number of records and the advantage is 0 under any scheme.
+//!
+//! All bounds are clamped into `[0, 1]`.
+//!
+//! The bounds assume that the client-side state stays secret. [`StateLeakage`] accounts for what the local table of a
+//! context gives away if it leaks, e.g., from a stolen snapshot: every scheme but DTE keeps the histogram of the
+//! dataset in the clear, so the leak reveals it exactly. A hardened table keyed by keyed hashes still reveals the
+//! counts, but not which plaintext each count belongs to.
+
+use std::f64::consts::PI;
+
+use crate::util::checked_div;
+
+/// The smoothing state of a single PFSE partition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionState {
+    /// The counts of the real messages (or of their parts) within this partition.
+    pub counts: Vec<usize>,
+    /// The number of distinct ciphertexts of this partition, including dummies.
+    pub ciphertext_num: usize,
+}
+
+/// The final smoothing state of a scheme.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemeState {
+    Pfse {
+        message_num: usize,
+        partitions: Vec<PartitionState>,
+    },
+    Ihbe {
+        message_num: usize,
+        /// The sizes of the homophone intervals.
+        intervals: Vec<u64>,
+    },
+    Bhe {
+        message_num: usize,
+        /// The band length `l`.
+        length: usize,
+    },
+    /// The dataset has a single distinct message, so the scheme encrypts it as one ciphertext without smoothing.
+    Degenerate { message_num: usize },
+}
+
+/// Compute the analytical advantage bound of the scheme state.
+pub fn advantage_bound(state: &SchemeState) -> f64 {
+    let bound = match state {
+        SchemeState::Pfse {
+            message_num,
+            partitions,
+        } => pfse_bound(*message_num, partitions),
+        SchemeState::Ihbe {
+            message_num,
+            intervals,
+        } => intervals.iter().min().and_then(|&interval| {
+            checked_div(
+                (*message_num as f64).sqrt(),
+                2.0 * (2.0 * PI).sqrt() * interval as f64,
+            )
+        }),
+        SchemeState::Bhe {
+            message_num,
+            length,
+        } => {
+            checked_div(*message_num as f64, PI * 2f64.powi(*length as i32 + 1))
+                .map(|e| e.sqrt() / 2.0)
+        }
+        SchemeState::Degenerate { .. } => Some(0.0),
+    };
+
+    // An empty state gives no guarantee at all.
+    bound.unwrap_or(1.0).clamp(0.0, 1.0)
+}
+
+fn pfse_bound(
+    message_num: usize,
+    partitions: &[PartitionState],
+) -> Option<f64> {
+    let n = message_num as f64;
+    partitions
+        .iter()
+        .filter(|partition| !partition.counts.is_empty())
+        .map(|partition| {
+            let f_i = partition
+                .counts
+                .iter()
+                .map(|&cnt| (cnt as f64 / n).powi(2))
+                .sum::<f64>();
+            checked_div(n * f_i, partition.ciphertext_num as f64).unwrap_or(1.0)
+        })
+        .reduce(f64::max)
+}
+
+/// What the local table of a context reveals if it leaks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateLeakage {
+    /// The plaintexts of the table with the count (or the frequency) of each, in descending order of the count. `None`
+    /// if the table only stores keyed hashes of the plaintexts.
+    pub histogram: Option<Vec<(Vec<u8>, f64)>>,
+    /// The counts of the table in descending order, which leak even if the plaintexts do not.
+    pub counts: Vec<f64>,
+    /// The Shannon entropy in bits of the distribution of the counts, i.e., how much the leaked histogram tells about
+    /// a single record.
+    pub entropy: f64,
+}
+
+impl StateLeakage {
+    /// The leakage of a table of `(key, count)` entries. The keys are plaintexts if `plaintexts` is set.
+    pub fn new(mut table: Vec<(Vec<u8>, f64)>, plaintexts: bool) -> Self {
+        table.sort_by(|lhs, rhs| {
+            rhs.1.total_cmp(&lhs.1).then(lhs.0.cmp(&rhs.0))
+        });
+        let counts = table.iter().map(|e| e.1).collect::<Vec<_>>();
+        let sum = counts.iter().sum::<f64>();
+        let entropy = counts
+            .iter()
+            .filter(|&&cnt| cnt > 0.0)
+            .map(|&cnt| -(cnt / sum) * (cnt / sum).log2())
+            .sum::<f64>();
+
+        Self {
+            histogram: plaintexts.then_some(table),
+            counts,
+            entropy: entropy.max(0.0),
+        }
+    }
+
+    /// The leakage of a context that keeps no local table.
+    pub fn none() -> Self {
+        Self::new(Vec::new(), false)
+    }
+
+    /// The number of entries of the table.
+    pub fn entries(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn reveals_plaintexts(&self) -> bool {
+        matches!(&self.histogram, Some(histogram) if !histogram.is_empty())
+    }
+}
@@ -0,0 +1,278 @@
+//! This module implements the two-server deployment, where the search tags of the ciphertexts live on an index server
+//! and the records themselves on a payload server, joined by a random document id. A tag is the smoothed ciphertext
+//! a scheme would store anyway, and a payload is the randomized encryption of its message under a key derived from
+//! the key of the context, so
+//!
+//! - a compromise of the index server alone reveals the smoothed tags, i.e., no more than the single-server mode;
+//! - a compromise of the payload server alone reveals the number and the sizes of the records only, since the ids are
+//!   random and the payloads are encrypted under fresh nonces.
+//!
+//! The join happens on the client: the tags matching a query are fetched from the index server, and their payloads
+//! from the payload server by id. The payload server thus sees which payloads are fetched together, but not by which
+//! tags, as long as the two servers do not collude.
+
+use std::fmt::Debug;
+
+use rand_core::{OsRng, RngCore};
+
+use crate::{
+    cipher::{Cipher, NONCE_LEN},
+    error::FseError,
+    fse::{AsBytes, BaseCrypto, FromBytes},
+    util::hmac,
+    Result,
+};
+
+#[cfg(feature = "db-mongo")]
+use mongodb::bson::{doc, Document};
+#[cfg(feature = "db-mongo")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "db-mongo")]
+use crate::{
+    db::{Connector, JOIN_FIELD},
+    fse::{token_filters, SearchOutcome},
+};
+
+/// The length of the random ids that join the tags to their payloads in bytes.
+pub const ID_LEN: usize = 16;
+
+/// The label of the derivation of the payload key from the key of the context.
+const PAYLOAD_KEY_LABEL: &[u8] = b"fse-split-payload-key";
+
+/// A ciphertext split into the tag stored by the index server and the payload stored by the payload server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaggedPayload {
+    /// The ciphertext the scheme would store in the single-server mode, which the searches match.
+    pub tag: Vec<u8>,
+    pub id: Vec<u8>,
+    /// `nonce || ciphertext` of the message of the tag under the payload key.
+    pub payload: Vec<u8>,
+}
+
+/// A fresh random id.
+pub fn random_id() -> Vec<u8> {
+    let mut id = vec![0u8; ID_LEN];
+    OsRng.fill_bytes(&mut id);
+    id
+}
+
+/// This trait lets every scheme emit (tag, payload) pairs for the two-server deployment. It is implemented for all
+/// contexts, since the tags are their ordinary ciphertexts.
+pub trait SplitEncryption<T>: BaseCrypto<T>
+where
+    T: AsBytes + FromBytes + Debug,
+{
+    /// The key of the payloads, derived from the key of the context so that it is not another secret to keep.
+    fn payload_key(&self) -> Vec<u8> {
+        let mut key = hmac(self.get_key(), PAYLOAD_KEY_LABEL);
+        key.truncate(self.get_cipher().key_len());
+        key
+    }
+
+    /// Split the ciphertexts of the context, e.g., those of [`crate::fse::PartitionFrequencySmoothing::smooth`],
+    /// into (tag, payload) pairs under fresh ids. A tag the context cannot decrypt, i.e., a dummy, gets the payload of
+    /// its own bytes, which no search ever fetches, so that the payload server cannot tell the dummies apart.
+    fn split(&self, tags: Vec<Vec<u8>>) -> Result<Vec<TaggedPayload>> {
+        let key = self.payload_key();
+        tags.into_iter()
+            .map(|tag| {
+                let message =
+                    match self.get_cipher().verify_key(self.get_key(), &tag) {
+                        Ok(()) => self.decrypt(&tag),
+                        Err(_) => None,
+                    }
+                    .unwrap_or_else(|| tag.clone());
+                Ok(TaggedPayload {
+                    payload: seal(self.get_cipher(), &key, &message)?,
+                    id: random_id(),
+                    tag,
+                })
+            })
+            .collect()
+    }
+
+    /// Encrypt `message` like [`BaseCrypto::encrypt`] and split each of its ciphertexts.
+    fn encrypt_split(&mut self, message: &T) -> Result<Vec<TaggedPayload>> {
+        let tags =
+            self.encrypt(message).ok_or("Cannot encrypt the message.")?;
+        self.split(tags)
+    }
+
+    /// Decrypt a payload into the message of its tag.
+    fn decrypt_payload(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        open(self.get_cipher(), &self.payload_key(), payload)
+    }
+
+    /// Search a given message `T` in the collection `name` of both servers of `store` under the token limit of the
+    /// context, and decrypt the payloads of the matching tags. See [`BaseCrypto::search_checked`].
+    #[cfg(feature = "db-mongo")]
+    fn search_split(
+        &mut self,
+        message: &T,
+        store: &SplitStore,
+        name: &str,
+    ) -> Result<SearchOutcome<T>> {
+        let query = self.query_tokens(message)?;
+        let mut results = Vec::new();
+        for filter in token_filters(&query.tokens) {
+            for document in store.search_joined(filter, name)? {
+                let message = self.decrypt_payload(&document.payload)?;
+                results.push(T::from_bytes(&message));
+            }
+        }
+
+        Ok(SearchOutcome {
+            results,
+            recall: query.recall(),
+        })
+    }
+}
+
+impl<T, C> SplitEncryption<T> for C
+where
+    T: AsBytes + FromBytes + Debug,
+    C: BaseCrypto<T> + ?Sized,
+{
+}
+
+fn seal(cipher: &dyn Cipher, key: &[u8], message: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = vec![0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let mut ciphertext = cipher
+        .encrypt(key, &nonce, message)
+        .ok_or("Cannot encrypt the payload.")?;
+    nonce.append(&mut ciphertext);
+    Ok(nonce)
+}
+
+fn open(cipher: &dyn Cipher, key: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    if payload.len() < NONCE_LEN {
+        return Err(FseError::DecryptionFailed.into());
+    }
+    let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+    cipher.verify_key(key, ciphertext)?;
+    cipher
+        .decrypt(key, nonce, ciphertext)
+        .ok_or_else(|| FseError::DecryptionFailed.into())
+}
+
+/// A document of the index server.
+#[cfg(feature = "db-mongo")]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TagDocument {
+    /// The tag, under the same field as the ciphertext of [`crate::db::Data`] so that the same filters match it.
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub id: Vec<u8>,
+}
+
+/// A document of the payload server.
+#[cfg(feature = "db-mongo")]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PayloadDocument {
+    #[serde(with = "serde_bytes")]
+    pub id: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub payload: Vec<u8>,
+}
+
+/// The connections to the index and the payload server. A collection of the store is the collection of the same name
+/// on both servers.
+#[cfg(feature = "db-mongo")]
+#[derive(Debug)]
+pub struct SplitStore {
+    index: Connector<TagDocument>,
+    payloads: Connector<PayloadDocument>,
+}
+
+#[cfg(feature = "db-mongo")]
+impl SplitStore {
+    pub fn new(
+        index: Connector<TagDocument>,
+        payloads: Connector<PayloadDocument>,
+    ) -> Self {
+        Self { index, payloads }
+    }
+
+    /// Connect to the index server and the payload server, each given by its address and its database name. See
+    /// [`Connector::new`] for `drop`.
+    pub fn connect(
+        (index_address, index_db): (&str, &str),
+        (payload_address, payload_db): (&str, &str),
+        drop: bool,
+    ) -> Result<Self> {
+        Ok(Self::new(
+            Connector::new(index_address, index_db, drop)?,
+            Connector::new(payload_address, payload_db, drop)?,
+        ))
+    }
+
+    pub fn get_index(&self) -> &Connector<TagDocument> {
+        &self.index
+    }
+
+    pub fn get_payloads(&self) -> &Connector<PayloadDocument> {
+        &self.payloads
+    }
+
+    /// Insert the pairs into the collection `name`. The payloads are inserted first, so that a failure never leaves
+    /// a tag that matches without a payload behind.
+    pub fn insert(&self, pairs: &[TaggedPayload], name: &str) -> Result<()> {
+        let payloads = pairs
+            .iter()
+            .map(|e| PayloadDocument {
+                id: e.id.clone(),
+                payload: e.payload.clone(),
+            })
+            .collect::<Vec<_>>();
+        self.payloads.insert(payloads, name)?;
+        self.payloads.create_index(name, doc! { JOIN_FIELD: 1 })?;
+
+        let tags = pairs
+            .iter()
+            .map(|e| TagDocument {
+                data: e.tag.clone(),
+                id: e.id.clone(),
+            })
+            .collect::<Vec<_>>();
+        self.index.insert(tags, name)
+    }
+
+    /// Match `filter` against the tags of the collection `name` and fetch the payloads of the matches. Fails if the
+    /// payload server misses any of them.
+    pub fn search_joined(
+        &self,
+        filter: Document,
+        name: &str,
+    ) -> Result<Vec<PayloadDocument>> {
+        let mut ids = Vec::new();
+        for tag in self.index.search(filter, name)? {
+            ids.push(tag?.id);
+        }
+        let payloads = self.payloads.find_by_ids(&ids, name)?;
+        if payloads.len() != ids.len() {
+            return Err(format!(
+                "The payload server misses {} of the {} payloads of {}.",
+                ids.len().saturating_sub(payloads.len()),
+                ids.len(),
+                name
+            )
+            .into());
+        }
+
+        Ok(payloads)
+    }
+
+    /// The sizes of the collection `name` on the index and the payload server.
+    pub fn size(&self, name: &str) -> (usize, usize) {
+        (self.index.size(name), self.payloads.size(name))
+    }
+
+    /// Drop the collection `name` on both servers.
+    pub fn drop_collection(&self, name: &str) {
+        self.index.drop_collection(name);
+        self.payloads.drop_collection(name);
+    }
+}
@@ -0,0 +1,177 @@
+//! This module abstracts the store of the ciphertexts behind [`Storage`], so that an experiment can run against
+//! MongoDB through [`Connector`] or, with the `db-sled` feature, against the embedded [`SledBackend`], which needs no
+//! database server. A storage only keeps the ciphertexts of each collection and looks them up by the search tokens;
+//! the contexts search it by [`StorageSearch::search_in`].
+
+use std::fmt::Debug;
+
+use crate::{
+    fse::{AsBytes, BaseCrypto, FromBytes, SearchOutcome},
+    token::TokenSet,
+    Result,
+};
+
+#[cfg(feature = "db-mongo")]
+use crate::{
+    db::{Connector, Data},
+    fse::token_filters,
+};
+
+/// A store of ciphertexts grouped into named collections.
+pub trait Storage: Debug + Send + Sync {
+    /// Append the ciphertexts to the collection `name`. A ciphertext may be stored more than once.
+    fn insert(&self, ciphertexts: &[Vec<u8>], name: &str) -> Result<()>;
+
+    /// Every stored copy of each of the `tokens` in the collection `name`, in no particular order.
+    fn lookup(&self, tokens: &TokenSet, name: &str) -> Result<Vec<Vec<u8>>>;
+
+    /// The size of the collection `name` in bytes as reported by the store.
+    fn size(&self, name: &str) -> Result<usize>;
+
+    fn drop_collection(&self, name: &str) -> Result<()>;
+}
+
+#[cfg(feature = "db-mongo")]
+impl Storage for Connector<Data> {
+    fn insert(&self, ciphertexts: &[Vec<u8>], name: &str) -> Result<()> {
+        let documents = ciphertexts.iter().cloned().map(Data::new).collect();
+        Connector::insert(self, documents, name)
+    }
+
+    fn lookup(&self, tokens: &TokenSet, name: &str) -> Result<Vec<Vec<u8>>> {
+        let mut res = Vec::new();
+        for filter in token_filters(tokens) {
+            for data in self.search(filter, name)? {
+                res.push(data?.data);
+            }
+        }
+        Ok(res)
+    }
+
+    fn size(&self, name: &str) -> Result<usize> {
+        Ok(Connector::size(self, name))
+    }
+
+    fn drop_collection(&self, name: &str) -> Result<()> {
+        Connector::drop_collection(self, name);
+        Ok(())
+    }
+}
+
+/// A store embedded in the process by [`sled`]. Each collection is a tree whose keys are the ciphertexts prefixed by
+/// their length and followed by a sequence number, so that the copies of a ciphertext are distinct keys and a token
+/// is looked up by a scan of its prefix. The values are empty.
+#[cfg(feature = "db-sled")]
+#[derive(Debug, Clone)]
+pub struct SledBackend {
+    db: sled::Db,
+}
+
+#[cfg(feature = "db-sled")]
+impl SledBackend {
+    /// Open the store at `path`, creating it if it does not exist.
+    pub fn open(path: &str) -> Result<Self> {
+        Ok(Self {
+            db: sled::open(path)?,
+        })
+    }
+
+    /// Open a store that is removed once it is dropped, e.g., for tests.
+    pub fn temporary() -> Result<Self> {
+        Ok(Self {
+            db: sled::Config::new().temporary(true).open()?,
+        })
+    }
+
+    /// The prefix of the keys of `ciphertext`.
+    fn prefix(ciphertext: &[u8]) -> Vec<u8> {
+        let mut prefix = Vec::with_capacity(4 + ciphertext.len());
+        prefix.extend_from_slice(&(ciphertext.len() as u32).to_be_bytes());
+        prefix.extend_from_slice(ciphertext);
+        prefix
+    }
+
+    /// Flush the written ciphertexts to the disk.
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "db-sled")]
+impl Storage for SledBackend {
+    fn insert(&self, ciphertexts: &[Vec<u8>], name: &str) -> Result<()> {
+        let tree = self.db.open_tree(name)?;
+        let mut batch = sled::Batch::default();
+        for ciphertext in ciphertexts.iter() {
+            let mut key = Self::prefix(ciphertext);
+            key.extend_from_slice(&self.db.generate_id()?.to_be_bytes());
+            batch.insert(key, &[]);
+        }
+        tree.apply_batch(batch)?;
+        Ok(())
+    }
+
+    fn lookup(&self, tokens: &TokenSet, name: &str) -> Result<Vec<Vec<u8>>> {
+        let tree = self.db.open_tree(name)?;
+        let mut res = Vec::new();
+        for token in tokens.iter() {
+            for entry in tree.scan_prefix(Self::prefix(token)) {
+                entry?;
+                res.push(token.to_vec());
+            }
+        }
+        Ok(res)
+    }
+
+    fn size(&self, name: &str) -> Result<usize> {
+        let tree = self.db.open_tree(name)?;
+        let mut size = 0;
+        for entry in tree.iter() {
+            size += entry?.0.len();
+        }
+        Ok(size)
+    }
+
+    fn drop_collection(&self, name: &str) -> Result<()> {
+        self.db.drop_tree(name)?;
+        Ok(())
+    }
+}
+
+/// This trait lets every context search a [`Storage`]. It is implemented for all contexts.
+pub trait StorageSearch<T>: BaseCrypto<T>
+where
+    T: AsBytes + FromBytes + Debug,
+{
+    /// Search a given message `T` in the collection `name` of `storage` under the token limit of the context. See
+    /// [`BaseCrypto::search_checked`].
+    fn search_in(
+        &mut self,
+        message: &T,
+        storage: &dyn Storage,
+        name: &str,
+    ) -> Result<SearchOutcome<T>> {
+        let query = self.query_tokens(message)?;
+        let results = storage
+            .lookup(&query.tokens, name)?
+            .iter()
+            .map(|ciphertext| {
+                self.decrypt_checked(ciphertext)
+                    .map(|message| T::from_bytes(&message))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(SearchOutcome {
+            results,
+            recall: query.recall(),
+        })
+    }
+}
+
+impl<T, C> StorageSearch<T> for C
+where
+    T: AsBytes + FromBytes + Debug,
+    C: BaseCrypto<T> + ?Sized,
+{
+}
@@ -0,0 +1,271 @@
+//! This module defines the test vectors of the encodings of IHBE, BHE and PFSE, so that other implementations (e.g., a
+//! Python reference) can check that they agree with this crate. The vectors are published in
+//! `testvectors/vectors.json` and regenerated by `cargo run --bin testvectors -- testvectors/vectors.json`.
+//!
+//! Each vector fixes the inputs of a scheme (a histogram and the parameters) and records the deterministic state it
+//! leads to together with sample encodings. The vectors hold encodings rather than ciphertexts: the encodings are what
+//! a scheme defines, while the ciphertexts only add the cipher on top and depend on the key. The histograms have
+//! distinct counts, as the order of messages with equal counts is not specified.
+//!
+//! All encodings are hex strings. The numbers in an encoding are 8 little-endian bytes each:
+//!
+//! - IHBE and BHE encode a message `m` with the homophone `t` as `m || "|" || t`.
+//! - PFSE encodes the `j`-th copy of `m` in the partition `i` (counted from 0) as `m || "|" || i || "|" || j`.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fse::{exponential, BaseCrypto, PartitionFrequencySmoothing, ValueType},
+    lpfse::{encode_homophone, EncoderBHE, EncoderIHBE, HomophoneEncoder},
+    params::PfseParams,
+    pfse::{encode_copy, ContextPFSE},
+    util::to_hex,
+    Result,
+};
+
+/// The version of the vectors. It changes whenever an encoding changes.
+pub const VECTORS_VERSION: u64 = 1;
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TestVectors {
+    pub version: u64,
+    pub ihbe: Vec<IhbeVector>,
+    pub bhe: Vec<BheVector>,
+    pub pfse: Vec<PfseVector>,
+}
+
+/// The homophones of each message under IHBE.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct IhbeVector {
+    pub advantage: f64,
+    pub histogram: BTreeMap<String, usize>,
+    /// Message -> `[start, end)` of its homophones.
+    pub intervals: BTreeMap<String, (u64, u64)>,
+    /// Message -> the encoding with the homophone `start`.
+    pub encodings: BTreeMap<String, String>,
+}
+
+/// The bands of each message under BHE.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct BheVector {
+    pub advantage: f64,
+    pub histogram: BTreeMap<String, usize>,
+    pub length: usize,
+    pub width: f64,
+    /// Message -> the number of its homophones, i.e., its homophones are `0..band`.
+    pub bands: BTreeMap<String, u64>,
+    /// Message -> the encoding with the last homophone `band - 1`.
+    pub encodings: BTreeMap<String, String>,
+}
+
+/// The partitions of PFSE under the [`exponential`] partition function.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct PfseVector {
+    pub params: PfseParams,
+    pub histogram: BTreeMap<String, usize>,
+    /// The messages of each partition and their counts in order, before the dummies are added.
+    pub partitions: Vec<Vec<(String, usize)>>,
+    /// Message -> `(partition, number of copies, repetitions of each copy)`.
+    pub local_table: BTreeMap<String, Vec<ValueType>>,
+    /// The number of dummies of each partition.
+    pub dummies: Vec<usize>,
+    /// Message -> the encoding of its first copy in its first partition.
+    pub encodings: BTreeMap<String, String>,
+}
+
+impl TestVectors {
+    /// Compute the vectors of the fixed inputs.
+    pub fn generate() -> Result<Self> {
+        let small = histogram(&[("a", 50), ("b", 30), ("c", 15), ("d", 5)]);
+        let zipf = histogram(&[
+            ("m0", 100),
+            ("m1", 60),
+            ("m2", 40),
+            ("m3", 25),
+            ("m4", 18),
+            ("m5", 12),
+            ("m6", 8),
+            ("m7", 5),
+            ("m8", 3),
+            ("m9", 1),
+        ]);
+
+        Ok(Self {
+            version: VECTORS_VERSION,
+            ihbe: vec![
+                IhbeVector::generate(&small, 0.1)?,
+                IhbeVector::generate(&zipf, 0.01)?,
+            ],
+            bhe: vec![
+                BheVector::generate(&small, 0.05)?,
+                BheVector::generate(&zipf, 0.01)?,
+            ],
+            pfse: vec![
+                PfseVector::generate(&small, PfseParams::new(1.0, 1.0, 0.5))?,
+                PfseVector::generate(&zipf, PfseParams::new(0.25, 1.0, 0.1))?,
+            ],
+        })
+    }
+
+    /// Recompute every vector from its inputs and check that this crate reproduces it.
+    pub fn validate(&self) -> Result<()> {
+        if self.version != VECTORS_VERSION {
+            return Err(format!(
+                "The vectors are of version {}, but the crate implements version {}.",
+                self.version, VECTORS_VERSION
+            )
+            .into());
+        }
+
+        for (i, vector) in self.ihbe.iter().enumerate() {
+            if IhbeVector::generate(&vector.histogram, vector.advantage)?
+                != *vector
+            {
+                return Err(
+                    format!("IHBE vector #{} does not match.", i).into()
+                );
+            }
+        }
+        for (i, vector) in self.bhe.iter().enumerate() {
+            if BheVector::generate(&vector.histogram, vector.advantage)?
+                != *vector
+            {
+                return Err(format!("BHE vector #{} does not match.", i).into());
+            }
+        }
+        for (i, vector) in self.pfse.iter().enumerate() {
+            if PfseVector::generate(&vector.histogram, vector.params)?
+                != *vector
+            {
+                return Err(
+                    format!("PFSE vector #{} does not match.", i).into()
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl IhbeVector {
+    fn generate(
+        histogram: &BTreeMap<String, usize>,
+        advantage: f64,
+    ) -> Result<Self> {
+        let mut encoder = EncoderIHBE::new();
+        encoder.initialize_histogram(&to_hash_map(histogram), advantage)?;
+
+        let mut intervals = BTreeMap::new();
+        let mut encodings = BTreeMap::new();
+        for message in histogram.keys() {
+            if let Some(range) = encoder.get_interval(message) {
+                intervals.insert(message.clone(), (range.start, range.end));
+                encodings.insert(
+                    message.clone(),
+                    to_hex(&encode_homophone(message, range.start)),
+                );
+            }
+        }
+
+        Ok(Self {
+            advantage,
+            histogram: histogram.clone(),
+            intervals,
+            encodings,
+        })
+    }
+}
+
+impl BheVector {
+    fn generate(
+        histogram: &BTreeMap<String, usize>,
+        advantage: f64,
+    ) -> Result<Self> {
+        let mut encoder = EncoderBHE::new();
+        encoder.count(&expand(histogram))?;
+        encoder.finish_counting(advantage)?;
+
+        let mut bands = BTreeMap::new();
+        let mut encodings = BTreeMap::new();
+        for message in histogram.keys() {
+            if let Some(band) = encoder.get_band(message) {
+                bands.insert(message.clone(), band);
+                encodings.insert(
+                    message.clone(),
+                    to_hex(&encode_homophone(message, band - 1)),
+                );
+            }
+        }
+
+        Ok(Self {
+            advantage,
+            histogram: histogram.clone(),
+            length: encoder.get_length(),
+            width: encoder.get_width(),
+            bands,
+            encodings,
+        })
+    }
+}
+
+impl PfseVector {
+    fn generate(
+        histogram: &BTreeMap<String, usize>,
+        params: PfseParams,
+    ) -> Result<Self> {
+        let mut ctx = ContextPFSE::default();
+        // The encodings do not depend on the key; the context merely requires one.
+        ctx.set_key(&[0u8; 32]);
+        ctx.set_params(&params)?;
+        ctx.partition_histogram(&to_hash_map(histogram), exponential)?;
+        let partitions = ctx
+            .get_partitions()
+            .iter()
+            .map(|partition| partition.inner.clone())
+            .collect();
+        let stats = ctx.transform();
+
+        let local_table = ctx
+            .get_local_table()
+            .iter()
+            .map(|(message, values)| (message.clone(), values.clone()))
+            .collect::<BTreeMap<_, _>>();
+        let encodings = local_table
+            .iter()
+            .filter_map(|(message, values)| {
+                let &(index, _, _) = values.first()?;
+                Some((message.clone(), to_hex(&encode_copy(message, index, 0))))
+            })
+            .collect();
+
+        Ok(Self {
+            params,
+            histogram: histogram.clone(),
+            partitions,
+            local_table,
+            dummies: stats.partitions.iter().map(|e| e.dummies).collect(),
+            encodings,
+        })
+    }
+}
+
+fn histogram(counts: &[(&str, usize)]) -> BTreeMap<String, usize> {
+    counts
+        .iter()
+        .map(|&(message, count)| (message.to_string(), count))
+        .collect()
+}
+
+fn to_hash_map(histogram: &BTreeMap<String, usize>) -> HashMap<String, usize> {
+    histogram.iter().map(|(k, v)| (k.clone(), *v)).collect()
+}
+
+/// The dataset with each message repeated by its count.
+fn expand(histogram: &BTreeMap<String, usize>) -> Vec<String> {
+    histogram
+        .iter()
+        .flat_map(|(message, count)| vec![message.clone(); *count])
+        .collect()
+}
@@ -0,0 +1,374 @@
+//! This module defines [`TokenSet`], the set of byte strings a message may be encoded or encrypted into. It is what
+//! [`crate::lpfse::HomophoneEncoder::encode_all`] and [`crate::fse::BaseCrypto::search_tokens`] return, and what the
+//! searches send to the server.
+//!
+//! Large token sets, e.g., the homophones of a frequent message under IHBE, are built into a [`TokenArena`] instead,
+//! which writes the tokens back to back into one reusable buffer and interns the prefixes they share. A frozen arena
+//! is a [`Tokens`], whose [`Token`]s are cheap to clone as they share its buffer.
+
+use std::{
+    cmp::Ordering,
+    collections::{btree_set, BTreeSet, HashMap},
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    ops::{Deref, Range},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+
+use crate::util::SizeAllocated;
+
+/// A set of tokens, i.e., homophones or ciphertexts. The tokens are kept sorted, so two sets with the same tokens
+/// are equal and iterate in the same order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TokenSet {
+    tokens: BTreeSet<ByteBuf>,
+}
+
+impl TokenSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a token. Returns whether it was not present yet.
+    pub fn insert(&mut self, token: Vec<u8>) -> bool {
+        self.tokens.insert(ByteBuf::from(token))
+    }
+
+    pub fn contains(&self, token: &[u8]) -> bool {
+        self.tokens.contains(serde_bytes::Bytes::new(token))
+    }
+
+    /// The number of tokens.
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// The total length of the tokens in bytes, i.e., roughly what a search sends to the server.
+    pub fn byte_len(&self) -> usize {
+        self.tokens.iter().map(|token| token.len()).sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.tokens.iter().map(|token| token.as_slice())
+    }
+
+    /// Iterate over the tokens in chunks of at most `size` tokens, e.g., to bound the size of a single query.
+    pub fn chunks(&self, size: usize) -> impl Iterator<Item = Vec<&[u8]>> + '_ {
+        let mut tokens = self.iter().peekable();
+        std::iter::from_fn(move || {
+            tokens.peek()?;
+            Some(tokens.by_ref().take(size.max(1)).collect())
+        })
+    }
+
+    /// The tokens that are in `self` or in `other`.
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            tokens: self.tokens.union(&other.tokens).cloned().collect(),
+        }
+    }
+
+    /// The tokens that are in both `self` and `other`.
+    pub fn intersection(&self, other: &Self) -> Self {
+        Self {
+            tokens: self.tokens.intersection(&other.tokens).cloned().collect(),
+        }
+    }
+
+    /// The tokens that are in `self` but not in `other`.
+    pub fn difference(&self, other: &Self) -> Self {
+        Self {
+            tokens: self.tokens.difference(&other.tokens).cloned().collect(),
+        }
+    }
+}
+
+impl FromIterator<Vec<u8>> for TokenSet {
+    fn from_iter<I: IntoIterator<Item = Vec<u8>>>(iter: I) -> Self {
+        Self {
+            tokens: iter.into_iter().map(ByteBuf::from).collect(),
+        }
+    }
+}
+
+impl From<Vec<Vec<u8>>> for TokenSet {
+    fn from(tokens: Vec<Vec<u8>>) -> Self {
+        tokens.into_iter().collect()
+    }
+}
+
+impl Extend<Vec<u8>> for TokenSet {
+    fn extend<I: IntoIterator<Item = Vec<u8>>>(&mut self, iter: I) {
+        self.tokens.extend(iter.into_iter().map(ByteBuf::from));
+    }
+}
+
+impl IntoIterator for TokenSet {
+    type Item = Vec<u8>;
+    type IntoIter =
+        std::iter::Map<btree_set::IntoIter<ByteBuf>, fn(ByteBuf) -> Vec<u8>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.tokens.into_iter().map(ByteBuf::into_vec)
+    }
+}
+
+impl SizeAllocated for TokenSet {
+    fn size_allocated(&self) -> usize {
+        self.byte_len()
+    }
+}
+
+/// An interned prefix of a [`TokenArena`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prefix {
+    start: usize,
+    end: usize,
+}
+
+/// A reusable buffer that tokens are written into back to back, so that building a token set costs a few growths of
+/// one buffer rather than an allocation per token. A prefix shared by many tokens, e.g., the encoding of a message
+/// that all its homophones start with, is interned once and copied from the arena.
+///
+/// [`TokenArena::clear`] keeps the capacity and the interned prefixes, so an arena kept across searches stops
+/// allocating once it has grown to the largest set.
+#[derive(Debug, Clone, Default)]
+pub struct TokenArena {
+    bytes: Vec<u8>,
+    /// The end of each token in `bytes`.
+    ends: Vec<usize>,
+    /// The interned prefixes stored back to back.
+    prefixes: Vec<u8>,
+    interned: HashMap<Vec<u8>, Prefix>,
+}
+
+impl TokenArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Intern `prefix`, or return it if it is already interned.
+    pub fn intern(&mut self, prefix: &[u8]) -> Prefix {
+        if let Some(interned) = self.interned.get(prefix) {
+            return *interned;
+        }
+
+        let interned = Prefix {
+            start: self.prefixes.len(),
+            end: self.prefixes.len() + prefix.len(),
+        };
+        self.prefixes.extend_from_slice(prefix);
+        self.interned.insert(prefix.to_vec(), interned);
+        interned
+    }
+
+    pub fn push(&mut self, token: &[u8]) {
+        self.bytes.extend_from_slice(token);
+        self.ends.push(self.bytes.len());
+    }
+
+    /// Push the token `prefix || suffix`, where `prefix` was interned by this arena.
+    pub fn push_prefixed(&mut self, prefix: Prefix, suffix: &[u8]) {
+        self.bytes
+            .extend_from_slice(&self.prefixes[prefix.start..prefix.end]);
+        self.bytes.extend_from_slice(suffix);
+        self.ends.push(self.bytes.len());
+    }
+
+    /// The number of tokens, duplicates included.
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    /// The total length of the tokens in bytes.
+    pub fn byte_len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        self.range(index).map(|range| &self.bytes[range])
+    }
+
+    /// Iterate over the tokens in the order they were pushed.
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        (0..self.len()).map(|i| &self.bytes[self.range(i).unwrap()])
+    }
+
+    /// Remove the tokens, keeping the capacity and the interned prefixes.
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.ends.clear();
+    }
+
+    /// Remove the tokens and the interned prefixes.
+    pub fn reset(&mut self) {
+        self.clear();
+        self.prefixes.clear();
+        self.interned.clear();
+    }
+
+    /// Copy the tokens into an immutable [`Tokens`], sorted and without duplicates like a [`TokenSet`]. The arena can
+    /// be reused afterwards.
+    pub fn freeze(&self) -> Tokens {
+        let mut ranges = (0..self.len())
+            .map(|i| self.range(i).unwrap())
+            .collect::<Vec<_>>();
+        ranges.sort_by(|lhs, rhs| {
+            self.bytes[lhs.clone()].cmp(&self.bytes[rhs.clone()])
+        });
+        ranges.dedup_by(|lhs, rhs| {
+            self.bytes[lhs.clone()] == self.bytes[rhs.clone()]
+        });
+
+        Tokens {
+            bytes: Arc::from(self.bytes.as_slice()),
+            ranges: Arc::from(ranges),
+        }
+    }
+
+    fn range(&self, index: usize) -> Option<Range<usize>> {
+        let end = *self.ends.get(index)?;
+        let start = match index {
+            0 => 0,
+            _ => self.ends[index - 1],
+        };
+        Some(start..end)
+    }
+}
+
+impl SizeAllocated for TokenArena {
+    fn size_allocated(&self) -> usize {
+        self.bytes.capacity()
+            + self.ends.capacity() * std::mem::size_of::<usize>()
+            + self.prefixes.capacity()
+            + self.interned.keys().map(Vec::len).sum::<usize>()
+    }
+}
+
+/// An immutable set of tokens that share one buffer, kept sorted and without duplicates. Cloning it, or a [`Token`]
+/// taken from it, only bumps a reference count.
+#[derive(Clone, Default)]
+pub struct Tokens {
+    bytes: Arc<[u8]>,
+    /// The range of each token in `bytes`, in the order of the tokens.
+    ranges: Arc<[Range<usize>]>,
+}
+
+impl Tokens {
+    /// The number of tokens.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The total length of the tokens in bytes.
+    pub fn byte_len(&self) -> usize {
+        self.ranges.iter().map(|range| range.len()).sum()
+    }
+
+    pub fn get(&self, index: usize) -> Option<Token> {
+        self.ranges.get(index).map(|range| Token {
+            bytes: self.bytes.clone(),
+            range: range.clone(),
+        })
+    }
+
+    /// Compare `token` against the tokens in place, without copying either.
+    pub fn contains(&self, token: &[u8]) -> bool {
+        self.ranges
+            .binary_search_by(|range| self.bytes[range.clone()].cmp(token))
+            .is_ok()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.ranges.iter().map(|range| &self.bytes[range.clone()])
+    }
+}
+
+impl PartialEq for Tokens {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for Tokens {}
+
+impl Debug for Tokens {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl From<&Tokens> for TokenSet {
+    fn from(tokens: &Tokens) -> Self {
+        tokens.iter().map(<[u8]>::to_vec).collect()
+    }
+}
+
+/// A token that shares the buffer of the [`Tokens`] it was taken from, like `bytes::Bytes`.
+#[derive(Clone)]
+pub struct Token {
+    bytes: Arc<[u8]>,
+    range: Range<usize>,
+}
+
+impl Deref for Token {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes[self.range.clone()]
+    }
+}
+
+impl AsRef<[u8]> for Token {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for Token {}
+
+impl PartialOrd for Token {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Token {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl Hash for Token {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl Debug for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
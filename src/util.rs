@@ -1,29 +1,112 @@
 //! Utility module that mainly implements the filesystem, networking, and some other intefaces.
 
 use std::{
-    collections::HashMap,
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
     fmt::Debug,
     fs::File,
     hash::Hash,
-    io::{BufRead, BufReader, Write},
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::PathBuf,
 };
 
 use array_tool::vec::Intersect;
-use csv::{Reader, ReaderBuilder};
+use csv::{ByteRecord, Reader, ReaderBuilder};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
 use log::error;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
 use rand_core::OsRng;
-use rand_distr::{Distribution, Normal, Zipf};
+use rand_distr::{uniform::SampleUniform, Distribution, Normal, Uniform, Zipf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use crate::{
-    fse::{HistType, Random, ValueType, DEFAULT_RANDOM_LEN},
+    error::FseError,
+    fse::{AsBytes, HistType, Random, ValueType, DEFAULT_RANDOM_LEN},
     Result,
 };
 
+/// The tolerance used when comparing frequencies, which are sums of many small floats.
+pub const EPSILON: f64 = 1e-9;
+
 /// A helper trait that defines an interface used to calculate the allocated size of an object.
 pub trait SizeAllocated {
     fn size_allocated(&self) -> usize;
 }
 
+/// A helper that writes the compact binary encoding of client-side states. All integers are stored in little endian.
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_f64(&mut self, value: f64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Write a length-prefixed byte array.
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.put_u64(bytes.len() as u64);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// A helper that reads the encoding produced by [`StateWriter`]. Each method returns `None` on truncated input.
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(len)?;
+        let bytes = self.buf.get(self.pos..end)?;
+        self.pos = end;
+        Some(bytes)
+    }
+
+    pub fn get_u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    pub fn get_usize(&mut self) -> Option<usize> {
+        self.get_u64()?.try_into().ok()
+    }
+
+    pub fn get_f64(&mut self) -> Option<f64> {
+        Some(f64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    pub fn get_bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.get_usize()?;
+        self.take(len)
+    }
+
+    /// Are all the bytes consumed?
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+}
+
 pub fn read_file(path: &str) -> Result<Vec<String>> {
     let mut strings = Vec::new();
     let file = File::open(path)?;
@@ -36,41 +119,197 @@ pub fn read_file(path: &str) -> Result<Vec<String>> {
     Ok(strings)
 }
 
-/// Read a whole csv file.
-fn read_csv(path: &str) -> Result<Reader<File>> {
-    Ok(ReaderBuilder::new().has_headers(true).from_path(path)?)
+/// The text encoding of a CSV file.
+#[derive(
+    Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvEncoding {
+    /// A field that is not valid UTF-8 makes its record malformed.
+    #[default]
+    Utf8,
+    /// Invalid UTF-8 sequences are replaced by U+FFFD instead.
+    Utf8Lossy,
+    /// ISO 8859-1, where every byte is a character.
+    Latin1,
 }
-/// Read the corresponding column.
-fn read_column(
+
+impl CsvEncoding {
+    fn decode(&self, field: &[u8]) -> Option<String> {
+        match self {
+            Self::Utf8 => std::str::from_utf8(field).ok().map(String::from),
+            Self::Utf8Lossy => {
+                Some(String::from_utf8_lossy(field).into_owned())
+            }
+            Self::Latin1 => Some(field.iter().map(|&b| b as char).collect()),
+        }
+    }
+}
+
+/// What happens to a record that cannot be parsed or decoded, e.g., one with a different number of fields.
+#[derive(
+    Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum BadLinePolicy {
+    /// Fail with [`FseError::MalformedRecord`].
+    #[default]
+    Error,
+    /// Leave the record out and count it in [`CsvStats::malformed`].
+    Skip,
+}
+
+/// How a CSV file is parsed. The default is a comma-delimited UTF-8 file with a header, where a malformed record is
+/// an error.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(default, deny_unknown_fields)]
+pub struct CsvOptions {
+    /// An ASCII character, e.g., `;` or `\t`.
+    pub delimiter: char,
+    /// An ASCII character.
+    pub quote: char,
+    /// Without a header, the columns are named by their indices, i.e., "0", "1", and so on.
+    pub has_headers: bool,
+    pub encoding: CsvEncoding,
+    pub on_bad_line: BadLinePolicy,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            quote: '"',
+            has_headers: true,
+            encoding: CsvEncoding::default(),
+            on_bad_line: BadLinePolicy::default(),
+        }
+    }
+}
+
+impl CsvOptions {
+    pub fn validate(&self) -> Result<()> {
+        for (name, c) in [("delimiter", self.delimiter), ("quote", self.quote)]
+        {
+            if !c.is_ascii() {
+                return Err(FseError::InvalidParams(format!(
+                    "The CSV {} must be an ASCII character, got {:?}",
+                    name, c
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The records of a CSV file that were read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CsvStats {
+    /// The number of records read.
+    pub rows: usize,
+    /// The number of malformed records left out under [`BadLinePolicy::Skip`].
+    pub malformed: usize,
+}
+
+/// Open a csv file.
+fn read_csv(path: &str, options: &CsvOptions) -> Result<Reader<File>> {
+    options.validate()?;
+    Ok(ReaderBuilder::new()
+        .delimiter(options.delimiter as u8)
+        .quote(options.quote as u8)
+        .has_headers(options.has_headers)
+        .from_path(path)?)
+}
+
+/// The column names, which are the indices of the columns if the file has no header.
+fn headers(
     reader: &mut Reader<File>,
-    column_name: &str,
+    options: &CsvOptions,
 ) -> Result<Vec<String>> {
-    // Locate the target column.
-    let index = match reader
-        .headers()
-        .unwrap()
+    let headers = reader.byte_headers()?.clone();
+    if !options.has_headers {
+        return Ok((0..headers.len()).map(|i| i.to_string()).collect());
+    }
+
+    headers
         .iter()
-        .enumerate()
-        .find(|&(_, str)| str == column_name)
-    {
-        Some(index) => index.0,
-        None => return Err("Not found.".into()),
-    };
+        .map(|field| {
+            options.encoding.decode(field).ok_or_else(|| {
+                FseError::MalformedRecord {
+                    line: 1,
+                    reason: "the header is not valid UTF-8".into(),
+                }
+                .into()
+            })
+        })
+        .collect()
+}
+
+/// Decode up to `limit` records and hand each to `f`. A malformed record, including one that `f` rejects with a
+/// reason, fails or is skipped according to the options, while an I/O error always fails.
+fn read_records<F>(
+    reader: &mut Reader<File>,
+    options: &CsvOptions,
+    limit: Option<usize>,
+    mut f: F,
+) -> Result<CsvStats>
+where
+    F: FnMut(Vec<String>) -> std::result::Result<(), String>,
+{
+    let mut stats = CsvStats::default();
+    let mut record = ByteRecord::new();
+    while stats.rows < limit.unwrap_or(usize::MAX) {
+        let line = reader.position().line();
+        let decoded = match reader.read_byte_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => record
+                .iter()
+                .map(|field| options.encoding.decode(field))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| format!("not valid {:?}", options.encoding)),
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        match (decoded.and_then(&mut f), options.on_bad_line) {
+            (Ok(()), _) => stats.rows += 1,
+            (Err(_), BadLinePolicy::Skip) => stats.malformed += 1,
+            (Err(reason), BadLinePolicy::Error) => {
+                return Err(FseError::MalformedRecord { line, reason }.into())
+            }
+        }
+    }
+
+    Ok(stats)
+}
 
-    let strings = reader
-        .records()
-        .map(|elem| {
-            elem.unwrap()
+/// Locate the columns among the headers.
+fn column_indices(
+    headers: &[String],
+    column_names: &[String],
+) -> Result<Vec<usize>> {
+    Ok(column_names
+        .iter()
+        .map(|column_name| {
+            headers
                 .iter()
-                .enumerate()
-                .find(|&(i, _)| i == index)
-                .unwrap()
-                .1
-                .to_string()
+                .position(|str| str == column_name)
+                .ok_or_else(|| format!("Column {} not found.", column_name))
         })
-        .collect();
+        .collect::<std::result::Result<Vec<_>, _>>()?)
+}
 
-    Ok(strings)
+/// Read the column names of a CSV file without reading its records.
+pub fn read_csv_headers(path: &str) -> Result<Vec<String>> {
+    read_csv_headers_with(path, &CsvOptions::default())
+}
+
+/// [`read_csv_headers`] under the given options.
+pub fn read_csv_headers_with(
+    path: &str,
+    options: &CsvOptions,
+) -> Result<Vec<String>> {
+    headers(&mut read_csv(path, options)?, options)
 }
 
 /// Parse a CSV file and read multiple columns.
@@ -78,21 +317,283 @@ pub fn read_csv_multiple(
     path: &str,
     column_names: &[String],
 ) -> Result<Vec<Vec<String>>> {
-    let mut reader = read_csv(path)?;
+    read_csv_multiple_with(path, column_names, &CsvOptions::default())
+        .map(|(strings, _)| strings)
+}
 
-    let mut strings = Vec::new();
-    for column_name in column_names.iter() {
-        strings.push(read_column(&mut reader, column_name)?);
-    }
+/// [`read_csv_multiple`] under the given options. Also returns how many records were read and skipped.
+pub fn read_csv_multiple_with(
+    path: &str,
+    column_names: &[String],
+    options: &CsvOptions,
+) -> Result<(Vec<Vec<String>>, CsvStats)> {
+    let mut reader = read_csv(path, options)?;
 
-    Ok(strings)
+    // Locate all the target columns first, as the records can only be iterated once.
+    let indices =
+        column_indices(&headers(&mut reader, options)?, column_names)?;
+
+    let mut strings = vec![Vec::new(); indices.len()];
+    let stats = read_records(&mut reader, options, None, |mut record| {
+        for (column, &index) in strings.iter_mut().zip(indices.iter()) {
+            column.push(match index < record.len() {
+                true => std::mem::take(&mut record[index]),
+                false => String::new(),
+            });
+        }
+        Ok(())
+    })?;
+
+    Ok((strings, stats))
 }
 
 /// Parse a CSV file and read the corresponding column.
 pub fn read_csv_exact(path: &str, column_name: &str) -> Result<Vec<String>> {
-    let mut reader = read_csv(path)?;
+    read_csv_exact_with(path, column_name, &CsvOptions::default())
+        .map(|(strings, _)| strings)
+}
+
+/// [`read_csv_exact`] under the given options. Also returns how many records were read and skipped.
+pub fn read_csv_exact_with(
+    path: &str,
+    column_name: &str,
+    options: &CsvOptions,
+) -> Result<(Vec<String>, CsvStats)> {
+    let (mut strings, stats) =
+        read_csv_multiple_with(path, &[column_name.to_string()], options)?;
+    Ok((strings.remove(0), stats))
+}
+
+/// Parse a CSV file of aggregated rows into the histogram of `value_column`, where each row stands for as many
+/// messages as its `count_column` says. The histogram can be given to the contexts directly, e.g., by
+/// [`crate::fse::PartitionFrequencySmoothing::partition_histogram`], instead of expanding the rows first.
+pub fn read_csv_weighted(
+    path: &str,
+    value_column: &str,
+    count_column: &str,
+) -> Result<HashMap<String, usize>> {
+    read_csv_weighted_with(
+        path,
+        value_column,
+        count_column,
+        &CsvOptions::default(),
+    )
+    .map(|(histogram, _)| histogram)
+}
+
+/// [`read_csv_weighted`] under the given options. A count that is not a non-negative integer makes its record
+/// malformed. Also returns how many records were read and skipped.
+pub fn read_csv_weighted_with(
+    path: &str,
+    value_column: &str,
+    count_column: &str,
+    options: &CsvOptions,
+) -> Result<(HashMap<String, usize>, CsvStats)> {
+    let mut reader = read_csv(path, options)?;
+    let indices = column_indices(
+        &headers(&mut reader, options)?,
+        &[value_column.to_string(), count_column.to_string()],
+    )?;
+
+    let mut histogram = HashMap::new();
+    let stats = read_records(&mut reader, options, None, |mut record| {
+        let field = |record: &mut Vec<String>, index: usize| {
+            record
+                .get_mut(index)
+                .map(std::mem::take)
+                .unwrap_or_default()
+        };
+        let count = field(&mut record, indices[1]);
+        let count = count.trim().parse::<usize>().map_err(|_| {
+            format!("the count {:?} is not a non-negative integer", count)
+        })?;
+        // A value that occurs zero times is not part of the dataset.
+        if count != 0 {
+            *histogram.entry(field(&mut record, indices[0])).or_insert(0) +=
+                count;
+        }
+        Ok(())
+    })?;
+
+    Ok((histogram, stats))
+}
+
+/// Is `value` an ISO 8601 date, i.e., `YYYY-MM-DD` optionally followed by a time?
+pub fn is_iso_date(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    bytes.len() >= 10
+        && bytes[4] == b'-'
+        && bytes[7] == b'-'
+        && [0..4, 5..7, 8..10]
+            .into_iter()
+            .all(|range| bytes[range].iter().all(u8::is_ascii_digit))
+}
+
+/// A string column is categorical if at most this fraction of its values are distinct; otherwise it is free text,
+/// e.g., names or identifiers, that smoothing does not apply to.
+pub const CATEGORICAL_RATIO: f64 = 0.5;
+
+/// The type of a CSV column inferred from its values.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    Integer,
+    Float,
+    Date,
+    Categorical,
+    Text,
+}
+
+impl ColumnType {
+    pub const ALL: [Self; 5] = [
+        Self::Integer,
+        Self::Float,
+        Self::Date,
+        Self::Categorical,
+        Self::Text,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Integer => "integer",
+            Self::Float => "float",
+            Self::Date => "date",
+            Self::Categorical => "categorical",
+            Self::Text => "text",
+        }
+    }
+}
+
+/// The inferred schema of a single column.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ColumnSchema {
+    pub name: String,
+    pub column_type: ColumnType,
+    /// The number of distinct non-empty values.
+    pub distinct: usize,
+    /// The number of records read.
+    pub rows: usize,
+    /// The number of empty values, which do not take part in the inference.
+    pub empty: usize,
+}
+
+impl ColumnSchema {
+    fn infer(name: &str, values: &[&str]) -> Self {
+        let values = values
+            .iter()
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .collect::<Vec<_>>();
+        let distinct = values.iter().collect::<HashSet<_>>().len();
+        let all = |f: fn(&str) -> bool| {
+            !values.is_empty() && values.iter().all(|value| f(value))
+        };
+
+        let column_type = if all(|value| value.parse::<i64>().is_ok()) {
+            ColumnType::Integer
+        } else if all(|value| value.parse::<f64>().is_ok()) {
+            ColumnType::Float
+        } else if all(is_iso_date) {
+            ColumnType::Date
+        } else if distinct as f64 <= values.len() as f64 * CATEGORICAL_RATIO {
+            ColumnType::Categorical
+        } else {
+            ColumnType::Text
+        };
+
+        Self {
+            name: name.to_string(),
+            column_type,
+            distinct,
+            rows: 0,
+            empty: 0,
+        }
+    }
+}
+
+/// Infer the type of every column of a CSV file from its first `rows` records, or all of them if `None`.
+pub fn infer_csv_schema(
+    path: &str,
+    rows: Option<usize>,
+) -> Result<Vec<ColumnSchema>> {
+    infer_csv_schema_with(path, rows, &CsvOptions::default())
+}
+
+/// [`infer_csv_schema`] under the given options. Malformed records that are skipped do not count towards `rows`.
+pub fn infer_csv_schema_with(
+    path: &str,
+    rows: Option<usize>,
+    options: &CsvOptions,
+) -> Result<Vec<ColumnSchema>> {
+    let mut reader = read_csv(path, options)?;
+    let headers = headers(&mut reader, options)?;
+
+    let mut records = Vec::new();
+    read_records(&mut reader, options, rows, |record| {
+        records.push(record);
+        Ok(())
+    })?;
+
+    Ok(headers
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let values = records
+                .iter()
+                .map(|record| record.get(index).map_or("", String::as_str))
+                .collect::<Vec<_>>();
+            ColumnSchema {
+                rows: values.len(),
+                empty: values.iter().filter(|e| e.trim().is_empty()).count(),
+                ..ColumnSchema::infer(name, &values)
+            }
+        })
+        .collect())
+}
+
+/// Expand the type selectors among `selectors`, e.g., `*categorical`, into the columns of that type in `schema`.
+/// Other selectors are column names and kept as they are. A selector that matches no column is an error, as the
+/// experiment would silently run on fewer columns than intended.
+pub fn select_columns(
+    schema: &[ColumnSchema],
+    selectors: &[String],
+) -> Result<Vec<String>> {
+    let mut columns = Vec::new();
+    for selector in selectors.iter() {
+        let name = match selector.strip_prefix('*') {
+            Some(name) => name,
+            None => {
+                columns.push(selector.clone());
+                continue;
+            }
+        };
+
+        let column_type = ColumnType::ALL
+            .into_iter()
+            .find(|e| e.name() == name)
+            .ok_or_else(|| {
+                FseError::InvalidParams(format!(
+                    "Unknown column type {}; expected one of {}.",
+                    name,
+                    ColumnType::ALL.map(|e| e.name()).join(", ")
+                ))
+            })?;
+        let matched = schema
+            .iter()
+            .filter(|e| e.column_type == column_type)
+            .map(|e| e.name.clone())
+            .collect::<Vec<_>>();
+        if matched.is_empty() {
+            return Err(FseError::InvalidParams(format!(
+                "No column is inferred as {}.",
+                name
+            ))
+            .into());
+        }
+        columns.extend(matched);
+    }
 
-    read_column(&mut reader, column_name)
+    Ok(columns)
 }
 
 pub fn write_file(path: &str, content: &[u8]) -> std::io::Result<()> {
@@ -100,38 +601,211 @@ pub fn write_file(path: &str, content: &[u8]) -> std::io::Result<()> {
 }
 
 /// Construct an ordered histogram vector from raw histogram
-pub fn build_histogram_vec<T>(histogram: &HashMap<T, usize>) -> Vec<HistType<T>>
+pub fn build_histogram_vec<T, S>(
+    histogram: &HashMap<T, usize, S>,
+) -> Vec<HistType<T>>
 where
     T: Hash + Eq + Clone,
 {
-    // Convert histogram into vector that is ordered by frequency.
+    // Convert histogram into vector that is ordered by frequency. Messages that never occur carry no frequency.
     let mut histogram_vec = Vec::new();
-    histogram.iter().for_each(|(key, &frequency)| {
-        histogram_vec.push((key.clone(), frequency))
-    });
+    histogram
+        .iter()
+        .filter(|(_, &frequency)| frequency != 0)
+        .for_each(|(key, &frequency)| {
+            histogram_vec.push((key.clone(), frequency))
+        });
     // Second, sort the vector in descending order.
     histogram_vec.sort_by(|lhs, rhs| rhs.1.cmp(&lhs.1));
     histogram_vec
 }
 
-/// Construct a raw histogram represented by the `HashMap`.
+/// The same as [`build_histogram_vec`] of [`build_histogram`], but the memory does not grow with the number of
+/// items beyond `threshold`: the items are sorted in runs of `threshold` that are spilled to temporary files, and the
+/// runs are merged while counting equal items (an external sort). Only the distinct items are held at the end.
+pub fn build_histogram_vec_spilled(
+    items: &[Vec<u8>],
+    threshold: usize,
+) -> Result<Vec<HistType<Vec<u8>>>> {
+    let mut runs = Vec::new();
+    for chunk in items.chunks(threshold.max(1)) {
+        let mut run = chunk.iter().collect::<Vec<_>>();
+        run.sort_unstable();
+        runs.push(SpillFile::write(&run)?);
+    }
+
+    let mut readers = runs
+        .iter()
+        .map(SpillFile::reader)
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let mut heap = BinaryHeap::new();
+    for (index, reader) in readers.iter_mut().enumerate() {
+        if let Some(item) = read_record(reader)? {
+            heap.push(Reverse((item, index)));
+        }
+    }
+
+    let mut histogram_vec: Vec<HistType<Vec<u8>>> = Vec::new();
+    while let Some(Reverse((item, index))) = heap.pop() {
+        if let Some(next) = read_record(&mut readers[index])? {
+            heap.push(Reverse((next, index)));
+        }
+        match histogram_vec.last_mut() {
+            Some((last, cnt)) if *last == item => *cnt += 1,
+            _ => histogram_vec.push((item, 1)),
+        }
+    }
+
+    histogram_vec.sort_by_key(|(_, cnt)| Reverse(*cnt));
+    Ok(histogram_vec)
+}
+
+/// A sorted run of [`build_histogram_vec_spilled`]. The file is removed when it is dropped.
+struct SpillFile {
+    path: PathBuf,
+}
+
+impl SpillFile {
+    fn write(items: &[&Vec<u8>]) -> std::io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "fse_spill_{}_{:016x}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        // Construct the guard first so that the file is removed even if writing fails.
+        let file = Self { path };
+        let mut writer = BufWriter::new(File::create(&file.path)?);
+        for item in items.iter() {
+            writer.write_all(&(item.len() as u64).to_le_bytes())?;
+            writer.write_all(item)?;
+        }
+        writer.flush()?;
+        Ok(file)
+    }
+
+    fn reader(&self) -> std::io::Result<BufReader<File>> {
+        Ok(BufReader::new(File::open(&self.path)?))
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Read a length-prefixed record, or `None` at the end of the file.
+fn read_record<R: Read>(reader: &mut R) -> std::io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 8];
+    match reader.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            return Ok(None)
+        }
+        Err(e) => return Err(e),
+    }
+
+    let mut record = vec![0u8; u64::from_le_bytes(len) as usize];
+    reader.read_exact(&mut record)?;
+    Ok(Some(record))
+}
+
+/// The hasher of the histograms counted by [`build_histogram`]: aHash with the `fast-hash` feature, SipHash otherwise.
+/// The datasets are the client's own, so the DoS resistance of SipHash buys nothing here.
+#[cfg(feature = "fast-hash")]
+pub type HistogramState = ahash::RandomState;
+#[cfg(not(feature = "fast-hash"))]
+pub type HistogramState = std::collections::hash_map::RandomState;
+
+/// A histogram under [`HistogramState`].
+pub type Histogram<T> = HashMap<T, usize, HistogramState>;
+
+/// The capacity a histogram is reserved with: small datasets never grow the map, and large ones skip the first rounds
+/// of growth without reserving a slot per item, which would waste memory on the usual low-cardinality columns.
+const HISTOGRAM_RESERVE: usize = 1 << 12;
+
+/// The number of items from which [`build_histogram_par`] counts in parallel.
+#[cfg(feature = "parallel")]
+pub const PARALLEL_THRESHOLD: usize = 1 << 20;
+
+/// Construct a raw histogram represented by the `HashMap`. The items are counted under [`HistogramState`], so only the
+/// distinct items are hashed again by the returned map; see [`build_histogram_fast`] to skip that, too.
 pub fn build_histogram<T>(dataset: &[T]) -> HashMap<T, usize>
 where
     T: Hash + Eq + Clone,
 {
-    let mut histogram = HashMap::<T, usize>::new();
-    // Construct the histogram for `dataset`.
-    for i in dataset.iter() {
-        let entry = histogram.entry(i.clone()).or_insert(0);
-        *entry = match entry.checked_add(1) {
-            Some(val) => val,
-            None => panic!("[-] Overflow detected."),
-        };
-    }
+    let histogram = build_histogram_fast(dataset);
+    let mut converted = HashMap::with_capacity(histogram.len());
+    converted.extend(histogram);
+    converted
+}
 
+/// The same as [`build_histogram`] but returns the histogram under [`HistogramState`], for callers that only read it.
+pub fn build_histogram_fast<T>(dataset: &[T]) -> Histogram<T>
+where
+    T: Hash + Eq + Clone,
+{
+    let mut histogram = Histogram::with_capacity_and_hasher(
+        dataset.len().min(HISTOGRAM_RESERVE),
+        Default::default(),
+    );
+    count_into(&mut histogram, dataset);
     histogram
 }
 
+/// The same as [`build_histogram`], but datasets of at least [`PARALLEL_THRESHOLD`] items are counted in chunks by
+/// the rayon thread pool and the counts of the chunks are merged.
+#[cfg(feature = "parallel")]
+pub fn build_histogram_par<T>(dataset: &[T]) -> HashMap<T, usize>
+where
+    T: Hash + Eq + Clone + Send + Sync,
+{
+    use rayon::prelude::*;
+
+    if dataset.len() < PARALLEL_THRESHOLD {
+        return build_histogram(dataset);
+    }
+
+    let chunk = dataset.len() / rayon::current_num_threads() + 1;
+    let histogram = dataset.par_chunks(chunk).map(build_histogram_fast).reduce(
+        Histogram::default,
+        |mut lhs, mut rhs| {
+            if lhs.len() < rhs.len() {
+                std::mem::swap(&mut lhs, &mut rhs);
+            }
+            for (item, cnt) in rhs {
+                let entry = lhs.entry(item).or_insert(0);
+                *entry =
+                    entry.checked_add(cnt).expect("[-] Overflow detected.");
+            }
+            lhs
+        },
+    );
+    let mut converted = HashMap::with_capacity(histogram.len());
+    converted.extend(histogram);
+    converted
+}
+
+fn count_into<T>(histogram: &mut Histogram<T>, dataset: &[T])
+where
+    T: Hash + Eq + Clone,
+{
+    for i in dataset.iter() {
+        // Looking up before inserting spares the clone of the items that have been counted already.
+        match histogram.get_mut(i) {
+            Some(entry) => {
+                *entry = match entry.checked_add(1) {
+                    Some(val) => val,
+                    None => panic!("[-] Overflow detected."),
+                }
+            }
+            None => {
+                histogram.insert(i.clone(), 1);
+            }
+        }
+    }
+}
+
 /// A helper function that computes the `i`-th value of the CDF, given a histogram and element number.
 pub fn compute_cdf<T>(
     index: usize,
@@ -151,6 +825,130 @@ pub fn compute_cdf<T>(
     sum
 }
 
+/// Encode bytes as a lowercase hex string.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Compute an unkeyed digest of a histogram `T -> count`. The digest does not depend on the iteration order of the map.
+pub fn histogram_digest<T>(histogram: &HashMap<T, usize>) -> Vec<u8>
+where
+    T: AsBytes,
+{
+    let mut entries = histogram
+        .iter()
+        .filter(|(_, &cnt)| cnt != 0)
+        .map(|(message, &cnt)| (message.to_bytes(), cnt))
+        .collect::<Vec<_>>();
+    entries.sort_unstable();
+
+    let mut writer = StateWriter::new();
+    writer.put_u64(entries.len() as u64);
+    for (message, cnt) in entries {
+        writer.put_bytes(&message);
+        writer.put_u64(cnt as u64);
+    }
+    Sha256::digest(writer.finish()).to_vec()
+}
+
+/// Bind a histogram digest to a secret key so that the fingerprint reveals nothing about the dataset to the server.
+pub fn keyed_fingerprint(key: &[u8], digest: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update((key.len() as u64).to_le_bytes());
+    hasher.update(key);
+    hasher.update(digest);
+    to_hex(&hasher.finalize())
+}
+
+/// HMAC-SHA256 of `message` under `key`.
+pub(crate) fn hmac(key: &[u8], message: &[u8]) -> Vec<u8> {
+    // HMAC accepts keys of any length, so this never fails.
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).unwrap();
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// HKDF-SHA256 (RFC 5869) of `len` bytes from the input keying material `ikm`. Returns `None` if `len` exceeds the
+/// 255 blocks HKDF can expand into.
+pub fn hkdf_sha256(
+    salt: &[u8],
+    ikm: &[u8],
+    info: &[u8],
+    len: usize,
+) -> Option<Vec<u8>> {
+    // Checked before the output is allocated, which could overflow otherwise.
+    if len > 255 * Sha256::output_size() {
+        return None;
+    }
+
+    let mut okm = vec![0u8; len];
+    Hkdf::<Sha256>::new(Some(salt), ikm)
+        .expand(info, &mut okm)
+        .ok()?;
+    Some(okm)
+}
+
+/// The keyed hash a hardened local table stores in place of `message`, under a hash key derived from the context key.
+pub(crate) fn keyed_tag(key: &[u8], message: &[u8]) -> Vec<u8> {
+    hmac(&hmac(key, b"fse-table-key"), message)
+}
+
+/// Construct a uniform distribution over `[start, end)`. Returns `None` if the interval is empty, in which case
+/// [`Uniform::new`] would panic.
+pub fn checked_uniform<X>(start: X, end: X) -> Option<Uniform<X>>
+where
+    X: SampleUniform + PartialOrd + Debug,
+{
+    if start < end {
+        Some(Uniform::new(start, end))
+    } else {
+        error!("Empty interval [{:?}, {:?}).", start, end);
+        None
+    }
+}
+
+/// Draw `size` records of `dataset` in a random order determined by `seed`, so that a size sweep does not depend on
+/// the size of the dataset and can be repeated. A dataset of at least `size` records is sampled without replacement,
+/// i.e., shuffled and truncated; a smaller one is sampled with replacement. An empty dataset gives an empty sample.
+pub fn sample_or_cycle<T: Clone>(
+    dataset: &[T],
+    size: usize,
+    seed: u64,
+) -> Vec<T> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    if size <= dataset.len() {
+        let mut sample = dataset.to_vec();
+        return sample.partial_shuffle(&mut rng, size).0.to_vec();
+    }
+    if dataset.is_empty() {
+        return Vec::new();
+    }
+
+    let index = Uniform::new(0, dataset.len());
+    (0..size)
+        .map(|_| dataset[index.sample(&mut rng)].clone())
+        .collect()
+}
+
+/// Round `value` up to the next integer while ignoring floating-point noise, e.g., `3.0000000001` becomes 3 rather
+/// than 4. Non-finite or negative values are mapped to 0.
+pub fn ceil_eps(value: f64) -> usize {
+    if !value.is_finite() || value <= 0.0 {
+        return 0;
+    }
+
+    (value - EPSILON).ceil().max(0.0) as usize
+}
+
+/// Divide `lhs` by `rhs`, or return `None` if the result is not a finite number.
+pub fn checked_div(lhs: f64, rhs: f64) -> Option<f64> {
+    let res = lhs / rhs;
+    match res.is_finite() {
+        true => Some(res),
+        false => None,
+    }
+}
+
 /// Pad the message dataset if the size does not match with the ciphertext dataset.
 #[cfg(feature = "attack")]
 pub fn pad_auxiliary<T>(
@@ -278,3 +1076,212 @@ where
     }
     dataset
 }
+
+/// A mixture of Zipf distributions over the ranks `1..=n` of a histogram. Each component is a pair `(weight, exponent)`.
+///
+/// This model is used to synthesize auxiliary distributions for the attacks that only *approximately* match the
+/// target distribution, because a real attacker never knows the exact histogram of the target dataset.
+#[derive(Debug, Clone)]
+pub struct ZipfMixture {
+    pub components: Vec<(f64, f64)>,
+}
+
+impl ZipfMixture {
+    /// The search range of the exponent of each component.
+    const EXPONENT_RANGE: (f64, f64) = (1e-3, 8.0);
+
+    /// Fit a mixture of `k` Zipf distributions to a histogram ordered by frequency (see [`build_histogram_vec`]) by
+    /// the EM algorithm. The exponent of each component is updated by a golden-section search on its likelihood.
+    pub fn fit<T>(
+        histogram: &[HistType<T>],
+        k: usize,
+        iterations: usize,
+    ) -> Self {
+        let n = histogram.len();
+        let k = k.max(1);
+        // Spread the initial exponents so that components do not collapse.
+        let mut components = (0..k)
+            .map(|j| (1.0 / k as f64, 0.5 + j as f64))
+            .collect::<Vec<_>>();
+
+        if n <= 1 {
+            return Self { components };
+        }
+
+        for _ in 0..iterations {
+            // E-step: compute the responsibilities of each component for each rank.
+            let pmfs = components
+                .iter()
+                .map(|&(_, s)| zipf_pmf(n, s))
+                .collect::<Vec<_>>();
+            let mut responsibilities = vec![vec![0f64; n]; k];
+            for i in 0..n {
+                let total =
+                    (0..k).map(|j| components[j].0 * pmfs[j][i]).sum::<f64>();
+                for j in 0..k {
+                    responsibilities[j][i] = match total > 0.0 {
+                        true => components[j].0 * pmfs[j][i] / total,
+                        false => 1.0 / k as f64,
+                    };
+                }
+            }
+
+            // M-step: update the weights and the exponents.
+            let count = histogram.iter().map(|e| e.1 as f64).sum::<f64>();
+            for j in 0..k {
+                let weights = histogram
+                    .iter()
+                    .zip(responsibilities[j].iter())
+                    .map(|(e, r)| e.1 as f64 * r)
+                    .collect::<Vec<_>>();
+                components[j].0 = weights.iter().sum::<f64>() / count;
+                components[j].1 = golden_section_max(
+                    |s| {
+                        let pmf = zipf_pmf(n, s);
+                        weights
+                            .iter()
+                            .zip(pmf.iter())
+                            .map(|(w, p)| w * p.max(f64::MIN_POSITIVE).ln())
+                            .sum::<f64>()
+                    },
+                    Self::EXPONENT_RANGE,
+                );
+            }
+        }
+
+        Self { components }
+    }
+
+    /// The probability mass of the mixture over ranks `1..=n`.
+    pub fn pmf(&self, n: usize) -> Vec<f64> {
+        let mut pmf = vec![0f64; n];
+        for &(weight, s) in self.components.iter() {
+            for (lhs, rhs) in pmf.iter_mut().zip(zipf_pmf(n, s)) {
+                *lhs += weight * rhs;
+            }
+        }
+        pmf
+    }
+
+    /// Sample an auxiliary histogram whose total-variation distance to `histogram` is (approximately) `tv`.
+    ///
+    /// The auxiliary is the mixture `(1 - a) * P + a * Z`, where `P` is the target distribution and `Z` assigns the
+    /// fitted Zipf-mixture frequencies to the messages in a random order. Since `TV(P, (1 - a) * P + a * Z) = a * TV(P, Z)`,
+    /// we pick `a = tv / TV(P, Z)` (capped at 1). The random order is determined by `seed`.
+    ///
+    /// The counts are rounded by the largest remainder, so the output keeps the total count of `histogram`. Messages
+    /// whose count rounds to zero are left out.
+    pub fn sample_auxiliary<T>(
+        &self,
+        histogram: &[HistType<T>],
+        tv: f64,
+        seed: u64,
+    ) -> Vec<HistType<T>>
+    where
+        T: Clone,
+    {
+        let n = histogram.len();
+        let total = histogram.iter().map(|e| e.1).sum::<usize>();
+        if n == 0 || total == 0 {
+            return histogram.to_vec();
+        }
+
+        let target = histogram
+            .iter()
+            .map(|e| e.1 as f64 / total as f64)
+            .collect::<Vec<_>>();
+        let mut model = self.pmf(n);
+        model.shuffle(&mut StdRng::seed_from_u64(seed));
+
+        let distance = 0.5
+            * target
+                .iter()
+                .zip(model.iter())
+                .map(|(p, z)| (p - z).abs())
+                .sum::<f64>();
+        let a = match distance > 0.0 {
+            true => (tv.max(0.0) / distance).min(1.0),
+            false => 0.0,
+        };
+
+        let shares = target
+            .iter()
+            .zip(model.iter())
+            .map(|(p, z)| ((1.0 - a) * p + a * z) * total as f64)
+            .collect::<Vec<_>>();
+        let mut counts = shares
+            .iter()
+            .map(|e| e.floor() as usize)
+            .collect::<Vec<_>>();
+        // The messages with the largest remainders take the occurrences lost by rounding down.
+        let lost = total.saturating_sub(counts.iter().sum());
+        let mut order = (0..n).collect::<Vec<_>>();
+        order.sort_by(|&lhs, &rhs| {
+            (shares[rhs] - shares[rhs].floor())
+                .total_cmp(&(shares[lhs] - shares[lhs].floor()))
+        });
+        for &index in order.iter().take(lost) {
+            counts[index] += 1;
+        }
+
+        let mut auxiliary = histogram
+            .iter()
+            .zip(counts)
+            .filter(|e| e.1 != 0)
+            .map(|(e, count)| (e.0.clone(), count))
+            .collect::<Vec<_>>();
+        auxiliary.sort_by_key(|e| std::cmp::Reverse(e.1));
+        auxiliary
+    }
+}
+
+/// Compute the total-variation distance between two histograms.
+pub fn total_variation<T>(lhs: &[HistType<T>], rhs: &[HistType<T>]) -> f64
+where
+    T: Hash + Eq + Clone,
+{
+    let lhs_total = lhs.iter().map(|e| e.1).sum::<usize>().max(1) as f64;
+    let rhs_total = rhs.iter().map(|e| e.1).sum::<usize>().max(1) as f64;
+
+    let mut frequencies = HashMap::<T, (f64, f64)>::new();
+    for (message, count) in lhs.iter() {
+        frequencies.entry(message.clone()).or_default().0 +=
+            *count as f64 / lhs_total;
+    }
+    for (message, count) in rhs.iter() {
+        frequencies.entry(message.clone()).or_default().1 +=
+            *count as f64 / rhs_total;
+    }
+
+    0.5 * frequencies
+        .values()
+        .map(|(p, q)| (p - q).abs())
+        .sum::<f64>()
+}
+
+/// The probability mass function of a Zipf distribution with exponent `s` over ranks `1..=n`.
+fn zipf_pmf(n: usize, s: f64) -> Vec<f64> {
+    let weights = (1..=n)
+        .map(|rank| (rank as f64).powf(-s))
+        .collect::<Vec<_>>();
+    let sum = weights.iter().sum::<f64>();
+    weights.into_iter().map(|w| w / sum).collect()
+}
+
+/// Find the maximum of a unimodal function within the given range.
+fn golden_section_max(f: impl Fn(f64) -> f64, range: (f64, f64)) -> f64 {
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let (mut lo, mut hi) = range;
+
+    for _ in 0..48 {
+        let lhs = hi - ratio * (hi - lo);
+        let rhs = lo + ratio * (hi - lo);
+        if f(lhs) < f(rhs) {
+            lo = lhs;
+        } else {
+            hi = rhs;
+        }
+    }
+
+    (lo + hi) / 2.0
+}
@@ -0,0 +1,269 @@
+mod correctness {
+    use std::collections::{HashMap, HashSet};
+
+    use fse::{
+        bucketed::ContextBucketed,
+        fse::{exponential, AsBytes, BaseCrypto, PartitionFrequencySmoothing},
+        lpfse::{ContextLPFSE, EncoderBHE, EncoderIHBE, HomophoneEncoder},
+        native::ContextNative,
+        params::{PfseParams, SaltAllocation, StreamingParams, WreParams},
+        pfse::ContextPFSE,
+        plain::ContextPlain,
+        streaming::ContextStreaming,
+        token::TokenSet,
+        wre::ContextWRE,
+    };
+    use proptest::prelude::*;
+
+    /// A server that only stores and matches opaque documents, each labeled with the row it stores or `None` for a
+    /// dummy. The labels are the oracle and are never used for matching.
+    #[derive(Default)]
+    struct Server {
+        documents: Vec<(Option<usize>, Vec<u8>)>,
+    }
+
+    impl Server {
+        fn insert(&mut self, row: Option<usize>, document: Vec<u8>) {
+            self.documents.push((row, document));
+        }
+
+        fn lookup(&self, tokens: &TokenSet) -> Vec<(Option<usize>, &[u8])> {
+            self.documents
+                .iter()
+                .filter(|(_, document)| tokens.contains(document))
+                .map(|(row, document)| (*row, document.as_slice()))
+                .collect()
+        }
+    }
+
+    /// Search every distinct message of `dataset` and compare the matched rows with the rows of that message. Fails on
+    /// a missed row, a matched dummy or a matched document of the message that `decodes` rejects. Returns the fraction
+    /// of the matched rows that hold another message, averaged over a search of each row.
+    fn check<C, S, D>(
+        ctx: &mut C,
+        dataset: &[String],
+        server: &Server,
+        mut search: S,
+        decodes: D,
+    ) -> std::result::Result<f64, TestCaseError>
+    where
+        S: FnMut(&mut C, &String) -> Option<TokenSet>,
+        D: Fn(&C, &[u8], &String) -> bool,
+    {
+        let mut rows = HashMap::<&String, HashSet<usize>>::new();
+        for (row, message) in dataset.iter().enumerate() {
+            rows.entry(message).or_default().insert(row);
+        }
+
+        let mut false_positives = 0.0;
+        for (message, expected) in rows.iter() {
+            let tokens = search(ctx, message);
+            prop_assert!(tokens.is_some(), "no tokens for {}", message);
+            let matched = server.lookup(&tokens.unwrap());
+
+            let mut found = HashSet::new();
+            let mut others = 0usize;
+            for (row, document) in matched.iter() {
+                let row = row.ok_or_else(|| {
+                    TestCaseError::fail(format!("{} matched a dummy", message))
+                })?;
+                match expected.contains(&row) {
+                    true => {
+                        prop_assert!(
+                            decodes(ctx, document, message),
+                            "row {} does not decode into {}",
+                            row,
+                            message
+                        );
+                        found.insert(row);
+                    }
+                    false => others += 1,
+                }
+            }
+            prop_assert_eq!(&found, expected, "{} missed rows", message);
+            false_positives +=
+                expected.len() as f64 * others as f64 / matched.len() as f64;
+        }
+
+        Ok(false_positives / dataset.len() as f64)
+    }
+
+    /// Store each row under one of the ciphertexts `encrypt` returns for it, in turn, and the smoothed documents that
+    /// are no ciphertext of any message as dummies.
+    fn load<E>(
+        dataset: &[String],
+        smoothed: Vec<Vec<u8>>,
+        mut encrypt: E,
+    ) -> Server
+    where
+        E: FnMut(&String) -> Vec<Vec<u8>>,
+    {
+        let mut server = Server::default();
+        let mut real = HashSet::new();
+        for (row, message) in dataset.iter().enumerate() {
+            let mut ciphertexts = encrypt(message);
+            assert!(!ciphertexts.is_empty(), "{} is not encrypted", message);
+            real.extend(ciphertexts.iter().cloned());
+            server.insert(
+                Some(row),
+                ciphertexts.swap_remove(row % ciphertexts.len()),
+            );
+        }
+        for document in smoothed {
+            if !real.contains(&document) {
+                server.insert(None, document);
+            }
+        }
+        server
+    }
+
+    /// Datasets of a uniform or a skewed distribution over up to 20 messages.
+    fn dataset() -> impl Strategy<Value = Vec<String>> {
+        prop_oneof![
+            prop::collection::vec(0usize..20, 1..300),
+            prop::collection::vec(0usize..400, 1..300)
+                .prop_map(|e| e.into_iter().map(|e| e * e / 8000).collect()),
+        ]
+        .prop_map(|e| e.into_iter().map(|e| format!("message_{}", e)).collect())
+    }
+
+    fn search<C: BaseCrypto<String>>(
+        ctx: &mut C,
+        message: &String,
+    ) -> Option<TokenSet> {
+        ctx.search_tokens(message)
+    }
+
+    fn decodes<C: BaseCrypto<String>>(
+        ctx: &C,
+        document: &[u8],
+        message: &String,
+    ) -> bool {
+        ctx.decrypt(document) == Some(message.to_bytes())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn test_native_correctness(
+            dataset in dataset(),
+            rnd in any::<bool>(),
+        ) {
+            let mut ctx = ContextNative::new(rnd);
+            ctx.key_generate();
+            let server =
+                load(&dataset, Vec::new(), |e| ctx.encrypt(e).unwrap());
+            // The randomized ciphertexts cannot be decrypted without their nonces.
+            let rate = check(
+                &mut ctx,
+                &dataset,
+                &server,
+                search,
+                |ctx, document, message| {
+                    rnd || decodes(ctx, document, message)
+                },
+            )?;
+            prop_assert_eq!(rate, 0.0);
+        }
+
+        #[test]
+        fn test_plain_correctness(dataset in dataset()) {
+            let mut ctx = ContextPlain::new();
+            let server =
+                load(&dataset, Vec::new(), |e| ctx.encrypt(e).unwrap());
+            let rate = check(&mut ctx, &dataset, &server, search, decodes)?;
+            prop_assert_eq!(rate, 0.0);
+        }
+
+        #[test]
+        fn test_pfse_correctness(
+            dataset in dataset(),
+            lambda in 0.1f64..1.0,
+        ) {
+            let mut ctx = ContextPFSE::default();
+            ctx.key_generate();
+            ctx.set_params(&PfseParams::new(lambda, 1.0, 0.1)).unwrap();
+            ctx.partition(&dataset, exponential).unwrap();
+            ctx.transform();
+            let smoothed = ctx.smooth();
+            let server =
+                load(&dataset, smoothed, |e| ctx.encrypt(e).unwrap());
+            let rate = check(&mut ctx, &dataset, &server, search, decodes)?;
+            prop_assert_eq!(rate, 0.0);
+        }
+
+        #[test]
+        fn test_lpfse_correctness(dataset in dataset(), bhe in any::<bool>()) {
+            let encoder: Box<dyn HomophoneEncoder<String>> = match bhe {
+                true => Box::new(EncoderBHE::new()),
+                false => Box::new(EncoderIHBE::new()),
+            };
+            let mut ctx = ContextLPFSE::new(0.1, encoder);
+            ctx.key_generate();
+            ctx.initialize(&dataset, "", "", false).unwrap();
+            let server =
+                load(&dataset, Vec::new(), |e| ctx.encrypt(e).unwrap());
+            let rate = check(&mut ctx, &dataset, &server, search, decodes)?;
+            prop_assert_eq!(rate, 0.0);
+        }
+
+        #[test]
+        fn test_wre_correctness(
+            dataset in dataset(),
+            lambda in 1usize..100,
+            fixed in any::<bool>(),
+        ) {
+            let allocation = match fixed {
+                true => SaltAllocation::Fixed,
+                false => SaltAllocation::Bucketized,
+            };
+            let params = WreParams::new(lambda).with_allocation(allocation);
+            let mut ctx = ContextWRE::from_params(&params).unwrap();
+            ctx.key_generate();
+            ctx.initialize(&dataset, "", "", false).unwrap();
+            let server =
+                load(&dataset, Vec::new(), |e| ctx.encrypt(e).unwrap());
+            let rate = check(&mut ctx, &dataset, &server, search, decodes)?;
+            prop_assert_eq!(rate, 0.0);
+        }
+
+        #[test]
+        fn test_streaming_correctness(dataset in dataset()) {
+            // The window covers every epoch, so that no row falls out of the searches.
+            let params = StreamingParams::new(50, 8, 4, 64, 4);
+            let mut ctx = ContextStreaming::from_params(&params).unwrap();
+            ctx.key_generate();
+            let server =
+                load(&dataset, Vec::new(), |e| ctx.encrypt(e).unwrap());
+            let rate = check(&mut ctx, &dataset, &server, search, decodes)?;
+            prop_assert_eq!(rate, 0.0);
+        }
+
+        #[test]
+        fn test_bucketed_correctness(
+            dataset in dataset(),
+            buckets in 1u64..16,
+        ) {
+            let params = PfseParams::new(0.25, 1.0, 0.1);
+            let mut ctx = ContextBucketed::new(buckets, &params).unwrap();
+            ctx.key_generate();
+            let stats = ctx.partition(&dataset, exponential).unwrap();
+            ctx.transform();
+            let smoothed = ctx.smooth();
+            let server =
+                load(&dataset, smoothed, |e| ctx.encrypt(e).unwrap());
+            // A bucket holds other messages, whose rows are the expected false positives.
+            let rate = check(
+                &mut ctx,
+                &dataset,
+                &server,
+                |ctx, e| ctx.search_tokens(e),
+                |ctx, document, message| {
+                    ctx.decrypt_bucket(document) == ctx.bucket(message)
+                },
+            )?;
+            prop_assert!((rate - stats.false_positive_rate).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,549 @@
+mod numeric_tests {
+    use std::collections::HashMap;
+
+    use proptest::prelude::*;
+
+    /// Histograms with zero counts, a single message, identical counts and a dominating message.
+    fn pathological_histogram() -> impl Strategy<Value = HashMap<String, usize>>
+    {
+        prop_oneof![
+            prop::collection::vec(0usize..100, 0..20),
+            (1usize..1000).prop_map(|cnt| vec![cnt]),
+            (1usize..100, 1usize..20).prop_map(|(cnt, len)| vec![cnt; len]),
+            (1usize..1000, 0usize..20).prop_map(|(cnt, len)| [
+                vec![cnt],
+                vec![1; len]
+            ]
+            .concat()),
+        ]
+        .prop_map(|counts| {
+            counts
+                .into_iter()
+                .enumerate()
+                .map(|(i, cnt)| (format!("message_{}", i), cnt))
+                .collect()
+        })
+    }
+
+    fn advantage() -> impl Strategy<Value = f64> {
+        prop_oneof![
+            0.01f64..1.0,
+            Just(0.0),
+            Just(-1.0),
+            Just(f64::NAN),
+            Just(f64::INFINITY),
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_pfse_pathological_histogram(
+            histogram in pathological_histogram(),
+            lambda in prop_oneof![0.0f64..2.0, Just(f64::NAN)],
+            scale in 0.5f64..2.0,
+            advantage in prop_oneof![0.1f64..1.0, Just(0.0)],
+        ) {
+            use fse::{
+                fse::{exponential, BaseCrypto, PartitionFrequencySmoothing},
+                params::PfseParams,
+                pfse::ContextPFSE,
+            };
+
+            let mut ctx = ContextPFSE::<String>::default();
+            ctx.key_generate();
+            let params = PfseParams::new(lambda, scale, advantage);
+            if ctx.set_params(&params).is_err() {
+                prop_assert!(params.validate().is_err());
+                return Ok(());
+            }
+            if ctx.partition_histogram(&histogram, exponential).is_err() {
+                prop_assert!(histogram.values().all(|&cnt| cnt == 0));
+                return Ok(());
+            }
+            prop_assert!(ctx.check_conservation(&histogram).is_ok());
+            ctx.transform();
+            ctx.smooth();
+
+            for message in ctx.get_local_table().keys().cloned().collect::<Vec<_>>() {
+                let ciphertexts = ctx.encrypt(&message).unwrap();
+                prop_assert!(!ciphertexts.is_empty());
+            }
+        }
+
+        #[test]
+        fn test_pfse_split_conservation(
+            counts in prop::collection::vec(1usize..1000, 2..50),
+            lambda in 0.05f64..2.0,
+            scale in 0.5f64..2.0,
+            partitions in prop_oneof![Just(None), (2usize..10).prop_map(Some)],
+        ) {
+            use fse::{
+                fse::{exponential, BaseCrypto, PartitionFrequencySmoothing},
+                params::{PartitionShape, PfseParams},
+                pfse::ContextPFSE,
+            };
+
+            let histogram = counts
+                .into_iter()
+                .enumerate()
+                .map(|(i, cnt)| (format!("message_{}", i), cnt))
+                .collect::<HashMap<_, _>>();
+            let mut params = PfseParams::new(lambda, scale, 0.5);
+            if let Some(partitions) = partitions {
+                params.shape = PartitionShape::EqualMass(partitions);
+            }
+            let mut ctx = ContextPFSE::<String>::default();
+            ctx.key_generate();
+            ctx.set_params(&params).unwrap();
+            ctx.partition_histogram(&histogram, exponential).unwrap();
+            prop_assert!(ctx.check_conservation(&histogram).is_ok());
+
+            // Each part of a split message gets its own copies, and no message gets more parts than occurrences.
+            let stats = ctx.transform();
+            if stats.partitions.iter().all(|e| !e.skipped) {
+                prop_assert!(ctx.check_conservation(&histogram).is_ok());
+            }
+            for (message, value) in ctx.get_local_table().iter() {
+                prop_assert!(value.len() <= histogram[message]);
+            }
+        }
+
+        #[test]
+        fn test_ihbe_pathological_histogram(
+            histogram in pathological_histogram(),
+            advantage in advantage(),
+        ) {
+            use fse::lpfse::{EncoderIHBE, HomophoneEncoder};
+
+            let mut encoder = EncoderIHBE::<String>::new();
+            let res = encoder.initialize_histogram(&histogram, advantage);
+            let local_table = encoder.local_table();
+            prop_assert_eq!(res.is_ok(), !local_table.is_empty());
+
+            for (message, &cnt) in histogram.iter() {
+                let valid = cnt != 0 && advantage.is_finite() && advantage > 0.0;
+                prop_assert_eq!(local_table.contains_key(message), valid);
+                if valid {
+                    let encoded = encoder.encode(message).unwrap();
+                    prop_assert_eq!(encoder.decode(&encoded).unwrap(), message.as_bytes());
+                }
+            }
+        }
+
+        #[test]
+        fn test_bhe_pathological_histogram(
+            histogram in pathological_histogram(),
+            advantage in advantage(),
+        ) {
+            use fse::lpfse::{EncoderBHE, HomophoneEncoder};
+
+            let mut encoder = EncoderBHE::<String>::new();
+            let res = encoder.initialize_histogram(&histogram, advantage);
+            let local_table = encoder.local_table();
+            prop_assert_eq!(res.is_ok(), !local_table.is_empty());
+
+            for message in histogram.keys() {
+                let encoded = encoder.encode(message);
+                prop_assert_eq!(encoded.is_some(), local_table.contains_key(message));
+                if let Some(encoded) = encoded {
+                    prop_assert_eq!(encoder.decode(&encoded).unwrap(), message.as_bytes());
+                }
+            }
+        }
+
+        #[test]
+        fn test_params_validation(
+            lambda in prop_oneof![-1.0f64..2.0, Just(f64::NAN)],
+            advantage in advantage(),
+        ) {
+            use fse::params::{LpfseParams, PfseParams, SchemeParams};
+
+            let valid_lambda = lambda > 0.0;
+            let valid_advantage = advantage.is_finite() && advantage > 0.0 && advantage <= 1.0;
+            let params = PfseParams::new(lambda, 1.0, advantage);
+            prop_assert_eq!(params.validate().is_ok(), valid_lambda && valid_advantage);
+            prop_assert_eq!(LpfseParams::new(advantage).validate().is_ok(), valid_advantage);
+            prop_assert!(SchemeParams::from(LpfseParams::new(0.5)).pfse().is_err());
+        }
+
+        #[test]
+        fn test_ceil_eps(value in 0usize..1_000_000, noise in -1e-10f64..1e-10) {
+            use fse::util::ceil_eps;
+
+            prop_assert_eq!(ceil_eps(value as f64 + noise), value);
+            prop_assert_eq!(ceil_eps(value as f64 + 0.5), value + 1);
+        }
+    }
+
+    #[test]
+    fn test_checked_uniform() {
+        use fse::util::checked_uniform;
+
+        assert!(checked_uniform(0u64, 0).is_none());
+        assert!(checked_uniform(5u64, 3).is_none());
+        assert!(checked_uniform(0u64, 1).is_some());
+    }
+
+    #[test]
+    fn test_soft_assignment() {
+        use fse::attack::LpAttacker;
+
+        // A deterministic encryption: every message has exactly one ciphertext.
+        let counts = [("a", 50usize), ("b", 30), ("c", 20)];
+        let mut correct = HashMap::new();
+        let mut local_table = HashMap::new();
+        let mut raw_ciphertexts = Vec::new();
+        for (message, count) in counts.iter() {
+            let ciphertext = message.as_bytes().to_vec();
+            correct.insert(message.to_string(), vec![ciphertext.clone()]);
+            local_table.insert(message.to_string(), vec![(0, 1, *count)]);
+            raw_ciphertexts.extend(vec![ciphertext; *count]);
+        }
+
+        let mut attacker = LpAttacker::<String>::new(1);
+        let hard = attacker.attack(&correct, &local_table, &raw_ciphertexts);
+        assert!((hard - 1.0).abs() < 1e-9);
+
+        let mut attacker = LpAttacker::<String>::with_regularization(1, 0.01);
+        let soft = attacker.attack(&correct, &local_table, &raw_ciphertexts);
+        assert!(soft > 0.99 && soft <= 1.0 + 1e-9);
+        for row in attacker.get_soft_assignment().unwrap() {
+            assert!((row.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        }
+
+        // A strong regularization spreads each message over the ciphertext histogram: 0.5^2 + 0.3^2 + 0.2^2.
+        let mut attacker = LpAttacker::<String>::with_regularization(1, 100.0);
+        let blind = attacker.attack(&correct, &local_table, &raw_ciphertexts);
+        assert!((blind - 0.38).abs() < 1e-2);
+
+        // No padding is needed when there are more ciphertexts than messages.
+        raw_ciphertexts.extend(vec![b"dummy".to_vec(); 5]);
+        let mut attacker = LpAttacker::<String>::with_regularization(2, 0.001);
+        let soft = attacker.attack(&correct, &local_table, &raw_ciphertexts);
+        assert_eq!(attacker.get_soft_assignment().unwrap()[0].len(), 4);
+        assert!(soft > 0.9);
+    }
+
+    #[test]
+    fn test_decile_accuracy() {
+        use fse::attack::{
+            decile_accuracy, rank_accuracy, MLEAttacker, Recovery,
+        };
+
+        // The two most frequent of 20 messages are fully recovered, the rest not at all.
+        let recovery = (0..20usize)
+            .map(|i| (i, (100 - i, if i < 2 { (100 - i) as f64 } else { 0.0 })))
+            .collect::<Recovery<usize>>();
+        assert_eq!(rank_accuracy(&recovery, 0.0..0.1), 1.0);
+        assert_eq!(rank_accuracy(&recovery, 0.1..1.0), 0.0);
+        let deciles = decile_accuracy(&recovery);
+        assert_eq!(deciles.len(), 10);
+        assert_eq!(deciles[0], 1.0);
+        assert!(deciles[1..].iter().all(|&e| e == 0.0));
+        // With fewer than ten messages some deciles are empty.
+        let recovery = Recovery::from([("a", (1, 1.0))]);
+        assert!(decile_accuracy(&recovery)[1].is_nan());
+
+        // The occurrence-weighted recovery adds up to the accuracy.
+        let counts = [("a", 50usize), ("b", 30), ("c", 20)];
+        let mut correct = HashMap::new();
+        let mut local_table = HashMap::new();
+        let mut raw_ciphertexts = Vec::new();
+        for (message, count) in counts.iter() {
+            let ciphertext = message.as_bytes().to_vec();
+            correct.insert(message.to_string(), vec![ciphertext.clone()]);
+            local_table.insert(message.to_string(), vec![(0, 1, *count)]);
+            raw_ciphertexts.extend(vec![ciphertext; *count]);
+        }
+        let mut attacker = MLEAttacker::<String>::new();
+        let accuracy =
+            attacker.attack(&correct, &local_table, &raw_ciphertexts);
+        let recovery = attacker.get_recovery().unwrap();
+        assert_eq!(recovery.len(), 3);
+        assert!((rank_accuracy(recovery, 0.0..1.0) - accuracy).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_composition_leakage() {
+        use fse::attack::{composition_leakage, AttackMeta};
+
+        // Each collection alone confuses two pairs of messages with the same frequency, but not the same pairs.
+        let data = ["a", "a", "b", "b", "c", "c", "d", "d"]
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        let encrypt = |split: &[&str]| {
+            let ciphertexts = data
+                .iter()
+                .enumerate()
+                .map(|(i, message)| match split.contains(&message.as_str()) {
+                    true => format!("{}{}", message, i).into_bytes(),
+                    false => message.clone().into_bytes(),
+                })
+                .collect::<Vec<_>>();
+            AttackMeta::from_records(&data, &ciphertexts)
+        };
+        let first = encrypt(&["a", "b"]);
+        let second = encrypt(&["b", "d"]);
+
+        let leakage = composition_leakage(&data, &first, &second).unwrap();
+        assert_eq!((leakage.first, leakage.second), (0.5, 0.5));
+        assert_eq!((leakage.joint, leakage.unique), (1.0, 1.0));
+        assert_eq!(leakage.gain(), 0.5);
+
+        // The same context twice leaks nothing more.
+        let leakage = composition_leakage(&data, &first, &first).unwrap();
+        assert_eq!(leakage.joint, leakage.first);
+        assert!(composition_leakage(&data[1..], &first, &second).is_err());
+    }
+
+    #[test]
+    fn test_precision_recall() {
+        use fse::attack::{
+            precision_recall, precision_recall_curve, Guess, LpAttacker,
+            MLEAttacker,
+        };
+
+        let guesses = vec![
+            Guess {
+                message: "a",
+                count: 10,
+                confidence: 2.0,
+                recovered: 1.0,
+            },
+            Guess {
+                message: "b",
+                count: 10,
+                confidence: 1.0,
+                recovered: 0.0,
+            },
+        ];
+        let trusted = precision_recall(&guesses, 1.5);
+        assert_eq!((trusted.precision, trusted.recall), (1.0, 0.5));
+        let all = precision_recall(&guesses, 0.0);
+        assert_eq!((all.precision, all.recall), (0.5, 0.5));
+        let none = precision_recall(&guesses, 3.0);
+        assert!(none.precision.is_nan());
+        assert_eq!(none.recall, 0.0);
+
+        // The frequency of `a` stands out while `b` and `c` can be confused, so `a` is the most trusted guess.
+        let counts = [("a", 50usize), ("b", 30), ("c", 29)];
+        let mut correct = HashMap::new();
+        let mut local_table = HashMap::new();
+        let mut raw_ciphertexts = Vec::new();
+        for (message, count) in counts.iter() {
+            let ciphertext = message.as_bytes().to_vec();
+            correct.insert(message.to_string(), vec![ciphertext.clone()]);
+            local_table.insert(message.to_string(), vec![(0, 1, *count)]);
+            raw_ciphertexts.extend(vec![ciphertext; *count]);
+        }
+        let mut attacker = MLEAttacker::<String>::new();
+        let accuracy =
+            attacker.attack(&correct, &local_table, &raw_ciphertexts);
+        let mle = attacker.get_guesses().unwrap().clone();
+        let mut attacker = LpAttacker::<String>::new(1);
+        attacker.attack(&correct, &local_table, &raw_ciphertexts);
+        let lp = attacker.get_guesses().unwrap().clone();
+        for guesses in [mle, lp] {
+            assert_eq!(guesses.len(), 3);
+            let most = guesses
+                .iter()
+                .max_by(|lhs, rhs| lhs.confidence.total_cmp(&rhs.confidence))
+                .unwrap();
+            assert_eq!(most.message, "a");
+
+            let curve = precision_recall_curve(&guesses, 3);
+            assert_eq!(curve.len(), 3);
+            assert_eq!(curve[0].precision, 1.0);
+            assert!((curve[0].recall - 50.0 / 109.0).abs() < 1e-9);
+            assert!((curve[2].recall - accuracy).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_leakage_collector() {
+        use fse::attack::{LeakageCollector, MLEAttacker};
+        use fse::bucketed::ContextBucketed;
+        use fse::fse::{BaseCrypto, PartitionFrequencySmoothing};
+        use fse::native::ContextNative;
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+        use fse::plain::ContextPlain;
+
+        let data = (0..20)
+            .flat_map(|i| vec![i.to_string(); 200 / (i + 1)])
+            .collect::<Vec<_>>();
+        let params = PfseParams::new(0.25, 1.0, 0.1);
+
+        let mut dte = ContextNative::new(false);
+        dte.key_generate();
+        let mut pfse = ContextPFSE::default();
+        pfse.key_generate();
+        pfse.set_params(&params).unwrap();
+        let mut bucketed = ContextBucketed::new(4, &params).unwrap();
+        bucketed.key_generate();
+
+        let mut accuracies = Vec::new();
+        let contexts: Vec<Box<dyn LeakageCollector<String>>> = vec![
+            Box::new(dte),
+            Box::new(pfse),
+            Box::new(bucketed),
+            Box::new(ContextPlain::new()),
+        ];
+        for mut ctx in contexts {
+            let meta = ctx.collect_leakage(&data).unwrap();
+            assert_eq!(meta.sequence.len(), data.len());
+            assert_eq!(meta.correct.len(), 20);
+            // Every record is encrypted under a ciphertext of its message.
+            assert!(data
+                .iter()
+                .zip(meta.sequence.iter())
+                .all(|(message, c)| meta.correct[message].contains(c)));
+
+            let mut attacker = MLEAttacker::<String>::new();
+            accuracies.push(attacker.attack(
+                &meta.correct,
+                &meta.local_table,
+                &meta.raw_ciphertexts,
+            ));
+        }
+
+        // Deterministic encryption of a skewed column is recovered up to ties; smoothing hides part of it.
+        assert!(accuracies[0] > 0.9);
+        assert!(accuracies[1] < accuracies[0]);
+        // The plaintext baseline leaks as much as DTE.
+        assert!(accuracies[3] > 0.9);
+    }
+
+    #[test]
+    fn test_dummy_mass() {
+        use fse::attack::LeakageCollector;
+        use fse::fse::{BaseCrypto, PartitionFrequencySmoothing};
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+
+        let data = (0..20)
+            .flat_map(|i| vec![i.to_string(); 200 / (i + 1)])
+            .collect::<Vec<_>>();
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1)).unwrap();
+        let meta = ctx.collect_leakage(&data).unwrap();
+
+        assert!(!meta.dummies.is_empty());
+        let mass = meta.dummy_mass();
+        assert!(mass > 0.0 && mass < 1.0);
+        let reachable = meta.reachable_ciphertexts();
+        assert_eq!(
+            reachable.len(),
+            ((1.0 - mass) * meta.raw_ciphertexts.len() as f64).round() as usize
+        );
+        // The dummies are encrypted: they decrypt under the key but are not in the domain.
+        for dummy in meta.dummies.iter() {
+            let plaintext = ctx.decrypt(dummy).unwrap();
+            assert!(!data.iter().any(|e| e.as_bytes() == plaintext));
+            assert!(!reachable.contains(dummy));
+        }
+    }
+
+    #[test]
+    fn test_dummy_realism() {
+        use std::{collections::HashSet, sync::Arc};
+
+        use fse::attack::LeakageCollector;
+        use fse::dummy::{DummyGenerator, FormatDummies, RangeDummies};
+        use fse::fse::{BaseCrypto, PartitionFrequencySmoothing};
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+
+        let data = (0..20)
+            .flat_map(|i| vec![format!("941{:02}", i); 200 / (i + 1)])
+            .collect::<Vec<_>>();
+        let collect = |ctx: &mut ContextPFSE<String>| {
+            ctx.key_generate();
+            ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1)).unwrap();
+            ctx.collect_leakage(&data).unwrap()
+        };
+
+        // The random dummies are all filtered out by their format.
+        let meta = collect(&mut ContextPFSE::default());
+        assert!(!meta.dummies.is_empty());
+        assert_eq!(meta.dummy_realism(), 0.0);
+        assert_eq!(meta.filtered_ciphertexts(), meta.reachable_ciphertexts());
+
+        // The dummies of the format of the column are all kept, and none of them is a real message.
+        let mut ctx = ContextPFSE::default();
+        ctx.set_dummy_generator(Arc::new(FormatDummies));
+        let meta = collect(&mut ctx);
+        assert!(!meta.dummies.is_empty());
+        assert_eq!(meta.dummy_realism(), 1.0);
+        assert_eq!(meta.filtered_ciphertexts(), meta.raw_ciphertexts);
+        let dummies = ctx.dummies().collect::<Vec<_>>();
+        assert_eq!(dummies.iter().collect::<HashSet<_>>().len(), dummies.len());
+        assert!(dummies.iter().all(|e| e.len() == 5 && !data.contains(e)));
+
+        // The numbers are drawn within the range of the real ones, and the exhausted range is drawn as far as it goes.
+        let values = [3, 5, 10, 20];
+        let support = values.iter().collect::<HashSet<_>>();
+        let dummies = RangeDummies.generate(&support, 10);
+        assert_eq!(dummies.len(), 10);
+        assert!(dummies.iter().all(|e| (3..=20).contains(e)));
+        assert!(dummies.iter().all(|e| !values.contains(e)));
+        assert_eq!(RangeDummies.generate(&support, 100).len(), 14);
+    }
+
+    #[test]
+    fn test_persistent_view() {
+        use fse::attack::{AttackMeta, LeakageCollector, PersistentView};
+        use fse::fse::{BaseCrypto, PartitionFrequencySmoothing};
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+
+        let data = (0..20)
+            .flat_map(|i| vec![i.to_string(); 200 / (i + 1)])
+            .collect::<Vec<_>>();
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1)).unwrap();
+        let mut meta = ctx.collect_leakage(&data).unwrap();
+
+        let mut view = PersistentView::new();
+        view.observe(&meta.raw_ciphertexts);
+        assert!(view.get_batches().is_empty());
+
+        let updates = vec!["0".to_string(), "0".to_string(), "5".to_string()];
+        let ciphertexts = updates
+            .iter()
+            .map(|e| ctx.encrypt_update(e).unwrap())
+            .collect::<Vec<_>>();
+        // The updates reuse the ciphertexts of the setup without any dummy.
+        assert!(updates
+            .iter()
+            .zip(ciphertexts.iter())
+            .all(|(message, c)| meta.correct[message].contains(c)));
+
+        let count = |meta: &AttackMeta<String>, message: &str| {
+            meta.local_table[message].iter().map(|e| e.2).sum::<usize>()
+        };
+        let before = count(&meta, "5");
+        meta.insert(&updates, &ciphertexts);
+        view.observe(&meta.raw_ciphertexts);
+        assert!(count(&meta, "5") > before);
+
+        // Diffing the snapshots isolates exactly the updates.
+        assert_eq!(view.get_snapshot_num(), 2);
+        let mut inserted = view.inserted();
+        let mut expected = ciphertexts.clone();
+        inserted.sort();
+        expected.sort();
+        assert_eq!(inserted, expected);
+        view.observe(&meta.raw_ciphertexts);
+        assert!(view.get_batches()[1].is_empty());
+
+        let updates_meta = AttackMeta::from_records(&updates, &ciphertexts);
+        assert_eq!(count(&updates_meta, "0"), 2);
+        assert_eq!(updates_meta.sequence, ciphertexts);
+    }
+}
@@ -1,6 +1,5 @@
 mod scheme_tests {
     use fse::fse::Conn;
-    use rand::seq::SliceRandom;
 
     const ADDRESS: &str = "mongodb://127.0.0.1:27017";
     const DB_NAME: &str = "bench";
@@ -20,27 +19,21 @@ mod scheme_tests {
         use fse::util::read_csv_exact;
         use fse::{
             fse::BaseCrypto, fse::PartitionFrequencySmoothing,
-            pfse::ContextPFSE,
+            params::PfseParams, pfse::ContextPFSE,
         };
 
         let vec = read_csv_exact("./data/test.csv", "order_number").unwrap();
         let mut ctx = ContextPFSE::default();
         ctx.initialize_conn(ADDRESS, DB_NAME, false);
         ctx.key_generate();
-        ctx.set_params(&vec![0.25, 1.0, 2_f64.powf(-12_f64)]);
-        ctx.partition(&vec, exp);
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 2_f64.powf(-12_f64)))
+            .unwrap();
+        ctx.partition(&vec, exp).unwrap();
         ctx.transform();
         ctx.store("./data/summary.txt").unwrap();
 
-        let documents = ctx
-            .smooth()
-            .into_iter()
-            .enumerate()
-            .map(|(_, ciphertext)| {
-                let data = String::from_utf8(ciphertext).unwrap();
-                Data { data }
-            })
-            .collect::<Vec<_>>();
+        let documents =
+            ctx.smooth().into_iter().map(Data::new).collect::<Vec<_>>();
 
         let conn = ctx.get_conn();
         conn.insert(documents, PFSE_COLLECTION).unwrap();
@@ -59,18 +52,18 @@ mod scheme_tests {
         let mut ctx =
             ContextLPFSE::new(2f64.powf(-10_f64), Box::new(EncoderIHBE::new()));
         ctx.key_generate();
-        ctx.initialize(&vec, ADDRESS, DB_NAME, false);
+        ctx.initialize(&vec, ADDRESS, DB_NAME, false).unwrap();
         ctx.store("./data/summary_ihbe.txt").unwrap();
 
         let mut ciphertexts = Vec::new();
         for message in vec.iter() {
             let ciphertext = ctx.encrypt(message).unwrap().remove(0);
-            ciphertexts.push(String::from_utf8(ciphertext).unwrap());
+            ciphertexts.push(ciphertext);
         }
 
         let mut plaintexts = Vec::new();
         for ciphertext in ciphertexts.iter() {
-            let plaintext = ctx.decrypt(ciphertext.as_bytes()).unwrap();
+            let plaintext = ctx.decrypt(ciphertext).unwrap();
             plaintexts.push(String::from_utf8(plaintext).unwrap());
         }
 
@@ -91,18 +84,18 @@ mod scheme_tests {
         let mut ctx =
             ContextLPFSE::new(2f64.powf(-10_f64), Box::new(EncoderBHE::new()));
         ctx.key_generate();
-        ctx.initialize(&vec, ADDRESS, DB_NAME, false);
+        ctx.initialize(&vec, ADDRESS, DB_NAME, false).unwrap();
         ctx.store("./data/summary_bhe.txt").unwrap();
 
         let mut ciphertexts = Vec::new();
         for message in vec.iter() {
             let ciphertext = ctx.encrypt(message).unwrap().remove(0);
-            ciphertexts.push(String::from_utf8(ciphertext).unwrap());
+            ciphertexts.push(ciphertext);
         }
 
         let mut plaintexts = Vec::new();
         for ciphertext in ciphertexts.iter() {
-            let plaintext = ctx.decrypt(ciphertext.as_bytes()).unwrap();
+            let plaintext = ctx.decrypt(ciphertext).unwrap();
             plaintexts.push(String::from_utf8(plaintext).unwrap());
         }
 
@@ -125,16 +118,14 @@ mod scheme_tests {
         use mongodb::bson::*;
 
         let mut ctx = ContextPFSE::<String>::default();
-        let doc = fse::db::Data {
-            data: "ooo".to_string(),
-        };
+        let doc = fse::db::Data::new(b"ooo".to_vec());
         ctx.initialize_conn("mongodb://127.0.0.1:27017", "bench", true);
         let conn = ctx.get_conn();
         conn.insert(vec![doc], "test_collection").unwrap();
 
         let mut doc = Document::new();
         let mut test_key = Document::new();
-        test_key.insert("data", "ooo");
+        test_key.insert("data", fse::db::to_binary("ooo"));
         doc.insert("$or", vec![test_key]);
 
         println!("{}", conn.size("test_collection"));
@@ -148,24 +139,2445 @@ mod scheme_tests {
         );
     }
 
+    #[test]
+    fn test_db_advise_indexes() {
+        use fse::db::{Connector, Data, WorkloadHints};
+
+        let conn = Connector::<Data>::new(
+            "mongodb://127.0.0.1:27017",
+            "fse_test",
+            true,
+        )
+        .unwrap();
+        let documents = (0..100u64)
+            .map(|i| Data::with_seq(vec![(i % 10) as u8], i))
+            .collect::<Vec<_>>();
+        conn.insert(documents, "test_indexes").unwrap();
+
+        let hints = WorkloadHints {
+            exact_data: false,
+            conjunctive: vec!["seq".into(), "column".into()],
+        };
+        let advice = conn.advise_indexes("test_indexes", &hints).unwrap();
+        assert_eq!(advice.created, vec!["data_1_seq_1".to_string()]);
+        assert_eq!(advice.dropped, vec!["data_1".to_string()]);
+        assert_eq!(advice.missing, vec!["column".to_string()]);
+        assert!(advice.plan_before.is_some() && advice.plan_after.is_some());
+
+        // Advising again changes nothing.
+        let advice = conn.advise_indexes("test_indexes", &hints).unwrap();
+        assert!(advice.created.is_empty() && advice.dropped.is_empty());
+        assert_eq!(advice.plan_before, advice.plan_after);
+        conn.drop_collection("test_indexes");
+    }
+
+    #[test]
+    fn test_db_search_timed() {
+        use fse::{db::Data, fse::BaseCrypto, native::ContextNative};
+
+        let mut ctx = ContextNative::<String>::new(false);
+        ctx.key_generate();
+        ctx.initialize_conn(ADDRESS, "fse_test", true);
+        let documents = (0..100)
+            .map(|i| {
+                Data::new(
+                    ctx.encrypt(&(i % 10).to_string()).unwrap()[0].clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        ctx.get_conn().insert(documents, "test_timed").unwrap();
+
+        let (results, timing) = ctx
+            .search_timed(&"3".to_string(), "test_timed", true)
+            .unwrap();
+        assert_eq!(results, vec!["3".to_string(); 10]);
+        let server = timing.server.unwrap();
+        assert!(server <= timing.round_trip);
+        assert_eq!(timing.network(), Some(timing.round_trip - server));
+        ctx.get_conn().drop_collection("test_timed");
+    }
+
+    #[test]
+    fn test_collection_id() {
+        use fse::{
+            db::{new_run_id, CollectionId},
+            params::{PfseParams, SchemeParams},
+            FSEType,
+        };
+
+        let params = SchemeParams::Pfse(PfseParams::new(0.25, 1.0, 0.1));
+        let id = CollectionId::new(&FSEType::LpfseIhbe, "order number")
+            .params(Some(&params))
+            .run("r1");
+        let name = id.name();
+        assert!(name.starts_with("fse.lpfse_ihbe.order-number."));
+        assert!(name.ends_with(".r1"));
+        assert!(name.starts_with(&CollectionId::scheme_prefix(&id.scheme)));
+        assert!(name.starts_with(&id.run_prefix()));
+        assert_eq!(CollectionId::parse(&name), Some(id.clone()));
+        assert_eq!(id.to_string(), name);
+
+        // The parameters and the run tell the collections apart.
+        let other = SchemeParams::Pfse(PfseParams::new(0.5, 1.0, 0.1));
+        assert_ne!(id.clone().params(Some(&other)).name(), name);
+        assert_ne!(id.clone().params(None).name(), name);
+        assert_ne!(id.clone().run(&new_run_id()).name(), name);
+        assert_eq!(id.clone().params(Some(&params)).name(), name);
+
+        assert_eq!(CollectionId::parse("loads"), None);
+        assert_eq!(CollectionId::parse("fse.aes.a.none.default"), None);
+        assert!(CollectionId::is_component(&new_run_id()));
+        assert!(!CollectionId::is_component("a.b"));
+        assert!(!CollectionId::is_component(""));
+    }
+
+    #[test]
+    fn test_split() {
+        use fse::fse::{exponential, BaseCrypto, PartitionFrequencySmoothing};
+        use fse::native::ContextNative;
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+        use fse::split::{SplitEncryption, ID_LEN};
+
+        // The tags are the ciphertexts of the scheme, and the payloads are randomized.
+        let mut ctx = ContextNative::new(false);
+        ctx.key_generate();
+        let message = "alice".to_string();
+        let lhs = ctx.encrypt_split(&message).unwrap();
+        let rhs = ctx.encrypt_split(&message).unwrap();
+        assert_eq!(lhs[0].tag, ctx.encrypt(&message).unwrap()[0]);
+        assert_eq!(lhs[0].tag, rhs[0].tag);
+        assert_ne!(lhs[0].payload, rhs[0].payload);
+        assert_ne!(lhs[0].id, rhs[0].id);
+        assert_eq!(lhs[0].id.len(), ID_LEN);
+        assert_eq!(
+            ctx.decrypt_payload(&lhs[0].payload).unwrap(),
+            message.as_bytes()
+        );
+
+        // A payload only opens under the key of its context.
+        let mut other = ContextNative::<String>::new(false);
+        other.key_generate();
+        assert!(other.decrypt_payload(&lhs[0].payload).is_err());
+        assert!(ctx.decrypt_payload(&lhs[0].payload[..4]).is_err());
+
+        // Every smoothed ciphertext gets a payload of its own message, and every dummy one of its own bytes.
+        let messages = (0..100)
+            .map(|e| format!("m{}", e * e % 7))
+            .collect::<Vec<_>>();
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1)).unwrap();
+        ctx.partition(&messages, exponential).unwrap();
+        ctx.transform();
+        let ciphertexts = ctx.smooth();
+        let pairs = ctx.split(ciphertexts.clone()).unwrap();
+        assert_eq!(pairs.len(), ciphertexts.len());
+        let mut dummies = 0;
+        for pair in pairs.iter() {
+            let message = match ctx.decrypt_checked(&pair.tag) {
+                Ok(message) => message,
+                Err(_) => {
+                    dummies += 1;
+                    pair.tag.clone()
+                }
+            };
+            assert_eq!(ctx.decrypt_payload(&pair.payload).unwrap(), message);
+        }
+        assert!(dummies < pairs.len());
+    }
+
+    #[test]
+    fn test_column_tokens() {
+        use fse::fse::{exponential, BaseCrypto, PartitionFrequencySmoothing};
+        use fse::keys::generate_key;
+        use fse::native::ContextNative;
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+
+        // Two columns under the same key share no token of the same value, but the same column always gets the same.
+        let key = generate_key();
+        let message = "alice".to_string();
+        let tokens = |column: &str| {
+            let mut ctx = ContextNative::new(false);
+            ctx.set_column_key(&key, column).unwrap();
+            ctx.search_tokens(&message).unwrap()
+        };
+        let (name, alias) = (tokens("name"), tokens("alias"));
+        assert!(name.iter().all(|e| !alias.contains(e)));
+        assert_eq!(name, tokens("name"));
+        let mut ctx = ContextNative::new(false);
+        ctx.set_key(&key);
+        assert!(name
+            .iter()
+            .all(|e| !ctx.search_tokens(&message).unwrap().contains(e)));
+
+        // The same holds for the copies of a smoothed value.
+        let messages = (0..100)
+            .map(|e| format!("m{}", e * e % 7))
+            .collect::<Vec<_>>();
+        let tokens = |column: &str| {
+            let mut ctx = ContextPFSE::default();
+            ctx.set_column_key(&key, column).unwrap();
+            ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1)).unwrap();
+            ctx.partition(&messages, exponential).unwrap();
+            ctx.transform();
+            ctx.search_tokens(&messages[0]).unwrap()
+        };
+        let (name, alias) = (tokens("name"), tokens("alias"));
+        assert!(!name.is_empty());
+        assert!(name.iter().all(|e| !alias.contains(e)));
+    }
+
+    #[test]
+    fn test_db_split() {
+        use fse::fse::BaseCrypto;
+        use fse::native::ContextNative;
+        use fse::split::{SplitEncryption, SplitStore};
+
+        let mut ctx = ContextNative::new(false);
+        ctx.key_generate();
+        let store = SplitStore::connect(
+            (ADDRESS, "fse_test_index"),
+            (ADDRESS, "fse_test_payloads"),
+            true,
+        )
+        .unwrap();
+        let pairs = (0..100)
+            .flat_map(|i| ctx.encrypt_split(&(i % 10).to_string()).unwrap())
+            .collect::<Vec<_>>();
+        store.insert(&pairs, "test_split").unwrap();
+
+        let outcome = ctx
+            .search_split(&"3".to_string(), &store, "test_split")
+            .unwrap();
+        assert_eq!(outcome.results, vec!["3".to_string(); 10]);
+        assert_eq!(outcome.recall, 1.0);
+
+        // The payload server alone cannot answer the query.
+        store.get_payloads().drop_collection("test_split");
+        assert!(ctx
+            .search_split(&"3".to_string(), &store, "test_split")
+            .is_err());
+        store.drop_collection("test_split");
+    }
+
+    #[test]
+    #[cfg(feature = "db-sled")]
+    fn test_sled_backend() {
+        use fse::fse::{
+            exponential, BaseCrypto, PartitionFrequencySmoothing, SearchOutcome,
+        };
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+        use fse::storage::{SledBackend, Storage, StorageSearch};
+
+        let messages = (0..1000)
+            .map(|e| format!("m{}", e * e % 17))
+            .collect::<Vec<_>>();
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1)).unwrap();
+        ctx.partition(&messages, exponential).unwrap();
+        ctx.transform();
+        let ciphertexts = ctx.smooth();
+
+        let storage = SledBackend::temporary().unwrap();
+        storage.insert(&ciphertexts, "test_sled").unwrap();
+        assert!(storage.size("test_sled").unwrap() > 0);
+        for message in ["m0", "m1", "m4"] {
+            let message = message.to_string();
+            let SearchOutcome { results, recall } =
+                ctx.search_in(&message, &storage, "test_sled").unwrap();
+            // Smoothing may store more copies of a message than it occurs.
+            let expected = messages.iter().filter(|e| **e == message).count();
+            assert!(results.len() >= expected);
+            assert!(results.iter().all(|e| *e == message));
+            assert_eq!(recall, 1.0);
+        }
+
+        storage.drop_collection("test_sled").unwrap();
+        assert_eq!(storage.size("test_sled").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_db_bandwidth() {
+        use fse::db::{Connector, Data};
+        use mongodb::bson::doc;
+
+        let conn = Connector::<Data>::new(ADDRESS, "fse_test_bandwidth", true)
+            .unwrap();
+        let documents = (0..10u8)
+            .map(|i| Data::new(vec![i; 32]))
+            .collect::<Vec<_>>();
+        conn.insert(documents, "test_bandwidth").unwrap();
+        let inserted = conn.get_bandwidth();
+        assert_eq!(inserted.insert_batches, 1);
+        assert!(inserted.insert_sent >= 10 * 32);
+        assert_eq!(inserted.search_sent, 0);
+
+        let filter = doc! { "data": fse::db::to_binary(vec![3u8; 32]) };
+        let found = conn
+            .search(filter, "test_bandwidth")
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        let searched = conn.get_bandwidth().since(&inserted);
+        assert_eq!(searched.searches, 1);
+        assert!(searched.search_sent >= 32);
+        assert!(searched.search_received >= 32);
+        assert_eq!(searched.insert_batches, 0);
+    }
+
+    #[test]
+    fn test_db_frequency_histogram() {
+        use fse::attack::ServerView;
+        use fse::db::{Connector, Data};
+
+        let conn = Connector::<Data>::new(ADDRESS, "fse_test_histogram", true)
+            .unwrap();
+        let documents = (0..4u8)
+            .flat_map(|i| vec![Data::new(vec![i; 8]); i as usize + 1])
+            .collect::<Vec<_>>();
+        conn.insert(documents, "test_histogram").unwrap();
+
+        let histogram = conn.frequency_histogram("test_histogram").unwrap();
+        assert_eq!(
+            histogram,
+            (0..4u8)
+                .rev()
+                .map(|i| (vec![i; 8], i as usize + 1))
+                .collect::<Vec<_>>()
+        );
+        let view = ServerView::observe(&conn, "test_histogram").unwrap();
+        let grouped =
+            ServerView::observe_histogram(&conn, "test_histogram").unwrap();
+        assert_eq!(view.get_histogram(), grouped.get_histogram());
+        assert_eq!(grouped.distance(view.get_ciphertexts()), 0.0);
+    }
+
+    #[test]
+    fn test_db_insert_smoothed() {
+        use fse::db::{to_binary, Connector, Data, LOADS_COLLECTION};
+        use mongodb::bson::doc;
+
+        const DB: &str = "fse_test_insert_smoothed";
+        const COLLECTION: &str = "test_loads";
+
+        let conn = Connector::<Data>::new(ADDRESS, DB, true).unwrap();
+        let documents =
+            (0..4u8).map(|i| Data::new(vec![i; 8])).collect::<Vec<_>>();
+        let count =
+            || conn.search(Default::default(), COLLECTION).unwrap().count();
+        conn.insert_smoothed("load", documents.clone(), COLLECTION, false)
+            .unwrap();
+        assert!(conn
+            .insert_smoothed("load", documents.clone(), COLLECTION, false)
+            .is_err());
+        assert_eq!(count(), 4);
+
+        // A loader that crashed after the record and half of the documents leaves the record pending; inserting the
+        // load again stores the missing documents only.
+        let client = mongodb::sync::Client::with_uri_str(ADDRESS).unwrap();
+        client
+            .database(DB)
+            .collection::<mongodb::bson::Document>(LOADS_COLLECTION)
+            .update_one(
+                doc! { "load_id": "load", "collection": COLLECTION },
+                doc! { "$set": { "pending": true } },
+                None,
+            )
+            .unwrap();
+        let stored = documents[2..]
+            .iter()
+            .map(|e| to_binary(&e.data))
+            .collect::<Vec<_>>();
+        conn.delete(doc! { "data": { "$in": stored } }, COLLECTION)
+            .unwrap();
+        conn.insert_smoothed("load", documents.clone(), COLLECTION, false)
+            .unwrap();
+        assert_eq!(count(), 4);
+        assert!(conn.loads(COLLECTION).unwrap().iter().all(|e| !e.pending));
+
+        conn.insert_smoothed("load", documents, COLLECTION, true)
+            .unwrap();
+        assert_eq!(count(), 8);
+    }
+
+    #[test]
+    fn test_db_export() {
+        use fse::db::{
+            Connector, Data, ExportManifest, PaddingPolicy, PADDING_FIELD,
+        };
+        use mongodb::bson::Document;
+
+        let conn =
+            Connector::<Data>::new(ADDRESS, "fse_test_export", true).unwrap();
+        let documents = (0..16u64)
+            .map(|i| Data::with_seq(vec![i as u8; 8], i))
+            .collect::<Vec<_>>();
+        conn.set_padding_policy(Some(PaddingPolicy::Uniform { max: 256 }))
+            .unwrap();
+        conn.insert_smoothed("load", documents.clone(), "test_export", false)
+            .unwrap();
+        conn.set_padding_policy(None).unwrap();
+
+        let path = std::env::temp_dir().join("fse_test_export.ndjson.gz");
+        let path = path.to_str().unwrap();
+        let manifest = conn.export("test_export", path).unwrap();
+        assert_eq!(manifest.count, 16);
+        assert_eq!(manifest.loads.len(), 1);
+        // Importing twice keeps the `_id`s and does not double the collection.
+        assert_eq!(conn.import(path, "test_import").unwrap(), 16);
+        assert_eq!(conn.import(path, "test_import").unwrap(), 16);
+        let mut imported = conn
+            .search(Default::default(), "test_import")
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        imported.sort_by_key(|data| data.seq);
+        assert_eq!(imported, documents);
+
+        // The `_id`s and the padding are imported as they were exported, and so is the load.
+        let client = mongodb::sync::Client::with_uri_str(ADDRESS).unwrap();
+        let padding = |collection: &str| {
+            client
+                .database("fse_test_export")
+                .collection::<Document>(collection)
+                .find(None, None)
+                .unwrap()
+                .map(|e| {
+                    let e = e.unwrap();
+                    (
+                        e.get_str("_id").unwrap().to_string(),
+                        e.get_binary_generic(PADDING_FIELD).unwrap().clone(),
+                    )
+                })
+                .collect::<std::collections::BTreeMap<_, _>>()
+        };
+        assert_eq!(padding("test_export"), padding("test_import"));
+        assert_eq!(conn.loads("test_import").unwrap().len(), 1);
+        assert!(conn
+            .insert_smoothed("load", documents, "test_import", false)
+            .is_err());
+
+        // A manifest that does not match the archive rejects the import.
+        let mut tampered = manifest;
+        tampered.count += 1;
+        std::fs::write(
+            ExportManifest::path_of(path),
+            serde_json::to_vec(&tampered).unwrap(),
+        )
+        .unwrap();
+        assert!(conn.import(path, "test_import").is_err());
+    }
+
+    #[test]
+    fn test_db_drop_prefix() {
+        use fse::{
+            db::{CollectionId, Connector, Data},
+            FSEType,
+        };
+
+        let conn =
+            Connector::<Data>::new(ADDRESS, "fse_test_prefix", true).unwrap();
+        let names = [FSEType::Dte, FSEType::Pfse]
+            .iter()
+            .map(|e| CollectionId::new(e, "column").run("prefix").name())
+            .collect::<Vec<_>>();
+        for name in names.iter() {
+            conn.insert(vec![Data::new(vec![0u8])], name).unwrap();
+        }
+
+        let prefix = CollectionId::scheme_prefix(&FSEType::Pfse);
+        assert_eq!(conn.collections(&prefix).unwrap(), vec![names[1].clone()]);
+        assert_eq!(conn.drop_prefix(&prefix).unwrap(), vec![names[1].clone()]);
+        assert_eq!(
+            conn.collections(&CollectionId::all_prefix()).unwrap(),
+            vec![names[0].clone()]
+        );
+    }
+
+    #[test]
+    fn test_db_result_policy() {
+        use fse::db::Data;
+        use fse::fse::{BaseCrypto, ResultPolicy};
+        use fse::native::ContextNative;
+
+        const COLLECTION: &str = "result_policy_collection";
+
+        let mut ctx = ContextNative::<String>::new(false);
+        ctx.key_generate();
+        ctx.initialize_conn(ADDRESS, DB_NAME, false);
+        ctx.get_conn().drop_collection(COLLECTION);
+        let message = "a".to_string();
+        let ciphertext = ctx.encrypt(&message).unwrap().remove(0);
+        let documents = vec![Data::new(ciphertext); 3];
+        ctx.get_conn().insert(documents, COLLECTION).unwrap();
+
+        let search = |ctx: &mut ContextNative<String>, policy| {
+            ctx.search_with_policy(&message, COLLECTION, policy)
+                .unwrap()
+        };
+        assert_eq!(
+            search(&mut ctx, ResultPolicy::Raw),
+            vec![(message.clone(), 1); 3]
+        );
+        assert_eq!(
+            search(&mut ctx, ResultPolicy::Dedup),
+            vec![(message.clone(), 1)]
+        );
+        assert_eq!(
+            search(&mut ctx, ResultPolicy::DedupWithCounts),
+            vec![(message.clone(), 3)]
+        );
+        ctx.get_conn().drop_collection(COLLECTION);
+    }
+
+    #[test]
+    fn test_db_search_limit() {
+        use fse::db::Data;
+        use fse::fse::BaseCrypto;
+        use fse::native::ContextNative;
+
+        const COLLECTION: &str = "search_limit_collection";
+
+        let mut ctx = ContextNative::<String>::new(false);
+        ctx.key_generate();
+        ctx.initialize_conn(ADDRESS, DB_NAME, false);
+        ctx.get_conn().drop_collection(COLLECTION);
+        let message = "a".to_string();
+        let ciphertext = ctx.encrypt(&message).unwrap().remove(0);
+        let documents = vec![Data::new(ciphertext); 10];
+        ctx.get_conn().insert(documents, COLLECTION).unwrap();
+
+        let limited = ctx.search_limit(&message, COLLECTION, 3).unwrap();
+        assert_eq!(limited.results, vec![message.clone(); 3]);
+        assert!(limited.truncated);
+        let limited = ctx.search_limit(&message, COLLECTION, 20).unwrap();
+        assert_eq!(limited.results, vec![message.clone(); 10]);
+        assert!(!limited.truncated);
+        assert!(ctx.search_limit(&message, COLLECTION, 0).is_err());
+        ctx.get_conn().drop_collection(COLLECTION);
+    }
+
+    #[test]
+    fn test_db_search_sample() {
+        use fse::db::Data;
+        use fse::fse::BaseCrypto;
+        use fse::native::ContextNative;
+
+        const COLLECTION: &str = "search_sample_collection";
+
+        let mut ctx = ContextNative::<String>::new(false);
+        ctx.key_generate();
+        ctx.initialize_conn(ADDRESS, DB_NAME, false);
+        ctx.get_conn().drop_collection(COLLECTION);
+        let mut documents = Vec::new();
+        for (message, count) in [("a", 10), ("b", 5)] {
+            let ciphertext =
+                ctx.encrypt(&message.to_string()).unwrap().remove(0);
+            documents.extend(vec![Data::new(ciphertext); count]);
+        }
+        ctx.get_conn().insert(documents, COLLECTION).unwrap();
+
+        let message = "a".to_string();
+        let sample = ctx.search_sample(&message, COLLECTION, 3).unwrap();
+        assert_eq!(sample, vec![message.clone(); 3]);
+        let sample = ctx.search_sample(&message, COLLECTION, 20).unwrap();
+        assert_eq!(sample, vec![message.clone(); 10]);
+        assert!(ctx.search_sample(&message, COLLECTION, 0).is_none());
+        ctx.get_conn().drop_collection(COLLECTION);
+    }
+
+    #[test]
+    fn test_db_backup_local_state() {
+        use fse::fse::{backup_collection, BaseCrypto, LocalState};
+        use fse::native::ContextNative;
+
+        const COLLECTION: &str = "backup_collection";
+
+        let mut ctx = ContextNative::<String>::new(true);
+        ctx.key_generate();
+        ctx.initialize_conn(ADDRESS, DB_NAME, false);
+        ctx.encrypt(&"a".to_string()).unwrap();
+        ctx.backup_local_state(COLLECTION).unwrap();
+        // A second backup replaces the first one.
+        ctx.encrypt(&"b".to_string()).unwrap();
+        ctx.backup_local_state(COLLECTION).unwrap();
+
+        let mut recovered = ContextNative::<String>::new(true);
+        recovered.set_key(ctx.get_key());
+        recovered
+            .recover_local_state(ctx.get_conn(), COLLECTION)
+            .unwrap();
+        assert_eq!(recovered.export_state(), ctx.export_state());
+
+        let backup = backup_collection(COLLECTION);
+        assert_eq!(
+            ctx.get_conn().collections(&backup).unwrap(),
+            vec![backup.clone()]
+        );
+
+        // The backup is bound to its collection and cannot be recovered as that of another one.
+        let other = backup_collection("other_collection");
+        ctx.get_conn().rename_collection(&backup, &other).unwrap();
+        assert!(recovered
+            .recover_local_state(ctx.get_conn(), "other_collection")
+            .is_err());
+        ctx.get_conn().drop_collection(&other);
+    }
+
     #[test]
     fn test_wre() {
-        use rand_core::OsRng;
-        use fse::util::read_csv_exact;
+        use fse::util::{read_csv_exact, sample_or_cycle};
         use fse::{fse::BaseCrypto, wre::ContextWRE};
+        use rand_core::{OsRng, RngCore};
 
-        let mut vec =
-            read_csv_exact("./data/test.csv", "order_number").unwrap();
-        vec.shuffle(&mut OsRng);
-        let messages = &vec[..100];
+        let vec = read_csv_exact("./data/test.csv", "order_number").unwrap();
+        let messages = &sample_or_cycle(&vec, 100, OsRng.next_u64());
 
         let mut ctx = ContextWRE::new(10);
         ctx.key_generate();
-        ctx.initialize(messages, ADDRESS, DB_NAME, true);
+        ctx.initialize(messages, ADDRESS, DB_NAME, true).unwrap();
 
         let ciphertexts = messages
             .iter()
             .map(|message| ctx.encrypt(message).unwrap())
             .collect::<Vec<_>>();
     }
+
+    #[test]
+    fn test_enrollment() {
+        use fse::enrollment::Enrollment;
+        use fse::fse::{exponential, BaseCrypto};
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+        use fse::util::build_histogram;
+
+        let messages = (0..100)
+            .flat_map(|i| vec![i.to_string(); i % 10 + 1])
+            .collect::<Vec<_>>();
+        let histogram = build_histogram(&messages);
+        let params = PfseParams::new(0.25, 1.0, 0.05);
+        let enrollment =
+            Enrollment::pfse(&histogram, &params, exponential).unwrap();
+        assert_eq!(enrollment.inner().get_message_num(), messages.len());
+
+        // The data owner seals the enrollment it imported.
+        let imported =
+            Enrollment::import(ContextPFSE::default(), &enrollment.export())
+                .unwrap();
+        assert_eq!(
+            imported.inner().get_local_table(),
+            enrollment.inner().get_local_table()
+        );
+        assert!(Enrollment::import(
+            ContextPFSE::<String>::default(),
+            &[0u8; 3]
+        )
+        .is_err());
+
+        let mut ctx = enrollment.seal(&[7u8; 32]);
+        let ciphertext = ctx.encrypt(&messages[0]).unwrap().remove(0);
+        let plaintext = ctx.decrypt(&ciphertext).unwrap();
+        assert_eq!(String::from_utf8(plaintext).unwrap(), messages[0]);
+        let mut imported = imported.seal(&[7u8; 32]);
+        assert_eq!(imported.encrypt(&messages[0]), ctx.encrypt(&messages[0]));
+    }
+
+    #[test]
+    fn test_local_state() {
+        use fse::fse::{exponential, BaseCrypto, LocalState};
+        use fse::lpfse::{ContextLPFSE, EncoderIHBE, HomophoneEncoder};
+        use fse::{
+            fse::PartitionFrequencySmoothing, params::PfseParams,
+            pfse::ContextPFSE,
+        };
+
+        let messages = (0..100)
+            .flat_map(|i| vec![i.to_string(); i % 10 + 1])
+            .collect::<Vec<_>>();
+
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 0.05)).unwrap();
+        ctx.partition(&messages, exponential).unwrap();
+        ctx.transform();
+
+        let mut recovered = ContextPFSE::<String>::default();
+        recovered.set_key(ctx.get_key());
+        recovered.import_state(&ctx.export_state()).unwrap();
+        assert_eq!(recovered.get_local_table(), ctx.get_local_table());
+        assert_eq!(recovered.encrypt(&messages[0]), ctx.encrypt(&messages[0]));
+        assert!(recovered.import_state(&[0u8; 3]).is_err());
+
+        let mut ctx = ContextLPFSE::new(1e-2, Box::new(EncoderIHBE::new()));
+        ctx.key_generate();
+        ctx.initialize(&messages, "", "", false).unwrap();
+
+        let mut recovered =
+            ContextLPFSE::new(1.0, Box::new(EncoderIHBE::<String>::new()));
+        recovered.set_key(ctx.get_key());
+        recovered.import_state(&ctx.export_state()).unwrap();
+        assert_eq!(
+            recovered.get_encoder().local_table(),
+            ctx.get_encoder().local_table()
+        );
+        let ciphertext = recovered.encrypt(&messages[0]).unwrap().remove(0);
+        assert_eq!(ctx.decrypt(&ciphertext).unwrap(), messages[0].as_bytes());
+    }
+
+    #[test]
+    fn test_dataset_fingerprint() {
+        use fse::error::FseError;
+        use fse::fse::{
+            exponential, BaseCrypto, DatasetFingerprint, LocalState,
+            PartitionFrequencySmoothing,
+        };
+        use fse::lpfse::{ContextLPFSE, EncoderBHE};
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+
+        let messages = (0..100)
+            .flat_map(|i| vec![i.to_string(); i % 10 + 1])
+            .collect::<Vec<_>>();
+        let build = |messages: &[String], key: &[u8]| {
+            let mut ctx = ContextPFSE::default();
+            ctx.set_key(key);
+            ctx.set_params(&PfseParams::new(0.25, 1.0, 0.05)).unwrap();
+            ctx.partition(messages, exponential).unwrap();
+            ctx
+        };
+
+        let uninitialized = ContextPFSE::<String>::default();
+        let err = uninitialized.fingerprint().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<FseError>(),
+            Some(&FseError::NotInitialized)
+        );
+
+        let key = [1u8; 32];
+        let ctx = build(&messages, &key);
+        let mut shuffled = messages.clone();
+        shuffled.reverse();
+        assert_eq!(
+            ctx.fingerprint().unwrap(),
+            build(&shuffled, &key).fingerprint().unwrap()
+        );
+        assert_ne!(
+            ctx.fingerprint().unwrap(),
+            build(&messages[1..], &key).fingerprint().unwrap()
+        );
+        assert_ne!(
+            ctx.fingerprint().unwrap(),
+            build(&messages, &[2u8; 32]).fingerprint().unwrap()
+        );
+
+        let mut recovered = ContextPFSE::<String>::default();
+        recovered.set_key(&key);
+        recovered.import_state(&ctx.export_state()).unwrap();
+        assert_eq!(
+            recovered.fingerprint().unwrap(),
+            ctx.fingerprint().unwrap()
+        );
+
+        let mut ctx = ContextLPFSE::new(1e-2, Box::new(EncoderBHE::new()));
+        ctx.set_key(&key);
+        ctx.initialize(&messages, "", "", false).unwrap();
+        assert_eq!(
+            ctx.fingerprint().unwrap(),
+            build(&messages, &key).fingerprint().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_params_deserialize() {
+        use fse::params::{LpfseParams, PfseParams, SchemeParams, WreParams};
+
+        let parse = |s: &str| serde_json::from_str::<SchemeParams>(s);
+        assert_eq!(
+            parse(r#"{"lambda": 0.25, "scale": 1.0, "advantage": 0.05}"#)
+                .unwrap(),
+            PfseParams::new(0.25, 1.0, 0.05).into()
+        );
+        assert_eq!(
+            parse(r#"{"advantage": 0.01}"#).unwrap(),
+            LpfseParams::new(0.01).into()
+        );
+        assert_eq!(
+            parse(r#"{"lambda": 10}"#).unwrap(),
+            WreParams::new(10).into()
+        );
+        assert!(parse(r#"{"lambda": 0.25, "advantage": 0.05}"#).is_err());
+        assert!(parse(r#"{"advantage": 2.0}"#).unwrap().lpfse().is_err());
+    }
+
+    #[test]
+    fn test_streaming() {
+        use fse::fse::BaseCrypto;
+        use fse::params::StreamingParams;
+        use fse::streaming::ContextStreaming;
+        use std::collections::HashSet;
+
+        let params = StreamingParams::new(100, 4, 10, 256, 4);
+        let mut ctx = ContextStreaming::<String>::from_params(&params).unwrap();
+        ctx.key_generate();
+
+        // Half of the stream is a single message.
+        let stream = (0..1000)
+            .map(|i| match i % 2 {
+                0 => "heavy".to_string(),
+                _ => (i % 50).to_string(),
+            })
+            .collect::<Vec<_>>();
+        let ciphertexts = stream
+            .iter()
+            .map(|message| ctx.encrypt(message).unwrap().remove(0))
+            .collect::<Vec<_>>();
+        assert_eq!(ctx.get_epoch(), 9);
+        assert_eq!(ctx.get_salts(&"heavy".to_string(), 0), Some(1));
+        assert!(ctx.get_salts(&"heavy".to_string(), 9).unwrap() >= 5);
+        assert_eq!(ctx.get_salts(&"1".to_string(), 9), Some(1));
+
+        let (plaintext, epoch) =
+            ctx.decrypt_with_epoch(ciphertexts.last().unwrap()).unwrap();
+        assert_eq!(plaintext, stream.last().unwrap().as_bytes());
+        assert_eq!(epoch, 9);
+
+        // The tokens of the last epoch cover every ciphertext of the heavy hitter in that epoch.
+        let tokens = ctx
+            .search_tokens_in(&"heavy".to_string(), 9..10)
+            .unwrap()
+            .into_iter()
+            .collect::<HashSet<_>>();
+        assert!(ciphertexts[900..]
+            .iter()
+            .zip(stream[900..].iter())
+            .filter(|(_, message)| message.as_str() == "heavy")
+            .all(|(ciphertext, _)| tokens.contains(ciphertext)));
+        assert_eq!(
+            ctx.search_tokens(&"heavy".to_string()).unwrap().len(),
+            (0..10)
+                .map(|epoch| ctx
+                    .get_salts(&"heavy".to_string(), epoch)
+                    .unwrap())
+                .sum::<u64>() as usize
+        );
+        assert!(StreamingParams::new(100, 0, 10, 256, 4).validate().is_err());
+    }
+
+    #[test]
+    fn test_advantage_bound() {
+        use fse::fse::{exponential, BaseCrypto, PartitionFrequencySmoothing};
+        use fse::lpfse::{ContextLPFSE, EncoderBHE, EncoderIHBE};
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+        use fse::security::{advantage_bound, SchemeState};
+
+        let messages = (0..100)
+            .flat_map(|i| vec![i.to_string(); i % 10 + 1])
+            .collect::<Vec<_>>();
+
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 0.05)).unwrap();
+        assert!(ctx.scheme_state().is_none());
+        ctx.partition(&messages, exponential).unwrap();
+        ctx.transform();
+        let bound = advantage_bound(&ctx.scheme_state().unwrap());
+        assert!(bound > 0.0 && bound <= 1.0);
+
+        let advantage = 0.01;
+        let mut ctx = ContextLPFSE::new(advantage, Box::new(EncoderBHE::new()));
+        assert!(ctx.scheme_state().is_none());
+        ctx.initialize(&messages, "", "", false).unwrap();
+        assert!(advantage_bound(&ctx.scheme_state().unwrap()) <= advantage);
+
+        let mut ctx =
+            ContextLPFSE::new(advantage, Box::new(EncoderIHBE::new()));
+        ctx.initialize(&messages, "", "", false).unwrap();
+        let bound = advantage_bound(&ctx.scheme_state().unwrap());
+        assert!(bound > 0.0 && bound <= 1.0);
+
+        let empty = SchemeState::Ihbe {
+            message_num: 10,
+            intervals: vec![],
+        };
+        assert_eq!(advantage_bound(&empty), 1.0);
+    }
+
+    #[test]
+    fn test_transform_stats() {
+        use fse::fse::{
+            BaseCrypto, PartitionFrequencySmoothing, PartitionStats,
+        };
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+        use std::collections::HashMap;
+
+        // A constant partition function with n * f(1) <= 1 puts every message into a single partition with k' = 0.05.
+        fn flat(_: f64, _: usize) -> f64 {
+            0.05
+        }
+        fn zero(_: f64, _: usize) -> f64 {
+            0.0
+        }
+
+        let histogram = HashMap::from([
+            ("a".to_string(), 6usize),
+            ("b".to_string(), 3),
+            ("c".to_string(), 1),
+        ]);
+
+        // baseline = 6 / 10, so the advantage is 0.5 * 0.6 = 0.3 and n_1 = ceil((36 + 9 + 1) / (10 * 0.3)) = 16.
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(1.0, 1.0, 0.5)).unwrap();
+        ctx.partition_histogram(&histogram, flat).unwrap();
+        let stats = ctx.transform();
+        assert_eq!(
+            stats.partitions,
+            vec![PartitionStats {
+                index: 0,
+                target: 16,
+                real: 3,
+                dummies: 13,
+                overflow: 0,
+                ciphertext_cnt: 20,
+                skipped: false,
+            }]
+        );
+        assert_eq!(stats.dummy_num(), 13);
+        assert_eq!(stats.dummy_ciphertext_num(), 260);
+        assert_eq!(ctx.smooth().len(), 3 * 20 + 260);
+
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(1.0, 1.0, 0.5)).unwrap();
+        ctx.partition_histogram(&histogram, zero).unwrap();
+        let stats = ctx.transform();
+        assert!(stats.partitions[0].skipped);
+        assert_eq!(stats.dummy_num(), 0);
+    }
+
+    #[test]
+    fn test_journal() {
+        use fse::error::FseError;
+        use fse::fse::{BaseCrypto, PartitionFrequencySmoothing, Replicated};
+        use fse::native::ContextNative;
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+        use std::collections::HashMap;
+
+        fn flat(_: f64, _: usize) -> f64 {
+            0.05
+        }
+
+        // Bulk mutations are shipped as snapshots.
+        let mut primary = ContextPFSE::default();
+        primary.enable_journal();
+        primary.key_generate();
+        primary.set_params(&PfseParams::new(1.0, 1.0, 0.5)).unwrap();
+        primary
+            .partition_histogram(
+                &HashMap::from([("a".to_string(), 6), ("b".to_string(), 3)]),
+                flat,
+            )
+            .unwrap();
+        primary.transform();
+        let mut standby = ContextPFSE::default();
+        standby.set_key(primary.get_key());
+        standby
+            .apply_journal(primary.get_journal().get_entries())
+            .unwrap();
+        assert_eq!(standby.get_local_table(), primary.get_local_table());
+        let message = "a".to_string();
+        assert_eq!(
+            standby.search_tokens(&message),
+            primary.search_tokens(&message)
+        );
+
+        // RND ships every counter it advances; replaying is idempotent.
+        let mut primary = ContextNative::new(true);
+        primary.enable_journal();
+        primary.key_generate();
+        let mut standby = ContextNative::new(true);
+        standby.set_key(primary.get_key());
+        primary.encrypt(&message).unwrap();
+        standby
+            .apply_journal(primary.get_journal().get_entries())
+            .unwrap();
+        primary.encrypt(&message).unwrap();
+        primary.encrypt(&"b".to_string()).unwrap();
+        standby
+            .apply_journal(primary.get_journal().get_entries())
+            .unwrap();
+        assert_eq!(standby.get_journal().last_seq(), Some(2));
+        assert_eq!(
+            standby.search_tokens(&message),
+            primary.search_tokens(&message)
+        );
+
+        // A missing update is detected.
+        let mut lagging = ContextNative::<String>::new(true);
+        let err = lagging
+            .apply_journal(primary.get_journal().entries_since(1))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<FseError>(),
+            Some(&FseError::JournalGap {
+                expected: 0,
+                found: 1
+            })
+        );
+
+        // After compaction a fresh standby catches up from the snapshot alone.
+        primary.compact_journal();
+        assert_eq!(primary.get_journal().get_entries().len(), 1);
+        lagging.set_key(primary.get_key());
+        lagging
+            .apply_journal(primary.get_journal().get_entries())
+            .unwrap();
+        assert_eq!(
+            lagging.search_tokens(&message),
+            primary.search_tokens(&message)
+        );
+        assert!(standby
+            .apply_journal(primary.get_journal().get_entries())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_save_incremental() {
+        use fse::fse::{BaseCrypto, Replicated};
+        use fse::native::ContextNative;
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join("fse_test_incremental.state");
+        let path = path.to_str().unwrap();
+        let messages = (0..64).map(|e| format!("m{}", e)).collect::<Vec<_>>();
+
+        let mut ctx = ContextNative::new(true);
+        ctx.key_generate();
+        for message in messages.iter() {
+            ctx.encrypt(message).unwrap();
+        }
+        // The first save writes the whole state, the later ones only the new nonces.
+        let full = ctx.save_incremental(path).unwrap();
+        assert_eq!(std::fs::metadata(path).unwrap().len() as usize, full);
+        assert_eq!(ctx.save_incremental(path).unwrap(), 0);
+        ctx.encrypt(&messages[0]).unwrap();
+        let appended = ctx.save_incremental(path).unwrap();
+        assert!(appended > 0 && appended < full / 8);
+
+        let mut restored = ContextNative::<String>::new(true);
+        restored.set_key(ctx.get_key());
+        restored.load_incremental(path).unwrap();
+        assert_eq!(
+            restored.search_tokens(&messages[0]),
+            ctx.search_tokens(&messages[0])
+        );
+
+        // A torn append is ignored, and the restored context keeps appending to the same file.
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(&[7, 0, 0])
+            .unwrap();
+        let mut torn = ContextNative::<String>::new(true);
+        torn.set_key(ctx.get_key());
+        torn.load_incremental(path).unwrap();
+        assert_eq!(
+            torn.search_tokens(&messages[0]),
+            ctx.search_tokens(&messages[0])
+        );
+        restored.encrypt(&messages[1]).unwrap();
+        assert!(restored.save_incremental(path).unwrap() < full / 8);
+
+        // Once the appended entries outgrow the snapshot, the file is compacted, i.e., rewritten as a whole.
+        let mut compacted = false;
+        for message in messages.iter().cycle().take(256) {
+            restored.encrypt(message).unwrap();
+            let written = restored.save_incremental(path).unwrap();
+            compacted |=
+                written == std::fs::metadata(path).unwrap().len() as usize;
+        }
+        assert!(compacted);
+        let mut reloaded = ContextNative::<String>::new(true);
+        reloaded.set_key(restored.get_key());
+        reloaded.load_incremental(path).unwrap();
+        for message in messages.iter() {
+            assert_eq!(
+                reloaded.search_tokens(message),
+                restored.search_tokens(message)
+            );
+        }
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    #[cfg(feature = "debug-crypto")]
+    fn test_identity_cipher() {
+        use fse::cipher::IdentityCipher;
+        use fse::fse::{BaseCrypto, PartitionFrequencySmoothing};
+        use fse::native::ContextNative;
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+        use std::collections::HashMap;
+
+        fn flat(_: f64, _: usize) -> f64 {
+            0.05
+        }
+
+        // The "ciphertexts" start with the message followed by `|partition|index`.
+        let mut ctx = ContextPFSE::default();
+        ctx.set_cipher(Box::new(IdentityCipher));
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(1.0, 1.0, 0.5)).unwrap();
+        ctx.partition_histogram(&HashMap::from([("a".to_string(), 2)]), flat)
+            .unwrap();
+        ctx.transform();
+        let ciphertexts = ctx.encrypt(&"a".to_string()).unwrap();
+        assert!(ciphertexts.iter().all(|e| e.starts_with(b"a|")));
+        assert_eq!(ctx.decrypt(&ciphertexts[0]).unwrap(), b"a");
+
+        // Distinct nonces still give distinct ciphertexts.
+        let mut ctx = ContextNative::new(true);
+        ctx.set_cipher(Box::new(IdentityCipher));
+        ctx.key_generate();
+        let message = "m".to_string();
+        let lhs = ctx.encrypt(&message).unwrap().remove(0);
+        let rhs = ctx.encrypt(&message).unwrap().remove(0);
+        assert!(lhs.starts_with(b"m") && rhs.starts_with(b"m"));
+        assert_ne!(lhs, rhs);
+    }
+
+    #[test]
+    #[cfg(feature = "arrow")]
+    fn test_columnar() {
+        use arrow_array::{Int64Array, StringArray};
+        use fse::columnar::{partition_column, MessageColumn};
+        use fse::fse::{BaseCrypto, PartitionFrequencySmoothing};
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+        use std::collections::HashMap;
+
+        fn flat(_: f64, _: usize) -> f64 {
+            0.05
+        }
+
+        let column =
+            StringArray::from(vec![Some("a"), None, Some("b"), Some("a")]);
+        assert_eq!(
+            column.histogram(),
+            HashMap::from([("a".to_string(), 2), ("b".to_string(), 1)])
+        );
+
+        let column = Int64Array::from(vec![Some(-1), Some(7), None, Some(7)]);
+        assert_eq!(column.histogram(), HashMap::from([(-1, 1), (7, 2)]));
+
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(1.0, 1.0, 0.5)).unwrap();
+        partition_column(&mut ctx, &column, flat).unwrap();
+        ctx.transform();
+        let ciphertexts = ctx.encrypt(&7).unwrap();
+        assert_eq!(ctx.decrypt(&ciphertexts[0]).unwrap(), 7i64.to_le_bytes());
+
+        let results = Int64Array::from_messages(vec![7, 7]);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results.value(1), 7);
+    }
+
+    #[test]
+    fn test_bhe_phases() {
+        use fse::error::FseError;
+        use fse::lpfse::{BhePhase, EncoderBHE, HomophoneEncoder};
+        use fse::util::SizeAllocated;
+
+        let messages = (0..1000)
+            .map(|i| format!("{}", i * i % 13))
+            .collect::<Vec<_>>();
+        let mut encoder = EncoderBHE::<String>::new();
+        assert_eq!(encoder.phase(), BhePhase::Counting);
+        assert!(encoder.encode(&messages[0]).is_none());
+        assert!(encoder.seal().is_err());
+
+        // Two passes: count in batches, then encode.
+        for batch in messages.chunks(300) {
+            encoder.count(batch).unwrap();
+        }
+        encoder.finish_counting(0.05).unwrap();
+        assert_eq!(encoder.phase(), BhePhase::Ready);
+        assert!(encoder.count(&messages).is_err());
+        let tokens = encoder.encode_all(&messages[0]).unwrap();
+        let encoded = encoder.encode(&messages[0]).unwrap();
+        assert!(tokens.contains(&encoded));
+
+        // Sealing drops the recorded homophones but keeps encoding.
+        let state = encoder.export_state();
+        let size = encoder.size_allocated();
+        encoder.seal().unwrap();
+        assert!(encoder.is_sealed());
+        assert!(encoder.size_allocated() < size);
+        assert_eq!(encoder.export_state(), state);
+        assert!(tokens.contains(&encoder.encode(&messages[0]).unwrap()));
+
+        let table = encoder.local_table();
+        assert!(encoder.initialize(&messages[..10], 0.05).is_err());
+        assert_eq!(encoder.local_table(), table);
+        let err = encoder.count(&messages).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FseError>(),
+            Some(FseError::InvalidPhase { .. })
+        ));
+    }
+
+    #[test]
+    fn test_envelope() {
+        use fse::envelope::{read_header, Portable, KDF_ITERATIONS};
+        use fse::error::FseError;
+        use fse::fse::{BaseCrypto, PartitionFrequencySmoothing};
+        use fse::native::ContextNative;
+        use fse::params::{PfseParams, SchemeParams};
+        use fse::pfse::ContextPFSE;
+        use std::collections::HashMap;
+
+        fn flat(_: f64, _: usize) -> f64 {
+            0.05
+        }
+
+        let mut ctx = ContextPFSE::default();
+        let params = PfseParams::new(1.0, 1.0, 0.5);
+        ctx.key_generate();
+        ctx.set_params(&params).unwrap();
+        ctx.partition_histogram(
+            &HashMap::from([("a".to_string(), 6), ("b".to_string(), 3)]),
+            flat,
+        )
+        .unwrap();
+        ctx.transform();
+
+        let path = std::env::temp_dir()
+            .join(format!("fse_envelope_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        ctx.export(path, "passphrase").unwrap();
+        let envelope = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let header = read_header(&envelope).unwrap();
+        assert_eq!(header.scheme, "pfse");
+        assert!(matches!(header.params, Some(SchemeParams::Pfse(_))));
+        assert_eq!(header.params, ctx.scheme_params());
+
+        let mut imported = ContextPFSE::<String>::default();
+        let err = imported
+            .import_envelope(&envelope, "wrong passphrase")
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FseError>(),
+            Some(FseError::InvalidEnvelope(_))
+        ));
+        let mut tampered = envelope.clone();
+        tampered[20] ^= 1;
+        assert!(imported.import_envelope(&tampered, "passphrase").is_err());
+        assert!(imported.get_key().is_empty());
+
+        // The iteration count is checked before any key is derived from it.
+        let stored = (KDF_ITERATIONS as u64).to_le_bytes();
+        let offset = envelope
+            .windows(stored.len())
+            .position(|e| e == stored)
+            .unwrap();
+        for iterations in [u32::MAX as u64, 1] {
+            let mut crafted = envelope.clone();
+            crafted[offset..offset + stored.len()]
+                .copy_from_slice(&iterations.to_le_bytes());
+            assert!(imported.import_envelope(&crafted, "passphrase").is_err());
+        }
+
+        imported.import_envelope(&envelope, "passphrase").unwrap();
+        assert_eq!(imported.get_key(), ctx.get_key());
+        assert_eq!(imported.get_local_table(), ctx.get_local_table());
+        let message = "a".to_string();
+        assert_eq!(
+            imported.search_tokens(&message),
+            ctx.search_tokens(&message)
+        );
+
+        // An envelope only fits a context of the same scheme.
+        let mut native = ContextNative::<String>::new(false);
+        assert!(native.import_envelope(&envelope, "passphrase").is_err());
+    }
+
+    #[test]
+    fn test_envelope_contexts() {
+        use fse::bucketed::ContextBucketed;
+        use fse::envelope::{read_header, Portable};
+        use fse::fse::{exponential, BaseCrypto};
+        use fse::hierarchical::{ContextHierarchical, Hierarchy};
+        use fse::params::{PfseParams, StreamingParams, WreParams};
+        use fse::plain::ContextPlain;
+        use fse::streaming::ContextStreaming;
+        use fse::util::build_histogram;
+        use fse::wre::ContextWRE;
+
+        let dataset = (0..500)
+            .map(|i| format!("94{}{:02}", i % 3, (i * i) % 37))
+            .collect::<Vec<_>>();
+        let message = dataset[0].clone();
+
+        let mut wre = ContextWRE::from_params(&WreParams {
+            lambda: 20,
+            allocation: Default::default(),
+        })
+        .unwrap();
+        wre.key_generate();
+        wre.initialize_histogram(&build_histogram(&dataset))
+            .unwrap();
+        let envelope = wre.export_envelope("passphrase").unwrap();
+        assert_eq!(read_header(&envelope).unwrap().scheme, "wre");
+        let mut imported = ContextWRE::new(1);
+        imported.import_envelope(&envelope, "passphrase").unwrap();
+        assert_eq!(imported.get_salts(&message), wre.get_salts(&message));
+        assert_eq!(
+            imported.search_tokens(&message),
+            wre.search_tokens(&message)
+        );
+
+        let mut plain = ContextPlain::<String>::new();
+        plain.key_generate();
+        let envelope = plain.export_envelope("passphrase").unwrap();
+        let mut imported = ContextPlain::<String>::new();
+        imported.import_envelope(&envelope, "passphrase").unwrap();
+        assert_eq!(imported.get_key(), plain.get_key());
+
+        let params = StreamingParams::new(100, 4, 10, 256, 4);
+        let mut streaming = ContextStreaming::from_params(&params).unwrap();
+        streaming.key_generate();
+        for message in dataset.iter() {
+            streaming.encrypt(message).unwrap();
+        }
+        let envelope = streaming.export_envelope("passphrase").unwrap();
+        let mut imported = ContextStreaming::from_params(
+            &StreamingParams::new(10, 1, 2, 16, 1),
+        )
+        .unwrap();
+        imported.import_envelope(&envelope, "passphrase").unwrap();
+        assert_eq!(imported.get_params(), streaming.get_params());
+        assert_eq!(imported.get_epoch(), streaming.get_epoch());
+        assert_eq!(imported.estimate(&message), streaming.estimate(&message));
+        assert_eq!(
+            imported.search_tokens(&message),
+            streaming.search_tokens(&message)
+        );
+
+        let params = PfseParams::new(0.25, 1.0, 0.1);
+        let mut bucketed = ContextBucketed::new(16, &params).unwrap();
+        bucketed.key_generate();
+        bucketed.partition(&dataset, exponential).unwrap();
+        bucketed.transform();
+        let envelope = bucketed.export_envelope("passphrase").unwrap();
+        let mut imported = ContextBucketed::new(16, &params).unwrap();
+        imported.import_envelope(&envelope, "passphrase").unwrap();
+        assert_eq!(imported.bucket(&message), bucketed.bucket(&message));
+        assert_eq!(
+            imported.search_tokens(&message),
+            bucketed.search_tokens(&message)
+        );
+
+        let zip = Hierarchy::Prefix(vec![3, 5]);
+        let mut hierarchical =
+            ContextHierarchical::new(zip.clone(), &params).unwrap();
+        hierarchical.key_generate();
+        hierarchical.partition(&dataset, exponential).unwrap();
+        hierarchical.transform();
+        let envelope = hierarchical.export_envelope("passphrase").unwrap();
+        let mut imported = ContextHierarchical::new(zip, &params).unwrap();
+        assert!(imported.import_envelope(&envelope, "wrong").is_err());
+        imported.import_envelope(&envelope, "passphrase").unwrap();
+        assert_eq!(
+            imported.search_tokens("941*"),
+            hierarchical.search_tokens("941*")
+        );
+        let mut shallow =
+            ContextHierarchical::new(Hierarchy::Prefix(vec![3]), &params)
+                .unwrap();
+        assert!(shallow.import_envelope(&envelope, "passphrase").is_err());
+    }
+
+    #[test]
+    fn test_smooth_into() {
+        use fse::fse::{exponential, BaseCrypto, PartitionFrequencySmoothing};
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+
+        let messages = (0..2000)
+            .map(|i| format!("{}", i * i % 97))
+            .collect::<Vec<_>>();
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 0.05)).unwrap();
+        ctx.partition(&messages, exponential).unwrap();
+        ctx.transform();
+        assert!(ctx.get_partitions().len() > 1);
+
+        let mut streamed = Vec::new();
+        ctx.smooth_into(|ciphertext| {
+            streamed.push(ciphertext);
+            Ok(())
+        })
+        .unwrap();
+        let mut smoothed = ctx.smooth();
+        streamed.sort();
+        smoothed.sort();
+        assert_eq!(streamed, smoothed);
+
+        // The sink may abort the stream.
+        let mut seen = 0;
+        let res = ctx.smooth_into(|_| {
+            seen += 1;
+            match seen {
+                10 => Err("full".into()),
+                _ => Ok(()),
+            }
+        });
+        assert!(res.is_err());
+        assert_eq!(seen, 10);
+    }
+
+    #[test]
+    fn test_smoothed_records() {
+        use std::collections::HashMap;
+
+        use fse::fse::{exponential, BaseCrypto, PartitionFrequencySmoothing};
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+
+        let messages = (0..1000)
+            .map(|e| format!("m{}", e * e % 17))
+            .collect::<Vec<_>>();
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1)).unwrap();
+        ctx.partition(&messages, exponential).unwrap();
+        ctx.transform();
+        let mut smoothed = HashMap::<Vec<u8>, usize>::new();
+        for ciphertext in ctx.smooth() {
+            *smoothed.entry(ciphertext).or_default() += 1;
+        }
+
+        for message in ["m0", "m1", "m4"] {
+            let message = message.to_string();
+            let tokens = ctx.encrypt(&message).unwrap();
+            let count = ctx.smoothed_count(&message).unwrap();
+            let mut records = HashMap::<Vec<u8>, usize>::new();
+            for ciphertext in ctx.smoothed_records(&message).unwrap() {
+                *records.entry(ciphertext).or_default() += 1;
+            }
+            let expected = tokens
+                .iter()
+                .map(|e| (e.clone(), smoothed[e]))
+                .collect::<HashMap<_, _>>();
+            assert_eq!(records, expected);
+            assert_eq!(records.values().sum::<usize>(), count);
+        }
+        assert!(ctx.smoothed_records(&"absent".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_vectors() {
+        use fse::testvectors::TestVectors;
+
+        let published: TestVectors =
+            serde_json::from_str(include_str!("../testvectors/vectors.json"))
+                .unwrap();
+        published.validate().unwrap();
+        // The published vectors must be regenerated whenever the fixed inputs change.
+        assert_eq!(TestVectors::generate().unwrap(), published);
+
+        let mut tampered = published;
+        tampered.bhe[0].bands.insert("a".to_string(), 1);
+        assert!(tampered.validate().is_err());
+    }
+
+    #[test]
+    fn test_key_commitment() {
+        use fse::cipher::{AesGcmCipher, Cipher, CommittingCipher, ZERO_NONCE};
+        use fse::error::FseError;
+        use fse::fse::BaseCrypto;
+        use fse::native::ContextNative;
+
+        let mut ctx = ContextNative::<String>::new(false);
+        ctx.key_generate();
+        let ciphertext = ctx.encrypt(&"m".to_string()).unwrap().remove(0);
+        assert_eq!(ctx.decrypt_checked(&ciphertext).unwrap(), b"m");
+
+        // A ciphertext of another key is rejected with a typed error instead of decrypting into garbage.
+        let mut other = ContextNative::<String>::new(false);
+        other.key_generate();
+        let err = other.decrypt_checked(&ciphertext).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<FseError>(),
+            Some(&FseError::KeyMismatch)
+        );
+        assert!(other.decrypt(&ciphertext).is_none());
+
+        // A tampered ciphertext under the right key is a decryption failure.
+        let mut tampered = ciphertext.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        let err = ctx.decrypt_checked(&tampered).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<FseError>(),
+            Some(&FseError::DecryptionFailed)
+        );
+
+        // The commitment does not depend on the plaintext, so the encryption stays deterministic.
+        let cipher = CommittingCipher::new(Box::new(AesGcmCipher));
+        let key = cipher.key_generate();
+        let lhs = cipher.encrypt(&key, &ZERO_NONCE, b"a").unwrap();
+        let rhs = cipher.encrypt(&key, &ZERO_NONCE, b"a").unwrap();
+        assert_eq!(lhs, rhs);
+        assert!(lhs.starts_with(&CommittingCipher::commitment(&key)));
+
+        // The associated data is authenticated, and empty associated data is the plain encryption.
+        let aad = cipher.encrypt_with_aad(&key, &ZERO_NONCE, b"a", b"x");
+        let aad = aad.unwrap();
+        assert_eq!(
+            cipher
+                .decrypt_with_aad(&key, &ZERO_NONCE, &aad, b"x")
+                .unwrap(),
+            b"a"
+        );
+        assert!(cipher
+            .decrypt_with_aad(&key, &ZERO_NONCE, &aad, b"y")
+            .is_none());
+        assert!(cipher.decrypt(&key, &ZERO_NONCE, &aad).is_none());
+        assert_eq!(
+            cipher
+                .encrypt_with_aad(&key, &ZERO_NONCE, b"a", b"")
+                .unwrap(),
+            lhs
+        );
+    }
+
+    #[test]
+    fn test_smooth_order() {
+        use fse::fse::{
+            exponential, BaseCrypto, InsertionOrder,
+            PartitionFrequencySmoothing, SmoothedLoad,
+        };
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+
+        let messages = (0..2000)
+            .map(|i| format!("{}", i * i % 97))
+            .collect::<Vec<_>>();
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 0.05)).unwrap();
+        ctx.partition(&messages, exponential).unwrap();
+        ctx.transform();
+
+        // The grouped output keeps equal ciphertexts together.
+        let grouped = ctx.smooth();
+        let runs = |ciphertexts: &[Vec<u8>]| {
+            ciphertexts.windows(2).filter(|e| e[0] != e[1]).count() + 1
+        };
+        let distinct = grouped
+            .iter()
+            .collect::<std::collections::HashSet<_>>()
+            .len();
+        assert_eq!(runs(&grouped), distinct);
+
+        let mut sorted = grouped.clone();
+        sorted.sort();
+        for order in [
+            InsertionOrder::Shuffle,
+            InsertionOrder::BatchedShuffle { size: 500 },
+        ] {
+            ctx.set_smooth_order(order);
+            let mut shuffled = ctx.smooth();
+            assert!(runs(&shuffled) > distinct);
+            shuffled.sort();
+            assert_eq!(shuffled, sorted);
+            // Each call shuffles anew, but the load stays the same.
+            let load = ctx.smooth_load();
+            assert_ne!(load.ciphertexts, ctx.smooth_load().ciphertexts);
+            assert_eq!(load.load_id, ctx.smooth_load().load_id);
+            assert_eq!(
+                load.load_id,
+                SmoothedLoad::new(grouped.clone()).load_id
+            );
+        }
+
+        // A batched shuffle only permutes the ciphertexts within each batch.
+        let mut batched = Vec::new();
+        ctx.smooth_ordered_into(
+            InsertionOrder::BatchedShuffle { size: 500 },
+            |ciphertext| {
+                batched.push(ciphertext);
+                Ok(())
+            },
+        )
+        .unwrap();
+        for (lhs, rhs) in batched.chunks(500).zip(grouped.chunks(500)) {
+            let (mut lhs, mut rhs) = (lhs.to_vec(), rhs.to_vec());
+            lhs.sort();
+            rhs.sort();
+            assert_eq!(lhs, rhs);
+        }
+    }
+
+    #[test]
+    fn test_advantage_schedule() {
+        use fse::fse::{exponential, BaseCrypto, PartitionFrequencySmoothing};
+        use fse::params::PfseParams;
+        use fse::pfse::{AdvantageSchedule, ContextPFSE};
+
+        let messages = (0..2000)
+            .map(|i| format!("{}", i * i % 97))
+            .collect::<Vec<_>>();
+        let dummies = |schedule: AdvantageSchedule| {
+            let mut ctx = ContextPFSE::default();
+            ctx.key_generate();
+            ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1)).unwrap();
+            ctx.set_advantage_schedule(schedule).unwrap();
+            ctx.partition(&messages, exponential).unwrap();
+            ctx.transform()
+                .partitions
+                .iter()
+                .map(|e| e.dummies)
+                .collect::<Vec<_>>()
+        };
+
+        let uniform = dummies(AdvantageSchedule::Uniform);
+        let head = dummies(AdvantageSchedule::PerPartition(vec![0.01]));
+        assert!(head[0] > uniform[0]);
+        assert_eq!(head[1..], uniform[1..]);
+        let function =
+            dummies(AdvantageSchedule::Function(|advantage, index| {
+                if index == 0 {
+                    0.01
+                } else {
+                    advantage
+                }
+            }));
+        assert_eq!(function, head);
+
+        let mut ctx = ContextPFSE::<String>::default();
+        assert!(ctx
+            .set_advantage_schedule(AdvantageSchedule::PerPartition(vec![
+                0.1, -1.0
+            ]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_ihbe_max_bits() {
+        use fse::envelope::Portable;
+        use fse::fse::BaseCrypto;
+        use fse::lpfse::{
+            ContextLPFSE, EncoderBHE, EncoderIHBE, HomophoneEncoder,
+        };
+        use fse::params::{LpfseParams, SchemeParams};
+        use fse::security::advantage_bound;
+        use std::collections::HashMap;
+
+        // One message is a million times rarer than the other.
+        let histogram = HashMap::from([
+            ("head".to_string(), 1_000_000usize),
+            ("tail".to_string(), 1),
+        ]);
+
+        let mut encoder = EncoderIHBE::new();
+        encoder.initialize_histogram(&histogram, 1e-3).unwrap();
+        assert!(encoder.residual_advantage().is_none());
+        let ideal = encoder.get_interval(&"head".to_string()).unwrap().end;
+        assert!(ideal > 1 << 30);
+
+        let params = LpfseParams::new(1e-3).with_max_bits(12);
+        let mut ctx = ContextLPFSE::from_params(
+            &params,
+            Box::new(EncoderIHBE::<String>::new()),
+        )
+        .unwrap();
+        ctx.key_generate();
+        ctx.initialize_histogram(&histogram).unwrap();
+        let residual = ctx.get_encoder().residual_advantage().unwrap();
+        assert!(residual > 1e-3 && residual <= 1.0);
+        assert!(advantage_bound(&ctx.scheme_state().unwrap()) > 1e-3);
+        let tokens = ctx.get_encoder().encode_all(&"head".to_string()).unwrap();
+        assert!(tokens.len() <= 1 << 12);
+        assert_eq!(ctx.search_tokens(&"tail".to_string()).unwrap().len(), 1);
+        assert_eq!(ctx.scheme_params().unwrap(), SchemeParams::from(params));
+
+        assert!(LpfseParams::new(0.1).with_max_bits(0).validate().is_err());
+        assert!(ContextLPFSE::from_params(
+            &params,
+            Box::new(EncoderBHE::<String>::new())
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_degenerate_datasets() {
+        use fse::error::FseError;
+        use fse::fse::{exponential, BaseCrypto, PartitionFrequencySmoothing};
+        use fse::lpfse::{
+            ContextLPFSE, EncoderBHE, EncoderIHBE, HomophoneEncoder,
+        };
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+        use fse::security::{advantage_bound, SchemeState};
+        use fse::wre::ContextWRE;
+
+        let is_empty = |res: fse::Result<()>| {
+            res.unwrap_err().downcast_ref::<FseError>()
+                == Some(&FseError::EmptyDataset)
+        };
+        let single = vec!["a".to_string(); 100];
+        let degenerate = Some(SchemeState::Degenerate { message_num: 100 });
+
+        // PFSE keeps a single message as one ciphertext without dummies.
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1)).unwrap();
+        assert!(is_empty(ctx.partition(&[], exponential)));
+        ctx.partition(&single, exponential).unwrap();
+        assert_eq!(ctx.transform().dummy_num(), 0);
+        let ciphertexts = ctx.smooth();
+        assert_eq!(ciphertexts.len(), 100);
+        assert!(ciphertexts.iter().all(|e| *e == ciphertexts[0]));
+        assert_eq!(
+            ctx.encrypt(&single[0]).unwrap(),
+            vec![ciphertexts[0].clone()]
+        );
+        assert_eq!(ctx.decrypt(&ciphertexts[0]).unwrap(), b"a");
+        assert_eq!(ctx.scheme_state(), degenerate);
+        assert_eq!(advantage_bound(&degenerate.clone().unwrap()), 0.0);
+
+        // IHBE and BHE give it a single homophone.
+        let encoders: [Box<dyn HomophoneEncoder<String>>; 2] =
+            [Box::new(EncoderIHBE::new()), Box::new(EncoderBHE::new())];
+        for encoder in encoders {
+            let mut ctx = ContextLPFSE::new(0.01, encoder);
+            ctx.key_generate();
+            assert!(is_empty(ctx.initialize(&[], "", "", false)));
+            assert!(ctx.scheme_state().is_none());
+            ctx.initialize(&single, "", "", false).unwrap();
+            assert_eq!(ctx.search_tokens(&single[0]).unwrap().len(), 1);
+            let ciphertext = ctx.encrypt(&single[0]).unwrap().remove(0);
+            assert_eq!(ctx.decrypt(&ciphertext).unwrap(), b"a");
+            assert_eq!(ctx.scheme_state(), degenerate);
+        }
+        let mut encoder = EncoderBHE::<String>::new();
+        assert!(is_empty(encoder.finish_counting(0.01)));
+
+        // WRE always takes the same salt.
+        let mut ctx = ContextWRE::new(1);
+        ctx.key_generate();
+        assert!(is_empty(ctx.initialize(&[], "", "", false)));
+        ctx.initialize(&single, "", "", false).unwrap();
+        let ciphertext = ctx.encrypt(&single[0]).unwrap();
+        assert_eq!(ciphertext.len(), 1);
+        assert_eq!(ctx.encrypt(&single[0]).unwrap(), ciphertext);
+        assert!(ctx.encrypt(&"b".to_string()).is_none());
+    }
+
+    #[test]
+    fn test_progress() {
+        use std::sync::{Arc, Mutex};
+
+        use fse::fse::{exponential, BaseCrypto, PartitionFrequencySmoothing};
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+        use fse::progress::{Phase, Progress, ProgressEvent};
+
+        let events = Arc::new(Mutex::new(Vec::<ProgressEvent>::new()));
+        let recorder = events.clone();
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1)).unwrap();
+        ctx.set_progress(Progress::new(move |event: &ProgressEvent| {
+            recorder.lock().unwrap().push(*event)
+        }));
+
+        let dataset = (0..100)
+            .flat_map(|i| vec![i.to_string(); 1000 / (i + 1)])
+            .collect::<Vec<_>>();
+        ctx.partition(&dataset, exponential).unwrap();
+        ctx.transform();
+        let ciphertexts = ctx.smooth();
+
+        let events = events.lock().unwrap();
+        for phase in [Phase::Partition, Phase::Transform, Phase::Smooth] {
+            let phase_events = events
+                .iter()
+                .filter(|e| e.phase == phase)
+                .collect::<Vec<_>>();
+            assert!(phase_events.windows(2).all(|e| e[0].done <= e[1].done));
+            // Only the last event finishes the phase, and it has processed everything.
+            let last = phase_events.last().unwrap();
+            assert!(last.finished);
+            assert_eq!(phase_events.iter().filter(|e| e.finished).count(), 1);
+            assert_eq!(Some(last.done), last.total);
+            assert_eq!(last.eta(), Some(std::time::Duration::ZERO));
+        }
+        let smooth = events.iter().rfind(|e| e.phase == Phase::Smooth);
+        assert_eq!(smooth.unwrap().total, Some(ciphertexts.len() as u64));
+    }
+
+    #[test]
+    fn test_static_encoder() {
+        use fse::fse::{BaseCrypto, LocalState};
+        use fse::lpfse::{BoxedEncoder, ContextLPFSE, EncoderIHBE};
+        use fse::util::build_histogram;
+
+        let dataset = (0..20)
+            .flat_map(|i| vec![i.to_string(); 100 / (i + 1)])
+            .collect::<Vec<_>>();
+
+        // The encoder is dispatched statically.
+        let mut ctx: ContextLPFSE<String, EncoderIHBE<String>> =
+            ContextLPFSE::new(0.01, EncoderIHBE::new());
+        ctx.key_generate();
+        ctx.initialize_histogram(&build_histogram(&dataset))
+            .unwrap();
+        for message in dataset.iter() {
+            let ciphertext = ctx.encrypt(message).unwrap().remove(0);
+            assert_eq!(ctx.decrypt(&ciphertext).unwrap(), message.as_bytes());
+        }
+
+        // The boxed context reads the same state and finds the same tokens.
+        let encoder: BoxedEncoder<String> = Box::new(EncoderIHBE::new());
+        let mut boxed: ContextLPFSE<String> = ContextLPFSE::new(0.01, encoder);
+        boxed.set_key(ctx.get_key());
+        boxed.import_state(&ctx.export_state()).unwrap();
+        for message in dataset.iter().take(10) {
+            assert_eq!(
+                boxed.search_tokens(message),
+                ctx.search_tokens(message)
+            );
+        }
+    }
+
+    #[test]
+    fn test_domain() {
+        use std::collections::HashSet;
+
+        use fse::fse::{
+            exponential, import_domain, BaseCrypto, Domain,
+            PartitionFrequencySmoothing,
+        };
+        use fse::lpfse::{ContextLPFSE, EncoderIHBE};
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+        use fse::util::build_histogram;
+
+        let dataset = (0..50)
+            .flat_map(|i| vec![i.to_string(); 500 / (i + 1)])
+            .collect::<Vec<_>>();
+        let expected = dataset.iter().cloned().collect::<HashSet<_>>();
+
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1)).unwrap();
+        ctx.partition(&dataset, exponential).unwrap();
+        assert!(ctx.transform().dummy_num() > 0);
+        // The dummies are not searchable.
+        let domain = ctx.export_domain();
+        assert_eq!(domain.len(), expected.len());
+        assert_eq!(domain.into_iter().collect::<HashSet<_>>(), expected);
+        assert!(ctx.contains(&"0".to_string()));
+        assert!(!ctx.contains(&"50".to_string()));
+
+        let blob = ctx.export_domain_encrypted().unwrap();
+        let imported =
+            import_domain::<String>(ctx.get_cipher(), ctx.get_key(), &blob)
+                .unwrap();
+        assert_eq!(imported.into_iter().collect::<HashSet<_>>(), expected);
+        let mut other = ContextPFSE::<String>::default();
+        other.key_generate();
+        assert!(import_domain::<String>(
+            other.get_cipher(),
+            other.get_key(),
+            &blob
+        )
+        .is_err());
+
+        let mut ctx = ContextLPFSE::new(0.01, EncoderIHBE::new());
+        ctx.key_generate();
+        ctx.initialize_histogram(&build_histogram(&dataset))
+            .unwrap();
+        let domain = ctx.export_domain();
+        assert_eq!(domain.into_iter().collect::<HashSet<_>>(), expected);
+        assert!(ctx.contains(&"49".to_string()));
+        assert!(!ctx.contains(&"50".to_string()));
+    }
+
+    #[test]
+    fn test_bucketed() {
+        use std::collections::HashSet;
+
+        use fse::bucketed::{estimate_false_positive_rate, ContextBucketed};
+        use fse::fse::exponential;
+        use fse::params::PfseParams;
+
+        let dataset = (0..2000)
+            .map(|i| format!("user{}@example.com", i))
+            .collect::<Vec<_>>();
+        let params = PfseParams::new(0.25, 1.0, 0.1);
+        assert!(ContextBucketed::<String>::new(0, &params).is_err());
+
+        let mut ctx = ContextBucketed::new(64, &params).unwrap();
+        assert!(ctx.partition(&dataset, exponential).is_err());
+        ctx.key_generate();
+        let stats = ctx.partition(&dataset, exponential).unwrap();
+        assert_eq!(stats.distinct, 2000);
+        assert_eq!(stats.messages, 2000);
+        assert!(stats.occupied <= 64);
+        // About 31 other identifiers share the bucket of each one.
+        let estimate = estimate_false_positive_rate(2000, 64);
+        assert!((stats.false_positive_rate - estimate).abs() < 0.02);
+        assert!(stats.false_positive_rate > 0.9);
+
+        ctx.transform();
+        let ciphertexts = ctx.smooth();
+        let message = &dataset[0];
+        let bucket = ctx.bucket(message).unwrap();
+        let tokens = ctx
+            .search_tokens(message)
+            .unwrap()
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let matches = ciphertexts
+            .iter()
+            .filter(|c| tokens.contains(*c))
+            .collect::<Vec<_>>();
+        let expected = dataset
+            .iter()
+            .filter(|e| ctx.bucket(e) == Some(bucket))
+            .count();
+        // Smoothing may add copies, but every record of the bucket matches.
+        assert!(matches.len() >= expected);
+        assert!(matches
+            .iter()
+            .all(|c| ctx.decrypt_bucket(c) == Some(bucket)));
+
+        // Enough buckets make false positives rare.
+        let mut ctx = ContextBucketed::new(1 << 30, &params).unwrap();
+        ctx.key_generate();
+        let stats = ctx.partition(&dataset, exponential).unwrap();
+        assert!(stats.false_positive_rate < 0.01);
+    }
+
+    #[test]
+    fn test_hierarchical() {
+        use std::collections::HashSet;
+
+        use fse::fse::exponential;
+        use fse::hierarchical::{ContextHierarchical, Hierarchy};
+        use fse::params::PfseParams;
+
+        let ip = Hierarchy::Segments {
+            separator: '.',
+            depths: vec![1, 2, 3],
+        };
+        assert_eq!(ip.generalize("10.1.2.3", 1), "10.1");
+        assert_eq!(ip.level_of("10.1.*"), Some((1, "10.1".to_string())));
+        assert_eq!(ip.level_of("10.1.2.3"), None);
+
+        let params = PfseParams::new(0.25, 1.0, 0.1);
+        let zip = Hierarchy::Prefix(vec![3, 5]);
+        assert!(ContextHierarchical::new(
+            Hierarchy::Prefix(vec![5, 3]),
+            &params
+        )
+        .is_err());
+        let dataset = (0..1000)
+            .map(|i| format!("94{}{:02}", i % 3, (i * i) % 37))
+            .collect::<Vec<_>>();
+        let mut ctx = ContextHierarchical::new(zip, &params).unwrap();
+        assert!(ctx.leakage().is_none());
+        ctx.key_generate();
+        ctx.partition(&dataset, exponential).unwrap();
+        ctx.transform();
+        let ciphertexts = ctx.smooth();
+        assert_eq!(ciphertexts.len(), 2);
+
+        // A prefix query matches every record under the prefix at its level.
+        let (level, tokens) = ctx.search_tokens("941*").unwrap();
+        assert_eq!(level, 0);
+        let tokens = tokens.into_iter().collect::<HashSet<_>>();
+        let matches = ciphertexts[0]
+            .iter()
+            .filter(|c| tokens.contains(*c))
+            .collect::<Vec<_>>();
+        let expected = dataset.iter().filter(|e| e.starts_with("941")).count();
+        assert!(matches.len() >= expected);
+        assert!(matches
+            .iter()
+            .all(|c| ctx.decrypt(0, c) == Some("941".to_string())));
+        // The levels are keyed apart.
+        assert!(ciphertexts[1].iter().all(|c| !tokens.contains(c)));
+
+        let leakage = ctx.leakage().unwrap();
+        assert_eq!(leakage.levels[0].distinct, 3);
+        let sum = leakage.levels.iter().map(|e| e.advantage).sum::<f64>();
+        assert_eq!(leakage.combined, sum.min(1.0));
+    }
+
+    #[test]
+    fn test_token_arena() {
+        use fse::lpfse::{EncoderBHE, EncoderIHBE, HomophoneEncoder};
+        use fse::token::{TokenArena, TokenSet};
+
+        let dataset = (0..20)
+            .flat_map(|i| vec![i.to_string(); 200 / (i + 1)])
+            .collect::<Vec<_>>();
+        let mut ihbe = EncoderIHBE::new();
+        ihbe.initialize(&dataset, 0.01).unwrap();
+        let mut bhe = EncoderBHE::new();
+        bhe.initialize(&dataset, 0.01).unwrap();
+        let encoders: Vec<Box<dyn HomophoneEncoder<String>>> =
+            vec![Box::new(ihbe), Box::new(bhe)];
+
+        let mut arena = TokenArena::new();
+        for encoder in encoders.iter() {
+            for message in ["0", "7", "19"].map(String::from) {
+                arena.clear();
+                let expected = encoder.encode_all(&message).unwrap();
+                let len =
+                    encoder.encode_all_into(&message, &mut arena).unwrap();
+                assert_eq!(len, arena.len());
+                assert_eq!(arena.iter().count(), expected.len());
+                assert!(arena.iter().all(|token| expected.contains(token)));
+
+                let tokens = arena.freeze();
+                assert_eq!(TokenSet::from(&tokens), expected);
+                assert!(expected.iter().all(|token| tokens.contains(token)));
+                assert!(!tokens.contains(b"unknown"));
+
+                // A token shares the buffer of its set and compares by its bytes.
+                let token = tokens.get(0).unwrap();
+                assert_eq!(&*token.clone(), expected.iter().next().unwrap());
+            }
+            assert!(encoder
+                .encode_all_into(&"unknown".to_string(), &mut arena)
+                .is_none());
+        }
+
+        // The prefixes are interned once and survive clearing.
+        let mut arena = TokenArena::new();
+        let prefix = arena.intern(b"ab|");
+        assert_eq!(arena.intern(b"ab|"), prefix);
+        arena.push_prefixed(prefix, &[1]);
+        arena.push(b"ab|\x01");
+        arena.push(b"c");
+        assert_eq!(arena.get(0), Some(&b"ab|\x01"[..]));
+        assert_eq!(arena.freeze().len(), 2);
+        arena.clear();
+        assert!(arena.is_empty());
+        arena.push_prefixed(prefix, &[2]);
+        assert_eq!(arena.get(0), Some(&b"ab|\x02"[..]));
+    }
+
+    #[test]
+    fn test_partition_shape() {
+        use fse::fse::{
+            exponential, BaseCrypto, FromBytes, PartitionFrequencySmoothing,
+        };
+        use fse::params::{PartitionShape, PfseParams, SchemeParams};
+        use fse::pfse::ContextPFSE;
+
+        // A flat histogram of 40 messages, which the exponential rule keeps in few partitions.
+        let messages =
+            (0..4000).map(|i| format!("{}", i % 40)).collect::<Vec<_>>();
+        let build = |shape: PartitionShape| {
+            let mut ctx = ContextPFSE::default();
+            ctx.key_generate();
+            ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1).with_shape(shape))
+                .unwrap();
+            ctx.partition(&messages, exponential).unwrap();
+            ctx
+        };
+
+        let ctx = build(PartitionShape::EqualMass(8));
+        assert_eq!(ctx.get_partitions().len(), 8);
+        for partition in ctx.get_partitions() {
+            let count = partition.inner.iter().map(|e| e.1).sum::<usize>();
+            assert!(count.abs_diff(500) <= 1);
+        }
+
+        let mut ctx = build(PartitionShape::EqualWidth(6));
+        let widths = ctx
+            .get_partitions()
+            .iter()
+            .map(|e| e.inner.len())
+            .collect::<Vec<_>>();
+        assert_eq!(widths, vec![6, 6, 6, 6, 6, 6, 4]);
+        ctx.transform();
+        let message = "7".to_string();
+        let ciphertext = ctx.encrypt(&message).unwrap().remove(0);
+        assert_eq!(
+            String::from_bytes(&ctx.decrypt(&ciphertext).unwrap()),
+            message
+        );
+        assert!(ctx.search_tokens(&message).unwrap().contains(&ciphertext));
+
+        let parse = |s: &str| serde_json::from_str::<SchemeParams>(s);
+        assert_eq!(
+            parse(
+                r#"{"lambda": 0.25, "scale": 1.0, "advantage": 0.05,
+                    "shape": {"equal_width": 16}}"#
+            )
+            .unwrap(),
+            PfseParams::new(0.25, 1.0, 0.05)
+                .with_shape(PartitionShape::EqualWidth(16))
+                .into()
+        );
+        assert!(PfseParams::new(0.25, 1.0, 0.05)
+            .with_shape(PartitionShape::EqualMass(0))
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_partition_split() {
+        use std::collections::HashMap;
+
+        use fse::fse::{exponential, BaseCrypto, PartitionFrequencySmoothing};
+        use fse::params::{PartitionShape, PfseParams};
+        use fse::pfse::ContextPFSE;
+
+        // Two equal masses of 0.5 over {a: 6, b: 4}: the first partition ends with a at the frequency 0.6, so the
+        // excess 0.1 of the 10 messages, i.e., one occurrence of a, is split off into the second partition.
+        let histogram =
+            HashMap::from([("a".to_string(), 6), ("b".to_string(), 4)]);
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(
+            &PfseParams::new(0.25, 1.0, 0.1)
+                .with_shape(PartitionShape::EqualMass(2)),
+        )
+        .unwrap();
+        ctx.partition_histogram(&histogram, exponential).unwrap();
+        let partitions = ctx
+            .get_partitions()
+            .iter()
+            .map(|e| e.inner.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            partitions,
+            vec![
+                vec![("a".to_string(), 5)],
+                vec![("b".to_string(), 4), ("a".to_string(), 1)]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_token_limit() {
+        use fse::error::FseError;
+        use fse::fse::{
+            exponential, BaseCrypto, PartitionFrequencySmoothing, TokenLimit,
+            TokenOverflow,
+        };
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+
+        let messages = (0..2000)
+            .map(|i| format!("{}", i * i % 97))
+            .collect::<Vec<_>>();
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 0.01)).unwrap();
+        ctx.partition(&messages, exponential).unwrap();
+        ctx.transform();
+
+        let message = ctx
+            .get_local_table()
+            .keys()
+            .max_by_key(|message| ctx.token_count(message))
+            .cloned()
+            .unwrap();
+        let tokens = ctx.search_tokens(&message).unwrap();
+        assert!(tokens.len() > 1);
+        assert_eq!(ctx.token_count(&message), Some(tokens.len()));
+        let query = ctx.query_tokens(&message).unwrap();
+        assert_eq!((query.tokens.len(), query.recall()), (tokens.len(), 1.0));
+
+        let max = tokens.len() / 2;
+        ctx.set_token_limit(Some(TokenLimit::new(max, TokenOverflow::Reject)));
+        let err = ctx.query_tokens(&message).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<FseError>(),
+            Some(&FseError::TooManyTokens {
+                tokens: tokens.len(),
+                limit: max,
+            })
+        );
+
+        ctx.set_token_limit(Some(TokenLimit::new(max, TokenOverflow::Sample)));
+        let query = ctx.query_tokens(&message).unwrap();
+        assert_eq!((query.tokens.len(), query.total), (max, tokens.len()));
+        assert!(query.tokens.iter().all(|token| tokens.contains(token)));
+        assert_eq!(query.recall(), max as f64 / tokens.len() as f64);
+    }
+
+    #[test]
+    fn test_migration() {
+        use fse::fse::{exponential, BaseCrypto, PartitionFrequencySmoothing};
+        use fse::migration::plan_migration;
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+
+        let messages = (0..2000)
+            .map(|i| format!("{}", i * i % 97))
+            .collect::<Vec<_>>();
+        let key = ContextPFSE::<String>::default().get_cipher().key_generate();
+        let build = |lambda: f64, key: &[u8]| {
+            let mut ctx = ContextPFSE::default();
+            ctx.set_key(key);
+            ctx.set_params(&PfseParams::new(lambda, 1.0, 0.1)).unwrap();
+            ctx.partition(&messages, exponential).unwrap();
+            ctx.transform();
+            ctx
+        };
+        let documents = |ctx: &ContextPFSE<String>| {
+            ctx.get_local_table()
+                .values()
+                .flatten()
+                .map(|&(_, size, cnt)| size * cnt)
+                .sum::<usize>()
+        };
+
+        let old = build(0.25, &key);
+        let plan = plan_migration(&old, &old.clone(), 8).unwrap();
+        assert!(plan.batches.is_empty());
+        assert_eq!((plan.kept, plan.deleted), (documents(&old), 0));
+        assert_eq!(plan.old_advantage, plan.new_advantage);
+
+        let new = build(0.5, &key);
+        let plan = plan_migration(&old, &new, 8).unwrap();
+        assert!(!plan.batches.is_empty());
+        assert!(plan.batches.iter().all(|batch| batch.messages.len() <= 8));
+        assert_eq!(plan.kept + plan.deleted, documents(&old));
+        assert_eq!(plan.kept + plan.reencrypted, documents(&new));
+        assert_eq!(plan.batches.last().unwrap().advantage, plan.new_advantage);
+        assert!(plan.interim_advantage >= plan.old_advantage);
+        assert!(plan.interim_advantage >= plan.new_advantage);
+
+        // Nothing is shared under another key.
+        let other = build(0.25, &old.get_cipher().key_generate());
+        let plan = plan_migration(&old, &other, 8).unwrap();
+        assert_eq!((plan.kept, plan.deleted), (0, documents(&old)));
+        assert!(plan_migration(&old, &new, 0).is_err());
+        assert!(plan_migration(&old, &ContextPFSE::default(), 8).is_err());
+    }
+
+    #[test]
+    fn test_security_level() {
+        use fse::cipher::SecurityLevel;
+        use fse::fse::{BaseCrypto, PartitionFrequencySmoothing};
+        use fse::keys::{KeyProvider, LocalFileProvider};
+        use fse::native::ContextNative;
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+        use std::collections::HashMap;
+
+        let message = "m".to_string();
+        for (level, key_len) in
+            [(SecurityLevel::Aes128, 16), (SecurityLevel::Aes256, 32)]
+        {
+            assert_eq!(level.key_len(), key_len);
+            assert_eq!(level.cipher().key_len(), key_len);
+
+            for rnd in [false, true] {
+                let mut ctx = ContextNative::new(rnd);
+                ctx.set_security_level(level);
+                ctx.key_generate();
+                assert_eq!(ctx.get_key().len(), key_len);
+                let ciphertext = ctx.encrypt(&message).unwrap().remove(0);
+                // The randomized ciphertexts are only matched by the search tokens.
+                match rnd {
+                    true => assert!(ctx
+                        .search_tokens(&message)
+                        .unwrap()
+                        .contains(&ciphertext)),
+                    false => {
+                        assert_eq!(ctx.decrypt(&ciphertext).unwrap(), b"m")
+                    }
+                }
+            }
+
+            let mut ctx = ContextPFSE::default();
+            ctx.set_security_level(level);
+            ctx.key_generate();
+            ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1)).unwrap();
+            ctx.partition_histogram(
+                &HashMap::from([(message.clone(), 10)]),
+                fse::fse::exponential,
+            )
+            .unwrap();
+            ctx.transform();
+            let ciphertexts = ctx.smooth();
+            assert_eq!(ctx.decrypt(&ciphertexts[0]).unwrap(), b"m");
+        }
+
+        // The provider serves both levels from the same stored key.
+        let dir = std::env::temp_dir().join("fse_test_security_level");
+        let provider = LocalFileProvider::open(&dir).unwrap();
+        let key = provider.get_key("native").unwrap();
+        assert_eq!(provider.get_key_of_len("native", 16).unwrap(), key[..16]);
+        assert!(provider.get_key_of_len("native", 64).is_err());
+        let mut ctx = ContextNative::<String>::new(false);
+        ctx.set_security_level(SecurityLevel::Aes128);
+        ctx.key_from_provider(&provider, "native").unwrap();
+        assert_eq!(ctx.get_key(), &key[..16]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_factory() {
+        use fse::error::FseError;
+        use fse::factory::{build_context, feature, is_enabled};
+        use fse::params::{PfseParams, SchemeParams};
+        use fse::FSEType;
+
+        let dataset = ["a", "a", "a", "b", "c"]
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        let params = SchemeParams::from(PfseParams::new(0.25, 1.0, 0.1));
+        let mut ctx =
+            build_context(&FSEType::Pfse, &dataset, Some(&params)).unwrap();
+        let ciphertexts = ctx.encrypt(&dataset[0]).unwrap();
+        assert_eq!(
+            ctx.decrypt(&ciphertexts[0]),
+            Some(dataset[0].as_bytes().to_vec())
+        );
+        assert!(build_context(&FSEType::Plain, &dataset, None).is_ok());
+        assert!(build_context(&FSEType::Dte, &dataset, None).is_ok());
+
+        let err = build_context(&FSEType::Pfse, &dataset, None).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FseError>(),
+            Some(FseError::InvalidParams(_))
+        ));
+        assert_eq!(feature(&FSEType::LpfseBhe), Some("lpfse"));
+        assert_eq!(feature(&FSEType::Plain), None);
+        assert!(is_enabled(&FSEType::Wre));
+    }
+
+    #[test]
+    fn test_native_batch() {
+        use fse::fse::{BaseCrypto, LocalState};
+        use fse::native::ContextNative;
+
+        let messages = (0..100)
+            .map(|e| format!("m{}", e * e % 7))
+            .collect::<Vec<_>>();
+        let mut ctx = ContextNative::new(true);
+        ctx.key_generate();
+        let batch = ctx.encrypt_batch(&messages, 4).unwrap();
+        assert_eq!(batch.len(), messages.len());
+
+        // The nonces are regenerated from the counters, so a batch equals encrypting the messages one by one.
+        let mut sequential = ContextNative::new(true);
+        sequential.set_key(ctx.get_key());
+        for (message, ciphertext) in messages.iter().zip(batch.iter()) {
+            assert_eq!(&sequential.encrypt(message).unwrap()[0], ciphertext);
+        }
+        for (message, ciphertext) in messages.iter().zip(batch.iter()) {
+            let tokens = ctx.search_tokens(message).unwrap();
+            assert!(tokens.contains(ciphertext));
+            assert_eq!(Some(tokens.len()), ctx.token_count(message));
+        }
+
+        // The state holds a counter per message only.
+        let mut restored = ContextNative::<String>::new(true);
+        restored.set_key(ctx.get_key());
+        restored.import_state(&ctx.export_state()).unwrap();
+        assert_eq!(
+            restored.search_tokens(&messages[0]),
+            ctx.search_tokens(&messages[0])
+        );
+        assert!(ctx.export_state().len() < 8 * 16 * 3);
+    }
+
+    #[test]
+    fn test_state_leakage() {
+        use fse::fse::{
+            exponential, BaseCrypto, Domain, LocalState,
+            PartitionFrequencySmoothing,
+        };
+        use fse::native::ContextNative;
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+        use fse::wre::ContextWRE;
+
+        let messages = (0..100)
+            .map(|e| format!("m{}", e * e % 7))
+            .collect::<Vec<_>>();
+
+        // The local table of PFSE is the histogram of the dataset.
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1)).unwrap();
+        ctx.partition(&messages, exponential).unwrap();
+        ctx.transform();
+        let leakage = ctx.state_leakage();
+        let histogram = leakage.histogram.unwrap();
+        assert_eq!(histogram.len(), 4);
+        assert_eq!(histogram.iter().map(|e| e.1).sum::<f64>(), 100.0);
+        assert!(leakage.entropy > 1.0 && leakage.entropy < 2.0);
+
+        // A hardened table leaks the same counts, but no plaintext.
+        let mut plain = ContextNative::new(true);
+        let mut hardened = ContextNative::new(true);
+        hardened.key_generate();
+        plain.set_key(hardened.get_key());
+        hardened.set_hardened(true).unwrap();
+        for message in messages.iter() {
+            assert_eq!(plain.encrypt(message), hardened.encrypt(message));
+        }
+        assert!(hardened.set_hardened(false).is_err());
+        let (lhs, rhs) = (plain.state_leakage(), hardened.state_leakage());
+        assert!(lhs.reveals_plaintexts() && !rhs.reveals_plaintexts());
+        assert_eq!(lhs.counts, rhs.counts);
+        assert_eq!(lhs.entropy, rhs.entropy);
+        let state = hardened.export_state();
+        assert!(!state.windows(2).any(|e| e == b"m1"));
+        let mut restored = ContextNative::<String>::new(true);
+        restored.set_key(hardened.get_key());
+        restored.import_state(&state).unwrap();
+        assert!(restored.is_hardened());
+        assert_eq!(
+            restored.search_tokens(&messages[1]),
+            plain.search_tokens(&messages[1])
+        );
+
+        let mut ctx = ContextWRE::new(10);
+        ctx.key_generate();
+        ctx.set_hardened(true).unwrap();
+        ctx.initialize_histogram(&fse::util::build_histogram(&messages))
+            .unwrap();
+        assert!(ctx.export_domain().is_empty());
+        assert!(ctx.contains(&messages[0]));
+        let ciphertext = ctx.encrypt(&messages[0]).unwrap().remove(0);
+        assert!(ctx
+            .search_tokens(&messages[0])
+            .unwrap()
+            .contains(&ciphertext));
+        assert_eq!(ctx.decrypt(&ciphertext).unwrap(), messages[0].as_bytes());
+        let leakage = ctx.state_leakage();
+        assert!(!leakage.reveals_plaintexts());
+        assert_eq!(leakage.entries(), 4);
+    }
 }
@@ -0,0 +1,839 @@
+mod util_tests {
+    #[test]
+    fn test_zipf_mixture_auxiliary() {
+        use fse::util::{
+            build_histogram, build_histogram_vec, generate_synthetic_zipf,
+            total_variation, ZipfMixture,
+        };
+
+        let support = (0..200).map(|e| e.to_string()).collect::<Vec<_>>();
+        let dataset = generate_synthetic_zipf(&support, 1.2);
+        let histogram = build_histogram_vec(&build_histogram(&dataset));
+
+        let model = ZipfMixture::fit(&histogram, 2, 10);
+        let weight = model.components.iter().map(|e| e.0).sum::<f64>();
+        assert!((weight - 1.0).abs() < 1e-6);
+
+        for tv in [0.0, 0.05, 0.1, 0.2] {
+            let auxiliary = model.sample_auxiliary(&histogram, tv, 7);
+            let distance = total_variation(&histogram, &auxiliary);
+            assert!((distance - tv).abs() < 0.02, "{} vs {}", distance, tv);
+            assert_eq!(
+                auxiliary.iter().map(|e| e.1).sum::<usize>(),
+                dataset.len()
+            );
+            assert!(auxiliary.iter().all(|e| e.1 != 0));
+            assert_eq!(auxiliary, model.sample_auxiliary(&histogram, tv, 7));
+        }
+    }
+
+    #[test]
+    fn test_audit_log() {
+        use fse::audit::AuditLog;
+
+        // A directory of its own keeps parallel runs apart.
+        let dir = std::env::temp_dir().join(format!(
+            "fse_test_audit_log_{}_{}",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        let path = path.to_str().unwrap();
+        let key = b"audit key";
+
+        let mut audit = AuditLog::open(path, key).unwrap();
+        audit.record("column", b"secret_value", 10, 3).unwrap();
+        audit.record("column", b"another_value", 1, 0).unwrap();
+        let anchor = audit.head().clone();
+        drop(audit);
+        // Re-opening the log continues the chain.
+        let mut audit = AuditLog::open(path, key).unwrap();
+        audit.record("column", b"secret_value", 10, 3).unwrap();
+        let head = AuditLog::verify(path, key).unwrap();
+        assert_eq!(&head, audit.head());
+        assert_eq!(head.count, 3);
+        assert_eq!(
+            AuditLog::verify_anchored(path, key, &anchor).unwrap(),
+            head
+        );
+        // Without the key, the chain cannot be recomputed.
+        assert!(AuditLog::verify(path, b"another key").is_err());
+
+        let content = std::fs::read_to_string(path).unwrap();
+        assert!(!content.contains("secret_value"));
+
+        // Cutting the last entries off leaves a valid chain, which only the anchor tells apart.
+        let truncated = content.lines().take(1).collect::<Vec<_>>().join("\n");
+        std::fs::write(path, truncated + "\n").unwrap();
+        assert_eq!(AuditLog::verify(path, key).unwrap().count, 1);
+        assert!(AuditLog::verify_anchored(path, key, &anchor).is_err());
+
+        // Drop the second entry.
+        let tampered = content
+            .lines()
+            .enumerate()
+            .filter(|&(idx, _)| idx != 1)
+            .map(|(_, line)| format!("{}\n", line))
+            .collect::<String>();
+        std::fs::write(path, tampered).unwrap();
+        assert!(AuditLog::verify(path, key).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_smoothed_load_id() {
+        use fse::fse::SmoothedLoad;
+
+        let load = SmoothedLoad::new(vec![b"ab".to_vec(), b"c".to_vec()]);
+        let same = SmoothedLoad::new(vec![b"ab".to_vec(), b"c".to_vec()]);
+        // The order of the ciphertexts does not matter.
+        let reordered = SmoothedLoad::new(vec![b"c".to_vec(), b"ab".to_vec()]);
+        assert_eq!(load.load_id, reordered.load_id);
+        // The ciphertexts are length-prefixed, so re-splitting them yields another load.
+        let split = SmoothedLoad::new(vec![b"a".to_vec(), b"bc".to_vec()]);
+
+        assert_eq!(load.load_id, same.load_id);
+        assert_ne!(load.load_id, split.load_id);
+        assert_eq!(load.documents().len(), 2);
+    }
+
+    #[test]
+    fn test_data_binary() {
+        use fse::db::{to_binary, Data};
+        use mongodb::bson::{from_document, to_document};
+
+        let data = Data::new(vec![0, 1, 255, b':']);
+        let document = to_document(&data).unwrap();
+        assert_eq!(document.get("data"), Some(&to_binary(&data)));
+        assert_eq!(from_document::<Data>(document).unwrap(), data);
+
+        assert_eq!(Data::from_base64(&data.to_base64()).unwrap(), data);
+        assert!(Data::from_base64("not base64!").is_err());
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        use std::{io, time::Duration};
+
+        use fse::db::{is_transient, RetryPolicy};
+        use mongodb::error::{Error, ErrorKind};
+
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1000,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(5), Duration::from_millis(1000));
+        assert_eq!(policy.backoff(100), Duration::from_millis(1000));
+        assert_eq!(RetryPolicy::none().max_attempts, 1);
+
+        let io = Error::from(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(is_transient(&io));
+        let unsupported = Error::from(ErrorKind::SessionsNotSupported);
+        assert!(!is_transient(&unsupported));
+    }
+
+    #[test]
+    fn test_insertion_order() {
+        use std::collections::HashMap;
+
+        use fse::attack::OrderAttacker;
+        use fse::fse::{InsertionOrder, SmoothedLoad};
+
+        let items = (0..100).collect::<Vec<_>>();
+        let mut arranged = items.clone();
+        InsertionOrder::AsIs.arrange(&mut arranged);
+        assert_eq!(arranged, items);
+
+        InsertionOrder::BatchedShuffle { size: 10 }.arrange(&mut arranged);
+        for (batch, chunk) in arranged.chunks(10).enumerate() {
+            assert!(chunk.iter().all(|e| e / 10 == batch));
+        }
+
+        InsertionOrder::Shuffle.arrange(&mut arranged);
+        arranged.sort();
+        assert_eq!(arranged, items);
+
+        let load = SmoothedLoad::new(vec![b"a".to_vec(), b"b".to_vec()]);
+        let documents = load.ordered_documents(InsertionOrder::Shuffle);
+        let seqs = documents.iter().map(|e| e.seq).collect::<Vec<_>>();
+        assert_eq!(seqs, vec![Some(0), Some(1)]);
+
+        // A sorted input inserted as it is gives every ciphertext away.
+        let messages = vec!["a", "a", "b", "c", "c", "c"];
+        let correct = messages
+            .iter()
+            .map(|e| (*e, vec![e.to_uppercase().into_bytes()]))
+            .collect::<HashMap<_, _>>();
+        let observed = messages
+            .iter()
+            .map(|e| e.to_uppercase().into_bytes())
+            .collect::<Vec<_>>();
+        let mut attacker = OrderAttacker::new();
+        assert_eq!(attacker.attack(&correct, &messages, &observed), 1.0);
+        let reversed = observed.iter().rev().cloned().collect::<Vec<_>>();
+        assert!(attacker.attack(&correct, &messages, &reversed) < 0.5);
+    }
+
+    #[test]
+    fn test_preprocess() {
+        use fse::preprocess::{DateUnit, Preprocess, Transform};
+
+        assert_eq!(Transform::Prefix { len: 3 }.apply("Tokyo"), "Tok");
+        assert_eq!(Transform::Prefix { len: 3 }.apply("東京都"), "東京都");
+        let truncate = Transform::TruncateDigits { digits: 2 };
+        assert_eq!(truncate.apply("12345"), "12300");
+        assert_eq!(truncate.apply("-199"), "-100");
+        assert_eq!(truncate.apply("n/a"), "n/a");
+        let month = Transform::BucketDate {
+            unit: DateUnit::Month,
+        };
+        assert_eq!(month.apply("2023-04-17T10:00:00Z"), "2023-04");
+        assert_eq!(month.apply("17/04/2023"), "17/04/2023");
+        assert_eq!(
+            Transform::EmailDomain.apply("Alice@Example.COM"),
+            "example.com"
+        );
+        assert_eq!(Transform::Hash { len: 4 }.apply("a").len(), 4);
+
+        // A pipeline applies the transformations in order.
+        let pipeline = [
+            Transform::BucketDate {
+                unit: DateUnit::Year,
+            },
+            Transform::TruncateDigits { digits: 1 },
+        ];
+        assert_eq!(
+            pipeline.apply_all(&["2023-04-17".to_string()]),
+            vec!["2020".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_token_set() {
+        use fse::token::TokenSet;
+
+        let lhs =
+            TokenSet::from(vec![b"b".to_vec(), b"a".to_vec(), b"a".to_vec()]);
+        let rhs = [b"b".to_vec(), b"cc".to_vec()]
+            .into_iter()
+            .collect::<TokenSet>();
+        assert_eq!(lhs.len(), 2);
+        assert!(lhs.contains(b"a"));
+        assert_eq!(rhs.byte_len(), 3);
+
+        let union = lhs.union(&rhs);
+        assert_eq!(
+            union.iter().collect::<Vec<_>>(),
+            vec![&b"a"[..], b"b", b"cc"]
+        );
+        assert_eq!(lhs.intersection(&rhs), TokenSet::from(vec![b"b".to_vec()]));
+        assert_eq!(lhs.difference(&rhs), TokenSet::from(vec![b"a".to_vec()]));
+
+        let chunks = union.chunks(2).map(|e| e.len()).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![2, 1]);
+        assert_eq!(TokenSet::new().chunks(2).count(), 0);
+
+        let encoded = serde_json::to_string(&union).unwrap();
+        assert_eq!(serde_json::from_str::<TokenSet>(&encoded).unwrap(), union);
+    }
+
+    #[test]
+    fn test_spilled_histogram() {
+        use fse::util::{
+            build_histogram, build_histogram_vec, build_histogram_vec_spilled,
+        };
+
+        let items = (0..1000u32)
+            .map(|i| ((i * i) % 37).to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let mut expected = build_histogram_vec(&build_histogram(&items));
+        let mut spilled = build_histogram_vec_spilled(&items, 64).unwrap();
+        // Ties in the counts may come in any order.
+        expected.sort();
+        spilled.sort();
+        assert_eq!(spilled, expected);
+        assert!(build_histogram_vec_spilled(&[], 64).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_message_encoding() {
+        use fse::fixed::FixedPoint;
+        use fse::fse::{AsBytes, FromBytes};
+
+        fn roundtrip<T: AsBytes + FromBytes + PartialEq + std::fmt::Debug>(
+            message: T,
+            len: usize,
+        ) {
+            let bytes = message.to_bytes();
+            assert_eq!(bytes.len(), len);
+            assert_eq!(message.byte_len(), len);
+            assert_eq!(T::from_bytes(&bytes), message);
+
+            // Only the prefix of a longer buffer is written.
+            let mut buf = vec![0xffu8; len + 2];
+            message.write_bytes(&mut buf);
+            assert_eq!(&buf[..len], bytes.as_slice());
+            assert_eq!(&buf[len..], &[0xff, 0xff]);
+        }
+
+        roundtrip(-3i8, 1);
+        roundtrip(i16::MIN, 2);
+        roundtrip(-7i32, 4);
+        roundtrip(i64::MAX, 8);
+        roundtrip(-1i128, 16);
+        roundtrip(200u8, 1);
+        roundtrip(u16::MAX, 2);
+        roundtrip(7u32, 4);
+        roundtrip(7u64, 8);
+        roundtrip(u128::MAX, 16);
+        roundtrip(7usize, 8);
+        roundtrip("héllo".to_string(), 6);
+        roundtrip(FixedPoint::<2>::from_raw(-1250), 8);
+        // The encoding does not depend on the platform.
+        assert_eq!(258u16.to_bytes(), vec![2, 1]);
+        assert_eq!(7usize.to_bytes(), 7u64.to_le_bytes());
+
+        let price = "-12.5".parse::<FixedPoint<2>>().unwrap();
+        assert_eq!(price.raw(), -1250);
+        assert_eq!(price.to_string(), "-12.50");
+        assert_eq!(price, FixedPoint::from_f64(-12.499999).unwrap());
+        assert_eq!(
+            "-0.05".parse::<FixedPoint<2>>().unwrap().to_string(),
+            "-0.05"
+        );
+        assert_eq!("3".parse::<FixedPoint<0>>().unwrap().to_string(), "3");
+        assert_eq!(
+            "12.50".parse::<FixedPoint<2>>().unwrap(),
+            FixedPoint::from_raw(1250)
+        );
+        for invalid in ["12.505", "", "-", ".5", "1e3", "1.-5"] {
+            assert!(invalid.parse::<FixedPoint<2>>().is_err(), "{}", invalid);
+        }
+        assert!(FixedPoint::<2>::from_f64(f64::NAN).is_none());
+    }
+
+    #[test]
+    fn test_padding_policy() {
+        use fse::db::{to_binary, Data, PaddingPolicy, PADDING_FIELD};
+        use mongodb::bson::{from_document, to_document};
+
+        let policy: PaddingPolicy =
+            serde_json::from_str(r#"{"distribution":"uniform","max":16}"#)
+                .unwrap();
+        assert_eq!(policy, PaddingPolicy::Uniform { max: 16 });
+        assert!((0..1000).all(|_| policy.sample().unwrap() <= 16));
+        // A document is padded up to the target size, and a larger one is not padded.
+        assert!(
+            (0..1000).all(|_| policy.sample_padding(4).unwrap().len() <= 12)
+        );
+        assert!(
+            (0..1000).all(|_| policy.sample_padding(20).unwrap().is_empty())
+        );
+
+        let policy = PaddingPolicy::Exponential {
+            mean: 32.0,
+            max: 100,
+        };
+        assert!(policy.validate().is_ok());
+        let samples = (0..10000)
+            .map(|_| policy.sample().unwrap())
+            .collect::<Vec<_>>();
+        assert!(samples.iter().all(|&e| e <= 100));
+        let mean = samples.iter().sum::<usize>() as f64 / 10000.0;
+        assert!((mean - 32.0).abs() < 4.0);
+        assert!(PaddingPolicy::Exponential { mean: 0.0, max: 1 }
+            .validate()
+            .is_err());
+
+        // The padding is stored next to the ciphertext and dropped when the document is read back.
+        let data = Data::new(vec![1, 2, 3]);
+        let mut document = to_document(&data).unwrap();
+        let padding = policy.sample_padding(0).unwrap();
+        document.insert(PADDING_FIELD, to_binary(padding));
+        assert_eq!(from_document::<Data>(document).unwrap(), data);
+    }
+
+    #[test]
+    fn test_infer_csv_schema() {
+        use fse::util::{infer_csv_schema, select_columns, ColumnType};
+
+        let path = std::env::temp_dir().join("fse_test_schema.csv");
+        let mut content = "id,price,day,city,name\n".to_string();
+        for i in 0..20 {
+            content.push_str(&format!(
+                "{},{}.5,2023-01-{:02},{},user{}\n",
+                i,
+                i,
+                i % 28 + 1,
+                ["tokyo", "paris"][i % 2],
+                i
+            ));
+        }
+        content.push_str(",,,,\n");
+        std::fs::write(&path, content).unwrap();
+        let path = path.to_str().unwrap();
+
+        let schema = infer_csv_schema(path, None).unwrap();
+        let types = schema.iter().map(|e| e.column_type).collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                ColumnType::Integer,
+                ColumnType::Float,
+                ColumnType::Date,
+                ColumnType::Categorical,
+                ColumnType::Text,
+            ]
+        );
+        assert_eq!((schema[3].distinct, schema[3].rows), (2, 21));
+        assert!(schema.iter().all(|e| e.empty == 1));
+
+        let selectors = vec!["*categorical".to_string(), "id".to_string()];
+        assert_eq!(
+            select_columns(&schema, &selectors).unwrap(),
+            vec!["city".to_string(), "id".to_string()]
+        );
+        assert!(select_columns(&schema, &["*boolean".to_string()]).is_err());
+
+        // Too few rows to tell the categories apart from free text.
+        let schema = infer_csv_schema(path, Some(1)).unwrap();
+        assert!(select_columns(&schema, &["*categorical".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_cancellation() {
+        use fse::{
+            db::{CancellationToken, Connector, Data},
+            error::FseError,
+        };
+        use mongodb::bson::Document;
+        use std::time::{Duration, Instant};
+
+        // The client connects lazily, so no server is needed as long as nothing reaches it.
+        let conn =
+            Connector::<Data>::new("mongodb://127.0.0.1:1", "fse_test", false)
+                .unwrap();
+        let token = CancellationToken::new();
+        conn.set_cancellation_token(token.clone());
+        conn.set_operation_timeout(Some(Duration::from_millis(100)));
+        assert_eq!(
+            conn.clone().get_operation_timeout(),
+            Some(Duration::from_millis(100))
+        );
+
+        token.cancel();
+        assert!(conn.get_cancellation_token().is_cancelled());
+        let err = conn.search(Document::new(), "test").err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<FseError>(),
+            Some(FseError::Cancelled { .. })
+        ));
+
+        // A cancelled token does not wait.
+        let instant = Instant::now();
+        token.wait(Duration::from_secs(10));
+        assert!(instant.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_frequency_cap() {
+        use fse::preprocess::{CapOverflow, FrequencyCap};
+
+        let cap: FrequencyCap =
+            serde_json::from_str(r#"{"max_count":2}"#).unwrap();
+        assert_eq!(cap.overflow, CapOverflow::Rnd);
+        assert!(cap.validate().is_ok());
+        assert!(FrequencyCap {
+            max_count: 0,
+            overflow: CapOverflow::Drop
+        }
+        .validate()
+        .is_err());
+
+        let values = ["a", "b", "a", "a", "c", "a", "b"]
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        let capped = cap.apply(&values);
+        assert_eq!(capped.kept, vec!["a", "b", "a", "c", "b"]);
+        assert_eq!(capped.diverted, vec!["a", "a"]);
+        assert!((capped.diverted_mass() - 2.0 / 7.0).abs() < 1e-9);
+        assert_eq!(cap.apply(&[]).diverted_mass(), 0.0);
+    }
+
+    #[test]
+    fn test_key_provider() {
+        use fse::{
+            fse::{BaseCrypto, FromBytes},
+            keys::{KeyProvider, LocalFileProvider, KEY_LEN},
+            native::ContextNative,
+        };
+
+        let dir = std::env::temp_dir().join("fse_test_keys");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let provider = LocalFileProvider::open(&dir).unwrap();
+        let key = provider.get_key("pfse").unwrap();
+        assert_eq!(key.len(), KEY_LEN);
+        assert_eq!(provider.get_key("pfse").unwrap(), key);
+        assert_ne!(provider.get_key("dte").unwrap(), key);
+        assert!(provider.get_key("../kek").is_err());
+        assert!(provider.get_key("").is_err());
+
+        // The keys survive a reopen, and only their wrapped form is stored.
+        let provider = LocalFileProvider::open(&dir).unwrap();
+        assert_eq!(provider.get_key("pfse").unwrap(), key);
+        let stored = std::fs::read(dir.join("pfse.key")).unwrap();
+        assert_ne!(stored, key);
+
+        let mut wrapped = provider.wrap(&key).unwrap();
+        assert_eq!(provider.unwrap(&wrapped).unwrap(), key);
+        *wrapped.last_mut().unwrap() ^= 1;
+        assert!(provider.unwrap(&wrapped).is_err());
+        assert!(provider.unwrap(&[]).is_err());
+
+        let mut ctx = ContextNative::new(false);
+        ctx.key_from_provider(&provider, "pfse").unwrap();
+        assert_eq!(ctx.get_key(), key.as_slice());
+        let message = "hello".to_string();
+        let ciphertext = ctx.encrypt(&message).unwrap().remove(0);
+        let decrypted = ctx.decrypt_checked(&ciphertext).unwrap();
+        assert_eq!(String::from_bytes(&decrypted), message);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_column_key() {
+        use fse::{keys::derive_column_key, util::hkdf_sha256, util::to_hex};
+
+        // RFC 5869, test case 1.
+        let okm = hkdf_sha256(
+            &(0..=0x0cu8).collect::<Vec<_>>(),
+            &[0x0b; 22],
+            &(0xf0..=0xf9u8).collect::<Vec<_>>(),
+            42,
+        )
+        .unwrap();
+        assert_eq!(
+            to_hex(&okm),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865"
+        );
+        assert!(hkdf_sha256(b"", b"", b"", 255 * 32 + 1).is_none());
+
+        let key = [7u8; 32];
+        let name = derive_column_key(&key, "name", 32).unwrap();
+        assert_eq!(name, derive_column_key(&key, "name", 32).unwrap());
+        assert_ne!(name, derive_column_key(&key, "city", 32).unwrap());
+        assert_ne!(name, key.to_vec());
+        assert_eq!(derive_column_key(&key, "name", 16).unwrap(), name[..16]);
+        assert!(derive_column_key(&key, "name", usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_csv_options() {
+        use fse::error::FseError;
+        use fse::util::{
+            infer_csv_schema_with, read_csv_exact, read_csv_exact_with,
+            read_csv_multiple_with, BadLinePolicy, CsvEncoding, CsvOptions,
+        };
+
+        let path = std::env::temp_dir().join("fse_test_options.csv");
+        // Latin-1 bytes, a quoted delimiter, and a record with a missing field.
+        std::fs::write(&path, b"'caf\xe9';1\n'a;b';2\nlonely\n'x';3\n")
+            .unwrap();
+        let path = path.to_str().unwrap();
+
+        let mut options = CsvOptions {
+            delimiter: ';',
+            quote: '\'',
+            has_headers: false,
+            encoding: CsvEncoding::Latin1,
+            on_bad_line: BadLinePolicy::Error,
+        };
+        let err = read_csv_exact_with(path, "0", &options).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FseError>(),
+            Some(FseError::MalformedRecord { line: 3, .. })
+        ));
+
+        options.on_bad_line = BadLinePolicy::Skip;
+        let (columns, stats) = read_csv_multiple_with(
+            path,
+            &["1".to_string(), "0".to_string()],
+            &options,
+        )
+        .unwrap();
+        assert_eq!(columns[0], vec!["1", "2", "3"]);
+        assert_eq!(columns[1], vec!["caf\u{e9}", "a;b", "x"]);
+        assert_eq!((stats.rows, stats.malformed), (3, 1));
+        let schema = infer_csv_schema_with(path, None, &options).unwrap();
+        assert_eq!(schema[1].rows, 3);
+
+        // Not valid UTF-8 under the default encoding.
+        options.encoding = CsvEncoding::Utf8;
+        let (_, stats) = read_csv_exact_with(path, "0", &options).unwrap();
+        assert_eq!((stats.rows, stats.malformed), (2, 2));
+
+        options.delimiter = '\u{2028}';
+        assert!(read_csv_exact_with(path, "0", &options).is_err());
+        // The default options expect a comma-delimited file with a header.
+        assert!(read_csv_exact(path, "0").is_err());
+    }
+
+    #[test]
+    fn test_drift_monitor() {
+        use std::collections::HashMap;
+
+        use fse::drift::{DriftMonitor, SpaceSaving};
+
+        let mut sketch = SpaceSaving::new(2);
+        for item in [1, 1, 1, 2, 2, 3] {
+            sketch.insert(item);
+        }
+        // 3 evicts 2 and inherits its count.
+        assert_eq!(sketch.top(1), vec![(1, 3)]);
+        assert_eq!(sketch.estimate(3), 3);
+        assert_eq!(sketch.total(), 6);
+
+        let key = vec![0u8; 32];
+        let histogram = (0..10u64).map(|e| (e, 10)).collect::<HashMap<_, _>>();
+        assert!(DriftMonitor::new(&key, &histogram, 0, 0.1).is_err());
+        assert!(DriftMonitor::new(&key, &histogram, 5, 0.0).is_err());
+
+        let mut monitor = DriftMonitor::new(&key, &histogram, 5, 0.2).unwrap();
+        assert_eq!(monitor.report().distance, 0.0);
+        for _ in 0..10 {
+            for message in 0..10u64 {
+                monitor.observe(&message);
+            }
+        }
+        let report = monitor.report();
+        assert!(report.distance < 1e-9 && !report.alert);
+        assert_eq!(report.inserted, 100);
+
+        // A single message takes over.
+        for _ in 0..400 {
+            monitor.observe(&0u64);
+        }
+        let report = monitor.report();
+        assert!(report.alert);
+        assert!(report.observed_skew > report.baseline_skew);
+    }
+
+    #[test]
+    fn test_read_csv_weighted() {
+        use fse::error::FseError;
+        use fse::fse::{exponential, BaseCrypto, PartitionFrequencySmoothing};
+        use fse::params::PfseParams;
+        use fse::pfse::ContextPFSE;
+        use fse::util::{
+            read_csv_weighted, read_csv_weighted_with, BadLinePolicy,
+            CsvOptions,
+        };
+        use fse::wre::ContextWRE;
+
+        let path = std::env::temp_dir().join("fse_test_weighted.csv");
+        std::fs::write(&path, "value,count\na,300\nb,20\na,100\nc,0\nd,many\n")
+            .unwrap();
+        let path = path.to_str().unwrap();
+
+        let err = read_csv_weighted(path, "value", "count").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FseError>(),
+            Some(FseError::MalformedRecord { line: 6, .. })
+        ));
+        assert!(read_csv_weighted(path, "value", "weight").is_err());
+
+        let options = CsvOptions {
+            on_bad_line: BadLinePolicy::Skip,
+            ..Default::default()
+        };
+        let (histogram, stats) =
+            read_csv_weighted_with(path, "value", "count", &options).unwrap();
+        assert_eq!((stats.rows, stats.malformed), (4, 1));
+        assert_eq!(histogram.len(), 2);
+        assert_eq!((histogram["a"], histogram["b"]), (400, 20));
+
+        let mut ctx = ContextPFSE::default();
+        ctx.key_generate();
+        ctx.set_params(&PfseParams::new(0.25, 1.0, 0.1)).unwrap();
+        ctx.partition_histogram(&histogram, exponential).unwrap();
+        assert_eq!(ctx.get_message_num(), 420);
+
+        let mut histogram = histogram;
+        histogram.remove("b");
+        let mut ctx = ContextWRE::new(1);
+        ctx.key_generate();
+        ctx.initialize_histogram(&histogram).unwrap();
+        assert!(ctx.encrypt(&"a".to_string()).is_some());
+        assert!(ctx.encrypt(&"b".to_string()).is_none());
+        assert!(ctx
+            .initialize_histogram(&std::collections::HashMap::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_connector_ownership() {
+        use fse::db::{Connector, Data};
+        use fse::fse::Conn;
+        use fse::pfse::ContextPFSE;
+
+        // Every connector is detached before its last owner is dropped, so the unreachable server is never asked to
+        // drop the database.
+        let address = "mongodb://127.0.0.1:1";
+        let conn = Connector::<Data>::new(address, "fse_test", true).unwrap();
+        assert!(conn.owns_database());
+        assert_eq!(conn.owner_count(), 1);
+
+        // Dropping the original first leaves the clone as the owner.
+        let clone = conn.clone();
+        assert_eq!(conn.owner_count(), 2);
+        drop(conn);
+        assert!(clone.owns_database());
+        assert_eq!(clone.owner_count(), 1);
+
+        // Dropping the clone first leaves the original as the owner.
+        let other = clone.clone();
+        drop(other);
+        assert_eq!(clone.owner_count(), 1);
+
+        // A detached clone does not count, and neither do its own clones.
+        let detached = clone.clone();
+        detached.detach();
+        assert!(!detached.owns_database());
+        assert_eq!(detached.clone().owner_count(), 0);
+        assert_eq!(clone.owner_count(), 1);
+        assert!(clone.owns_database());
+
+        // The last owner keeps the database by detaching.
+        clone.detach();
+        assert!(!clone.owns_database());
+        drop(clone);
+
+        let conn = Connector::<Data>::new(address, "fse_test", false).unwrap();
+        assert!(!conn.owns_database());
+        assert_eq!(conn.owner_count(), 1);
+
+        // Cloning a context shares the ownership of its connector.
+        let mut ctx = ContextPFSE::<String>::default();
+        ctx.initialize_conn(address, "fse_test", true);
+        let clone = ctx.clone();
+        assert_eq!(ctx.get_conn().owner_count(), 2);
+        drop(ctx);
+        assert!(clone.get_conn().owns_database());
+        clone.get_conn().detach();
+    }
+
+    #[test]
+    fn test_salt_count_attack() {
+        use std::collections::HashMap;
+
+        use fse::attack::{LeakageCollector, SaltCountAttacker};
+        use fse::fse::BaseCrypto;
+        use fse::params::{SaltAllocation, WreParams};
+        use fse::wre::ContextWRE;
+
+        // Distinct counts with a single tag each are given away.
+        let correct = [("a", 1), ("b", 2), ("c", 3)]
+            .into_iter()
+            .map(|(message, _)| (message, vec![message.as_bytes().to_vec()]))
+            .collect::<HashMap<_, _>>();
+        let local_table = [("a", 1), ("b", 2), ("c", 3)]
+            .into_iter()
+            .map(|(message, count)| (message, vec![(0, 1, count)]))
+            .collect::<HashMap<_, _>>();
+        let observed = ["a", "b", "b", "c", "c", "c"]
+            .iter()
+            .map(|e| e.as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let mut attacker = SaltCountAttacker::new();
+        assert_eq!(attacker.attack(&correct, &local_table, &observed), 1.0);
+
+        let data = (0..10)
+            .flat_map(|i| vec![i.to_string(); (i + 1) * 20])
+            .collect::<Vec<_>>();
+        for allocation in [SaltAllocation::Fixed, SaltAllocation::Bucketized] {
+            let params = WreParams::new(10).with_allocation(allocation);
+            let mut ctx = ContextWRE::from_params(&params).unwrap();
+            ctx.key_generate();
+            let meta = ctx.collect_leakage(&data).unwrap();
+
+            for (message, ciphertext) in data.iter().zip(meta.sequence.iter()) {
+                let plaintext = ctx.decrypt(ciphertext).unwrap();
+                assert_eq!(plaintext, message.as_bytes());
+            }
+            for (message, ciphertexts) in meta.correct.iter() {
+                let tokens = ctx.search_tokens(message).unwrap();
+                assert!(ciphertexts.iter().all(|e| tokens.contains(e)));
+            }
+
+            let mut attacker = SaltCountAttacker::new();
+            let accuracy = attacker.attack(
+                &meta.correct,
+                &meta.local_table,
+                &meta.raw_ciphertexts,
+            );
+            assert!((0.0..=1.0).contains(&accuracy));
+            let recovery = attacker.get_recovery().unwrap();
+            let occurrences = recovery.values().map(|e| e.0).sum::<usize>();
+            assert_eq!(occurrences, data.len());
+        }
+    }
+
+    #[test]
+    fn test_build_histogram() {
+        use fse::util::{build_histogram, build_histogram_fast};
+
+        let dataset = (0..10_000).map(|i| i * i % 97).collect::<Vec<_>>();
+        let histogram = build_histogram(&dataset);
+        assert_eq!(histogram.len(), 49);
+        assert_eq!(histogram.values().sum::<usize>(), 10_000);
+        let fast = build_histogram_fast(&dataset);
+        assert!(histogram.iter().all(|(k, v)| fast.get(k) == Some(v)));
+        assert_eq!(histogram.len(), fast.len());
+    }
+
+    #[test]
+    fn test_sample_or_cycle() {
+        use std::collections::HashSet;
+
+        use fse::util::sample_or_cycle;
+
+        let dataset = (0..100).collect::<Vec<_>>();
+        // A smaller sample has no repetition.
+        let sample = sample_or_cycle(&dataset, 30, 7);
+        assert_eq!(sample.len(), 30);
+        assert_eq!(sample.iter().collect::<HashSet<_>>().len(), 30);
+        assert_eq!(sample, sample_or_cycle(&dataset, 30, 7));
+        // The whole dataset is a permutation of it.
+        let mut all = sample_or_cycle(&dataset, 100, 7);
+        all.sort();
+        assert_eq!(all, dataset);
+        // A larger sample is drawn with replacement from the dataset.
+        let large = sample_or_cycle(&dataset, 1000, 7);
+        assert_eq!(large.len(), 1000);
+        assert!(large.iter().all(|e| dataset.contains(e)));
+        assert!(sample_or_cycle::<usize>(&[], 10, 7).is_empty());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_build_histogram_par() {
+        use fse::util::{
+            build_histogram, build_histogram_par, PARALLEL_THRESHOLD,
+        };
+
+        let dataset = (0..PARALLEL_THRESHOLD + 7)
+            .map(|i| i % 1013)
+            .collect::<Vec<_>>();
+        assert_eq!(build_histogram_par(&dataset), build_histogram(&dataset));
+    }
+}